@@ -0,0 +1,44 @@
+fn main() {
+    #[cfg(any(feature = "grpc", feature = "protobuf"))]
+    {
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("vendored protoc");
+        // SAFETY: build scripts are single-threaded at this point
+        unsafe { std::env::set_var("PROTOC", protoc) };
+    }
+    // gRPC stubs are only generated when the feature is enabled
+    #[cfg(feature = "grpc")]
+    tonic_prost_build::compile_protos("proto/queue.proto").expect("compile queue.proto");
+    // Wire messages only, no service
+    #[cfg(feature = "protobuf")]
+    tonic_prost_build::configure()
+        .build_client(false)
+        .build_server(false)
+        .compile_protos(&["proto/wire.proto"], &["proto"])
+        .expect("compile wire.proto");
+    // C header for the C API, parsed from the capi module alone
+    #[cfg(feature = "capi")]
+    {
+        let config = cbindgen::Config {
+            usize_is_size_t: true,
+            enumeration: cbindgen::EnumConfig {
+                prefix_with_name: true,
+                rename_variants: cbindgen::RenameRule::ScreamingSnakeCase,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        cbindgen::Builder::new()
+            .with_config(config)
+            .with_src("src/bindings/capi/mod.rs")
+            .with_language(cbindgen::Language::C)
+            .with_include_guard("DISTRIBUTED_QUEUE_H")
+            .with_header("/* Generated by the build script from src/bindings/capi, do not edit */")
+            .with_cpp_compat(true)
+            .generate()
+            .expect("generate C header")
+            .write_to_file("include/distributed_queue.h");
+        println!("cargo:rerun-if-changed=src/bindings/capi/mod.rs");
+    }
+    println!("cargo:rerun-if-changed=proto/queue.proto");
+    println!("cargo:rerun-if-changed=proto/wire.proto");
+}
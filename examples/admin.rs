@@ -0,0 +1,49 @@
+//! Inspect topics on a running node
+//! `admin <node-addr> <topic>...` prints each topic's stats as JSON, followed by the
+//! remote events it gave up applying, if any.
+//! Set `QUEUE_TOKEN` for nodes with access control.
+
+#![allow(non_snake_case)]
+
+use DistributedQueueMini::engine::client::QueueClient;
+use serde_json::Value;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        eprintln!("usage: admin <node-addr> <topic>...");
+        std::process::exit(2);
+    }
+    let client = QueueClient::<Value>::connect(args[0].as_str()).unwrap_or_else(|e| {
+        eprintln!("cannot connect to {}: {}", args[0], e);
+        std::process::exit(1);
+    });
+    let client = match std::env::var("QUEUE_TOKEN") {
+        Ok(token) => client.with_token(token),
+        Err(_) => client,
+    };
+
+    let mut failed = false;
+    for topic in &args[1..] {
+        match client.stats(topic) {
+            Ok(stats) => {
+                println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+                match client.failed_events(topic) {
+                    Ok(events) if events.is_empty() => {}
+                    Ok(events) => println!("{}", serde_json::to_string_pretty(&events).unwrap()),
+                    Err(e) => {
+                        eprintln!("{}: {}", topic, e);
+                        failed = true;
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("{}: {}", topic, e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
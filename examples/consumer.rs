@@ -0,0 +1,35 @@
+//! Pull messages from a running node as they arrive
+//! `consumer <node-addr> <topic> [count]`; runs until interrupted unless a count is given.
+//! Set `QUEUE_TOKEN` for nodes with access control.
+
+#![allow(non_snake_case)]
+
+use DistributedQueueMini::engine::client::QueueClient;
+use serde_json::Value;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        eprintln!("usage: consumer <node-addr> <topic> [count]");
+        std::process::exit(2);
+    }
+    let client = QueueClient::<Value>::connect(args[0].as_str()).unwrap_or_else(|e| {
+        eprintln!("cannot connect to {}: {}", args[0], e);
+        std::process::exit(1);
+    });
+    let client = match std::env::var("QUEUE_TOKEN") {
+        Ok(token) => client.with_token(token),
+        Err(_) => client,
+    };
+    let count = args.get(2).map(|n| n.parse().expect("count must be a number")).unwrap_or(usize::MAX);
+
+    for item in client.subscribe(&args[1]).take(count) {
+        match item {
+            Ok(item) => println!("{}", item),
+            Err(e) => {
+                eprintln!("dequeue failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,43 @@
+//! Push messages to a running node
+//! `producer <node-addr> <topic> [message...]`; reads messages from stdin, one per line, if none are given.
+//! Messages that parse as JSON are sent as-is, anything else as a JSON string.
+//! Set `QUEUE_TOKEN` for nodes with access control.
+
+#![allow(non_snake_case)]
+
+use std::io::BufRead;
+use DistributedQueueMini::engine::client::QueueClient;
+use serde_json::Value;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        eprintln!("usage: producer <node-addr> <topic> [message...]");
+        std::process::exit(2);
+    }
+    let client = QueueClient::<Value>::connect(args[0].as_str()).unwrap_or_else(|e| {
+        eprintln!("cannot connect to {}: {}", args[0], e);
+        std::process::exit(1);
+    });
+    let client = match std::env::var("QUEUE_TOKEN") {
+        Ok(token) => client.with_token(token),
+        Err(_) => client,
+    };
+    let topic = &args[1];
+
+    let messages: Box<dyn Iterator<Item = String> + '_> = if args.len() > 2 {
+        Box::new(args[2..].iter().cloned())
+    } else {
+        Box::new(std::io::stdin().lock().lines().map_while(Result::ok))
+    };
+    for message in messages {
+        let item = serde_json::from_str(&message).unwrap_or(Value::String(message));
+        match client.enqueue(topic, item) {
+            Ok(event_id) => println!("enqueued event {}", event_id),
+            Err(e) => {
+                eprintln!("enqueue failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,148 @@
+/* Generated by the build script from src/bindings/capi, do not edit */
+
+#ifndef DISTRIBUTED_QUEUE_H
+#define DISTRIBUTED_QUEUE_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Status of a C API call; calls that report a count or flag return it as a non-negative value instead
+ */
+typedef enum DqStatus {
+  DQ_STATUS_OK = 0,
+  /**
+   * A required pointer was NULL
+   */
+  DQ_STATUS_NULL_ARGUMENT = -1,
+  /**
+   * A string wasn't UTF-8, or an event buffer wasn't a valid event
+   */
+  DQ_STATUS_INVALID_ARGUMENT = -2,
+  /**
+   * Enqueue rejected: queue at capacity
+   */
+  DQ_STATUS_FULL = -3,
+  /**
+   * Enqueue rejected: over the rate limit or a quota
+   */
+  DQ_STATUS_THROTTLED = -4,
+  /**
+   * Any other queue error
+   */
+  DQ_STATUS_FAILED = -5,
+} DqStatus;
+
+/**
+ * A queue node, opaque to C
+ */
+typedef struct DqNode DqNode;
+
+/**
+ * Byte buffer handed to the caller, released with `dq_buffer_free`
+ * `data` is NULL for an empty buffer
+ */
+typedef struct DqBuffer {
+  uint8_t *data;
+  size_t len;
+} DqBuffer;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Create node `node_id` replicating with the `peer_count` nodes in `peers`
+ * Returns NULL if a name is NULL or not UTF-8.
+ *
+ * # Safety
+ * `node_id` and the first `peer_count` entries of `peers` must be NUL-terminated strings;
+ * `peers` may be NULL if `peer_count` is 0.
+ */
+struct DqNode *dq_node_new(const char *node_id, const char *const *peers, size_t peer_count);
+
+/**
+ * Release a node created by `dq_node_new`; NULL is ignored
+ *
+ * # Safety
+ * `node` must come from `dq_node_new` and not be used afterwards.
+ */
+void dq_node_free(struct DqNode *node);
+
+/**
+ * Enqueue a copy of the `len` bytes at `data`
+ * On success writes the enqueue event to `event_out` (if not NULL), to be sent to every peer.
+ *
+ * # Safety
+ * `node` must be a live node; `data` must point to `len` readable bytes (or be NULL if `len` is 0);
+ * `event_out` must be NULL or writable.
+ */
+enum DqStatus dq_enqueue(const struct DqNode *node,
+                         const uint8_t *data,
+                         size_t len,
+                         struct DqBuffer *event_out);
+
+/**
+ * Dequeue the next item into `item_out`
+ * Returns 1 if an item was dequeued, 0 if the queue was empty, or a negative `DqStatus`.
+ * The dequeue event goes to `event_out` (if not NULL) either way, to be sent to every peer.
+ *
+ * # Safety
+ * `node` must be a live node; `item_out` and `event_out` must be NULL or writable.
+ */
+int32_t dq_dequeue(const struct DqNode *node,
+                   struct DqBuffer *item_out,
+                   struct DqBuffer *event_out);
+
+/**
+ * Apply an event buffer received from another node
+ * Returns 1 if applied, 0 if it was a duplicate or is buffered until its causal
+ * predecessors arrive, or a negative `DqStatus`.
+ *
+ * # Safety
+ * `node` must be a live node; `event` must point to `len` readable bytes.
+ */
+int32_t dq_apply_remote_event(const struct DqNode *node, const uint8_t *event, size_t len);
+
+/**
+ * Take the events the node created while applying remote ones (e.g. quota rejections)
+ * Writes them to `events_out` as a JSON array, each element an event to send to every peer
+ * and apply with `dq_apply_remote_event`. Returns how many there were, or a negative `DqStatus`.
+ *
+ * # Safety
+ * `node` must be a live node; `events_out` must be writable.
+ */
+int64_t dq_take_outgoing(const struct DqNode *node, struct DqBuffer *events_out);
+
+/**
+ * Items currently queued; 0 for NULL
+ *
+ * # Safety
+ * `node` must be NULL or a live node.
+ */
+size_t dq_len(const struct DqNode *node);
+
+/**
+ * Current queue stats as a JSON object, see `QueueStats`
+ *
+ * # Safety
+ * `node` must be a live node; `stats_out` must be writable.
+ */
+enum DqStatus dq_stats(const struct DqNode *node, struct DqBuffer *stats_out);
+
+/**
+ * Release a buffer returned by this API
+ *
+ * # Safety
+ * `buffer` must come from this API, unmodified, and not be freed twice.
+ */
+void dq_buffer_free(struct DqBuffer buffer);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* DISTRIBUTED_QUEUE_H */
@@ -0,0 +1,72 @@
+//! Cluster administration from the command line: `queuectl [--addr host:port] <command>`
+//! Talks to a node's HTTP API, `QUEUE_ADDR` or 127.0.0.1:8080 by default, and prints
+//! its JSON answer. Set `QUEUE_TOKEN` for nodes with access control.
+//! `queuectl diff` compares two NDJSON log files locally instead.
+
+use DistributedQueueMini::core::buildcore::{LogFormat, diff_histories, read_logs};
+use DistributedQueueMini::engine::admin::AdminClient;
+use serde_json::Value;
+
+const USAGE: &str = "usage: queuectl [--addr host:port] <command>
+
+commands:
+  status                  readiness and per-topic stats
+  peers                   peer liveness and backpressure
+  pending [topic]         buffered events, retries and transfers per topic, or a topic's buffered events
+  purge <topic> <key>     purge the items logged under key
+  pause <topic>           refuse enqueues and hand out nothing until resumed
+  resume <topic>          undo a pause
+  snapshot <topic>        point-in-time snapshot of the topic
+  config <topic>          settings in effect on the topic
+  rebalance <topic>       spread the topic's owned queues over the placement members
+  diff <log> <log>        events one NDJSON log has and the other lacks, reorderings, and where they part";
+
+fn main() {
+    let mut addr = std::env::var("QUEUE_ADDR").unwrap_or_else(|_| "127.0.0.1:8080".to_string());
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("--addr") {
+        if args.len() < 2 {
+            usage();
+        }
+        addr = args.remove(1);
+        args.remove(0);
+    }
+    let mut client = AdminClient::new(addr);
+    if let Ok(token) = std::env::var("QUEUE_TOKEN") {
+        client = client.with_token(token);
+    }
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["status"] => client.status(),
+        ["peers"] => client.peers(),
+        ["pending"] => client.pending(None),
+        ["pending", topic] => client.pending(Some(topic)),
+        ["purge", topic, key] => client.purge(topic, key),
+        ["pause", topic] => client.pause(topic),
+        ["resume", topic] => client.resume(topic),
+        ["snapshot", topic] => client.snapshot(topic),
+        ["config", topic] => client.config(topic),
+        ["rebalance", topic] => client.rebalance(topic),
+        ["diff", left, right] => diff(left, right),
+        _ => usage(),
+    };
+    match result {
+        Ok(answer) => println!("{}", serde_json::to_string_pretty(&answer).unwrap_or_default()),
+        Err(e) => {
+            eprintln!("queuectl: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Compare the histories in two log files, see `diff_histories`
+fn diff(left: &str, right: &str) -> std::io::Result<Value> {
+    let left = read_logs::<Value>(left, LogFormat::Ndjson)?;
+    let right = read_logs::<Value>(right, LogFormat::Ndjson)?;
+    serde_json::to_value(diff_histories(&left, &right)).map_err(std::io::Error::other)
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
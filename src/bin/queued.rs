@@ -0,0 +1,528 @@
+//! Standalone queue node: `queued [config.toml]`
+//! Starts the peer transport and optional HTTP API, then runs until SIGINT/SIGTERM.
+//! Changes to the config file's buffer limit, rate limits, retention and peers are applied
+//! while it runs, as soon as the file is saved or on `POST /admin/reload`.
+//! `queued shell [node-addr]` attaches an interactive shell to a running node instead
+//! (set `QUEUE_TOKEN` for nodes with access control).
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, SystemTime};
+use DistributedQueueMini::core::buildcore::{AuditEntry, AuditLog, DistributedQueueSystem, LogFormat, LogSink, LogWriter, NdjsonSink, RetryPolicy};
+use DistributedQueueMini::engine::config::ServerConfig;
+#[cfg(feature = "auth")]
+use DistributedQueueMini::engine::config::AuthConfig;
+use DistributedQueueMini::engine::http;
+use DistributedQueueMini::engine::network::{ReliableTransport, TcpTransport};
+#[cfg(feature = "tls")]
+use DistributedQueueMini::engine::network::TlsConfig;
+#[cfg(feature = "auth")]
+use DistributedQueueMini::engine::network::Keyring;
+#[cfg(feature = "encrypt")]
+use DistributedQueueMini::core::buildcore::PayloadCipher;
+#[cfg(feature = "compress")]
+use DistributedQueueMini::engine::network::Compression;
+use DistributedQueueMini::engine::node::Node;
+use DistributedQueueMini::engine::placement::{DEFAULT_VNODES, HashRing};
+use DistributedQueueMini::engine::shell::Shell;
+use DistributedQueueMini::engine::webhook::WebhookDispatcher;
+use DistributedQueueMini::engine::mirror::ClusterMirror;
+#[cfg(feature = "sqs")]
+use DistributedQueueMini::engine::sqs::SqsFacade;
+#[cfg(feature = "redis")]
+use DistributedQueueMini::engine::redis::{RedisBridge, RedisStream};
+#[cfg(feature = "nats")]
+use DistributedQueueMini::engine::nats::{NatsConnector, NatsServer};
+#[cfg(feature = "mqtt")]
+use DistributedQueueMini::engine::mqtt::{MqttBridge, MqttBroker, QoS};
+use serde_json::Value;
+
+/// Peer address used by `queued shell` when none is given
+const DEFAULT_NODE_ADDR: &str = "127.0.0.1:7000";
+/// How often the config file is checked for changes
+const RELOAD_POLL: Duration = Duration::from_secs(1);
+
+#[cfg(feature = "encrypt")]
+type Cipher = Option<PayloadCipher>;
+#[cfg(not(feature = "encrypt"))]
+type Cipher = Option<std::convert::Infallible>;
+
+fn main() {
+    if std::env::args().nth(1).as_deref() == Some("shell") {
+        let addr = std::env::args().nth(2).unwrap_or_else(|| DEFAULT_NODE_ADDR.to_string());
+        if let Err(e) = shell(&addr) {
+            eprintln!("queued shell: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let path = std::env::args().nth(1).unwrap_or_else(|| "queued.toml".to_string());
+    let config = match ServerConfig::load(&path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("failed to load {}: {}", path, e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = run(config, PathBuf::from(path)) {
+        eprintln!("queued: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(config: ServerConfig, path: PathBuf) -> std::io::Result<()> {
+    if let Some(dir) = &config.storage_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+    let format = config.log_format;
+    if !format.is_available() {
+        return Err(std::io::Error::other(format!("log_format = {} but queued was built without the `{}` feature", format, format)));
+    }
+
+    // Every topic tracks the configured peers and, with a storage dir, writes its log there
+    let node_id = config.node_id.clone();
+    let storage_dir = config.storage_dir.clone();
+    let cipher = encryption(&config)?;
+    // Settings that can change while running, read again for each new topic
+    let live = Arc::new(RwLock::new(config.clone()));
+    let settings = live.clone();
+    let quotas = config.quotas.clone().unwrap_or_default();
+    let dequeue_policy = config.dequeue_policy;
+    let retry = config.retry.as_ref().map(|retry| retry.to_policy());
+    // Failed posts are retried even without `[retry]`
+    let webhook_retry: HashMap<String, RetryPolicy> =
+        config.webhooks.iter().map(|(topic, webhook)| (topic.clone(), webhook.retry_policy(retry))).collect();
+    let mut webhooks = HashMap::new();
+    for (topic, webhook) in &config.webhooks {
+        webhooks.insert(topic.clone(), webhook.to_webhook()?);
+    }
+    if cipher.is_some() && format != LogFormat::Ndjson {
+        return Err(std::io::Error::other("encryption is only supported with log_format = ndjson"));
+    }
+    let node = Node::<Value>::with_topic_factory(&config.node_id, move |topic| {
+        let settings = settings.read().unwrap();
+        let mut builder = DistributedQueueSystem::builder(node_id.clone()).peers(&settings.peer_ids()).dequeue_policy(dequeue_policy);
+        if let Some(max) = settings.max_buffered_events {
+            builder = builder.max_buffered_events(max);
+        }
+        if let Some(max) = settings.max_queue_bytes {
+            builder = builder.max_bytes(max);
+        }
+        if let Some(worker) = settings.id_worker {
+            builder = builder.id_worker(worker);
+        }
+        if let Some(retention) = &settings.retention {
+            builder = builder.retention(retention.to_policy());
+        }
+        if let Some(max) = quotas.default {
+            builder = builder.default_node_quota(max);
+        }
+        for (node, max) in &quotas.nodes {
+            builder = builder.node_quota(node, *max);
+        }
+        // Partitions `topic#N` share their topic's webhook
+        let base = topic.split_once('#').map_or(topic, |(base, _)| base);
+        if let Some(policy) = webhook_retry.get(base).copied().or(retry) {
+            builder = builder.retry_policy(policy);
+        }
+        if let Some(limits) = &settings.rate_limit {
+            if let Some(limit) = limits.node {
+                builder = builder.rate_limit(limit, limits.throttle());
+            }
+            if let Some(limit) = limits.client {
+                builder = builder.client_rate_limit(limit, limits.throttle());
+            }
+        }
+        // Topics `namespace/topic` get their logs in the namespace's own directory
+        if let Some(dir) = &storage_dir {
+            let path = dir.join(format!("{}.{}", topic, format.extension()));
+            let sink = path.parent().map_or(Ok(()), std::fs::create_dir_all).and_then(|_| log_sink(&path, topic, format, &cipher));
+            match sink {
+                Ok(sink) => builder = builder.log_sink(sink),
+                Err(e) => eprintln!("no log file for topic {}: {}", topic, e),
+            }
+        }
+        builder
+    });
+    let mut node = with_auth(node, &config)?;
+    if let Some(placement) = &config.placement {
+        let ring = HashRing::with_members(&config.peer_ids(), placement.vnodes.unwrap_or(DEFAULT_VNODES));
+        node = node.with_placement(ring);
+        if let Some(steal) = &placement.steal {
+            node = node.with_work_stealing(steal.to_policy());
+        }
+    }
+    if let Some(ms) = config.heartbeat_ms {
+        node = node.with_heartbeat(Duration::from_millis(ms));
+    }
+    if let Some(backpressure) = &config.backpressure {
+        node = node.with_backpressure(backpressure.to_policy());
+    }
+    node = node.with_health(config.health.clone().unwrap_or_default().to_policy(config.storage_dir.clone()));
+    for (topic, count) in &config.partitions {
+        node = node.with_partitions(topic, *count);
+    }
+    for (namespace, quota) in &config.namespaces {
+        node = node.with_namespace_quota(namespace, *quota);
+    }
+    if let Some(acl) = &config.acl {
+        node = node.with_acl(acl.to_acl());
+    }
+    if let Some(path) = config.audit_log() {
+        node = node.with_audit_log(AuditLog::append_to(path)?);
+    }
+    node.audit_log().record(AuditEntry::admin(&config.node_id, "config_load", None, config_summary(&config)));
+    let node = Arc::new(node);
+
+    let peer_addrs: Vec<(&str, _)> = config.peers.iter().map(|p| (p.id.as_str(), p.addr)).collect();
+    let mut transport = bind_transport(&config, &peer_addrs)?;
+    if let Some(max_frame) = config.max_frame_len {
+        transport = transport.with_max_frame_len(max_frame);
+    }
+    let transport = Arc::new(transport);
+    eprintln!("{} listening for peers on {}", config.node_id, transport.local_addr());
+    let receiver = match config.retransmit_ms {
+        Some(ms) => {
+            let reliable = ReliableTransport::new(transport.clone()).with_retransmit_after(Duration::from_millis(ms));
+            #[cfg(feature = "auth")]
+            let reliable = match &config.auth {
+                Some(auth) => reliable.with_keyring(keyring(auth)),
+                None => reliable,
+            };
+            node.start(Arc::new(reliable))
+        }
+        None => node.start(transport.clone()),
+    };
+
+    let api = match config.http {
+        Some(addr) => {
+            let server = http::serve(node.clone(), addr)?;
+            eprintln!("{} serving HTTP on {}", config.node_id, addr);
+            Some(server)
+        }
+        None => None,
+    };
+    let sqs = serve_sqs(&node, &config)?;
+    let bridges = redis_bridges(&node, &config)?;
+    let connectors = nats_connectors(&node, &config)?;
+    let mqtt = mqtt_bridges(&node, &config)?;
+    let mut mirrors = Vec::new();
+    for (topic, mirror) in &config.mirrors {
+        let remote = mirror.to_remote();
+        eprintln!("{} mirroring {} to {}", config.node_id, topic, remote);
+        mirrors.push(ClusterMirror::start(node.clone(), topic, remote, mirror.position_file.clone())?);
+    }
+    let dispatcher = (!webhooks.is_empty()).then(|| {
+        for (topic, webhook) in &webhooks {
+            eprintln!("{} posting {} to {}", config.node_id, topic, webhook.url());
+        }
+        WebhookDispatcher::start(node.clone(), webhooks)
+    });
+
+    let reloaded = Arc::downgrade(&node);
+    let peers = transport.clone();
+    let config_path = path.clone();
+    node.set_reloader(move || {
+        let Some(node) = reloaded.upgrade() else {
+            return Ok(Vec::new());
+        };
+        let new = ServerConfig::load(&config_path)?;
+        let mut live = live.write().unwrap();
+        let mut changes = live.reload(&new, &node, &peers);
+        if live.needs_restart(&new) {
+            changes.push("other changes take a restart".to_string());
+        }
+        live.max_buffered_events = new.max_buffered_events;
+        live.rate_limit = new.rate_limit;
+        live.retention = new.retention;
+        live.peers = new.peers;
+        Ok(changes)
+    });
+
+    let (stop, stopped) = mpsc::channel();
+    ctrlc::set_handler(move || {
+        stop.send(()).ok();
+    })
+    .map_err(std::io::Error::other)?;
+    let mut modified = modified_at(&path);
+    while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(RELOAD_POLL) {
+        let now = modified_at(&path);
+        if now == modified {
+            continue;
+        }
+        modified = now;
+        match node.reload() {
+            Ok(changes) if changes.is_empty() => {}
+            Ok(changes) => eprintln!("{} reloaded {}: {}", config.node_id, path.display(), changes.join(", ")),
+            Err(e) => eprintln!("{} kept its configuration, reloading {} failed: {}", config.node_id, path.display(), e),
+        }
+    }
+
+    eprintln!("{} shutting down", config.node_id);
+    if let Some(dispatcher) = dispatcher {
+        dispatcher.shutdown();
+    }
+    if let Some(api) = api {
+        api.shutdown();
+    }
+    if let Some(sqs) = sqs {
+        sqs.shutdown();
+    }
+    for mirror in mirrors {
+        mirror.shutdown();
+    }
+    // Bridges and connectors stop when dropped
+    drop(bridges);
+    drop(connectors);
+    drop(mqtt);
+    receiver.shutdown();
+    transport.shutdown();
+    Ok(())
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// What the audit trail records about the configuration a node started with
+fn config_summary(config: &ServerConfig) -> String {
+    let mut partitions: Vec<String> = config.partitions.iter().map(|(topic, count)| format!("{}={}", topic, count)).collect();
+    partitions.sort();
+    let mut namespaces: Vec<&str> = config.namespaces.keys().map(String::as_str).collect();
+    namespaces.sort();
+    format!(
+        "peers [{}], partitions [{}], namespaces [{}], acl {}",
+        config.peer_ids().join(", "),
+        partitions.join(", "),
+        namespaces.join(", "),
+        if config.acl.is_some() { "on" } else { "off" },
+    )
+}
+
+fn serve_sqs(node: &Arc<Node<Value>>, config: &ServerConfig) -> std::io::Result<Option<http::HttpServer>> {
+    let Some(addr) = config.sqs else {
+        return Ok(None);
+    };
+    #[cfg(feature = "sqs")]
+    {
+        let server = SqsFacade::new(node.clone()).serve(addr)?;
+        eprintln!("{} serving SQS on {}", config.node_id, addr);
+        Ok(Some(server))
+    }
+    #[cfg(not(feature = "sqs"))]
+    {
+        let _ = (node, addr);
+        Err(std::io::Error::other("sqs is configured but queued was built without the `sqs` feature"))
+    }
+}
+
+#[cfg(feature = "redis")]
+type Bridges = Vec<RedisBridge>;
+#[cfg(not(feature = "redis"))]
+type Bridges = Vec<std::convert::Infallible>;
+
+fn redis_bridges(node: &Arc<Node<Value>>, config: &ServerConfig) -> std::io::Result<Bridges> {
+    let Some(redis) = &config.redis else {
+        return Ok(Vec::new());
+    };
+    #[cfg(feature = "redis")]
+    {
+        let stream = |key: &str| match &redis.password {
+            Some(password) => RedisStream::new(&redis.addr, key).with_password(password),
+            None => RedisStream::new(&redis.addr, key),
+        };
+        let mut bridges = Vec::new();
+        for (topic, key) in &redis.mirror {
+            bridges.push(RedisBridge::mirror(node.clone(), topic, stream(key))?);
+            eprintln!("{} mirroring {} to redis stream {}", config.node_id, topic, key);
+        }
+        for (topic, key) in &redis.ingest {
+            bridges.push(RedisBridge::ingest(node.clone(), topic, stream(key), "$")?);
+            eprintln!("{} ingesting redis stream {} into {}", config.node_id, key, topic);
+        }
+        Ok(bridges)
+    }
+    #[cfg(not(feature = "redis"))]
+    {
+        let _ = (node, redis);
+        Err(std::io::Error::other("redis is configured but queued was built without the `redis` feature"))
+    }
+}
+
+#[cfg(feature = "nats")]
+type Connectors = Vec<NatsConnector>;
+#[cfg(not(feature = "nats"))]
+type Connectors = Vec<std::convert::Infallible>;
+
+fn nats_connectors(node: &Arc<Node<Value>>, config: &ServerConfig) -> std::io::Result<Connectors> {
+    let Some(nats) = &config.nats else {
+        return Ok(Vec::new());
+    };
+    #[cfg(feature = "nats")]
+    {
+        let server = match &nats.token {
+            Some(token) => NatsServer::new(&nats.addr).with_token(token),
+            None => NatsServer::new(&nats.addr),
+        };
+        let mut connectors = Vec::new();
+        for (topic, subject) in &nats.publish {
+            connectors.push(NatsConnector::publish(node.clone(), topic, &server, subject)?);
+            eprintln!("{} publishing {} to nats {}.*", config.node_id, topic, subject);
+        }
+        for (topic, subject) in &nats.subscribe {
+            connectors.push(NatsConnector::subscribe(node.clone(), topic, &server, subject, nats.queue_group.as_deref())?);
+            eprintln!("{} enqueuing nats {} onto {}", config.node_id, subject, topic);
+        }
+        Ok(connectors)
+    }
+    #[cfg(not(feature = "nats"))]
+    {
+        let _ = (node, nats);
+        Err(std::io::Error::other("nats is configured but queued was built without the `nats` feature"))
+    }
+}
+
+#[cfg(feature = "mqtt")]
+type MqttBridges = Vec<MqttBridge>;
+#[cfg(not(feature = "mqtt"))]
+type MqttBridges = Vec<std::convert::Infallible>;
+
+fn mqtt_bridges(node: &Arc<Node<Value>>, config: &ServerConfig) -> std::io::Result<MqttBridges> {
+    let Some(mqtt) = &config.mqtt else {
+        return Ok(Vec::new());
+    };
+    #[cfg(feature = "mqtt")]
+    {
+        let mut broker = MqttBroker::new(&mqtt.addr);
+        if let Some(prefix) = &mqtt.client_id {
+            broker = broker.with_client_id(prefix);
+        }
+        if let Some(username) = &mqtt.username {
+            broker = broker.with_credentials(username, mqtt.password.clone());
+        }
+        let qos = QoS::try_from(mqtt.qos)?;
+        let mut bridges = Vec::new();
+        for (topic, filter) in &mqtt.subscribe {
+            bridges.push(MqttBridge::ingest(node.clone(), topic, &broker, filter, qos)?);
+            eprintln!("{} enqueuing mqtt {} onto {} (QoS {})", config.node_id, filter, topic, mqtt.qos);
+        }
+        Ok(bridges)
+    }
+    #[cfg(not(feature = "mqtt"))]
+    {
+        let _ = (node, mqtt);
+        Err(std::io::Error::other("mqtt is configured but queued was built without the `mqtt` feature"))
+    }
+}
+
+#[cfg(feature = "auth")]
+fn keyring(auth: &AuthConfig) -> Keyring {
+    auth.keys.iter().fold(Keyring::new(), |ring, (id, key)| ring.with_key(id, key))
+}
+
+fn with_auth(node: Node<Value>, config: &ServerConfig) -> std::io::Result<Node<Value>> {
+    let Some(auth) = &config.auth else {
+        return Ok(node);
+    };
+    #[cfg(feature = "auth")]
+    {
+        if !auth.keys.contains_key(&config.node_id) {
+            return Err(std::io::Error::other(format!("auth keys have no entry for {}", config.node_id)));
+        }
+        Ok(node.with_keyring(keyring(auth)))
+    }
+    #[cfg(not(feature = "auth"))]
+    {
+        let _ = (auth, node);
+        Err(std::io::Error::other("auth is configured but queued was built without the `auth` feature"))
+    }
+}
+
+fn encryption(config: &ServerConfig) -> std::io::Result<Cipher> {
+    let Some(encryption) = &config.encryption else {
+        return Ok(None);
+    };
+    #[cfg(feature = "encrypt")]
+    {
+        let mut keys = Vec::new();
+        for (id, key) in &encryption.keys {
+            let id: u32 = id.parse().map_err(|_| std::io::Error::other(format!("encryption key ID {} is not a number", id)))?;
+            keys.push((id, PayloadCipher::parse_key(key)?));
+        }
+        keys.sort_by_key(|(id, _)| *id);
+        let Some((active, key)) = keys.pop() else {
+            return Err(std::io::Error::other("encryption is configured without keys"));
+        };
+        Ok(Some(keys.into_iter().fold(PayloadCipher::new(active, key), |cipher, (id, key)| cipher.with_key(id, key))))
+    }
+    #[cfg(not(feature = "encrypt"))]
+    {
+        let _ = encryption;
+        Err(std::io::Error::other("encryption is configured but queued was built without the `encrypt` feature"))
+    }
+}
+
+/// Topic log file, encrypted with a key derived for the topic when configured
+#[cfg_attr(not(feature = "encrypt"), allow(unused_variables))]
+fn log_sink(path: &Path, topic: &str, format: LogFormat, cipher: &Cipher) -> std::io::Result<Box<dyn LogSink<Value>>> {
+    if format != LogFormat::Ndjson {
+        return Ok(Box::new(LogWriter::append_to(path, format)?));
+    }
+    let sink = NdjsonSink::append_to(path)?;
+    #[cfg(feature = "encrypt")]
+    if let Some(cipher) = cipher {
+        return Ok(Box::new(sink.with_cipher(cipher.for_queue(topic))));
+    }
+    Ok(Box::new(sink))
+}
+
+fn bind_transport(config: &ServerConfig, peers: &[(&str, SocketAddr)]) -> std::io::Result<TcpTransport<Value>> {
+    let Some(files) = &config.tls else {
+        return with_compression(TcpTransport::bind(&config.node_id, config.listen, peers)?, config);
+    };
+    #[cfg(feature = "tls")]
+    {
+        let tls = TlsConfig::from_pem_files(&files.cert, &files.key, &files.ca)?;
+        with_compression(TcpTransport::bind_tls(&config.node_id, config.listen, peers, tls)?, config)
+    }
+    #[cfg(not(feature = "tls"))]
+    {
+        let _ = files;
+        Err(std::io::Error::other("tls is configured but queued was built without the `tls` feature"))
+    }
+}
+
+fn with_compression(transport: TcpTransport<Value>, config: &ServerConfig) -> std::io::Result<TcpTransport<Value>> {
+    let Some(compression) = &config.compression else {
+        return Ok(transport);
+    };
+    #[cfg(feature = "compress")]
+    {
+        let mut settings = Compression::zstd();
+        if let Some(threshold) = compression.threshold {
+            settings = settings.with_threshold(threshold);
+        }
+        if let Some(level) = compression.level {
+            settings = settings.with_level(level);
+        }
+        Ok(transport.with_compression(settings))
+    }
+    #[cfg(not(feature = "compress"))]
+    {
+        let _ = (compression, transport);
+        Err(std::io::Error::other("compression is configured but queued was built without the `compress` feature"))
+    }
+}
+
+fn shell(addr: &str) -> std::io::Result<()> {
+    let mut shell = Shell::connect(addr)?;
+    if let Ok(token) = std::env::var("QUEUE_TOKEN") {
+        shell = shell.with_token(token);
+    }
+    eprintln!("attached to {} (type `help` for commands)", addr);
+    shell.run(std::io::stdin().lock(), std::io::stdout())
+}
@@ -0,0 +1,37 @@
+//! Terminal dashboard for running nodes: `queuetop <node-addr>... [--interval-ms N]`
+//! Set `QUEUE_TOKEN` for nodes with access control.
+
+use std::time::Duration;
+use DistributedQueueMini::engine::dashboard::Dashboard;
+
+/// Refresh interval when none is given
+const DEFAULT_INTERVAL: Duration = Duration::from_millis(500);
+
+fn main() {
+    let mut addrs = Vec::new();
+    let mut interval = DEFAULT_INTERVAL;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--interval-ms" {
+            let ms = args.next().and_then(|ms| ms.parse().ok()).unwrap_or_else(|| {
+                eprintln!("--interval-ms needs a number");
+                std::process::exit(2);
+            });
+            interval = Duration::from_millis(ms);
+        } else {
+            addrs.push(arg);
+        }
+    }
+    if addrs.is_empty() {
+        eprintln!("usage: queuetop <node-addr>... [--interval-ms N]");
+        std::process::exit(2);
+    }
+    let mut dashboard = Dashboard::new(&addrs);
+    if let Ok(token) = std::env::var("QUEUE_TOKEN") {
+        dashboard = dashboard.with_token(token);
+    }
+    if let Err(e) = dashboard.run(interval) {
+        eprintln!("queuetop: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,244 @@
+//! C API
+//! `extern "C"` functions around a `DistributedQueueSystem<Vec<u8>>`, for embedding a node
+//! in C and C++ applications. Items are byte buffers; events are JSON byte buffers the
+//! application carries between nodes however it likes (its own sockets, shared memory, ...),
+//! handing the ones it receives to `dq_apply_remote_event`.
+//! The header is generated into `include/distributed_queue.h` by the build script.
+//!
+//! Buffers returned to the caller belong to it and are released with `dq_buffer_free`;
+//! nodes are released with `dq_node_free`.
+
+use std::ffi::{CStr, c_char};
+use std::ptr;
+use std::slice;
+use crate::core::buildcore::{DistributedQueueSystem, Event, QueueError};
+
+/// Status of a C API call; calls that report a count or flag return it as a non-negative value instead
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DqStatus {
+    Ok = 0,
+    /// A required pointer was NULL
+    NullArgument = -1,
+    /// A string wasn't UTF-8, or an event buffer wasn't a valid event
+    InvalidArgument = -2,
+    /// Enqueue rejected: queue at capacity
+    Full = -3,
+    /// Enqueue rejected: over the rate limit or a quota
+    Throttled = -4,
+    /// Any other queue error
+    Failed = -5,
+}
+
+impl From<QueueError> for DqStatus {
+    fn from(e: QueueError) -> Self {
+        match e {
+            QueueError::Full { .. } | QueueError::FullBytes { .. } => DqStatus::Full,
+            QueueError::RateLimited { .. } | QueueError::QuotaExceeded { .. } => DqStatus::Throttled,
+            _ => DqStatus::Failed,
+        }
+    }
+}
+
+/// Byte buffer handed to the caller, released with `dq_buffer_free`
+/// `data` is NULL for an empty buffer
+#[repr(C)]
+#[derive(Debug)]
+pub struct DqBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl DqBuffer {
+    fn empty() -> Self {
+        Self { data: ptr::null_mut(), len: 0 }
+    }
+
+    fn from_vec(bytes: Vec<u8>) -> Self {
+        if bytes.is_empty() {
+            return Self::empty();
+        }
+        let len = bytes.len();
+        let data = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+        Self { data, len }
+    }
+}
+
+/// A queue node, opaque to C
+pub struct DqNode {
+    queue: DistributedQueueSystem<Vec<u8>>,
+}
+
+fn encode_event(event: &Event<Vec<u8>>) -> DqBuffer {
+    DqBuffer::from_vec(serde_json::to_vec(event).expect("events serialize"))
+}
+
+/// Write `buffer` to `out` if the caller asked for it
+unsafe fn store(out: *mut DqBuffer, buffer: DqBuffer) {
+    if out.is_null() {
+        unsafe { dq_buffer_free(buffer) };
+    } else {
+        unsafe { out.write(buffer) };
+    }
+}
+
+/// Create node `node_id` replicating with the `peer_count` nodes in `peers`
+/// Returns NULL if a name is NULL or not UTF-8.
+///
+/// # Safety
+/// `node_id` and the first `peer_count` entries of `peers` must be NUL-terminated strings;
+/// `peers` may be NULL if `peer_count` is 0.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dq_node_new(node_id: *const c_char, peers: *const *const c_char, peer_count: usize) -> *mut DqNode {
+    if node_id.is_null() || (peers.is_null() && peer_count > 0) {
+        return ptr::null_mut();
+    }
+    let Ok(node_id) = unsafe { CStr::from_ptr(node_id) }.to_str() else {
+        return ptr::null_mut();
+    };
+    let peers = if peer_count == 0 { &[][..] } else { unsafe { slice::from_raw_parts(peers, peer_count) } };
+    let mut names = Vec::with_capacity(peers.len());
+    for &peer in peers {
+        if peer.is_null() {
+            return ptr::null_mut();
+        }
+        match unsafe { CStr::from_ptr(peer) }.to_str() {
+            Ok(name) => names.push(name),
+            Err(_) => return ptr::null_mut(),
+        }
+    }
+    let queue = DistributedQueueSystem::new_with_nodes(node_id.to_string(), &names);
+    Box::into_raw(Box::new(DqNode { queue }))
+}
+
+/// Release a node created by `dq_node_new`; NULL is ignored
+///
+/// # Safety
+/// `node` must come from `dq_node_new` and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dq_node_free(node: *mut DqNode) {
+    if !node.is_null() {
+        drop(unsafe { Box::from_raw(node) });
+    }
+}
+
+/// Enqueue a copy of the `len` bytes at `data`
+/// On success writes the enqueue event to `event_out` (if not NULL), to be sent to every peer.
+///
+/// # Safety
+/// `node` must be a live node; `data` must point to `len` readable bytes (or be NULL if `len` is 0);
+/// `event_out` must be NULL or writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dq_enqueue(node: *const DqNode, data: *const u8, len: usize, event_out: *mut DqBuffer) -> DqStatus {
+    let Some(node) = (unsafe { node.as_ref() }) else {
+        return DqStatus::NullArgument;
+    };
+    if data.is_null() && len > 0 {
+        return DqStatus::NullArgument;
+    }
+    let item = if len == 0 { Vec::new() } else { unsafe { slice::from_raw_parts(data, len) }.to_vec() };
+    match node.queue.try_enqueue(item) {
+        Ok(event) => {
+            unsafe { store(event_out, encode_event(&event)) };
+            DqStatus::Ok
+        }
+        Err(e) => e.into(),
+    }
+}
+
+/// Dequeue the next item into `item_out`
+/// Returns 1 if an item was dequeued, 0 if the queue was empty, or a negative `DqStatus`.
+/// The dequeue event goes to `event_out` (if not NULL) either way, to be sent to every peer.
+///
+/// # Safety
+/// `node` must be a live node; `item_out` and `event_out` must be NULL or writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dq_dequeue(node: *const DqNode, item_out: *mut DqBuffer, event_out: *mut DqBuffer) -> i32 {
+    let Some(node) = (unsafe { node.as_ref() }) else {
+        return DqStatus::NullArgument as i32;
+    };
+    let (item, event) = node.queue.dequeue();
+    unsafe { store(event_out, encode_event(&event)) };
+    let found = item.is_some();
+    let item = item.map_or_else(DqBuffer::empty, |item| DqBuffer::from_vec(item.to_vec()));
+    unsafe { store(item_out, item) };
+    found as i32
+}
+
+/// Apply an event buffer received from another node
+/// Returns 1 if applied, 0 if it was a duplicate or is buffered until its causal
+/// predecessors arrive, or a negative `DqStatus`.
+///
+/// # Safety
+/// `node` must be a live node; `event` must point to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dq_apply_remote_event(node: *const DqNode, event: *const u8, len: usize) -> i32 {
+    let Some(node) = (unsafe { node.as_ref() }) else {
+        return DqStatus::NullArgument as i32;
+    };
+    if event.is_null() {
+        return DqStatus::NullArgument as i32;
+    }
+    let bytes = unsafe { slice::from_raw_parts(event, len) };
+    match serde_json::from_slice::<Event<Vec<u8>>>(bytes) {
+        Ok(event) => node.queue.apply_remote_event(event) as i32,
+        Err(_) => DqStatus::InvalidArgument as i32,
+    }
+}
+
+/// Take the events the node created while applying remote ones (e.g. quota rejections)
+/// Writes them to `events_out` as a JSON array, each element an event to send to every peer
+/// and apply with `dq_apply_remote_event`. Returns how many there were, or a negative `DqStatus`.
+///
+/// # Safety
+/// `node` must be a live node; `events_out` must be writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dq_take_outgoing(node: *const DqNode, events_out: *mut DqBuffer) -> i64 {
+    let Some(node) = (unsafe { node.as_ref() }) else {
+        return DqStatus::NullArgument as i64;
+    };
+    if events_out.is_null() {
+        return DqStatus::NullArgument as i64;
+    }
+    let events = node.queue.take_outgoing();
+    let bytes = serde_json::to_vec(&events).expect("events serialize");
+    unsafe { events_out.write(DqBuffer::from_vec(bytes)) };
+    events.len() as i64
+}
+
+/// Items currently queued; 0 for NULL
+///
+/// # Safety
+/// `node` must be NULL or a live node.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dq_len(node: *const DqNode) -> usize {
+    unsafe { node.as_ref() }.map_or(0, |node| node.queue.queue_state().0)
+}
+
+/// Current queue stats as a JSON object, see `QueueStats`
+///
+/// # Safety
+/// `node` must be a live node; `stats_out` must be writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dq_stats(node: *const DqNode, stats_out: *mut DqBuffer) -> DqStatus {
+    let Some(node) = (unsafe { node.as_ref() }) else {
+        return DqStatus::NullArgument;
+    };
+    if stats_out.is_null() {
+        return DqStatus::NullArgument;
+    }
+    let bytes = serde_json::to_vec(&node.queue.stats()).expect("stats serialize");
+    unsafe { stats_out.write(DqBuffer::from_vec(bytes)) };
+    DqStatus::Ok
+}
+
+/// Release a buffer returned by this API
+///
+/// # Safety
+/// `buffer` must come from this API, unmodified, and not be freed twice.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dq_buffer_free(buffer: DqBuffer) {
+    if !buffer.data.is_null() {
+        drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(buffer.data, buffer.len)) });
+    }
+}
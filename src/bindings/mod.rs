@@ -0,0 +1,8 @@
+//! Bindings for other languages, each behind its own feature
+
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,156 @@
+//! Python bindings
+//! A `distributed_queue` extension module wrapping `DistributedQueueSystem<serde_json::Value>`,
+//! so the queue and its replication can be scripted from Python, e.g. in notebooks.
+//! Items are any JSON-like Python value (None, bool, int, float, str, list, tuple, dict with str keys);
+//! events, log entries and stats come back as dicts shaped like their JSON serialization,
+//! and an event dict from one node can be handed to `apply_remote_event` on another.
+
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyDict, PyFloat, PyInt, PyList, PyString, PyTuple};
+use serde::Serialize;
+use serde_json::{Map, Number, Value};
+use crate::core::buildcore::{DistributedQueueSystem as Inner, Event};
+
+/// Convert a Python value to the JSON value stored as the item
+fn to_json(obj: &Bound<'_, PyAny>) -> PyResult<Value> {
+    if obj.is_none() {
+        return Ok(Value::Null);
+    }
+    // bool before int: Python bools are ints
+    if let Ok(b) = obj.cast::<PyBool>() {
+        return Ok(Value::Bool(b.is_true()));
+    }
+    if obj.is_instance_of::<PyInt>() {
+        if let Ok(n) = obj.extract::<i64>() {
+            return Ok(Value::from(n));
+        }
+        return obj.extract::<u64>().map(Value::from)
+            .map_err(|_| PyValueError::new_err("integer out of 64-bit range"));
+    }
+    if let Ok(f) = obj.cast::<PyFloat>() {
+        return Number::from_f64(f.value()).map(Value::Number)
+            .ok_or_else(|| PyValueError::new_err("NaN and infinity aren't valid items"));
+    }
+    if let Ok(s) = obj.cast::<PyString>() {
+        return Ok(Value::String(s.to_str()?.to_owned()));
+    }
+    if let Ok(list) = obj.cast::<PyList>() {
+        return list.iter().map(|v| to_json(&v)).collect::<PyResult<_>>().map(Value::Array);
+    }
+    if let Ok(tuple) = obj.cast::<PyTuple>() {
+        return tuple.iter().map(|v| to_json(&v)).collect::<PyResult<_>>().map(Value::Array);
+    }
+    if let Ok(dict) = obj.cast::<PyDict>() {
+        let mut map = Map::new();
+        for (k, v) in dict.iter() {
+            let key = k.cast::<PyString>()
+                .map_err(|_| PyTypeError::new_err("dict keys must be str"))?;
+            map.insert(key.to_str()?.to_owned(), to_json(&v)?);
+        }
+        return Ok(Value::Object(map));
+    }
+    Err(PyTypeError::new_err(format!("can't convert {} to a queue item", obj.get_type().name()?)))
+}
+
+/// Convert a JSON value to the matching Python value
+fn to_py<'py>(py: Python<'py>, value: &Value) -> PyResult<Bound<'py, PyAny>> {
+    Ok(match value {
+        Value::Null => py.None().into_bound(py),
+        Value::Bool(b) => PyBool::new(py, *b).to_owned().into_any(),
+        Value::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => i.into_pyobject(py)?.into_any(),
+            (None, Some(u)) => u.into_pyobject(py)?.into_any(),
+            _ => PyFloat::new(py, n.as_f64().unwrap_or(f64::NAN)).into_any(),
+        },
+        Value::String(s) => PyString::new(py, s).into_any(),
+        Value::Array(items) => {
+            let items = items.iter().map(|v| to_py(py, v)).collect::<PyResult<Vec<_>>>()?;
+            PyList::new(py, items)?.into_any()
+        }
+        Value::Object(map) => {
+            let dict = PyDict::new(py);
+            for (k, v) in map {
+                dict.set_item(k, to_py(py, v)?)?;
+            }
+            dict.into_any()
+        }
+    })
+}
+
+/// Convert anything serializable (events, log entries, stats) to Python via its JSON form
+fn serialized<'py>(py: Python<'py>, value: &impl Serialize) -> PyResult<Bound<'py, PyAny>> {
+    let json = serde_json::to_value(value).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    to_py(py, &json)
+}
+
+/// One node's queue, replicated by passing event dicts between nodes
+#[pyclass(name = "DistributedQueueSystem", module = "distributed_queue")]
+pub struct DistributedQueueSystem {
+    inner: Inner<Value>,
+}
+
+#[pymethods]
+impl DistributedQueueSystem {
+    /// Node `node_id`, replicating with `peers`
+    #[new]
+    #[pyo3(signature = (node_id, peers = Vec::new()))]
+    fn new(node_id: String, peers: Vec<String>) -> Self {
+        let peers: Vec<&str> = peers.iter().map(String::as_str).collect();
+        Self { inner: Inner::new_with_nodes(node_id, &peers) }
+    }
+
+    #[getter]
+    fn node_id(&self) -> &str {
+        self.inner.node_id()
+    }
+
+    /// Enqueue an item; returns the event to replicate to the other nodes
+    fn enqueue<'py>(&self, py: Python<'py>, item: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyAny>> {
+        let event = self.inner.try_enqueue(to_json(item)?)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        serialized(py, &event)
+    }
+
+    /// Dequeue the next item; returns `(item, event)`, with `item` None if the queue was empty
+    fn dequeue<'py>(&self, py: Python<'py>) -> PyResult<(Bound<'py, PyAny>, Bound<'py, PyAny>)> {
+        let (item, event) = self.inner.dequeue();
+        let item = match item {
+            Some(item) => to_py(py, &item)?,
+            None => py.None().into_bound(py),
+        };
+        Ok((item, serialized(py, &event)?))
+    }
+
+    /// Apply an event dict from another node; False if it was a duplicate or got buffered
+    fn apply_remote_event(&self, event: &Bound<'_, PyAny>) -> PyResult<bool> {
+        let event: Event<Value> = serde_json::from_value(to_json(event)?)
+            .map_err(|e| PyValueError::new_err(format!("not an event: {}", e)))?;
+        Ok(self.inner.apply_remote_event(event))
+    }
+
+    /// The in-memory log, oldest entry first
+    fn logs<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyAny>>> {
+        self.inner.logs().iter().map(|entry| serialized(py, entry)).collect()
+    }
+
+    /// Queue depth, buffered events, log length and clock
+    fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        serialized(py, &self.inner.stats())
+    }
+
+    /// Items currently queued
+    fn __len__(&self) -> usize {
+        self.inner.queue_state().0
+    }
+
+    fn __repr__(&self) -> String {
+        format!("DistributedQueueSystem({:?}, depth={})", self.inner.node_id(), self.inner.queue_state().0)
+    }
+}
+
+/// The `distributed_queue` Python module
+#[pymodule]
+pub fn distributed_queue(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<DistributedQueueSystem>()
+}
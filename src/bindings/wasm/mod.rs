@@ -0,0 +1,93 @@
+//! JS bindings
+//! A `DistributedQueueSystem` class wrapping `DistributedQueueSystem<serde_json::Value>` for
+//! wasm32-unknown-unknown, so a browser page can run several nodes side by side and decide
+//! when (and whether) each event reaches each peer, e.g. to visualize causal delivery.
+//! Items are any JSON-compatible JS value; events, log entries and stats are plain objects
+//! shaped like their JSON serialization, and an event from one node can be handed to
+//! `applyRemoteEvent` on another.
+
+use serde::Serialize;
+use serde_json::Value;
+use serde_wasm_bindgen::Serializer;
+use wasm_bindgen::prelude::*;
+use crate::core::buildcore::{DistributedQueueSystem as Inner, Event};
+
+/// Convert to a JS value, with maps (clocks) as plain objects rather than `Map`s
+fn to_js(value: &impl Serialize) -> Result<JsValue, JsError> {
+    value.serialize(&Serializer::json_compatible()).map_err(|e| JsError::new(&e.to_string()))
+}
+
+/// One node's queue, replicated by passing event objects between nodes
+#[wasm_bindgen(js_name = DistributedQueueSystem)]
+pub struct WasmQueue {
+    inner: Inner<Value>,
+}
+
+#[wasm_bindgen(js_class = DistributedQueueSystem)]
+impl WasmQueue {
+    /// Node `nodeId`, replicating with `peers`
+    #[wasm_bindgen(constructor)]
+    pub fn new(node_id: String, peers: Vec<String>) -> WasmQueue {
+        let peers: Vec<&str> = peers.iter().map(String::as_str).collect();
+        WasmQueue { inner: Inner::new_with_nodes(node_id, &peers) }
+    }
+
+    #[wasm_bindgen(getter, js_name = nodeId)]
+    pub fn node_id(&self) -> String {
+        self.inner.node_id().to_string()
+    }
+
+    /// Items currently queued
+    #[wasm_bindgen(getter)]
+    pub fn depth(&self) -> usize {
+        self.inner.queue_state().0
+    }
+
+    /// Enqueue an item; returns the event to replicate to the other nodes
+    pub fn enqueue(&self, item: JsValue) -> Result<JsValue, JsError> {
+        let item: Value = serde_wasm_bindgen::from_value(item).map_err(|e| JsError::new(&e.to_string()))?;
+        let event = self.inner.try_enqueue(item)?;
+        to_js(&event)
+    }
+
+    /// Dequeue the next item; returns `{ item, event }`, with `item` null if the queue was empty
+    pub fn dequeue(&self) -> Result<JsValue, JsError> {
+        #[derive(Serialize)]
+        struct Dequeued<'a> {
+            item: Option<&'a Value>,
+            event: &'a Event<Value>,
+        }
+        let (item, event) = self.inner.dequeue();
+        to_js(&Dequeued { item: item.as_deref(), event: &event })
+    }
+
+    /// Apply an event object from another node; false if it was a duplicate or got buffered
+    #[wasm_bindgen(js_name = applyRemoteEvent)]
+    pub fn apply_remote_event(&self, event: JsValue) -> Result<bool, JsError> {
+        let event: Event<Value> = serde_wasm_bindgen::from_value(event)
+            .map_err(|e| JsError::new(&format!("not an event: {}", e)))?;
+        Ok(self.inner.apply_remote_event(event))
+    }
+
+    /// Events created while applying remote ones (e.g. quota rejections), to replicate like local ones
+    #[wasm_bindgen(js_name = takeOutgoing)]
+    pub fn take_outgoing(&self) -> Result<JsValue, JsError> {
+        to_js(&self.inner.take_outgoing())
+    }
+
+    /// Remote events waiting in the causal buffer
+    #[wasm_bindgen(js_name = pendingEvents)]
+    pub fn pending_events(&self) -> usize {
+        self.inner.pending_events_count()
+    }
+
+    /// The in-memory log, oldest entry first
+    pub fn logs(&self) -> Result<JsValue, JsError> {
+        to_js(&self.inner.logs())
+    }
+
+    /// Queue depth, buffered events, log length and clock
+    pub fn stats(&self) -> Result<JsValue, JsError> {
+        to_js(&self.inner.stats())
+    }
+}
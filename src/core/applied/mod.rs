@@ -0,0 +1,110 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::sync::Mutex;
+use crate::core::nodeid::NodeId;
+
+/// Default number of shards for the applied-events map
+const DEFAULT_SHARDS: usize = 16;
+
+/// Applied event IDs per origin node, sharded by origin so that events
+/// from different nodes don't serialize on a single lock
+pub struct AppliedEvents {
+    shards: Vec<Mutex<HashMap<NodeId, Applied>>>,
+    hasher: RandomState,
+}
+
+/// One origin's applied events: IDs with the origin's clock entry, and the clock
+/// entry at or below which records were collected (see `prune`)
+#[derive(Default)]
+struct Applied {
+    ids: HashMap<u64, u64>,
+    floor: u64,
+}
+
+impl AppliedEvents {
+    /// Create a tracker with the default shard count
+    pub(crate) fn new() -> Self {
+        Self::with_shards(DEFAULT_SHARDS)
+    }
+
+    /// Create a tracker with a given number of shards (at least one)
+    pub(crate) fn with_shards(count: usize) -> Self {
+        let count = count.max(1);
+        Self {
+            shards: (0..count).map(|_| Mutex::new(HashMap::new())).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Pick the shard responsible for an origin node
+    fn shard(&self, origin: NodeId) -> &Mutex<HashMap<NodeId, Applied>> {
+        let index = self.hasher.hash_one(origin) as usize % self.shards.len();
+        &self.shards[index]
+    }
+
+    /// Check whether an event from `origin` has already been applied; `time` is the
+    /// origin's entry in the event's clock
+    pub(crate) fn contains(&self, origin: NodeId, event_id: u64, time: u64) -> bool {
+        let shard = self.shard(origin).lock().unwrap();
+        shard.get(&origin).is_some_and(|applied| (0 < time && time <= applied.floor) || applied.ids.contains_key(&event_id))
+    }
+
+    /// Mark an event as applied; returns false if it was already recorded
+    pub(crate) fn insert(&self, origin: NodeId, event_id: u64, time: u64) -> bool {
+        let mut shard = self.shard(origin).lock().unwrap();
+        shard.entry(origin).or_default().ids.insert(event_id, time).is_none()
+    }
+
+    /// Forget the events of each origin at or below its entry in `stable`, answering for
+    /// them by clock entry from then on; returns how many records were removed
+    /// Only sound if events are delivered in order per origin, i.e. causal consistency
+    pub(crate) fn prune(&self, stable: &HashMap<NodeId, u64>) -> usize {
+        let mut removed = 0;
+        for shard in &self.shards {
+            for (origin, applied) in shard.lock().unwrap().iter_mut() {
+                let Some(&floor) = stable.get(origin).filter(|&&floor| floor > applied.floor) else {
+                    continue;
+                };
+                let before = applied.ids.len();
+                applied.ids.retain(|_, time| *time > floor);
+                removed += before - applied.ids.len();
+                applied.floor = floor;
+            }
+        }
+        removed
+    }
+
+    /// Estimated bytes held by the records
+    pub(crate) fn estimated_bytes(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| {
+                let shard = shard.lock().unwrap();
+                shard.capacity() * size_of::<(NodeId, Applied)>() + shard.values().map(|applied| applied.ids.capacity() * size_of::<(u64, u64)>()).sum::<usize>()
+            })
+            .sum()
+    }
+
+    /// Forget the events of an origin that restarted, answering for them by clock entry:
+    /// those of its earlier incarnations are all at or below `floor`
+    pub(crate) fn restart(&self, origin: NodeId, floor: u64) {
+        let mut shard = self.shard(origin).lock().unwrap();
+        let applied = shard.entry(origin).or_default();
+        applied.ids.retain(|_, time| *time > floor);
+        applied.floor = applied.floor.max(floor);
+    }
+
+    /// Number of applied events still recorded individually, across all shards
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|s| s.lock().unwrap().values().map(|applied| applied.ids.len()).sum::<usize>())
+            .sum()
+    }
+
+    /// Check if no events have been applied
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
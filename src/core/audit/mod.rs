@@ -0,0 +1,162 @@
+//! Append-only audit trail, kept apart from the operation log
+//! Records refused client operations and administrative actions: purges, state
+//! overrides, membership and configuration changes
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use serde::{Deserialize, Serialize};
+use crate::core::event::now_ms;
+use crate::core::storage::AuditStore;
+#[cfg(feature = "persist")]
+use std::io::Write;
+
+/// Audit entries kept in memory; older ones only survive in the writer
+const DEFAULT_RETAINED: usize = 1024;
+
+/// One audited action
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp_ms: u64,         // wall-clock time the action was recorded
+    pub actor: String,             // identity that attempted the action
+    pub action: String,            // e.g. "produce", "consume", "purge", "member_add"
+    pub topic: Option<String>,
+    pub allowed: bool,
+    pub detail: Option<String>,
+}
+
+impl AuditEntry {
+    /// Entry for an action that was refused
+    pub fn denied(actor: &str, action: &str, topic: &str) -> Self {
+        Self {
+            timestamp_ms: now_ms(),
+            actor: actor.to_string(),
+            action: action.to_string(),
+            topic: Some(topic.to_string()),
+            allowed: false,
+            detail: None,
+        }
+    }
+
+    /// Entry for an administrative action that was carried out
+    pub fn admin(actor: &str, action: &str, topic: Option<&str>, detail: impl Into<String>) -> Self {
+        Self {
+            timestamp_ms: now_ms(),
+            actor: actor.to_string(),
+            action: action.to_string(),
+            topic: topic.map(str::to_string),
+            allowed: true,
+            detail: Some(detail.into()),
+        }
+    }
+}
+
+/// Audit trail: recent entries in memory, every entry to the store and writer if there are any
+pub struct AuditLog {
+    recent: Mutex<VecDeque<AuditEntry>>,
+    retained: usize,
+    store: Option<Mutex<Box<dyn AuditStore>>>,
+    #[cfg(feature = "persist")]
+    writer: Option<Mutex<Box<dyn Write + Send>>>,
+    /// Entries the store or writer failed to take
+    failed: AtomicU64,
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AuditLog {
+    pub fn new() -> Self {
+        Self {
+            recent: Mutex::new(VecDeque::new()),
+            retained: DEFAULT_RETAINED,
+            store: None,
+            #[cfg(feature = "persist")]
+            writer: None,
+            failed: AtomicU64::new(0),
+        }
+    }
+
+    /// Also append every entry to `writer` as NDJSON
+    #[cfg(feature = "persist")]
+    pub fn with_writer(mut self, writer: Box<dyn Write + Send>) -> Self {
+        self.writer = Some(Mutex::new(writer));
+        self
+    }
+
+    /// Also append every entry to `store`, where `page` reads them back
+    pub fn with_store(mut self, store: Box<dyn AuditStore>) -> Self {
+        self.store = Some(Mutex::new(store));
+        self
+    }
+
+    /// Append to the file at `path` as checksummed NDJSON, creating it if needed
+    #[cfg(feature = "persist")]
+    pub fn append_to(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let file = crate::core::storage::AuditFile::open(path)?;
+        Ok(Self::new().with_store(Box::new(file)))
+    }
+
+    /// Record an entry; it stays among the recent ones even if the store or writer fails
+    pub fn record(&self, entry: AuditEntry) {
+        if let Some(store) = &self.store
+            && store.lock().unwrap().append(&entry).is_err()
+        {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+        }
+        #[cfg(feature = "persist")]
+        if let Some(writer) = &self.writer {
+            let mut writer = writer.lock().unwrap();
+            let written = serde_json::to_string(&entry)
+                .map_err(std::io::Error::other)
+                .and_then(|line| writeln!(writer, "{}", line))
+                .and_then(|_| writer.flush());
+            if written.is_err() {
+                self.failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() >= self.retained {
+            recent.pop_front();
+        }
+        recent.push_back(entry);
+    }
+
+    /// How many times the store or writer failed to take an entry
+    pub fn write_failures(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Recent entries, oldest first
+    pub fn entries(&self) -> Vec<AuditEntry> {
+        self.recent.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Read up to `limit` entries from position `offset` of the store, or of the
+    /// in-memory entries without one
+    pub fn page(&self, offset: usize, limit: usize) -> io::Result<Vec<AuditEntry>> {
+        match &self.store {
+            Some(store) => store.lock().unwrap().read(offset, limit),
+            None => Ok(self.recent.lock().unwrap().iter().skip(offset).take(limit).cloned().collect()),
+        }
+    }
+
+    /// Number of entries in the store, 0 without one
+    pub fn stored(&self) -> usize {
+        self.store.as_ref().map_or(0, |store| store.lock().unwrap().len())
+    }
+
+    /// Number of entries held in memory
+    pub fn len(&self) -> usize {
+        self.recent.lock().unwrap().len()
+    }
+
+    /// Check if nothing is held in memory
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
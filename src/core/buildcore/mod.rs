@@ -1,178 +1,1577 @@
 use std::cmp::Reverse;
 pub use crate::core::{
-    queue::{Queue, SafeQueue},
+    queue::{DequeuePolicy, Queue, SafeQueue},
     clock::{VectorClock, SafeVectorClock},
-    log::{LogEntry, Logger, SafeLogger, State},
-    event::{Event, EventOp}
+    log::{LogEntry, LogView, Logger, SafeLogger, State},
+    storage::{AuditStore, LogStore, LogSink, MemoryStore},
+    event::{Event, EventOp, KeySequence, EPOCH_SHIFT},
+    applied::AppliedEvents,
+    nodeid::NodeId,
+    builder::{DistributedQueueSystemBuilder, ConsistencyLevel, ClockType, DEFAULT_MAX_CLOCK_SKEW, DEFAULT_MAX_EVENT_GAP},
+    error::{EventRejection, QueueError},
+    metrics::{Counter, Metrics},
+    snapshot::{Snapshot, SnapshotDelta},
+    stats::{MemoryStats, QueueStats},
+    audit::{AuditEntry, AuditLog},
+    retention::{GcReport, RetentionPolicy},
+    ratelimit::{RateLimit, RateLimiter, Throttle},
+    size::MessageSize,
+    retry::RetryPolicy,
+    redelivery::FailedEvent,
+    quarantine::QuarantinedEvent,
+    stalled::{BufferedEvent, MissingEvents},
+    signal::ArrivalSignal,
+    control::{ControlOp, DeliveryMode, TopicConfig},
+    lease::Lease,
+    history::{Divergence, HistoryDiff, HistoryEvent, Reordered, diff_histories},
+    trace::TraceContext,
 };
-use std::collections::{BinaryHeap, HashMap, HashSet};
-use std::sync::{Arc, Mutex};
+#[cfg(feature = "persist")]
+pub use crate::core::storage::{AuditFile, FileStore, NdjsonSink, SnapshotChain, SnapshotFile, LogReport, verify_log};
+#[cfg(feature = "persist")]
+pub use crate::core::storage::{LogFormat, LogReader, LogWriter, read_logs};
+#[cfg(feature = "persist")]
+pub use crate::core::storage::export_csv;
+#[cfg(feature = "parquet")]
+pub use crate::core::storage::export_parquet;
+#[cfg(feature = "encrypt")]
+pub use crate::core::storage::PayloadCipher;
+#[cfg(feature = "metrics")]
+pub use crate::core::metrics::MetricsSnapshot;
+#[cfg(feature = "payload")]
+pub use crate::core::payload::Payload;
+#[cfg(any(feature = "net", feature = "persist", feature = "payload"))]
+pub use crate::core::size::serialized_size;
+#[cfg(feature = "net")]
+pub(crate) use crate::core::quarantine::panic_message;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use crate::core::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::core::event::now_ms;
+use crate::core::redelivery::MAX_FAILED_EVENTS;
+use crate::core::quarantine::{self, MAX_QUARANTINED};
+use crate::core::builder::{ItemSizeFn, OrderingKeyFn};
+use crate::core::id;
+
+/// Deliveries remembered for `fail`; older ones can no longer be reported failed
+const MAX_TRACKED_DELIVERIES: usize = 4096;
 
 /// Unified Queue System Builder
 pub struct DistributedQueueSystem<T> {
-    node_id:String,
-    queue: SafeQueue<T>,
+    node_id:NodeId,
+    queue: SafeQueue<Arc<T>>, // payloads are shared with events and log entries
     logger: SafeLogger<T>,
     clock: SafeVectorClock,
-    applied_events: Mutex<HashMap<String, HashSet<u64>>>, // Track applied events per node to prevent duplicates
+    applied_events: AppliedEvents, // Track applied events per node to prevent duplicates (sharded by origin)
     event_buffer: Mutex<BinaryHeap<Reverse<Event<T>>>>, // Event buffer for ordering (events that arrived out of order)
+    max_buffered_events: RwLock<Option<usize>>, // changeable at runtime, like the rate limits
+    consistency: ConsistencyLevel,
+    metrics: Metrics,
+    peer_clocks: Mutex<HashMap<NodeId, HashMap<NodeId, u64>>>, // latest clock seen in each peer's events
+    departed: Mutex<HashSet<NodeId>>, // peers removed by `ControlOp::MemberRemoved`, no longer holding back the watermark
+    clock_skew: Mutex<HashMap<NodeId, i64>>, // latest wall-clock offset per peer, in ms
+    max_clock_skew: Duration,
+    max_event_gap: u64,
+    transferred: Mutex<VecDeque<(u64, Arc<T>, u32)>>, // items transferred to us, waiting to be handed out: transfer id, item, failures so far
+    rate_limiter: RwLock<Option<RateLimiter>>, // all local enqueues
+    client_rate_limiter: RwLock<Option<RateLimiter>>, // local enqueues per client identity
+    node_quotas: HashMap<NodeId, usize>, // most unconsumed items per origin node
+    default_node_quota: Option<usize>,
+    outbox: Mutex<Vec<Event<T>>>, // events created while applying remote ones, to be broadcast
+    retry: RwLock<Option<RetryPolicy>>, // replaced by a `TopicConfig` with max_attempts
+    default_retry: Option<RetryPolicy>, // the builder's, for configs without max_attempts
+    redelivery: RetryPolicy,
+    redeliveries: Mutex<Vec<(Instant, Event<T>, u32)>>, // remote events waiting out their backoff, with attempts so far
+    failed_events: Mutex<VecDeque<FailedEvent>>,
+    quarantine: Mutex<VecDeque<QuarantinedEvent<T>>>,
+    deliveries: Mutex<VecDeque<(u64, Arc<T>, u32)>>, // recent local dequeues: event id, item, failures so far
+    attempts: Mutex<HashMap<(NodeId, u64), u32>>, // failures so far of queued retries, by enqueue source
+    retries: Mutex<Vec<(Instant, Arc<T>, u32)>>, // failed items waiting out their backoff
+    redelivered: AtomicU64, // failed items enqueued again, here or by replicas
+    dead_letters: Mutex<Vec<Arc<T>>>,
+    traces: Mutex<HashMap<(NodeId, u64), TraceContext>>, // trace contexts of queued traced items, by enqueue source
+    paused: AtomicBool,
+    lease_duration: Option<Duration>, // leases are required to dequeue with one
+    lease: Mutex<Option<Lease>>, // latest of `ControlOp::LeaseGranted`
+    held_until: AtomicU64, // expiry of the latest lease of ours that every peer acknowledged
+    token_hold: Option<Duration>, // the token is required to dequeue with one
+    token: Mutex<(u64, Option<NodeId>, Instant)>, // round, holder (the first ring member before any pass), and when it arrived here
+    deleted: AtomicBool, // set by `ControlOp::TopicDeleted`
+    topic_config: Mutex<Option<(TopicConfig, (u64, NodeId))>>, // latest of `ControlOp::TopicCreated` and `TopicConfigured`, with version and origin
+    defaults: TopicConfig, // the builder's settings, which topic configs override
+    ttl: RwLock<Option<Duration>>,
+    delivery_mode: RwLock<DeliveryMode>,
+    read_only: bool,
+    control_config: Mutex<BTreeMap<String, String>>, // settings from `ControlOp::ConfigUpdated`
+    arrivals: Arc<ArrivalSignal>, // notified when an item is queued or transferred to us
+    ordering_key: Option<OrderingKeyFn<T>>, // keys of local enqueues for keyed FIFO
+    item_size: ItemSizeFn<T>, // estimated bytes of an item, for memory stats
+    key_sequences: Mutex<HashMap<String, u64>>, // highest sequence seen per ordering key
+    epoch: u64,
+    epochs: Mutex<HashMap<NodeId, u64>>, // newest epoch seen in each origin's events
 }
 
-impl<T: Clone + Send + 'static> DistributedQueueSystem<T> {
-    /// Create a new QueueSystem
+impl<T: Send + Sync + 'static> DistributedQueueSystem<T> {
+    /// Create a new QueueSystem that learns its peers from incoming events
     pub fn new(node_id:String) -> Self {
-        Self {
-            queue: Arc::new(Mutex::new(Queue::new())),
-            logger: Arc::new(Mutex::new(Logger::new(node_id.clone()))),
-            clock: Arc::new(VectorClock::new_single(&node_id)),
-            applied_events: Mutex::new(HashMap::new()),
-            event_buffer: Mutex::new(BinaryHeap::new()),
-            node_id,
-        }
+        Self::builder(node_id).clock_type(ClockType::Dynamic).build()
     }
 
     /// Create a new QueueSystem with known nodes
     pub fn new_with_nodes(node_id:String, nodes: &[&str]) -> Self {
-        Self{
-            node_id: node_id.clone(),
-            queue: Arc::new(Mutex::new(Queue::new())),
-            logger: Arc::new(Mutex::new(Logger::new(node_id.clone()))),
-            clock: Arc::new(VectorClock::new(&node_id, nodes)),
-            applied_events: Mutex::new(HashMap::new()),
-            event_buffer: Mutex::new(BinaryHeap::new())
+        Self::builder(node_id).peers(nodes).build()
+    }
+
+    /// Start configuring a QueueSystem
+    pub fn builder(node_id: impl Into<String>) -> DistributedQueueSystemBuilder<T> {
+        DistributedQueueSystemBuilder::new(node_id)
+    }
+
+    pub(crate) fn from_builder(builder: DistributedQueueSystemBuilder<T>) -> Self {
+        let node_id = NodeId::intern(&builder.node_id);
+        if let Some(worker) = builder.id_worker {
+            id::assign_worker(&builder.node_id, worker);
+        }
+        let peers: Vec<&str> = builder.peers.iter().map(|p| p.as_str()).collect();
+        let mut clock = VectorClock::new(node_id.as_str(), &peers);
+        if builder.clock_type == ClockType::Dynamic {
+            clock = clock.with_dynamic_membership();
+        }
+        clock.merge(&HashMap::from([(node_id, builder.epoch << EPOCH_SHIFT)]));
+
+        let mut logger = Logger::new(node_id);
+        if let Some(max) = builder.max_log_entries {
+            logger = logger.with_max_entries(max);
+        }
+        if let Some(store) = builder.log_store {
+            logger = logger.with_store(store);
+        }
+        if let Some(sink) = builder.log_sink {
+            logger = logger.with_sink(sink);
+        }
+        if let Some(key_fn) = builder.log_key {
+            logger = logger.with_key_fn(key_fn);
+        }
+        if let Some(policy) = builder.retention {
+            logger = logger.with_retention(policy);
+        }
+
+        let mut queue = Queue::with_capacity(builder.queue_capacity).with_policy(builder.dequeue_policy);
+        if let Some((max, size)) = builder.max_bytes {
+            queue = queue.with_max_bytes(max, Box::new(move |item: &Arc<T>| size(item)));
+        }
+        let defaults = TopicConfig {
+            capacity: builder.queue_capacity,
+            max_attempts: builder.retry.map(|policy| policy.max_attempts()),
+            ttl_ms: builder.ttl.map(|ttl| ttl.as_millis() as u64),
+            delivery_mode: Some(builder.delivery_mode),
+            priority: Some(builder.priority.is_some()),
+            ..TopicConfig::default()
+        };
+        if let Some(priority) = builder.priority {
+            queue = queue.with_priority(Arc::new(move |item: &Arc<T>| priority(item)));
+        }
+
+        Self {
+            node_id,
+            queue: Arc::new(Mutex::new(queue)),
+            logger: Arc::new(Mutex::new(logger)),
+            clock: Arc::new(clock),
+            applied_events: AppliedEvents::new(),
+            event_buffer: Mutex::new(BinaryHeap::new()),
+            max_buffered_events: RwLock::new(builder.max_buffered_events),
+            consistency: builder.consistency,
+            metrics: Metrics::new(),
+            peer_clocks: Mutex::new(HashMap::new()),
+            departed: Mutex::new(HashSet::new()),
+            clock_skew: Mutex::new(HashMap::new()),
+            max_clock_skew: builder.max_clock_skew,
+            max_event_gap: builder.max_event_gap,
+            transferred: Mutex::new(VecDeque::new()),
+            rate_limiter: RwLock::new(builder.rate_limit.map(|(limit, throttle)| RateLimiter::new(limit, throttle))),
+            client_rate_limiter: RwLock::new(builder.client_rate_limit.map(|(limit, throttle)| RateLimiter::new(limit, throttle))),
+            node_quotas: builder.node_quotas.iter().map(|(node, &max)| (NodeId::intern(node), max)).collect(),
+            default_node_quota: builder.default_node_quota,
+            outbox: Mutex::new(Vec::new()),
+            retry: RwLock::new(builder.retry),
+            default_retry: builder.retry,
+            redelivery: builder.redelivery,
+            redeliveries: Mutex::new(Vec::new()),
+            failed_events: Mutex::new(VecDeque::new()),
+            quarantine: Mutex::new(VecDeque::new()),
+            deliveries: Mutex::new(VecDeque::new()),
+            attempts: Mutex::new(HashMap::new()),
+            retries: Mutex::new(Vec::new()),
+            redelivered: AtomicU64::new(0),
+            dead_letters: Mutex::new(Vec::new()),
+            traces: Mutex::new(HashMap::new()),
+            paused: AtomicBool::new(false),
+            lease_duration: builder.lease,
+            lease: Mutex::new(None),
+            held_until: AtomicU64::new(0),
+            token_hold: builder.token_hold,
+            token: Mutex::new((0, None, Instant::now())),
+            deleted: AtomicBool::new(false),
+            topic_config: Mutex::new(None),
+            defaults,
+            ttl: RwLock::new(builder.ttl),
+            delivery_mode: RwLock::new(builder.delivery_mode),
+            read_only: builder.read_only,
+            control_config: Mutex::new(BTreeMap::new()),
+            arrivals: builder.arrivals.unwrap_or_default(),
+            ordering_key: builder.ordering_key,
+            item_size: builder.item_size,
+            key_sequences: Mutex::new(HashMap::new()),
+            epoch: builder.epoch,
+            epochs: Mutex::new(HashMap::new()),
         }
     }
 
     /// Enqueue with logging + clock
-    pub fn enqueue(&self, item: T) -> Event<T> {
+    /// Fails if the node is read-only or paused, or the queue is at capacity or over its rate limit
+    pub fn enqueue(&self, item: T) -> Result<Event<T>, QueueError> {
+        self.enqueue_arc(Arc::new(item))
+    }
+
+    /// Enqueue an already shared payload without copying it
+    pub fn enqueue_arc(&self, item: Arc<T>) -> Result<Event<T>, QueueError> {
+        self.try_enqueue_arc(item)
+    }
+
+    /// Enqueue, failing if the queue is at capacity
+    pub fn try_enqueue(&self, item: T) -> Result<Event<T>, QueueError> {
+        self.try_enqueue_arc(Arc::new(item))
+    }
+
+    /// Enqueue on behalf of a client identity, subject to the per-client rate limit
+    pub fn try_enqueue_as(&self, identity: &str, item: T) -> Result<Event<T>, QueueError> {
+        self.try_enqueue_traced_as(identity, item, None)
+    }
+
+    /// Enqueue an item that is part of a distributed trace
+    /// The enqueue event carries `trace` to the replicas, and the item's dequeue event carries it on
+    pub fn try_enqueue_traced(&self, item: T, trace: TraceContext) -> Result<Event<T>, QueueError> {
+        self.enqueue_traced(Arc::new(item), Some(trace))
+    }
+
+    /// `try_enqueue_as` with the trace context the client sent, if any
+    pub fn try_enqueue_traced_as(&self, identity: &str, item: T, trace: Option<TraceContext>) -> Result<Event<T>, QueueError> {
+        self.throttle(self.client_rate_limiter.read().unwrap().as_ref(), identity)?;
+        self.enqueue_traced(Arc::new(item), trace)
+    }
+
+    /// Enqueue a shared payload, failing if the queue is at capacity or over its rate limit
+    /// Capacity and rate limits only apply to local enqueues; replicated events are always applied
+    pub fn try_enqueue_arc(&self, item: Arc<T>) -> Result<Event<T>, QueueError> {
+        self.enqueue_traced(item, None)
+    }
+
+    fn enqueue_traced(&self, item: Arc<T>, trace: Option<TraceContext>) -> Result<Event<T>, QueueError> {
+        self.admit_local()?;
+        let queue = self.queue.lock().unwrap();
+        self.check_room(&queue, &item)?;
+        Ok(self.append_enqueue(queue, item, trace))
+    }
+
+    /// Check that a local enqueue of `item` would be admitted, taking a rate limit token,
+    /// without enqueueing it; for enqueues proposed to a Raft log, see `enqueue_committed`
+    #[cfg(feature = "net")]
+    pub(crate) fn admit(&self, item: T) -> Result<T, QueueError> {
+        self.admit_local()?;
+        let item = Arc::new(item);
+        self.check_room(&self.queue.lock().unwrap(), &item)?;
+        Ok(Arc::into_inner(item).expect("the item isn't shared yet"))
+    }
+
+    /// Enqueue an item a Raft log committed, without the checks `admit` ran before it was
+    /// proposed, so every node applies the log alike whatever its own limits and state
+    #[cfg(feature = "net")]
+    pub(crate) fn enqueue_committed(&self, item: T) -> Event<T> {
+        self.append_enqueue(self.queue.lock().unwrap(), Arc::new(item), None)
+    }
+
+    /// Refuse a local enqueue if the node is read-only or paused, or over its rate limit
+    fn admit_local(&self) -> Result<(), QueueError> {
+        if self.read_only {
+            return Err(QueueError::ReadOnly);
+        }
+        if self.is_paused() {
+            return Err(QueueError::Paused);
+        }
+        self.throttle(self.rate_limiter.read().unwrap().as_ref(), self.node_id.as_str())
+    }
+
+    /// Refuse a local enqueue of `item` the queue has no room or quota for
+    fn check_room(&self, queue: &Queue<Arc<T>>, item: &Arc<T>) -> Result<(), QueueError> {
+        if queue.capacity().is_some_and(|capacity| queue.len() >= capacity) {
+            return Err(QueueError::Full { capacity: queue.capacity().unwrap_or(0) });
+        }
+        if !queue.fits(item) {
+            return Err(QueueError::FullBytes { max_bytes: queue.max_bytes().unwrap_or(0) });
+        }
+        if let Some(quota) = self.over_quota(queue, self.node_id) {
+            self.metrics.inc(Counter::QuotaExceeded);
+            return Err(QueueError::QuotaExceeded { origin: self.node_id.to_string(), quota });
+        }
+        Ok(())
+    }
+
+    /// Enqueue an admitted item, logging it and ticking the clock
+    fn append_enqueue(&self, mut queue: MutexGuard<'_, Queue<Arc<T>>>, item: Arc<T>, trace: Option<TraceContext>) -> Event<T> {
         let vector_time = self.clock.tick_snapshot();
         // Create event before applying to enable broadcasting
-        let event = Event::new_enqueue(self.node_id.clone(), item.clone(), vector_time.clone());
+        let mut event = Event::new_enqueue(self.node_id, item.clone(), vector_time).with_epoch(self.epoch);
+        event.trace = trace;
+        event.sequence = self.next_sequence(&item);
         // Apply the operation locally
-        self.apply_enqueue_op(&item, vector_time, Some(event.global_id), event.clone());
+        queue.enqueue_sequenced(item, self.node_id, event.global_id, event.sequence.clone());
+        drop(queue);
+        self.arrivals.notify();
+        self.remember_trace(&event);
+        self.log("enqueue", State::Committed, Arc::new(event.clone()));
+        self.metrics.inc(Counter::Enqueued);
         event
     }
 
+    /// Next sequence of the item's ordering key, if keyed FIFO is on and the item has a key
+    fn next_sequence(&self, item: &T) -> Option<KeySequence> {
+        let key = self.ordering_key.as_ref()?(item)?;
+        let mut sequences = self.key_sequences.lock().unwrap();
+        let seq = sequences.entry(key.clone()).or_insert(0);
+        *seq += 1;
+        Some(KeySequence { key, seq: *seq })
+    }
+
+    /// Quota `origin` has used up, if it has one
+    fn over_quota(&self, queue: &Queue<Arc<T>>, origin: NodeId) -> Option<usize> {
+        let quota = self.node_quotas.get(&origin).copied().or(self.default_node_quota)?;
+        (queue.count_from(origin) >= quota).then_some(quota)
+    }
+
+    /// Events this queue created while applying remote ones, such as quota rejections
+    /// The caller broadcasts them to peers like its own local operations
+    pub fn take_outgoing(&self) -> Vec<Event<T>> {
+        std::mem::take(&mut *self.outbox.lock().unwrap())
+    }
+
+    /// Take a rate limit token, counting attempts that had to wait or were rejected
+    fn throttle(&self, limiter: Option<&RateLimiter>, key: &str) -> Result<(), QueueError> {
+        let Some(limiter) = limiter else {
+            return Ok(());
+        };
+        if limiter.try_acquire(key).is_ok() {
+            return Ok(());
+        }
+        self.metrics.inc(Counter::Throttled);
+        limiter.acquire(key)
+    }
+
+    /// Most out-of-order events buffered, unbounded if None
+    pub fn max_buffered_events(&self) -> Option<usize> {
+        *self.max_buffered_events.read().unwrap()
+    }
+
+    /// Change the most out-of-order events buffered; events already buffered are kept
+    pub fn set_max_buffered_events(&self, max: Option<usize>) {
+        *self.max_buffered_events.write().unwrap() = max;
+    }
+
+    /// Limit on all local enqueues, if any
+    pub fn rate_limit(&self) -> Option<(RateLimit, Throttle)> {
+        self.rate_limiter.read().unwrap().as_ref().map(|limiter| (limiter.limit(), limiter.throttle()))
+    }
+
+    /// Change the limit on all local enqueues, or lift it with None
+    /// An unchanged limit keeps its buckets, a changed one starts them full
+    pub fn set_rate_limit(&self, limit: Option<(RateLimit, Throttle)>) {
+        replace_limiter(&self.rate_limiter, limit);
+    }
+
+    /// Limit on each client identity's enqueues, if any
+    pub fn client_rate_limit(&self) -> Option<(RateLimit, Throttle)> {
+        self.client_rate_limiter.read().unwrap().as_ref().map(|limiter| (limiter.limit(), limiter.throttle()))
+    }
+
+    /// Change the limit on each client identity's enqueues, as `set_rate_limit`
+    pub fn set_client_rate_limit(&self, limit: Option<(RateLimit, Throttle)>) {
+        replace_limiter(&self.client_rate_limiter, limit);
+    }
+
+    /// Change the log retention policy, or keep everything from now on with None
+    pub fn set_retention(&self, policy: Option<RetentionPolicy<T>>) {
+        self.logger.lock().unwrap().set_retention(policy);
+    }
+
+    /// Remove every item whose log key is `key` from the queue, and its item from every log
+    /// entry, here and (once the returned event is broadcast) on every replica
+    /// Log entries stay in place, marked `redacted`, and the purge is logged as a tombstone;
+    /// sinks only see the tombstone, as what they wrote is out of the log's hands
+    /// Needs a key index, see `log_key`
+    pub fn purge_item(&self, key: &str) -> Result<Event<T>, QueueError> {
+        if !self.logger.lock().unwrap().has_key_index() {
+            return Err(QueueError::NoKeyIndex);
+        }
+        let event = Event::new_purge(self.node_id, key.to_string(), self.clock.tick_snapshot()).with_epoch(self.epoch);
+        self.apply_purge_op(key, event.clone());
+        Ok(event)
+    }
+
+    /// Apply a control action here and return its event, to broadcast so every replica
+    /// applies it at the same point relative to the data operations
+    pub fn control(&self, op: ControlOp) -> Event<T> {
+        let event = Event::new_control(self.node_id, op.clone(), self.clock.tick_snapshot()).with_epoch(self.epoch);
+        self.apply_control_op(op, event.clone());
+        event
+    }
+
+    /// Check if a `ControlOp::Paused` is in effect
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Whether this replica refuses local enqueues and dequeues, see `DistributedQueueSystemBuilder::read_only`
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Whether local dequeues hand out nothing: the queue is paused or read-only, or
+    /// needs a lease this node doesn't hold
+    pub(crate) fn holds_items(&self) -> bool {
+        self.read_only
+            || self.is_paused()
+            || (self.lease_duration.is_some() && !self.holds_lease())
+            || (self.token_hold.is_some() && !self.holds_token())
+    }
+
+    /// Nodes the token circulates among, in name order: the clock's, removed members aside
+    fn token_ring(&self) -> Vec<NodeId> {
+        let departed = self.departed.lock().unwrap().clone();
+        let mut ring: Vec<NodeId> = self.clock.snapshot().into_keys().filter(|node| !departed.contains(node)).collect();
+        ring.sort();
+        ring
+    }
+
+    /// Node after `node` in the token ring, wrapping around
+    fn next_in_ring(&self, node: NodeId) -> Option<NodeId> {
+        let ring = self.token_ring();
+        ring.iter().copied().find(|&next| next > node).or_else(|| ring.first().copied())
+    }
+
+    /// Node holding the queue's dequeue token, see `DistributedQueueSystemBuilder::token_ring`
+    pub fn token_holder(&self) -> Option<NodeId> {
+        let holder = self.token.lock().unwrap().1;
+        holder.or_else(|| self.token_ring().first().copied())
+    }
+
+    /// Whether this node holds the token
+    pub fn holds_token(&self) -> bool {
+        self.token_holder() == Some(self.node_id)
+    }
+
+    /// Pass the token on to the next node once this node has held it long enough; returns
+    /// the pass to broadcast, if any
+    /// The node runs it in its receive loop; a lone node keeps the token
+    pub fn pass_token(&self) -> Option<Event<T>> {
+        let hold = self.token_hold?;
+        if !self.holds_token() {
+            return None;
+        }
+        let (round, _, since) = *self.token.lock().unwrap();
+        let to = self.next_in_ring(self.node_id).filter(|&to| to != self.node_id)?;
+        (since.elapsed() >= hold).then(|| self.control(ControlOp::TokenPassed { to, round: round + 1 }))
+    }
+
+    /// Latest lease granted on the queue, held or not
+    pub fn lease(&self) -> Option<Lease> {
+        *self.lease.lock().unwrap()
+    }
+
+    /// Whether this node holds the queue's lease: the latest grant is ours, and every peer
+    /// acknowledged it or an earlier grant of ours that hasn't expired
+    pub fn holds_lease(&self) -> bool {
+        let Some(lease) = self.lease() else {
+            return false;
+        };
+        if lease.holder != self.node_id {
+            return false;
+        }
+        if self.is_acknowledged(&lease) {
+            self.held_until.fetch_max(lease.expires_ms, Ordering::SeqCst);
+        }
+        now_ms() < self.held_until.load(Ordering::SeqCst)
+    }
+
+    /// Whether every peer has reached the grant of `lease`, going by the clocks in their
+    /// events; peers counted as for the stability watermark
+    fn is_acknowledged(&self, lease: &Lease) -> bool {
+        let departed = self.departed.lock().unwrap().clone();
+        let peer_clocks = self.peer_clocks.lock().unwrap();
+        self.clock
+            .snapshot()
+            .keys()
+            .filter(|&&peer| peer != self.node_id && !departed.contains(&peer))
+            .all(|peer| peer_clocks.get(peer).and_then(|clock| clock.get(&lease.holder)).is_some_and(|&time| time >= lease.granted_at))
+    }
+
+    /// Grant this node the queue's lease for `duration`; returns the grant to broadcast
+    /// It is held once every peer acknowledges it, see `holds_lease`. Refused while another
+    /// node's lease lasts, clock skew included
+    pub fn acquire_lease(&self, duration: Duration) -> Result<Event<T>, QueueError> {
+        let now = now_ms();
+        let current = self.lease();
+        if let Some(lease) = current.filter(|lease| lease.holder != self.node_id)
+            && now < lease.expires_ms + self.max_clock_skew.as_millis() as u64
+        {
+            return Err(QueueError::Leased { holder: lease.holder.to_string(), expires_ms: lease.expires_ms });
+        }
+        let term = current.map_or(0, |lease| lease.term) + 1;
+        Ok(self.control(ControlOp::LeaseGranted { holder: self.node_id, term, expires_ms: now + duration.as_millis() as u64 }))
+    }
+
+    /// Acquire the lease for a dequeue if the queue needs one, or renew ours once half of
+    /// it has passed; returns the grant to broadcast, if any
+    /// Nothing while our latest grant awaits acknowledgement or another node holds it
+    pub fn renew_lease(&self) -> Option<Event<T>> {
+        let duration = self.lease_duration?;
+        if let Some(lease) = self.lease().filter(|lease| lease.holder == self.node_id && now_ms() < lease.expires_ms) {
+            let half_left = lease.expires_ms - now_ms() > duration.as_millis() as u64 / 2;
+            if half_left || !self.is_acknowledged(&lease) {
+                return None;
+            }
+        }
+        self.acquire_lease(duration).ok()
+    }
+
+    /// Whether a `ControlOp::TopicDeleted` was applied; the node drops the queue then
+    pub fn is_deleted(&self) -> bool {
+        self.deleted.load(Ordering::SeqCst)
+    }
+
+    /// Settings the topic was created or last configured with, see `ControlOp::TopicConfigured`
+    pub fn topic_config(&self) -> Option<TopicConfig> {
+        self.topic_config.lock().unwrap().as_ref().map(|(config, _)| config.clone())
+    }
+
+    /// Version of `topic_config`, 0 for the one the topic was created with or without one
+    pub fn topic_config_version(&self) -> u64 {
+        self.topic_config.lock().unwrap().as_ref().map_or(0, |(_, (version, _))| *version)
+    }
+
+    /// Settings in effect: `topic_config` over the builder's
+    /// Retention only shows when configured, as the builder's policy may not be expressible
+    pub fn effective_config(&self) -> TopicConfig {
+        self.defaults.merge(&self.topic_config().unwrap_or_default())
+    }
+
+    /// Settings applied by `ControlOp::ConfigUpdated`, latest value per key
+    pub fn control_config(&self) -> BTreeMap<String, String> {
+        self.control_config.lock().unwrap().clone()
+    }
+
     /// Dequeue an item
     /// Optionally merge with external Lamport clock
-    pub fn dequeue(&self) -> (Option<T>, Event<T>) {
+    /// A paused queue hands out nothing, as if it were empty
+    pub fn dequeue(&self) -> (Option<Arc<T>>, Event<T>) {
+        self.dequeue_held(self.holds_items())
+    }
+
+    /// Dequeue a Raft log committed, handing out the head whatever this node's own state, so
+    /// every node applies the log alike; the proposer checked `holds_items` before
+    #[cfg(feature = "net")]
+    pub(crate) fn dequeue_committed(&self) -> (Option<Arc<T>>, Event<T>) {
+        self.dequeue_held(false)
+    }
+
+    /// Dequeue, handing out nothing if `held`
+    fn dequeue_held(&self, held: bool) -> (Option<Arc<T>>, Event<T>) {
        let vector_time = self.clock.tick_snapshot();
 
         // Perform the actual dequeue
         let mut queue = self.queue.lock().unwrap();
-        let item = queue.dequeue();
+        let dequeued = if held { None } else { queue.dequeue_with_source() };
         drop(queue);
+        let failures = dequeued.as_ref().map_or(0, |(_, source)| self.forget_attempts(*source));
+        let trace = dequeued.as_ref().and_then(|(_, source)| self.take_trace(*source));
+        let item = dequeued.map(|(item, _)| item);
 
         // Create event for broadcasting
-        let event = Event::new_dequeue(self.node_id.clone(), item.clone(), vector_time.clone());
+        let mut event = Event::new_dequeue(self.node_id, item.clone(), vector_time).with_epoch(self.epoch);
+        event.trace = trace;
+        if let Some(item) = item.clone() {
+            event.delivery_attempt = failures + 1;
+            self.track_delivery(event.global_id, item, failures);
+        }
 
         // Log the operation
-        let mut logger = self.logger.lock().unwrap();
-        logger.log("dequeue", item.clone(), State::Delivered, vector_time, Some(event.global_id), event.clone());
+        self.log("dequeue", State::Delivered, Arc::new(event.clone()));
+        self.metrics.inc(Counter::Dequeued);
         (item, event)
 
     }
 
+    /// Dequeue the first item, in arrival order, that `pred` accepts
+    /// The event names the enqueue that added the item, so replicas remove the same one
+    /// Returns None, logging nothing, if no item matches or the queue is paused or read-only
+    pub fn dequeue_if(&self, pred: impl FnMut(&T) -> bool) -> Option<(Arc<T>, Event<T>)> {
+        self.take(pred, false)
+    }
+
+    /// Dequeue the item `dequeue` would take, only if `pred` accepts it, see `dequeue_if`
+    pub fn dequeue_head_if(&self, pred: impl FnMut(&T) -> bool) -> Option<(Arc<T>, Event<T>)> {
+        self.take(pred, true)
+    }
+
+    fn take(&self, mut pred: impl FnMut(&T) -> bool, head_only: bool) -> Option<(Arc<T>, Event<T>)> {
+        if self.holds_items() {
+            return None;
+        }
+        let mut queue = self.queue.lock().unwrap();
+        let (item, source) = queue.take_where(|item| pred(item), head_only)?;
+        let vector_time = self.clock.tick_snapshot();
+        drop(queue);
+        let failures = self.forget_attempts(source);
+        let mut event = Event::new_take(self.node_id, source.0, source.1, item.clone(), vector_time).with_epoch(self.epoch);
+        event.trace = self.take_trace(source);
+        event.delivery_attempt = failures + 1;
+        self.track_delivery(event.global_id, item.clone(), failures);
+        self.log("dequeue", State::Delivered, Arc::new(event.clone()));
+        self.metrics.inc(Counter::Dequeued);
+        Some((item, event))
+    }
+
+    /// Dequeue up to `max` items on behalf of node `to`, e.g. for work stealing
+    /// Each item is a transfer event; the node it names hands the item out once the event reaches it
+    pub fn transfer(&self, to: &str, max: usize) -> Vec<Event<T>> {
+        let to = NodeId::intern(to);
+        let mut events = Vec::new();
+        if self.holds_items() {
+            return events;
+        }
+        for _ in 0..max {
+            let mut queue = self.queue.lock().unwrap();
+            let Some((item, source)) = queue.dequeue_with_source() else {
+                break;
+            };
+            let failures = self.forget_attempts(source);
+            let mut event = Event::new_transfer(self.node_id, to, item.clone(), self.clock.tick_snapshot()).with_epoch(self.epoch);
+            event.trace = self.take_trace(source);
+            drop(queue);
+            self.log("transfer", State::Delivered, Arc::new(event.clone()));
+            self.metrics.inc(Counter::Dequeued);
+            if to == self.node_id {
+                self.transferred.lock().unwrap().push_back((event.global_id, item, failures));
+                self.arrivals.notify();
+            }
+            events.push(event);
+        }
+        events
+    }
+
+    /// Next item transferred to this node, already removed from the queue everywhere
+    pub fn take_transferred(&self) -> Option<Arc<T>> {
+        self.take_transferred_delivery().map(|(_, item)| item)
+    }
+
+    /// Next transferred item with its delivery ID for `fail`, the ID of the transfer event
+    pub fn take_transferred_delivery(&self) -> Option<(u64, Arc<T>)> {
+        let (id, item, failures) = self.transferred.lock().unwrap().pop_front()?;
+        self.track_delivery(id, item.clone(), failures);
+        Some((id, item))
+    }
+
+    /// Number of transferred items not handed out yet
+    pub fn transferred_len(&self) -> usize {
+        self.transferred.lock().unwrap().len()
+    }
+
+    /// Which delivery of its item `delivery` is, 1 for the first, while it can be failed;
+    /// dequeue events carry it too, see `Event::delivery_attempt`
+    pub fn delivery_attempt(&self, delivery: u64) -> Option<u32> {
+        self.deliveries.lock().unwrap().iter().find(|(id, _, _)| *id == delivery).map(|(_, _, failures)| failures + 1)
+    }
+
+    /// Remember an item handed out, so it can be reported failed; only with a retry policy
+    fn track_delivery(&self, id: u64, item: Arc<T>, failures: u32) {
+        if self.retry.read().unwrap().is_none() || *self.delivery_mode.read().unwrap() == DeliveryMode::AtMostOnce {
+            return;
+        }
+        let mut deliveries = self.deliveries.lock().unwrap();
+        if deliveries.len() >= MAX_TRACKED_DELIVERIES {
+            deliveries.pop_front();
+        }
+        deliveries.push_back((id, item, failures));
+    }
+
+    /// Report that a delivered item was processed, so it can no longer be reported failed
+    /// Returns false if the delivery isn't tracked (no retry policy, or too old, or settled)
+    pub fn ack(&self, delivery: u64) -> bool {
+        let mut deliveries = self.deliveries.lock().unwrap();
+        let Some(position) = deliveries.iter().position(|(id, _, _)| *id == delivery) else {
+            return false;
+        };
+        deliveries.remove(position);
+        true
+    }
+
+    /// Report that many delivered items were processed, as one replicated ack event
+    /// Deliveries that aren't tracked are skipped; returns None if none of them were
+    pub fn ack_batch(&self, deliveries: &[u64]) -> Option<Event<T>> {
+        let mut tracked = self.deliveries.lock().unwrap();
+        let mut settled = Vec::new();
+        tracked.retain(|(id, _, _)| {
+            let acked = deliveries.contains(id);
+            if acked {
+                settled.push(*id);
+            }
+            !acked
+        });
+        drop(tracked);
+        if settled.is_empty() {
+            return None;
+        }
+        let event = Event::new_ack(self.node_id, settled, self.clock.tick_snapshot()).with_epoch(self.epoch);
+        self.log("ack", State::Committed, Arc::new(event.clone()));
+        Some(event)
+    }
+
+    /// Report that processing a delivered item failed; `delivery` is the ID of the local
+    /// dequeue that took it, or of the transfer that handed it to this node
+    /// The failure is logged as `Failed`; with a retry policy the item is enqueued again
+    /// once its backoff has passed (see `process_retries`), or dead-lettered when out of attempts
+    pub fn fail(&self, delivery: u64) -> Result<Event<T>, QueueError> {
+        let mut deliveries = self.deliveries.lock().unwrap();
+        let position = deliveries.iter().position(|(id, _, _)| *id == delivery).ok_or(QueueError::UnknownDelivery { delivery })?;
+        let (_, item, failures) = deliveries.remove(position).unwrap();
+        drop(deliveries);
+
+        let policy = self.retry.read().unwrap().expect("deliveries are only tracked with a retry policy");
+        let attempt = failures + 1;
+        let dead_letter = attempt >= policy.max_attempts();
+        let event = Event::new_fail(self.node_id, delivery, attempt, dead_letter, item.clone(), self.clock.tick_snapshot()).with_epoch(self.epoch);
+        self.log("fail", State::Failed, Arc::new(event.clone()));
+        self.metrics.inc(Counter::Failed);
+        if dead_letter {
+            self.logger.lock().unwrap().update_event_state(self.node_id, event.global_id, State::DeadLettered).ok();
+            self.metrics.inc(Counter::DeadLettered);
+            self.dead_letters.lock().unwrap().push(item);
+        } else {
+            self.retries.lock().unwrap().push((Instant::now() + policy.delay(attempt), item, attempt));
+        }
+        Ok(event)
+    }
+
+    /// Move the log entry of event `event_id` from `origin` to `state`, if `State::can_become`
+    /// allows it; returns the state update to broadcast so replicas' entries follow
+    pub fn update_state(&self, origin: &str, event_id: u64, state: State) -> Result<Event<T>, QueueError> {
+        let origin = NodeId::intern(origin);
+        self.logger.lock().unwrap().update_event_state(origin, event_id, state)?;
+        let event = Event::new_state_update(self.node_id, origin, event_id, state, self.clock.tick_snapshot()).with_epoch(self.epoch);
+        self.log("update", state, Arc::new(event.clone()));
+        Ok(event)
+    }
+
+    /// Move an in-memory log entry, by local log ID, to `state`; like `update_state`
+    /// the returned update carries the event ID, so replicas find their own entry
+    pub fn update_entry_state(&self, log_id: u64, state: State) -> Result<Event<T>, QueueError> {
+        let (origin, event_id) = self
+            .log_view()
+            .iter()
+            .find(|entry| entry.local_log_id == log_id)
+            .map(|entry| (entry.event.origin_node, entry.event.global_id))
+            .ok_or(QueueError::UnknownEntry { id: log_id })?;
+        self.update_state(origin.as_str(), event_id, state)
+    }
+
+    /// Enqueue failed items whose backoff has passed; returns the events to broadcast
+    /// Retries go back in regardless of capacity, quotas and rate limits, they were admitted once
+    pub fn process_retries(&self) -> Vec<Event<T>> {
+        let now = Instant::now();
+        let mut retries = self.retries.lock().unwrap();
+        let (due, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut *retries).into_iter().partition(|(at, _, _)| *at <= now);
+        *retries = waiting;
+        drop(retries);
+
+        let mut events = Vec::new();
+        for (_, item, attempt) in due {
+            let mut queue = self.queue.lock().unwrap();
+            let event = Event::new_retry(self.node_id, attempt, item.clone(), self.clock.tick_snapshot()).with_epoch(self.epoch);
+            queue.enqueue(item, self.node_id, event.global_id);
+            drop(queue);
+            self.arrivals.notify();
+            self.attempts.lock().unwrap().insert((self.node_id, event.global_id), attempt);
+            self.log("enqueue", State::Committed, Arc::new(event.clone()));
+            self.metrics.inc(Counter::Retried);
+            self.redelivered.fetch_add(1, Ordering::Relaxed);
+            events.push(event);
+        }
+        events
+    }
+
+    /// Failed items waiting to be retried
+    pub fn pending_retries(&self) -> usize {
+        self.retries.lock().unwrap().len()
+    }
+
+    /// Failed items enqueued again so far, by this node or replicas
+    pub fn redeliveries(&self) -> u64 {
+        self.redelivered.load(Ordering::Relaxed)
+    }
+
+    /// Items enqueued and dequeued so far, replicated ones included; enqueues count retries
+    pub fn totals(&self) -> (u64, u64) {
+        let queue = self.queue.lock().unwrap();
+        (queue.enqueued(), queue.dequeued())
+    }
+
+    /// How long the oldest queued item has waited since it was enqueued (or retried),
+    /// read off the Snowflake ID of its event
+    pub fn oldest_item_age(&self) -> Option<Duration> {
+        let oldest = self.queue.lock().unwrap().sources().map(|&(_, event_id)| id::split_id(event_id).0).min()?;
+        Some(Duration::from_millis(now_ms().saturating_sub(oldest)))
+    }
+
+    /// Drop items queued for longer than the TTL, aged like `oldest_item_age`; returns
+    /// their take events, to broadcast so replicas drop them too
+    /// Read-only replicas wait for those; the node runs it in its receive loop
+    pub fn expire(&self) -> Vec<Event<T>> {
+        let Some(ttl) = *self.ttl.read().unwrap() else {
+            return Vec::new();
+        };
+        if self.read_only {
+            return Vec::new();
+        }
+        let cutoff = now_ms().saturating_sub(ttl.as_millis() as u64);
+        let mut queue = self.queue.lock().unwrap();
+        let expired: Vec<(NodeId, u64)> = queue.sources().filter(|&&(_, event_id)| id::split_id(event_id).0 <= cutoff).copied().collect();
+        let mut events = Vec::new();
+        for (origin, event_id) in expired {
+            if let Some(item) = queue.take_event(origin, event_id) {
+                events.push(Event::new_take(self.node_id, origin, event_id, item, self.clock.tick_snapshot()).with_epoch(self.epoch));
+            }
+        }
+        drop(queue);
+        for event in &events {
+            if let EventOp::Take { origin, event_id } = event.op {
+                self.forget_attempts((origin, event_id));
+                self.take_trace((origin, event_id));
+            }
+            self.log("expire", State::Committed, Arc::new(event.clone()));
+        }
+        events
+    }
+
+    /// Items that failed on their last allowed attempt, here or on a replica
+    pub fn dead_letters(&self) -> Vec<Arc<T>> {
+        self.dead_letters.lock().unwrap().clone()
+    }
+
+    /// Stop tracking a queued retry that left the queue, returning its failures so far
+    fn forget_attempts(&self, source: (NodeId, u64)) -> u32 {
+        self.attempts.lock().unwrap().remove(&source).unwrap_or(0)
+    }
+
+    /// Remember the trace context of a traced enqueue until its item leaves the queue
+    fn remember_trace(&self, event: &Event<T>) {
+        if let Some(trace) = event.trace {
+            self.traces.lock().unwrap().insert((event.origin_node, event.global_id), trace);
+        }
+    }
+
+    /// Trace context of a queued item that left the queue, if it was traced
+    fn take_trace(&self, source: (NodeId, u64)) -> Option<TraceContext> {
+        self.traces.lock().unwrap().remove(&source)
+    }
+
     /// Apply remote event from another node
+    /// False if it was a duplicate, is buffered until its causal predecessors arrive, or
+    /// failed; `try_apply_remote_event` says why
     pub fn apply_remote_event(&self, event: Event<T>) -> bool {
-        // Update our clock with the event's timestamp
-        self.clock.update(&event.clock);
+        self.try_apply_remote_event(event).unwrap_or(false)
+    }
+
+    /// Apply a remote event, reporting why if it couldn't be applied or buffered
+    /// Ok(false) for duplicates and for events buffered until their causal predecessors arrive.
+    /// Events failing for a transient reason are redelivered after a backoff (see
+    /// `process_redeliveries`); invalid ones go straight to `failed_events`.
+    pub fn try_apply_remote_event(&self, event: Event<T>) -> Result<bool, QueueError> {
+        self.attempt_remote_event(event, 1)
+    }
+
+    /// Apply or buffer a remote event on its `attempt`th try, scheduling it again if that fails
+    fn attempt_remote_event(&self, event: Event<T>, attempt: u32) -> Result<bool, QueueError> {
+        let (origin, event_id) = (event.origin_node, event.global_id);
+        self.apply_or_buffer(event).map_err(|failed| {
+            let (error, event) = *failed;
+            if error.is_transient() && attempt < self.redelivery.max_attempts() {
+                let due = Instant::now() + self.redelivery.delay(attempt);
+                self.redeliveries.lock().unwrap().push((due, event, attempt));
+            } else {
+                let mut failed = self.failed_events.lock().unwrap();
+                if failed.len() >= MAX_FAILED_EVENTS {
+                    failed.pop_front();
+                }
+                failed.push_back(FailedEvent { origin, event_id, attempts: attempt, error: error.to_string(), failed_at_ms: now_ms() });
+            }
+            error
+        })
+    }
+
+    /// Apply a remote event, or buffer it until its causal predecessors arrive
+    /// Hands the event back with the error if it can be neither
+    fn apply_or_buffer(&self, event: Event<T>) -> Result<bool, Box<(QueueError, Event<T>)>> {
+        let epoch = self.check_epoch(&event);
         // Check for duplicates
-        {
-            let mut applied = self.applied_events.lock().unwrap();
-            let node_events = applied.entry(event.origin_node.clone()).or_insert_with(HashSet::new);
-            if node_events.contains(&event.global_id) {
-                return false;  // Already applied
+        if epoch.is_ok() && self.applied_events.contains(event.origin_node, event.global_id, event.clock.get(&event.origin_node).copied().unwrap_or(0)) {
+            self.metrics.inc(Counter::RemoteDuplicates);
+            return Ok(false);  // Already applied
+        }
+        let restart = match epoch.and_then(|restart| self.validate(&event, restart).map(|()| restart)) {
+            Ok(restart) => restart,
+            Err(reason) => {
+                self.metrics.inc(Counter::RemoteInvalid);
+                let error = QueueError::InvalidEvent { origin: event.origin_node.to_string(), event_id: event.global_id, reason };
+                return Err(Box::new((error, event)));
             }
+        };
+        if let Some(start) = restart {
+            self.restart_origin(&event, start);
         }
+        self.measure_skew(&event);
 
         // Check if we can apply this even immediately or need to buffer it
-        if self.can_apply_event(&event) {
+        if self.consistency == ConsistencyLevel::Eventual || self.can_apply_event(&event) {
             self.apply_event_immediately(event);
             self.process_buffered_events();
-            true
+            Ok(true)
         } else{
-            // Buffer the event for later processing
+            // Buffer the event for later processing, unless the buffer is full
             let mut buffer = self.event_buffer.lock().unwrap();
+            if let Some(max) = self.max_buffered_events().filter(|&max| buffer.len() >= max) {
+                self.metrics.inc(Counter::BufferDropped);
+                return Err(Box::new((QueueError::BufferFull { capacity: max }, event)));
+            }
             buffer.push(Reverse(event));
-            false
+            self.metrics.inc(Counter::RemoteBuffered);
+            Ok(false)
         }
     }
 
-    /// Check if an event can be applied (causal consistency)
-    fn can_apply_event(&self, event: &Event<T>) -> bool {
-        // With vector clocks, we should check if the event's vector clock
-        // is consistent with our current state. For now, simplified logic:
+    /// Apply remote events whose redelivery backoff has passed; returns how many were applied
+    /// Run it periodically, like `process_retries`; events created meanwhile wait in `take_outgoing`
+    pub fn process_redeliveries(&self) -> usize {
+        let now = Instant::now();
+        let mut redeliveries = self.redeliveries.lock().unwrap();
+        let (due, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut *redeliveries).into_iter().partition(|(at, _, _)| *at <= now);
+        *redeliveries = waiting;
+        drop(redeliveries);
+
+        due.into_iter().filter(|(_, event, attempt)| {
+            matches!(self.attempt_remote_event(event.clone(), attempt + 1), Ok(true))
+        }).count()
+    }
+
+    /// Remote events waiting to be applied again
+    pub fn pending_redeliveries(&self) -> usize {
+        self.redeliveries.lock().unwrap().len()
+    }
+
+    /// Remote events given up on, oldest first: invalid ones, and ones still failing after
+    /// every redelivery attempt
+    pub fn failed_events(&self) -> Vec<FailedEvent> {
+        self.failed_events.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Remote events set aside because applying them panicked, oldest first
+    pub fn quarantined(&self) -> Vec<QuarantinedEvent<T>> {
+        self.quarantine.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Compare a remote event's epoch with the newest seen from its origin
+    /// Events of an older epoch, or of one too large to start a clock at, are rejected. The
+    /// first of a newer one means the origin restarted; it gets where the new epoch's clock
+    /// starts, for `restart_origin` once the event is valid.
+    fn check_epoch(&self, event: &Event<T>) -> Result<Option<u64>, EventRejection> {
+        if event.epoch >> (u64::BITS - EPOCH_SHIFT) != 0 {
+            return Err(EventRejection::EpochTooLarge { epoch: event.epoch });
+        }
+        let newest = self.epochs.lock().unwrap().get(&event.origin_node).copied().unwrap_or(0);
+        if event.epoch < newest {
+            return Err(EventRejection::OldEpoch { epoch: event.epoch, newest });
+        }
+        Ok((event.epoch > newest).then_some(event.epoch << EPOCH_SHIFT))
+    }
+
+    /// Take a valid event of a newer epoch as a restart of its origin: its delivered events
+    /// here move up to `start`, and events of its earlier incarnations still buffered are dropped
+    fn restart_origin(&self, event: &Event<T>, start: u64) {
+        let origin = event.origin_node;
+        let mut epochs = self.epochs.lock().unwrap();
+        // Another event of the epoch may have got here first
+        if epochs.get(&origin).is_some_and(|&newest| newest >= event.epoch) {
+            return;
+        }
+        epochs.insert(origin, event.epoch);
+        drop(epochs);
+        self.clock.merge(&HashMap::from([(origin, start)]));
+        self.applied_events.restart(origin, start);
+        self.event_buffer.lock().unwrap().retain(|Reverse(buffered)| buffered.origin_node != origin || buffered.epoch >= event.epoch);
+    }
+
+    /// Check a remote event before it is applied or buffered
+    /// The first event of a newer epoch counts from `restart`, where that epoch's clock starts
+    fn validate(&self, event: &Event<T>, restart: Option<u64>) -> Result<(), EventRejection> {
+        let needs_item = match event.op {
+            EventOp::Enqueue | EventOp::Transfer { .. } | EventOp::Fail { .. } | EventOp::Retry { .. } | EventOp::Take { .. } => Some(true),
+            EventOp::Reject { .. } | EventOp::StateUpdate { .. } | EventOp::Purge { .. } | EventOp::Control { .. } | EventOp::Ack { .. } => Some(false),
+            EventOp::Dequeue => None, // empty dequeues carry no item
+        };
+        match (needs_item, event.item.is_some()) {
+            (Some(true), false) => return Err(EventRejection::MissingItem),
+            (Some(false), true) => return Err(EventRejection::UnexpectedItem),
+            _ => {}
+        }
+        let origin_time = event.clock.get(&event.origin_node).copied().unwrap_or(0);
+        if origin_time == 0 {
+            return Err(EventRejection::MissingOriginTime);
+        }
+        let delivered = self.clock.get(event.origin_node).max(restart.unwrap_or(0));
+        if origin_time.saturating_sub(delivered) > self.max_event_gap {
+            return Err(EventRejection::TooFarAhead { origin_time, delivered, max_gap: self.max_event_gap });
+        }
+        // Causal delivery applies each origin's events in order, so an earlier origin
+        // time can only be a replay; eventual consistency may apply them in any order
+        if self.consistency == ConsistencyLevel::Causal && origin_time <= delivered {
+            return Err(EventRejection::Stale { origin_time, delivered });
+        }
+        Ok(())
+    }
+
+    /// Log an operation, then apply the retention policy if there is one
+    fn log(&self, op: &str, state: State, event: Arc<Event<T>>) {
+        let mut logger = self.logger.lock().unwrap();
+        if logger.log(op, state, event).is_err() {
+            self.metrics.inc(Counter::StoreFailed);
+        }
+        if logger.has_retention() {
+            drop(logger);
+            if self.enforce_retention().is_err() {
+                self.metrics.inc(Counter::StoreFailed);
+            }
+        }
+    }
+
+    /// Remove log entries the retention policy no longer keeps; returns how many were removed
+    /// Runs after every logged operation; call it directly to expire entries by age on an idle queue
+    pub fn enforce_retention(&self) -> std::io::Result<usize> {
+        let watermark = self.stability_watermark();
+        let mut logger = self.logger.lock().unwrap();
+        logger.enforce_retention(&watermark, SystemTime::now())
+    }
+
+    /// The stable frontier: clock every known node is known to have reached, the pointwise
+    /// minimum of our own clock and the latest clock seen in each peer's events or heartbeats
+    /// Every node has applied every event below it, so log entries there can be compacted,
+    /// snapshots there agree everywhere, and deliveries there won't be replayed anywhere.
+    /// Peers we haven't heard from hold the watermark at zero; removed members don't count
+    pub fn stability_watermark(&self) -> HashMap<NodeId, u64> {
+        let own = self.clock.snapshot();
+        let departed = self.departed.lock().unwrap().clone();
+        let peer_clocks = self.peer_clocks.lock().unwrap();
+        let peers: Vec<NodeId> = own.keys().copied().filter(|&n| n != self.node_id && !departed.contains(&n)).collect();
+        own.iter()
+            .map(|(&node, &time)| {
+                let min = peers
+                    .iter()
+                    .map(|peer| peer_clocks.get(peer).and_then(|c| c.get(&node)).copied().unwrap_or(0))
+                    .fold(time, u64::min);
+                (node, min)
+            })
+            .collect()
+    }
+
+    /// Drop what the stable frontier makes unnecessary: records of applied remote events,
+    /// duplicates below it being recognized by clock (causal consistency only), and the log
+    /// entries the retention policy lets go, tombstones included, archived first if it archives
+    pub fn collect_garbage(&self) -> std::io::Result<GcReport> {
+        let watermark = self.stability_watermark();
+        let applied = match self.consistency {
+            ConsistencyLevel::Causal => self.applied_events.prune(&watermark),
+            ConsistencyLevel::Eventual => 0,
+        };
+        let log_entries = self.logger.lock().unwrap().enforce_retention(&watermark, SystemTime::now())?;
+        Ok(GcReport { applied, log_entries })
+    }
+
+    /// Applied remote events still recorded individually for duplicate detection
+    pub fn applied_records(&self) -> usize {
+        self.applied_events.len()
+    }
+
+    /// Whether every node has applied `event`, i.e. it is below the stable frontier
+    pub fn is_stable(&self, event: &Event<T>) -> bool {
+        let reached = event.clock.get(&event.origin_node).copied().unwrap_or(0);
+        self.stability_watermark().get(&event.origin_node).is_some_and(|&stable| stable >= reached)
+    }
+
+    /// Record a clock `peer` reports having reached, e.g. from a heartbeat, so the
+    /// stability watermark advances without waiting for the peer's next event
+    pub fn observe_peer_clock(&self, peer: NodeId, clock: &HashMap<NodeId, u64>) {
+        if peer == self.node_id {
+            return;
+        }
+        let mut peer_clocks = self.peer_clocks.lock().unwrap();
+        let seen = peer_clocks.entry(peer).or_default();
+        for (&node, &time) in clock {
+            let known = seen.entry(node).or_insert(0);
+            *known = (*known).max(time);
+        }
+    }
+
+    /// Events peers are known to have delivered that this queue hasn't yet, going by the
+    /// latest clocks seen in their events and heartbeats
+    pub fn lag(&self) -> u64 {
+        let own = self.clock.snapshot();
+        let mut ahead: HashMap<NodeId, u64> = HashMap::new();
+        for clock in self.peer_clocks.lock().unwrap().values() {
+            for (&node, &time) in clock {
+                let known = ahead.entry(node).or_insert(0);
+                *known = (*known).max(time);
+            }
+        }
+        ahead.iter().map(|(node, &time)| time.saturating_sub(own.get(node).copied().unwrap_or(0))).sum()
+    }
+
+    /// Events this node originated that a peer at `clock` hasn't delivered yet, oldest first
+    /// Read back from the log, so entries retention removed and redacted items can't be resent
+    pub fn events_missing_from(&self, clock: &HashMap<NodeId, u64>) -> std::io::Result<Vec<Event<T>>> {
+        let reached = clock.get(&self.node_id).copied().unwrap_or(0);
+        if self.clock.snapshot().get(&self.node_id).copied().unwrap_or(0) <= reached {
+            return Ok(Vec::new());
+        }
+        let mut sent = HashSet::new();
+        let mut missing: Vec<Event<T>> = self
+            .logs_since(clock)?
+            .into_iter()
+            .filter(|entry| !entry.redacted && entry.event.origin_node == self.node_id)
+            .filter(|entry| entry.event.clock.get(&self.node_id).copied().unwrap_or(0) > reached)
+            .filter(|entry| sent.insert(entry.event.global_id))
+            .map(|entry| (*entry.event).clone())
+            .collect();
+        missing.sort_by_key(|event| event.clock.get(&self.node_id).copied().unwrap_or(0));
+        Ok(missing)
+    }
+
+    /// Positions of the log entries still held, counting every entry ever logged
+    /// `log_page` offsets are relative to the start of this range
+    pub fn retained_range(&self) -> Range<usize> {
+        self.logger.lock().unwrap().retained_range()
+    }
+
+    /// Up to `limit` log entries from position `position` of `retained_range` on, or from the
+    /// oldest retained one if retention discarded it; returns the position of the first entry
+    pub fn log_from(&self, position: usize, limit: usize) -> std::io::Result<(usize, Vec<LogEntry<T>>)> {
+        self.logger.lock().unwrap().read_from(position, limit)
+    }
+
+    /// Compare the origin's wall clock with ours as the event arrives
+    fn measure_skew(&self, event: &Event<T>) {
+        if event.timestamp_ms == 0 || event.origin_node == self.node_id {
+            return;
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as i64);
+        let skew = event.timestamp_ms as i64 - now;
+        let limit = self.max_clock_skew.as_millis() as i64;
+        self.clock_skew.lock().unwrap().insert(event.origin_node, skew);
+        if skew.abs() > limit {
+            self.metrics.inc(Counter::ClockSkewed);
+        }
+    }
+
+    /// Latest wall-clock offset of each peer in ms, positive if the peer is ahead
+    /// Includes the network delay of the event it was measured on
+    pub fn clock_skew(&self) -> HashMap<NodeId, i64> {
+        self.clock_skew.lock().unwrap().clone()
+    }
+
+    /// This node's incarnation, see `DistributedQueueSystemBuilder::epoch`
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Newest epoch seen in each origin's events, for origins that have one
+    pub fn peer_epochs(&self) -> HashMap<NodeId, u64> {
+        self.epochs.lock().unwrap().clone()
+    }
 
-        // Check if we've seen the immediately preceding event from the same node
-        let my_clock = self.clock.snapshot();
-        let event_node_time = event.clock.get(&event.origin_node).copied().unwrap_or(0);
-        let my_node_time = my_clock.get(&event.origin_node).copied().unwrap_or(0);
+    /// Peers whose latest offset exceeds the configured limit
+    pub fn skewed_peers(&self) -> Vec<NodeId> {
+        let limit = self.max_clock_skew.as_millis() as i64;
+        self.clock_skew.lock().unwrap().iter().filter(|(_, skew)| skew.abs() > limit).map(|(&node, _)| node).collect()
+    }
 
-        // Simple causality check: event should be exactly next from that node
-        event_node_time == my_node_time + 1
+    /// Count an operation handled outside the queue, e.g. an event rejected by the network layer
+    pub(crate) fn record(&self, counter: Counter) {
+        self.metrics.inc(counter);
+    }
+
+    /// Check if an event can be applied (causal consistency)
+    fn can_apply_event(&self, event: &Event<T>) -> bool {
+        // Our clock only advances as events are delivered, so it doubles as the
+        // per-origin delivery vector
+        self.clock.can_deliver(event.origin_node, &event.clock)
     }
 
     /// Apply an event immediately
     fn apply_event_immediately(&self, event:Event<T>) {
-        // Mark as applied
-        {
-            let mut applied = self.applied_events.lock().unwrap();
-            let node_events = applied.entry(event.origin_node.clone()).or_insert_with(HashSet::new);
-            node_events.insert(event.global_id);
+        // Mark as applied and record delivery in our clock
+        self.applied_events.insert(event.origin_node, event.global_id, event.clock.get(&event.origin_node).copied().unwrap_or(0));
+        self.clock.merge(&event.clock);
+        let mut peer_clocks = self.peer_clocks.lock().unwrap();
+        let seen = peer_clocks.entry(event.origin_node).or_default();
+        for (&node, &time) in &event.clock {
+            let known = seen.entry(node).or_insert(0);
+            *known = (*known).max(time);
+        }
+        drop(peer_clocks);
+        self.metrics.inc(Counter::RemoteApplied);
+
+        // A panicking operation is quarantined instead of unwinding through the buffer
+        let applying = event.clone();
+        if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| self.apply_op(event))) {
+            self.quarantine_event(applying, quarantine::panic_message(&*panic));
         }
+    }
 
-        // Apply the operation
+    /// Set aside an event whose operation panicked
+    /// The panic may have poisoned locks the operation held; the state behind them is
+    /// kept as the operation left it
+    fn quarantine_event(&self, event: Event<T>, error: String) {
+        self.queue.clear_poison();
+        self.logger.clear_poison();
+        self.outbox.clear_poison();
+        self.transferred.clear_poison();
+        self.deliveries.clear_poison();
+        self.attempts.clear_poison();
+        self.retries.clear_poison();
+        self.dead_letters.clear_poison();
+        self.traces.clear_poison();
+        self.metrics.inc(Counter::Quarantined);
+        let mut quarantine = self.quarantine.lock().unwrap();
+        if quarantine.len() >= MAX_QUARANTINED {
+            quarantine.pop_front();
+        }
+        quarantine.push_back(QuarantinedEvent { event, error, quarantined_at_ms: now_ms() });
+    }
+
+    /// Apply a delivered event's operation
+    fn apply_op(&self, event: Event<T>) {
         match event.op {
             EventOp::Enqueue => {
                 if let Some(item) = event.item.clone() {
-                    self.apply_enqueue_op(&item, event.clock.clone(), Some(event.global_id), event.clone());
+                    self.apply_enqueue_op(item, event);
                 }
             }
             EventOp::Dequeue => {
-                self.apply_dequeue_op(event.clock.clone(), Some(event.global_id), event.clone());
+                self.apply_dequeue_op(event);
+            }
+            EventOp::Transfer { to } => {
+                self.apply_transfer_op(to, event);
+            }
+            EventOp::Reject { origin, event_id } => {
+                self.apply_reject_op(origin, event_id, event);
+            }
+            EventOp::Fail { dead_letter, .. } => {
+                self.apply_fail_op(dead_letter, event);
+            }
+            EventOp::Retry { attempt } => {
+                if let Some(item) = event.item.clone() {
+                    self.apply_retry_op(attempt, item, event);
+                }
+            }
+            EventOp::StateUpdate { origin, event_id, state } => {
+                self.apply_state_update_op(origin, event_id, state, event);
+            }
+            EventOp::Purge { ref key } => {
+                let key = key.clone();
+                self.apply_purge_op(&key, event);
+            }
+            EventOp::Control { ref op } => {
+                let op = op.clone();
+                self.apply_control_op(op, event);
+            }
+            EventOp::Take { origin, event_id } => {
+                self.apply_take_op(origin, event_id, event);
+            }
+            EventOp::Ack { .. } => {
+                self.log("ack", State::Committed, Arc::new(event));
             }
         }
     }
 
     /// Process any buffered events that can now be applied
+    /// Events a buffered one depends on have a lower vector time, so going through the buffer
+    /// lowest first delivers a whole chain in one pass. Another pass follows one that delivered
+    /// anything, for events buffered meanwhile.
     fn process_buffered_events(&self) {
-        let mut buffer = self. event_buffer.lock().unwrap();
-        let mut to_apply = Vec::new();
-        let mut remaining = BinaryHeap::new();
-
-        while let Some(Reverse(event)) = buffer.pop() {
-            if self.can_apply_event(&event) {
-                to_apply.push(event);
-            } else{
-                remaining.push(Reverse(event));
+        loop {
+            // Apply events outside the lock
+            let waiting = std::mem::take(&mut *self.event_buffer.lock().unwrap()).into_sorted_vec();
+            let mut delivered = false;
+            let mut remaining = Vec::new();
+            for Reverse(event) in waiting.into_iter().rev() {
+                if self.can_apply_event(&event) {
+                    self.apply_event_immediately(event);
+                    delivered = true;
+                } else {
+                    remaining.push(Reverse(event));
+                }
+            }
+            // An origin may have restarted meanwhile, see `check_epoch`
+            let epochs = self.epochs.lock().unwrap();
+            remaining.retain(|Reverse(event)| event.epoch >= epochs.get(&event.origin_node).copied().unwrap_or(0));
+            drop(epochs);
+            self.event_buffer.lock().unwrap().extend(remaining);
+            if !delivered {
+                break;
             }
         }
-        *buffer = remaining;
-        drop(buffer);
+    }
 
-        // Apply events outside the lock
-        for event in to_apply {
-            self.apply_event_immediately(event);
+    /// Internal helper to apply enqueue operation, rejecting it if its origin is over quota
+    fn apply_enqueue_op(&self, item: Arc<T>, event: Event<T>) {
+        let mut queue = self.queue.lock().unwrap();
+        if self.over_quota(&queue, event.origin_node).is_some() {
+            drop(queue);
+            self.metrics.inc(Counter::QuotaExceeded);
+            let reject = Event::new_reject(self.node_id, event.origin_node, event.global_id, self.clock.tick_snapshot()).with_epoch(self.epoch);
+            self.log("reject", State::Failed, Arc::new(reject.clone()));
+            self.outbox.lock().unwrap().push(reject);
+            return;
+        }
+        if let Some(sequence) = &event.sequence {
+            let mut sequences = self.key_sequences.lock().unwrap();
+            let seq = sequences.entry(sequence.key.clone()).or_insert(0);
+            *seq = (*seq).max(sequence.seq);
         }
+        queue.enqueue_sequenced(item, event.origin_node, event.global_id, event.sequence.clone());
+        drop(queue);
+        self.arrivals.notify();
+        self.remember_trace(&event);
+        self.log("enqueue", State::Committed, Arc::new(event));
     }
 
-    /// Internal helper to apply enqueue operation
-    fn apply_enqueue_op(&self, item: &T, clock:HashMap<String, u64>, event_id: Option<u64>,  event: Event<T>) {
+    /// Internal helper to apply a rejection: the rejected item leaves every replica,
+    /// so the producer and the nodes that accepted it agree with the one that refused it
+    /// Our entry for the rejected enqueue is marked Failed, as on every replica applying it
+    fn apply_reject_op(&self, origin: NodeId, event_id: u64, event: Event<T>) {
+        self.queue.lock().unwrap().remove_event(origin, event_id);
+        self.forget_attempts((origin, event_id));
+        self.take_trace((origin, event_id));
+        self.log("reject", State::Failed, Arc::new(event));
+        self.logger.lock().unwrap().update_event_state(origin, event_id, State::Failed).ok();
+    }
+
+    /// Internal helper to apply a failure; the item already left the queue with its dequeue
+    fn apply_fail_op(&self, dead_letter: bool, event: Event<T>) {
+        let (origin, id) = (event.origin_node, event.global_id);
+        if dead_letter && let Some(item) = event.item.clone() {
+            self.dead_letters.lock().unwrap().push(item);
+        }
+        self.log("fail", State::Failed, Arc::new(event));
+        if dead_letter {
+            self.logger.lock().unwrap().update_event_state(origin, id, State::DeadLettered).ok();
+        }
+    }
+
+    /// Internal helper to apply a state update to our entry for the same event
+    /// Entries already spilled or removed keep their state; the update itself is still logged
+    fn apply_state_update_op(&self, origin: NodeId, event_id: u64, state: State, event: Event<T>) {
+        if let Err(QueueError::InvalidTransition { .. }) = self.logger.lock().unwrap().update_event_state(origin, event_id, state) {
+            self.metrics.inc(Counter::TransitionRejected);
+        }
+        self.log("update", state, Arc::new(event));
+    }
+
+    /// Internal helper to apply a purge: items with `key` leave the queue and every local
+    /// holding, their log entries (spilled ones included) lose their item, and the purge
+    /// itself is logged as the tombstone recording it
+    fn apply_purge_op(&self, key: &str, event: Event<T>) {
         let mut queue = self.queue.lock().unwrap();
-        queue.enqueue(item.clone());
-        drop(queue);
         let mut logger = self.logger.lock().unwrap();
-        logger.log("enqueue", Some(item.clone()), State::Committed, clock, event_id, event);
+        let matches = |item: &Arc<T>| logger.key_of(item).as_deref() == Some(key);
+        for source in queue.remove_where(&matches) {
+            self.forget_attempts(source);
+            self.take_trace(source);
+        }
+        self.transferred.lock().unwrap().retain(|(_, item, _)| !matches(item));
+        self.deliveries.lock().unwrap().retain(|(_, item, _)| !matches(item));
+        self.retries.lock().unwrap().retain(|(_, item, _)| !matches(item));
+        self.dead_letters.lock().unwrap().retain(|item| !matches(item));
+        drop(queue);
+        if logger.redact_key(key).is_err() {
+            self.metrics.inc(Counter::StoreFailed);
+        }
+        drop(logger);
+        self.log("purge", State::Committed, Arc::new(event));
+    }
+
+    /// Internal helper to apply a control action; membership, topic and config changes
+    /// beyond this queue are up to the caller, e.g. `Node`, and all are logged
+    fn apply_control_op(&self, op: ControlOp, event: Event<T>) {
+        let ack = match op {
+            ControlOp::LeaseGranted { holder, term, .. } if holder != self.node_id => Some(ControlOp::LeaseAcked { holder, term }),
+            _ => None,
+        };
+        match op {
+            ControlOp::MemberAdded { member } => {
+                self.departed.lock().unwrap().remove(&member);
+                self.clock.add_node(member.as_str());
+            }
+            ControlOp::MemberRemoved { member } => {
+                let held = self.token_holder() == Some(member);
+                self.departed.lock().unwrap().insert(member);
+                if held {
+                    let mut token = self.token.lock().unwrap();
+                    token.1 = self.next_in_ring(member);
+                    token.2 = Instant::now();
+                }
+            }
+            ControlOp::TokenPassed { to, round } => {
+                let mut token = self.token.lock().unwrap();
+                if round > token.0 {
+                    *token = (round, Some(to), Instant::now());
+                }
+            }
+            ControlOp::LeaseGranted { holder, term, expires_ms } => {
+                let granted_at = event.clock.get(&holder).copied().unwrap_or(0);
+                let lease = Lease { holder, term, expires_ms, granted_at };
+                let mut current = self.lease.lock().unwrap();
+                if current.is_none_or(|current| lease.supersedes(&current)) {
+                    *current = Some(lease);
+                }
+            }
+            ControlOp::LeaseAcked { .. } => {}
+            ControlOp::Paused => self.paused.store(true, Ordering::SeqCst),
+            ControlOp::Resumed => self.paused.store(false, Ordering::SeqCst),
+            ControlOp::ConfigUpdated { key, value } => {
+                self.control_config.lock().unwrap().insert(key, value);
+            }
+            ControlOp::TopicCreated { config: Some(config), .. } => self.apply_topic_config(config, (0, event.origin_node)),
+            ControlOp::TopicConfigured { config, version, .. } => self.apply_topic_config(config, (version, event.origin_node)),
+            ControlOp::TopicCreated { config: None, .. } => {}
+            ControlOp::TopicDeleted { .. } => {
+                self.deleted.store(true, Ordering::SeqCst);
+                self.queue.lock().unwrap().clear();
+                self.retries.lock().unwrap().clear();
+                self.transferred.lock().unwrap().clear();
+                self.deliveries.lock().unwrap().clear();
+            }
+        }
+        self.log("control", State::Committed, Arc::new(event));
+        // Acknowledge others' lease grants, after logging them to keep the log causal
+        if let Some(ack) = ack {
+            let ack = self.control(ack);
+            self.outbox.lock().unwrap().push(ack);
+        }
+    }
+
+    /// Take on the settings of a topic unless newer ones are in effect; unset ones go back
+    /// to the builder's, but retention replaces the configured policy, archive included,
+    /// until configured again, and max_attempts keeps the configured backoff
+    fn apply_topic_config(&self, config: TopicConfig, version: (u64, NodeId)) {
+        let mut current = self.topic_config.lock().unwrap();
+        if current.as_ref().is_some_and(|(_, applied)| *applied >= version) {
+            return;
+        }
+        let effective = self.defaults.merge(&config);
+        let mut queue = self.queue.lock().unwrap();
+        queue.set_capacity(effective.capacity);
+        queue.set_prioritized(effective.priority == Some(true));
+        drop(queue);
+        if config.retention_ms.is_some() || config.retention_entries.is_some() {
+            let mut policy = RetentionPolicy::new();
+            if let Some(ms) = config.retention_ms {
+                policy = policy.max_age(Duration::from_millis(ms));
+            }
+            if let Some(max) = config.retention_entries {
+                policy = policy.max_entries(max);
+            }
+            self.set_retention(Some(policy));
+        }
+        *self.retry.write().unwrap() = match config.max_attempts {
+            Some(max_attempts) => {
+                let backoff = self.default_retry.unwrap_or(RetryPolicy::new(max_attempts));
+                Some(RetryPolicy { max_attempts: max_attempts.max(1), ..backoff })
+            }
+            None => self.default_retry,
+        };
+        *self.ttl.write().unwrap() = effective.ttl_ms.map(Duration::from_millis);
+        *self.delivery_mode.write().unwrap() = effective.delivery_mode.unwrap_or_default();
+        *current = Some((config, version));
+    }
+
+    /// Internal helper to apply a retry, an enqueue that remembers the item's failures
+    fn apply_retry_op(&self, attempt: u32, item: Arc<T>, event: Event<T>) {
+        self.queue.lock().unwrap().enqueue(item, event.origin_node, event.global_id);
+        self.redelivered.fetch_add(1, Ordering::Relaxed);
+        self.arrivals.notify();
+        self.attempts.lock().unwrap().insert((event.origin_node, event.global_id), attempt);
+        self.log("enqueue", State::Committed, Arc::new(event));
+    }
+
+    /// IDs of this node's enqueues that a replica rejected, from the in-memory log
+    pub fn rejected_enqueues(&self) -> Vec<u64> {
+        self.log_view()
+            .iter()
+            .filter_map(|entry| match entry.event.op {
+                EventOp::Reject { origin, event_id } if origin == self.node_id => Some(event_id),
+                _ => None,
+            })
+            .collect()
     }
 
     /// Internal helper to apply dequeue op
-    fn apply_dequeue_op(&self, clock:HashMap<String, u64>, event_id:Option<u64>, event: Event<T>) {
+    fn apply_dequeue_op(&self, event: Event<T>) {
+        // The origin took nothing, e.g. its queue was empty or paused
         let mut queue = self.queue.lock().unwrap();
-        let item = queue.dequeue();
+        let dequeued = if event.item.is_some() { queue.dequeue_with_source() } else { None };
         drop(queue);
-        let mut logger = self.logger.lock().unwrap();
-        logger.log("dequeue", item, State::Delivered, clock, event_id, event);
+        if let Some((_, source)) = dequeued {
+            self.forget_attempts(source);
+            self.take_trace(source);
+        }
+        self.log("dequeue", State::Delivered, Arc::new(event));
+    }
+
+    /// Internal helper to apply a take, removing the item its enqueue added
+    /// The item is gone already if e.g. a concurrent dequeue elsewhere took it first
+    fn apply_take_op(&self, origin: NodeId, event_id: u64, event: Event<T>) {
+        if self.queue.lock().unwrap().take_event(origin, event_id).is_some() {
+            self.forget_attempts((origin, event_id));
+            self.take_trace((origin, event_id));
+        }
+        self.log("dequeue", State::Delivered, Arc::new(event));
+    }
+
+    /// Internal helper to apply transfer op, keeping the item if it was transferred to us
+    fn apply_transfer_op(&self, to: NodeId, event: Event<T>) {
+        let mut queue = self.queue.lock().unwrap();
+        let dequeued = queue.dequeue_with_source();
+        drop(queue);
+        let failures = dequeued.map_or(0, |(_, source)| {
+            self.take_trace(source);
+            self.forget_attempts(source)
+        });
+        if to == self.node_id
+            && let Some(item) = event.item.clone()
+        {
+            self.transferred.lock().unwrap().push_back((event.global_id, item, failures));
+            self.arrivals.notify();
+        }
+        self.log("transfer", State::Delivered, Arc::new(event));
     }
 
     /// Get current queue state
@@ -181,10 +1580,269 @@ impl<T: Clone + Send + 'static> DistributedQueueSystem<T> {
         (queue.len(), queue.is_empty())
     }
 
-    /// Expose logs
+    /// How full the queue or the causal buffer is, whichever is fuller; 1.0 at capacity
+    /// None if neither has a limit, see `queue_capacity`, `max_bytes` and `max_buffered_events`
+    pub fn load(&self) -> Option<f64> {
+        let queue = self.queue.lock().unwrap();
+        let depth = queue.fill();
+        drop(queue);
+        let buffered = self.max_buffered_events().map(|max| self.pending_events_count() as f64 / max.max(1) as f64);
+        match (depth, buffered) {
+            (Some(depth), Some(buffered)) => Some(depth.max(buffered)),
+            (depth, buffered) => depth.or(buffered),
+        }
+    }
+
+    /// Dequeue, waiting up to `max_wait` for an item to arrive if the queue is empty
+    /// Returns None if none did, without the empty dequeue `dequeue` would log
+    pub fn dequeue_wait(&self, max_wait: Duration) -> Option<(Arc<T>, Event<T>)> {
+        let deadline = Instant::now() + max_wait;
+        loop {
+            let seen = self.arrivals.count();
+            if !self.holds_items()
+                && !self.queue.lock().unwrap().is_empty()
+                && let (Some(item), event) = self.dequeue()
+            {
+                return Some((item, event));
+            }
+            if !self.arrivals.wait_past(seen, deadline) {
+                return None;
+            }
+        }
+    }
+
+    /// Item the next dequeue would take, without removing it
+    pub fn peek(&self) -> Option<Arc<T>> {
+        self.queue.lock().unwrap().peek().cloned()
+    }
+
+    /// Expose logs (in-memory entries only, see `log_page` for spilled ones)
     pub fn logs(&self) -> Vec<LogEntry<T>> {
-      let logger = self.logger.lock().unwrap();
-        logger.entries.clone()
+        self.log_view().to_vec()
+    }
+
+    /// Log entries (spilled ones included) that happened after the given clock
+    pub fn logs_since(&self, clock: &HashMap<NodeId, u64>) -> std::io::Result<Vec<LogEntry<T>>> {
+        let logger = self.logger.lock().unwrap();
+        logger.get_entries_since(clock)
+    }
+
+    /// Receive every log entry recorded from now on, local and replicated operations alike
+    /// Unbounded; drop the receiver to unsubscribe
+    pub fn subscribe_logs(&self) -> std::sync::mpsc::Receiver<LogEntry<T>> {
+        self.logger.lock().unwrap().subscribe()
+    }
+
+    /// Log entries recording event `event_id` (one per origin using that ID), spilled ones included
+    pub fn find_by_event_id(&self, event_id: u64) -> std::io::Result<Vec<LogEntry<T>>> {
+        self.logger.lock().unwrap().find_by_event_id(event_id)
+    }
+
+    /// Log entries whose item has `key`, see `DistributedQueueSystemBuilder::log_key`
+    pub fn find_by_key(&self, key: &str) -> std::io::Result<Vec<LogEntry<T>>> {
+        self.logger.lock().unwrap().find_by_key(key)
+    }
+
+    /// Current queue depth, buffer and log sizes, and counters
+    pub fn stats(&self) -> QueueStats {
+        let (depth, bytes) = {
+            let queue = self.queue.lock().unwrap();
+            (queue.len(), queue.bytes())
+        };
+        let log_entries = self.logger.lock().unwrap().len();
+        QueueStats {
+            node_id: self.node_id,
+            depth,
+            bytes,
+            pending_events: self.pending_events_count(),
+            log_entries,
+            clock: self.clock.snapshot(),
+            stable: self.stability_watermark(),
+            clock_skew_ms: self.clock_skew(),
+            pending_redeliveries: self.pending_redeliveries(),
+            failed_events: self.failed_events.lock().unwrap().len(),
+            quarantined: self.quarantine.lock().unwrap().len(),
+            paused: self.is_paused(),
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics.snapshot(),
+        }
+    }
+
+    /// Estimated bytes held by the queue, the log, the event buffer and applied-event
+    /// tracking, to see which one grows; items are measured with the builder's `item_size`
+    pub fn memory_stats(&self) -> MemoryStats {
+        let item_size = |item: &T| (self.item_size)(item);
+        let event_buffer = {
+            let buffer = self.event_buffer.lock().unwrap();
+            buffer.capacity() * size_of::<Reverse<Event<T>>>() + buffer.iter().map(|Reverse(event)| event.estimated_bytes(&item_size)).sum::<usize>()
+        };
+        MemoryStats {
+            queue: self.queue.lock().unwrap().estimated_bytes(|item| item_size(item)),
+            log: self.logger.lock().unwrap().estimated_bytes(&item_size),
+            event_buffer,
+            applied_events: self.applied_events.estimated_bytes(),
+        }
+    }
+
+    /// Snapshot of the current queue contents and clock
+    pub fn snapshot(&self) -> Snapshot<T> {
+        let queue = self.queue.lock().unwrap();
+        let items = queue.iter().cloned().collect();
+        let sources = queue.sources().copied().collect();
+        drop(queue);
+        Snapshot {
+            node_id: self.node_id,
+            clock: self.clock.snapshot(),
+            items,
+            sources,
+            pending_events: self.pending_events_count(),
+        }
+    }
+
+    /// Replace the queue contents with `snapshot`'s, e.g. one taken by a peer, and catch the
+    /// clock up to it; events it covers are then ignored as already applied, and later ones
+    /// apply on top. Items of snapshots without sources are attributed to the snapshot's node.
+    pub fn restore(&self, snapshot: Snapshot<T>) {
+        let sources = snapshot.sources.into_iter().chain(std::iter::repeat((snapshot.node_id, 0)));
+        let mut queue = self.queue.lock().unwrap();
+        queue.clear();
+        for (item, (origin, event_id)) in snapshot.items.into_iter().zip(sources) {
+            queue.enqueue(item, origin, event_id);
+        }
+        drop(queue);
+        self.clock.merge(&snapshot.clock);
+        for (&origin, &time) in &snapshot.clock {
+            self.applied_events.restart(origin, time);
+        }
+        self.process_buffered_events();
+    }
+
+    /// The queue contents as this node had them after applying exactly the logged events
+    /// within `frontier`, a vector clock by node name (nodes missing from it count as 0);
+    /// e.g. given node X's clock at some point, what X saw then, as far as this node applied
+    /// the same events in the same order. The whole log is replayed, so it fails once
+    /// retention discarded entries; see `rebuild_from`. Purged items are not brought back.
+    pub fn rebuild_at(&self, frontier: HashMap<String, u64>) -> std::io::Result<Snapshot<T>> {
+        let discarded = self.retained_range().start;
+        if discarded > 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("retention discarded the first {} log entries, rebuild from a snapshot", discarded),
+            ));
+        }
+        self.replay(None, frontier)
+    }
+
+    /// `rebuild_at`, starting from `base` and replaying the log entries it doesn't cover,
+    /// e.g. with a snapshot saved before retention discarded entries; `base` must be within the frontier
+    pub fn rebuild_from(&self, base: Snapshot<T>, frontier: HashMap<String, u64>) -> std::io::Result<Snapshot<T>> {
+        self.replay(Some(base), frontier)
+    }
+
+    fn replay(&self, base: Option<Snapshot<T>>, frontier: HashMap<String, u64>) -> std::io::Result<Snapshot<T>> {
+        // A name never registered is no event's origin, so it counts as 0 like a missing one;
+        // interning it would let any caller grow the registry for good
+        let frontier: HashMap<NodeId, u64> = frontier.iter().filter_map(|(node, &time)| Some((NodeId::lookup(node)?, time))).collect();
+        let time_of = |clock: &HashMap<NodeId, u64>, node: NodeId| clock.get(&node).copied().unwrap_or(0);
+        let mut queue = self.queue.lock().unwrap().empty_like(self.defaults.priority == Some(true));
+        let mut clock = HashMap::new();
+        if let Some(base) = &base {
+            if let Some((&node, _)) = base.clock.iter().find(|&(&node, &time)| time > time_of(&frontier, node)) {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("snapshot is past the frontier for {}", node)));
+            }
+            let sources = base.sources.iter().copied().chain(std::iter::repeat((base.node_id, 0)));
+            for (item, (origin, event_id)) in base.items.iter().zip(sources) {
+                queue.enqueue(item.clone(), origin, event_id);
+            }
+            clock.clone_from(&base.clock);
+        }
+
+        let entries = {
+            let logger = self.logger.lock().unwrap();
+            logger.page(0, logger.len())?
+        };
+        let mut config_version = None;
+        for entry in entries {
+            let event = &entry.event;
+            let time = time_of(&event.clock, event.origin_node);
+            let covered = base.as_ref().is_some_and(|base| time <= time_of(&base.clock, event.origin_node));
+            if covered || time > time_of(&frontier, event.origin_node) {
+                continue;
+            }
+            match &event.op {
+                EventOp::Enqueue | EventOp::Retry { .. } => {
+                    // Purged items are gone from their entries
+                    if let Some(item) = event.item.clone() {
+                        queue.enqueue_sequenced(item, event.origin_node, event.global_id, event.sequence.clone());
+                    }
+                }
+                // A dequeue that took nothing carries no item
+                EventOp::Dequeue if event.item.is_some() => {
+                    queue.dequeue_with_source();
+                }
+                EventOp::Transfer { .. } => {
+                    queue.dequeue_with_source();
+                }
+                EventOp::Take { origin, event_id } => {
+                    queue.take_event(*origin, *event_id);
+                }
+                EventOp::Reject { origin, event_id } => {
+                    queue.remove_event(*origin, *event_id);
+                }
+                EventOp::Purge { key } => {
+                    let logger = self.logger.lock().unwrap();
+                    queue.remove_where(|item| logger.key_of(item).as_deref() == Some(key.as_str()));
+                }
+                EventOp::Control { op } => {
+                    let configured = match op {
+                        ControlOp::TopicCreated { config: Some(config), .. } => Some((config, 0)),
+                        ControlOp::TopicConfigured { config, version, .. } => Some((config, *version)),
+                        ControlOp::TopicDeleted { .. } => {
+                            queue.clear();
+                            None
+                        }
+                        _ => None,
+                    };
+                    if let Some((config, version)) = configured
+                        && config_version.is_none_or(|applied| applied < (version, event.origin_node))
+                    {
+                        config_version = Some((version, event.origin_node));
+                        queue.set_prioritized(self.defaults.merge(config).priority == Some(true));
+                    }
+                }
+                _ => {}
+            }
+            for (&node, &time) in &event.clock {
+                let reached = clock.entry(node).or_insert(0);
+                *reached = (*reached).max(time);
+            }
+        }
+
+        Ok(Snapshot {
+            node_id: self.node_id,
+            clock,
+            items: queue.iter().cloned().collect(),
+            sources: queue.sources().copied().collect(),
+            pending_events: 0,
+        })
+    }
+
+    /// Snapshot of the in-memory log that can be iterated without blocking writers
+    pub fn log_view(&self) -> LogView<T> {
+        let logger = self.logger.lock().unwrap();
+        logger.view()
+    }
+
+    /// Page through the full log, including entries spilled to storage
+    pub fn log_page(&self, offset: usize, limit: usize) -> std::io::Result<Vec<LogEntry<T>>> {
+        let logger = self.logger.lock().unwrap();
+        logger.page(offset, limit)
+    }
+
+    /// The most recent `count` log entries, reading spilled ones back if needed
+    pub fn log_tail(&self, count: usize) -> std::io::Result<Vec<LogEntry<T>>> {
+        let logger = self.logger.lock().unwrap();
+        logger.page(logger.len().saturating_sub(count), count)
     }
 
     /// Get current clock time
@@ -192,9 +1850,20 @@ impl<T: Clone + Send + 'static> DistributedQueueSystem<T> {
         self.clock.now()
     }
 
+    /// The full vector clock: events delivered from each node
+    pub fn vector_clock(&self) -> HashMap<NodeId, u64> {
+        self.clock.snapshot()
+    }
+
     /// Get node ID
     pub fn node_id(&self) -> &str {
-        &self.node_id
+        self.node_id.as_str()
+    }
+
+    /// Operation counters for this node
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
     }
 
     /// Get pending events in buffer
@@ -202,4 +1871,28 @@ impl<T: Clone + Send + 'static> DistributedQueueSystem<T> {
         let buffer = self.event_buffer.lock().unwrap();
         buffer.len()
     }
+
+    /// Events in the causal buffer, in delivery order, with the predecessors each is waiting for
+    pub fn buffered_events(&self) -> Vec<BufferedEvent> {
+        let buffer = self.event_buffer.lock().unwrap();
+        let mut events: Vec<&Event<T>> = buffer.iter().map(|Reverse(event)| event).collect();
+        events.sort();
+        events
+            .into_iter()
+            .map(|event| BufferedEvent {
+                origin: event.origin_node,
+                event_id: event.global_id,
+                sequence: event.clock.get(&event.origin_node).copied().unwrap_or(0),
+                missing: self.clock.missing(event.origin_node, &event.clock),
+            })
+            .collect()
+    }
+}
+
+/// Swap the limiter behind `slot` for one enforcing `limit`, unless it already does
+fn replace_limiter(slot: &RwLock<Option<RateLimiter>>, limit: Option<(RateLimit, Throttle)>) {
+    let mut slot = slot.write().unwrap();
+    if slot.as_ref().map(|limiter| (limiter.limit(), limiter.throttle())) != limit {
+        *slot = limit.map(|(limit, throttle)| RateLimiter::new(limit, throttle));
+    }
 }
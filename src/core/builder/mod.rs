@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::core::buildcore::{DequeuePolicy, DistributedQueueSystem};
+use crate::core::control::DeliveryMode;
+use crate::core::log::KeyFn;
+use crate::core::ratelimit::{RateLimit, Throttle};
+use crate::core::retention::RetentionPolicy;
+use crate::core::retry::RetryPolicy;
+use crate::core::redelivery;
+use crate::core::signal::ArrivalSignal;
+use crate::core::size::MessageSize;
+use crate::core::storage::{LogSink, LogStore};
+
+/// How remote events are ordered before being applied
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConsistencyLevel {
+    /// Buffer events until their causal predecessors from the origin have been applied
+    #[default]
+    Causal,
+    /// Apply remote events as soon as they arrive, in arrival order
+    Eventual,
+}
+
+/// How the vector clock treats node membership
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClockType {
+    /// Only the node itself and the configured peers are tracked; unknown nodes are ignored
+    #[default]
+    Fixed,
+    /// Nodes seen in remote clocks are added to the vector clock on the fly
+    Dynamic,
+}
+
+/// Wall-clock offset tolerated before a peer's events count as skewed
+pub const DEFAULT_MAX_CLOCK_SKEW: Duration = Duration::from_secs(1);
+
+/// How far past the delivered events of its origin a remote event may be
+pub const DEFAULT_MAX_EVENT_GAP: u64 = 1_000_000;
+
+/// Extracts the ordering key of an item, see `keyed_fifo`
+pub(crate) type OrderingKeyFn<T> = Box<dyn Fn(&T) -> Option<String> + Send + Sync>;
+
+/// Ranks an item for dequeues, higher first, see `priority`
+pub(crate) type ItemPriorityFn<T> = Box<dyn Fn(&T) -> u8 + Send + Sync>;
+
+/// Estimates the bytes of an item, see `item_size`
+pub(crate) type ItemSizeFn<T> = Box<dyn Fn(&T) -> usize + Send + Sync>;
+
+/// Builder for `DistributedQueueSystem`
+pub struct DistributedQueueSystemBuilder<T> {
+    pub(crate) node_id: String,
+    pub(crate) peers: Vec<String>,
+    pub(crate) queue_capacity: Option<usize>,
+    pub(crate) max_bytes: Option<(usize, ItemSizeFn<T>)>,
+    pub(crate) dequeue_policy: DequeuePolicy,
+    pub(crate) consistency: ConsistencyLevel,
+    pub(crate) clock_type: ClockType,
+    pub(crate) max_log_entries: Option<usize>,
+    pub(crate) log_store: Option<Box<dyn LogStore<T>>>,
+    pub(crate) log_sink: Option<Box<dyn LogSink<T>>>,
+    pub(crate) log_key: Option<KeyFn<T>>,
+    pub(crate) ordering_key: Option<OrderingKeyFn<T>>,
+    pub(crate) item_size: ItemSizeFn<T>,
+    pub(crate) max_buffered_events: Option<usize>,
+    pub(crate) arrivals: Option<Arc<ArrivalSignal>>,
+    pub(crate) retention: Option<RetentionPolicy<T>>,
+    pub(crate) max_clock_skew: Duration,
+    pub(crate) max_event_gap: u64,
+    pub(crate) rate_limit: Option<(RateLimit, Throttle)>,
+    pub(crate) client_rate_limit: Option<(RateLimit, Throttle)>,
+    pub(crate) node_quotas: HashMap<String, usize>,
+    pub(crate) default_node_quota: Option<usize>,
+    pub(crate) retry: Option<RetryPolicy>,
+    pub(crate) redelivery: RetryPolicy,
+    pub(crate) epoch: u64,
+    pub(crate) id_worker: Option<u16>,
+    pub(crate) read_only: bool,
+    pub(crate) ttl: Option<Duration>,
+    pub(crate) delivery_mode: DeliveryMode,
+    pub(crate) priority: Option<ItemPriorityFn<T>>,
+    pub(crate) lease: Option<Duration>,
+    pub(crate) token_hold: Option<Duration>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Send + Sync + 'static> DistributedQueueSystemBuilder<T> {
+    /// Start a builder for the given node
+    pub fn new(node_id: impl Into<String>) -> Self {
+        Self {
+            node_id: node_id.into(),
+            peers: Vec::new(),
+            queue_capacity: None,
+            max_bytes: None,
+            dequeue_policy: DequeuePolicy::default(),
+            consistency: ConsistencyLevel::default(),
+            clock_type: ClockType::default(),
+            max_log_entries: None,
+            log_store: None,
+            log_sink: None,
+            log_key: None,
+            ordering_key: None,
+            item_size: Box::new(|item| size_of_val(item)),
+            max_buffered_events: None,
+            arrivals: None,
+            retention: None,
+            max_clock_skew: DEFAULT_MAX_CLOCK_SKEW,
+            max_event_gap: DEFAULT_MAX_EVENT_GAP,
+            rate_limit: None,
+            client_rate_limit: None,
+            node_quotas: HashMap::new(),
+            default_node_quota: None,
+            retry: None,
+            redelivery: redelivery::default_policy(),
+            epoch: 0,
+            id_worker: None,
+            read_only: false,
+            ttl: None,
+            delivery_mode: DeliveryMode::default(),
+            priority: None,
+            lease: None,
+            token_hold: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Known peer nodes, tracked in the vector clock from the start
+    pub fn peers(mut self, peers: &[&str]) -> Self {
+        self.peers = peers.iter().map(|p| p.to_string()).collect();
+        self
+    }
+
+    /// Maximum number of items accepted by local enqueues
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    /// Maximum bytes of items accepted by local enqueues, measured by `MessageSize`
+    /// Counts towards `load`, so a queue near its byte limit engages backpressure too
+    pub fn max_bytes(self, max: usize) -> Self
+    where
+        T: MessageSize,
+    {
+        self.max_bytes_by(max, T::message_size)
+    }
+
+    /// `max_bytes` with items measured by `size`, e.g. `serialized_size`
+    pub fn max_bytes_by(mut self, max: usize, size: impl Fn(&T) -> usize + Send + Sync + 'static) -> Self {
+        self.max_bytes = Some((max, Box::new(size)));
+        self
+    }
+
+    /// Which item dequeues take; every replica must use the same policy
+    pub fn dequeue_policy(mut self, policy: DequeuePolicy) -> Self {
+        self.dequeue_policy = policy;
+        self
+    }
+
+    /// Ordering guarantee for remote events
+    pub fn consistency(mut self, level: ConsistencyLevel) -> Self {
+        self.consistency = level;
+        self
+    }
+
+    /// Membership behaviour of the vector clock
+    pub fn clock_type(mut self, clock_type: ClockType) -> Self {
+        self.clock_type = clock_type;
+        self
+    }
+
+    /// Keep at most this many log entries in memory
+    /// Older entries go to the storage backend, or are dropped if none is set
+    pub fn max_log_entries(mut self, max: usize) -> Self {
+        self.max_log_entries = Some(max);
+        self
+    }
+
+    /// Storage backend receiving log entries spilled out of memory
+    pub fn storage(mut self, store: Box<dyn LogStore<T>>) -> Self {
+        self.log_store = Some(store);
+        self
+    }
+
+    /// Sink receiving every log entry as it is recorded
+    pub fn log_sink(mut self, sink: Box<dyn LogSink<T>>) -> Self {
+        self.log_sink = Some(sink);
+        self
+    }
+
+    /// Index log entries by the key `key_fn` extracts from their item, see `find_by_key`
+    pub fn log_key(mut self, key_fn: impl Fn(&T) -> Option<String> + Send + 'static) -> Self {
+        self.log_key = Some(Box::new(key_fn));
+        self
+    }
+
+    /// Hand out items with the same key (from `key_fn`) in enqueue order, also across nodes
+    /// Each enqueue carries the next sequence of its key; items of different keys, or
+    /// without one, may interleave. Relies on causal consistency to see earlier sequences
+    /// first, and retried items are queued again without one
+    pub fn keyed_fifo(mut self, key_fn: impl Fn(&T) -> Option<String> + Send + Sync + 'static) -> Self {
+        self.ordering_key = Some(Box::new(key_fn));
+        self
+    }
+
+    /// Estimate the bytes of an item with `size`, for `memory_stats`; by default only
+    /// its inline size counts, so give one that counts its heap data too
+    pub fn item_size(mut self, size: impl Fn(&T) -> usize + Send + Sync + 'static) -> Self {
+        self.item_size = Box::new(size);
+        self
+    }
+
+    /// Apply remote events but refuse local enqueues and hand nothing out, e.g. for an
+    /// analytics mirror that must not consume items; reads work as usual
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// Maximum number of out-of-order events held for causal delivery
+    pub fn max_buffered_events(mut self, max: usize) -> Self {
+        self.max_buffered_events = Some(max);
+        self
+    }
+
+    /// Notify `signal` whenever an item lands, e.g. to wait on several queues at once
+    pub fn arrival_signal(mut self, signal: Arc<ArrivalSignal>) -> Self {
+        self.arrivals = Some(signal);
+        self
+    }
+
+    /// Remove old log entries once every known node has seen them
+    pub fn retention(mut self, policy: RetentionPolicy<T>) -> Self {
+        self.retention = Some(policy);
+        self
+    }
+
+    /// Count events from peers whose wall clock is further than `skew` from ours in `clock_skewed`
+    /// Measured against receive time, so network delay counts towards it
+    pub fn max_clock_skew(mut self, skew: Duration) -> Self {
+        self.max_clock_skew = skew;
+        self
+    }
+
+    /// This node's incarnation, which must grow each time the node restarts without its
+    /// state, e.g. a restart count kept on disk; below 2^32
+    /// Events carry it, and peers take the first event of a newer epoch as a restart of
+    /// its origin rather than a replay, and reject events of older ones. The clock's own
+    /// counter starts at `epoch << EPOCH_SHIFT`, past any earlier incarnation's.
+    pub fn epoch(mut self, epoch: u64) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    /// Worker number in this node's event and log IDs, see `core::id`; derived from
+    /// the node ID if not given. Give each node its own to rule out equal IDs
+    pub fn id_worker(mut self, worker: u16) -> Self {
+        self.id_worker = Some(worker);
+        self
+    }
+
+    /// Reject remote events more than `gap` events past what their origin has delivered here
+    pub fn max_event_gap(mut self, gap: u64) -> Self {
+        self.max_event_gap = gap;
+        self
+    }
+
+    /// Limit all local enqueues on this node
+    pub fn rate_limit(mut self, limit: RateLimit, throttle: Throttle) -> Self {
+        self.rate_limit = Some((limit, throttle));
+        self
+    }
+
+    /// Limit each client identity's enqueues separately, see `try_enqueue_as`
+    pub fn client_rate_limit(mut self, limit: RateLimit, throttle: Throttle) -> Self {
+        self.client_rate_limit = Some((limit, throttle));
+        self
+    }
+
+    /// Most unconsumed items `node` may have in the queue; replicated enqueues over it
+    /// are rejected and the rejection replicated back to the producer
+    pub fn node_quota(mut self, node: &str, max: usize) -> Self {
+        self.node_quotas.insert(node.to_string(), max);
+        self
+    }
+
+    /// Quota for nodes without their own `node_quota`
+    pub fn default_node_quota(mut self, max: usize) -> Self {
+        self.default_node_quota = Some(max);
+        self
+    }
+
+    /// Retry items whose delivery is reported failed, see `DistributedQueueSystem::fail`
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Drop items still queued `ttl` after their enqueue, see `DistributedQueueSystem::expire`
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Whether failed deliveries may be retried, see `DeliveryMode`
+    pub fn delivery_mode(mut self, mode: DeliveryMode) -> Self {
+        self.delivery_mode = mode;
+        self
+    }
+
+    /// Dequeue the oldest item of the highest `priority`, whatever the dequeue policy
+    /// Items of an ordering key still leave in sequence order. Topics can turn it off,
+    /// see `TopicConfig::priority`; every replica must rank items the same
+    pub fn priority(mut self, priority: impl Fn(&T) -> u8 + Send + Sync + 'static) -> Self {
+        self.priority = Some(Box::new(priority));
+        self
+    }
+
+    /// Dequeue only while holding the queue's lease, acquiring it for `duration` at a time;
+    /// see `DistributedQueueSystem::renew_lease`. Leases need every peer to acknowledge
+    /// them, so a peer that is down holds dequeues up until it is removed
+    pub fn lease(mut self, duration: Duration) -> Self {
+        self.lease = Some(duration);
+        self
+    }
+
+    /// Dequeue only while holding the queue's token, which circulates among the nodes in
+    /// name order, each holding it for `hold`; see `DistributedQueueSystem::pass_token`
+    /// Whoever gets the token has applied every dequeue before it, so items leave in
+    /// order with one consumer at a time, at the cost of waiting for the token. A removed
+    /// member's token goes on to the next node
+    pub fn token_ring(mut self, hold: Duration) -> Self {
+        self.token_hold = Some(hold);
+        self
+    }
+
+    /// Backoff and attempts for remote events that couldn't be applied for a transient reason,
+    /// see `DistributedQueueSystem::process_redeliveries`
+    pub fn redelivery_policy(mut self, policy: RetryPolicy) -> Self {
+        self.redelivery = policy;
+        self
+    }
+
+    /// Build the queue system
+    pub fn build(self) -> DistributedQueueSystem<T> {
+        DistributedQueueSystem::from_builder(self)
+    }
+}
@@ -1,101 +1,190 @@
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, RwLock};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::collections::HashMap;
+use crate::core::nodeid::NodeId;
+use crate::core::stalled::MissingEvents;
+
+/// Node slots of a vector clock: node IDs map to an index into the counters array.
+/// Slots are only ever appended, so an index stays valid for the clock's lifetime.
+#[derive(Debug, Default)]
+struct ClockSlots {
+    index: HashMap<NodeId, usize>,
+    names: Vec<NodeId>,
+    counters: Vec<AtomicU64>,
+}
+
+impl ClockSlots {
+    /// Add a node slot if missing and return its index
+    fn insert(&mut self, node_id: NodeId) -> usize {
+        if let Some(&i) = self.index.get(&node_id) {
+            return i;
+        }
+        let i = self.counters.len();
+        self.index.insert(node_id, i);
+        self.names.push(node_id);
+        self.counters.push(AtomicU64::new(0));
+        i
+    }
+
+    fn snapshot(&self) -> HashMap<NodeId, u64> {
+        self.names
+            .iter()
+            .zip(&self.counters)
+            .map(|(k, v)| (*k, v.load(Ordering::SeqCst)))
+            .collect()
+    }
+}
 
 /// Vector Clock
 #[derive(Debug, Clone)]
 pub struct VectorClock {
-    /// Each node ID maps to an atomic counter
-    clock: Arc<Mutex<HashMap<String, Arc<AtomicU64>>>>,
-    node_id: String,
+    /// Each node ID maps to an atomic counter; the lock is only taken for writing
+    /// when a new node joins, so ticks and snapshots share a read lock
+    clock: Arc<RwLock<ClockSlots>>,
+    node_id: NodeId,
+    /// Index of this node's own counter
+    own: usize,
+    /// Add nodes seen in remote clocks instead of ignoring them
+    dynamic: bool,
 }
 
 impl VectorClock {
     /// Create a new clock starting at 0
     pub(crate) fn new(node_id: &str, nodes: &[&str]) -> Self {
-        let mut map = HashMap::new();
+        let mut slots = ClockSlots::default();
         for &id in nodes {
-            map.insert(id.to_string(), Arc::new(AtomicU64::new(0)));
+            slots.insert(NodeId::intern(id));
         }
         // Ensure the current node is included
-        if !map.contains_key(node_id) {
-            map.insert(node_id.to_string(), Arc::new(AtomicU64::new(0)));
-        }
+        let node_id = NodeId::intern(node_id);
+        let own = slots.insert(node_id);
         Self {
-            clock: Arc::new(Mutex::new(map)),
-            node_id: node_id.to_string()
+            clock: Arc::new(RwLock::new(slots)),
+            node_id,
+            own,
+            dynamic: false,
         }
     }
+
+    /// Learn unknown nodes from remote clocks during `update`/`merge`
+    pub(crate) fn with_dynamic_membership(mut self) -> Self {
+        self.dynamic = true;
+        self
+    }
     // Create a new clock with just the current node (for single-process testing)
     pub fn new_single(node_id: &str) -> Self {
-        let mut map = HashMap::new();
-        map.insert(node_id.to_string(), Arc::new(AtomicU64::new(0)));
-        Self {
-            clock: Arc::new(Mutex::new(map)),
-            node_id: node_id.to_string()
-        }
+        Self::new(node_id, &[])
+    }
+
+    /// Node that owns this clock
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
     }
 
     /// Get current clock
     pub(crate) fn now(&self) -> u64 {
-        let map = self.clock.lock().unwrap();
-        map[&self.node_id].load(Ordering::SeqCst)
+        let slots = self.clock.read().unwrap();
+        slots.counters[self.own].load(Ordering::SeqCst)
+    }
+
+    /// Counter of `node`, 0 if it isn't tracked
+    pub(crate) fn get(&self, node: NodeId) -> u64 {
+        let slots = self.clock.read().unwrap();
+        slots.index.get(&node).map_or(0, |&i| slots.counters[i].load(Ordering::SeqCst))
     }
 
     /// Get the full vector clock as a HashMap snapshot
-    pub fn snapshot(&self) -> HashMap<String, u64> {
-        let map = self.clock.lock().unwrap();
-        map.iter()
-            .map(|(k, v)| (k.clone(), v.load(Ordering::SeqCst)))
-            .collect()
+    pub fn snapshot(&self) -> HashMap<NodeId, u64> {
+        self.clock.read().unwrap().snapshot()
     }
 
     /// Increment clock for a local event
-    pub(crate) fn tick(&self) -> u64 {
-        let map = self.clock.lock().unwrap();
-        let counter = map.get(&self.node_id).unwrap();
-        counter.fetch_add(1, Ordering::SeqCst) + 1
+    pub fn tick(&self) -> u64 {
+        let slots = self.clock.read().unwrap();
+        slots.counters[self.own].fetch_add(1, Ordering::SeqCst) + 1
     }
 
     // Update this clock with a remote vector clock (taking max of each component)
-    pub fn update(&self, remote: &HashMap<String, u64>) {
-        let map = self.clock.lock().unwrap();
+    pub fn update(&self, remote: &HashMap<NodeId, u64>) {
         // First, increment our own clock
-        if let Some(local) = map.get(&self.node_id) {
-            local.fetch_add(1, Ordering::SeqCst);
-        }
-
+        self.tick();
         // Then update with remote values (take max)
-        for (id, remote_val) in remote {
-            if let Some(local) = map.get(id) {
-                let mut current = local.load(Ordering::SeqCst);
-                while current < *remote_val {
-                    match local.compare_exchange_weak(
-                        current,
-                        *remote_val,
-                        Ordering::SeqCst,
-                        Ordering::Acquire
-                    ) {
-                        Ok(_) => break,
-                        Err(new_current) => current = new_current,
-                    }
+        self.merge(remote);
+    }
+
+    /// Merge a delivered event's clock (component-wise max) without ticking
+    /// Only local operations tick, so each node's component counts its own events
+    pub fn merge(&self, remote: &HashMap<NodeId, u64>) {
+        let mut unknown = Vec::new();
+        {
+            let slots = self.clock.read().unwrap();
+            for (id, remote_val) in remote {
+                if let Some(&i) = slots.index.get(id) {
+                    slots.counters[i].fetch_max(*remote_val, Ordering::SeqCst);
+                } else if self.dynamic {
+                    unknown.push((*id, *remote_val));
                 }
-            } else {
-                // If we don't know about this node, we could add it
-                // For now, we'll just ignore unknown nodes
+                // With fixed membership, unknown nodes are ignored
             }
         }
+        if !unknown.is_empty() {
+            let mut slots = self.clock.write().unwrap();
+            for (id, remote_val) in unknown {
+                let i = slots.insert(id);
+                slots.counters[i].fetch_max(remote_val, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Causal delivery check for an event from `origin`: it must be the next
+    /// event from the origin, and everything it depends on from other nodes must
+    /// already be delivered. With fixed membership, unknown nodes are ignored.
+    pub fn can_deliver(&self, origin: NodeId, remote: &HashMap<NodeId, u64>) -> bool {
+        let slots = self.clock.read().unwrap();
+        let local = |id: &NodeId| slots.index.get(id).map(|&i| slots.counters[i].load(Ordering::SeqCst));
+        let origin_time = remote.get(&origin).copied().unwrap_or(0);
+        if origin_time != local(&origin).unwrap_or(0) + 1 {
+            return false;
+        }
+        remote.iter().filter(|(id, _)| **id != origin).all(|(id, &time)| match local(id) {
+            Some(mine) => time <= mine,
+            None => !self.dynamic || time == 0,
+        })
     }
+
+    /// Events `can_deliver` is waiting for before an event from `origin` with clock `remote`:
+    /// the origin's earlier ones, and those of other nodes the event depends on
+    pub fn missing(&self, origin: NodeId, remote: &HashMap<NodeId, u64>) -> Vec<MissingEvents> {
+        let slots = self.clock.read().unwrap();
+        let local = |id: &NodeId| slots.index.get(id).map(|&i| slots.counters[i].load(Ordering::SeqCst));
+        let mut missing: Vec<MissingEvents> = remote
+            .iter()
+            .filter_map(|(&node, &time)| {
+                let delivered = match local(&node) {
+                    Some(mine) => mine,
+                    None if self.dynamic => 0,
+                    None => return None,
+                };
+                // The event itself is the origin's next one, not a dependency
+                let needed = if node == origin { time.saturating_sub(1) } else { time };
+                (needed > delivered).then_some(MissingEvents { node, from: delivered + 1, to: needed })
+            })
+            .collect();
+        missing.sort_by_key(|m| m.node);
+        missing
+    }
+
     /// Add a new node to the vector clock
     pub fn add_node(&self, node_id: &str) {
-        let mut map = self.clock.lock().unwrap();
-        if !map.contains_key(node_id) {
-            map.insert(node_id.to_string(), Arc::new(AtomicU64::new(0)));
+        let node_id = NodeId::intern(node_id);
+        if self.clock.read().unwrap().index.contains_key(&node_id) {
+            return;
         }
+        self.clock.write().unwrap().insert(node_id);
     }
 
     /// Check if this vector clock happened before another (partial ordering)
-    pub fn happened_before(&self, other: &HashMap<String, u64>) -> bool {
+    pub fn happened_before(&self, other: &HashMap<NodeId, u64>) -> bool {
         let my_snapshot = self.snapshot();
 
         let mut strictly_less = false;
@@ -118,11 +207,13 @@ impl VectorClock {
         strictly_less
     }
 
-    pub fn tick_snapshot(&self) -> HashMap<String, u64> {
-        self.tick(); // increment local counter
-        self.snapshot() // return the snapshot
+    /// Tick the local counter and take a snapshot under the same read lock
+    pub fn tick_snapshot(&self) -> HashMap<NodeId, u64> {
+        let slots = self.clock.read().unwrap();
+        slots.counters[self.own].fetch_add(1, Ordering::SeqCst); // increment local counter
+        slots.snapshot() // return the snapshot
     }
 }
 
 /// Thread-safe shared clock
-pub type SafeVectorClock = Arc<VectorClock>;
\ No newline at end of file
+pub type SafeVectorClock = Arc<VectorClock>;
@@ -0,0 +1,145 @@
+//! Control-plane operations
+//! Membership changes, topic creation, pauses and config updates travel as events in the
+//! same causal stream as enqueues and dequeues, so every replica applies them at the same
+//! point relative to the data, and they are logged where they happened for replay.
+
+use std::fmt::{Display, Formatter};
+use serde::{Deserialize, Serialize};
+use crate::core::nodeid::NodeId;
+
+/// A control action, carried by `EventOp::Control`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ControlOp {
+    /// `member` joined the cluster; its events become deliverable from here on
+    MemberAdded { member: NodeId },
+    /// `member` left the cluster
+    MemberRemoved { member: NodeId },
+    /// The queue was created as `topic`; the first entry of a topic created on purpose
+    /// With a config, every replica applies the same settings, see `TopicConfig`
+    TopicCreated {
+        topic: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        config: Option<TopicConfig>,
+    },
+    /// The queue was deleted as `topic`; its items are dropped and nodes forget it
+    TopicDeleted { topic: String },
+    /// The settings of `topic` became `config`, replacing earlier ones; replicas keep the
+    /// highest `version`, ties going to the greater origin, whatever order they apply them in
+    TopicConfigured { topic: String, config: TopicConfig, version: u64 },
+    /// Local enqueues are refused and dequeues hand out nothing until `Resumed`
+    Paused,
+    Resumed,
+    /// The queue's dequeue lease went to `holder` until `expires_ms`, see `crate::core::buildcore::Lease`
+    LeaseGranted { holder: NodeId, term: u64, expires_ms: u64 },
+    /// The lease `holder` was granted in `term` reached the sender
+    LeaseAcked { holder: NodeId, term: u64 },
+    /// The holder of the queue's dequeue token passed it on to `to`, for the `round`th time
+    TokenPassed { to: NodeId, round: u64 },
+    /// Setting `key` of the queue changed to `value`, see `DistributedQueueSystem::control_config`
+    ConfigUpdated { key: String, value: String },
+}
+
+impl Display for ControlOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ControlOp::MemberAdded { member } => write!(f, "member {} added", member),
+            ControlOp::MemberRemoved { member } => write!(f, "member {} removed", member),
+            ControlOp::TopicCreated { topic, config: None } => write!(f, "topic {} created", topic),
+            ControlOp::TopicCreated { topic, config: Some(config) } => write!(f, "topic {} created with {}", topic, config),
+            ControlOp::TopicDeleted { topic } => write!(f, "topic {} deleted", topic),
+            ControlOp::TopicConfigured { topic, config, version } => write!(f, "topic {} configured with {} (version {})", topic, config, version),
+            ControlOp::Paused => write!(f, "paused"),
+            ControlOp::Resumed => write!(f, "resumed"),
+            ControlOp::LeaseGranted { holder, term, expires_ms } => write!(f, "lease {} granted to {} until {}", term, holder, expires_ms),
+            ControlOp::LeaseAcked { holder, term } => write!(f, "lease {} of {} acknowledged", term, holder),
+            ControlOp::TokenPassed { to, round } => write!(f, "token passed to {} (round {})", to, round),
+            ControlOp::ConfigUpdated { key, value } => write!(f, "{} set to {}", key, value),
+        }
+    }
+}
+
+/// How often a dequeued item may be handed out
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryMode {
+    /// Failed deliveries are retried, with a retry policy
+    #[default]
+    AtLeastOnce,
+    /// Deliveries aren't tracked, so `fail` knows none of them and nothing is retried
+    AtMostOnce,
+}
+
+impl Display for DeliveryMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeliveryMode::AtLeastOnce => write!(f, "at least once"),
+            DeliveryMode::AtMostOnce => write!(f, "at most once"),
+        }
+    }
+}
+
+/// Settings of a topic carried by its creation or configuration, so every replica has
+/// the same ones; unset ones keep what the node configures topics with
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopicConfig {
+    /// Most items queued
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capacity: Option<usize>,
+    /// Log entries kept for at most this many ms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention_ms: Option<u64>,
+    /// Most log entries kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention_entries: Option<usize>,
+    /// Dead-letter items after this many failed deliveries, retrying them with the
+    /// default backoff until then
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+    /// Items still queued this many ms after their enqueue are dropped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delivery_mode: Option<DeliveryMode>,
+    /// Dequeue by the node's item priority, see `DistributedQueueSystemBuilder::priority`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<bool>,
+}
+
+impl TopicConfig {
+    /// No settings: the topic is configured like any other
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// These settings with those set in `overrides` replaced
+    pub fn merge(&self, overrides: &TopicConfig) -> TopicConfig {
+        TopicConfig {
+            capacity: overrides.capacity.or(self.capacity),
+            retention_ms: overrides.retention_ms.or(self.retention_ms),
+            retention_entries: overrides.retention_entries.or(self.retention_entries),
+            max_attempts: overrides.max_attempts.or(self.max_attempts),
+            ttl_ms: overrides.ttl_ms.or(self.ttl_ms),
+            delivery_mode: overrides.delivery_mode.or(self.delivery_mode),
+            priority: overrides.priority.or(self.priority),
+        }
+    }
+}
+
+impl Display for TopicConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let settings = [
+            self.capacity.map(|max| format!("capacity {}", max)),
+            self.retention_ms.map(|ms| format!("retention {} ms", ms)),
+            self.retention_entries.map(|max| format!("retention {} entries", max)),
+            self.max_attempts.map(|max| format!("dead letters after {} attempts", max)),
+            self.ttl_ms.map(|ms| format!("ttl {} ms", ms)),
+            self.delivery_mode.map(|mode| format!("delivery {}", mode)),
+            self.priority.map(|on| format!("priority {}", if on { "on" } else { "off" })),
+        ];
+        let settings: Vec<String> = settings.into_iter().flatten().collect();
+        if settings.is_empty() {
+            return write!(f, "defaults");
+        }
+        write!(f, "{}", settings.join(", "))
+    }
+}
@@ -0,0 +1,125 @@
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+use crate::core::log::State;
+
+/// Errors returned by queue operations
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueueError {
+    /// Local enqueue rejected because the queue is at capacity
+    Full { capacity: usize },
+    /// Local enqueue rejected because the item doesn't fit in the queue's byte limit
+    FullBytes { max_bytes: usize },
+    /// Local enqueue throttled by a rate limit; a token is due after `retry_after`
+    RateLimited { retry_after: Duration },
+    /// Enqueue refused because `origin` already has `quota` unconsumed items queued
+    QuotaExceeded { origin: String, quota: usize },
+    /// Failure reported for a dequeue this node doesn't know (or no longer tracks)
+    UnknownDelivery { delivery: u64 },
+    /// State change a log entry can't make, see `State::can_become`
+    InvalidTransition { from: State, to: State },
+    /// No in-memory log entry with this ID (spilled and removed entries can't change)
+    UnknownEntry { id: u64 },
+    /// Remote event neither applied nor buffered: the causal buffer already holds `capacity` events
+    BufferFull { capacity: usize },
+    /// Remote event `event_id` of `origin` failed validation and was not applied
+    InvalidEvent { origin: String, event_id: u64, reason: EventRejection },
+    /// Local enqueue would create a topic in `namespace`, which already has its `max` topics
+    TooManyTopics { namespace: String, max: usize },
+    /// Local enqueue refused because `namespace` already has `quota` unconsumed items queued
+    NamespaceQuotaExceeded { namespace: String, quota: usize },
+    /// Purge requested on a queue whose log has no key index to find the items by
+    NoKeyIndex,
+    /// Local enqueue refused because the queue is paused, see `ControlOp::Paused`
+    Paused,
+    /// Local enqueue or dequeue refused by a read-only replica
+    ReadOnly,
+    /// Lease refused because `holder` holds the queue's lease until `expires_ms`
+    Leased { holder: String, expires_ms: u64 },
+    /// Operation refused by a node in Raft mode that doesn't lead; `leader` is the one to ask, if known
+    NotLeader { leader: Option<String> },
+    /// Operation appended to the Raft log at `index` but not applied in time; it may still be
+    CommitTimedOut { index: u64 },
+}
+
+/// Why a remote event failed validation, see `DistributedQueueSystem::try_apply_remote_event`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventRejection {
+    /// The clock has no (or a zero) component for the origin, though every event ticks it
+    MissingOriginTime,
+    /// The origin's component is more than `max_gap` past the `delivered` events of the origin
+    TooFarAhead { origin_time: u64, delivered: u64, max_gap: u64 },
+    /// The origin's component was already delivered, but under another event ID
+    Stale { origin_time: u64, delivered: u64 },
+    /// The operation needs an item and the event has none
+    MissingItem,
+    /// The operation carries no item and the event has one
+    UnexpectedItem,
+    /// From an earlier incarnation of its origin than one already heard from
+    OldEpoch { epoch: u64, newest: u64 },
+    /// From an epoch of 2^32 or more, which no clock can start at
+    EpochTooLarge { epoch: u64 },
+}
+
+impl Display for EventRejection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventRejection::MissingOriginTime => write!(f, "clock has no time for its origin"),
+            EventRejection::TooFarAhead { origin_time, delivered, max_gap } => {
+                write!(f, "origin time {} is over {} past the {} delivered", origin_time, max_gap, delivered)
+            }
+            EventRejection::Stale { origin_time, delivered } => {
+                write!(f, "origin time {} was already delivered ({} so far)", origin_time, delivered)
+            }
+            EventRejection::MissingItem => write!(f, "operation needs an item"),
+            EventRejection::UnexpectedItem => write!(f, "operation carries no item"),
+            EventRejection::OldEpoch { epoch, newest } => write!(f, "epoch {} is older than the origin's epoch {}", epoch, newest),
+            EventRejection::EpochTooLarge { epoch } => write!(f, "epoch {} is 2^32 or more", epoch),
+        }
+    }
+}
+
+impl Display for QueueError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueueError::Full { capacity } => write!(f, "queue is full (capacity {})", capacity),
+            QueueError::FullBytes { max_bytes } => write!(f, "queue is full (limit {} bytes)", max_bytes),
+            QueueError::RateLimited { retry_after } => write!(f, "rate limited, retry in {} ms", retry_after.as_millis()),
+            QueueError::QuotaExceeded { origin, quota } => write!(f, "{} already has {} unconsumed items queued, its quota", origin, quota),
+            QueueError::UnknownDelivery { delivery } => write!(f, "no delivery {} to fail", delivery),
+            QueueError::InvalidTransition { from, to } => write!(f, "log entry can't go from {:?} to {:?}", from, to),
+            QueueError::UnknownEntry { id } => write!(f, "no in-memory log entry {}", id),
+            QueueError::BufferFull { capacity } => write!(f, "event buffer is full ({} events)", capacity),
+            QueueError::TooManyTopics { namespace, max } => write!(f, "namespace {} already has {} topics, its quota", namespace, max),
+            QueueError::NamespaceQuotaExceeded { namespace, quota } => {
+                write!(f, "namespace {} already has {} unconsumed items queued, its quota", namespace, quota)
+            }
+            QueueError::InvalidEvent { origin, event_id, reason } => write!(f, "invalid event {} from {}: {}", event_id, origin, reason),
+            QueueError::NoKeyIndex => write!(f, "no log key configured to find items by"),
+            QueueError::Paused => write!(f, "queue is paused"),
+            QueueError::ReadOnly => write!(f, "replica is read-only"),
+            QueueError::Leased { holder, expires_ms } => write!(f, "queue is leased to {} until {}", holder, expires_ms),
+            QueueError::NotLeader { leader: Some(leader) } => write!(f, "not the leader, {} is", leader),
+            QueueError::NotLeader { leader: None } => write!(f, "not the leader, and no leader is known"),
+            QueueError::CommitTimedOut { index } => write!(f, "log entry {} was not applied in time", index),
+        }
+    }
+}
+
+impl QueueError {
+    /// Whether the same operation may succeed if tried again later
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            QueueError::Full { .. }
+                | QueueError::RateLimited { .. }
+                | QueueError::QuotaExceeded { .. }
+                | QueueError::BufferFull { .. }
+                | QueueError::NamespaceQuotaExceeded { .. }
+                | QueueError::Paused
+                | QueueError::Leased { .. }
+                | QueueError::NotLeader { .. }
+        )
+    }
+}
+
+impl std::error::Error for QueueError {}
@@ -1,49 +1,290 @@
-use std::sync::atomic::{AtomicU64, Ordering};
 use std::collections::HashMap;
+use std::sync::Arc;
+use crate::core::time::{Duration, SystemTime, UNIX_EPOCH};
 use serde::{Serialize, Deserialize};
+use crate::core::control::ControlOp;
+use crate::core::id;
+use crate::core::log::State;
+use crate::core::nodeid::NodeId;
+use crate::core::trace::TraceContext;
 
-static EVENT_COUNTER: AtomicU64 = AtomicU64::new(1); // global counter for unique event IDs
+/// A node's own clock counter starts at its epoch shifted left by this many bits, so a
+/// restarted node's events come after everything its previous incarnations sent
+pub const EPOCH_SHIFT: u32 = 32;
+
+/// Wall-clock time in ms since the Unix epoch
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum EventOp {
     Enqueue,
     Dequeue,
+    /// A dequeue on behalf of another node, which processes the item
+    Transfer { to: NodeId },
+    /// A replica turned down enqueue `event_id` of `origin`, e.g. over its quota
+    Reject { origin: NodeId, event_id: u64 },
+    /// A consumer failed to process the item of dequeue `delivery`; its `attempt`th failure
+    Fail { delivery: u64, attempt: u32, dead_letter: bool },
+    /// A failed item enqueued again, carrying the failures so far
+    Retry { attempt: u32 },
+    /// The log entry of event `event_id` of `origin` moved to `state`
+    StateUpdate { origin: NodeId, event_id: u64, state: State },
+    /// Every item with log key `key` removed from queues and redacted from logs everywhere
+    Purge { key: String },
+    /// A control-plane action, ordered with the data operations
+    Control { op: ControlOp },
+    /// A dequeue of the item enqueue `event_id` of `origin` added, wherever it was in the queue
+    Take { origin: NodeId, event_id: u64 },
+    /// The origin's consumers processed the items its dequeues `deliveries` took
+    Ack { deliveries: Vec<u64> },
 }
 
-#[derive( Clone, Debug, Serialize, Deserialize)]
+/// Position of an item among the items enqueued with the same ordering key, see `keyed_fifo`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeySequence {
+    pub key: String,
+    /// One past the highest sequence of the key the origin had seen; concurrent
+    /// enqueues on different nodes may share one, and are then ordered by origin
+    pub seq: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Event<T> {
     pub global_id: u64,           // unique event ID
-    pub origin_node: String,
+    pub origin_node: NodeId,
     pub op: EventOp,
-    pub item: Option<T>,
-    pub clock: HashMap<String, u64>,
+    pub item: Option<Arc<T>>,     // shared with the queue and log, never deep-copied
+    pub clock: HashMap<NodeId, u64>,
+    #[serde(default)]
+    pub timestamp_ms: u64,        // origin's wall-clock time at creation, ms since the Unix epoch (0 if unknown)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<TraceContext>, // trace of the item, on enqueues and dequeues of traced items
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<KeySequence>, // ordering key and sequence, on enqueues of keyed FIFO queues
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub epoch: u64,               // origin's incarnation, see `DistributedQueueSystemBuilder::epoch` (0 if it has none)
+    #[serde(default, skip_serializing_if = "is_zero_u32")]
+    pub delivery_attempt: u32,    // on dequeues and takes of an item, which delivery of it this is: 1 for the first, one more per failure
+}
+
+fn is_zero(epoch: &u64) -> bool {
+    *epoch == 0
+}
+
+fn is_zero_u32(attempt: &u32) -> bool {
+    *attempt == 0
+}
+
+// Manual impl: cloning an event only bumps the payload refcount, so T needn't be Clone
+impl<T> Clone for Event<T> {
+    fn clone(&self) -> Self {
+        Self {
+            global_id: self.global_id,
+            origin_node: self.origin_node,
+            op: self.op.clone(),
+            item: self.item.clone(),
+            clock: self.clock.clone(),
+            timestamp_ms: self.timestamp_ms,
+            trace: self.trace,
+            sequence: self.sequence.clone(),
+            epoch: self.epoch,
+            delivery_attempt: self.delivery_attempt,
+        }
+    }
 }
 
 impl<T> Event<T> {
 
-    fn next_id() -> u64 {
-        EVENT_COUNTER.fetch_add(1, Ordering::SeqCst)
+    /// Event IDs are Snowflake IDs of the origin, see `core::id`
+    fn next_id(origin_node: NodeId) -> u64 {
+        id::next_id(origin_node)
+    }
+
+    /// Estimated bytes of the event, counting `item_size` for its payload
+    pub(crate) fn estimated_bytes(&self, item_size: &dyn Fn(&T) -> usize) -> usize {
+        size_of::<Self>()
+            + self.clock.capacity() * size_of::<(NodeId, u64)>()
+            + self.item.as_deref().map_or(0, item_size)
+            + self.sequence.as_ref().map_or(0, |sequence| sequence.key.capacity())
     }
 
-    pub fn new_enqueue(origin_node: String, item: T, clock:  HashMap<String, u64>) -> Self {
+    pub fn new_enqueue(origin_node: NodeId, item: Arc<T>, clock:  HashMap<NodeId, u64>) -> Self {
         Self {
-            global_id: Self::next_id(),
+            global_id: Self::next_id(origin_node),
             origin_node,
             op: EventOp::Enqueue,
             item: Some(item),
             clock,
+            timestamp_ms: now_ms(),
+            trace: None,
+            sequence: None,
+            epoch: 0,
+            delivery_attempt: 0,
         }
     }
 
-    pub fn new_dequeue(origin_node: String, item: Option<T>, clock:  HashMap<String, u64>) -> Self {
+    pub fn new_dequeue(origin_node: NodeId, item: Option<Arc<T>>, clock:  HashMap<NodeId, u64>) -> Self {
         Self {
-            global_id: Self::next_id(),
+            global_id: Self::next_id(origin_node),
             origin_node,
             op: EventOp::Dequeue,
             item,
             clock,
+            timestamp_ms: now_ms(),
+            trace: None,
+            sequence: None,
+            epoch: 0,
+            delivery_attempt: 0,
         }
     }
+
+    pub fn new_transfer(origin_node: NodeId, to: NodeId, item: Arc<T>, clock: HashMap<NodeId, u64>) -> Self {
+        Self {
+            global_id: Self::next_id(origin_node),
+            origin_node,
+            op: EventOp::Transfer { to },
+            item: Some(item),
+            clock,
+            timestamp_ms: now_ms(),
+            trace: None,
+            sequence: None,
+            epoch: 0,
+            delivery_attempt: 0,
+        }
+    }
+
+    pub fn new_reject(origin_node: NodeId, rejected_origin: NodeId, rejected_id: u64, clock: HashMap<NodeId, u64>) -> Self {
+        Self {
+            global_id: Self::next_id(origin_node),
+            origin_node,
+            op: EventOp::Reject { origin: rejected_origin, event_id: rejected_id },
+            item: None,
+            clock,
+            timestamp_ms: now_ms(),
+            trace: None,
+            sequence: None,
+            epoch: 0,
+            delivery_attempt: 0,
+        }
+    }
+
+    pub fn new_fail(origin_node: NodeId, delivery: u64, attempt: u32, dead_letter: bool, item: Arc<T>, clock: HashMap<NodeId, u64>) -> Self {
+        Self {
+            global_id: Self::next_id(origin_node),
+            origin_node,
+            op: EventOp::Fail { delivery, attempt, dead_letter },
+            item: Some(item),
+            clock,
+            timestamp_ms: now_ms(),
+            trace: None,
+            sequence: None,
+            epoch: 0,
+            delivery_attempt: 0,
+        }
+    }
+
+    pub fn new_retry(origin_node: NodeId, attempt: u32, item: Arc<T>, clock: HashMap<NodeId, u64>) -> Self {
+        Self {
+            global_id: Self::next_id(origin_node),
+            origin_node,
+            op: EventOp::Retry { attempt },
+            item: Some(item),
+            clock,
+            timestamp_ms: now_ms(),
+            trace: None,
+            sequence: None,
+            epoch: 0,
+            delivery_attempt: 0,
+        }
+    }
+
+    pub fn new_state_update(origin_node: NodeId, updated_origin: NodeId, updated_id: u64, state: State, clock: HashMap<NodeId, u64>) -> Self {
+        Self {
+            global_id: Self::next_id(origin_node),
+            origin_node,
+            op: EventOp::StateUpdate { origin: updated_origin, event_id: updated_id, state },
+            item: None,
+            clock,
+            timestamp_ms: now_ms(),
+            trace: None,
+            sequence: None,
+            epoch: 0,
+            delivery_attempt: 0,
+        }
+    }
+
+    pub fn new_purge(origin_node: NodeId, key: String, clock: HashMap<NodeId, u64>) -> Self {
+        Self {
+            global_id: Self::next_id(origin_node),
+            origin_node,
+            op: EventOp::Purge { key },
+            item: None,
+            clock,
+            timestamp_ms: now_ms(),
+            trace: None,
+            sequence: None,
+            epoch: 0,
+            delivery_attempt: 0,
+        }
+    }
+
+    pub fn new_control(origin_node: NodeId, op: ControlOp, clock: HashMap<NodeId, u64>) -> Self {
+        Self {
+            global_id: Self::next_id(origin_node),
+            origin_node,
+            op: EventOp::Control { op },
+            item: None,
+            clock,
+            timestamp_ms: now_ms(),
+            trace: None,
+            sequence: None,
+            epoch: 0,
+            delivery_attempt: 0,
+        }
+    }
+
+    pub fn new_take(origin_node: NodeId, taken_origin: NodeId, taken_id: u64, item: Arc<T>, clock: HashMap<NodeId, u64>) -> Self {
+        Self {
+            global_id: Self::next_id(origin_node),
+            origin_node,
+            op: EventOp::Take { origin: taken_origin, event_id: taken_id },
+            item: Some(item),
+            clock,
+            timestamp_ms: now_ms(),
+            trace: None,
+            sequence: None,
+            epoch: 0,
+            delivery_attempt: 0,
+        }
+    }
+
+    pub fn new_ack(origin_node: NodeId, deliveries: Vec<u64>, clock: HashMap<NodeId, u64>) -> Self {
+        Self {
+            global_id: Self::next_id(origin_node),
+            origin_node,
+            op: EventOp::Ack { deliveries },
+            item: None,
+            clock,
+            timestamp_ms: now_ms(),
+            trace: None,
+            sequence: None,
+            epoch: 0,
+            delivery_attempt: 0,
+        }
+    }
+
+    /// The same event, from incarnation `epoch` of its origin
+    pub fn with_epoch(mut self, epoch: u64) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    /// Origin's wall-clock time when the event was created
+    pub fn timestamp(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.timestamp_ms)
+    }
     /// Get the timestamp for this event's originating node
     fn origin_timestamp(&self) -> u64 {
         self.clock.get(&self.origin_node).copied().unwrap_or(0)
@@ -55,7 +296,7 @@ impl<T> Event<T> {
         // Sum all clock values, weighted by node_id hash for determinism
         let mut total = 0u64;
         for (node, &time) in &self.clock {
-            let node_hash = node.chars().map(|c| c as u64).sum::<u64>();
+            let node_hash = node.as_str().chars().map(|c| c as u64).sum::<u64>();
             total = total.saturating_add(time.saturating_mul(1000).saturating_add(node_hash % 1000));
         }
         total
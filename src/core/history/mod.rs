@@ -0,0 +1,142 @@
+//! Comparing two nodes' histories
+//! Replicas that applied the same events should hold the same queue, but concurrent events
+//! may reach them in different orders, and a lost or misapplied event makes them diverge
+//! for good. `diff_histories` lines two logs up by event (origin and ID) to find what one
+//! applied and the other didn't, what they applied in a different relative order, and where
+//! their orders first part; the clock reached just before that point can be handed to
+//! `DistributedQueueSystem::rebuild_at` on both nodes to compare their queues there.
+
+use std::collections::{HashMap, HashSet};
+use serde::Serialize;
+use crate::core::log::LogEntry;
+use crate::core::nodeid::NodeId;
+
+/// An event, at the index of its entry in one of the logs compared
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct HistoryEvent {
+    pub origin: NodeId,
+    pub event_id: u64,
+    pub index: usize,
+}
+
+/// An event both logs applied, out of order with the others, at the index of its entry in each
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct Reordered {
+    pub origin: NodeId,
+    pub event_id: u64,
+    pub left_index: usize,
+    pub right_index: usize,
+}
+
+/// Where the orders of two logs first part
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Divergence {
+    /// Events both logs applied, in the same order, before it
+    pub common_prefix: usize,
+    /// Event each log applied next, None if its log ends there
+    pub left: Option<HistoryEvent>,
+    pub right: Option<HistoryEvent>,
+    /// Vector clock the common prefix reached
+    pub clock: HashMap<NodeId, u64>,
+}
+
+/// Differences between two logs, see `diff_histories`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct HistoryDiff {
+    /// Events only the left log applied, in its order
+    pub only_left: Vec<HistoryEvent>,
+    /// Events only the right log applied, in its order
+    pub only_right: Vec<HistoryEvent>,
+    /// Fewest shared events whose moves would put both logs in the same relative order
+    pub reordered: Vec<Reordered>,
+    /// None if both logs applied the same events in the same order
+    pub divergence: Option<Divergence>,
+}
+
+impl HistoryDiff {
+    /// Whether both logs applied the same events in the same order
+    pub fn is_identical(&self) -> bool {
+        self.divergence.is_none()
+    }
+
+    /// Whether both logs applied the same events, in whatever order
+    pub fn same_events(&self) -> bool {
+        self.only_left.is_empty() && self.only_right.is_empty()
+    }
+}
+
+/// Compare the events two nodes' logs applied, e.g. read with `read_logs` or `log_page`
+/// Each event counts once, at its first entry
+pub fn diff_histories<T>(left_log: &[LogEntry<T>], right_log: &[LogEntry<T>]) -> HistoryDiff {
+    let (left, right) = (applied_events(left_log), applied_events(right_log));
+    let positions = |events: &[HistoryEvent]| -> HashMap<(NodeId, u64), usize> {
+        events.iter().enumerate().map(|(position, event)| ((event.origin, event.event_id), position)).collect()
+    };
+    let (in_left, in_right) = (positions(&left), positions(&right));
+    let only = |events: &[HistoryEvent], other: &HashMap<(NodeId, u64), usize>| -> Vec<HistoryEvent> {
+        events.iter().filter(|event| !other.contains_key(&(event.origin, event.event_id))).copied().collect()
+    };
+
+    // Shared events in left order, kept in place if on a longest run in right order too
+    let shared: Vec<(HistoryEvent, HistoryEvent)> = left
+        .iter()
+        .filter_map(|event| in_right.get(&(event.origin, event.event_id)).map(|&position| (*event, right[position])))
+        .collect();
+    let in_order = longest_increasing(&shared.iter().map(|(_, right)| right.index).collect::<Vec<_>>());
+    let reordered = shared
+        .iter()
+        .enumerate()
+        .filter(|(position, _)| !in_order.contains(position))
+        .map(|(_, (left, right))| Reordered { origin: left.origin, event_id: left.event_id, left_index: left.index, right_index: right.index })
+        .collect();
+
+    let common_prefix = left
+        .iter()
+        .zip(&right)
+        .take_while(|(left, right)| (left.origin, left.event_id) == (right.origin, right.event_id))
+        .count();
+    let divergence = (common_prefix < left.len().max(right.len())).then(|| {
+        let mut clock = HashMap::new();
+        for event in &left[..common_prefix] {
+            for (&node, &time) in &left_log[event.index].event.clock {
+                let reached = clock.entry(node).or_insert(0);
+                *reached = (*reached).max(time);
+            }
+        }
+        Divergence { common_prefix, left: left.get(common_prefix).copied(), right: right.get(common_prefix).copied(), clock }
+    });
+
+    HistoryDiff { only_left: only(&left, &in_right), only_right: only(&right, &in_left), reordered, divergence }
+}
+
+/// The events of a log in the order it applied them, each at its first entry
+fn applied_events<T>(log: &[LogEntry<T>]) -> Vec<HistoryEvent> {
+    let mut seen = HashSet::new();
+    log.iter()
+        .enumerate()
+        .filter(|(_, entry)| seen.insert((entry.event.origin_node, entry.event.global_id)))
+        .map(|(index, entry)| HistoryEvent { origin: entry.event.origin_node, event_id: entry.event.global_id, index })
+        .collect()
+}
+
+/// Positions of a longest strictly increasing subsequence of `values`
+fn longest_increasing(values: &[usize]) -> HashSet<usize> {
+    // tails[k]: position ending the smallest-valued increasing run of length k + 1 found so far
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous = vec![None; values.len()];
+    for (position, &value) in values.iter().enumerate() {
+        let length = tails.partition_point(|&tail| values[tail] < value);
+        previous[position] = length.checked_sub(1).map(|before| tails[before]);
+        match tails.get_mut(length) {
+            Some(tail) => *tail = position,
+            None => tails.push(position),
+        }
+    }
+    let mut run = HashSet::new();
+    let mut position = tails.last().copied();
+    while let Some(at) = position {
+        run.insert(at);
+        position = previous[at];
+    }
+    run
+}
@@ -0,0 +1,104 @@
+//! Snowflake-style IDs, unique across the nodes and processes of a cluster
+//! An ID packs 41 bits of milliseconds since `ID_EPOCH_MS`, 10 bits of worker and 12
+//! bits of sequence within the millisecond, so IDs of one worker grow with time and a
+//! restarted process doesn't hand out the IDs of its previous run.
+//!
+//! Event and log IDs come from a generator per node, whose worker is derived from the
+//! node ID unless assigned with `assign_worker`. Derived workers of different nodes may
+//! coincide; event IDs are only ever compared along with their origin, so that is safe
+//! for them, but give each node its own worker where IDs must be unique on their own.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use crate::core::event::now_ms;
+use crate::core::nodeid::NodeId;
+
+/// Start of ID time, 2024-01-01 UTC in ms since the Unix epoch
+pub const ID_EPOCH_MS: u64 = 1_704_067_200_000;
+
+pub const WORKER_BITS: u32 = 10;
+pub const SEQUENCE_BITS: u32 = 12;
+
+/// Highest worker number
+pub const MAX_WORKER: u16 = (1 << WORKER_BITS) - 1;
+
+const SEQUENCE_MASK: u64 = (1 << SEQUENCE_BITS) - 1;
+
+/// Hands out IDs for one worker
+#[derive(Debug)]
+pub struct IdGenerator {
+    worker: u16,
+    /// Milliseconds and sequence of the last ID, packed like in the ID
+    last: AtomicU64,
+}
+
+impl IdGenerator {
+    /// Generator for `worker`, taken modulo `MAX_WORKER + 1`
+    pub const fn new(worker: u16) -> Self {
+        Self { worker: worker & MAX_WORKER, last: AtomicU64::new(0) }
+    }
+
+    /// Generator for the worker derived from `node`, see `worker_for`
+    pub fn for_node(node: &str) -> Self {
+        Self::new(worker_for(node))
+    }
+
+    pub fn worker(&self) -> u16 {
+        self.worker
+    }
+
+    /// Next ID, greater than every ID handed out before
+    /// Past 4096 IDs in a millisecond, or with the wall clock set back, IDs run ahead of
+    /// the clock rather than waiting for it
+    pub fn next_id(&self) -> u64 {
+        let now = now_ms().saturating_sub(ID_EPOCH_MS) << SEQUENCE_BITS;
+        let mut last = self.last.load(Ordering::Relaxed);
+        loop {
+            let next = now.max(last + 1);
+            match self.last.compare_exchange_weak(last, next, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => {
+                    let (ms, sequence) = (next >> SEQUENCE_BITS, next & SEQUENCE_MASK);
+                    return (ms << (WORKER_BITS + SEQUENCE_BITS)) | (u64::from(self.worker) << SEQUENCE_BITS) | sequence;
+                }
+                Err(current) => last = current,
+            }
+        }
+    }
+}
+
+/// Parts of an ID: creation time in ms since the Unix epoch, worker and sequence
+pub fn split_id(id: u64) -> (u64, u16, u16) {
+    let ms = (id >> (WORKER_BITS + SEQUENCE_BITS)) + ID_EPOCH_MS;
+    let worker = ((id >> SEQUENCE_BITS) & u64::from(MAX_WORKER)) as u16;
+    (ms, worker, (id & SEQUENCE_MASK) as u16)
+}
+
+/// Worker derived from a node ID: its FNV-1a hash, the same in every process
+pub fn worker_for(node: &str) -> u16 {
+    let hash = node.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3));
+    (hash % (u64::from(MAX_WORKER) + 1)) as u16
+}
+
+fn generators() -> &'static Mutex<HashMap<NodeId, Arc<IdGenerator>>> {
+    static GENERATORS: OnceLock<Mutex<HashMap<NodeId, Arc<IdGenerator>>>> = OnceLock::new();
+    GENERATORS.get_or_init(Default::default)
+}
+
+/// Have `node` hand out IDs as `worker` in this process, e.g. from the node's
+/// configuration; set it before the node creates events
+pub fn assign_worker(node: &str, worker: u16) {
+    let generator = IdGenerator::new(worker);
+    let mut generators = generators().lock().unwrap();
+    if let Some(previous) = generators.get(&NodeId::intern(node)) {
+        // Carry on after the IDs handed out so far, in case the worker stays the same
+        generator.last.store(previous.last.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+    generators.insert(NodeId::intern(node), Arc::new(generator));
+}
+
+/// Next ID of `node`, for its events and log entries
+pub(crate) fn next_id(node: NodeId) -> u64 {
+    let generator = generators().lock().unwrap().entry(node).or_insert_with(|| Arc::new(IdGenerator::for_node(node.as_str()))).clone();
+    generator.next_id()
+}
@@ -0,0 +1,29 @@
+//! Dequeue leases
+//! Replicas apply a remote dequeue to whatever item their own queue would hand out, so
+//! two nodes dequeuing at once can both hand out the head. With leases on (see
+//! `DistributedQueueSystemBuilder::lease`) only the node holding the queue's lease
+//! dequeues. A lease is granted by a `ControlOp::LeaseGranted` in the causal stream,
+//! and held once every peer has acknowledged it with a `ControlOp::LeaseAcked`, so a
+//! concurrent grant, resolved the same way everywhere, can't go unnoticed by its loser.
+
+use serde::{Deserialize, Serialize};
+use crate::core::nodeid::NodeId;
+
+/// The latest lease granted on a queue
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lease {
+    pub holder: NodeId,
+    /// Raised by every grant; the highest term wins, ties going to the greater holder
+    pub term: u64,
+    /// Wall-clock ms the holder stops dequeuing at; others wait out clock skew on top
+    pub expires_ms: u64,
+    /// The holder's clock time of the grant, which acknowledging peers have reached
+    pub granted_at: u64,
+}
+
+impl Lease {
+    /// Whether this lease wins over `other`, so every replica keeps the same one
+    pub fn supersedes(&self, other: &Lease) -> bool {
+        (self.term, self.holder) > (other.term, other.holder)
+    }
+}
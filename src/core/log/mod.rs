@@ -1,33 +1,102 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fmt::{Display, Formatter};
-use std::fs::OpenOptions;
+use std::ops::Range;
+use crate::core::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicU64, Ordering};
-use crate::core::event::Event;
+use std::sync::mpsc::{self, Receiver, Sender};
+use crate::core::error::QueueError;
+use crate::core::id;
+use crate::core::event::{Event, now_ms};
+use crate::core::nodeid::NodeId;
+use crate::core::retention::{RetentionPolicy, is_stable};
+use crate::core::storage::{LogSink, LogStore};
 use serde::{Serialize, Deserialize};
-use std::io::Write;
+#[cfg(feature = "persist")]
+use crate::core::storage::{LogFormat, LogWriter};
 
-static LOG_ID_COUNTER: AtomicU64 = AtomicU64::new(1);
 /// State of a queue operation
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// Entries only move forward: Pending → Committed → Delivered, any of those → Failed,
+/// and Failed → DeadLettered once the item is out of attempts
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum State {
     Pending,
     Committed,
     Delivered,
     Failed,
+    DeadLettered,
+}
+
+impl State {
+    /// Check if an entry in this state may move to `next`
+    pub fn can_become(self, next: State) -> bool {
+        matches!(
+            (self, next),
+            (State::Pending, State::Committed)
+                | (State::Committed, State::Delivered)
+                | (State::Pending | State::Committed | State::Delivered, State::Failed)
+                | (State::Failed, State::DeadLettered)
+        )
+    }
 }
 
 /// Log entry recording an operation
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Item and clock live in the shared event rather than being copied into every entry
+#[derive(Debug, Serialize, Deserialize)]
 pub struct LogEntry<T> {
     pub local_log_id: u64,
-    pub local_node: String,
-    pub op: String,                //"enqueue" or "dequeue"
-    pub item: Option<T>,      // The item being enqueued/dequeued
+    pub local_node: NodeId,
+    pub op: String,                //"enqueue", "dequeue", "transfer", "reject", "fail", "update", "purge", "expire", "ack" or "control"
     pub state: State,              // Current State
-    pub clock:HashMap<String, u64>,              // Logical Clock
-    pub event_global_id: Option<u64>,
-    pub event: Option<Event<T>>,
+    pub event: Arc<Event<T>>,      // The event this entry records
+    #[serde(default)]
+    pub logged_at_ms: u64,         // local wall-clock time of logging, ms since the Unix epoch (0 if unknown)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub redacted: bool,            // item removed by a purge
+}
+
+impl<T> LogEntry<T> {
+    /// The item being enqueued/dequeued
+    /// For replicated dequeues this is the item the origin node delivered
+    pub fn item(&self) -> Option<&Arc<T>> {
+        self.event.item.as_ref()
+    }
+
+    /// Logical clock of the operation
+    pub fn clock(&self) -> &HashMap<NodeId, u64> {
+        &self.event.clock
+    }
+
+    /// Local wall-clock time the entry was logged
+    pub fn logged_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.logged_at_ms)
+    }
+
+    /// Global ID of the recorded event
+    pub fn event_global_id(&self) -> u64 {
+        self.event.global_id
+    }
+
+    /// Drop the item, keeping the rest of the entry as a record of the operation
+    pub(crate) fn redact(&mut self) {
+        let mut event = (*self.event).clone();
+        event.item = None;
+        self.event = Arc::new(event);
+        self.redacted = true;
+    }
+}
+
+impl<T> Clone for LogEntry<T> {
+    fn clone(&self) -> Self {
+        Self {
+            local_log_id: self.local_log_id,
+            local_node: self.local_node,
+            op: self.op.clone(),
+            state: self.state,
+            event: self.event.clone(),
+            logged_at_ms: self.logged_at_ms,
+            redacted: self.redacted,
+        }
+    }
 }
 
 impl <T: std::fmt::Debug> Display for LogEntry<T> {
@@ -38,32 +107,493 @@ impl <T: std::fmt::Debug> Display for LogEntry<T> {
             self.local_log_id,
             self.local_node,
             self.op,
-            self.item,
+            self.item(),
             self.state,
-            self.clock,
-            self.event_global_id,
+            self.clock(),
+            self.event_global_id(),
             self.event,
         )
     }
 }
 
 
-#[derive(Clone, Debug)]
+/// Entries per in-memory log segment
+const SEGMENT_SIZE: usize = 256;
+
+/// Up to `SEGMENT_SIZE` consecutive spilled entries, summarized by the latest time of each node
+/// in their clocks, so `get_entries_since` reads back only the runs that can hold later entries
+struct SpilledRun {
+    len: usize,
+    latest: HashMap<NodeId, u64>,
+}
+
+/// Extracts the key an item is indexed by, see `Logger::with_key_index`
+pub(crate) type KeyFn<T> = Box<dyn Fn(&T) -> Option<String> + Send>;
+
+/// Read-only snapshot of the in-memory log
+/// Holds the shared segments, so readers iterate without holding the logger lock
+/// and without copying entries
+pub struct LogView<T> {
+    segments: Vec<Arc<Vec<LogEntry<T>>>>,
+}
+
+impl<T> LogView<T> {
+    /// Iterate entries in log order
+    pub fn iter(&self) -> impl Iterator<Item = &LogEntry<T>> {
+        self.segments.iter().flat_map(|s| s.iter())
+    }
+
+    /// Number of entries in the snapshot
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(|s| s.len()).sum()
+    }
+
+    /// Check if the snapshot is empty
+    pub fn is_empty(&self) -> bool {
+        self.segments.iter().all(|s| s.is_empty())
+    }
+
+    /// Copy the entries out
+    pub fn to_vec(&self) -> Vec<LogEntry<T>> {
+        self.iter().cloned().collect()
+    }
+}
+
 /// Logger storing all entries
+/// Entries live in fixed-size segments shared with `LogView` readers; only the
+/// tail segment is written, and it is copied on write if a reader still holds it.
+/// With a cap configured, the oldest entries are spilled to the storage backend
+/// and can be paged back with `page`
+/// With a retention policy, old entries are removed from the front altogether
+/// Entries are indexed by event ID (and item key, if configured) by their position
+/// counting every entry ever logged, so lookups don't scan the log
 pub struct Logger<T> {
-    pub(crate) entries: Vec<LogEntry<T>>,
-    local_node: String,
+    segments: VecDeque<Arc<Vec<LogEntry<T>>>>,
+    local_node: NodeId,
+    max_entries: Option<usize>,
+    store: Option<Box<dyn LogStore<T>>>,
+    sink: Option<Box<dyn LogSink<T>>>,
+    retention: Option<RetentionPolicy<T>>,
+    /// When the newest entries were logged, only tracked for retention;
+    /// older entries (e.g. already in an opened store) have no known age
+    logged_at: VecDeque<SystemTime>,
+    /// Entries removed by retention
+    discarded: usize,
+    /// Summaries of the newest spilled entries, oldest first; entries in the store before them have none
+    spilled_runs: VecDeque<SpilledRun>,
+    by_event: HashMap<u64, Vec<usize>>,
+    by_key: HashMap<String, Vec<usize>>,
+    key_fn: Option<KeyFn<T>>,
+    /// Receivers of every new entry, dropped once their receiver is gone
+    subscribers: Vec<Sender<LogEntry<T>>>,
 }
 
-impl<T:Clone> Logger<T> {
-    pub  fn new(local_node: String) -> Self {
-        Self {entries:Vec::new(), local_node}
+impl<T> Logger<T> {
+    pub  fn new(local_node: NodeId) -> Self {
+        Self {
+            segments: VecDeque::new(),
+            local_node,
+            max_entries: None,
+            store: None,
+            sink: None,
+            retention: None,
+            logged_at: VecDeque::new(),
+            discarded: 0,
+            spilled_runs: VecDeque::new(),
+            by_event: HashMap::new(),
+            by_key: HashMap::new(),
+            key_fn: None,
+            subscribers: Vec::new(),
+        }
+    }
+
+    /// Also index entries by the key `key_fn` extracts from their item, see `find_by_key`
+    /// Entries already in a reopened store are not indexed
+    pub fn with_key_index(self, key_fn: impl Fn(&T) -> Option<String> + Send + 'static) -> Self {
+        self.with_key_fn(Box::new(key_fn))
+    }
+
+    pub(crate) fn with_key_fn(mut self, key_fn: KeyFn<T>) -> Self {
+        self.key_fn = Some(key_fn);
+        self
+    }
+
+    /// Keep at most `max_entries` in memory; older ones are spilled to the
+    /// storage backend, or dropped if there is none
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Storage backend for spilled entries
+    /// Entries already in it are read once, to summarize them for `get_entries_since`
+    pub fn with_store(mut self, store: Box<dyn LogStore<T>>) -> Self {
+        let mut offset = 0;
+        while offset < store.len() {
+            let Ok(entries) = store.read(offset, SEGMENT_SIZE) else {
+                // Left unsummarized, so always read
+                self.spilled_runs.clear();
+                break;
+            };
+            if entries.is_empty() {
+                break;
+            }
+            offset += entries.len();
+            self.summarize_spilled(&entries);
+        }
+        self.store = Some(store);
+        self
+    }
+
+    /// Sink receiving every entry as it is logged
+    pub fn with_sink(mut self, sink: Box<dyn LogSink<T>>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    /// Remove old entries according to `policy` (see `enforce_retention`)
+    pub fn with_retention(mut self, policy: RetentionPolicy<T>) -> Self {
+        self.retention = Some(policy);
+        self
+    }
+
+    /// Replace the retention policy, or stop removing entries with None; applies from the next entry logged
+    pub fn set_retention(&mut self, policy: Option<RetentionPolicy<T>>) {
+        self.retention = policy;
+    }
+
+    /// Receive every entry logged from now on, in log order
+    /// The channel is unbounded, so keep up with it; drop the receiver to unsubscribe
+    pub fn subscribe(&mut self) -> Receiver<LogEntry<T>> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    /// Check if a retention policy is set
+    pub fn has_retention(&self) -> bool {
+        self.retention.is_some()
+    }
+
+    /// Positions of the entries still held, counting every entry ever logged;
+    /// `page` offsets are relative to the start of this range
+    pub fn retained_range(&self) -> Range<usize> {
+        self.discarded..self.discarded + self.len()
+    }
+
+    /// Up to `limit` entries from absolute position `position` on, or from the oldest retained
+    /// entry if that one was discarded; returns the position of the first entry with them
+    pub fn read_from(&self, position: usize, limit: usize) -> std::io::Result<(usize, Vec<LogEntry<T>>)> {
+        let start = position.max(self.discarded);
+        Ok((start, self.page(start - self.discarded, limit)?))
+    }
+
+    /// Snapshot of the in-memory entries for lock-free reading
+    pub fn view(&self) -> LogView<T> {
+        LogView { segments: self.segments.iter().cloned().collect() }
+    }
+
+    /// Iterate in-memory entries in log order
+    pub fn iter(&self) -> impl Iterator<Item = &LogEntry<T>> {
+        self.segments.iter().flat_map(|s| s.iter())
+    }
+
+    /// Number of entries held in memory
+    pub fn in_memory(&self) -> usize {
+        self.segments.iter().map(|s| s.len()).sum()
+    }
+
+    /// Estimated bytes of the entries held in memory and their indexes, counting
+    /// `item_size` for each payload
+    pub(crate) fn estimated_bytes(&self, item_size: &dyn Fn(&T) -> usize) -> usize {
+        let entries: usize = self
+            .segments
+            .iter()
+            .map(|segment| segment.capacity() * size_of::<LogEntry<T>>() + segment.iter().map(|entry| entry.op.capacity() + entry.event.estimated_bytes(item_size)).sum::<usize>())
+            .sum();
+        let by_event: usize = self.by_event.values().map(|positions| positions.capacity() * size_of::<usize>()).sum();
+        let by_key: usize = self.by_key.iter().map(|(key, positions)| key.capacity() + positions.capacity() * size_of::<usize>()).sum();
+        entries
+            + self.logged_at.capacity() * size_of::<SystemTime>()
+            + self.by_event.capacity() * size_of::<(u64, Vec<usize>)>()
+            + by_event
+            + self.by_key.capacity() * size_of::<(String, Vec<usize>)>()
+            + by_key
+    }
+
+    /// Number of entries spilled to the storage backend
+    pub fn spilled(&self) -> usize {
+        self.store.as_ref().map_or(0, |s| s.len())
+    }
+
+    /// Total number of entries, in memory and spilled
+    pub fn len(&self) -> usize {
+        self.spilled() + self.in_memory()
+    }
+
+    /// Check if nothing has been logged
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read up to `limit` entries starting at `offset`, transparently reading
+    /// spilled entries back from the storage backend
+    pub fn page(&self, offset: usize, limit: usize) -> std::io::Result<Vec<LogEntry<T>>> {
+        let spilled = self.spilled();
+        let mut page = match &self.store {
+            Some(store) if offset < spilled => store.read(offset, limit)?,
+            _ => Vec::new(),
+        };
+        let start = offset.saturating_sub(spilled);
+        let remaining = limit - page.len();
+        page.extend(self.iter().skip(start).take(remaining).cloned());
+        Ok(page)
+    }
+
+    /// Segment and offset of in-memory entry `index`; every segment but the first and last is full
+    fn locate(&self, index: usize) -> Option<(usize, usize)> {
+        let front = self.segments.front()?.len();
+        let (segment, offset) = match index.checked_sub(front) {
+            None => (0, index),
+            Some(rest) => (1 + rest / SEGMENT_SIZE, rest % SEGMENT_SIZE),
+        };
+        (offset < self.segments.get(segment)?.len()).then_some((segment, offset))
+    }
+
+    fn get(&self, index: usize) -> Option<&LogEntry<T>> {
+        let (segment, offset) = self.locate(index)?;
+        Some(&self.segments[segment][offset])
+    }
+
+    /// Entry at an absolute position, reading it back from the store if it was spilled
+    fn at(&self, position: usize) -> std::io::Result<Option<LogEntry<T>>> {
+        let Some(offset) = position.checked_sub(self.discarded) else {
+            return Ok(None);
+        };
+        let spilled = self.spilled();
+        match &self.store {
+            Some(store) if offset < spilled => Ok(store.read(offset, 1)?.pop()),
+            _ => Ok(self.get(offset - spilled).cloned()),
+        }
+    }
+
+    fn lookup(&self, positions: Option<&Vec<usize>>) -> std::io::Result<Vec<LogEntry<T>>> {
+        let mut entries = Vec::new();
+        for &position in positions.into_iter().flatten() {
+            entries.extend(self.at(position)?);
+        }
+        Ok(entries)
+    }
+
+    /// Entries recording event `event_id`, in log order, spilled ones included
+    /// Usually one; events of different origins can share an ID
+    pub fn find_by_event_id(&self, event_id: u64) -> std::io::Result<Vec<LogEntry<T>>> {
+        self.lookup(self.by_event.get(&event_id))
+    }
+
+    /// Entries whose item has `key`, in log order, spilled ones included
+    /// Always empty without `with_key_index`
+    pub fn find_by_key(&self, key: &str) -> std::io::Result<Vec<LogEntry<T>>> {
+        self.lookup(self.by_key.get(key))
+    }
+
+    /// Key the configured key index extracts from `item`, if any
+    pub(crate) fn key_of(&self, item: &T) -> Option<String> {
+        self.key_fn.as_ref().and_then(|key_fn| key_fn(item))
+    }
+
+    /// Check if entries are indexed by item key
+    pub fn has_key_index(&self) -> bool {
+        self.key_fn.is_some()
+    }
+
+    /// Redact every entry whose item has `key`, spilled ones included, and forget the key
+    /// Redacted entries keep their place, op and clock but lose their item
+    /// Returns the number of entries redacted; sinks already written are not touched
+    pub fn redact_key(&mut self, key: &str) -> std::io::Result<usize> {
+        let Some(positions) = self.by_key.remove(key) else {
+            return Ok(0);
+        };
+        let first_spilled = self.discarded;
+        let first_in_memory = first_spilled + self.spilled();
+        let spilled: Vec<usize> = positions.iter().filter(|&&p| p < first_in_memory).map(|&p| p - first_spilled).collect();
+        for &position in &positions {
+            if let Some((segment, offset)) = position.checked_sub(first_in_memory).and_then(|i| self.locate(i)) {
+                Arc::make_mut(&mut self.segments[segment])[offset].redact();
+            }
+        }
+        if let Some(store) = self.store.as_mut()
+            && !spilled.is_empty()
+        {
+            store.redact(&spilled)?;
+        }
+        Ok(positions.len())
+    }
+
+    /// Index an entry about to be logged at `position`
+    fn index(&mut self, entry: &LogEntry<T>, position: usize) {
+        self.by_event.entry(entry.event.global_id).or_default().push(position);
+        if let Some(key) = self.key_fn.as_ref().zip(entry.item()).and_then(|(key_fn, item)| key_fn(item)) {
+            self.by_key.entry(key).or_default().push(position);
+        }
+    }
+
+    /// Drop index positions of entries no longer held, `removed` being the oldest ones left
+    fn unindex(&mut self, removed: &[LogEntry<T>]) {
+        let first_kept = self.discarded;
+        let prune = |positions: &mut Vec<usize>| {
+            positions.retain(|&p| p >= first_kept);
+            positions.is_empty()
+        };
+        for entry in removed {
+            if let Some(positions) = self.by_event.get_mut(&entry.event.global_id)
+                && prune(positions)
+            {
+                self.by_event.remove(&entry.event.global_id);
+            }
+            if let Some(key) = self.key_fn.as_ref().zip(entry.item()).and_then(|(key_fn, item)| key_fn(item))
+                && let Some(positions) = self.by_key.get_mut(&key)
+                && prune(positions)
+            {
+                self.by_key.remove(&key);
+            }
+        }
+    }
+
+    /// Append to the tail segment, opening a new one when it is full
+    fn push(&mut self, entry: LogEntry<T>) {
+        if self.segments.back().is_none_or(|s| s.len() >= SEGMENT_SIZE) {
+            self.segments.push_back(Arc::new(Vec::with_capacity(SEGMENT_SIZE)));
+        }
+        let tail = self.segments.back_mut().unwrap();
+        Arc::make_mut(tail).push(entry);
+    }
+
+    /// Move entries beyond the cap to the storage backend
+    fn spill(&mut self) -> std::io::Result<()> {
+        let Some(max) = self.max_entries else {
+            return Ok(());
+        };
+        let in_memory = self.in_memory();
+        if in_memory <= max {
+            return Ok(());
+        }
+        let overflow = in_memory - max;
+        let oldest: Vec<LogEntry<T>> = self.iter().take(overflow).cloned().collect();
+        match self.store.as_mut() {
+            Some(store) => {
+                store.append(&oldest)?;
+                self.summarize_spilled(&oldest);
+            }
+            None => {
+                self.discarded += overflow;
+                self.unindex(&oldest);
+            }
+        }
+        self.drop_front(overflow);
+        Ok(())
+    }
+
+    /// Add entries just spilled to the summaries of the runs they fall in
+    fn summarize_spilled(&mut self, entries: &[LogEntry<T>]) {
+        for entry in entries {
+            if self.spilled_runs.back().is_none_or(|run| run.len >= SEGMENT_SIZE) {
+                self.spilled_runs.push_back(SpilledRun { len: 0, latest: HashMap::new() });
+            }
+            let run = self.spilled_runs.back_mut().unwrap();
+            run.len += 1;
+            for (&node, &time) in entry.clock() {
+                let latest = run.latest.entry(node).or_insert(0);
+                *latest = (*latest).max(time);
+            }
+        }
+    }
+
+    /// Forget the summaries of the oldest `count` spilled entries, once discarded from the store
+    /// A run losing some of its entries keeps its summary, which still covers the rest
+    fn discard_spilled(&mut self, count: usize) {
+        // Unsummarized entries, before the runs, went first; the store is already `count` shorter
+        let summarized: usize = self.spilled_runs.iter().map(|run| run.len).sum();
+        let unsummarized = (self.spilled() + count).saturating_sub(summarized);
+        let mut count = count.saturating_sub(unsummarized);
+        while count > 0 {
+            let Some(front) = self.spilled_runs.front_mut() else { break };
+            if front.len <= count {
+                count -= front.len;
+                self.spilled_runs.pop_front();
+            } else {
+                front.len -= count;
+                count = 0;
+            }
+        }
+    }
+
+    /// Remove the oldest `count` in-memory entries
+    fn drop_front(&mut self, mut overflow: usize) {
+        while overflow > 0 {
+            let front = self.segments.front_mut().unwrap();
+            if front.len() <= overflow {
+                overflow -= front.len();
+                self.segments.pop_front();
+            } else {
+                Arc::make_mut(front).drain(..overflow);
+                overflow = 0;
+            }
+        }
+        // Ages belong to the newest entries
+        let excess = self.logged_at.len().saturating_sub(self.len());
+        self.logged_at.drain(..excess);
+    }
+
+    /// Remove entries the retention policy no longer keeps, oldest first,
+    /// stopping at the first one whose clock is not covered by `watermark`
+    /// Returns the number of entries removed
+    pub fn enforce_retention(&mut self, watermark: &HashMap<NodeId, u64>, now: SystemTime) -> std::io::Result<usize> {
+        let Some(policy) = &self.retention else {
+            return Ok(0);
+        };
+        let len = self.len();
+        let unknown_age = len - self.logged_at.len();
+        let over_cap = policy.max_entries.map_or(0, |max| len.saturating_sub(max));
+        let too_old = policy.max_age.map_or(0, |age| {
+            unknown_age + self.logged_at.iter().take_while(|&&t| now.duration_since(t).is_ok_and(|d| d > age)).count()
+        });
+        let candidates = if policy.once_stable { len } else { over_cap.max(too_old) };
+        if candidates == 0 {
+            return Ok(0);
+        }
+        let oldest = self.page(0, candidates)?;
+        let removable = oldest.iter().take_while(|e| is_stable(e.clock(), watermark)).count();
+        if removable == 0 {
+            return Ok(0);
+        }
+        if let Some(archive) = self.retention.as_mut().and_then(|p| p.archive.as_mut()) {
+            for entry in &oldest[..removable] {
+                archive.write(entry)?;
+            }
+        }
+        let from_store = removable.min(self.spilled());
+        if let Some(store) = self.store.as_mut()
+            && from_store > 0
+        {
+            store.discard_front(from_store)?;
+            self.discard_spilled(from_store);
+        }
+        self.drop_front(removable - from_store);
+        self.discarded += removable;
+        self.unindex(&oldest[..removable]);
+        Ok(removable)
     }
 
     /// Log an operation
-    pub fn log(&mut self, op: &str, item: Option<T>, state: State, clock: HashMap<String, u64>, event_global_id: Option<u64>, event: Event<T>) {
+    /// The entry is logged even if writing it to the sink or spilling to the store fails;
+    /// the first such error is returned
+    pub fn log(&mut self, op: &str, state: State, event: Arc<Event<T>>) -> std::io::Result<()> {
         // --- Negative-space assertion: op validity ---
-        assert!(op == "enqueue" || op == "dequeue", "Operation must be enqueue or dequeue");
+        assert!(
+            matches!(op, "enqueue" | "dequeue" | "transfer" | "reject" | "fail" | "update" | "purge" | "expire" | "ack" | "control"),
+            "Operation must be enqueue, dequeue, transfer, reject, fail, update, purge, expire, ack or control"
+        );
 
         // --- Negative-space assertion: state must match operation ---
         if op == "enqueue" {
@@ -72,72 +602,122 @@ impl<T:Clone> Logger<T> {
                 "Enqueue must start as Pending or Commited"
             );
         }
-        if op == "dequeue" {
+        if op == "dequeue" || op == "transfer" {
             assert!(
                 matches!(state, State::Delivered),
                 "Dequeue must result in Delivered"
             );
         }
+        if op == "reject" || op == "fail" {
+            assert!(matches!(state, State::Failed), "Reject and fail must be Failed");
+        }
+        if op == "update" {
+            assert!(!matches!(state, State::Pending), "Nothing moves back to Pending");
+        }
+        if matches!(op, "purge" | "expire" | "ack" | "control") {
+            assert!(matches!(state, State::Committed), "Purge, expire, ack and control must be Committed");
+        }
 
-        let local_log_id = LOG_ID_COUNTER.fetch_add(1, Ordering::SeqCst);
+        let local_log_id = id::next_id(self.local_node);
 
-        // --- Log entry insertion ---
-        let before = self.entries.len();
-        self.entries.push(LogEntry {
+        let entry = LogEntry {
             local_log_id,
-            local_node: self.local_node.clone(),
+            local_node: self.local_node,
             op: op.into(),
-            item,
             state,
-            clock,
-            event_global_id ,
-            event:Some(event),
-        });
+            event,
+            logged_at_ms: now_ms(),
+            redacted: false,
+        };
+        let written = match self.sink.as_mut() {
+            Some(sink) => sink.write(&entry),
+            None => Ok(()),
+        };
+
+        self.subscribers.retain(|tx| tx.send(entry.clone()).is_ok());
+
+        // --- Log entry insertion ---
+        let before = self.in_memory();
+        self.index(&entry, self.discarded + self.len());
+        self.push(entry);
+        if self.retention.is_some() {
+            self.logged_at.push_back(SystemTime::now());
+        }
 
         // --- Negative-space assertion: log length increased exactly by 1 ---
         assert_eq!(
-            self.entries.len(),
+            self.in_memory(),
             before + 1,
             "Logger must increase by exactly one entry"
         );
+
+        // Entries stay in memory if the backend fails, so nothing is lost
+        let spilled = self.spill();
+        written.and(spilled)
+    }
+
+    /// Update the state of an in-memory entry (spilled entries are immutable)
+    /// Fails if there is no such entry or `State::can_become` forbids the transition
+    pub fn update_entry_state(&mut self, log_id:u64, new_state:State) -> Result<(), QueueError> {
+        let index = self.iter().position(|e| e.local_log_id == log_id).ok_or(QueueError::UnknownEntry { id: log_id })?;
+        self.set_state(index, new_state)
+    }
+
+    /// Update the state of the in-memory entry recording event `event_id` of `origin`
+    /// Log IDs are local, so this is how replicas address the same entry
+    pub fn update_event_state(&mut self, origin: NodeId, event_id: u64, new_state: State) -> Result<(), QueueError> {
+        let first_in_memory = self.discarded + self.spilled();
+        let index = self
+            .by_event
+            .get(&event_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|&p| p.checked_sub(first_in_memory))
+            .find(|&i| self.get(i).is_some_and(|e| e.event.origin_node == origin))
+            .ok_or(QueueError::UnknownEntry { id: event_id })?;
+        self.set_state(index, new_state)
     }
 
-    pub fn update_entry_state(&mut self, log_id:u64, new_state:State) -> bool{
-        if let Some(entry) = self.entries.iter_mut().find(|e| e.local_log_id ==log_id){
-            entry.state = new_state;
-            true
-        } else{
-            false
+    /// Move in-memory entry `index` to `new_state` if `State::can_become` allows it
+    fn set_state(&mut self, index: usize, new_state: State) -> Result<(), QueueError> {
+        let (segment, offset) = self.locate(index).expect("index of an in-memory entry");
+        let from = self.segments[segment][offset].state;
+        if !from.can_become(new_state) {
+            return Err(QueueError::InvalidTransition { from, to: new_state });
         }
+        Arc::make_mut(&mut self.segments[segment])[offset].state = new_state;
+        Ok(())
     }
 
-    pub fn get_entries_since(&self, clock: &HashMap<String, u64>) -> Vec<LogEntry<T>> {
-        self.entries
-            .iter()
-            .filter(|entry| {
-                // happened_after: entry.clock > given clock
-                entry.clock.iter().any(|(node, &time)| {
-                    let &other_time = clock.get(node).unwrap_or(&0);
-                    time > other_time
-                })
-            })
-            .cloned()
-            .collect()
+    /// Entries (spilled ones included) that happened after the given clock
+    /// Spilled entries are only read back from runs whose summary has a later time than `clock`
+    pub fn get_entries_since(&self, clock: &HashMap<NodeId, u64>) -> std::io::Result<Vec<LogEntry<T>>> {
+        // happened_after: some time in the entry's clock is past the given one
+        let after = |entry: &LogEntry<T>| !is_stable(entry.clock(), clock);
+        let mut entries = Vec::new();
+        if let Some(store) = &self.store {
+            let summarized: usize = self.spilled_runs.iter().map(|run| run.len).sum();
+            let mut offset = store.len().saturating_sub(summarized);
+            if offset > 0 {
+                entries.extend(store.read(0, offset)?.into_iter().filter(after));
+            }
+            for run in &self.spilled_runs {
+                if !is_stable(&run.latest, clock) {
+                    entries.extend(store.read(offset, run.len)?.into_iter().filter(after));
+                }
+                offset += run.len;
+            }
+        }
+        entries.extend(self.iter().filter(|entry| after(entry)).cloned());
+        Ok(entries)
     }
 }
 
 
-pub fn append_logs<T: Serialize>(log: &Vec<LogEntry<T>>, path: &str) -> std::io::Result<()> {
-    let mut file = OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open(path)?;
-
-    for entry in log {
-        let json = serde_json::to_string(entry).expect("Serialization failed");
-        writeln!(file, "{}", json)?; // one JSON object per line
-    }
-    Ok(())
+/// Append entries to an NDJSON file; see `LogWriter` for other formats
+#[cfg(feature = "persist")]
+pub fn append_logs<T: Serialize>(log: &[LogEntry<T>], path: &str) -> std::io::Result<()> {
+    LogWriter::append_to(path, LogFormat::Ndjson)?.write_entries(log)
 }
 /// Thread-safe wrapper
 pub type SafeLogger<T> = Arc<Mutex<Logger<T>>>;
\ No newline at end of file
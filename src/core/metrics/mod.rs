@@ -0,0 +1,122 @@
+//! Operation counters; compiled down to no-ops without the `metrics` feature
+
+#[cfg(feature = "metrics")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "metrics")]
+use serde::{Deserialize, Serialize};
+
+/// Countable queue operations
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Counter {
+    Enqueued,
+    Dequeued,
+    RemoteApplied,
+    RemoteDuplicates,
+    RemoteBuffered,
+    BufferDropped,
+    RemoteRejected,
+    ClockSkewed,
+    Throttled,
+    QuotaExceeded,
+    Failed,
+    Retried,
+    DeadLettered,
+    RemoteInvalid,
+    Quarantined,
+    StoreFailed,
+    TransitionRejected,
+}
+
+/// Per-node operation counters
+#[cfg(feature = "metrics")]
+#[derive(Debug, Default)]
+pub struct Metrics {
+    counters: [AtomicU64; 17],
+}
+
+#[cfg(feature = "metrics")]
+impl Metrics {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Increment a counter
+    pub(crate) fn inc(&self, counter: Counter) {
+        self.counters[counter as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn get(&self, counter: Counter) -> u64 {
+        self.counters[counter as usize].load(Ordering::Relaxed)
+    }
+
+    /// Point-in-time copy of all counters
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            enqueued: self.get(Counter::Enqueued),
+            dequeued: self.get(Counter::Dequeued),
+            remote_applied: self.get(Counter::RemoteApplied),
+            remote_duplicates: self.get(Counter::RemoteDuplicates),
+            remote_buffered: self.get(Counter::RemoteBuffered),
+            buffer_dropped: self.get(Counter::BufferDropped),
+            remote_rejected: self.get(Counter::RemoteRejected),
+            clock_skewed: self.get(Counter::ClockSkewed),
+            throttled: self.get(Counter::Throttled),
+            quota_exceeded: self.get(Counter::QuotaExceeded),
+            failed: self.get(Counter::Failed),
+            retried: self.get(Counter::Retried),
+            dead_lettered: self.get(Counter::DeadLettered),
+            remote_invalid: self.get(Counter::RemoteInvalid),
+            quarantined: self.get(Counter::Quarantined),
+            store_failed: self.get(Counter::StoreFailed),
+            transition_rejected: self.get(Counter::TransitionRejected),
+        }
+    }
+}
+
+/// Counter values at a point in time
+#[cfg(feature = "metrics")]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+    pub enqueued: u64,
+    pub dequeued: u64,
+    pub remote_applied: u64,
+    pub remote_duplicates: u64,
+    pub remote_buffered: u64,
+    pub buffer_dropped: u64,
+    pub remote_rejected: u64,  // remote events that failed authentication
+    #[serde(default)]
+    pub clock_skewed: u64,     // remote events whose origin's wall clock was off by more than the threshold
+    #[serde(default)]
+    pub throttled: u64,        // local enqueues over a rate limit, rejected or delayed
+    #[serde(default)]
+    pub quota_exceeded: u64,   // enqueues, local or remote, refused by an origin quota
+    #[serde(default)]
+    pub failed: u64,           // deliveries reported failed on this node
+    #[serde(default)]
+    pub retried: u64,          // failed items this node enqueued again
+    #[serde(default)]
+    pub dead_lettered: u64,    // failed items out of attempts
+    #[serde(default)]
+    pub remote_invalid: u64,   // remote events that failed validation, see `EventRejection`
+    #[serde(default)]
+    pub quarantined: u64,      // remote events whose operation panicked, see `QuarantinedEvent`
+    #[serde(default)]
+    pub store_failed: u64,     // log sink writes, spills and other log store operations that failed
+    #[serde(default)]
+    pub transition_rejected: u64, // state updates our entry for the event could not take
+}
+
+/// No-op counters when the `metrics` feature is disabled
+#[cfg(not(feature = "metrics"))]
+#[derive(Debug, Default)]
+pub struct Metrics;
+
+#[cfg(not(feature = "metrics"))]
+impl Metrics {
+    pub(crate) fn new() -> Self {
+        Metrics
+    }
+
+    #[inline]
+    pub(crate) fn inc(&self, _counter: Counter) {}
+}
@@ -5,3 +5,28 @@ mod clock;
 pub mod log;
 pub mod buildcore;
 mod event;
+mod applied;
+mod nodeid;
+pub mod id;
+pub mod storage;
+mod builder;
+mod error;
+pub mod metrics;
+mod snapshot;
+mod stats;
+mod audit;
+mod retention;
+mod ratelimit;
+mod size;
+mod retry;
+mod redelivery;
+mod quarantine;
+mod stalled;
+mod signal;
+mod control;
+mod lease;
+mod history;
+#[cfg(feature = "payload")]
+mod payload;
+mod trace;
+pub mod time;
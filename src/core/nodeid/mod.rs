@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::{OnceLock, RwLock};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Process-wide registry of interned node names
+#[derive(Default)]
+struct Registry {
+    names: Vec<&'static str>,
+    lookup: HashMap<&'static str, u32>,
+}
+
+/// Most names the registry holds before names read from peers and clients are refused
+const MAX_NODE_NAMES: usize = 1 << 18;
+/// Longest node name accepted from peers and clients
+const MAX_NODE_NAME_LEN: usize = 64;
+
+static REGISTRY: OnceLock<RwLock<Registry>> = OnceLock::new();
+
+fn registry() -> &'static RwLock<Registry> {
+    REGISTRY.get_or_init(|| RwLock::new(Registry::default()))
+}
+
+/// Interned node identifier
+/// A small integer index into a process-wide name registry, so events, clocks and
+/// log entries can carry node IDs without cloning strings. Serializes as the name.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    /// Intern a node name, returning the existing ID if already registered
+    /// Interned names live for the rest of the process
+    pub fn intern(name: &str) -> Self {
+        if let Some(&id) = registry().read().unwrap().lookup.get(name) {
+            return NodeId(id);
+        }
+        let mut reg = registry().write().unwrap();
+        // Another thread may have registered the name between the two locks
+        if let Some(&id) = reg.lookup.get(name) {
+            return NodeId(id);
+        }
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let id = reg.names.len() as u32;
+        reg.names.push(name);
+        reg.lookup.insert(name, id);
+        NodeId(id)
+    }
+
+    /// ID of a registered name, without registering it
+    pub fn lookup(name: &str) -> Option<Self> {
+        registry().read().unwrap().lookup.get(name).map(|&id| NodeId(id))
+    }
+
+    /// Intern a name read from a peer or client, unless it is new and longer than
+    /// `MAX_NODE_NAME_LEN` or the registry already holds `MAX_NODE_NAMES` names
+    /// Interned names are never freed, so received data must not grow the registry without bound
+    pub fn try_intern(name: &str) -> Option<Self> {
+        if let Some(id) = Self::lookup(name) {
+            return Some(id);
+        }
+        if name.len() > MAX_NODE_NAME_LEN || registry().read().unwrap().names.len() >= MAX_NODE_NAMES {
+            return None;
+        }
+        Some(Self::intern(name))
+    }
+
+    /// Human-readable node name
+    pub fn as_str(&self) -> &'static str {
+        registry().read().unwrap().names[self.0 as usize]
+    }
+}
+
+impl From<&str> for NodeId {
+    fn from(name: &str) -> Self {
+        NodeId::intern(name)
+    }
+}
+
+impl From<String> for NodeId {
+    fn from(name: String) -> Self {
+        NodeId::intern(&name)
+    }
+}
+
+impl From<&String> for NodeId {
+    fn from(name: &String) -> Self {
+        NodeId::intern(name)
+    }
+}
+
+impl PartialEq<str> for NodeId {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for NodeId {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+/// Ordered by name, so ordering is the same on every process regardless of intern order
+impl PartialOrd for NodeId {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for NodeId {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        if self.0 == other.0 {
+            return std::cmp::Ordering::Equal;
+        }
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl Display for NodeId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Debug for NodeId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl Serialize for NodeId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for NodeId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        NodeId::try_intern(&name).ok_or_else(|| D::Error::custom(format!("node name {:?} is too long or too many nodes are known", name)))
+    }
+}
@@ -0,0 +1,91 @@
+//! Dynamic payloads for queues carrying mixed message types
+//! `DistributedQueueSystem<Payload>` holds raw bytes, text and JSON side by side, for
+//! clusters whose producers don't agree on a single item type. Typed users keep
+//! their own `T`.
+//!
+//! Payloads serialize untagged, as the bare value, so a `Payload` node replicates with
+//! nodes whose `T` is `String`, `Vec<u8>` or `serde_json::Value`. Coming in, a string
+//! becomes `String`, an array of integers 0-255 becomes `Bytes` and anything else `Json`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// An item of one of several kinds
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Payload {
+    String(String),
+    Bytes(Vec<u8>),
+    Json(Value),
+}
+
+impl Payload {
+    /// Raw bytes: the bytes of `Bytes`, or the UTF-8 of `String`
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Payload::Bytes(bytes) => Some(bytes),
+            Payload::String(text) => Some(text.as_bytes()),
+            Payload::Json(_) => None,
+        }
+    }
+
+    /// Text: a `String`, or a `Json` string
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Payload::String(text) => Some(text),
+            Payload::Json(Value::String(text)) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// The JSON value of a `Json` payload
+    pub fn as_json(&self) -> Option<&Value> {
+        match self {
+            Payload::Json(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Payload size in bytes; JSON is measured serialized
+    pub fn len(&self) -> usize {
+        match self {
+            Payload::Bytes(bytes) => bytes.len(),
+            Payload::String(text) => text.len(),
+            Payload::Json(value) => value.to_string().len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl From<Vec<u8>> for Payload {
+    fn from(bytes: Vec<u8>) -> Self {
+        Payload::Bytes(bytes)
+    }
+}
+
+impl From<&[u8]> for Payload {
+    fn from(bytes: &[u8]) -> Self {
+        Payload::Bytes(bytes.to_vec())
+    }
+}
+
+impl From<String> for Payload {
+    fn from(text: String) -> Self {
+        Payload::String(text)
+    }
+}
+
+impl From<&str> for Payload {
+    fn from(text: &str) -> Self {
+        Payload::String(text.to_string())
+    }
+}
+
+impl From<Value> for Payload {
+    fn from(value: Value) -> Self {
+        Payload::Json(value)
+    }
+}
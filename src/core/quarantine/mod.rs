@@ -0,0 +1,35 @@
+//! Quarantining poison events
+//! A remote event whose operation panics while being applied is set aside rather than
+//! taking the receive loop down with it. It still counts as delivered, so the events
+//! buffered behind it go on being applied; the event itself is kept, with the panic
+//! message, for operators to inspect. It is not retried: it may have been partly applied.
+
+use std::any::Any;
+use serde::{Deserialize, Serialize};
+use crate::core::event::Event;
+
+/// Quarantined events remembered per queue; the oldest are forgotten first
+pub(crate) const MAX_QUARANTINED: usize = 256;
+
+/// A remote event set aside because applying it panicked
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QuarantinedEvent<T> {
+    pub event: Event<T>,
+    pub error: String,             // the panic message
+    pub quarantined_at_ms: u64,    // local wall-clock time, ms since the Unix epoch
+}
+
+impl<T> Clone for QuarantinedEvent<T> {
+    fn clone(&self) -> Self {
+        Self { event: self.event.clone(), error: self.error.clone(), quarantined_at_ms: self.quarantined_at_ms }
+    }
+}
+
+/// Message of a caught panic, if it was a string
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "panic without a message".to_string(),
+    }
+}
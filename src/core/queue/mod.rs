@@ -1,28 +1,193 @@
 use std::sync::{Arc, Mutex};
-use std::collections::VecDeque;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, VecDeque};
+use serde::Deserialize;
+use crate::core::event::KeySequence;
+use crate::core::nodeid::NodeId;
+
+/// Which item a dequeue takes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DequeuePolicy {
+    /// Oldest item first
+    #[default]
+    Fifo,
+    /// Take turns between origin nodes, in name order, oldest item of each
+    /// Depends only on the queue contents and the previous pick, so replicas that
+    /// apply the same dequeues pick the same items
+    RoundRobin,
+}
+
+/// Measures the bytes of an item, see `Queue::with_max_bytes`
+pub(crate) type SizeFn<T> = Box<dyn Fn(&T) -> usize + Send + Sync>;
+
+/// Ranks an item, higher first, see `Queue::with_priority`
+pub(crate) type PriorityFn<T> = Arc<dyn Fn(&T) -> u8 + Send + Sync>;
 
 /// core queue structure: handles only enqueue/dequeue logic
+/// Each item remembers the enqueue event (origin node and ID) that added it, and its
+/// key sequence if it has one; items of a key leave in sequence order
 pub struct Queue<T>{
     items: VecDeque<T>,
+    sources: VecDeque<(NodeId, u64)>,
+    sequences: VecDeque<Option<KeySequence>>,
+    per_origin: BTreeMap<NodeId, usize>,
+    capacity: Option<usize>,
+    max_bytes: Option<(usize, SizeFn<T>)>,
+    bytes: usize, // total size of the items, tracked with a byte limit only
+    enqueued: u64, // items ever added
+    dequeued: u64, // items ever handed out or taken, purges aside
+    policy: DequeuePolicy,
+    last_origin: Option<NodeId>, // origin of the last round-robin pick
+    priority: Option<PriorityFn<T>>,
+    prioritized: bool, // whether dequeues go by `priority`
 }
 
 impl <T> Queue <T> {
     /// Create a new, empty queue
     pub(crate) fn new() -> Self {
-        Self{ items:VecDeque::new() }
+        Self{
+            items: VecDeque::new(),
+            sources: VecDeque::new(),
+            sequences: VecDeque::new(),
+            per_origin: BTreeMap::new(),
+            capacity: None,
+            max_bytes: None,
+            bytes: 0,
+            enqueued: 0,
+            dequeued: 0,
+            policy: DequeuePolicy::default(),
+            last_origin: None,
+            priority: None,
+            prioritized: false,
+        }
+    }
+
+    /// Create a new, empty queue that reports full at `capacity` items
+    pub(crate) fn with_capacity(capacity: Option<usize>) -> Self {
+        Self{ capacity, ..Self::new() }
+    }
+
+    /// Also report full once the items, measured by `size`, take `max` bytes
+    pub(crate) fn with_max_bytes(mut self, max: usize, size: SizeFn<T>) -> Self {
+        self.bytes = self.items.iter().map(&size).sum();
+        self.max_bytes = Some((max, size));
+        self
+    }
+
+    /// Estimated bytes held, counting `item_size` for each item's data outside the queue
+    pub(crate) fn estimated_bytes(&self, item_size: impl Fn(&T) -> usize) -> usize {
+        self.items.capacity() * size_of::<T>()
+            + self.items.iter().map(item_size).sum::<usize>()
+            + self.sources.capacity() * size_of::<(NodeId, u64)>()
+            + self.sequences.capacity() * size_of::<Option<KeySequence>>()
+            + self.sequences.iter().flatten().map(|sequence| sequence.key.capacity()).sum::<usize>()
+            + self.per_origin.len() * size_of::<(NodeId, usize)>()
+    }
+
+    /// Enqueue an item added by event `event_id` of `origin`
+    pub(crate) fn enqueue(&mut self, item: T, origin: NodeId, event_id: u64) {
+        self.enqueue_sequenced(item, origin, event_id, None);
     }
 
-    /// Enqueue an item
-    pub(crate) fn enqueue(&mut self, item: T) {
+    /// Enqueue an item with its key sequence, if it has one
+    pub(crate) fn enqueue_sequenced(&mut self, item: T, origin: NodeId, event_id: u64, sequence: Option<KeySequence>) {
+        if let Some((_, size)) = &self.max_bytes {
+            self.bytes += size(&item);
+        }
         self.items.push_back(item);
+        self.enqueued += 1;
+        self.sources.push_back((origin, event_id));
+        self.sequences.push_back(sequence);
+        *self.per_origin.entry(origin).or_insert(0) += 1;
         // --post operation assertion
-        assert!(self.items.len() > 0, "Queue must have at least one item after enqueue");
+        assert!(!self.items.is_empty(), "Queue must have at least one item after enqueue");
+    }
+
+    /// Dequeue the items in the order `policy` picks them
+    pub(crate) fn with_policy(mut self, policy: DequeuePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Rank items by `priority`, dequeueing by it while prioritized, as from the start
+    pub(crate) fn with_priority(mut self, priority: PriorityFn<T>) -> Self {
+        self.priority = Some(priority);
+        self.prioritized = true;
+        self
+    }
+
+    /// Empty queue picking items the way this one does, prioritized or not as given
+    pub(crate) fn empty_like(&self, prioritized: bool) -> Self {
+        Self { policy: self.policy, priority: self.priority.clone(), prioritized, ..Self::new() }
     }
 
-    /// Dequeue an item
-    pub(crate) fn dequeue(&mut self) -> Option<T> {
+    /// Whether dequeues go by priority; never without a priority to go by
+    pub fn is_prioritized(&self) -> bool {
+        self.prioritized && self.priority.is_some()
+    }
+
+    /// Dequeue by priority or not from now on
+    pub(crate) fn set_prioritized(&mut self, prioritized: bool) {
+        self.prioritized = prioritized;
+    }
+
+    /// Position of the item the next dequeue takes
+    /// Prioritized, the oldest of the highest priority, whatever the policy
+    fn next_index(&self) -> Option<usize> {
+        if self.items.is_empty() {
+            return None;
+        }
+        if let Some(priority) = self.priority.as_ref().filter(|_| self.prioritized) {
+            let index = self.items.iter().enumerate().max_by_key(|&(i, item)| (priority(item), Reverse(i))).map(|(i, _)| i)?;
+            return Some(self.first_of_key(index));
+        }
+        let index = match self.policy {
+            DequeuePolicy::Fifo => Some(0),
+            DequeuePolicy::RoundRobin => {
+                let after = self.last_origin.and_then(|last| self.per_origin.range(last..).map(|(&o, _)| o).find(|&o| o != last));
+                let origin = after.or_else(|| self.per_origin.keys().next().copied())?;
+                self.sources.iter().position(|&(o, _)| o == origin)
+            }
+        }?;
+        Some(self.first_of_key(index))
+    }
+
+    /// Position of the item that goes out first among those sharing the key of the item
+    /// at `index`: lowest sequence, then origin and enqueue ID, so replicas agree
+    fn first_of_key(&self, index: usize) -> usize {
+        let Some(key) = self.sequences[index].as_ref().map(|sequence| &sequence.key) else {
+            return index;
+        };
+        self.sequences
+            .iter()
+            .zip(&self.sources)
+            .enumerate()
+            .filter_map(|(i, (sequence, source))| sequence.as_ref().filter(|s| &s.key == key).map(|s| (s.seq, *source, i)))
+            .min()
+            .map_or(index, |(.., i)| i)
+    }
+
+    /// Remove the item at `index`, which is in range
+    fn remove_at(&mut self, index: usize) -> (T, (NodeId, u64)) {
+        let source = self.sources.remove(index).unwrap();
+        self.sequences.remove(index);
+        self.forget(source.0);
+        let item = self.items.remove(index).unwrap();
+        if let Some((_, size)) = &self.max_bytes {
+            self.bytes -= size(&item);
+        }
+        (item, source)
+    }
+
+    /// Dequeue an item along with the enqueue event (origin and ID) that added it
+    pub(crate) fn dequeue_with_source(&mut self) -> Option<(T, (NodeId, u64))> {
         let len_before = self.items.len();
-        let result = self.items.pop_front();
+        let result = self.next_index().map(|index| self.remove_at(index));
+        if let Some((_, (origin, _))) = &result {
+            self.last_origin = Some(*origin);
+            self.dequeued += 1;
+        }
         // -- post op assertion: queue size decreases if dequeue succeeded
         match result {
             Some(_) => assert_eq!(self.items.len(), len_before - 1, "Queue length should decrease by 1"),
@@ -31,6 +196,60 @@ impl <T> Queue <T> {
         result
     }
 
+    /// Remove the item added by a given enqueue event, wherever it is in the queue
+    pub(crate) fn remove_event(&mut self, origin: NodeId, event_id: u64) -> Option<T> {
+        let index = self.sources.iter().position(|&source| source == (origin, event_id))?;
+        Some(self.remove_at(index).0)
+    }
+
+    /// `remove_event` for a dequeue, counted as one
+    pub(crate) fn take_event(&mut self, origin: NodeId, event_id: u64) -> Option<T> {
+        let item = self.remove_event(origin, event_id)?;
+        self.dequeued += 1;
+        Some(item)
+    }
+
+    /// Remove the first item in arrival order matching `pred`, or only the item the next
+    /// dequeue takes if `head_only`, along with the enqueue event that added it
+    /// An item waiting behind an earlier one of its key is skipped
+    /// Round-robin turns are left as they were, as on replicas removing it by its event
+    pub(crate) fn take_where(&mut self, mut pred: impl FnMut(&T) -> bool, head_only: bool) -> Option<(T, (NodeId, u64))> {
+        let index = match head_only {
+            true => self.next_index().filter(|&index| pred(&self.items[index]))?,
+            false => (0..self.items.len()).find(|&index| self.first_of_key(index) == index && pred(&self.items[index]))?,
+        };
+        self.dequeued += 1;
+        Some(self.remove_at(index))
+    }
+
+    /// Remove every item matching `pred`, returning the enqueue events that added them
+    pub(crate) fn remove_where(&mut self, mut pred: impl FnMut(&T) -> bool) -> Vec<(NodeId, u64)> {
+        let mut removed = Vec::new();
+        let mut index = 0;
+        while index < self.items.len() {
+            if pred(&self.items[index]) {
+                removed.push(self.remove_at(index).1);
+            } else {
+                index += 1;
+            }
+        }
+        removed
+    }
+
+    fn forget(&mut self, origin: NodeId) {
+        if let Some(count) = self.per_origin.get_mut(&origin) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_origin.remove(&origin);
+            }
+        }
+    }
+
+    /// Number of queued items enqueued by `origin`
+    pub fn count_from(&self, origin: NodeId) -> usize {
+        self.per_origin.get(&origin).copied().unwrap_or(0)
+    }
+
     /// Get the current queue length
     pub fn len(&self) -> usize {
         self.items.len()
@@ -41,6 +260,83 @@ impl <T> Queue <T> {
         self.items.is_empty()
     }
 
+    /// Item the next dequeue takes
+    pub fn peek(&self) -> Option<&T> {
+        self.items.get(self.next_index()?)
+    }
+
+    /// Dequeue order
+    pub fn policy(&self) -> DequeuePolicy {
+        self.policy
+    }
+
+    /// Iterate items from head to tail, in arrival order
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.items.iter()
+    }
+
+    /// Enqueue events (origin and ID) of the items, in arrival order
+    pub fn sources(&self) -> impl Iterator<Item = &(NodeId, u64)> {
+        self.sources.iter()
+    }
+
+    /// Drop every item, keeping capacity, byte limit, policy and totals
+    pub(crate) fn clear(&mut self) {
+        let max_bytes = self.max_bytes.take();
+        *self = Self { max_bytes, enqueued: self.enqueued, dequeued: self.dequeued, ..Self::with_capacity(self.capacity).with_policy(self.policy) };
+    }
+
+    /// Items ever added, replicated ones and retries included
+    pub fn enqueued(&self) -> u64 {
+        self.enqueued
+    }
+
+    /// Items ever dequeued, here or on replicas
+    pub fn dequeued(&self) -> u64 {
+        self.dequeued
+    }
+
+    /// Configured capacity, if any
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+
+    /// Change the capacity; items beyond a lowered one stay until dequeued
+    pub(crate) fn set_capacity(&mut self, capacity: Option<usize>) {
+        self.capacity = capacity;
+    }
+
+    /// Configured byte limit, if any
+    pub fn max_bytes(&self) -> Option<usize> {
+        self.max_bytes.as_ref().map(|(max, _)| *max)
+    }
+
+    /// Total size of the items; 0 without a byte limit, which is what measures them
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Check if the queue has reached its capacity, in items or bytes
+    pub fn is_full(&self) -> bool {
+        self.capacity.is_some_and(|c| self.items.len() >= c) || self.max_bytes().is_some_and(|max| self.bytes >= max)
+    }
+
+    /// Whether `item` fits within the byte limit
+    pub fn fits(&self, item: &T) -> bool {
+        self.max_bytes.as_ref().is_none_or(|(max, size)| self.bytes + size(item) <= *max)
+    }
+
+    /// How full the queue is, in items or bytes, whichever is fuller; 1.0 at the limit
+    /// None without either limit
+    pub fn fill(&self) -> Option<f64> {
+        let items = self.capacity.map(|capacity| self.items.len() as f64 / capacity.max(1) as f64);
+        let bytes = self.max_bytes().map(|max| self.bytes as f64 / max.max(1) as f64);
+        match (items, bytes) {
+            (Some(items), Some(bytes)) => Some(items.max(bytes)),
+            (items, bytes) => items.or(bytes),
+        }
+    }
+
 }
 
 /// Thread-safe wrapper around the queue
@@ -0,0 +1,93 @@
+//! Token-bucket rate limiting of local enqueues
+//! A queue can limit all of its local enqueues and, separately, the enqueues of
+//! each client identity. Replicated events are never limited.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use crate::core::time::{Duration, Instant};
+use serde::Deserialize;
+use crate::core::error::QueueError;
+
+/// Sustained rate and burst size of a token bucket
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub struct RateLimit {
+    /// Tokens added per second
+    pub rate: f64,
+    /// Bucket size, the most enqueues allowed back to back
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub fn per_second(rate: f64, burst: u32) -> Self {
+        Self { rate, burst }
+    }
+}
+
+/// What happens to an enqueue over the limit
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Throttle {
+    /// Fail with `QueueError::RateLimited`
+    #[default]
+    Reject,
+    /// Wait for a token
+    Block,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+/// Token buckets sharing one limit, one per key
+pub struct RateLimiter {
+    limit: RateLimit,
+    throttle: Throttle,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit, throttle: Throttle) -> Self {
+        Self { limit, throttle, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    pub fn throttle(&self) -> Throttle {
+        self.throttle
+    }
+
+    /// Take a token from `key`'s bucket, or how long until one is available
+    pub fn try_acquire(&self, key: &str) -> Result<(), Duration> {
+        let burst = self.limit.burst.max(1) as f64;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket { tokens: burst, refilled: now });
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.limit.rate).min(burst);
+        bucket.refilled = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        if self.limit.rate <= 0.0 {
+            return Err(Duration::MAX);
+        }
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.limit.rate))
+    }
+
+    /// Take a token, waiting for it or failing depending on the throttle
+    /// Errors are returned only when the attempt was throttled
+    /// On wasm32 there is no blocking, so `Throttle::Block` rejects like `Throttle::Reject`
+    pub fn acquire(&self, key: &str) -> Result<(), QueueError> {
+        let block = self.throttle == Throttle::Block && cfg!(not(target_arch = "wasm32"));
+        loop {
+            match self.try_acquire(key) {
+                Ok(()) => return Ok(()),
+                Err(wait) if block && wait != Duration::MAX => std::thread::sleep(wait),
+                Err(retry_after) => return Err(QueueError::RateLimited { retry_after }),
+            }
+        }
+    }
+}
@@ -0,0 +1,28 @@
+//! Redelivering remote events that couldn't be applied
+//! A remote event that fails for a transient reason (the causal buffer is full) waits out
+//! a backoff and is applied again by `DistributedQueueSystem::process_redeliveries`. One that
+//! is invalid, or still failing once the policy's attempts are used up, is kept as a
+//! `FailedEvent` for operators to inspect instead of being dropped without a trace.
+
+use serde::{Deserialize, Serialize};
+use crate::core::nodeid::NodeId;
+use crate::core::retry::RetryPolicy;
+use crate::core::time::Duration;
+
+/// Failed events remembered per queue; the oldest are forgotten first
+pub(crate) const MAX_FAILED_EVENTS: usize = 1024;
+
+/// Redelivery used unless the builder sets one: 5 attempts, 100 ms doubling up to 10 s
+pub(crate) fn default_policy() -> RetryPolicy {
+    RetryPolicy::new(5).backoff(Duration::from_millis(100), 2.0, Duration::from_secs(10))
+}
+
+/// A remote event given up on
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailedEvent {
+    pub origin: NodeId,
+    pub event_id: u64,
+    pub attempts: u32,      // times application was tried
+    pub error: String,      // why the last attempt failed
+    pub failed_at_ms: u64,  // local wall-clock time it was given up on, ms since the Unix epoch
+}
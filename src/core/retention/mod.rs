@@ -0,0 +1,67 @@
+//! Log retention: which old entries may be removed from the log
+//! Entries are only ever removed from the front of the log, and only once
+//! every known node has seen them (see `DistributedQueueSystem::stability_watermark`),
+//! so a lagging peer can still catch up from what is left.
+
+use std::collections::HashMap;
+use std::time::Duration;
+use crate::core::nodeid::NodeId;
+use crate::core::storage::LogSink;
+
+/// Limits on how much log history a queue keeps
+pub struct RetentionPolicy<T> {
+    pub(crate) max_age: Option<Duration>,
+    pub(crate) max_entries: Option<usize>,
+    pub(crate) archive: Option<Box<dyn LogSink<T>>>,
+    pub(crate) once_stable: bool,
+}
+
+/// What a garbage collection pass removed, see `DistributedQueueSystem::collect_garbage`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GcReport {
+    /// Records of applied remote events, now recognized by clock instead
+    pub applied: usize,
+    pub log_entries: usize,
+}
+
+impl<T> RetentionPolicy<T> {
+    /// Policy that keeps everything until a limit is set
+    pub fn new() -> Self {
+        Self { max_age: None, max_entries: None, archive: None, once_stable: false }
+    }
+
+    /// Remove entries logged longer than `age` ago
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    /// Keep at most `max` entries, in memory and in storage combined
+    pub fn max_entries(mut self, max: usize) -> Self {
+        self.max_entries = Some(max);
+        self
+    }
+
+    /// Remove entries as soon as every node has seen them, whatever their age or number
+    pub fn once_stable(mut self) -> Self {
+        self.once_stable = true;
+        self
+    }
+
+    /// Hand removed entries to `sink` instead of just dropping them
+    pub fn archive_to(mut self, sink: Box<dyn LogSink<T>>) -> Self {
+        self.archive = Some(sink);
+        self
+    }
+}
+
+impl<T> Default for RetentionPolicy<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Check if every node in `clock` is at or below the watermark
+pub(crate) fn is_stable(clock: &HashMap<NodeId, u64>, watermark: &HashMap<NodeId, u64>) -> bool {
+    clock.iter().all(|(node, &time)| time <= watermark.get(node).copied().unwrap_or(0))
+}
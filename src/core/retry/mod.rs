@@ -0,0 +1,46 @@
+//! Retrying items whose processing failed
+//! A consumer reports a failed delivery with `DistributedQueueSystem::fail`. The
+//! failure is logged as `Failed` and replicated; the item is then enqueued again
+//! after a backoff, or dead-lettered once it has used up its attempts.
+
+use std::time::Duration;
+
+/// How failed deliveries are retried
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub(crate) max_attempts: u32,
+    pub(crate) initial_backoff: Duration,
+    pub(crate) multiplier: f64,
+    pub(crate) max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Dead-letter an item after `max_attempts` failed deliveries
+    /// Backs off 1 s after the first failure, doubling up to a minute
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            initial_backoff: Duration::from_secs(1),
+            multiplier: 2.0,
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+
+    /// Wait `initial` after the first failure, growing by `multiplier` per failure up to `max`
+    pub fn backoff(mut self, initial: Duration, multiplier: f64, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.multiplier = multiplier.max(1.0);
+        self.max_backoff = max;
+        self
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Delay before retrying an item that has failed `failures` times
+    pub fn delay(&self, failures: u32) -> Duration {
+        let factor = self.multiplier.powi(failures.saturating_sub(1).min(64) as i32);
+        self.initial_backoff.mul_f64(factor).min(self.max_backoff)
+    }
+}
@@ -0,0 +1,56 @@
+//! Waking consumers when items arrive
+//! Queues notify an `ArrivalSignal` whenever an item lands, so a consumer can sleep until
+//! then instead of polling, blocked on a thread or as a registered async task; queues
+//! built with the same signal share it.
+
+use std::sync::{Condvar, Mutex};
+use std::task::Waker;
+use crate::core::time::Instant;
+
+/// Counts item arrivals and wakes threads waiting for the next one
+#[derive(Default)]
+pub struct ArrivalSignal {
+    arrivals: Mutex<u64>,
+    arrived: Condvar,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl ArrivalSignal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Arrivals so far
+    pub fn count(&self) -> u64 {
+        *self.arrivals.lock().unwrap()
+    }
+
+    /// Record an arrival and wake every waiting thread and registered task
+    pub fn notify(&self) {
+        *self.arrivals.lock().unwrap() += 1;
+        self.arrived.notify_all();
+        for waker in std::mem::take(&mut *self.wakers.lock().unwrap()) {
+            waker.wake();
+        }
+    }
+
+    /// Wake the task of `waker` on the next arrival
+    pub fn register(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock().unwrap();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+
+    /// Wait until the count passes `seen`, or `deadline`; returns false if the deadline came first
+    pub fn wait_past(&self, seen: u64, deadline: Instant) -> bool {
+        let mut arrivals = self.arrivals.lock().unwrap();
+        while *arrivals <= seen {
+            let Some(left) = deadline.checked_duration_since(Instant::now()).filter(|left| !left.is_zero()) else {
+                return false;
+            };
+            arrivals = self.arrived.wait_timeout(arrivals, left).unwrap().0;
+        }
+        true
+    }
+}
@@ -0,0 +1,67 @@
+//! Item sizes in bytes, for queues limited by bytes as well as items
+//! See `DistributedQueueSystemBuilder::max_bytes`. Types without a `MessageSize` can be
+//! measured serialized, with `max_bytes_by(max, serialized_size)`, which like the
+//! `serde_json::Value` impl needs JSON support (the `net`, `persist` or `payload` feature).
+
+#[cfg(any(feature = "net", feature = "persist", feature = "payload"))]
+use std::io;
+#[cfg(any(feature = "net", feature = "persist", feature = "payload"))]
+use serde::Serialize;
+
+/// Size of a message's payload in bytes
+pub trait MessageSize {
+    fn message_size(&self) -> usize;
+}
+
+impl MessageSize for String {
+    fn message_size(&self) -> usize {
+        self.len()
+    }
+}
+
+impl MessageSize for Vec<u8> {
+    fn message_size(&self) -> usize {
+        self.len()
+    }
+}
+
+/// Measured serialized, as it goes over the wire
+#[cfg(any(feature = "net", feature = "persist", feature = "payload"))]
+impl MessageSize for serde_json::Value {
+    fn message_size(&self) -> usize {
+        serialized_size(self)
+    }
+}
+
+#[cfg(feature = "payload")]
+impl MessageSize for crate::core::payload::Payload {
+    fn message_size(&self) -> usize {
+        self.len()
+    }
+}
+
+/// Length of `item` encoded as JSON, 0 if it can't be
+#[cfg(any(feature = "net", feature = "persist", feature = "payload"))]
+pub fn serialized_size<T: Serialize>(item: &T) -> usize {
+    let mut counter = ByteCounter(0);
+    match serde_json::to_writer(&mut counter, item) {
+        Ok(()) => counter.0,
+        Err(_) => 0,
+    }
+}
+
+/// Writer that only counts the bytes written to it
+#[cfg(any(feature = "net", feature = "persist", feature = "payload"))]
+struct ByteCounter(usize);
+
+#[cfg(any(feature = "net", feature = "persist", feature = "payload"))]
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use crate::core::nodeid::NodeId;
+
+/// Point-in-time view of a node's queue state
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot<T> {
+    pub node_id: NodeId,
+    pub clock: HashMap<NodeId, u64>,
+    pub items: Vec<Arc<T>>,       // queue contents, head first
+    /// Enqueue event (origin and ID) of each item, empty in snapshots saved before they were kept
+    #[serde(default)]
+    pub sources: Vec<(NodeId, u64)>,
+    pub pending_events: usize,    // events waiting in the causal buffer
+}
+
+// Not derived, which would need T: Clone; items are shared
+impl<T> Clone for Snapshot<T> {
+    fn clone(&self) -> Self {
+        Self {
+            node_id: self.node_id,
+            clock: self.clock.clone(),
+            items: self.items.clone(),
+            sources: self.sources.clone(),
+            pending_events: self.pending_events,
+        }
+    }
+}
+
+/// Changes to a queue from one snapshot to a later one, see `Snapshot::delta_to`
+/// Items only ever join a queue at the tail, so the later contents are the earlier ones
+/// less those removed, followed by those added.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SnapshotDelta<T> {
+    pub node_id: NodeId,
+    /// Clock of the earlier snapshot, which the delta applies to
+    pub base_clock: HashMap<NodeId, u64>,
+    pub clock: HashMap<NodeId, u64>,
+    /// Positions in the earlier snapshot of the items gone since, ascending
+    pub removed: Vec<usize>,
+    pub added: Vec<Arc<T>>,       // items queued since, head first
+    pub added_sources: Vec<(NodeId, u64)>,
+    pub pending_events: usize,
+}
+
+impl<T> Snapshot<T> {
+    /// Enqueue event of the item at `index`, attributed to the snapshot's node if unknown
+    fn source(&self, index: usize) -> (NodeId, u64) {
+        self.sources.get(index).copied().unwrap_or((self.node_id, 0))
+    }
+
+    /// What changed from this snapshot to `next`, a later one of the same queue
+    pub fn delta_to(&self, next: &Snapshot<T>) -> SnapshotDelta<T> {
+        // Items of unknown source are told apart by identity, as they would match any such item
+        let same = |i: usize, j: usize| {
+            let source = self.source(i);
+            source == next.source(j) && (source.1 != 0 || Arc::ptr_eq(&self.items[i], &next.items[j]))
+        };
+        let mut removed = Vec::new();
+        let mut kept = 0;
+        for i in 0..self.items.len() {
+            if kept < next.items.len() && same(i, kept) {
+                kept += 1;
+            } else {
+                removed.push(i);
+            }
+        }
+        SnapshotDelta {
+            node_id: next.node_id,
+            base_clock: self.clock.clone(),
+            clock: next.clock.clone(),
+            removed,
+            added: next.items[kept..].to_vec(),
+            added_sources: (kept..next.items.len()).map(|j| next.source(j)).collect(),
+            pending_events: next.pending_events,
+        }
+    }
+
+    /// Whether `delta` was taken from this snapshot
+    pub fn precedes(&self, delta: &SnapshotDelta<T>) -> bool {
+        self.clock == delta.base_clock && delta.removed.last().is_none_or(|&last| last < self.items.len())
+    }
+
+    /// Bring this snapshot forward by `delta`, which must have been taken from it (see `precedes`)
+    pub fn apply(&mut self, delta: SnapshotDelta<T>) {
+        debug_assert!(self.precedes(&delta), "delta taken from another snapshot");
+        self.sources = (0..self.items.len()).map(|i| self.source(i)).collect();
+        remove_positions(&mut self.items, &delta.removed);
+        remove_positions(&mut self.sources, &delta.removed);
+        self.items.extend(delta.added);
+        self.sources.extend(delta.added_sources);
+        self.node_id = delta.node_id;
+        self.clock = delta.clock;
+        self.pending_events = delta.pending_events;
+    }
+}
+
+/// Remove the elements at `positions`, which are ascending
+fn remove_positions<X>(elements: &mut Vec<X>, positions: &[usize]) {
+    let mut positions = positions.iter().peekable();
+    let mut index = 0;
+    elements.retain(|_| {
+        let removed = positions.next_if_eq(&&index).is_some();
+        index += 1;
+        !removed
+    });
+}
@@ -0,0 +1,23 @@
+//! Why buffered remote events are waiting
+//! A remote event stays in the causal buffer until every event it depends on has been
+//! delivered; `DistributedQueueSystem::buffered_events` lists the ones still missing.
+
+use serde::{Deserialize, Serialize};
+use crate::core::nodeid::NodeId;
+
+/// Events `from..=to` of `node`, by its clock entry, that have to be delivered first
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MissingEvents {
+    pub node: NodeId,
+    pub from: u64,
+    pub to: u64,
+}
+
+/// A remote event waiting in the causal buffer
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BufferedEvent {
+    pub origin: NodeId,
+    pub event_id: u64,
+    pub sequence: u64,              // the origin's entry in the event's clock
+    pub missing: Vec<MissingEvents>, // empty if it is deliverable and just not yet taken out
+}
@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use crate::core::nodeid::NodeId;
+#[cfg(feature = "metrics")]
+use crate::core::metrics::MetricsSnapshot;
+
+/// Current state of a queue, as reported by stats endpoints
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QueueStats {
+    pub node_id: NodeId,
+    pub depth: usize,               // items currently in the queue
+    #[serde(default)]
+    pub bytes: usize,               // size of the queued items, only measured with a byte limit
+    pub pending_events: usize,      // remote events waiting in the causal buffer
+    pub log_entries: usize,         // log length, spilled entries included
+    pub clock: HashMap<NodeId, u64>,
+    #[serde(default)]
+    pub stable: HashMap<NodeId, u64>, // stable frontier, see `DistributedQueueSystem::stability_watermark`
+    #[serde(default)]
+    pub clock_skew_ms: HashMap<NodeId, i64>, // latest wall-clock offset per peer, positive if the peer is ahead
+    #[serde(default)]
+    pub pending_redeliveries: usize, // remote events waiting to be applied again
+    #[serde(default)]
+    pub failed_events: usize,        // remote events given up on, see `DistributedQueueSystem::failed_events`
+    #[serde(default)]
+    pub quarantined: usize,          // remote events set aside after panicking, see `DistributedQueueSystem::quarantined`
+    #[serde(default)]
+    pub paused: bool,                // see `ControlOp::Paused`
+    #[cfg(feature = "metrics")]
+    pub metrics: MetricsSnapshot,
+}
+
+/// Estimated bytes held in memory by a queue's parts, see `DistributedQueueSystem::memory_stats`
+/// Payloads are shared between the parts, and counted in each part holding them
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryStats {
+    pub queue: usize,          // queued items and their sources
+    pub log: usize,            // log entries held in memory and their indexes; spilled ones don't count
+    pub event_buffer: usize,   // remote events waiting in the causal buffer
+    pub applied_events: usize, // IDs of applied events, kept to drop duplicates
+}
+
+impl MemoryStats {
+    pub fn total(&self) -> usize {
+        self.queue + self.log + self.event_buffer + self.applied_events
+    }
+}
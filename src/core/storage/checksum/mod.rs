@@ -0,0 +1,143 @@
+//! Per-entry checksums for NDJSON log files
+//! Each line ends with a `crc32` field covering the rest of the line, so the
+//! file stays plain NDJSON while truncated or damaged entries can be detected.
+//! Lines written before checksums were added are still accepted.
+
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+/// `,"crc32":"` + 8 hex digits + `"}`
+const SUFFIX_LEN: usize = 20;
+const FIELD: &str = r#","crc32":""#;
+
+/// Append the checksum field to a serialized JSON object
+pub(crate) fn seal_line(mut json: Vec<u8>) -> Vec<u8> {
+    let crc = crc32fast::hash(&json);
+    json.pop(); // closing brace
+    json.extend_from_slice(format!(r#"{}{:08x}"}}"#, FIELD, crc).as_bytes());
+    json
+}
+
+/// Strip and check a line's checksum; `Ok(None)` if the line has none
+pub(crate) fn check_line(line: &str) -> Result<Option<String>, CorruptionKind> {
+    let Some(split) = line.len().checked_sub(SUFFIX_LEN) else {
+        return Ok(None);
+    };
+    let Some((field, tail)) = line.get(split..).and_then(|s| s.split_at_checked(FIELD.len())) else {
+        return Ok(None);
+    };
+    if field != FIELD || !tail.ends_with("\"}") {
+        return Ok(None);
+    }
+    let expected = u32::from_str_radix(&tail[..8], 16).map_err(|_| CorruptionKind::Malformed)?;
+    let body = format!("{}}}", &line[..split]);
+    if crc32fast::hash(body.as_bytes()) != expected {
+        return Err(CorruptionKind::ChecksumMismatch);
+    }
+    Ok(Some(body))
+}
+
+/// Parse an NDJSON line, checking its checksum if it has one
+pub(crate) fn parse_line<T: serde::de::DeserializeOwned>(line: &str) -> io::Result<T> {
+    let body = check_line(line).map_err(|kind| io::Error::new(io::ErrorKind::InvalidData, kind.to_string()))?;
+    serde_json::from_str(body.as_deref().unwrap_or(line)).map_err(io::Error::other)
+}
+
+/// What is wrong with a damaged entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorruptionKind {
+    /// Last line ends without a newline, as after a crash mid-write
+    Truncated,
+    ChecksumMismatch,
+    /// Not valid JSON
+    Malformed,
+}
+
+impl Display for CorruptionKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CorruptionKind::Truncated => write!(f, "entry is truncated"),
+            CorruptionKind::ChecksumMismatch => write!(f, "checksum does not match"),
+            CorruptionKind::Malformed => write!(f, "entry is not valid JSON"),
+        }
+    }
+}
+
+/// First damaged entry in a log file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Corruption {
+    pub line: usize,   // 1-based
+    pub offset: u64,   // byte offset where the entry starts
+    pub kind: CorruptionKind,
+}
+
+/// Result of scanning a log file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogReport {
+    /// Intact entries before the first damaged one
+    pub valid_entries: usize,
+    /// Intact entries without a checksum (written by older versions)
+    pub unchecked_entries: usize,
+    /// Last known-good position: truncating the file here drops only damaged data
+    pub valid_bytes: u64,
+    pub corruption: Option<Corruption>,
+}
+
+impl LogReport {
+    /// Check if every entry is intact
+    pub fn is_clean(&self) -> bool {
+        self.corruption.is_none()
+    }
+}
+
+/// Scan an NDJSON log file, stopping at the first truncated or corrupted entry
+pub fn verify_log(path: impl AsRef<Path>) -> io::Result<LogReport> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut report = LogReport { valid_entries: 0, unchecked_entries: 0, valid_bytes: 0, corruption: None };
+    let mut line = String::new();
+    let mut number = 0;
+    loop {
+        line.clear();
+        let n = match reader.read_line(&mut line) {
+            Ok(0) => break,
+            Ok(n) => n,
+            // Torn multi-byte character
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                report.corruption = Some(Corruption { line: number + 1, offset: report.valid_bytes, kind: CorruptionKind::Malformed });
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        number += 1;
+        let text = line.trim_end_matches(['\n', '\r']);
+        let verdict = if !line.ends_with('\n') {
+            Err(CorruptionKind::Truncated)
+        } else if text.trim().is_empty() {
+            Ok(None)
+        } else {
+            check_line(text).and_then(|body| match body {
+                Some(_) => Ok(Some(true)),
+                None => match serde_json::from_str::<serde_json::Value>(text) {
+                    Ok(_) => Ok(Some(false)),
+                    Err(_) => Err(CorruptionKind::Malformed),
+                },
+            })
+        };
+        match verdict {
+            Ok(checked) => {
+                if let Some(checked) = checked {
+                    report.valid_entries += 1;
+                    report.unchecked_entries += usize::from(!checked);
+                }
+                report.valid_bytes += n as u64;
+            }
+            Err(kind) => {
+                report.corruption = Some(Corruption { line: number, offset: report.valid_bytes, kind });
+                break;
+            }
+        }
+    }
+    Ok(report)
+}
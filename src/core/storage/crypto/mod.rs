@@ -0,0 +1,152 @@
+//! Payload encryption for data written to disk
+//! Only items are encrypted; IDs, clocks and states stay readable so files can
+//! still be indexed and inspected. Each sealed item records the ID of the key
+//! that sealed it, so after a rotation older files remain readable.
+
+use std::collections::BTreeMap;
+use std::io;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use ring::aead::{Aad, CHACHA20_POLY1305, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::hkdf;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Key length in bytes
+pub const KEY_LEN: usize = 32;
+
+/// JSON field marking an encrypted item
+const SEALED_FIELD: &str = "$sealed";
+
+/// An encrypted payload
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sealed {
+    pub key: u32,          // ID of the key that sealed it
+    pub nonce: String,     // base64
+    pub data: String,      // base64 ciphertext and tag
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// ChaCha20-Poly1305 keys by ID; the active key seals, every key opens
+#[derive(Clone)]
+pub struct PayloadCipher {
+    keys: BTreeMap<u32, [u8; KEY_LEN]>,
+    active: u32,
+}
+
+impl PayloadCipher {
+    /// Cipher sealing with `key`
+    pub fn new(key_id: u32, key: [u8; KEY_LEN]) -> Self {
+        Self { keys: BTreeMap::from([(key_id, key)]), active: key_id }
+    }
+
+    /// Fresh random key
+    pub fn generate_key() -> [u8; KEY_LEN] {
+        let mut key = [0u8; KEY_LEN];
+        SystemRandom::new().fill(&mut key).expect("system RNG unavailable");
+        key
+    }
+
+    /// Parse a hex-encoded key
+    pub fn parse_key(hex: &str) -> io::Result<[u8; KEY_LEN]> {
+        let hex = hex.trim();
+        if hex.len() != KEY_LEN * 2 {
+            return Err(invalid(format!("key must be {} hex characters", KEY_LEN * 2)));
+        }
+        let mut key = [0u8; KEY_LEN];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid("key is not hex"))?;
+        }
+        Ok(key)
+    }
+
+    /// Keep `key` for opening data sealed before a rotation
+    pub fn with_key(mut self, key_id: u32, key: [u8; KEY_LEN]) -> Self {
+        self.keys.insert(key_id, key);
+        self
+    }
+
+    /// Seal new data with `key` from now on; older keys still open existing data
+    pub fn rotate(&mut self, key_id: u32, key: [u8; KEY_LEN]) {
+        self.keys.insert(key_id, key);
+        self.active = key_id;
+    }
+
+    /// ID of the key that seals new data
+    pub fn active_key_id(&self) -> u32 {
+        self.active
+    }
+
+    /// Cipher with keys derived for one queue, so each queue's files use their own keys
+    pub fn for_queue(&self, queue: &str) -> Self {
+        let keys = self
+            .keys
+            .iter()
+            .map(|(&id, key)| {
+                let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, b"dqm-queue-key").extract(key);
+                let mut derived = [0u8; KEY_LEN];
+                prk.expand(&[queue.as_bytes()], hkdf::HKDF_SHA256)
+                    .and_then(|okm| okm.fill(&mut derived))
+                    .expect("HKDF output length is valid");
+                (id, derived)
+            })
+            .collect();
+        Self { keys, active: self.active }
+    }
+
+    fn key(&self, key_id: u32) -> io::Result<LessSafeKey> {
+        let bytes = self.keys.get(&key_id).ok_or_else(|| invalid(format!("unknown key {}", key_id)))?;
+        Ok(LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, bytes).expect("key length is valid")))
+    }
+
+    /// Encrypt with the active key
+    pub fn seal(&self, plaintext: &[u8]) -> io::Result<Sealed> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new().fill(&mut nonce).map_err(|_| io::Error::other("system RNG unavailable"))?;
+        let mut data = plaintext.to_vec();
+        self.key(self.active)?
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(self.active.to_be_bytes()), &mut data)
+            .map_err(|_| io::Error::other("encryption failed"))?;
+        Ok(Sealed { key: self.active, nonce: BASE64.encode(nonce), data: BASE64.encode(data) })
+    }
+
+    /// Decrypt with whichever key sealed it
+    pub fn open(&self, sealed: &Sealed) -> io::Result<Vec<u8>> {
+        let nonce: [u8; NONCE_LEN] = BASE64
+            .decode(&sealed.nonce)
+            .ok()
+            .and_then(|n| n.try_into().ok())
+            .ok_or_else(|| invalid("bad nonce"))?;
+        let mut data = BASE64.decode(&sealed.data).map_err(|_| invalid("bad ciphertext encoding"))?;
+        let plaintext = self
+            .key(sealed.key)?
+            .open_in_place(Nonce::assume_unique_for_key(nonce), Aad::from(sealed.key.to_be_bytes()), &mut data)
+            .map_err(|_| invalid("decryption failed: wrong key or tampered data"))?;
+        Ok(plaintext.to_vec())
+    }
+
+    /// Replace a JSON value with its sealed form; null (no item) is left alone
+    pub(crate) fn seal_value(&self, slot: &mut Value) -> io::Result<()> {
+        if slot.is_null() {
+            return Ok(());
+        }
+        let plaintext = serde_json::to_vec(slot).map_err(io::Error::other)?;
+        let sealed = serde_json::to_value(self.seal(&plaintext)?).map_err(io::Error::other)?;
+        *slot = Value::Object([(SEALED_FIELD.to_string(), sealed)].into_iter().collect());
+        Ok(())
+    }
+
+    /// Undo `seal_value`; values that aren't sealed are left alone
+    pub(crate) fn open_value(&self, slot: &mut Value) -> io::Result<()> {
+        let Some(sealed) = slot.get(SEALED_FIELD) else {
+            return Ok(());
+        };
+        let sealed: Sealed = serde_json::from_value(sealed.clone()).map_err(io::Error::other)?;
+        *slot = serde_json::from_slice(&self.open(&sealed)?).map_err(io::Error::other)?;
+        Ok(())
+    }
+}
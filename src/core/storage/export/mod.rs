@@ -0,0 +1,96 @@
+//! Log exports for analysis tools: CSV, and Parquet with the `parquet` feature
+//! Both flatten an entry into one row: its fields, the item as JSON, and one
+//! `clock.<node>` column per node seen in any of the exported clocks (0 where absent).
+//! Pass `log_page(0, usize::MAX)` to export everything, spilled entries included.
+
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use serde::Serialize;
+use crate::core::log::LogEntry;
+use crate::core::nodeid::NodeId;
+
+/// Columns before the clock columns
+const COLUMNS: [&str; 9] = ["local_log_id", "local_node", "op", "state", "event_global_id", "origin_node", "timestamp_ms", "logged_at_ms", "item"];
+
+/// Nodes with a clock column, sorted by name
+fn clock_nodes<T>(entries: &[LogEntry<T>]) -> Vec<NodeId> {
+    let nodes: BTreeSet<&str> = entries.iter().flat_map(|e| e.clock().keys().map(|n| n.as_str())).collect();
+    nodes.into_iter().map(NodeId::from).collect()
+}
+
+fn item_json<T: Serialize>(entry: &LogEntry<T>) -> io::Result<Option<String>> {
+    entry.item().map(|item| serde_json::to_string(item.as_ref())).transpose().map_err(io::Error::other)
+}
+
+/// Quote a CSV field if it needs it (RFC 4180)
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Write entries as CSV with a header row; entries without an item leave `item` empty
+pub fn export_csv<T: Serialize>(entries: &[LogEntry<T>], mut writer: impl Write) -> io::Result<()> {
+    let nodes = clock_nodes(entries);
+    let header: Vec<String> = COLUMNS.iter().map(|c| c.to_string()).chain(nodes.iter().map(|n| csv_field(&format!("clock.{}", n)))).collect();
+    writeln!(writer, "{}", header.join(","))?;
+    for entry in entries {
+        let mut row = vec![
+            entry.local_log_id.to_string(),
+            csv_field(entry.local_node.as_str()),
+            csv_field(&entry.op),
+            format!("{:?}", entry.state),
+            entry.event_global_id().to_string(),
+            csv_field(entry.event.origin_node.as_str()),
+            entry.event.timestamp_ms.to_string(),
+            entry.logged_at_ms.to_string(),
+            item_json(entry)?.map(|json| csv_field(&json)).unwrap_or_default(),
+        ];
+        row.extend(nodes.iter().map(|n| entry.clock().get(n).copied().unwrap_or(0).to_string()));
+        writeln!(writer, "{}", row.join(","))?;
+    }
+    writer.flush()
+}
+
+/// Write entries as a Parquet file with the same columns as `export_csv`
+/// Numbers are UInt64 columns, `item` is a nullable string
+#[cfg(feature = "parquet")]
+pub fn export_parquet<T: Serialize>(entries: &[LogEntry<T>], writer: impl Write + Send) -> io::Result<()> {
+    use std::sync::Arc;
+    use arrow_array::{ArrayRef, RecordBatch, StringArray, UInt64Array};
+    use arrow_schema::{Field, Schema};
+    use parquet::arrow::ArrowWriter;
+
+    let nodes = clock_nodes(entries);
+    let numbers = |f: &dyn Fn(&LogEntry<T>) -> u64| -> ArrayRef { Arc::new(UInt64Array::from_iter_values(entries.iter().map(f))) };
+    let strings = |f: &dyn Fn(&LogEntry<T>) -> String| -> ArrayRef { Arc::new(StringArray::from_iter_values(entries.iter().map(f))) };
+    let items: Vec<Option<String>> = entries.iter().map(item_json).collect::<io::Result<_>>()?;
+
+    let mut columns = vec![
+        numbers(&|e| e.local_log_id),
+        strings(&|e| e.local_node.to_string()),
+        strings(&|e| e.op.clone()),
+        strings(&|e| format!("{:?}", e.state)),
+        numbers(&|e| e.event_global_id()),
+        strings(&|e| e.event.origin_node.to_string()),
+        numbers(&|e| e.event.timestamp_ms),
+        numbers(&|e| e.logged_at_ms),
+        Arc::new(StringArray::from(items)) as ArrayRef,
+    ];
+    columns.extend(nodes.iter().map(|n| numbers(&|e| e.clock().get(n).copied().unwrap_or(0))));
+    let fields: Vec<Field> = COLUMNS
+        .iter()
+        .map(|name| name.to_string())
+        .chain(nodes.iter().map(|n| format!("clock.{}", n)))
+        .zip(&columns)
+        .map(|(name, column)| Field::new(name.as_str(), column.data_type().clone(), name == "item"))
+        .collect();
+
+    let batch = RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).map_err(io::Error::other)?;
+    let mut parquet = ArrowWriter::try_new(writer, batch.schema(), None).map_err(io::Error::other)?;
+    parquet.write(&batch).map_err(io::Error::other)?;
+    parquet.close().map_err(io::Error::other)?;
+    Ok(())
+}
@@ -0,0 +1,450 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, de::DeserializeOwned};
+use crate::core::audit::AuditEntry;
+use crate::core::log::LogEntry;
+use crate::core::snapshot::{Snapshot, SnapshotDelta};
+use crate::core::storage::{AuditStore, LogSink, LogStore};
+use crate::core::storage::checksum::{parse_line, seal_line};
+#[cfg(feature = "encrypt")]
+use crate::core::storage::PayloadCipher;
+
+/// Optional cipher for the items written by a file backend
+#[cfg(feature = "encrypt")]
+type Cipher = Option<PayloadCipher>;
+#[cfg(not(feature = "encrypt"))]
+type Cipher = ();
+
+/// Writes each entry as one JSON line to any writer
+pub struct NdjsonSink<W> {
+    writer: W,
+    cipher: Cipher,
+}
+
+impl<W: Write> NdjsonSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, cipher: Cipher::default() }
+    }
+
+    /// Encrypt each entry's item before it is written
+    #[cfg(feature = "encrypt")]
+    pub fn with_cipher(mut self, cipher: PayloadCipher) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+}
+
+impl NdjsonSink<File> {
+    /// Append to a file, creating it if needed
+    pub fn append_to(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(Self::new(file))
+    }
+}
+
+impl<T: Serialize, W: Write + Send> LogSink<T> for NdjsonSink<W> {
+    fn write(&mut self, entry: &LogEntry<T>) -> io::Result<()> {
+        self.writer.write_all(&encode_entry(&self.cipher, entry)?)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()
+    }
+}
+
+/// NDJSON file store, one checksummed entry per line (same format as `append_logs`)
+pub struct FileStore<T> {
+    path: PathBuf,
+    file: File,
+    /// Byte offset of each stored line, so paging doesn't rescan the file
+    offsets: Vec<u64>,
+    end: u64,
+    cipher: Cipher,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> FileStore<T> {
+    /// Create a new store, truncating any existing file
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).write(true).truncate(true).read(true).open(&path)?;
+        Ok(Self { path, file, offsets: Vec::new(), end: 0, cipher: Cipher::default(), _marker: PhantomData })
+    }
+
+    /// Open an existing store, indexing the entries already on disk
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).read(true).open(&path)?;
+        let (offsets, end) = index_lines(&path)?;
+        Ok(Self { path, file, offsets, end, cipher: Cipher::default(), _marker: PhantomData })
+    }
+
+    /// Encrypt items on append and decrypt them on read
+    /// Entries written without a cipher stay readable
+    #[cfg(feature = "encrypt")]
+    pub fn with_cipher(mut self, cipher: PayloadCipher) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    /// Path of the backing file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> LogStore<T> for FileStore<T> {
+    fn append(&mut self, entries: &[LogEntry<T>]) -> io::Result<()> {
+        let mut buf = Vec::new();
+        for entry in entries {
+            self.offsets.push(self.end + buf.len() as u64);
+            buf.extend(encode_entry(&self.cipher, entry)?);
+            buf.push(b'\n');
+        }
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(&buf)?;
+        self.file.flush()?;
+        self.end += buf.len() as u64;
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    fn read(&self, offset: usize, limit: usize) -> io::Result<Vec<LogEntry<T>>> {
+        let lines = read_lines(&self.path, &self.offsets, self.end, offset, limit)?;
+        lines.iter().map(|line| decode_entry(&self.cipher, line)).collect()
+    }
+
+    /// Rewrite the file without its first `count` lines
+    fn discard_front(&mut self, count: usize) -> io::Result<()> {
+        let count = count.min(self.offsets.len());
+        if count == 0 {
+            return Ok(());
+        }
+        let start = self.offsets.get(count).copied().unwrap_or(self.end);
+        let mut rest = Vec::new();
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(start))?;
+        file.take(self.end - start).read_to_end(&mut rest)?;
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, &rest)?;
+        std::fs::rename(&tmp, &self.path)?;
+        self.file = OpenOptions::new().append(true).read(true).open(&self.path)?;
+        self.offsets = self.offsets[count..].iter().map(|offset| offset - start).collect();
+        self.end -= start;
+        Ok(())
+    }
+
+    /// Rewrite the file with the lines at `positions` redacted
+    fn redact(&mut self, positions: &[usize]) -> io::Result<()> {
+        let mut contents = Vec::new();
+        File::open(&self.path)?.take(self.end).read_to_end(&mut contents)?;
+        let mut rewritten = Vec::with_capacity(contents.len());
+        let mut offsets = Vec::with_capacity(self.offsets.len());
+        for (i, &start) in self.offsets.iter().enumerate() {
+            let end = self.offsets.get(i + 1).map_or(self.end, |&next| next) as usize;
+            offsets.push(rewritten.len() as u64);
+            if positions.contains(&i) {
+                let line = std::str::from_utf8(&contents[start as usize..end]).map_err(io::Error::other)?;
+                let mut entry: LogEntry<T> = decode_entry(&self.cipher, line.trim_end())?;
+                entry.redact();
+                rewritten.extend(encode_entry(&self.cipher, &entry)?);
+                rewritten.push(b'\n');
+            } else {
+                rewritten.extend_from_slice(&contents[start as usize..end]);
+            }
+        }
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, &rewritten)?;
+        std::fs::rename(&tmp, &self.path)?;
+        self.file = OpenOptions::new().append(true).read(true).open(&self.path)?;
+        self.offsets = offsets;
+        self.end = rewritten.len() as u64;
+        Ok(())
+    }
+}
+
+/// Audit trail file, one checksummed NDJSON line per entry like the log files
+/// Only ever appended to, so an audit file can't be edited through the queue
+pub struct AuditFile {
+    path: PathBuf,
+    file: File,
+    offsets: Vec<u64>,
+    end: u64,
+}
+
+impl AuditFile {
+    /// Open (or create) the file, indexing the entries already on disk
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).read(true).open(&path)?;
+        let (offsets, end) = index_lines(&path)?;
+        Ok(Self { path, file, offsets, end })
+    }
+
+    /// Path of the backing file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AuditStore for AuditFile {
+    fn append(&mut self, entry: &AuditEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry).map(seal_line).map_err(io::Error::other)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.flush()?;
+        self.offsets.push(self.end);
+        self.end += line.len() as u64;
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    fn read(&self, offset: usize, limit: usize) -> io::Result<Vec<AuditEntry>> {
+        let lines = read_lines(&self.path, &self.offsets, self.end, offset, limit)?;
+        lines.iter().map(|line| parse_line(line)).collect()
+    }
+}
+
+/// Byte offset of each non-blank line of a file, and the file's length
+fn index_lines(path: &Path) -> io::Result<(Vec<u64>, u64)> {
+    let mut offsets = Vec::new();
+    let mut end = 0u64;
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let n = reader.read_line(&mut line)?;
+        if n == 0 {
+            break;
+        }
+        if !line.trim().is_empty() {
+            offsets.push(end);
+        }
+        end += n as u64;
+    }
+    Ok((offsets, end))
+}
+
+/// Up to `limit` non-blank lines starting at indexed line `offset`, reading no further than `end`
+fn read_lines(path: &Path, offsets: &[u64], end: u64, offset: usize, limit: usize) -> io::Result<Vec<String>> {
+    let Some(&start) = offsets.get(offset) else {
+        return Ok(Vec::new());
+    };
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    BufReader::new(file.take(end - start))
+        .lines()
+        .filter(|line| !matches!(line, Ok(l) if l.trim().is_empty()))
+        .take(limit)
+        .collect()
+}
+
+/// Snapshot saved as a single JSON document
+pub struct SnapshotFile {
+    path: PathBuf,
+    #[cfg_attr(not(feature = "encrypt"), allow(dead_code))]
+    cipher: Cipher,
+}
+
+impl SnapshotFile {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self { path: path.as_ref().to_path_buf(), cipher: Cipher::default() }
+    }
+
+    /// Encrypt each queued item on save and decrypt it on load
+    #[cfg(feature = "encrypt")]
+    pub fn with_cipher(mut self, cipher: PayloadCipher) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    /// Path of the snapshot file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write `snapshot`, replacing the file only once it is fully written
+    pub fn save<T: Serialize>(&self, snapshot: &Snapshot<T>) -> io::Result<()> {
+        #[allow(unused_mut)]
+        let mut value = serde_json::to_value(snapshot).map_err(io::Error::other)?;
+        #[cfg(feature = "encrypt")]
+        if let Some(cipher) = &self.cipher
+            && let Some(items) = value.get_mut("items").and_then(|i| i.as_array_mut())
+        {
+            for item in items {
+                cipher.seal_value(item)?;
+            }
+        }
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec(&value).map_err(io::Error::other)?)?;
+        std::fs::rename(tmp, &self.path)
+    }
+
+    /// Read the snapshot back
+    pub fn load<T: DeserializeOwned>(&self) -> io::Result<Snapshot<T>> {
+        #[allow(unused_mut)]
+        let mut value: serde_json::Value = serde_json::from_slice(&std::fs::read(&self.path)?).map_err(io::Error::other)?;
+        #[cfg(feature = "encrypt")]
+        if let Some(cipher) = &self.cipher
+            && let Some(items) = value.get_mut("items").and_then(|i| i.as_array_mut())
+        {
+            for item in items {
+                cipher.open_value(item)?;
+            }
+        }
+        serde_json::from_value(value).map_err(io::Error::other)
+    }
+}
+
+/// Default number of deltas saved after a base snapshot before the next full one
+pub const DEFAULT_MAX_DELTAS: usize = 16;
+
+/// Incremental snapshots: a full base snapshot in a `SnapshotFile`, and the deltas saved
+/// since in the file beside it with the `deltas` extension, one checksummed NDJSON line each
+/// Every `max_deltas` saves a fresh base is written instead and the deltas start over.
+/// Deltas left over from before the base, as after a crash between the two, are skipped.
+pub struct SnapshotChain<T> {
+    base: SnapshotFile,
+    deltas_path: PathBuf,
+    max_deltas: usize,
+    deltas: usize,
+    latest: Option<Snapshot<T>>,
+}
+
+impl<T: Serialize + DeserializeOwned> SnapshotChain<T> {
+    /// Chain with its base at `path`
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            base: SnapshotFile::new(&path),
+            deltas_path: path.as_ref().with_extension("deltas"),
+            max_deltas: DEFAULT_MAX_DELTAS,
+            deltas: 0,
+            latest: None,
+        }
+    }
+
+    /// Save a full base snapshot after `max` deltas
+    pub fn with_max_deltas(mut self, max: usize) -> Self {
+        self.max_deltas = max;
+        self
+    }
+
+    /// Encrypt each queued item on save and decrypt it on load, in the base and deltas alike
+    #[cfg(feature = "encrypt")]
+    pub fn with_cipher(mut self, cipher: PayloadCipher) -> Self {
+        self.base = self.base.with_cipher(cipher);
+        self
+    }
+
+    /// Deltas saved since the base
+    pub fn deltas(&self) -> usize {
+        self.deltas
+    }
+
+    /// Save `snapshot`, as a delta from the one saved or loaded last if there is one and the
+    /// chain isn't full yet; returns true if it was saved as a full base
+    pub fn save(&mut self, snapshot: Snapshot<T>) -> io::Result<bool> {
+        let delta = match &self.latest {
+            Some(latest) if self.deltas < self.max_deltas => latest.delta_to(&snapshot),
+            _ => {
+                self.base.save(&snapshot)?;
+                std::fs::write(&self.deltas_path, b"")?;
+                (self.deltas, self.latest) = (0, Some(snapshot));
+                return Ok(true);
+            }
+        };
+        let mut file = OpenOptions::new().append(true).create(true).open(&self.deltas_path)?;
+        file.write_all(&self.encode_delta(&delta)?)?;
+        file.write_all(b"\n")?;
+        file.sync_data()?;
+        self.deltas += 1;
+        self.latest = Some(snapshot);
+        Ok(false)
+    }
+
+    /// Read the base back and bring it forward by the deltas that follow it
+    /// A last line cut short, as by a crash while it was written, is ignored.
+    pub fn load(&mut self) -> io::Result<Snapshot<T>> {
+        let mut snapshot = self.base.load::<T>()?;
+        let text = match std::fs::read_to_string(&self.deltas_path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let complete = text.rfind('\n').map_or("", |end| &text[..end]);
+        self.deltas = 0;
+        for line in complete.lines().filter(|line| !line.trim().is_empty()) {
+            let delta = self.decode_delta(line)?;
+            if snapshot.precedes(&delta) {
+                snapshot.apply(delta);
+                self.deltas += 1;
+            }
+        }
+        self.latest = Some(snapshot.clone());
+        Ok(snapshot)
+    }
+
+    /// One checksummed NDJSON line for `delta`, with the added items sealed if there is a cipher
+    fn encode_delta(&self, delta: &SnapshotDelta<T>) -> io::Result<Vec<u8>> {
+        #[allow(unused_mut)]
+        let mut value = serde_json::to_value(delta).map_err(io::Error::other)?;
+        #[cfg(feature = "encrypt")]
+        if let Some(cipher) = &self.base.cipher
+            && let Some(items) = value.get_mut("added").and_then(|i| i.as_array_mut())
+        {
+            for item in items {
+                cipher.seal_value(item)?;
+            }
+        }
+        serde_json::to_vec(&value).map(seal_line).map_err(io::Error::other)
+    }
+
+    /// Parse a line of the deltas file, checking its checksum and opening sealed items
+    fn decode_delta(&self, line: &str) -> io::Result<SnapshotDelta<T>> {
+        #[allow(unused_mut)]
+        let mut value: serde_json::Value = parse_line(line)?;
+        #[cfg(feature = "encrypt")]
+        if let Some(cipher) = &self.base.cipher
+            && let Some(items) = value.get_mut("added").and_then(|i| i.as_array_mut())
+        {
+            for item in items {
+                cipher.open_value(item)?;
+            }
+        }
+        serde_json::from_value(value).map_err(io::Error::other)
+    }
+}
+
+/// One checksummed NDJSON line for `entry`, with the item sealed if there is a cipher
+#[cfg_attr(not(feature = "encrypt"), allow(clippy::let_unit_value, unused_variables))]
+fn encode_entry<T: Serialize>(cipher: &Cipher, entry: &LogEntry<T>) -> io::Result<Vec<u8>> {
+    #[cfg(feature = "encrypt")]
+    if let Some(cipher) = cipher {
+        let mut value = serde_json::to_value(entry).map_err(io::Error::other)?;
+        if let Some(item) = value.pointer_mut("/event/item") {
+            cipher.seal_value(item)?;
+        }
+        return serde_json::to_vec(&value).map(seal_line).map_err(io::Error::other);
+    }
+    serde_json::to_vec(entry).map(seal_line).map_err(io::Error::other)
+}
+
+/// Parse an NDJSON line, checking its checksum and opening a sealed item if there is a cipher
+#[cfg_attr(not(feature = "encrypt"), allow(clippy::let_unit_value, unused_variables))]
+fn decode_entry<T: DeserializeOwned>(cipher: &Cipher, line: &str) -> io::Result<LogEntry<T>> {
+    #[cfg(feature = "encrypt")]
+    if let Some(cipher) = cipher {
+        let mut value: serde_json::Value = parse_line(line)?;
+        if let Some(item) = value.pointer_mut("/event/item") {
+            cipher.open_value(item)?;
+        }
+        return serde_json::from_value(value).map_err(io::Error::other);
+    }
+    parse_line(line)
+}
@@ -0,0 +1,235 @@
+//! Serialization formats for log files
+//! NDJSON stays the default and matches every other file the crate writes.
+//! MessagePack and CBOR entries are framed as `[length u32][crc32 u32][payload]`
+//! (little-endian), so the readers can tell a torn or damaged entry from the end
+//! of the file just like the NDJSON checksums do.
+
+use std::fmt::{Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use crate::core::log::LogEntry;
+use crate::core::storage::LogSink;
+use crate::core::storage::checksum::{parse_line, seal_line};
+
+/// How log entries are written to a file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogFormat {
+    /// One checksummed JSON object per line
+    #[default]
+    #[serde(rename = "ndjson")]
+    Ndjson,
+    /// Needs the `msgpack` feature
+    #[serde(rename = "msgpack")]
+    MessagePack,
+    /// Needs the `cbor` feature
+    #[serde(rename = "cbor")]
+    Cbor,
+}
+
+impl LogFormat {
+    /// Check if this build can read and write the format
+    pub fn is_available(self) -> bool {
+        match self {
+            LogFormat::Ndjson => true,
+            LogFormat::MessagePack => cfg!(feature = "msgpack"),
+            LogFormat::Cbor => cfg!(feature = "cbor"),
+        }
+    }
+
+    /// Conventional file extension
+    pub fn extension(self) -> &'static str {
+        match self {
+            LogFormat::Ndjson => "ndjson",
+            LogFormat::MessagePack => "msgpack",
+            LogFormat::Cbor => "cbor",
+        }
+    }
+
+    fn check_available(self) -> io::Result<()> {
+        if self.is_available() {
+            return Ok(());
+        }
+        let feature = if self == LogFormat::Cbor { "cbor" } else { "msgpack" };
+        Err(io::Error::new(io::ErrorKind::Unsupported, format!("{} logs need the `{}` feature", self, feature)))
+    }
+
+    fn encode<T: Serialize>(self, entry: &LogEntry<T>) -> io::Result<Vec<u8>> {
+        match self {
+            LogFormat::Ndjson => {
+                let mut line = serde_json::to_vec(entry).map(seal_line).map_err(io::Error::other)?;
+                line.push(b'\n');
+                Ok(line)
+            }
+            #[cfg(feature = "msgpack")]
+            LogFormat::MessagePack => frame(rmp_serde::to_vec_named(entry).map_err(io::Error::other)?),
+            #[cfg(feature = "cbor")]
+            LogFormat::Cbor => {
+                let mut payload = Vec::new();
+                ciborium::into_writer(entry, &mut payload).map_err(io::Error::other)?;
+                frame(payload)
+            }
+            #[allow(unreachable_patterns)] // only reachable with a format feature off
+            _ => Err(self.check_available().unwrap_err()),
+        }
+    }
+
+    #[cfg_attr(not(any(feature = "msgpack", feature = "cbor")), allow(unused_variables))]
+    fn decode<T: DeserializeOwned>(self, payload: &[u8]) -> io::Result<LogEntry<T>> {
+        match self {
+            LogFormat::Ndjson => parse_line(std::str::from_utf8(payload).map_err(io::Error::other)?),
+            #[cfg(feature = "msgpack")]
+            LogFormat::MessagePack => rmp_serde::from_slice(payload).map_err(io::Error::other),
+            #[cfg(feature = "cbor")]
+            LogFormat::Cbor => ciborium::from_reader(payload).map_err(io::Error::other),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.check_available().unwrap_err()),
+        }
+    }
+}
+
+impl Display for LogFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            LogFormat::Ndjson => "ndjson",
+            LogFormat::MessagePack => "msgpack",
+            LogFormat::Cbor => "cbor",
+        })
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = io::Error;
+
+    fn from_str(name: &str) -> io::Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "ndjson" | "json" => Ok(LogFormat::Ndjson),
+            "msgpack" | "messagepack" => Ok(LogFormat::MessagePack),
+            "cbor" => Ok(LogFormat::Cbor),
+            other => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown log format {}", other))),
+        }
+    }
+}
+
+/// Length and checksum header of a binary entry
+#[cfg(any(feature = "msgpack", feature = "cbor"))]
+fn frame(payload: Vec<u8>) -> io::Result<Vec<u8>> {
+    let len = u32::try_from(payload.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "entry too large"))?;
+    let mut record = Vec::with_capacity(8 + payload.len());
+    record.extend_from_slice(&len.to_le_bytes());
+    record.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    record.extend_from_slice(&payload);
+    Ok(record)
+}
+
+/// Writes log entries in a chosen format
+pub struct LogWriter<W> {
+    writer: W,
+    format: LogFormat,
+}
+
+impl<W: Write> LogWriter<W> {
+    /// Fails if the format's feature isn't enabled
+    pub fn new(writer: W, format: LogFormat) -> io::Result<Self> {
+        format.check_available()?;
+        Ok(Self { writer, format })
+    }
+
+    pub fn format(&self) -> LogFormat {
+        self.format
+    }
+
+    /// Write entries in order and flush
+    pub fn write_entries<T: Serialize>(&mut self, entries: &[LogEntry<T>]) -> io::Result<()> {
+        for entry in entries {
+            self.writer.write_all(&self.format.encode(entry)?)?;
+        }
+        self.writer.flush()
+    }
+}
+
+impl LogWriter<BufWriter<File>> {
+    /// Append to a file, creating it if needed
+    pub fn append_to(path: impl AsRef<Path>, format: LogFormat) -> io::Result<Self> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Self::new(BufWriter::new(file), format)
+    }
+}
+
+impl<T: Serialize, W: Write + Send> LogSink<T> for LogWriter<W> {
+    fn write(&mut self, entry: &LogEntry<T>) -> io::Result<()> {
+        self.write_entries(std::slice::from_ref(entry))
+    }
+}
+
+/// Reads back what a `LogWriter` wrote
+/// A damaged entry is an `InvalidData` error and a torn last entry an `UnexpectedEof`
+pub struct LogReader<R> {
+    reader: R,
+    format: LogFormat,
+}
+
+impl<R: BufRead> LogReader<R> {
+    /// Fails if the format's feature isn't enabled
+    pub fn new(reader: R, format: LogFormat) -> io::Result<Self> {
+        format.check_available()?;
+        Ok(Self { reader, format })
+    }
+
+    /// Next entry, `None` at the end of the file
+    pub fn next_entry<T: DeserializeOwned>(&mut self) -> io::Result<Option<LogEntry<T>>> {
+        if self.format == LogFormat::Ndjson {
+            let mut line = String::new();
+            loop {
+                line.clear();
+                if self.reader.read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+                if !line.ends_with('\n') {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "entry is truncated"));
+                }
+                if !line.trim().is_empty() {
+                    return self.format.decode(line.trim_end().as_bytes()).map(Some);
+                }
+            }
+        }
+        let mut header = [0u8; 8];
+        match self.reader.read(&mut header[..1])? {
+            0 => return Ok(None),
+            _ => self.reader.read_exact(&mut header[1..])?,
+        }
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+        let mut payload = Vec::new();
+        (&mut self.reader).take(len as u64).read_to_end(&mut payload)?;
+        if payload.len() < len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "entry is truncated"));
+        }
+        if crc32fast::hash(&payload) != crc {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "checksum does not match"));
+        }
+        self.format.decode(&payload).map(Some)
+    }
+
+    /// All remaining entries
+    pub fn read_entries<T: DeserializeOwned>(&mut self) -> io::Result<Vec<LogEntry<T>>> {
+        let mut entries = Vec::new();
+        while let Some(entry) = self.next_entry()? {
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
+
+impl LogReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>, format: LogFormat) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?), format)
+    }
+}
+
+/// Read a whole log file written by `LogWriter` or `append_logs`
+pub fn read_logs<T: DeserializeOwned>(path: impl AsRef<Path>, format: LogFormat) -> io::Result<Vec<LogEntry<T>>> {
+    LogReader::open(path, format)?.read_entries()
+}
@@ -0,0 +1,141 @@
+use std::io;
+use crate::core::audit::AuditEntry;
+use crate::core::log::LogEntry;
+
+#[cfg(feature = "persist")]
+mod file;
+#[cfg(feature = "persist")]
+mod checksum;
+#[cfg(feature = "persist")]
+pub use checksum::{Corruption, CorruptionKind, LogReport, verify_log};
+#[cfg(feature = "persist")]
+pub use file::{AuditFile, FileStore, NdjsonSink, SnapshotChain, SnapshotFile};
+#[cfg(feature = "persist")]
+mod format;
+#[cfg(feature = "persist")]
+pub use format::{LogFormat, LogReader, LogWriter, read_logs};
+#[cfg(feature = "persist")]
+mod export;
+#[cfg(feature = "persist")]
+pub use export::export_csv;
+#[cfg(feature = "parquet")]
+pub use export::export_parquet;
+#[cfg(feature = "encrypt")]
+mod crypto;
+#[cfg(feature = "encrypt")]
+pub use crypto::{KEY_LEN, PayloadCipher, Sealed};
+
+/// Storage backend for log entries spilled out of memory
+/// Entries are addressed by their position in the spilled sequence
+pub trait LogStore<T>: Send {
+    /// Append entries in log order
+    fn append(&mut self, entries: &[LogEntry<T>]) -> io::Result<()>;
+
+    /// Number of stored entries
+    fn len(&self) -> usize;
+
+    /// Check if nothing has been stored
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read up to `limit` entries starting at position `offset`
+    fn read(&self, offset: usize, limit: usize) -> io::Result<Vec<LogEntry<T>>>;
+
+    /// Remove the oldest `count` entries, for retention; positions shift down by `count`
+    fn discard_front(&mut self, count: usize) -> io::Result<()> {
+        let _ = count;
+        Err(io::Error::new(io::ErrorKind::Unsupported, "store does not support retention"))
+    }
+
+    /// Drop the items of the entries at `positions`, for purges; see `LogEntry::redacted`
+    fn redact(&mut self, positions: &[usize]) -> io::Result<()> {
+        let _ = positions;
+        Err(io::Error::new(io::ErrorKind::Unsupported, "store does not support redaction"))
+    }
+}
+
+/// Append-only storage backend for the audit trail, see `AuditLog::with_store`
+/// Unlike `LogStore` nothing is ever removed or rewritten
+pub trait AuditStore: Send {
+    /// Append an entry
+    fn append(&mut self, entry: &AuditEntry) -> io::Result<()>;
+
+    /// Number of stored entries
+    fn len(&self) -> usize;
+
+    /// Check if nothing has been stored
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read up to `limit` entries starting at position `offset`
+    fn read(&self, offset: usize, limit: usize) -> io::Result<Vec<AuditEntry>>;
+}
+
+/// In-memory audit store, mostly useful for tests
+impl AuditStore for Vec<AuditEntry> {
+    fn append(&mut self, entry: &AuditEntry) -> io::Result<()> {
+        self.push(entry.clone());
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn read(&self, offset: usize, limit: usize) -> io::Result<Vec<AuditEntry>> {
+        Ok(self.iter().skip(offset).take(limit).cloned().collect())
+    }
+}
+
+/// Sink receiving every log entry as it is recorded
+pub trait LogSink<T>: Send {
+    fn write(&mut self, entry: &LogEntry<T>) -> io::Result<()>;
+}
+
+/// In-memory store, mostly useful for tests
+pub struct MemoryStore<T> {
+    entries: Vec<LogEntry<T>>,
+}
+
+impl<T> MemoryStore<T> {
+    pub fn new() -> Self {
+        Self { entries: Vec::new() }
+    }
+}
+
+impl<T> Default for MemoryStore<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Send + Sync> LogStore<T> for MemoryStore<T> {
+    fn append(&mut self, entries: &[LogEntry<T>]) -> io::Result<()> {
+        self.entries.extend_from_slice(entries);
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn read(&self, offset: usize, limit: usize) -> io::Result<Vec<LogEntry<T>>> {
+        Ok(self.entries.iter().skip(offset).take(limit).cloned().collect())
+    }
+
+    fn discard_front(&mut self, count: usize) -> io::Result<()> {
+        self.entries.drain(..count.min(self.entries.len()));
+        Ok(())
+    }
+
+    fn redact(&mut self, positions: &[usize]) -> io::Result<()> {
+        for &position in positions {
+            if let Some(entry) = self.entries.get_mut(position) {
+                entry.redact();
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,8 @@
+//! Clock types used by the core
+//! `std::time` natively; on wasm32, where `Instant::now` and `SystemTime::now` panic,
+//! the browser-backed equivalents from `web-time`.
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+#[cfg(target_arch = "wasm32")]
+pub use web_time::{Duration, Instant, SystemTime, UNIX_EPOCH};
@@ -0,0 +1,59 @@
+//! W3C trace context carried with queued items
+//! Set on an enqueue, it travels with the enqueue and dequeue events, so every hop
+//! of a message (producer, replicas, consumer) can report spans to one distributed trace.
+
+use std::fmt::{Display, Formatter};
+use serde::{Deserialize, Serialize};
+
+/// Trace and parent span of an operation, serialized as a `traceparent` header value
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct TraceContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// Sampled context; IDs must not be zero
+    pub fn new(trace_id: u128, span_id: u64) -> Self {
+        Self { trace_id, span_id, sampled: true }
+    }
+
+    /// Parse a `traceparent` value, `00-<trace id>-<span id>-<flags>`
+    pub fn parse(traceparent: &str) -> Option<Self> {
+        let mut parts = traceparent.trim().split('-');
+        let (version, trace_id, span_id, flags) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        if version.len() != 2 || trace_id.len() != 32 || span_id.len() != 16 || flags.len() != 2 || version == "ff" {
+            return None;
+        }
+        // Later versions may append fields, version 00 may not
+        if version == "00" && parts.next().is_some() {
+            return None;
+        }
+        let trace_id = u128::from_str_radix(trace_id, 16).ok().filter(|&id| id != 0)?;
+        let span_id = u64::from_str_radix(span_id, 16).ok().filter(|&id| id != 0)?;
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        Some(Self { trace_id, span_id, sampled: flags & 1 == 1 })
+    }
+}
+
+impl Display for TraceContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "00-{:032x}-{:016x}-{:02x}", self.trace_id, self.span_id, u8::from(self.sampled))
+    }
+}
+
+impl From<TraceContext> for String {
+    fn from(context: TraceContext) -> Self {
+        context.to_string()
+    }
+}
+
+impl TryFrom<String> for TraceContext {
+    type Error = String;
+
+    fn try_from(traceparent: String) -> Result<Self, String> {
+        Self::parse(&traceparent).ok_or_else(|| format!("invalid traceparent {:?}", traceparent))
+    }
+}
@@ -0,0 +1,121 @@
+//! Per-topic access control for client-facing APIs
+//! Clients present a token, which maps to an identity; identities are granted
+//! produce, consume and/or admin on topic patterns. Grants to `*` apply to everyone,
+//! including clients without a (known) token.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use serde::Deserialize;
+
+/// Identity used for clients without a known token
+pub const ANONYMOUS: &str = "anonymous";
+
+/// Topic node-wide actions, e.g. a configuration reload, are checked against; only a
+/// grant on the pattern `*` covers it
+pub const ALL_TOPICS: &str = "*";
+
+/// What a client may do on a topic
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    /// Enqueue
+    Produce,
+    /// Dequeue and read (peek, stats, logs)
+    Consume,
+    /// Administrative actions: purge, pause, resume, configure, rebalance, reload
+    Admin,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::Produce => "produce",
+            Permission::Consume => "consume",
+            Permission::Admin => "admin",
+        }
+    }
+}
+
+/// Refused operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessDenied {
+    pub identity: String,
+    pub topic: String,
+    pub permission: Permission,
+}
+
+impl Display for AccessDenied {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} may not {} on topic {}", self.identity, self.permission.as_str(), self.topic)
+    }
+}
+
+impl std::error::Error for AccessDenied {}
+
+struct Grant {
+    pattern: String,
+    permissions: Vec<Permission>,
+}
+
+impl Grant {
+    /// `*` matches every topic, `prefix*` every topic starting with prefix
+    fn matches(&self, topic: &str) -> bool {
+        match self.pattern.strip_suffix('*') {
+            Some(prefix) => topic.starts_with(prefix),
+            None => self.pattern == topic,
+        }
+    }
+}
+
+/// Token-to-identity map and per-identity topic grants
+#[derive(Default)]
+pub struct Acl {
+    tokens: HashMap<String, String>,
+    grants: HashMap<String, Vec<Grant>>,
+}
+
+impl Acl {
+    /// Access control that denies everything until grants are added
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Clients presenting `token` act as `identity`
+    pub fn token(mut self, token: &str, identity: &str) -> Self {
+        self.tokens.insert(token.to_string(), identity.to_string());
+        self
+    }
+
+    /// Let `identity` (or `*` for everyone) use topics matching `pattern`
+    pub fn grant(mut self, identity: &str, pattern: &str, permissions: &[Permission]) -> Self {
+        self.grants.entry(identity.to_string()).or_default().push(Grant {
+            pattern: pattern.to_string(),
+            permissions: permissions.to_vec(),
+        });
+        self
+    }
+
+    /// Identity for a presented token
+    pub fn identify(&self, token: Option<&str>) -> &str {
+        token.and_then(|t| self.tokens.get(t)).map_or(ANONYMOUS, |id| id.as_str())
+    }
+
+    /// Check if `identity` holds `permission` on `topic`
+    pub fn is_allowed(&self, identity: &str, topic: &str, permission: Permission) -> bool {
+        [identity, "*"].iter().any(|id| {
+            self.grants.get(*id).is_some_and(|grants| {
+                grants.iter().any(|g| g.matches(topic) && g.permissions.contains(&permission))
+            })
+        })
+    }
+
+    /// Resolve `token` and check it against the grants
+    pub fn check(&self, token: Option<&str>, topic: &str, permission: Permission) -> Result<(), AccessDenied> {
+        let identity = self.identify(token);
+        if self.is_allowed(identity, topic, permission) {
+            Ok(())
+        } else {
+            Err(AccessDenied { identity: identity.to_string(), topic: topic.to_string(), permission })
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Client for a node's HTTP admin API, behind the `queuectl` binary
+//! Each call is one request to the node's HTTP address (see `crate::engine::http`),
+//! answered with the JSON body; error statuses become `io::Error`s carrying the
+//! node's message. Topics and keys are percent-encoded into the URL.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use serde_json::Value;
+use crate::engine::http::percent_encode;
+
+/// How long to wait for a node to accept a request and answer it
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Admin client for one node
+#[derive(Clone, Debug)]
+pub struct AdminClient {
+    addr: String,
+    token: Option<String>,
+    timeout: Duration,
+}
+
+impl AdminClient {
+    /// Client for the node serving HTTP on `addr`, `host:port`
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self { addr: addr.into(), token: None, timeout: DEFAULT_TIMEOUT }
+    }
+
+    /// Authenticate as the holder of `token`, for nodes with access control
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Readiness and per-topic stats
+    pub fn status(&self) -> io::Result<Value> {
+        self.call("GET", "/admin/status")
+    }
+
+    /// Peer liveness and backpressure
+    pub fn peers(&self) -> io::Result<Value> {
+        self.call("GET", "/cluster")
+    }
+
+    /// What waits besides the items: per topic, or the buffered events of one topic
+    pub fn pending(&self, topic: Option<&str>) -> io::Result<Value> {
+        match topic {
+            Some(topic) => self.call("GET", &format!("/queues/{}/buffered-events", percent_encode(topic))),
+            None => self.call("GET", "/admin/pending"),
+        }
+    }
+
+    /// Purge the items of `topic` logged under `key`
+    pub fn purge(&self, topic: &str, key: &str) -> io::Result<Value> {
+        self.call("POST", &format!("/queues/{}/purge?key={}", percent_encode(topic), percent_encode(key)))
+    }
+
+    pub fn pause(&self, topic: &str) -> io::Result<Value> {
+        self.call("POST", &format!("/queues/{}/pause", percent_encode(topic)))
+    }
+
+    pub fn resume(&self, topic: &str) -> io::Result<Value> {
+        self.call("POST", &format!("/queues/{}/resume", percent_encode(topic)))
+    }
+
+    pub fn snapshot(&self, topic: &str) -> io::Result<Value> {
+        self.call("GET", &format!("/queues/{}/snapshot", percent_encode(topic)))
+    }
+
+    /// Settings in effect on `topic`
+    pub fn config(&self, topic: &str) -> io::Result<Value> {
+        self.call("GET", &format!("/queues/{}/config", percent_encode(topic)))
+    }
+
+    /// Spread the queues of `topic` the node owns over the placement members
+    pub fn rebalance(&self, topic: &str) -> io::Result<Value> {
+        self.call("POST", &format!("/admin/rebalance?topic={}", percent_encode(topic)))
+    }
+
+    fn call(&self, method: &str, path: &str) -> io::Result<Value> {
+        let addr = self
+            .addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} does not resolve", self.addr)))?;
+        let mut stream = TcpStream::connect_timeout(&addr, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let auth = self.token.as_ref().map_or_else(String::new, |token| format!("Authorization: Bearer {}\r\n", token));
+        write!(stream, "{} {} HTTP/1.1\r\nHost: {}\r\n{}Content-Length: 0\r\nConnection: close\r\n\r\n", method, path, self.addr, auth)?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+        let response = String::from_utf8_lossy(&response);
+        let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response");
+        let (head, body) = response.split_once("\r\n\r\n").ok_or_else(malformed)?;
+        // "HTTP/1.1 200 OK"
+        let status: u16 = head.split_whitespace().nth(1).and_then(|status| status.parse().ok()).ok_or_else(malformed)?;
+        let body: Value = if body.is_empty() { Value::Null } else { serde_json::from_str(body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))? };
+        match status {
+            200..=299 => Ok(body),
+            _ => {
+                let message = body.get("error").and_then(Value::as_str).map_or_else(|| format!("HTTP {}", status), str::to_string);
+                let kind = match status {
+                    403 => io::ErrorKind::PermissionDenied,
+                    404 => io::ErrorKind::NotFound,
+                    400 => io::ErrorKind::InvalidInput,
+                    _ => io::ErrorKind::Other,
+                };
+                Err(io::Error::new(kind, message))
+            }
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Claim checks: large items go to a blob store, the queue only carries a reference
+//! A topic of `Claim<T>` items holds small items inline and larger ones as a `BlobRef`,
+//! so events, logs and snapshots stay small. `ClaimCheck` stores items above its
+//! threshold on enqueue and fetches them back on dequeue. Every node of the cluster
+//! needs the same store, e.g. a shared directory or an object store behind `BlobStore`.
+//!
+//! Blobs outlive the dequeue, as failed items are queued again by reference; remove
+//! them with `ClaimCheck::release` once an item is done with.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use crate::core::buildcore::Event;
+use crate::engine::node::Node;
+
+/// Where claim-checked items are kept, by key; implement it for S3 and the like
+pub trait BlobStore: Send + Sync {
+    fn put(&self, key: &str, data: &[u8]) -> io::Result<()>;
+
+    /// NotFound if there is no blob under `key`
+    fn get(&self, key: &str) -> io::Result<Vec<u8>>;
+
+    /// Removing a missing blob is not an error
+    fn delete(&self, key: &str) -> io::Result<()>;
+}
+
+/// Blobs as files in one directory
+pub struct FileBlobStore {
+    dir: PathBuf,
+}
+
+impl FileBlobStore {
+    /// Store blobs in `dir`, creating it if needed
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, key: &str) -> io::Result<PathBuf> {
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid blob key {:?}", key)));
+        }
+        Ok(self.dir.join(key))
+    }
+}
+
+impl BlobStore for FileBlobStore {
+    fn put(&self, key: &str, data: &[u8]) -> io::Result<()> {
+        // Written aside and renamed, so readers never see part of a blob
+        let path = self.path(key)?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(tmp, path)
+    }
+
+    fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.path(key)?)
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(key)?) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// Reference to an item kept in a blob store
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobRef {
+    pub key: String,
+    /// Length of the item encoded as JSON
+    pub len: u64,
+    /// CRC32 of the encoded item
+    pub crc32: u32,
+}
+
+/// An item of a claim-checked topic: inline, or in the blob store
+/// Serializes as the bare item, or as `{"$blob": {...}}`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Claim<T> {
+    Blob {
+        #[serde(rename = "$blob")]
+        blob: BlobRef,
+    },
+    Inline(T),
+}
+
+impl<T> Claim<T> {
+    /// Reference to the stored item, None if it is inline
+    pub fn blob(&self) -> Option<&BlobRef> {
+        match self {
+            Claim::Blob { blob } => Some(blob),
+            Claim::Inline(_) => None,
+        }
+    }
+}
+
+/// Stores items above a size threshold in a blob store, see the module docs
+#[derive(Clone)]
+pub struct ClaimCheck {
+    store: Arc<dyn BlobStore>,
+    threshold: usize,
+}
+
+impl ClaimCheck {
+    /// Keep items whose JSON encoding is longer than `threshold` bytes in `store`
+    pub fn new(store: Arc<dyn BlobStore>, threshold: usize) -> Self {
+        Self { store, threshold }
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// `item` as it goes in the queue: in the blob store if it is over the threshold
+    pub fn check<T: Serialize>(&self, item: T) -> io::Result<Claim<T>> {
+        let data = serde_json::to_vec(&item).map_err(io::Error::other)?;
+        if data.len() <= self.threshold {
+            return Ok(Claim::Inline(item));
+        }
+        let blob = BlobRef { key: next_key(), len: data.len() as u64, crc32: crc32fast::hash(&data) };
+        self.store.put(&blob.key, &data)?;
+        Ok(Claim::Blob { blob })
+    }
+
+    /// The item `claim` stands for, fetched from the blob store if need be
+    pub fn redeem<T: Clone + DeserializeOwned>(&self, claim: &Claim<T>) -> io::Result<T> {
+        let blob = match claim {
+            Claim::Inline(item) => return Ok(item.clone()),
+            Claim::Blob { blob } => blob,
+        };
+        let data = self.store.get(&blob.key)?;
+        if data.len() as u64 != blob.len || crc32fast::hash(&data) != blob.crc32 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("blob {} doesn't match its reference", blob.key)));
+        }
+        serde_json::from_slice(&data).map_err(io::Error::other)
+    }
+
+    /// Remove the stored item of `claim`, if any
+    pub fn release<T>(&self, claim: &Claim<T>) -> io::Result<()> {
+        match claim.blob() {
+            Some(blob) => self.store.delete(&blob.key),
+            None => Ok(()),
+        }
+    }
+
+    /// Enqueue `item` on `topic`, storing it first if it is over the threshold
+    pub fn enqueue<T>(&self, node: &Node<Claim<T>>, topic: &str, item: T) -> io::Result<Event<Claim<T>>>
+    where
+        T: Serialize + Send + Sync + 'static,
+    {
+        let claim = self.check(item)?;
+        let stored = claim.blob().cloned();
+        node.enqueue(topic, claim).map_err(|e| {
+            // Nothing refers to the blob of a refused enqueue
+            if let Some(blob) = stored {
+                self.store.delete(&blob.key).ok();
+            }
+            io::Error::other(e)
+        })
+    }
+
+    /// Dequeue from `topic`, with the item fetched back if it was stored; the claim
+    /// comes along for `release`
+    pub fn dequeue<T>(&self, node: &Node<Claim<T>>, topic: &str) -> io::Result<Option<(T, Arc<Claim<T>>)>>
+    where
+        T: Clone + DeserializeOwned + Send + Sync + 'static,
+    {
+        let Some(claim) = node.dequeue(topic).0 else {
+            return Ok(None);
+        };
+        Ok(Some((self.redeem(&claim)?, claim)))
+    }
+}
+
+/// Blob key unique across nodes and restarts: time, process and a counter
+fn next_key() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+    format!("{:x}-{:x}-{:x}", nanos, std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed))
+}
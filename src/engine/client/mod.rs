@@ -0,0 +1,368 @@
+//! Lightweight client for a remote node
+//! Speaks the node's peer protocol, so producers and consumers don't need to
+//! embed a `DistributedQueueSystem` of their own.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::marker::PhantomData;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use serde::{Serialize, de::DeserializeOwned};
+use crate::core::buildcore::{FailedEvent, LogEntry, NodeId, QueueStats, TraceContext};
+use crate::core::buildcore::Snapshot;
+use crate::core::id::IdGenerator;
+use crate::engine::namespace::NamespaceStats;
+use crate::engine::stats::TopicStats;
+use crate::engine::transfer::SnapshotDownload;
+use crate::engine::network::{Codec, DEFAULT_MAX_FRAME_LEN, Decoder, Envelope, Frame, MIN_FRAME_LEN, Message, Request, Response, Security, Stream, frame};
+#[cfg(feature = "tls")]
+use crate::engine::network::TlsConfig;
+#[cfg(feature = "compress")]
+use crate::engine::network::Compression;
+
+/// How long to wait for a node to answer a request
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often a subscription polls an empty topic
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+static CLIENT_IDS: IdGenerator = IdGenerator::new(0);
+
+struct Connection {
+    writer: Stream,
+    reader: BufReader<Stream>,
+    codec: Codec,
+    max_frame: usize,
+    decoder: Decoder,
+}
+
+/// Client connected to one node
+pub struct QueueClient<T> {
+    client_id: NodeId,
+    node_addr: SocketAddr,
+    connection: Mutex<Connection>,
+    next_request: AtomicU64,
+    token: Option<String>,
+    _item: PhantomData<fn(T) -> T>,
+}
+
+impl<T: Serialize + DeserializeOwned> QueueClient<T> {
+    /// Connect to the node's peer address
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Self::connect_with(addr, "", Security::Plain)
+    }
+
+    /// Connect to a node whose transport uses TLS; `node_id` is the name its certificate is issued for
+    #[cfg(feature = "tls")]
+    pub fn connect_tls(addr: impl ToSocketAddrs, node_id: &str, tls: &TlsConfig) -> io::Result<Self> {
+        Self::connect_with(addr, node_id, Security::Tls(tls.clone()))
+    }
+
+    fn connect_with(addr: impl ToSocketAddrs, node_id: &str, security: Security) -> io::Result<Self> {
+        let Some(node_addr) = addr.to_socket_addrs()?.next() else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to"));
+        };
+        let stream = security.connect(node_id, node_addr)?;
+        stream.set_read_timeout(Some(DEFAULT_TIMEOUT))?;
+        let reader = BufReader::new(stream.try_clone()?);
+        // Unique per process and client, also across restarts, so replies find their way back
+        let client_id = NodeId::intern(&format!("client-{}-{:x}", std::process::id(), CLIENT_IDS.next_id()));
+        Ok(Self {
+            client_id,
+            node_addr,
+            connection: Mutex::new(Connection {
+                writer: stream,
+                reader,
+                codec: Codec::default(),
+                max_frame: DEFAULT_MAX_FRAME_LEN,
+                decoder: Decoder::new(DEFAULT_MAX_FRAME_LEN),
+            }),
+            next_request: AtomicU64::new(1),
+            token: None,
+            _item: PhantomData,
+        })
+    }
+
+    /// Present `token` with every request, for nodes with access control
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Compress large requests, and ask the node to compress large replies
+    /// Stays uncompressed if the node doesn't support it
+    #[cfg(feature = "compress")]
+    pub fn with_compression(self, compression: Compression) -> io::Result<Self> {
+        {
+            let mut connection = self.connection.lock().unwrap();
+            let codec = Codec { compression: Some(compression), ..Codec::default() };
+            connection.codec = frame::handshake(&mut connection.writer, &codec)?;
+        }
+        Ok(self)
+    }
+
+    /// Longest line sent or accepted; longer requests are sent in chunks
+    /// Should match the node's limit
+    pub fn with_max_frame_len(self, max_frame: usize) -> Self {
+        {
+            let mut connection = self.connection.lock().unwrap();
+            connection.max_frame = max_frame.max(MIN_FRAME_LEN);
+            connection.decoder = Decoder::new(max_frame);
+        }
+        self
+    }
+
+    /// Check if large frames are compressed on this connection
+    pub fn is_compressed(&self) -> bool {
+        self.connection.lock().unwrap().codec.compression.is_some()
+    }
+
+    /// Change how long to wait for replies
+    pub fn set_timeout(&self, timeout: Duration) -> io::Result<()> {
+        self.connection.lock().unwrap().writer.set_read_timeout(Some(timeout))
+    }
+
+    /// ID this client identifies itself with
+    pub fn client_id(&self) -> &str {
+        self.client_id.as_str()
+    }
+
+    /// Address of the node
+    pub fn node_addr(&self) -> SocketAddr {
+        self.node_addr
+    }
+
+    /// Send a request and wait for its response
+    fn request(&self, request: Request<T>) -> io::Result<Response<T>> {
+        self.request_traced(request, None).map(|(response, _)| response)
+    }
+
+    /// Send a request in a trace and wait for its response and the trace context the node replied with
+    fn request_traced(&self, request: Request<T>, trace: Option<TraceContext>) -> io::Result<(Response<T>, Option<TraceContext>)> {
+        let id = self.next_request.fetch_add(1, Ordering::SeqCst);
+        let envelope = Envelope::new(self.client_id, Message::Request { id, request, token: self.token.clone() }).with_trace(trace);
+        let mut connection = self.connection.lock().unwrap();
+        let line = frame::encode(&envelope, &connection.codec, connection.max_frame)?;
+        connection.writer.write_all(&line)?;
+        loop {
+            let mut reply = String::new();
+            if connection.reader.read_line(&mut reply)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "node closed the connection"));
+            }
+            let Some(Frame::Envelope(envelope)) = connection.decoder.decode::<T>(&reply)? else {
+                continue;
+            };
+            match envelope.message {
+                Message::Response { id: reply_id, response } if reply_id == id => return Ok((response, envelope.trace)),
+                // Late reply to a request that already timed out
+                _ => continue,
+            }
+        }
+    }
+
+    /// Enqueue an item, returning the event ID
+    pub fn enqueue(&self, topic: &str, item: T) -> io::Result<u64> {
+        match self.request(Request::Enqueue { topic: topic.to_string(), item, key: None })? {
+            Response::Enqueued { event_id } => Ok(event_id),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Enqueue an item on the partition its key maps to, returning the event ID
+    pub fn enqueue_keyed(&self, topic: &str, key: &str, item: T) -> io::Result<u64> {
+        match self.request(Request::Enqueue { topic: topic.to_string(), item, key: Some(key.to_string()) })? {
+            Response::Enqueued { event_id } => Ok(event_id),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Enqueue an item as part of a distributed trace, `trace` being the producer's span
+    /// Returns the event ID and the context of the node's enqueue span, which the item carries on
+    pub fn enqueue_traced(&self, topic: &str, item: T, trace: TraceContext) -> io::Result<(u64, TraceContext)> {
+        match self.request_traced(Request::Enqueue { topic: topic.to_string(), item, key: None }, Some(trace))? {
+            (Response::Enqueued { event_id }, reply) => Ok((event_id, reply.unwrap_or(trace))),
+            (other, _) => Err(unexpected(other)),
+        }
+    }
+
+    /// Dequeue the head item of a topic with the trace context it carries, if it was enqueued traced
+    /// The context is that of the node's dequeue span, for the consumer to continue the trace from
+    pub fn dequeue_traced(&self, topic: &str) -> io::Result<Option<(Arc<T>, Option<TraceContext>)>> {
+        match self.request_traced(Request::Dequeue { topic: topic.to_string(), partitions: None }, None)? {
+            (Response::Dequeued { item, .. }, trace) => Ok(item.map(|item| (item, trace))),
+            (other, _) => Err(unexpected(other)),
+        }
+    }
+
+    /// Dequeue the head item of a topic
+    pub fn dequeue(&self, topic: &str) -> io::Result<Option<Arc<T>>> {
+        match self.request(Request::Dequeue { topic: topic.to_string(), partitions: None })? {
+            Response::Dequeued { item, .. } => Ok(item),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Dequeue the head item of a topic with its delivery ID, to report it failed with `fail`
+    pub fn dequeue_delivery(&self, topic: &str) -> io::Result<Option<(u64, Arc<T>)>> {
+        match self.request(Request::Dequeue { topic: topic.to_string(), partitions: None })? {
+            Response::Dequeued { item: Some(item), delivery: Some(delivery) } => Ok(Some((delivery, item))),
+            Response::Dequeued { item: None, .. } => Ok(None),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Report that processing a dequeued item failed, returning the failure's event ID
+    /// The node retries or dead-letters the item according to the topic's retry policy
+    pub fn fail(&self, topic: &str, delivery: u64) -> io::Result<u64> {
+        match self.request(Request::Fail { topic: topic.to_string(), delivery })? {
+            Response::Failed { event_id } => Ok(event_id),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Dequeue from the partitions of a topic assigned to this consumer
+    pub fn dequeue_partitions(&self, topic: &str, partitions: &[u32]) -> io::Result<Option<Arc<T>>> {
+        match self.request(Request::Dequeue { topic: topic.to_string(), partitions: Some(partitions.to_vec()) })? {
+            Response::Dequeued { item, .. } => Ok(item),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Head item of a topic, without removing it
+    pub fn peek(&self, topic: &str) -> io::Result<Option<Arc<T>>> {
+        match self.request(Request::Peek { topic: topic.to_string() })? {
+            Response::Peeked { item } => Ok(item),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// The most recent `count` log entries of a topic
+    pub fn log_tail(&self, topic: &str, count: usize) -> io::Result<Vec<LogEntry<T>>> {
+        match self.request(Request::LogTail { topic: topic.to_string(), count })? {
+            Response::Logs(entries) => Ok(entries),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Stats of a topic
+    pub fn stats(&self, topic: &str) -> io::Result<QueueStats> {
+        match self.request(Request::Stats { topic: topic.to_string() })? {
+            Response::Stats(stats) => Ok(stats),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Statistics of a topic as the node sees them, see `Node::topic_stats`
+    pub fn topic_stats(&self, topic: &str) -> io::Result<TopicStats> {
+        match self.request(Request::TopicStats { topic: topic.to_string() })? {
+            Response::TopicStats(stats) => Ok(stats),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Remote events a topic gave up applying, oldest first
+    pub fn failed_events(&self, topic: &str) -> io::Result<Vec<FailedEvent>> {
+        match self.request(Request::FailedEvents { topic: topic.to_string() })? {
+            Response::FailedEvents(events) => Ok(events),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Totals over a namespace's queues on the node
+    pub fn namespace_stats(&self, namespace: &str) -> io::Result<NamespaceStats> {
+        match self.request(Request::NamespaceStats { namespace: namespace.to_string() })? {
+            Response::NamespaceStats(stats) => Ok(stats),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Names of the topics on the node
+    pub fn topics(&self) -> io::Result<Vec<String>> {
+        match self.request(Request::Topics)? {
+            Response::Topics(names) => Ok(names),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Pull a snapshot of a topic, chunk by chunk, see `crate::engine::transfer`
+    /// If it fails partway, e.g. as the node replaced the snapshot, a fresh one is pulled once more
+    pub fn fetch_snapshot(&self, topic: &str) -> io::Result<Snapshot<T>> {
+        let mut download = SnapshotDownload::new();
+        if let Err(e) = self.resume_snapshot(topic, &mut download) {
+            if download.digest().is_none() {
+                return Err(e);
+            }
+            download = SnapshotDownload::new();
+            self.resume_snapshot(topic, &mut download)?;
+        }
+        download.finish()
+    }
+
+    /// Pull the rest of a snapshot of a topic into `download`, starting a fresh one if it is new
+    /// A download that broke off can be resumed from another client of the same node
+    pub fn resume_snapshot(&self, topic: &str, download: &mut SnapshotDownload) -> io::Result<()> {
+        while !download.is_complete() {
+            self.fetch_chunk(topic, download)?;
+        }
+        Ok(())
+    }
+
+    /// Pull the next chunk of a snapshot of a topic into `download`
+    pub fn fetch_chunk(&self, topic: &str, download: &mut SnapshotDownload) -> io::Result<()> {
+        let request = Request::Snapshot { topic: topic.to_string(), offset: download.offset(), digest: download.digest(), max: download.chunk_len() };
+        match self.request(request)? {
+            Response::SnapshotChunk(chunk) => download.accept(chunk),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Consume items from a topic as they arrive
+    pub fn subscribe(&self, topic: &str) -> Subscription<'_, T> {
+        Subscription { client: self, topic: topic.to_string(), poll_interval: DEFAULT_POLL_INTERVAL, failed: false }
+    }
+}
+
+fn unexpected<T>(response: Response<T>) -> io::Error {
+    match response {
+        Response::Error(message) => io::Error::other(message),
+        _ => io::Error::new(io::ErrorKind::InvalidData, "unexpected response from node"),
+    }
+}
+
+/// Blocking iterator over items dequeued from a topic
+/// Polls while the topic is empty; ends after the first error
+pub struct Subscription<'a, T> {
+    client: &'a QueueClient<T>,
+    topic: String,
+    poll_interval: Duration,
+    failed: bool,
+}
+
+impl<T> Subscription<'_, T> {
+    /// How long to wait between polls of an empty topic
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Iterator for Subscription<'_, T> {
+    type Item = io::Result<Arc<T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        loop {
+            match self.client.dequeue(&self.topic) {
+                Ok(Some(item)) => return Some(Ok(item)),
+                Ok(None) => thread::sleep(self.poll_interval),
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,501 @@
+//! TOML configuration for the `queued` node server
+//! Buffer limits, rate limits, retention and peers can be changed while a node runs,
+//! see `ServerConfig::reload`; everything else takes a restart.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use std::time::Duration;
+use crate::core::buildcore::{DequeuePolicy, LogFormat, RateLimit, RetentionPolicy, RetryPolicy, Throttle};
+use crate::engine::acl::{Acl, Permission};
+use crate::engine::health::HealthPolicy;
+use crate::engine::namespace::NamespaceQuota;
+use crate::engine::network::TcpTransport;
+use crate::engine::node::{BackpressurePolicy, Node};
+use crate::engine::placement::StealPolicy;
+use crate::engine::webhook::Webhook;
+use crate::engine::mirror::RemoteCluster;
+
+/// Attempts of a webhook topic's items when neither the webhook nor `[retry]` set a policy
+pub const DEFAULT_WEBHOOK_ATTEMPTS: u32 = 5;
+
+/// A peer node and the address of its transport listener
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct PeerConfig {
+    pub id: String,
+    pub addr: SocketAddr,
+}
+
+/// PEM files for TLS on the peer transport
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct TlsFiles {
+    /// This node's certificate chain, issued for its node ID
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// CA that signs every node's certificate
+    pub ca: PathBuf,
+}
+
+/// Event signing keys, by node ID; must include this node's own key
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct AuthConfig {
+    pub keys: HashMap<String, String>,
+}
+
+/// Keys for encrypting stored items, hex-encoded by key ID
+/// The highest ID encrypts new data; keep retired keys so older files stay readable
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct EncryptionConfig {
+    pub keys: HashMap<String, String>,
+}
+
+/// Compression of large frames on the peer transport
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct CompressionConfig {
+    /// Smallest frame in bytes worth compressing
+    pub threshold: Option<usize>,
+    /// zstd level
+    pub level: Option<i32>,
+}
+
+/// Token-bucket limits on enqueues, applied per topic
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct RateLimitConfig {
+    /// All enqueues on this node
+    pub node: Option<RateLimit>,
+    /// Each client identity's enqueues
+    pub client: Option<RateLimit>,
+    /// Wait for a token instead of rejecting the enqueue
+    #[serde(default)]
+    pub block: bool,
+}
+
+impl RateLimitConfig {
+    pub fn throttle(&self) -> Throttle {
+        if self.block { Throttle::Block } else { Throttle::Reject }
+    }
+}
+
+/// Log history each topic keeps; see `RetentionPolicy`
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct RetentionConfig {
+    pub max_age_ms: Option<u64>,
+    pub max_entries: Option<usize>,
+    /// Remove entries as soon as every node has seen them
+    #[serde(default)]
+    pub once_stable: bool,
+}
+
+impl RetentionConfig {
+    pub fn to_policy<T>(&self) -> RetentionPolicy<T> {
+        let mut policy = RetentionPolicy::new();
+        if let Some(ms) = self.max_age_ms {
+            policy = policy.max_age(Duration::from_millis(ms));
+        }
+        if let Some(max) = self.max_entries {
+            policy = policy.max_entries(max);
+        }
+        if self.once_stable {
+            policy = policy.once_stable();
+        }
+        policy
+    }
+}
+
+/// Most unconsumed items each origin node may have in a topic
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct QuotaConfig {
+    /// Quota for nodes not listed in `nodes`, unlimited if absent
+    pub default: Option<usize>,
+    #[serde(default)]
+    pub nodes: HashMap<String, usize>,
+}
+
+/// Retries of items whose processing failed; backoff defaults from `RetryPolicy::new`
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct RetryConfig {
+    /// Failed deliveries before an item is dead-lettered
+    pub max_attempts: u32,
+    pub initial_backoff_ms: Option<u64>,
+    pub multiplier: Option<f64>,
+    pub max_backoff_ms: Option<u64>,
+}
+
+impl RetryConfig {
+    pub fn to_policy(&self) -> RetryPolicy {
+        let default = RetryPolicy::new(self.max_attempts);
+        default.backoff(
+            self.initial_backoff_ms.map_or(default.initial_backoff, Duration::from_millis),
+            self.multiplier.unwrap_or(default.multiplier),
+            self.max_backoff_ms.map_or(default.max_backoff, Duration::from_millis),
+        )
+    }
+}
+
+/// HTTP endpoint the items of a topic are posted to, see `WebhookDispatcher`
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct WebhookConfig {
+    /// `http://host[:port][/path]`
+    pub url: String,
+    pub timeout_ms: Option<u64>,
+    /// Retries of failed posts; falls back to `[retry]`, then `DEFAULT_WEBHOOK_ATTEMPTS`
+    pub retry: Option<RetryConfig>,
+}
+
+impl WebhookConfig {
+    pub fn to_webhook(&self) -> io::Result<Webhook> {
+        let webhook = Webhook::new(&self.url)?;
+        Ok(match self.timeout_ms {
+            Some(ms) => webhook.with_timeout(Duration::from_millis(ms)),
+            None => webhook,
+        })
+    }
+
+    /// Retry policy of the webhook's topic, given the server-wide one
+    pub fn retry_policy(&self, fallback: Option<RetryPolicy>) -> RetryPolicy {
+        self.retry.as_ref().map(RetryConfig::to_policy).or(fallback).unwrap_or(RetryPolicy::new(DEFAULT_WEBHOOK_ATTEMPTS))
+    }
+}
+
+/// Cluster the enqueues of a topic are mirrored to, see `ClusterMirror`
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct MirrorConfig {
+    /// Peer addresses (`host:port`) of nodes of the other cluster, tried in order
+    pub addrs: Vec<String>,
+    /// Topic enqueued on there, the mirrored topic's name if absent
+    pub topic: Option<String>,
+    pub token: Option<String>,
+    /// File the mirror keeps its position in, so a restarted node resumes there
+    pub position_file: Option<PathBuf>,
+}
+
+impl MirrorConfig {
+    pub fn to_remote(&self) -> RemoteCluster {
+        let remote = RemoteCluster::new(self.addrs.iter().cloned());
+        let remote = match &self.topic {
+            Some(topic) => remote.with_topic(topic),
+            None => remote,
+        };
+        match &self.token {
+            Some(token) => remote.with_token(token),
+            None => remote,
+        }
+    }
+}
+
+/// Redis Streams bridges (need the `redis` feature): topic to stream name
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct RedisConfig {
+    /// `host:port` of the Redis server
+    pub addr: String,
+    pub password: Option<String>,
+    /// Topics whose enqueues on this node are appended to a stream
+    #[serde(default)]
+    pub mirror: HashMap<String, String>,
+    /// Topics new entries of a stream are enqueued onto
+    #[serde(default)]
+    pub ingest: HashMap<String, String>,
+}
+
+/// NATS connectors (need the `nats` feature): topic to subject
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct NatsConfig {
+    /// `host:port` of the NATS server
+    pub addr: String,
+    pub token: Option<String>,
+    /// Topics whose events on this node are published to `<subject>.<op>`
+    #[serde(default)]
+    pub publish: HashMap<String, String>,
+    /// Topics the messages of a subject are enqueued onto
+    #[serde(default)]
+    pub subscribe: HashMap<String, String>,
+    /// Queue group the subscriptions join, so nodes share messages instead of each enqueuing them all
+    pub queue_group: Option<String>,
+}
+
+/// MQTT ingest bridges (need the `mqtt` feature): topic to MQTT topic filter
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct MqttConfig {
+    /// `host:port` of the MQTT broker
+    pub addr: String,
+    /// Prefix of the bridges' client IDs, the node ID if absent
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// QoS of the subscriptions: 0 at most once, 1 at least once, 2 exactly once
+    #[serde(default = "default_mqtt_qos")]
+    pub qos: u8,
+    /// Topics the messages matching a filter are enqueued onto
+    #[serde(default)]
+    pub subscribe: HashMap<String, String>,
+}
+
+fn default_mqtt_qos() -> u8 {
+    1
+}
+
+/// Consistent-hash placement of queue ownership over this node and its peers
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct PlacementConfig {
+    /// Ring points per node, `DEFAULT_VNODES` if absent
+    pub vnodes: Option<u32>,
+    /// Work stealing between idle nodes and owners of deep queues, disabled if absent
+    pub steal: Option<StealConfig>,
+}
+
+/// Work stealing limits; defaults from `StealPolicy`
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct StealConfig {
+    /// Shallowest queue worth stealing from
+    pub min_depth: Option<usize>,
+    /// Most items taken per request
+    pub batch: Option<usize>,
+}
+
+impl StealConfig {
+    pub fn to_policy(&self) -> StealPolicy {
+        let default = StealPolicy::default();
+        StealPolicy { min_depth: self.min_depth.unwrap_or(default.min_depth), batch: self.batch.unwrap_or(default.batch) }
+    }
+}
+
+/// Backpressure marks; defaults from `BackpressurePolicy`
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct BackpressureConfig {
+    /// Load (fill of the queue or causal buffer) at which peers are asked to hold back
+    pub high: Option<f64>,
+    /// Load at which they may send again
+    pub low: Option<f64>,
+    /// Most events held back per peer and topic
+    pub max_held: Option<usize>,
+}
+
+impl BackpressureConfig {
+    pub fn to_policy(&self) -> BackpressurePolicy {
+        let default = BackpressurePolicy::default();
+        BackpressurePolicy {
+            high: self.high.unwrap_or(default.high),
+            low: self.low.unwrap_or(default.low),
+            max_held: self.max_held.unwrap_or(default.max_held),
+        }
+    }
+}
+
+/// Probe thresholds; defaults from `HealthPolicy`
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct HealthConfig {
+    /// Load of a topic's queue or causal buffer past which the node isn't ready
+    pub max_load: Option<f64>,
+    /// Events a topic may be behind peers before the node isn't ready
+    pub max_lag: Option<u64>,
+    /// How long the receive loop may go without turning before the node isn't healthy
+    pub stall_timeout_ms: Option<u64>,
+    /// Peers not heard from for longer are unreachable; three heartbeats if absent
+    pub peer_timeout_ms: Option<u64>,
+}
+
+impl HealthConfig {
+    /// Policy probing `storage_dir`, if any
+    pub fn to_policy(&self, storage_dir: Option<PathBuf>) -> HealthPolicy {
+        let default = HealthPolicy::default();
+        HealthPolicy {
+            max_load: self.max_load.unwrap_or(default.max_load),
+            max_lag: self.max_lag.unwrap_or(default.max_lag),
+            stall_timeout: self.stall_timeout_ms.map_or(default.stall_timeout, Duration::from_millis),
+            peer_timeout: self.peer_timeout_ms.map(Duration::from_millis),
+            storage_dir,
+        }
+    }
+}
+
+/// Topic grant for one identity (or `*` for everyone)
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct GrantConfig {
+    pub identity: String,
+    /// Topic name, `*`, or a `prefix*` pattern
+    pub topics: String,
+    pub allow: Vec<Permission>,
+}
+
+/// Client access control
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct AclConfig {
+    /// Token to identity
+    #[serde(default)]
+    pub tokens: HashMap<String, String>,
+    #[serde(default)]
+    pub grants: Vec<GrantConfig>,
+    /// NDJSON file for refused operations, in-memory only if absent; the top-level `audit_log` takes precedence
+    pub audit_log: Option<PathBuf>,
+}
+
+impl AclConfig {
+    pub fn to_acl(&self) -> Acl {
+        let acl = self.tokens.iter().fold(Acl::new(), |acl, (token, id)| acl.token(token, id));
+        self.grants.iter().fold(acl, |acl, g| acl.grant(&g.identity, &g.topics, &g.allow))
+    }
+}
+
+/// Node server configuration
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ServerConfig {
+    pub node_id: String,
+    /// Transport listen address for peer traffic
+    pub listen: SocketAddr,
+    /// HTTP API listen address, disabled if absent
+    pub http: Option<SocketAddr>,
+    /// SQS-compatible API listen address (needs the `sqs` feature), disabled if absent
+    pub sqs: Option<SocketAddr>,
+    /// Directory for per-topic logs, disabled if absent
+    pub storage_dir: Option<PathBuf>,
+    /// Format of the topic logs: ndjson (default), msgpack or cbor (need the features of the same name)
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// TLS for peer traffic (needs the `tls` feature), plaintext if absent
+    pub tls: Option<TlsFiles>,
+    /// HMAC event signing (needs the `auth` feature), disabled if absent
+    pub auth: Option<AuthConfig>,
+    /// Per-topic client permissions, everything allowed if absent
+    pub acl: Option<AclConfig>,
+    /// NDJSON audit trail of administrative actions and refused operations, in-memory only if absent
+    pub audit_log: Option<PathBuf>,
+    /// Item encryption for topic logs (needs the `encrypt` feature), plaintext if absent
+    pub encryption: Option<EncryptionConfig>,
+    /// zstd for large frames to peers that support it (needs the `compress` feature), disabled if absent
+    pub compression: Option<CompressionConfig>,
+    /// Longest line sent to or accepted from peers, longer envelopes are chunked
+    pub max_frame_len: Option<usize>,
+    /// Interval of heartbeats to peers, for failure detection and catching peers up; none if absent
+    pub heartbeat_ms: Option<u64>,
+    /// Send events to peers over ordered, acknowledged channels, retransmitting after this
+    /// many ms without an ack; plain sends if absent. Use on every node or none
+    pub retransmit_ms: Option<u64>,
+    /// Most out-of-order events buffered per topic, unbounded if absent
+    pub max_buffered_events: Option<usize>,
+    /// Most bytes of items per topic accepted by local enqueues, measured serialized; unbounded if absent
+    pub max_queue_bytes: Option<usize>,
+    /// Worker number in the node's event and log IDs (0-1023), unique per node; derived from `node_id` if absent
+    pub id_worker: Option<u16>,
+    /// Ask peers to hold back events for topics whose buffer fills up, disabled if absent
+    pub backpressure: Option<BackpressureConfig>,
+    /// Thresholds of the `/healthz` and `/readyz` probes, defaults if absent
+    pub health: Option<HealthConfig>,
+    /// Per-origin quotas; should match on every node
+    pub quotas: Option<QuotaConfig>,
+    /// Dequeue order: fifo (default) or round_robin between origin nodes; must match on every node
+    #[serde(default)]
+    pub dequeue_policy: DequeuePolicy,
+    /// Retry failed deliveries, failures are only logged if absent
+    pub retry: Option<RetryConfig>,
+    /// Enqueue rate limits, unlimited if absent
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Log retention of every topic, everything is kept if absent
+    pub retention: Option<RetentionConfig>,
+    /// Dequeue only queues this node owns on the placement ring, every node serves every queue if absent
+    pub placement: Option<PlacementConfig>,
+    /// Partition count per partitioned topic; must match on every node
+    #[serde(default)]
+    pub partitions: HashMap<String, u32>,
+    /// Topic and item limits per namespace (`namespace/topic`); should match on every node
+    #[serde(default)]
+    pub namespaces: HashMap<String, NamespaceQuota>,
+    /// Webhook per topic its dequeued items are posted to
+    #[serde(default)]
+    pub webhooks: HashMap<String, WebhookConfig>,
+    /// Other cluster per topic the enqueues made on this node are mirrored to
+    #[serde(default)]
+    pub mirrors: HashMap<String, MirrorConfig>,
+    /// Redis Streams bridges, disabled if absent
+    pub redis: Option<RedisConfig>,
+    /// NATS connectors, disabled if absent
+    pub nats: Option<NatsConfig>,
+    /// MQTT ingest bridges, disabled if absent
+    pub mqtt: Option<MqttConfig>,
+    #[serde(default)]
+    pub peers: Vec<PeerConfig>,
+}
+
+impl ServerConfig {
+    /// Parse a TOML document
+    pub fn from_toml(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    /// Read and parse a TOML file
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Self::from_toml(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Audit trail file, from the top level or the `[acl]` section
+    pub fn audit_log(&self) -> Option<&Path> {
+        self.audit_log.as_deref().or_else(|| self.acl.as_ref()?.audit_log.as_deref())
+    }
+
+    /// Peer IDs, for seeding vector clocks
+    pub fn peer_ids(&self) -> Vec<&str> {
+        self.peers.iter().map(|p| p.id.as_str()).collect()
+    }
+
+    /// Whether `new` changes settings that `reload` leaves alone, which take a restart
+    pub fn needs_restart(&self, new: &ServerConfig) -> bool {
+        let reloaded = ServerConfig {
+            max_buffered_events: self.max_buffered_events,
+            rate_limit: self.rate_limit.clone(),
+            retention: self.retention.clone(),
+            peers: self.peers.clone(),
+            ..new.clone()
+        };
+        reloaded != *self
+    }
+
+    /// Apply the buffer limit, rate limits, retention and peers of `new` that differ from
+    /// this configuration to the topics of `node` and to `transport`; returns what changed
+    /// New peers are added and announced as members, removed ones announced and dropped.
+    /// Topics created from now on should be configured from `new` too.
+    pub fn reload<T>(&self, new: &ServerConfig, node: &Node<T>, transport: &TcpTransport<T>) -> Vec<String>
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned + Send + Sync + 'static,
+    {
+        let mut changes = Vec::new();
+        let topics: Vec<_> = node.topic_names().iter().filter_map(|name| node.get_topic(name)).collect();
+        if new.max_buffered_events != self.max_buffered_events {
+            topics.iter().for_each(|queue| queue.set_max_buffered_events(new.max_buffered_events));
+            changes.push(format!("max_buffered_events = {:?}", new.max_buffered_events));
+        }
+        if new.rate_limit != self.rate_limit {
+            let limits = new.rate_limit.clone().unwrap_or_default();
+            for queue in &topics {
+                queue.set_rate_limit(limits.node.map(|limit| (limit, limits.throttle())));
+                queue.set_client_rate_limit(limits.client.map(|limit| (limit, limits.throttle())));
+            }
+            changes.push(format!("rate_limit = {:?}", new.rate_limit));
+        }
+        if new.retention != self.retention {
+            topics.iter().for_each(|queue| queue.set_retention(new.retention.as_ref().map(RetentionConfig::to_policy)));
+            changes.push(format!("retention = {:?}", new.retention));
+        }
+        for peer in &new.peers {
+            let known = match self.peers.iter().find(|old| old.id == peer.id) {
+                Some(old) if old.addr == peer.addr => continue,
+                Some(_) => true,
+                None => false,
+            };
+            transport.add_peer(&peer.id, peer.addr);
+            if known {
+                changes.push(format!("peer {} moved to {}", peer.id, peer.addr));
+            } else {
+                node.announce_membership(&peer.id, true);
+                changes.push(format!("peer {} added at {}", peer.id, peer.addr));
+            }
+        }
+        for old in self.peers.iter().filter(|old| !new.peers.iter().any(|peer| peer.id == old.id)) {
+            node.announce_membership(&old.id, false);
+            transport.remove_peer(&old.id);
+            changes.push(format!("peer {} removed", old.id));
+        }
+        changes
+    }
+}
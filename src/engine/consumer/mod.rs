@@ -0,0 +1,222 @@
+//! Consuming a topic as a sequence of deliveries
+//! `Node::subscribe` blocks between items, `Node::subscribe_stream` (with the `stream`
+//! feature) is an async `Stream` woken as items land; both hand out what `receive_wait`
+//! would, so placement and work stealing apply. `Node::register_consumer` instead runs a
+//! pool of workers handing each delivery to a handler, acking or failing it by the result.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use crate::core::buildcore::panic_message;
+use crate::engine::node::Node;
+use crate::engine::worker::{ErrorLog, WorkerErrors};
+#[cfg(feature = "stream")]
+use std::pin::Pin;
+#[cfg(feature = "stream")]
+use std::task::{Context, Poll};
+
+/// How long one wait of an unbounded subscription lasts before waiting again
+const WAIT_SLICE: Duration = Duration::from_secs(3600);
+
+/// Counts a consumer of a topic for as long as it lives, see `Node::consumers`
+struct Counted {
+    counts: Arc<Mutex<HashMap<String, usize>>>,
+    topic: String,
+}
+
+impl Counted {
+    fn new<T: Send + Sync + 'static>(node: &Node<T>, topic: &str) -> Self {
+        let counts = node.consumer_counts().clone();
+        *counts.lock().unwrap().entry(topic.to_string()).or_insert(0) += 1;
+        Self { counts, topic: topic.to_string() }
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap();
+        if let Some(count) = counts.get_mut(&self.topic) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.topic);
+            }
+        }
+    }
+}
+
+/// An item handed out to a consumer
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delivery<T> {
+    pub topic: String,
+    /// Report a processing failure with `Node::fail(topic, delivery)`
+    pub delivery: u64,
+    pub item: Arc<T>,
+    /// Which delivery of the item this is, 1 for the first; a retried item's count goes
+    /// on across nodes, and it is dead-lettered once past the retry policy's attempts
+    pub attempt: u32,
+}
+
+impl<T: Send + Sync + 'static> Delivery<T> {
+    fn new(node: &Node<T>, topic: &str, delivery: u64, item: Arc<T>) -> Self {
+        // Untracked deliveries can't fail, so they are never redeliveries
+        let attempt = node.delivery_attempt(topic, delivery).unwrap_or(1);
+        Self { topic: topic.to_string(), delivery, item, attempt }
+    }
+}
+
+/// Blocking iterator over the deliveries of a topic, see `Node::subscribe`
+pub struct Subscription<'a, T> {
+    node: &'a Node<T>,
+    topic: String,
+    idle_timeout: Option<Duration>,
+    _counted: Counted,
+}
+
+impl<'a, T: Send + Sync + 'static> Subscription<'a, T> {
+    pub(crate) fn new(node: &'a Node<T>, topic: &str) -> Self {
+        Self { node, topic: topic.to_string(), idle_timeout: None, _counted: Counted::new(node, topic) }
+    }
+
+    /// End the iteration once no item arrived for `timeout`, instead of waiting forever
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+}
+
+impl<T: Send + Sync + 'static> Iterator for Subscription<'_, T> {
+    type Item = Delivery<T>;
+
+    fn next(&mut self) -> Option<Delivery<T>> {
+        loop {
+            let wait = self.idle_timeout.unwrap_or(WAIT_SLICE);
+            if let Some((delivery, item)) = self.node.receive_wait(&self.topic, wait) {
+                return Some(Delivery::new(self.node, &self.topic, delivery, item));
+            }
+            if self.idle_timeout.is_some() {
+                return None;
+            }
+        }
+    }
+}
+
+/// Async stream of the deliveries of a topic, see `Node::subscribe_stream`
+/// Never ends; drop it to unsubscribe
+#[cfg(feature = "stream")]
+pub struct DeliveryStream<T> {
+    node: Arc<Node<T>>,
+    topic: String,
+    _counted: Counted,
+}
+
+#[cfg(feature = "stream")]
+impl<T: Send + Sync + 'static> DeliveryStream<T> {
+    pub(crate) fn new(node: Arc<Node<T>>, topic: &str) -> Self {
+        let counted = Counted::new(&node, topic);
+        Self { node, topic: topic.to_string(), _counted: counted }
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<T: Send + Sync + 'static> futures_core::Stream for DeliveryStream<T> {
+    type Item = Delivery<T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Delivery<T>>> {
+        // Registered before looking, so an item landing in between still wakes the task
+        self.node.arrivals().register(cx.waker());
+        if self.node.has_arrivals(&self.topic)
+            && let Some((delivery, item)) = self.node.next_delivery(&self.topic)
+        {
+            return Poll::Ready(Some(Delivery::new(&self.node, &self.topic, delivery, item)));
+        }
+        Poll::Pending
+    }
+}
+
+/// How long a worker waits for an item before checking whether it should stop
+const WORKER_WAIT: Duration = Duration::from_millis(100);
+
+/// Worker threads handing a topic's deliveries to a handler, see `Node::register_consumer`
+pub struct ConsumerPool {
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+    errors: ErrorLog,
+}
+
+impl ConsumerPool {
+    /// Run `concurrency` workers (at least one), each taking the next delivery of `topic`
+    /// and acking it if `handler` returns Ok, failing it if it returns Err or panics
+    pub(crate) fn start<T, E, F>(node: Arc<Node<T>>, topic: &str, handler: F, concurrency: usize) -> Self
+    where
+        T: Send + Sync + 'static,
+        E: Display,
+        F: Fn(Delivery<T>) -> Result<(), E> + Send + Sync + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let errors = ErrorLog::default();
+        let handler = Arc::new(handler);
+        let threads = (0..concurrency.max(1))
+            .map(|_| {
+                let (node, stop, handler, topic, errors) = (node.clone(), stop.clone(), handler.clone(), topic.to_string(), errors.clone());
+                let counted = Counted::new(&node, &topic);
+                thread::spawn(move || {
+                    let _counted = counted;
+                    while !stop.load(Ordering::SeqCst) {
+                        node.process_retries();
+                        if let Some((delivery, item)) = node.receive_wait(&topic, WORKER_WAIT) {
+                            consume(&node, &topic, &*handler, Delivery::new(&node, &topic, delivery, item), &errors);
+                        }
+                    }
+                })
+            })
+            .collect();
+        Self { stop, threads, errors }
+    }
+
+    /// Errors the handler ran into, see `WorkerErrors`
+    pub fn errors(&self) -> WorkerErrors {
+        self.errors.snapshot()
+    }
+
+    /// Stop taking deliveries and wait for the ones being handled
+    pub fn shutdown(mut self) {
+        self.stop_and_join();
+    }
+
+    fn stop_and_join(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            thread.join().ok();
+        }
+    }
+}
+
+impl Drop for ConsumerPool {
+    fn drop(&mut self) {
+        self.stop_and_join();
+    }
+}
+
+/// Hand one delivery to `handler`, then ack or fail it
+fn consume<T, E: Display>(node: &Node<T>, topic: &str, handler: &dyn Fn(Delivery<T>) -> Result<(), E>, delivery: Delivery<T>, errors: &ErrorLog)
+where
+    T: Send + Sync + 'static,
+{
+    let id = delivery.delivery;
+    let error = match panic::catch_unwind(AssertUnwindSafe(|| handler(delivery))) {
+        Ok(Ok(())) => {
+            node.ack(topic, id);
+            return;
+        }
+        Ok(Err(e)) => e.to_string(),
+        Err(panic) => format!("handler panicked: {}", panic_message(&*panic)),
+    };
+    errors.record(format_args!("delivery {} of {} failed: {}", id, topic, error));
+    if let Err(e) = node.fail(topic, id) {
+        errors.record(format_args!("delivery {} of {} is not retried: {}", id, topic, e));
+    }
+}
@@ -0,0 +1,189 @@
+//! Live terminal dashboard over one or more nodes (`queuetop <node-addr>...`)
+//! Polls each node through the client protocol and shows per-topic depth,
+//! clocks, buffered events and the most recent log entries.
+
+use std::io;
+use std::time::{Duration, Instant};
+use ratatui::Frame;
+use ratatui::crossterm::event::{self, Event as TermEvent, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, Paragraph, Row, Table};
+use serde_json::Value;
+use crate::core::buildcore::{LogEntry, QueueStats};
+use crate::engine::client::QueueClient;
+
+/// Log entries kept per node
+const DEFAULT_LOG_LINES: usize = 20;
+
+/// Stats of one topic on a node
+pub struct TopicStatus {
+    pub topic: String,
+    pub stats: QueueStats,
+}
+
+/// What the dashboard last saw of a node
+pub struct NodeStatus {
+    pub addr: String,
+    pub topics: Vec<TopicStatus>,
+    pub recent: Vec<(String, LogEntry<Value>)>, // (topic, entry), newest first
+    pub error: Option<String>,
+}
+
+/// Polls a set of nodes; connections are (re)opened on demand
+pub struct Dashboard {
+    nodes: Vec<(String, Option<QueueClient<Value>>)>,
+    log_lines: usize,
+    token: Option<String>,
+}
+
+impl Dashboard {
+    /// Watch the nodes at the given peer addresses
+    pub fn new(addrs: &[String]) -> Self {
+        Self { nodes: addrs.iter().map(|a| (a.clone(), None)).collect(), log_lines: DEFAULT_LOG_LINES, token: None }
+    }
+
+    /// Number of recent log entries shown per node
+    pub fn with_log_lines(mut self, log_lines: usize) -> Self {
+        self.log_lines = log_lines;
+        self
+    }
+
+    /// Present `token` to each node's access control
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Poll every node once
+    pub fn refresh(&mut self) -> Vec<NodeStatus> {
+        let log_lines = self.log_lines;
+        let token = self.token.clone();
+        self.nodes
+            .iter_mut()
+            .map(|(addr, client)| {
+                let mut status = NodeStatus { addr: addr.clone(), topics: Vec::new(), recent: Vec::new(), error: None };
+                if client.is_none() {
+                    match QueueClient::connect(addr.as_str()) {
+                        Ok(c) => *client = Some(match &token {
+                            Some(token) => c.with_token(token),
+                            None => c,
+                        }),
+                        Err(e) => {
+                            status.error = Some(e.to_string());
+                            return status;
+                        }
+                    }
+                }
+                if let Err(e) = poll_node(client.as_ref().unwrap(), log_lines, &mut status) {
+                    // Reconnect on the next refresh
+                    *client = None;
+                    status.error = Some(e.to_string());
+                }
+                status
+            })
+            .collect()
+    }
+
+    /// Take over the terminal and redraw every `interval` until `q` or Esc is pressed
+    pub fn run(&mut self, interval: Duration) -> io::Result<()> {
+        let mut terminal = ratatui::init();
+        let result = (|| {
+            let mut nodes = self.refresh();
+            let mut last = Instant::now();
+            loop {
+                terminal.draw(|frame| draw(frame, &nodes))?;
+                let timeout = interval.saturating_sub(last.elapsed());
+                if event::poll(timeout)?
+                    && let TermEvent::Key(key) = event::read()?
+                    && key.kind == KeyEventKind::Press
+                    && matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+                {
+                    return Ok(());
+                }
+                if last.elapsed() >= interval {
+                    nodes = self.refresh();
+                    last = Instant::now();
+                }
+            }
+        })();
+        ratatui::restore();
+        result
+    }
+}
+
+fn poll_node(client: &QueueClient<Value>, log_lines: usize, status: &mut NodeStatus) -> io::Result<()> {
+    for topic in client.topics()? {
+        let stats = client.stats(&topic)?;
+        for entry in client.log_tail(&topic, log_lines)? {
+            status.recent.push((topic.clone(), entry));
+        }
+        status.topics.push(TopicStatus { topic, stats });
+    }
+    // Log IDs are assigned in order on each node, so they interleave topics correctly
+    status.recent.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.local_log_id));
+    status.recent.truncate(log_lines);
+    Ok(())
+}
+
+fn format_clock(stats: &QueueStats) -> String {
+    let mut clock: Vec<_> = stats.clock.iter().collect();
+    clock.sort();
+    let parts: Vec<String> = clock.iter().map(|(node, time)| format!("{}:{}", node, time)).collect();
+    parts.join(" ")
+}
+
+fn draw(frame: &mut Frame, nodes: &[NodeStatus]) {
+    let [header, queues, logs] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Percentage(50),
+        Constraint::Fill(1),
+    ])
+    .areas(frame.area());
+
+    frame.render_widget(Paragraph::new(format!("queuetop: {} node(s), q to quit", nodes.len())), header);
+
+    let mut rows = Vec::new();
+    for node in nodes {
+        if let Some(error) = &node.error {
+            rows.push(Row::new(vec![node.addr.clone(), "-".into(), "-".into(), "-".into(), "-".into(), format!("unreachable: {}", error)]));
+            continue;
+        }
+        for topic in &node.topics {
+            rows.push(Row::new(vec![
+                format!("{} ({})", topic.stats.node_id, node.addr),
+                topic.topic.clone(),
+                topic.stats.depth.to_string(),
+                topic.stats.pending_events.to_string(),
+                topic.stats.log_entries.to_string(),
+                format_clock(&topic.stats),
+            ]));
+        }
+    }
+    let widths = [
+        Constraint::Length(28),
+        Constraint::Length(16),
+        Constraint::Length(8),
+        Constraint::Length(8),
+        Constraint::Length(8),
+        Constraint::Fill(1),
+    ];
+    let table = Table::new(rows, widths)
+        .header(Row::new(["node", "topic", "depth", "pending", "log", "clock"]).style(Style::new().add_modifier(Modifier::BOLD)))
+        .block(Block::bordered().title("queues"));
+    frame.render_widget(table, queues);
+
+    let lines: Vec<Line> = nodes
+        .iter()
+        .flat_map(|node| node.recent.iter().map(move |(topic, e)| (node, topic, e)))
+        .map(|(node, topic, e)| {
+            let item = e.item().map_or("-".to_string(), |i| i.to_string());
+            Line::from(format!(
+                "{} {} #{} {} {} {:?} {}",
+                node.addr, topic, e.local_log_id, e.event.origin_node, e.op, e.state, item
+            ))
+        })
+        .collect();
+    frame.render_widget(List::new(lines).block(Block::bordered().title("recent log entries")), logs);
+}
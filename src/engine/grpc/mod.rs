@@ -0,0 +1,381 @@
+//! gRPC service exposing a node's queue, plus the generated client
+//! Items travel as raw bytes so non-Rust processes can produce and consume
+//! With access control, see `QueueGrpcService::with_acl`, callers authenticate with an
+//! `authorization: Bearer <token>` metadata entry and refused calls get PERMISSION_DENIED.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status};
+use crate::core::buildcore::{ControlOp, DistributedQueueSystem, Event, EventOp, KeySequence, LogEntry, NodeId, State, TraceContext};
+use crate::engine::acl::{Acl, Permission};
+
+/// Generated protobuf messages and service stubs
+pub mod proto {
+    tonic::include_proto!("dqm.v1");
+}
+
+use proto::queue_service_server::{QueueService, QueueServiceServer};
+pub use proto::queue_service_client::QueueServiceClient;
+
+/// Queue system carrying byte payloads, as served over gRPC
+pub type ByteQueue = DistributedQueueSystem<Vec<u8>>;
+
+fn clock_to_proto(clock: &HashMap<NodeId, u64>) -> HashMap<String, u64> {
+    clock.iter().map(|(k, v)| (k.to_string(), *v)).collect()
+}
+
+/// Node a received name refers to, see `NodeId::try_intern`
+fn node_id(name: &str) -> Result<NodeId, Status> {
+    NodeId::try_intern(name).ok_or_else(|| Status::invalid_argument(format!("node name {:?} is too long or too many nodes are known", name)))
+}
+
+fn clock_from_proto(clock: HashMap<String, u64>) -> Result<HashMap<NodeId, u64>, Status> {
+    clock.into_iter().map(|(k, v)| Ok((node_id(&k)?, v))).collect()
+}
+
+fn state_to_proto(state: State) -> proto::State {
+    match state {
+        State::Pending => proto::State::Pending,
+        State::Committed => proto::State::Committed,
+        State::Delivered => proto::State::Delivered,
+        State::Failed => proto::State::Failed,
+        State::DeadLettered => proto::State::DeadLettered,
+    }
+}
+
+fn state_from_proto(state: i32) -> Result<State, Status> {
+    match proto::State::try_from(state) {
+        Ok(proto::State::Pending) => Ok(State::Pending),
+        Ok(proto::State::Committed) => Ok(State::Committed),
+        Ok(proto::State::Delivered) => Ok(State::Delivered),
+        Ok(proto::State::Failed) => Ok(State::Failed),
+        Ok(proto::State::DeadLettered) => Ok(State::DeadLettered),
+        Err(_) => Err(Status::invalid_argument(format!("unknown state {}", state))),
+    }
+}
+
+fn control_to_proto(op: &ControlOp) -> (proto::ControlKind, String, String) {
+    match op {
+        ControlOp::MemberAdded { member } => (proto::ControlKind::MemberAdded, member.to_string(), String::new()),
+        ControlOp::MemberRemoved { member } => (proto::ControlKind::MemberRemoved, member.to_string(), String::new()),
+        // The config travels as JSON in the value, empty without one
+        ControlOp::TopicCreated { topic, config } => {
+            let config = config.as_ref().map_or_else(String::new, |config| serde_json::to_string(config).unwrap_or_default());
+            (proto::ControlKind::TopicCreated, topic.clone(), config)
+        }
+        ControlOp::TopicDeleted { topic } => (proto::ControlKind::TopicDeleted, topic.clone(), String::new()),
+        // Versioned configs travel as a JSON [version, config] pair
+        ControlOp::TopicConfigured { topic, config, version } => {
+            (proto::ControlKind::TopicConfigured, topic.clone(), serde_json::to_string(&(version, config)).unwrap_or_default())
+        }
+        ControlOp::Paused => (proto::ControlKind::Paused, String::new(), String::new()),
+        ControlOp::Resumed => (proto::ControlKind::Resumed, String::new(), String::new()),
+        // Lease terms travel in the value, with the expiry as a JSON [term, expires_ms] pair
+        ControlOp::LeaseGranted { holder, term, expires_ms } => {
+            (proto::ControlKind::LeaseGranted, holder.to_string(), serde_json::to_string(&(term, expires_ms)).unwrap_or_default())
+        }
+        ControlOp::LeaseAcked { holder, term } => (proto::ControlKind::LeaseAcked, holder.to_string(), term.to_string()),
+        ControlOp::TokenPassed { to, round } => (proto::ControlKind::TokenPassed, to.to_string(), round.to_string()),
+        ControlOp::ConfigUpdated { key, value } => (proto::ControlKind::ConfigUpdated, key.clone(), value.clone()),
+    }
+}
+
+fn control_from_proto(kind: i32, subject: String, value: String) -> Result<ControlOp, Status> {
+    let kind = proto::ControlKind::try_from(kind).map_err(|_| Status::invalid_argument(format!("unknown control action {}", kind)))?;
+    if subject.is_empty() && !matches!(kind, proto::ControlKind::Paused | proto::ControlKind::Resumed) {
+        return Err(Status::invalid_argument("control event has no subject"));
+    }
+    Ok(match kind {
+        proto::ControlKind::MemberAdded => ControlOp::MemberAdded { member: node_id(&subject)? },
+        proto::ControlKind::MemberRemoved => ControlOp::MemberRemoved { member: node_id(&subject)? },
+        proto::ControlKind::TopicCreated => {
+            let config = match value.as_str() {
+                "" => None,
+                json => Some(serde_json::from_str(json).map_err(|e| Status::invalid_argument(format!("invalid topic config: {}", e)))?),
+            };
+            ControlOp::TopicCreated { topic: subject, config }
+        }
+        proto::ControlKind::TopicDeleted => ControlOp::TopicDeleted { topic: subject },
+        proto::ControlKind::TopicConfigured => {
+            let (version, config) = serde_json::from_str(&value).map_err(|e| Status::invalid_argument(format!("invalid topic config: {}", e)))?;
+            ControlOp::TopicConfigured { topic: subject, config, version }
+        }
+        proto::ControlKind::Paused => ControlOp::Paused,
+        proto::ControlKind::Resumed => ControlOp::Resumed,
+        proto::ControlKind::LeaseGranted => {
+            let (term, expires_ms) = serde_json::from_str(&value).map_err(|e| Status::invalid_argument(format!("invalid lease: {}", e)))?;
+            ControlOp::LeaseGranted { holder: node_id(&subject)?, term, expires_ms }
+        }
+        proto::ControlKind::LeaseAcked => {
+            let term = value.parse().map_err(|e| Status::invalid_argument(format!("invalid lease term: {}", e)))?;
+            ControlOp::LeaseAcked { holder: node_id(&subject)?, term }
+        }
+        proto::ControlKind::TokenPassed => {
+            let round = value.parse().map_err(|e| Status::invalid_argument(format!("invalid token round: {}", e)))?;
+            ControlOp::TokenPassed { to: node_id(&subject)?, round }
+        }
+        proto::ControlKind::ConfigUpdated => ControlOp::ConfigUpdated { key: subject, value },
+    })
+}
+
+impl From<&Event<Vec<u8>>> for proto::Event {
+    fn from(event: &Event<Vec<u8>>) -> Self {
+        let op = match event.op {
+            EventOp::Enqueue => proto::Op::Enqueue,
+            EventOp::Dequeue => proto::Op::Dequeue,
+            EventOp::Transfer { .. } => proto::Op::Transfer,
+            EventOp::Reject { .. } => proto::Op::Reject,
+            EventOp::Fail { .. } => proto::Op::Fail,
+            EventOp::Retry { .. } => proto::Op::Retry,
+            EventOp::StateUpdate { .. } => proto::Op::StateUpdate,
+            EventOp::Purge { .. } => proto::Op::Purge,
+            EventOp::Control { .. } => proto::Op::Control,
+            EventOp::Take { .. } => proto::Op::Take,
+        EventOp::Ack { .. } => proto::Op::Ack,
+        };
+        let transfer_to = match event.op {
+            EventOp::Transfer { to } => to.to_string(),
+            _ => String::new(),
+        };
+        let (rejected_event, rejected_origin) = match event.op {
+            EventOp::Reject { origin, event_id } => (event_id, origin.to_string()),
+            _ => (0, String::new()),
+        };
+        let (delivery, attempt, dead_letter) = match event.op {
+            EventOp::Fail { delivery, attempt, dead_letter } => (delivery, attempt, dead_letter),
+            EventOp::Retry { attempt } => (0, attempt, false),
+            EventOp::Dequeue | EventOp::Take { .. } => (0, event.delivery_attempt, false),
+            _ => (0, 0, false),
+        };
+        let (updated_event, updated_origin, updated_state) = match event.op {
+            EventOp::StateUpdate { origin, event_id, state } => (event_id, origin.to_string(), state_to_proto(state)),
+            _ => (0, String::new(), proto::State::Pending),
+        };
+        let (taken_event, taken_origin) = match event.op {
+            EventOp::Take { origin, event_id } => (event_id, origin.to_string()),
+            _ => (0, String::new()),
+        };
+        let acked = match &event.op {
+            EventOp::Ack { deliveries } => deliveries.clone(),
+            _ => Vec::new(),
+        };
+        let purged_key = match &event.op {
+            EventOp::Purge { key } => key.clone(),
+            _ => String::new(),
+        };
+        let (control, control_subject, control_value) = match &event.op {
+            EventOp::Control { op } => control_to_proto(op),
+            _ => (proto::ControlKind::MemberAdded, String::new(), String::new()),
+        };
+        proto::Event {
+            global_id: event.global_id,
+            origin_node: event.origin_node.to_string(),
+            op: op as i32,
+            item: event.item.as_ref().map(|i| i.to_vec()),
+            clock: clock_to_proto(&event.clock),
+            timestamp_ms: event.timestamp_ms,
+            transfer_to,
+            rejected_event,
+            rejected_origin,
+            delivery,
+            attempt,
+            dead_letter,
+            updated_event,
+            updated_origin,
+            updated_state: updated_state as i32,
+            traceparent: event.trace.map(|trace| trace.to_string()).unwrap_or_default(),
+            purged_key,
+            control: control as i32,
+            control_subject,
+            control_value,
+            taken_event,
+            taken_origin,
+            sequence_key: event.sequence.as_ref().map(|sequence| sequence.key.clone()).unwrap_or_default(),
+            sequence: event.sequence.as_ref().map_or(0, |sequence| sequence.seq),
+            epoch: event.epoch,
+            acked,
+        }
+    }
+}
+
+impl TryFrom<proto::Event> for Event<Vec<u8>> {
+    type Error = Status;
+
+    fn try_from(event: proto::Event) -> Result<Self, Status> {
+        let op = match proto::Op::try_from(event.op) {
+            Ok(proto::Op::Enqueue) => EventOp::Enqueue,
+            Ok(proto::Op::Dequeue) => EventOp::Dequeue,
+            Ok(proto::Op::Transfer) if !event.transfer_to.is_empty() => EventOp::Transfer { to: node_id(&event.transfer_to)? },
+            Ok(proto::Op::Transfer) => return Err(Status::invalid_argument("transfer event has no target node")),
+            Ok(proto::Op::Reject) if !event.rejected_origin.is_empty() => {
+                EventOp::Reject { origin: node_id(&event.rejected_origin)?, event_id: event.rejected_event }
+            }
+            Ok(proto::Op::Reject) => return Err(Status::invalid_argument("reject event has no rejected origin")),
+            Ok(proto::Op::Fail) => EventOp::Fail { delivery: event.delivery, attempt: event.attempt, dead_letter: event.dead_letter },
+            Ok(proto::Op::Retry) => EventOp::Retry { attempt: event.attempt },
+            Ok(proto::Op::StateUpdate) if !event.updated_origin.is_empty() => EventOp::StateUpdate {
+                origin: node_id(&event.updated_origin)?,
+                event_id: event.updated_event,
+                state: state_from_proto(event.updated_state)?,
+            },
+            Ok(proto::Op::StateUpdate) => return Err(Status::invalid_argument("state update has no updated origin")),
+            Ok(proto::Op::Purge) if !event.purged_key.is_empty() => EventOp::Purge { key: event.purged_key },
+            Ok(proto::Op::Purge) => return Err(Status::invalid_argument("purge event has no key")),
+            Ok(proto::Op::Control) => EventOp::Control { op: control_from_proto(event.control, event.control_subject, event.control_value)? },
+            Ok(proto::Op::Take) if !event.taken_origin.is_empty() => {
+                EventOp::Take { origin: node_id(&event.taken_origin)?, event_id: event.taken_event }
+            }
+            Ok(proto::Op::Take) => return Err(Status::invalid_argument("take event has no taken origin")),
+            Ok(proto::Op::Ack) if !event.acked.is_empty() => EventOp::Ack { deliveries: event.acked },
+            Ok(proto::Op::Ack) => return Err(Status::invalid_argument("ack event settles no deliveries")),
+            Err(_) => return Err(Status::invalid_argument(format!("unknown op {}", event.op))),
+        };
+        if event.origin_node.is_empty() {
+            return Err(Status::invalid_argument("event has no origin node"));
+        }
+        // The attempt field carries the delivery attempt on dequeues and takes
+        let delivery_attempt = if matches!(op, EventOp::Dequeue | EventOp::Take { .. }) { event.attempt } else { 0 };
+        Ok(Event {
+            global_id: event.global_id,
+            origin_node: node_id(&event.origin_node)?,
+            op,
+            item: event.item.map(Arc::new),
+            clock: clock_from_proto(event.clock)?,
+            timestamp_ms: event.timestamp_ms,
+            trace: match event.traceparent.as_str() {
+                "" => None,
+                traceparent => Some(TraceContext::parse(traceparent).ok_or_else(|| Status::invalid_argument("invalid traceparent"))?),
+            },
+            sequence: (!event.sequence_key.is_empty()).then_some(KeySequence { key: event.sequence_key, seq: event.sequence }),
+            epoch: event.epoch,
+            delivery_attempt,
+        })
+    }
+}
+
+impl From<&LogEntry<Vec<u8>>> for proto::LogEntry {
+    fn from(entry: &LogEntry<Vec<u8>>) -> Self {
+        let state = state_to_proto(entry.state);
+        proto::LogEntry {
+            local_log_id: entry.local_log_id,
+            local_node: entry.local_node.to_string(),
+            op: entry.op.clone(),
+            state: state as i32,
+            event: Some(entry.event.as_ref().into()),
+            logged_at_ms: entry.logged_at_ms,
+            redacted: entry.redacted,
+        }
+    }
+}
+
+/// gRPC front end for a single node
+pub struct QueueGrpcService {
+    node: Arc<ByteQueue>,
+    access: Option<(Acl, String)>,
+}
+
+impl QueueGrpcService {
+    pub fn new(node: Arc<ByteQueue>) -> Self {
+        Self { node, access: None }
+    }
+
+    /// Check calls against `acl` as operations on `topic`: enqueues need produce, reads and
+    /// dequeues consume, and applying a replicated event both; without one anyone may call
+    pub fn with_acl(mut self, acl: Acl, topic: impl Into<String>) -> Self {
+        self.access = Some((acl, topic.into()));
+        self
+    }
+
+    fn authorize<M>(&self, request: &Request<M>, permissions: &[Permission]) -> Result<(), Status> {
+        let Some((acl, topic)) = &self.access else {
+            return Ok(());
+        };
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim);
+        permissions
+            .iter()
+            .try_for_each(|&permission| acl.check(token, topic, permission))
+            .map_err(|denied| Status::permission_denied(denied.to_string()))
+    }
+
+    /// Wrap in the generated tonic server type
+    pub fn into_server(self) -> QueueServiceServer<Self> {
+        QueueServiceServer::new(self)
+    }
+}
+
+#[tonic::async_trait]
+impl QueueService for QueueGrpcService {
+    async fn enqueue(&self, request: Request<proto::EnqueueRequest>) -> Result<Response<proto::EnqueueResponse>, Status> {
+        self.authorize(&request, &[Permission::Produce])?;
+        let item = request.into_inner().item;
+        let event = self.node.try_enqueue(item).map_err(|e| Status::resource_exhausted(e.to_string()))?;
+        Ok(Response::new(proto::EnqueueResponse { event: Some((&event).into()) }))
+    }
+
+    async fn dequeue(&self, request: Request<proto::DequeueRequest>) -> Result<Response<proto::DequeueResponse>, Status> {
+        self.authorize(&request, &[Permission::Consume])?;
+        let (item, event) = self.node.dequeue();
+        Ok(Response::new(proto::DequeueResponse {
+            item: item.map(|i| i.to_vec()),
+            event: Some((&event).into()),
+        }))
+    }
+
+    async fn apply_event(&self, request: Request<proto::ApplyEventRequest>) -> Result<Response<proto::ApplyEventResponse>, Status> {
+        self.authorize(&request, &[Permission::Produce, Permission::Consume])?;
+        let event = request
+            .into_inner()
+            .event
+            .ok_or_else(|| Status::invalid_argument("missing event"))?;
+        let applied = self.node.apply_remote_event(event.try_into()?);
+        let outgoing = self.node.take_outgoing().iter().map(Into::into).collect();
+        Ok(Response::new(proto::ApplyEventResponse { applied, outgoing }))
+    }
+
+    async fn get_logs_since(&self, request: Request<proto::GetLogsSinceRequest>) -> Result<Response<proto::GetLogsSinceResponse>, Status> {
+        self.authorize(&request, &[Permission::Consume])?;
+        let clock = clock_from_proto(request.into_inner().clock)?;
+        let entries = self.node.logs_since(&clock).map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(proto::GetLogsSinceResponse {
+            entries: entries.iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn snapshot(&self, request: Request<proto::SnapshotRequest>) -> Result<Response<proto::SnapshotResponse>, Status> {
+        self.authorize(&request, &[Permission::Consume])?;
+        let snapshot = self.node.snapshot();
+        Ok(Response::new(proto::SnapshotResponse {
+            node_id: snapshot.node_id.to_string(),
+            clock: clock_to_proto(&snapshot.clock),
+            items: snapshot.items.iter().map(|i| i.to_vec()).collect(),
+            pending_events: snapshot.pending_events as u64,
+        }))
+    }
+}
+
+/// Serve a node on an already bound listener until `shutdown` resolves
+pub async fn serve_with_shutdown(
+    node: Arc<ByteQueue>,
+    incoming: TcpIncoming,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), tonic::transport::Error> {
+    tonic::transport::Server::builder()
+        .add_service(QueueGrpcService::new(node).into_server())
+        .serve_with_incoming_shutdown(incoming, shutdown)
+        .await
+}
+
+/// Serve a node on `addr` until the process exits
+pub async fn serve(node: Arc<ByteQueue>, addr: std::net::SocketAddr) -> Result<(), tonic::transport::Error> {
+    tonic::transport::Server::builder()
+        .add_service(QueueGrpcService::new(node).into_server())
+        .serve(addr)
+        .await
+}
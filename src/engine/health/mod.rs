@@ -0,0 +1,83 @@
+//! Liveness and readiness of a node, for orchestrators' probes
+//! `Node::health` tells whether the node works at all: its receive loop keeps turning
+//! and its storage takes writes; restart it otherwise. `Node::readiness` also tells
+//! whether it should get traffic: peers reachable, queues and causal buffers short of
+//! saturation, and caught up with what peers have. HTTP serves them as `/healthz` and `/readyz`.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+/// Load past which a queue counts as saturated, unless configured otherwise
+pub const DEFAULT_MAX_LOAD: f64 = 0.9;
+/// Events a node may be behind its peers and still count as caught up, unless configured otherwise
+pub const DEFAULT_MAX_LAG: u64 = 100;
+/// How long the receive loop may go without turning, unless configured otherwise
+pub const DEFAULT_STALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What counts as healthy and ready, see `Node::with_health`
+#[derive(Clone, Debug, PartialEq)]
+pub struct HealthPolicy {
+    /// Queues loaded past this are saturated, see `DistributedQueueSystem::load`
+    pub max_load: f64,
+    /// Events a queue may be behind its peers, see `DistributedQueueSystem::lag`
+    pub max_lag: u64,
+    pub stall_timeout: Duration,
+    /// Peers not heard from for longer are unreachable; three heartbeats if absent,
+    /// and reachability goes unchecked without heartbeats
+    pub peer_timeout: Option<Duration>,
+    /// Directory that must take writes, e.g. where logs go
+    pub storage_dir: Option<PathBuf>,
+}
+
+impl Default for HealthPolicy {
+    fn default() -> Self {
+        Self {
+            max_load: DEFAULT_MAX_LOAD,
+            max_lag: DEFAULT_MAX_LAG,
+            stall_timeout: DEFAULT_STALL_TIMEOUT,
+            peer_timeout: None,
+            storage_dir: None,
+        }
+    }
+}
+
+/// Outcome of one check
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Check {
+    pub ok: bool,
+    /// What is wrong, or why nothing was checked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Check {
+    pub fn pass() -> Self {
+        Self { ok: true, detail: None }
+    }
+
+    pub fn fail(detail: impl Into<String>) -> Self {
+        Self { ok: false, detail: Some(detail.into()) }
+    }
+
+    /// Passed, noting why there was nothing to check
+    pub fn skip(detail: impl Into<String>) -> Self {
+        Self { ok: true, detail: Some(detail.into()) }
+    }
+}
+
+/// Checks of a probe, by name; ok when all of them are
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HealthReport {
+    pub node_id: String,
+    pub ok: bool,
+    pub checks: BTreeMap<String, Check>,
+}
+
+impl HealthReport {
+    pub fn new(node_id: &str, checks: impl IntoIterator<Item = (&'static str, Check)>) -> Self {
+        let checks: BTreeMap<String, Check> = checks.into_iter().map(|(name, check)| (name.to_string(), check)).collect();
+        Self { node_id: node_id.to_string(), ok: checks.values().all(|check| check.ok), checks }
+    }
+}
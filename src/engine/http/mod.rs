@@ -0,0 +1,496 @@
+//! HTTP REST API for a node
+//!
+//! - `POST   /queues/{topic}/messages` enqueue the JSON body
+//! - `DELETE /queues/{topic}/messages` dequeue the head item (204 if empty)
+//! - `POST   /queues/{topic}/deliveries/{delivery}/fail` report a dequeued item failed
+//! - `POST   /queues/{topic}/acks`     report the deliveries in the JSON array body processed, see `Node::ack_batch`
+//! - `GET    /queues/{topic}/stats`    queue stats
+//! - `GET    /queues/{topic}/memory`   estimated bytes held by the queue, log, event buffer and applied-event tracking
+//! - `GET    /queues/{topic}/failed-events` remote events the queue gave up applying
+//! - `GET    /queues/{topic}/buffered-events` remote events waiting in the causal buffer, with what they wait for
+//! - `GET    /logs[?topic=name|namespace=name]` log entries, grouped by topic
+//! - `GET    /namespaces`              namespaces with topics on the node
+//! - `GET    /namespaces/{namespace}/stats` totals over the namespace's queues
+//! - `GET    /cluster`                 peer liveness and backpressure
+//! - `POST   /queues/{topic}/purge?key=K` purge the items logged under key K, see `Node::purge_item`
+//! - `POST   /queues/{topic}/pause`    refuse enqueues and hand out nothing until resumed
+//! - `POST   /queues/{topic}/resume`   undo a pause
+//! - `GET    /queues/{topic}/snapshot` point-in-time snapshot of the queue; with `?at=N1:5,N2:3`
+//!   the queue as it was at that vector clock, see `DistributedQueueSystem::rebuild_at`
+//! - `GET    /queues/{topic}/config`   settings in effect, see `Node::topic_config`
+//! - `PUT    /queues/{topic}/config`   override the settings in the JSON body, see `Node::configure_topic`
+//! - `GET    /admin/status`            readiness and per-topic stats of the node
+//! - `GET    /admin/pending`           per topic: buffered remote events, failed items waiting to be retried, transferred items
+//! - `POST   /admin/rebalance?topic=name` spread the topic's owned queues over the placement members, see `Node::rebalance`
+//! - `POST   /admin/reload`            re-read and apply the node's configuration, see `Node::reload`
+//! - `GET    /healthz`                 liveness, see `Node::health`; 503 if a check fails
+//! - `GET    /readyz`                  readiness, see `Node::readiness`; 503 if a check fails
+//!
+//! Topics in a namespace are addressed as `/namespaces/{namespace}/queues/{topic}/...`,
+//! with the same operations as above. Path segments and query values are percent-decoded,
+//! so topics and keys with `/`, `?`, `&` or spaces are sent encoded, see `percent_encode`.
+//!
+//! With access control on the node, clients authenticate with `Authorization: Bearer <token>`;
+//! refused operations get 403. Purges, pauses, resumes, config changes and `/admin/*` need
+//! the admin permission, on the topic or, for node-wide ones, on `*`. Enqueues over a rate limit get 429 with `Retry-After`. With placement, dequeues of queues another node owns get 409,
+//! unless the owner transferred items to this node (work stealing).
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::json;
+use tiny_http::{Header, Method, Request, Response, Server};
+use crate::core::buildcore::{ControlOp, EventOp, QueueError, TopicConfig};
+use crate::engine::acl::{ALL_TOPICS, Permission};
+use crate::engine::health::HealthReport;
+use crate::engine::namespace::{namespaced, split_namespace};
+use crate::engine::node::Node;
+
+/// Handle to a running HTTP server
+pub struct HttpServer {
+    server: Arc<Server>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl HttpServer {
+    /// Address the server is listening on
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+
+    /// Stop accepting requests and wait for the worker thread
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        self.server.unblock();
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+    }
+}
+
+impl Drop for HttpServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Start serving `node` on `addr` in a background thread
+pub fn serve<T>(node: Arc<Node<T>>, addr: impl ToSocketAddrs) -> io::Result<HttpServer>
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    spawn(addr, move |request| handle_request(&node, request))
+}
+
+/// Listen on `addr` and pass each request to `handler` on a background thread
+pub(crate) fn spawn(addr: impl ToSocketAddrs, handler: impl Fn(Request) + Send + 'static) -> io::Result<HttpServer> {
+    let server = Arc::new(Server::http(addr).map_err(io::Error::other)?);
+    let worker = server.clone();
+    let handle = thread::spawn(move || {
+        for request in worker.incoming_requests() {
+            handler(request);
+        }
+    });
+    Ok(HttpServer { server, handle: Some(handle) })
+}
+
+type JsonResponse = Response<io::Cursor<Vec<u8>>>;
+
+fn json_response(status: u16, body: &serde_json::Value) -> JsonResponse {
+    let header = Header::from_bytes("Content-Type", "application/json").unwrap();
+    Response::from_string(body.to_string()).with_status_code(status).with_header(header)
+}
+
+fn error_response(status: u16, message: &str) -> JsonResponse {
+    json_response(status, &json!({ "error": message }))
+}
+
+fn handle_request<T>(node: &Node<T>, mut request: Request)
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    // Split before decoding, so an encoded `/` stays inside its segment
+    let Some(decoded) = path.split('/').filter(|s| !s.is_empty()).map(percent_decode).collect::<Option<Vec<_>>>() else {
+        request.respond(error_response(400, "malformed percent-encoding in path")).ok();
+        return;
+    };
+    let mut segments: Vec<&str> = decoded.iter().map(String::as_str).collect();
+    // `/namespaces/{ns}/queues/{topic}/...` is `/queues/{ns}/{topic}/...` with the full topic name
+    let full_topic;
+    if let ["namespaces", namespace, "queues", topic, rest @ ..] = segments.as_slice() {
+        full_topic = namespaced(namespace, topic);
+        segments = ["queues", full_topic.as_str()].into_iter().chain(rest.iter().copied()).collect();
+    }
+    let method = request.method().clone();
+    let token = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Authorization"))
+        .and_then(|h| h.value.as_str().strip_prefix("Bearer "))
+        .map(|t| t.trim().to_string());
+    let token = token.as_deref();
+    let rebalanced = query_param(query, "topic");
+
+    let needs = match (&method, segments.as_slice()) {
+        (Method::Post, ["queues", topic, "messages"]) => Some((*topic, Permission::Produce)),
+        (Method::Post, ["queues", topic, "purge"])
+        | (Method::Post, ["queues", topic, "pause"])
+        | (Method::Post, ["queues", topic, "resume"])
+        | (Method::Put, ["queues", topic, "config"]) => Some((*topic, Permission::Admin)),
+        (Method::Post, ["admin", "rebalance"]) => Some((rebalanced.as_deref().unwrap_or(ALL_TOPICS), Permission::Admin)),
+        (Method::Get, ["admin", "status"])
+        | (Method::Get, ["admin", "pending"])
+        | (Method::Post, ["admin", "reload"]) => Some((ALL_TOPICS, Permission::Admin)),
+        (Method::Delete, ["queues", topic, "messages"])
+        | (Method::Post, ["queues", topic, "deliveries", _, "fail"])
+        | (Method::Post, ["queues", topic, "acks"])
+        | (Method::Get, ["queues", topic, "stats"])
+        | (Method::Get, ["queues", topic, "memory"])
+        | (Method::Get, ["queues", topic, "snapshot"])
+        | (Method::Get, ["queues", topic, "config"])
+        | (Method::Get, ["queues", topic, "failed-events"])
+        | (Method::Get, ["queues", topic, "buffered-events"]) => {
+            Some((*topic, Permission::Consume))
+        }
+        _ => None,
+    };
+    if let Some((topic, permission)) = needs
+        && let Err(denied) = node.authorize(token, topic, permission)
+    {
+        request.respond(error_response(403, &denied.to_string())).ok();
+        return;
+    }
+    let identity = node.identify(token);
+
+    let response = match (&method, segments.as_slice()) {
+        (Method::Post, ["queues", topic, "messages"]) => {
+            let mut body = Vec::new();
+            match request.as_reader().read_to_end(&mut body) {
+                Ok(_) => enqueue(node, topic, &body, token),
+                Err(e) => error_response(400, &e.to_string()),
+            }
+        }
+        (Method::Delete, ["queues", topic, "messages"]) => dequeue(node, topic),
+        (Method::Post, ["queues", topic, "deliveries", delivery, "fail"]) => match delivery.parse() {
+            Ok(delivery) => match node.fail(topic, delivery) {
+                Ok(event) => json_response(200, &json!({ "event_id": event.global_id })),
+                Err(e) => error_response(404, &e.to_string()),
+            },
+            Err(_) => error_response(400, "delivery must be a number"),
+        },
+        (Method::Post, ["queues", topic, "acks"]) => {
+            let mut body = Vec::new();
+            match request.as_reader().read_to_end(&mut body) {
+                Ok(_) => ack_batch(node, topic, &body),
+                Err(e) => error_response(400, &e.to_string()),
+            }
+        }
+        (Method::Get, ["queues", topic, "stats"]) => match node.get_topic(topic) {
+            Some(queue) => json_response(200, &json!(queue.stats())),
+            None => error_response(404, "unknown topic"),
+        },
+        (Method::Get, ["queues", topic, "memory"]) => match node.get_topic(topic) {
+            Some(queue) => json_response(200, &json!(queue.memory_stats())),
+            None => error_response(404, "unknown topic"),
+        },
+        (Method::Get, ["queues", topic, "failed-events"]) => match node.get_topic(topic) {
+            Some(queue) => json_response(200, &json!(queue.failed_events())),
+            None => error_response(404, "unknown topic"),
+        },
+        (Method::Get, ["queues", topic, "buffered-events"]) => match node.get_topic(topic) {
+            Some(queue) => json_response(200, &json!(queue.buffered_events())),
+            None => error_response(404, "unknown topic"),
+        },
+        (Method::Post, ["queues", topic, "purge"]) => match query_param(query, "key").filter(|key| !key.is_empty()) {
+            Some(key) => match node.purge_item_as(identity, topic, &key) {
+                Ok(events) => json_response(200, &json!({ "event_ids": events.iter().map(|event| event.global_id).collect::<Vec<_>>() })),
+                Err(e) => error_response(400, &e.to_string()),
+            },
+            None => error_response(400, "key is required"),
+        },
+        (Method::Post, ["queues", topic, "pause"]) => control(node, identity, topic, ControlOp::Paused),
+        (Method::Post, ["queues", topic, "resume"]) => control(node, identity, topic, ControlOp::Resumed),
+        (Method::Get, ["queues", topic, "snapshot"]) => match (node.get_topic(topic), query_param(query, "at")) {
+            (Some(queue), None) => json_response(200, &json!(queue.snapshot())),
+            (Some(queue), Some(at)) => match parse_frontier(&at) {
+                Some(frontier) => match queue.rebuild_at(frontier) {
+                    Ok(snapshot) => json_response(200, &json!(snapshot)),
+                    Err(e) => error_response(409, &e.to_string()),
+                },
+                None => error_response(400, "at must be node:time pairs separated by commas"),
+            },
+            (None, _) => error_response(404, "unknown topic"),
+        },
+        (Method::Get, ["queues", topic, "config"]) => match node.topic_config(topic) {
+            Some(config) => json_response(200, &json!(config)),
+            None => error_response(404, "unknown topic"),
+        },
+        (Method::Put, ["queues", topic, "config"]) => {
+            let mut body = Vec::new();
+            match request.as_reader().read_to_end(&mut body) {
+                Ok(_) => configure(node, identity, topic, &body),
+                Err(e) => error_response(400, &e.to_string()),
+            }
+        }
+        (Method::Get, ["logs"]) => logs(node, query, token),
+        (Method::Get, ["cluster"]) => json_response(200, &json!(node.cluster_status())),
+        (Method::Get, ["namespaces"]) => json_response(200, &json!(node.namespaces())),
+        (Method::Get, ["namespaces", namespace, "stats"]) => namespace_stats(node, namespace, token),
+        (Method::Get, ["healthz"]) => probe_response(node.health()),
+        (Method::Get, ["readyz"]) => probe_response(node.readiness()),
+        (Method::Get, ["admin", "status"]) => status(node, token),
+        (Method::Get, ["admin", "pending"]) => pending(node, token),
+        (Method::Post, ["admin", "rebalance"]) => match rebalanced.as_deref() {
+            Some(topic) => json_response(200, &json!({ "transferred": node.rebalance_as(identity, topic) })),
+            None => error_response(400, "topic is required"),
+        },
+        (Method::Post, ["admin", "reload"]) => match node.reload_as(identity) {
+            Ok(changes) => json_response(200, &json!({ "changes": changes })),
+            Err(e) if e.kind() == std::io::ErrorKind::Unsupported => error_response(404, &e.to_string()),
+            Err(e) => error_response(500, &e.to_string()),
+        },
+        _ => error_response(404, "not found"),
+    };
+    request.respond(response).ok();
+}
+
+/// Decoded value of the query parameter `name`; malformed values count as missing
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query.split('&').find_map(|pair| percent_decode(pair.strip_prefix(name)?.strip_prefix('=')?))
+}
+
+/// Escape everything but unreserved characters (RFC 3986), for a path segment or query value
+pub fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Undo `percent_encode`; `None` for a truncated or non-hex escape, or invalid UTF-8
+pub fn percent_decode(text: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Vector clock written as `node:time,node:time`
+fn parse_frontier(at: &str) -> Option<HashMap<String, u64>> {
+    at.split(',')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (node, time) = pair.rsplit_once(':')?;
+            Some((node.to_string(), time.parse().ok()?))
+        })
+        .collect()
+}
+
+/// Pause or resume every queue of an existing topic
+fn control<T>(node: &Node<T>, identity: &str, topic: &str, op: ControlOp) -> JsonResponse
+where
+    T: Send + Sync + 'static,
+{
+    if node.topic_stats(topic).is_none() {
+        return error_response(404, "unknown topic");
+    }
+    let events = node.control_as(identity, topic, op);
+    json_response(200, &json!({ "event_ids": events.iter().map(|event| event.global_id).collect::<Vec<_>>() }))
+}
+
+/// Override settings of an existing topic, answering with those in effect
+fn configure<T>(node: &Node<T>, identity: &str, topic: &str, body: &[u8]) -> JsonResponse
+where
+    T: Send + Sync + 'static,
+{
+    let overrides: TopicConfig = match serde_json::from_slice(body) {
+        Ok(overrides) => overrides,
+        Err(e) => return error_response(400, &format!("invalid topic config: {}", e)),
+    };
+    if node.configure_topic_as(identity, topic, overrides).is_empty() {
+        return error_response(404, "unknown topic");
+    }
+    match node.topic_config(topic) {
+        Some(config) => json_response(200, &json!(config)),
+        None => error_response(404, "unknown topic"),
+    }
+}
+
+/// Settle the deliveries listed in the body, answering with how many were tracked
+fn ack_batch<T>(node: &Node<T>, topic: &str, body: &[u8]) -> JsonResponse
+where
+    T: Send + Sync + 'static,
+{
+    let deliveries: Vec<u64> = match serde_json::from_slice(body) {
+        Ok(deliveries) => deliveries,
+        Err(e) => return error_response(400, &format!("invalid deliveries: {}", e)),
+    };
+    if node.topic_stats(topic).is_none() {
+        return error_response(404, "unknown topic");
+    }
+    let events = node.ack_batch(topic, &deliveries);
+    let acked: usize = events
+        .iter()
+        .map(|event| match &event.op {
+            EventOp::Ack { deliveries } => deliveries.len(),
+            _ => 0,
+        })
+        .sum();
+    json_response(200, &json!({ "acked": acked, "event_ids": events.iter().map(|event| event.global_id).collect::<Vec<_>>() }))
+}
+
+/// Readiness and the stats of the topics the client may consume
+fn status<T>(node: &Node<T>, token: Option<&str>) -> JsonResponse
+where
+    T: Send + Sync + 'static,
+{
+    let topics: BTreeMap<String, _> = node
+        .topic_names()
+        .into_iter()
+        .filter(|name| node.is_permitted(token, name, Permission::Consume))
+        .filter_map(|name| Some((name.clone(), node.topic_stats(&name)?)))
+        .collect();
+    json_response(200, &json!({ "node_id": node.node_id(), "ready": node.readiness(), "topics": topics }))
+}
+
+/// What is waiting in each topic the client may consume, besides its items
+fn pending<T>(node: &Node<T>, token: Option<&str>) -> JsonResponse
+where
+    T: Send + Sync + 'static,
+{
+    let mut by_topic = BTreeMap::new();
+    for name in node.topic_names() {
+        if !node.is_permitted(token, &name, Permission::Consume) {
+            continue;
+        }
+        if let Some(queue) = node.get_topic(&name) {
+            let waiting = json!({
+                "buffered_events": queue.pending_events_count(),
+                "pending_retries": queue.pending_retries(),
+                "transferred": queue.transferred_len(),
+            });
+            by_topic.insert(name, waiting);
+        }
+    }
+    json_response(200, &json!(by_topic))
+}
+
+fn probe_response(report: HealthReport) -> JsonResponse {
+    json_response(if report.ok { 200 } else { 503 }, &json!(report))
+}
+
+fn enqueue<T>(node: &Node<T>, topic: &str, body: &[u8], token: Option<&str>) -> JsonResponse
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    let item: T = match serde_json::from_slice(body) {
+        Ok(item) => item,
+        Err(e) => return error_response(400, &format!("invalid item: {}", e)),
+    };
+    match node.enqueue_as(node.identify(token), topic, None, item) {
+        Ok(event) => json_response(201, &json!({ "event_id": event.global_id, "clock": event.clock })),
+        Err(e @ QueueError::RateLimited { retry_after }) => {
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            error_response(429, &e.to_string()).with_header(Header::from_bytes("Retry-After", seconds.to_string()).unwrap())
+        }
+        Err(e) => error_response(503, &e.to_string()),
+    }
+}
+
+fn dequeue<T>(node: &Node<T>, topic: &str) -> JsonResponse
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    if node.partition_count(topic).is_some() {
+        return match node.dequeue_partitions_delivery(topic, &node.owned_partitions(topic)) {
+            Some((partition, delivery, item)) => {
+                json_response(200, &json!({ "item": item, "partition": partition, "delivery": delivery, "attempt": attempt(node, topic, delivery) }))
+            }
+            None => match node.take_or_steal(topic) {
+                Some((delivery, item)) => json_response(200, &json!({ "item": item, "delivery": delivery, "attempt": attempt(node, topic, delivery) })),
+                None => Response::from_data(Vec::new()).with_status_code(204),
+            },
+        };
+    }
+    if node.get_topic(topic).is_none() {
+        return error_response(404, "unknown topic");
+    }
+    if let Err(e) = node.check_owner(topic) {
+        return match node.take_or_steal(topic) {
+            Some((delivery, item)) => json_response(200, &json!({ "item": item, "delivery": delivery, "attempt": attempt(node, topic, delivery) })),
+            None => error_response(409, &e.to_string()),
+        };
+    }
+    match node.dequeue(topic) {
+        (Some(item), event) => {
+            json_response(200, &json!({ "item": item, "event_id": event.global_id, "delivery": event.global_id, "attempt": event.delivery_attempt }))
+        }
+        (None, _) => Response::from_data(Vec::new()).with_status_code(204),
+    }
+}
+
+/// Which delivery of its item `delivery` is, as for `Delivery::attempt`
+fn attempt<T: Send + Sync + 'static>(node: &Node<T>, topic: &str, delivery: u64) -> u32 {
+    node.delivery_attempt(topic, delivery).unwrap_or(1)
+}
+
+/// A namespace's totals, for clients that may consume all of its topics
+fn namespace_stats<T>(node: &Node<T>, namespace: &str, token: Option<&str>) -> JsonResponse
+where
+    T: Send + Sync + 'static,
+{
+    if let Err(denied) = node.authorize_namespace(token, namespace, Permission::Consume) {
+        return error_response(403, &denied.to_string());
+    }
+    match node.namespace_stats(namespace) {
+        Some(stats) => json_response(200, &json!(stats)),
+        None => error_response(404, "unknown namespace"),
+    }
+}
+
+fn logs<T>(node: &Node<T>, query: &str, token: Option<&str>) -> JsonResponse
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    let wanted = query_param(query, "topic");
+    let namespace = query_param(query, "namespace");
+    // An explicitly requested topic is checked (and audited); listings just skip hidden topics
+    if let Some(topic) = &wanted
+        && let Err(denied) = node.authorize(token, topic, Permission::Consume)
+    {
+        return error_response(403, &denied.to_string());
+    }
+    let mut by_topic = BTreeMap::new();
+    for name in node.topic_names() {
+        if wanted.as_ref().is_some_and(|w| *w != name)
+            || namespace.as_deref().is_some_and(|ns| split_namespace(&name).0 != ns)
+            || !node.is_permitted(token, &name, Permission::Consume) {
+            continue;
+        }
+        if let Some(queue) = node.get_topic(&name) {
+            by_topic.insert(name, queue.logs());
+        }
+    }
+    json_response(200, &json!(by_topic))
+}
@@ -0,0 +1,262 @@
+//! Mirroring of a topic to another cluster
+//! A mirror tails the log of a topic's queues and enqueues the item of every enqueue made on
+//! this node on a node of the other cluster, through a `QueueClient`, so that cluster can take
+//! over as a warm standby. Like `RedisBridge::mirror`, replicated enqueues are left to the nodes
+//! they were made on, so every node of the cluster runs the mirror; retries are not mirrored.
+//! An enqueue is only forwarded once every node applied it, so one a peer rejected over its
+//! quota never is; while a peer is out of reach, the mirror waits for it.
+//! How far each queue's log was mirrored is kept in a position file, if given, and a restarted
+//! mirror resumes there; the other cluster may see an item twice if the node stops between
+//! forwarding it and saving the position, never zero times unless retention discarded it first.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use serde::{Serialize, de::DeserializeOwned};
+use crate::core::buildcore::{EventOp, LogEntry, State};
+use crate::engine::client::QueueClient;
+use crate::engine::node::Node;
+use crate::engine::partition::partition_name;
+use crate::engine::worker::{ErrorLog, WorkerErrors};
+
+/// How often a mirror polls a log with nothing new to forward
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Most log entries read at a time
+const READ_BATCH: usize = 100;
+/// How long to wait before reconnecting after an error
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Nodes of the cluster a topic is mirrored to, tried in order until one answers
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RemoteCluster {
+    addrs: Vec<String>,
+    topic: Option<String>,
+    token: Option<String>,
+}
+
+impl RemoteCluster {
+    /// Cluster reached through the peer addresses (`host:port`) of its nodes
+    pub fn new<S: Into<String>>(addrs: impl IntoIterator<Item = S>) -> Self {
+        Self { addrs: addrs.into_iter().map(Into::into).collect(), topic: None, token: None }
+    }
+
+    /// Enqueue on `topic` there, instead of the mirrored topic's name
+    pub fn with_topic(mut self, topic: impl Into<String>) -> Self {
+        self.topic = Some(topic.into());
+        self
+    }
+
+    /// Present `token`, for clusters with access control
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    fn connect<T: Serialize + DeserializeOwned>(&self) -> io::Result<QueueClient<T>> {
+        let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to");
+        for addr in &self.addrs {
+            match QueueClient::connect(addr.as_str()) {
+                Ok(client) => {
+                    return Ok(match &self.token {
+                        Some(token) => client.with_token(token.clone()),
+                        None => client,
+                    });
+                }
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+}
+
+impl fmt::Display for RemoteCluster {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}]", self.addrs.join(", "))
+    }
+}
+
+/// How far the log of each queue was mirrored, in `DistributedQueueSystem::retained_range` positions
+struct Positions {
+    reached: Mutex<BTreeMap<String, usize>>,
+    file: Option<PathBuf>,
+}
+
+impl Positions {
+    fn load(file: Option<PathBuf>) -> io::Result<Self> {
+        let reached = match &file {
+            Some(path) if path.exists() => serde_json::from_slice(&std::fs::read(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            _ => BTreeMap::new(),
+        };
+        Ok(Self { reached: Mutex::new(reached), file })
+    }
+
+    fn get(&self, queue: &str) -> usize {
+        self.reached.lock().unwrap().get(queue).copied().unwrap_or(0)
+    }
+
+    /// Record that `queue` was mirrored up to `position`, saving the positions if it moved
+    fn advance(&self, queue: &str, position: usize) -> io::Result<()> {
+        let mut reached = self.reached.lock().unwrap();
+        if reached.insert(queue.to_string(), position) == Some(position) {
+            return Ok(());
+        }
+        match &self.file {
+            Some(path) => save(path, &reached),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Write the positions next to `path` first, so a crash never leaves a torn file
+fn save(path: &Path, reached: &BTreeMap<String, usize>) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    std::fs::write(&temporary, serde_json::to_vec(reached).map_err(io::Error::other)?)?;
+    std::fs::rename(&temporary, path)
+}
+
+/// Handle to a running mirror
+pub struct ClusterMirror {
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+    positions: Arc<Positions>,
+    errors: ErrorLog,
+}
+
+impl ClusterMirror {
+    /// Forward the enqueues made on this node to `topic` (its partitions included) to `remote`,
+    /// from the positions saved in `position_file` on, or from the oldest retained log entry
+    /// Keyed enqueues keep their ordering key; partitions are enqueued on the remote topic,
+    /// which spreads them over its own partitions
+    pub fn start<T>(node: Arc<Node<T>>, topic: &str, remote: RemoteCluster, position_file: Option<PathBuf>) -> io::Result<Self>
+    where
+        T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    {
+        let queues = match node.partition_count(topic) {
+            Some(count) => (0..count).map(|p| partition_name(topic, p)).collect(),
+            None => vec![topic.to_string()],
+        };
+        let positions = Arc::new(Positions::load(position_file)?);
+        let stop = Arc::new(AtomicBool::new(false));
+        let errors = ErrorLog::default();
+        let target = remote.topic.clone().unwrap_or_else(|| topic.to_string());
+        let threads = queues
+            .into_iter()
+            .map(|queue| {
+                let (node, remote, target, positions, running) = (node.clone(), remote.clone(), target.clone(), positions.clone(), stop.clone());
+                let errors = errors.clone();
+                thread::spawn(move || {
+                    let mut client = None;
+                    while !running.load(Ordering::SeqCst) {
+                        match mirror_batch(&node, &queue, &target, &remote, &mut client, &positions, &errors) {
+                            Ok(0) => thread::sleep(POLL_INTERVAL),
+                            Ok(_) => {}
+                            Err(e) => {
+                                errors.record(format_args!("cannot mirror {} to {}: {}", queue, remote, e));
+                                client = None;
+                                thread::sleep(RECONNECT_DELAY);
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        Ok(Self { stop, threads, positions, errors })
+    }
+
+    /// Log position each queue was mirrored up to
+    pub fn positions(&self) -> BTreeMap<String, usize> {
+        self.positions.reached.lock().unwrap().clone()
+    }
+
+    /// Errors the mirror ran into, see `WorkerErrors`
+    pub fn errors(&self) -> WorkerErrors {
+        self.errors.snapshot()
+    }
+
+    /// Stop the mirror and wait for it
+    pub fn shutdown(mut self) {
+        self.stop_and_join();
+    }
+
+    fn stop_and_join(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            thread.join().ok();
+        }
+    }
+}
+
+impl Drop for ClusterMirror {
+    fn drop(&mut self) {
+        self.stop_and_join();
+    }
+}
+
+/// Forward the enqueues logged in `queue` past its position, and save the position reached
+/// Returns how many log entries it got through; entries retention discarded first count as errors
+fn mirror_batch<T>(node: &Node<T>, queue: &str, target: &str, remote: &RemoteCluster, client: &mut Option<QueueClient<T>>, positions: &Positions, errors: &ErrorLog) -> io::Result<usize>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    let Some(log) = node.get_topic(queue) else {
+        return Ok(0);
+    };
+    // A log shorter than the position was lost on a restart; start over on the new one
+    let position = match positions.get(queue) {
+        position if position > log.retained_range().end => 0,
+        position => position,
+    };
+    let (start, entries) = log.log_from(position, READ_BATCH)?;
+    if start > position {
+        errors.record(format_args!("{} log entries of {} were discarded before they were mirrored", start - position, queue));
+    }
+    let mut reached = start;
+    let mut forwarded = Ok(());
+    for entry in &entries {
+        // Any rejection of the enqueue reached this node by the time every node applied it
+        if is_local_enqueue(entry) && !log.is_stable(&entry.event) {
+            break;
+        }
+        if let Some((key, item)) = mirrored_item(entry)
+            && let Err(e) = forward(client, remote, target, key, item)
+        {
+            forwarded = Err(e);
+            break;
+        }
+        reached += 1;
+    }
+    positions.advance(queue, reached)?;
+    forwarded.map(|()| reached - position)
+}
+
+fn is_local_enqueue<T>(entry: &LogEntry<T>) -> bool {
+    matches!(entry.event.op, EventOp::Enqueue) && entry.event.origin_node == entry.local_node
+}
+
+/// Ordering key and item of an enqueue made on the node that logged it, unless rejected
+fn mirrored_item<T: Clone>(entry: &LogEntry<T>) -> Option<(Option<&str>, T)> {
+    let event = &entry.event;
+    if !is_local_enqueue(entry) || !matches!(entry.state, State::Committed | State::Delivered) || entry.redacted {
+        return None;
+    }
+    let item = event.item.as_deref()?.clone();
+    Some((event.sequence.as_ref().map(|sequence| sequence.key.as_str()), item))
+}
+
+fn forward<T: Serialize + DeserializeOwned>(client: &mut Option<QueueClient<T>>, remote: &RemoteCluster, topic: &str, key: Option<&str>, item: T) -> io::Result<()> {
+    let client = match client {
+        Some(client) => client,
+        None => client.insert(remote.connect()?),
+    };
+    match key {
+        Some(key) => client.enqueue_keyed(topic, key, item)?,
+        None => client.enqueue(topic, item)?,
+    };
+    Ok(())
+}
@@ -1,3 +1,52 @@
-mod node;
-mod network;
-mod process;
\ No newline at end of file
+pub mod node;
+pub mod acl;
+pub mod partition;
+pub mod namespace;
+pub mod placement;
+pub mod stats;
+#[cfg(feature = "net")]
+pub mod network;
+#[cfg(feature = "net")]
+pub mod client;
+#[cfg(feature = "net")]
+pub mod health;
+#[cfg(feature = "net")]
+pub mod consumer;
+#[cfg(feature = "net")]
+mod trace;
+#[cfg(feature = "net")]
+pub mod raft;
+#[cfg(feature = "net")]
+pub mod topology;
+#[cfg(feature = "net")]
+pub mod transfer;
+#[cfg(feature = "net")]
+pub mod mirror;
+#[cfg(feature = "net")]
+pub mod worker;
+#[cfg(feature = "persist")]
+pub mod blob;
+#[cfg(feature = "sim")]
+pub mod process;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "http")]
+pub mod admin;
+#[cfg(feature = "http")]
+pub mod webhook;
+#[cfg(feature = "sqs")]
+pub mod sqs;
+#[cfg(feature = "redis")]
+pub mod redis;
+#[cfg(feature = "nats")]
+pub mod nats;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "server")]
+pub mod config;
+#[cfg(feature = "server")]
+pub mod shell;
+#[cfg(feature = "tui")]
+pub mod dashboard;
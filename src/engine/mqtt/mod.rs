@@ -0,0 +1,349 @@
+//! MQTT ingest bridge, for edge devices feeding topics directly
+//! Subscribes to an MQTT topic filter (MQTT 3.1.1) and enqueues each message published to it,
+//! the payload taken as a JSON item or else as a string. The subscription's QoS sets the guarantee:
+//! - QoS 0: at most once, messages are not acknowledged
+//! - QoS 1: at least once, acknowledged (PUBACK) once enqueued; the broker may redeliver one
+//!   whose ack was lost, so it can be enqueued twice
+//! - QoS 2: exactly once, received (PUBREC) once enqueued and remembered until the broker releases it
+//!
+//! With QoS 1 and 2 the session is persistent, so the broker keeps messages while the bridge is
+//! disconnected. A message that can't be enqueued for a transient reason, e.g. a rate limit, is
+//! not acknowledged; the bridge reconnects and the broker delivers it again. One that would
+//! never be enqueued, e.g. as it is not a valid item, is acknowledged and dropped, and kept
+//! among the bridge's errors.
+
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use crate::engine::node::Node;
+use crate::engine::worker::{ErrorLog, WorkerErrors};
+
+/// How long the bridge waits for a packet before checking whether it was stopped
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// How long to wait for the rest of a packet, or for the broker to answer
+const PACKET_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait before reconnecting after an error
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// Keep alive announced to the broker; the bridge pings at half of it when idle
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+/// Packet types, the high nibble of the first byte
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const PUBREC: u8 = 5;
+const PUBREL: u8 = 6;
+const PUBCOMP: u8 = 7;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+const DISCONNECT: u8 = 14;
+
+/// MQTT quality of service of a subscription
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum QoS {
+    AtMostOnce = 0,
+    AtLeastOnce = 1,
+    ExactlyOnce = 2,
+}
+
+impl TryFrom<u8> for QoS {
+    type Error = io::Error;
+
+    fn try_from(level: u8) -> io::Result<Self> {
+        match level {
+            0 => Ok(QoS::AtMostOnce),
+            1 => Ok(QoS::AtLeastOnce),
+            2 => Ok(QoS::ExactlyOnce),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid MQTT QoS {}", level))),
+        }
+    }
+}
+
+/// An MQTT broker and the credentials to connect with
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MqttBroker {
+    addr: String,
+    client_id: Option<String>,
+    credentials: Option<(String, Option<String>)>,
+}
+
+impl MqttBroker {
+    /// Broker at `addr` (`host:port`)
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self { addr: addr.into(), client_id: None, credentials: None }
+    }
+
+    /// Prefix of the client IDs bridges connect with, `<prefix>-<topic>`; the node ID if not set
+    /// Client IDs name persistent sessions, so keep them stable across restarts
+    pub fn with_client_id(mut self, prefix: impl Into<String>) -> Self {
+        self.client_id = Some(prefix.into());
+        self
+    }
+
+    /// Authenticate with a user name and, optionally, a password
+    pub fn with_credentials(mut self, username: impl Into<String>, password: Option<String>) -> Self {
+        self.credentials = Some((username.into(), password));
+        self
+    }
+
+    fn connect(&self, client_id: &str, filter: &str, qos: QoS) -> io::Result<Connection> {
+        let addr = self
+            .addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} does not resolve", self.addr)))?;
+        let mut connection = Connection { stream: TcpStream::connect(addr)?, last_sent: Instant::now(), next_packet_id: 1 };
+
+        // Keep the session only if there is something to keep
+        let mut flags = if qos == QoS::AtMostOnce { 0x02 } else { 0 };
+        let mut packet = string(b"MQTT");
+        packet.push(4);
+        let flags_at = packet.len();
+        packet.push(0);
+        packet.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+        packet.extend(string(client_id.as_bytes()));
+        if let Some((username, password)) = &self.credentials {
+            flags |= 0x80;
+            packet.extend(string(username.as_bytes()));
+            if let Some(password) = password {
+                flags |= 0x40;
+                packet.extend(string(password.as_bytes()));
+            }
+        }
+        packet[flags_at] = flags;
+        connection.send(CONNECT << 4, &packet)?;
+        match connection.receive(PACKET_TIMEOUT)? {
+            Some((header, body)) if header >> 4 == CONNACK && body.len() == 2 => match body[1] {
+                0 => {}
+                4 | 5 => return Err(io::Error::new(io::ErrorKind::PermissionDenied, "MQTT broker refused the credentials")),
+                code => return Err(io::Error::other(format!("MQTT broker refused the connection, code {}", code))),
+            },
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "no CONNACK from MQTT broker")),
+        }
+
+        let id = connection.packet_id();
+        let mut packet = id.to_be_bytes().to_vec();
+        packet.extend(string(filter.as_bytes()));
+        packet.push(qos as u8);
+        connection.send(SUBSCRIBE << 4 | 0x02, &packet)?;
+        // Publishes may arrive before the SUBACK; they're redelivered, the session being persistent
+        loop {
+            match connection.receive(PACKET_TIMEOUT)? {
+                Some((header, body)) if header >> 4 == SUBACK && body.starts_with(&id.to_be_bytes()) => {
+                    return match body.get(2) {
+                        Some(0x80) | None => Err(io::Error::other(format!("MQTT broker refused the subscription to {}", filter))),
+                        Some(_) => Ok(connection),
+                    };
+                }
+                Some(_) => continue,
+                None => return Err(io::Error::new(io::ErrorKind::InvalidData, "no SUBACK from MQTT broker")),
+            }
+        }
+    }
+}
+
+struct Connection {
+    stream: TcpStream,
+    last_sent: Instant,
+    next_packet_id: u16,
+}
+
+impl Connection {
+    fn packet_id(&mut self) -> u16 {
+        let id = self.next_packet_id;
+        // Packet IDs are non-zero
+        self.next_packet_id = self.next_packet_id.checked_add(1).unwrap_or(1);
+        id
+    }
+
+    fn send(&mut self, header: u8, body: &[u8]) -> io::Result<()> {
+        let mut packet = vec![header];
+        // Remaining length, 7 bits per byte with a continuation bit
+        let mut len = body.len();
+        loop {
+            let byte = (len % 128) as u8;
+            len /= 128;
+            packet.push(if len > 0 { byte | 0x80 } else { byte });
+            if len == 0 {
+                break;
+            }
+        }
+        packet.extend_from_slice(body);
+        self.stream.write_all(&packet)?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    /// Wait up to `timeout` for the next packet: its first byte and the rest of it
+    fn receive(&mut self, timeout: Duration) -> io::Result<Option<(u8, Vec<u8>)>> {
+        self.stream.set_read_timeout(Some(timeout))?;
+        let mut header = [0];
+        match self.stream.read(&mut header) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "MQTT broker closed the connection")),
+            Ok(_) => {}
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        self.stream.set_read_timeout(Some(PACKET_TIMEOUT))?;
+        let (mut len, mut shift) = (0usize, 0);
+        loop {
+            let mut byte = [0];
+            self.stream.read_exact(&mut byte)?;
+            len |= ((byte[0] & 0x7f) as usize) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+            if shift > 21 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed MQTT remaining length"));
+            }
+        }
+        let mut body = vec![0; len];
+        self.stream.read_exact(&mut body)?;
+        Ok(Some((header[0], body)))
+    }
+
+    fn ack(&mut self, kind: u8, id: u16) -> io::Result<()> {
+        self.send(kind << 4, &id.to_be_bytes())
+    }
+}
+
+/// Length-prefixed MQTT string
+fn string(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = (bytes.len() as u16).to_be_bytes().to_vec();
+    encoded.extend_from_slice(bytes);
+    encoded
+}
+
+/// Handle to a running bridge
+pub struct MqttBridge {
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+    errors: ErrorLog,
+}
+
+impl MqttBridge {
+    /// Enqueue the messages published to `filter` (wildcards allowed) onto `topic`
+    pub fn ingest<T>(node: Arc<Node<T>>, topic: &str, broker: &MqttBroker, filter: &str, qos: QoS) -> io::Result<Self>
+    where
+        T: DeserializeOwned + Send + Sync + 'static,
+    {
+        let client_id = format!("{}-{}", broker.client_id.as_deref().unwrap_or(node.node_id()), topic);
+        let mut connection = broker.connect(&client_id, filter, qos)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let errors = ErrorLog::default();
+        let (running, broker, topic, filter, failed) = (stop.clone(), broker.clone(), topic.to_string(), filter.to_string(), errors.clone());
+        let thread = thread::spawn(move || {
+            // QoS 2 messages enqueued and not released yet, kept across reconnects like the session
+            let mut received = HashSet::new();
+            while !running.load(Ordering::SeqCst) {
+                if let Err(e) = receive_next(&node, &topic, &mut connection, &mut received, &failed) {
+                    failed.record(format_args!("MQTT bridge of {} reconnecting: {}", topic, e));
+                    thread::sleep(RECONNECT_DELAY);
+                    if let Ok(reconnected) = broker.connect(&client_id, &filter, qos) {
+                        connection = reconnected;
+                    }
+                }
+            }
+            connection.send(DISCONNECT << 4, &[]).ok();
+        });
+        Ok(Self { stop, threads: vec![thread], errors })
+    }
+
+    /// Errors the bridge ran into, see `WorkerErrors`
+    pub fn errors(&self) -> WorkerErrors {
+        self.errors.snapshot()
+    }
+
+    /// Stop the bridge and wait for it
+    pub fn shutdown(mut self) {
+        self.stop_and_join();
+    }
+
+    fn stop_and_join(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            thread.join().ok();
+        }
+    }
+}
+
+impl Drop for MqttBridge {
+    fn drop(&mut self) {
+        self.stop_and_join();
+    }
+}
+
+/// Handle the next packet from the broker, pinging it if the connection was idle
+/// Errors mean the connection should be replaced, as for a QoS 1 or 2 message that can't be
+/// enqueued yet; other messages that can't be enqueued are dropped, and recorded in `errors`
+fn receive_next<T>(node: &Node<T>, topic: &str, connection: &mut Connection, received: &mut HashSet<u16>, errors: &ErrorLog) -> io::Result<()>
+where
+    T: DeserializeOwned + Send + Sync + 'static,
+{
+    if connection.last_sent.elapsed() >= KEEP_ALIVE / 2 {
+        connection.send(PINGREQ << 4, &[])?;
+    }
+    let Some((header, body)) = connection.receive(POLL_INTERVAL)? else {
+        return Ok(());
+    };
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed MQTT packet");
+    match header >> 4 {
+        PUBLISH => {
+            let qos = QoS::try_from((header >> 1) & 0x03)?;
+            let name_len = u16::from_be_bytes([*body.first().ok_or_else(invalid)?, *body.get(1).ok_or_else(invalid)?]) as usize;
+            let mut at = 2 + name_len;
+            let id = match qos {
+                QoS::AtMostOnce => 0,
+                _ => {
+                    let id = body.get(at..at + 2).ok_or_else(invalid)?;
+                    at += 2;
+                    u16::from_be_bytes([id[0], id[1]])
+                }
+            };
+            let payload = body.get(at..).ok_or_else(invalid)?;
+            // Redelivered before the broker got the PUBREC
+            if qos == QoS::ExactlyOnce && received.contains(&id) {
+                return connection.ack(PUBREC, id);
+            }
+            let text = String::from_utf8_lossy(payload).into_owned();
+            let item = serde_json::from_str(&text).or_else(|_| serde_json::from_value(Value::String(text)));
+            let enqueued = match item {
+                Ok(item) => node.enqueue(topic, item).map(|_| ()).map_err(|e| (e.is_transient(), e.to_string())),
+                Err(e) => Err((false, format!("not a valid item: {}", e))),
+            };
+            if let Err((transient, e)) = enqueued {
+                // Left unacknowledged, the broker delivers it again once reconnected
+                if transient && qos != QoS::AtMostOnce {
+                    return Err(io::Error::other(format!("cannot enqueue message {}: {}", id, e)));
+                }
+                errors.record(format_args!("dropped MQTT message {} for {}: {}", id, topic, e));
+            }
+            match qos {
+                QoS::AtMostOnce => {}
+                QoS::AtLeastOnce => connection.ack(PUBACK, id)?,
+                QoS::ExactlyOnce => {
+                    received.insert(id);
+                    connection.ack(PUBREC, id)?;
+                }
+            }
+        }
+        PUBREL => {
+            let id = body.get(..2).ok_or_else(invalid)?;
+            let id = u16::from_be_bytes([id[0], id[1]]);
+            received.remove(&id);
+            connection.ack(PUBCOMP, id)?;
+        }
+        // PINGRESP, SUBACK
+        _ => {}
+    }
+    Ok(())
+}
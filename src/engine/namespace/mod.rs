@@ -0,0 +1,75 @@
+//! Namespaces: a level above topics, so one cluster can serve several applications
+//! A topic named `namespace/topic` belongs to that namespace; topics without a `/`
+//! belong to `DEFAULT_NAMESPACE`. Each topic is still its own queue with its own log,
+//! so namespaces are isolated by construction; on top of that a node can cap each
+//! namespace's topics and queued items (`Node::with_namespace_quota`), report stats
+//! per namespace, and grant access per namespace with `namespace/*` ACL patterns.
+
+use serde::{Deserialize, Serialize};
+use crate::core::buildcore::QueueStats;
+
+/// Namespace of topics whose name has no namespace part
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// Separates the namespace from the topic in a full topic name
+pub const SEPARATOR: char = '/';
+
+/// Full name of `topic` in `namespace`
+pub fn namespaced(namespace: &str, topic: &str) -> String {
+    format!("{}{}{}", namespace, SEPARATOR, topic)
+}
+
+/// Split a full topic name (or partition queue name) into namespace and the rest
+pub fn split_namespace(name: &str) -> (&str, &str) {
+    name.split_once(SEPARATOR).unwrap_or((DEFAULT_NAMESPACE, name))
+}
+
+/// Limits on one namespace's use of a node; unlimited until set
+/// Only local enqueues are refused: replicated events are always applied, so
+/// every node should configure the same quotas
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct NamespaceQuota {
+    /// Most topics; a partitioned topic counts once
+    pub max_topics: Option<usize>,
+    /// Most unconsumed items over all of the namespace's topics
+    pub max_items: Option<usize>,
+}
+
+impl NamespaceQuota {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_topics(mut self, max: usize) -> Self {
+        self.max_topics = Some(max);
+        self
+    }
+
+    pub fn max_items(mut self, max: usize) -> Self {
+        self.max_items = Some(max);
+        self
+    }
+}
+
+/// Totals over a namespace's queues on one node
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamespaceStats {
+    pub namespace: String,
+    pub topics: usize,         // a partitioned topic counts once
+    pub depth: usize,          // items currently queued
+    pub pending_events: usize, // remote events waiting in causal buffers
+    pub log_entries: usize,
+}
+
+impl NamespaceStats {
+    pub(crate) fn new(namespace: &str) -> Self {
+        Self { namespace: namespace.to_string(), ..Self::default() }
+    }
+
+    /// Add one of the namespace's queues
+    pub(crate) fn add(&mut self, stats: &QueueStats) {
+        self.depth += stats.depth;
+        self.pending_events += stats.pending_events;
+        self.log_entries += stats.log_entries;
+    }
+}
@@ -0,0 +1,294 @@
+//! NATS connector
+//! Publishes the events of a topic to NATS as JSON, on `<subject>.<op>` (`orders.events.enqueue`,
+//! `orders.events.dequeue`, ..), and/or enqueues the messages of a subject onto a topic, the payload
+//! taken as a JSON item or else as a string. Subscribing to the deliver subject of a JetStream
+//! push consumer works too: its messages are acknowledged once enqueued, negatively acknowledged
+//! (`-NAK`) if the topic refuses them for now, so JetStream redelivers them, and terminated
+//! (`+TERM`) if they would never be enqueued. The connector keeps every failure among its errors.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+use crate::engine::node::Node;
+use crate::engine::partition::partition_name;
+use crate::engine::worker::{ErrorLog, WorkerErrors};
+
+/// How long a connector waits for events or messages before checking whether it was stopped
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// How long to wait for the rest of a message once its header line arrived
+const PAYLOAD_TIMEOUT: Duration = Duration::from_secs(5);
+/// Reply subjects of JetStream messages, which expect an ack
+const JETSTREAM_ACK_PREFIX: &str = "$JS.ACK.";
+
+/// A NATS server and the credentials to connect with
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NatsServer {
+    addr: String,
+    token: Option<String>,
+}
+
+impl NatsServer {
+    /// Server at `addr` (`host:port`)
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self { addr: addr.into(), token: None }
+    }
+
+    /// Authenticate with a token
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    fn connect(&self) -> io::Result<Connection> {
+        let addr = self
+            .addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} does not resolve", self.addr)))?;
+        let stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(PAYLOAD_TIMEOUT))?;
+        let mut connection = Connection { reader: BufReader::new(stream.try_clone()?), writer: stream, line: String::new() };
+        // The server greets with INFO before anything else
+        let mut info = String::new();
+        connection.reader.read_line(&mut info)?;
+        if !info.starts_with("INFO ") {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("not a NATS server, got {:?}", info.trim_end())));
+        }
+        let mut options = json!({ "verbose": false, "pedantic": false, "name": "DistributedQueueMini", "lang": "rust", "version": env!("CARGO_PKG_VERSION") });
+        if let Some(token) = &self.token {
+            options["auth_token"] = json!(token);
+        }
+        write!(connection.writer, "CONNECT {}\r\nPING\r\n", options)?;
+        // PONG once the server accepted CONNECT; authorization failures come back as -ERR
+        match connection.next_message(PAYLOAD_TIMEOUT)? {
+            Some(Incoming::Pong) => Ok(connection),
+            Some(Incoming::Error(message)) => Err(io::Error::other(format!("nats: {}", message))),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "no PONG from NATS server")),
+        }
+    }
+}
+
+/// What the server sent
+enum Incoming {
+    Message { subject: String, reply: Option<String>, payload: Vec<u8> },
+    Pong,
+    Error(String),
+}
+
+struct Connection {
+    writer: TcpStream,
+    reader: BufReader<TcpStream>,
+    /// Start of a line that didn't arrive completely before a read timed out
+    line: String,
+}
+
+impl Connection {
+    fn publish(&mut self, subject: &str, payload: &[u8]) -> io::Result<()> {
+        let mut frame = format!("PUB {} {}\r\n", subject, payload.len()).into_bytes();
+        frame.extend_from_slice(payload);
+        frame.extend_from_slice(b"\r\n");
+        self.writer.write_all(&frame)
+    }
+
+    /// Wait up to `timeout` for the next message, answering the server's PINGs meanwhile
+    fn next_message(&mut self, timeout: Duration) -> io::Result<Option<Incoming>> {
+        self.writer.set_read_timeout(Some(timeout))?;
+        loop {
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "NATS server closed the connection")),
+                Ok(_) => {}
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => return Ok(None),
+                Err(e) => return Err(e),
+            }
+            if !self.line.ends_with('\n') {
+                continue;
+            }
+            let line = std::mem::take(&mut self.line);
+            let mut parts = line.split_whitespace();
+            match parts.next() {
+                Some("PING") => self.writer.write_all(b"PONG\r\n")?,
+                Some("PONG") => return Ok(Some(Incoming::Pong)),
+                Some("-ERR") => return Ok(Some(Incoming::Error(line[4..].trim().trim_matches('\'').to_string()))),
+                // MSG <subject> <sid> [reply-to] <#bytes>
+                Some("MSG") => {
+                    let parts: Vec<&str> = parts.collect();
+                    let (subject, reply, len) = match parts.as_slice() {
+                        [subject, _, len] => (*subject, None, *len),
+                        [subject, _, reply, len] => (*subject, Some(reply.to_string()), *len),
+                        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("malformed MSG {:?}", line.trim_end()))),
+                    };
+                    let len: usize = len.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "malformed MSG size"))?;
+                    self.writer.set_read_timeout(Some(PAYLOAD_TIMEOUT))?;
+                    let mut payload = vec![0; len + 2];
+                    self.reader.read_exact(&mut payload)?;
+                    payload.truncate(len);
+                    return Ok(Some(Incoming::Message { subject: subject.to_string(), reply, payload }));
+                }
+                // +OK, INFO updates
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Handle to a running connector
+pub struct NatsConnector {
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+    errors: ErrorLog,
+}
+
+impl NatsConnector {
+    /// Publish the events this node makes on `topic` (its partitions included) to `<subject>.<op>`
+    /// Replicated events are left to the nodes they were made on, so each is published once
+    /// when every node publishes the topic
+    pub fn publish<T>(node: Arc<Node<T>>, topic: &str, nats: &NatsServer, subject: &str) -> io::Result<Self>
+    where
+        T: Serialize + Send + Sync + 'static,
+    {
+        let queues = match node.partition_count(topic) {
+            Some(count) => (0..count).map(|p| partition_name(topic, p)).collect(),
+            None => vec![topic.to_string()],
+        };
+        // Connected up front, so a failure leaves no thread behind
+        let connections = queues.iter().map(|_| nats.connect()).collect::<io::Result<Vec<_>>>()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let errors = ErrorLog::default();
+        let mut threads = Vec::new();
+        for (queue, mut connection) in queues.into_iter().zip(connections) {
+            let entries = node.topic(&queue).subscribe_logs();
+            let (running, nats, subject, errors) = (stop.clone(), nats.clone(), subject.to_string(), errors.clone());
+            threads.push(thread::spawn(move || {
+                while !running.load(Ordering::SeqCst) {
+                    let entry = match entries.recv_timeout(POLL_INTERVAL) {
+                        Ok(entry) => entry,
+                        Err(RecvTimeoutError::Timeout) => {
+                            // Keep the connection alive
+                            if let Err(e) = connection.next_message(Duration::from_millis(1)) {
+                                errors.record(format_args!("lost NATS connection for {}: {}", subject, e));
+                                connection = match nats.connect() {
+                                    Ok(connection) => connection,
+                                    Err(_) => continue,
+                                };
+                            }
+                            continue;
+                        }
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    };
+                    if entry.event.origin_node != entry.local_node {
+                        continue;
+                    }
+                    let payload = match serde_json::to_vec(&entry.event) {
+                        Ok(payload) => payload,
+                        Err(e) => {
+                            errors.record(format_args!("cannot publish event {}: {}", entry.event.global_id, e));
+                            continue;
+                        }
+                    };
+                    let op_subject = format!("{}.{}", subject, entry.op);
+                    if connection.publish(&op_subject, &payload).is_err() {
+                        let published = nats.connect().and_then(|mut reconnected| {
+                            reconnected.publish(&op_subject, &payload)?;
+                            connection = reconnected;
+                            Ok(())
+                        });
+                        if let Err(e) = published {
+                            errors.record(format_args!("cannot publish event {} to {}: {}", entry.event.global_id, op_subject, e));
+                        }
+                    }
+                }
+            }));
+        }
+        Ok(Self { stop, threads, errors })
+    }
+
+    /// Enqueue the messages of `subject` onto `topic`
+    /// Nodes subscribing in the same `queue_group` share the messages; without one, every
+    /// subscribed node enqueues every message
+    pub fn subscribe<T>(node: Arc<Node<T>>, topic: &str, nats: &NatsServer, subject: &str, queue_group: Option<&str>) -> io::Result<Self>
+    where
+        T: DeserializeOwned + Send + Sync + 'static,
+    {
+        let subscription = match queue_group {
+            Some(group) => format!("SUB {} {} 1\r\n", subject, group),
+            None => format!("SUB {} 1\r\n", subject),
+        };
+        let mut connection = nats.connect()?;
+        connection.writer.write_all(subscription.as_bytes())?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let errors = ErrorLog::default();
+        let (running, nats, topic, failed) = (stop.clone(), nats.clone(), topic.to_string(), errors.clone());
+        let thread = thread::spawn(move || {
+            while !running.load(Ordering::SeqCst) {
+                let (subject, reply, payload) = match connection.next_message(POLL_INTERVAL) {
+                    Ok(Some(Incoming::Message { subject, reply, payload })) => (subject, reply, payload),
+                    Ok(Some(Incoming::Error(message))) => {
+                        failed.record(format_args!("NATS error on {}: {}", topic, message));
+                        continue;
+                    }
+                    Ok(_) => continue,
+                    Err(e) => {
+                        failed.record(format_args!("lost NATS subscription for {}: {}", topic, e));
+                        thread::sleep(POLL_INTERVAL);
+                        if let Ok(mut reconnected) = nats.connect()
+                            && reconnected.writer.write_all(subscription.as_bytes()).is_ok()
+                        {
+                            connection = reconnected;
+                        }
+                        continue;
+                    }
+                };
+                let text = String::from_utf8_lossy(&payload).into_owned();
+                let item = serde_json::from_str(&text).or_else(|_| serde_json::from_value(Value::String(text)));
+                let enqueued = match item {
+                    Ok(item) => node.enqueue(&topic, item).map(|_| ()).map_err(|e| (e.is_transient(), e.to_string())),
+                    Err(e) => Err((false, format!("not a valid item: {}", e))),
+                };
+                if let Err((_, e)) = &enqueued {
+                    failed.record(format_args!("cannot enqueue message from {} on {}: {}", subject, topic, e));
+                }
+                let Some(reply) = reply.filter(|reply| reply.starts_with(JETSTREAM_ACK_PREFIX)) else {
+                    continue;
+                };
+                let ack: &[u8] = match enqueued {
+                    Ok(()) => b"",
+                    Err((true, _)) => b"-NAK",
+                    Err((false, _)) => b"+TERM",
+                };
+                if let Err(e) = connection.publish(&reply, ack) {
+                    failed.record(format_args!("cannot ack message from {}: {}", subject, e));
+                }
+            }
+        });
+        Ok(Self { stop, threads: vec![thread], errors })
+    }
+
+    /// Errors the connector ran into, see `WorkerErrors`
+    pub fn errors(&self) -> WorkerErrors {
+        self.errors.snapshot()
+    }
+
+    /// Stop the connector and wait for it
+    pub fn shutdown(mut self) {
+        self.stop_and_join();
+    }
+
+    fn stop_and_join(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            thread.join().ok();
+        }
+    }
+}
+
+impl Drop for NatsConnector {
+    fn drop(&mut self) {
+        self.stop_and_join();
+    }
+}
@@ -0,0 +1,144 @@
+//! HMAC-SHA256 signing of replicated events and other peer messages
+//! Each node has its own key, and every node holds the keys of the nodes it
+//! accepts events from. An event is signed with its origin node's key, so a
+//! receiver can check the claimed `origin_node` before applying it; the other
+//! messages only peers send are signed with the sender's key, and rejected
+//! unsigned. Client requests and their responses are never signed.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use crate::core::buildcore::NodeId;
+use super::{Envelope, Message};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Why an envelope failed verification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthError {
+    /// No key for the node the message claims to come from
+    UnknownNode(NodeId),
+    MissingSignature,
+    BadSignature,
+    /// The message could not be encoded for signing
+    Encoding(String),
+}
+
+impl Display for AuthError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::UnknownNode(node) => write!(f, "no key for node {}", node),
+            AuthError::MissingSignature => write!(f, "message is not signed"),
+            AuthError::BadSignature => write!(f, "signature does not match"),
+            AuthError::Encoding(e) => write!(f, "cannot encode message: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+/// Per-node signing keys
+#[derive(Clone, Default)]
+pub struct Keyring {
+    keys: HashMap<NodeId, Vec<u8>>,
+}
+
+impl Keyring {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add or replace a node's key
+    pub fn with_key(mut self, node: &str, key: impl AsRef<[u8]>) -> Self {
+        self.keys.insert(NodeId::intern(node), key.as_ref().to_vec());
+        self
+    }
+
+    /// Check if the keyring has a key for `node`
+    pub fn contains(&self, node: &str) -> bool {
+        self.keys.contains_key(&NodeId::intern(node))
+    }
+
+    /// Sign an envelope with its signer's key, see `signer`; requests and responses are left unsigned
+    pub fn sign<T: Serialize>(&self, envelope: &mut Envelope<T>) -> Result<(), AuthError> {
+        let Some(origin) = signer(envelope) else {
+            return Ok(());
+        };
+        let mac = self.mac(origin, &envelope.message)?;
+        envelope.signature = Some(to_hex(&mac.finalize().into_bytes()));
+        Ok(())
+    }
+
+    /// Verify an envelope was signed by its signer; requests and responses always pass
+    pub fn verify<T: Serialize>(&self, envelope: &Envelope<T>) -> Result<(), AuthError> {
+        let Some(origin) = signer(envelope) else {
+            return Ok(());
+        };
+        let signature = envelope.signature.as_deref().ok_or(AuthError::MissingSignature)?;
+        let signature = from_hex(signature).ok_or(AuthError::BadSignature)?;
+        self.mac(origin, &envelope.message)?
+            .verify_slice(&signature)
+            .map_err(|_| AuthError::BadSignature)
+    }
+
+    fn mac<T: Serialize>(&self, origin: NodeId, message: &Message<T>) -> Result<HmacSha256, AuthError> {
+        let key = self.keys.get(&origin).ok_or(AuthError::UnknownNode(origin))?;
+        let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+        mac.update(&canonical_bytes(message)?);
+        Ok(mac)
+    }
+}
+
+/// Signs outgoing and checks incoming envelopes, for any payload type the implementor can encode
+pub trait Authenticator<T>: Send + Sync {
+    fn sign(&self, envelope: &mut Envelope<T>) -> Result<(), AuthError>;
+    fn verify(&self, envelope: &Envelope<T>) -> Result<(), AuthError>;
+}
+
+impl<T: Serialize> Authenticator<T> for Keyring {
+    fn sign(&self, envelope: &mut Envelope<T>) -> Result<(), AuthError> {
+        Keyring::sign(self, envelope)
+    }
+
+    fn verify(&self, envelope: &Envelope<T>) -> Result<(), AuthError> {
+        Keyring::verify(self, envelope)
+    }
+}
+
+/// Node whose key signs an envelope: an event's origin, which relays keep, or the
+/// sender of a message only peers send; None for client requests and responses
+fn signer<T>(envelope: &Envelope<T>) -> Option<NodeId> {
+    match &envelope.message {
+        Message::Event { event, .. } => Some(event.origin_node),
+        Message::Raft { .. }
+        | Message::Steal { .. }
+        | Message::Heartbeat { .. }
+        | Message::Backpressure { .. }
+        | Message::Ack { .. } => Some(envelope.from),
+        _ => None,
+    }
+}
+
+/// Encoding that is identical on sender and receiver
+/// Going through `serde_json::Value` sorts object keys, so hash map order doesn't matter
+fn canonical_bytes<T: Serialize>(message: &Message<T>) -> Result<Vec<u8>, AuthError> {
+    serde_json::to_value(message)
+        .and_then(|value| serde_json::to_vec(&value))
+        .map_err(|e| AuthError::Encoding(e.to_string()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
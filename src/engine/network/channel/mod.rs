@@ -0,0 +1,271 @@
+//! Ordered, reliable channels between nodes
+//! `ReliableTransport` numbers the events it sends to each peer and holds on to them
+//! until the peer acknowledges them, retransmitting what goes unacknowledged. The
+//! receiving side hands events up in the order they were sent, once each, so a lost or
+//! reordered envelope costs a retransmission instead of a stall in the causal buffer.
+//! Requests, responses and the other messages pass through untouched; every node of
+//! a cluster must use it, as acknowledgements are messages older nodes don't know.
+//! Given a keyring, it signs its acknowledgements and ignores unsigned or forged ones,
+//! which would otherwise release events from the retransmission window unseen.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::core::buildcore::NodeId;
+use super::{Envelope, Message, Transport};
+#[cfg(feature = "auth")]
+use super::{Authenticator, Keyring};
+
+/// How long an event goes unacknowledged before it is sent again, unless configured otherwise
+pub const DEFAULT_RETRANSMIT_AFTER: Duration = Duration::from_millis(200);
+/// Most unacknowledged events per peer before sends to it fail, unless configured otherwise
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 10_000;
+
+static NEXT_CHANNEL: AtomicU64 = AtomicU64::new(0);
+
+/// Position of an envelope on its sender's channel to the receiver
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelSeq {
+    /// The sender's channels, new each time it starts; a higher one replaces the last
+    pub channel: u64,
+    pub seq: u64,
+    /// Every envelope up to here was acknowledged, so a receiver that lost track of the
+    /// channel (e.g. restarted) resumes after it rather than waiting for them
+    pub acked: u64,
+}
+
+/// Sent envelopes the peer hasn't acknowledged yet
+struct Outgoing<T> {
+    next: u64,
+    acked: u64,
+    unacked: VecDeque<(Envelope<T>, Instant)>, // in sequence order, with when they were last sent
+}
+
+impl<T> Default for Outgoing<T> {
+    fn default() -> Self {
+        Self { next: 0, acked: 0, unacked: VecDeque::new() }
+    }
+}
+
+/// Where a peer's channel to us stands
+struct Incoming<T> {
+    channel: u64,
+    delivered: u64,
+    early: BTreeMap<u64, Envelope<T>>, // arrived ahead of a missing one
+}
+
+/// A transport whose events reach each peer in order and exactly once, see the module docs
+pub struct ReliableTransport<T, X> {
+    inner: X,
+    channel: u64,
+    retransmit_after: Duration,
+    max_in_flight: usize,
+    outgoing: Mutex<HashMap<NodeId, Outgoing<T>>>,
+    incoming: Mutex<HashMap<NodeId, Incoming<T>>>,
+    ready: Mutex<VecDeque<Envelope<T>>>, // released in order, not handed up yet
+    #[cfg(feature = "auth")]
+    auth: Option<Box<dyn Authenticator<T>>>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, X: Transport<T>> ReliableTransport<T, X> {
+    /// Run reliable channels over `inner`
+    pub fn new(inner: X) -> Self {
+        // Later starts get higher channels, so peers can tell a restart from a stale envelope
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_micros() as u64);
+        Self {
+            inner,
+            channel: (started << 8) | (NEXT_CHANNEL.fetch_add(1, Ordering::Relaxed) & 0xff),
+            retransmit_after: DEFAULT_RETRANSMIT_AFTER,
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            outgoing: Mutex::new(HashMap::new()),
+            incoming: Mutex::new(HashMap::new()),
+            ready: Mutex::new(VecDeque::new()),
+            #[cfg(feature = "auth")]
+            auth: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Send events again once they have gone unacknowledged this long
+    pub fn with_retransmit_after(mut self, after: Duration) -> Self {
+        self.retransmit_after = after;
+        self
+    }
+
+    /// Fail sends to a peer once this many of its events are unacknowledged
+    pub fn with_max_in_flight(mut self, max: usize) -> Self {
+        self.max_in_flight = max.max(1);
+        self
+    }
+
+    /// Sign acknowledgements with this node's key and ignore those not signed by their
+    /// sender; every node should use the keyring its `Node::with_keyring` does
+    #[cfg(feature = "auth")]
+    pub fn with_keyring(mut self, keyring: Keyring) -> Self
+    where
+        T: Serialize,
+    {
+        self.auth = Some(Box::new(keyring));
+        self
+    }
+
+    /// The transport the channels run over
+    pub fn inner(&self) -> &X {
+        &self.inner
+    }
+
+    /// Events sent to `peer` that it hasn't acknowledged yet
+    pub fn in_flight(&self, peer: &str) -> usize {
+        self.outgoing.lock().unwrap().get(&NodeId::from(peer)).map_or(0, |out| out.unacked.len())
+    }
+
+    /// Send the events that have gone unacknowledged too long again
+    fn retransmit(&self) {
+        let mut due = Vec::new();
+        let mut outgoing = self.outgoing.lock().unwrap();
+        for (&peer, out) in outgoing.iter_mut() {
+            for (envelope, sent) in out.unacked.iter_mut().filter(|(_, sent)| sent.elapsed() >= self.retransmit_after) {
+                *sent = Instant::now();
+                let mut copy = copy_event(envelope).expect("only events are sequenced");
+                if let Some(seq) = &mut copy.seq {
+                    seq.acked = out.acked;
+                }
+                due.push((peer, copy));
+            }
+        }
+        drop(outgoing);
+        for (peer, envelope) in due {
+            self.inner.send(peer, &envelope).ok();
+        }
+    }
+
+    /// Forget the events `peer` acknowledged
+    fn acknowledge(&self, peer: NodeId, channel: u64, seq: u64) {
+        if channel != self.channel {
+            return;
+        }
+        if let Some(out) = self.outgoing.lock().unwrap().get_mut(&peer) {
+            out.acked = out.acked.max(seq);
+            while out.unacked.front().and_then(|(envelope, _)| envelope.seq).is_some_and(|s| s.seq <= seq) {
+                out.unacked.pop_front();
+            }
+        }
+    }
+
+    /// Take in an envelope from the inner transport; returns it if it can be handed up now
+    fn receive(&self, envelope: Envelope<T>) -> Option<Envelope<T>> {
+        if let Message::Ack { channel, seq } = envelope.message {
+            #[cfg(feature = "auth")]
+            if let Some(auth) = &self.auth
+                && auth.verify(&envelope).is_err()
+            {
+                return None;
+            }
+            self.acknowledge(envelope.from, channel, seq);
+            return None;
+        }
+        let Some(ChannelSeq { channel, seq, acked }) = envelope.seq else {
+            return Some(envelope);
+        };
+        let from = envelope.from;
+        let mut incoming = self.incoming.lock().unwrap();
+        let state = incoming.entry(from).or_insert_with(|| Incoming { channel, delivered: acked, early: BTreeMap::new() });
+        if channel < state.channel {
+            return None; // from before the sender restarted
+        }
+        if channel > state.channel {
+            *state = Incoming { channel, delivered: acked, early: BTreeMap::new() };
+        }
+        if seq > state.delivered {
+            state.early.insert(seq, envelope);
+        }
+        let mut ready = self.ready.lock().unwrap();
+        while let Some(next) = state.early.remove(&(state.delivered + 1)) {
+            state.delivered += 1;
+            ready.push_back(next);
+        }
+        let delivered = state.delivered;
+        drop(incoming);
+        let next = ready.pop_front();
+        drop(ready);
+        // Duplicates are acknowledged again, in case the first ack got lost
+        #[allow(unused_mut)]
+        let mut ack = Envelope::new(self.inner.local_id(), Message::Ack { channel, seq: delivered });
+        #[cfg(feature = "auth")]
+        if let Some(auth) = &self.auth
+            && auth.sign(&mut ack).is_err()
+        {
+            return next;
+        }
+        self.inner.send(from, &ack).ok();
+        next
+    }
+}
+
+/// A copy of an event envelope, which only shares the payload; None for other messages
+fn copy_event<T>(envelope: &Envelope<T>) -> Option<Envelope<T>> {
+    let Message::Event { topic, event } = &envelope.message else {
+        return None;
+    };
+    let mut copy = Envelope::event(envelope.from, topic, event.clone()).with_trace(envelope.trace);
+    copy.signature = envelope.signature.clone();
+    copy.seq = envelope.seq;
+    Some(copy)
+}
+
+impl<T: Send + Sync, X: Transport<T>> Transport<T> for ReliableTransport<T, X> {
+    fn local_id(&self) -> NodeId {
+        self.inner.local_id()
+    }
+
+    fn peers(&self) -> Vec<NodeId> {
+        self.inner.peers()
+    }
+
+    /// Events are queued for retransmission even if this send fails, which is still reported
+    fn send(&self, peer: NodeId, envelope: &Envelope<T>) -> io::Result<()> {
+        let Some(mut copy) = copy_event(envelope) else {
+            return self.inner.send(peer, envelope);
+        };
+        let mut outgoing = self.outgoing.lock().unwrap();
+        let out = outgoing.entry(peer).or_default();
+        if out.unacked.len() >= self.max_in_flight {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!("{} events to {} are unacknowledged", out.unacked.len(), peer),
+            ));
+        }
+        out.next += 1;
+        copy.seq = Some(ChannelSeq { channel: self.channel, seq: out.next, acked: out.acked });
+        let sent = copy_event(&copy).expect("copied from an event");
+        out.unacked.push_back((copy, Instant::now()));
+        drop(outgoing);
+        self.inner.send(peer, &sent)
+    }
+
+    /// Also retransmits, so waits no longer than the retransmission interval at a time
+    fn recv_timeout(&self, timeout: Duration) -> Option<Envelope<T>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.retransmit();
+            if let Some(envelope) = self.ready.lock().unwrap().pop_front() {
+                return Some(envelope);
+            }
+            let wait = deadline.saturating_duration_since(Instant::now()).min(self.retransmit_after);
+            match self.inner.recv_timeout(wait) {
+                Some(envelope) => {
+                    if let Some(envelope) = self.receive(envelope) {
+                        return Some(envelope);
+                    }
+                }
+                None if Instant::now() >= deadline => return None,
+                None => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Length-prefixed framing for transports that carry bytes rather than lines
+//! A frame is its payload's length, the payload's CRC32 and the CRC32 of those 8 bytes,
+//! each a 4-byte big-endian integer, then the payload. Lengths over the codec's limit are
+//! refused on both sides, and a length is only used once the header checksum vouches for
+//! it, so nothing is allocated or skipped on a damaged length. A frame whose payload
+//! checksum doesn't match is skipped and decoding goes on with the next one; an oversized
+//! length or a damaged header means the stream is no longer in step, so the decoder
+//! refuses everything after it.
+//! The TCP transport frames its lines this way on connections where both ends announced
+//! `frames` in the handshake, and keeps plain lines, which older nodes expect, elsewhere.
+
+use std::fmt::{Display, Formatter};
+use std::io;
+use serde::Serialize;
+
+/// Bytes before a frame's payload
+pub const FRAME_HEADER_LEN: usize = 12;
+
+/// Header bytes covered by the header checksum: length and payload checksum
+const CHECKED_HEADER_LEN: usize = 8;
+
+/// What is wrong with a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    /// Announces a payload longer than the limit
+    Oversized { len: usize, max: usize },
+    /// The payload doesn't match its checksum
+    ChecksumMismatch,
+    /// The length or payload checksum doesn't match the header checksum
+    HeaderChecksumMismatch,
+    /// The input ended partway through a frame
+    Truncated { missing: usize },
+    /// More bytes follow a frame that should stand alone, as a datagram does
+    Trailing { extra: usize },
+}
+
+impl Display for FrameError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameError::Oversized { len, max } => write!(f, "frame of {} bytes exceeds the {} byte limit", len, max),
+            FrameError::ChecksumMismatch => write!(f, "frame checksum does not match"),
+            FrameError::HeaderChecksumMismatch => write!(f, "frame header checksum does not match"),
+            FrameError::Truncated { missing } => write!(f, "frame is truncated, {} bytes missing", missing),
+            FrameError::Trailing { extra } => write!(f, "{} bytes follow the frame", extra),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<FrameError> for io::Error {
+    fn from(e: FrameError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// Encodes frames, and decodes them with a `FrameDecoder`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameCodec {
+    max_len: usize,
+}
+
+impl FrameCodec {
+    /// Frames with payloads of up to `max_len` bytes
+    pub const fn new(max_len: usize) -> Self {
+        Self { max_len: if max_len < u32::MAX as usize { max_len } else { u32::MAX as usize } }
+    }
+
+    /// Longest payload sent or accepted, in bytes
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    pub fn encode(&self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        if payload.len() > self.max_len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, FrameError::Oversized { len: payload.len(), max: self.max_len }));
+        }
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+        frame.extend((payload.len() as u32).to_be_bytes());
+        frame.extend(crc32fast::hash(payload).to_be_bytes());
+        frame.extend(crc32fast::hash(&frame).to_be_bytes());
+        frame.extend(payload);
+        Ok(frame)
+    }
+
+    /// Encode a value's JSON as one frame
+    pub fn encode_json<T: Serialize>(&self, value: &T) -> io::Result<Vec<u8>> {
+        self.encode(&serde_json::to_vec(value).map_err(io::Error::other)?)
+    }
+
+    /// The payload of a buffer holding exactly one frame, e.g. a datagram
+    pub fn decode_one<'a>(&self, bytes: &'a [u8]) -> Result<&'a [u8], FrameError> {
+        let Some(len) = self.header(bytes)? else {
+            return Err(FrameError::Truncated { missing: FRAME_HEADER_LEN - bytes.len() });
+        };
+        let end = FRAME_HEADER_LEN + len;
+        match bytes.len().cmp(&end) {
+            std::cmp::Ordering::Less => Err(FrameError::Truncated { missing: end - bytes.len() }),
+            std::cmp::Ordering::Greater => Err(FrameError::Trailing { extra: bytes.len() - end }),
+            std::cmp::Ordering::Equal => check(bytes).map(|()| &bytes[FRAME_HEADER_LEN..]),
+        }
+    }
+
+    /// A decoder for one stream of frames
+    pub fn decoder(&self) -> FrameDecoder {
+        FrameDecoder { codec: *self, buf: Vec::new(), start: 0, broken: None }
+    }
+
+    /// Payload length announced by a complete header, checked against the header checksum
+    /// and the limit; for readers that need it before reading the payload
+    pub fn payload_len(&self, header: &[u8; FRAME_HEADER_LEN]) -> Result<usize, FrameError> {
+        self.header(header).map(|len| len.expect("complete header"))
+    }
+
+    /// Payload length announced by a frame's header, None if the header is incomplete
+    /// An oversized length is refused as soon as it has arrived, before the rest of the header.
+    fn header(&self, bytes: &[u8]) -> Result<Option<usize>, FrameError> {
+        let Some(len) = bytes.get(..4) else {
+            return Ok(None);
+        };
+        let len = u32::from_be_bytes(len.try_into().expect("4 bytes")) as usize;
+        if len > self.max_len {
+            return Err(FrameError::Oversized { len, max: self.max_len });
+        }
+        let Some(crc) = bytes.get(CHECKED_HEADER_LEN..FRAME_HEADER_LEN) else {
+            return Ok(None);
+        };
+        if crc32fast::hash(&bytes[..CHECKED_HEADER_LEN]) != u32::from_be_bytes(crc.try_into().expect("4 bytes")) {
+            return Err(FrameError::HeaderChecksumMismatch);
+        }
+        Ok(Some(len))
+    }
+}
+
+/// Check the payload of a complete frame against its header
+fn check(frame: &[u8]) -> Result<(), FrameError> {
+    let crc = u32::from_be_bytes(frame[4..CHECKED_HEADER_LEN].try_into().expect("4 bytes"));
+    if crc32fast::hash(&frame[FRAME_HEADER_LEN..]) != crc {
+        return Err(FrameError::ChecksumMismatch);
+    }
+    Ok(())
+}
+
+/// Reassembles frames from bytes as they arrive, in pieces of any size
+pub struct FrameDecoder {
+    codec: FrameCodec,
+    buf: Vec<u8>,
+    start: usize, // of the first frame not decoded yet
+    broken: Option<FrameError>,
+}
+
+impl FrameDecoder {
+    /// Take in bytes read from the stream
+    pub fn push(&mut self, bytes: &[u8]) {
+        if self.broken.is_some() {
+            return;
+        }
+        if self.start > 0 && self.start >= self.buf.len() / 2 {
+            self.buf.drain(..self.start);
+            self.start = 0;
+        }
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// The next complete frame's payload; `Ok(None)` until one is complete
+    /// A frame failing its payload checksum is skipped, so the call after it goes on with the
+    /// next. After an oversized one or a damaged header every call fails, see the module docs.
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, FrameError> {
+        if let Some(e) = self.broken {
+            return Err(e);
+        }
+        let pending = &self.buf[self.start..];
+        let len = match self.codec.header(pending) {
+            Ok(Some(len)) => len,
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                self.broken = Some(e);
+                self.buf = Vec::new();
+                self.start = 0;
+                return Err(e);
+            }
+        };
+        let Some(frame) = pending.get(..FRAME_HEADER_LEN + len) else {
+            return Ok(None);
+        };
+        let checked = check(frame).map(|()| frame[FRAME_HEADER_LEN..].to_vec());
+        self.start += FRAME_HEADER_LEN + len;
+        checked.map(Some)
+    }
+
+    /// Bytes of a frame that isn't complete yet
+    pub fn pending(&self) -> usize {
+        self.buf.len() - self.start
+    }
+
+    /// Whether the decoder gave up on the stream after an oversized frame or a damaged header
+    pub fn is_broken(&self) -> bool {
+        self.broken.is_some()
+    }
+
+    /// Call once the stream has ended: fails if it ended partway through a frame
+    pub fn finish(&self) -> Result<(), FrameError> {
+        if let Some(e) = self.broken {
+            return Err(e);
+        }
+        let pending = &self.buf[self.start..];
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let missing = match self.codec.header(pending) {
+            Ok(Some(len)) => FRAME_HEADER_LEN + len - pending.len(),
+            _ => FRAME_HEADER_LEN.saturating_sub(pending.len()),
+        };
+        Err(FrameError::Truncated { missing })
+    }
+}
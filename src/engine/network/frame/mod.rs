@@ -0,0 +1,460 @@
+//! Line framing for the peer protocol
+//! Every line is a JSON envelope, a `hello` announcing the sender's protocol
+//! versions and capabilities,
+//! a protobuf envelope (`protobuf` feature) or a zstd-compressed line
+//! (`compress` feature). Protobuf and compressed frames are only sent on
+//! connections where both ends announced support.
+//! Lines longer than the frame limit are split into `chunk` lines, reassembled
+//! and checksummed by the receiving side before the envelope is decoded.
+//! Between nodes that both announce `frames`, each line travels as a `FrameCodec`
+//! frame instead, length-prefixed and checksummed; receivers take either.
+
+use std::io::{self, BufRead, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use super::{Envelope, FRAME_HEADER_LEN, FrameCodec, FrameError, Stream};
+#[cfg(any(feature = "compress", feature = "protobuf"))]
+use base64::Engine;
+#[cfg(any(feature = "compress", feature = "protobuf"))]
+use base64::engine::general_purpose::STANDARD as BASE64;
+
+/// How long a connecting side waits for the hello reply; nodes predating
+/// the handshake never answer
+const HELLO_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Longest line sent or accepted, in bytes, unless configured otherwise
+pub const DEFAULT_MAX_FRAME_LEN: usize = 1 << 20;
+/// Smallest configurable frame limit; chunks need room for their header
+pub const MIN_FRAME_LEN: usize = 256;
+/// Largest message accepted after reassembly
+const MAX_MESSAGE_LEN: usize = 256 << 20;
+/// Room left in a chunk line for everything but the data
+const CHUNK_OVERHEAD: usize = 128;
+/// Longest line sent as a frame; longer ones are chunked, so a frame's first byte is
+/// always 0 and never mistaken for the start of a line
+const MAX_FRAMED_LEN: usize = (1 << 24) - 1;
+
+static NEXT_CHUNKED: AtomicU64 = AtomicU64::new(1);
+
+/// Peer protocol version of this build; bump on changes older nodes would misread
+/// 1: JSON lines and compression, 2: chunking, protobuf and version negotiation
+pub const PROTOCOL_VERSION: u32 = 2;
+/// Oldest protocol version this build still speaks
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+fn first_version() -> u32 {
+    1
+}
+
+/// Protocol versions and capabilities announced when a connection opens
+/// Both ends speak the highest version they have in common, and only use
+/// capabilities both announced
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Hello {
+    /// Highest version spoken; nodes predating versioning announce none and speak 1
+    #[serde(default = "first_version")]
+    pub version: u32,
+    /// Lowest version spoken
+    #[serde(default = "first_version")]
+    pub min_version: u32,
+    #[serde(default)]
+    pub compression: Vec<String>,
+    /// Envelope encodings besides JSON
+    #[serde(default)]
+    pub encodings: Vec<String>,
+    /// Other optional features by name, so new ones need no handshake change
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+/// What a node that predates versioning implies
+impl Default for Hello {
+    fn default() -> Self {
+        Self { version: 1, min_version: 1, compression: Vec::new(), encodings: Vec::new(), features: Vec::new() }
+    }
+}
+
+/// Name of the protobuf encoding in the handshake
+pub const PROTOBUF: &str = "protobuf";
+/// Name of length-prefixed, checksummed framing among the handshake's features
+pub const FRAMES: &str = "frames";
+
+#[derive(Serialize, Deserialize)]
+struct HelloLine {
+    hello: Hello,
+}
+
+#[cfg(feature = "compress")]
+#[derive(Serialize, Deserialize)]
+struct ZstdLine {
+    zstd: String,
+}
+
+#[cfg(feature = "protobuf")]
+#[derive(Serialize, Deserialize)]
+struct ProtobufLine {
+    pb: String,
+}
+
+/// One piece of a line too long to send whole
+#[derive(Serialize, Deserialize)]
+struct Chunk {
+    id: u64,
+    index: u32,
+    count: u32,
+    crc32: u32, // of the whole line
+    data: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChunkLine {
+    chunk: Chunk,
+}
+
+/// Compression of large frames
+#[cfg(feature = "compress")]
+#[derive(Clone, Copy, Debug)]
+pub struct Compression {
+    threshold: usize,
+    level: i32,
+}
+
+#[cfg(feature = "compress")]
+impl Compression {
+    /// Algorithm name used in the handshake
+    pub const ZSTD: &'static str = "zstd";
+
+    /// Compress frames of 1 KiB and more at the default level
+    pub fn zstd() -> Self {
+        Self { threshold: 1024, level: zstd::DEFAULT_COMPRESSION_LEVEL }
+    }
+
+    /// Smallest encoded frame, in bytes, worth compressing
+    pub fn with_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// zstd level, 1 (fast) to 22 (small)
+    pub fn with_level(mut self, level: i32) -> Self {
+        self.level = level;
+        self
+    }
+}
+
+/// Compression settings; always `None` without the `compress` feature
+#[cfg(feature = "compress")]
+pub(crate) type Compressor = Option<Compression>;
+#[cfg(not(feature = "compress"))]
+pub(crate) type Compressor = Option<std::convert::Infallible>;
+
+/// Protocol version and how frames are encoded beyond plain JSON lines
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Codec {
+    pub(crate) version: u32,
+    pub(crate) compression: Compressor,
+    pub(crate) protobuf: bool,
+    pub(crate) frames: bool,
+}
+
+impl Codec {
+    /// What a node offers before any configuration: frames, and protobuf if built with it
+    pub(crate) fn local() -> Self {
+        Self { version: PROTOCOL_VERSION, compression: None, protobuf: cfg!(feature = "protobuf"), frames: true }
+    }
+}
+
+/// Plain JSON lines at version 1, what is spoken with nodes that predate the handshake
+impl Default for Codec {
+    fn default() -> Self {
+        Self { version: 1, compression: None, protobuf: false, frames: false }
+    }
+}
+
+/// What this side announces
+pub(crate) fn local_hello(codec: &Codec) -> Hello {
+    let mut hello = Hello { version: PROTOCOL_VERSION, min_version: MIN_PROTOCOL_VERSION, ..Hello::default() };
+    #[cfg(feature = "compress")]
+    if codec.compression.is_some() {
+        hello.compression.push(Compression::ZSTD.to_string());
+    }
+    if codec.protobuf {
+        hello.encodings.push(PROTOBUF.to_string());
+    }
+    if codec.frames {
+        hello.features.push(FRAMES.to_string());
+    }
+    hello
+}
+
+/// Settings to send with once the other side has announced `remote`
+/// Fails if the two sides have no protocol version in common
+pub(crate) fn negotiate(codec: &Codec, remote: &Hello) -> io::Result<Codec> {
+    let version = PROTOCOL_VERSION.min(remote.version);
+    if version < MIN_PROTOCOL_VERSION || version < remote.min_version {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "no common protocol version: this node speaks {}-{}, the peer {}-{}",
+                MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, remote.min_version, remote.version
+            ),
+        ));
+    }
+    #[cfg(feature = "compress")]
+    let compression = codec.compression.filter(|_| remote.compression.iter().any(|c| c == Compression::ZSTD));
+    #[cfg(not(feature = "compress"))]
+    let compression = None;
+    Ok(Codec {
+        version,
+        compression,
+        protobuf: codec.protobuf && remote.encodings.iter().any(|e| e == PROTOBUF),
+        frames: codec.frames && remote.features.iter().any(|f| f == FRAMES),
+    })
+}
+
+/// A decoded line
+#[allow(clippy::large_enum_variant)] // short-lived, boxing the envelope would only add an allocation
+pub(crate) enum Frame<T> {
+    Envelope(Envelope<T>),
+    Hello(Hello),
+}
+
+pub(crate) fn hello_line(hello: &Hello) -> io::Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(&HelloLine { hello: hello.clone() }).map_err(io::Error::other)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// Encode an envelope as one line, as protobuf if `codec` allows, and compressed
+/// if it allows and the line is large enough, then as a frame if it allows
+/// Lines longer than `max_frame` are sent as several chunk lines
+pub(crate) fn encode<T: Serialize>(envelope: &Envelope<T>, codec: &Codec, max_frame: usize) -> io::Result<Vec<u8>> {
+    let max_frame = if codec.frames { max_frame.min(MAX_FRAMED_LEN) } else { max_frame };
+    let lines = encode_lines(envelope, codec, max_frame)?;
+    if !codec.frames {
+        return Ok(lines);
+    }
+    let framing = FrameCodec::new(MAX_FRAMED_LEN);
+    let mut frames = Vec::with_capacity(lines.len() + FRAME_HEADER_LEN);
+    for line in lines.split(|&byte| byte == b'\n').filter(|line| !line.is_empty()) {
+        frames.extend(framing.encode(line)?);
+    }
+    Ok(frames)
+}
+
+#[cfg_attr(not(any(feature = "compress", feature = "protobuf")), allow(unused_variables))]
+fn encode_lines<T: Serialize>(envelope: &Envelope<T>, codec: &Codec, max_frame: usize) -> io::Result<Vec<u8>> {
+    #[cfg(feature = "protobuf")]
+    let mut line = if codec.protobuf {
+        let pb = BASE64.encode(super::wire::encode(envelope)?);
+        serde_json::to_vec(&ProtobufLine { pb }).map_err(io::Error::other)?
+    } else {
+        serde_json::to_vec(envelope).map_err(io::Error::other)?
+    };
+    #[cfg(not(feature = "protobuf"))]
+    let mut line = serde_json::to_vec(envelope).map_err(io::Error::other)?;
+    #[cfg(feature = "compress")]
+    if let Some(compression) = codec.compression
+        && line.len() >= compression.threshold
+    {
+        let packed = zstd::encode_all(line.as_slice(), compression.level)?;
+        line = serde_json::to_vec(&ZstdLine { zstd: BASE64.encode(packed) }).map_err(io::Error::other)?;
+    }
+    if line.len() >= max_frame {
+        return chunk_lines(line, max_frame);
+    }
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// Split a line into chunk lines of at most `max_frame` bytes each
+fn chunk_lines(line: Vec<u8>, max_frame: usize) -> io::Result<Vec<u8>> {
+    if line.len() > MAX_MESSAGE_LEN {
+        return Err(invalid(format!("message of {} bytes exceeds the {} byte limit", line.len(), MAX_MESSAGE_LEN)));
+    }
+    let line = String::from_utf8(line).map_err(io::Error::other)?;
+    let parts = split_escaped(&line, max_frame.max(MIN_FRAME_LEN) - CHUNK_OVERHEAD);
+    let (id, crc32) = (NEXT_CHUNKED.fetch_add(1, Ordering::Relaxed), crc32fast::hash(line.as_bytes()));
+    let mut lines = Vec::with_capacity(line.len() + parts.len() * CHUNK_OVERHEAD);
+    for (index, data) in parts.iter().enumerate() {
+        let chunk = Chunk { id, index: index as u32, count: parts.len() as u32, crc32, data: data.to_string() };
+        serde_json::to_writer(&mut lines, &ChunkLine { chunk }).map_err(io::Error::other)?;
+        lines.push(b'\n');
+    }
+    Ok(lines)
+}
+
+/// Split on character boundaries so each piece takes at most `budget` bytes once
+/// escaped as a JSON string; the line is compact JSON, so only quotes and
+/// backslashes grow
+fn split_escaped(line: &str, budget: usize) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut size) = (0, 0);
+    for (i, c) in line.char_indices() {
+        let cost = if matches!(c, '"' | '\\') { 2 } else { c.len_utf8() };
+        if size + cost > budget && i > start {
+            parts.push(&line[start..i]);
+            (start, size) = (i, 0);
+        }
+        size += cost;
+    }
+    parts.push(&line[start..]);
+    parts
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Decode one line
+pub(crate) fn decode<T: DeserializeOwned>(line: &str) -> io::Result<Frame<T>> {
+    if line.starts_with(r#"{"hello""#) {
+        let hello: HelloLine = serde_json::from_str(line).map_err(io::Error::other)?;
+        return Ok(Frame::Hello(hello.hello));
+    }
+    if line.starts_with(r#"{"zstd""#) {
+        #[cfg(feature = "compress")]
+        {
+            let packed: ZstdLine = serde_json::from_str(line).map_err(io::Error::other)?;
+            let packed = BASE64.decode(packed.zstd).map_err(io::Error::other)?;
+            let inner = String::from_utf8(zstd::decode_all(packed.as_slice())?).map_err(io::Error::other)?;
+            return decode(&inner);
+        }
+        #[cfg(not(feature = "compress"))]
+        return Err(invalid("compressed frame, but built without the `compress` feature"));
+    }
+    if line.starts_with(r#"{"pb""#) {
+        #[cfg(feature = "protobuf")]
+        {
+            let line: ProtobufLine = serde_json::from_str(line).map_err(io::Error::other)?;
+            let bytes = BASE64.decode(line.pb).map_err(io::Error::other)?;
+            return super::wire::decode(&bytes).map(Frame::Envelope);
+        }
+        #[cfg(not(feature = "protobuf"))]
+        return Err(invalid("protobuf frame, but built without the `protobuf` feature"));
+    }
+    serde_json::from_str(line).map(Frame::Envelope).map_err(io::Error::other)
+}
+
+/// Read the next line or frame, whichever the other side sent, without its newline or header
+/// Frames failing their payload checksum are skipped, a damaged header fails the read; None
+/// once the connection is closed.
+/// The frame limit is left to the `Decoder`, as for lines.
+pub(crate) fn read_frame(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let framing = FrameCodec::new(MAX_FRAMED_LEN);
+    loop {
+        let Some(&first) = reader.fill_buf()?.first() else {
+            return Ok(None);
+        };
+        if first != 0 {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            let len = line.trim_end_matches(['\r', '\n']).len();
+            line.truncate(len);
+            return Ok(Some(line));
+        }
+        let mut header = [0; FRAME_HEADER_LEN];
+        reader.read_exact(&mut header)?;
+        // A damaged length would leave the connection out of step, so it ends here
+        let len = framing.payload_len(&header)?;
+        let mut frame = header.to_vec();
+        frame.resize(FRAME_HEADER_LEN + len, 0);
+        reader.read_exact(&mut frame[FRAME_HEADER_LEN..])?;
+        match framing.decode_one(&frame) {
+            Ok(line) => return String::from_utf8(line.to_vec()).map(Some).map_err(io::Error::other),
+            Err(FrameError::ChecksumMismatch) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Decodes the lines of one connection, reassembling chunked ones
+pub(crate) struct Decoder {
+    max_frame: usize,
+    partial: Option<Partial>,
+}
+
+/// Chunks of one line received so far
+struct Partial {
+    id: u64,
+    count: u32,
+    crc32: u32,
+    data: String,
+    received: u32,
+}
+
+impl Decoder {
+    pub(crate) fn new(max_frame: usize) -> Self {
+        Self { max_frame: max_frame.max(MIN_FRAME_LEN), partial: None }
+    }
+
+    /// Decode one line; `Ok(None)` while a chunked line is still incomplete
+    /// A bad chunk discards the whole line it belongs to
+    pub(crate) fn decode<T: DeserializeOwned>(&mut self, line: &str) -> io::Result<Option<Frame<T>>> {
+        if line.len() > self.max_frame {
+            self.partial = None;
+            return Err(invalid(format!("frame of {} bytes exceeds the {} byte limit", line.len(), self.max_frame)));
+        }
+        if !line.starts_with(r#"{"chunk""#) {
+            return decode(line).map(Some);
+        }
+        let chunk = serde_json::from_str::<ChunkLine>(line).map_err(io::Error::other)?.chunk;
+        let mut partial = match self.partial.take() {
+            Some(partial) if partial.id == chunk.id => partial,
+            _ if chunk.index == 0 => Partial { id: chunk.id, count: chunk.count, crc32: chunk.crc32, data: String::new(), received: 0 },
+            _ => return Err(invalid(format!("chunk {} of a message whose start was lost", chunk.index))),
+        };
+        if chunk.index != partial.received || chunk.count != partial.count || chunk.crc32 != partial.crc32 {
+            return Err(invalid("chunk out of sequence"));
+        }
+        if partial.data.len() + chunk.data.len() > MAX_MESSAGE_LEN {
+            return Err(invalid(format!("chunked message exceeds the {} byte limit", MAX_MESSAGE_LEN)));
+        }
+        partial.data.push_str(&chunk.data);
+        partial.received += 1;
+        if partial.received < partial.count {
+            self.partial = Some(partial);
+            return Ok(None);
+        }
+        if crc32fast::hash(partial.data.as_bytes()) != partial.crc32 {
+            return Err(invalid("reassembled message does not match its checksum"));
+        }
+        // A chunk never contains another chunked line
+        if partial.data.starts_with(r#"{"chunk""#) {
+            return Err(invalid("nested chunked message"));
+        }
+        decode(&partial.data).map(Some)
+    }
+}
+
+/// Announce versions and `codec` on a freshly opened connection and agree on what to send with
+/// Falls back to version 1 if the other side doesn't answer in time, and fails
+/// if it answers with versions this node doesn't speak
+pub(crate) fn handshake(stream: &mut Stream, codec: &Codec) -> io::Result<Codec> {
+    stream.write_all(&hello_line(&local_hello(codec))?)?;
+    let timeout = stream.read_timeout()?;
+    stream.set_read_timeout(Some(HELLO_TIMEOUT))?;
+    let reply = read_line_unbuffered(stream);
+    stream.set_read_timeout(timeout)?;
+    match reply.ok().and_then(|line| decode::<()>(&line).ok()) {
+        Some(Frame::Hello(remote)) => negotiate(codec, &remote),
+        _ => Ok(Codec::default()),
+    }
+}
+
+/// Read one line without buffering past it, so the stream can be handed on
+fn read_line_unbuffered(stream: &mut Stream) -> io::Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        if stream.read(&mut byte)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed during handshake"));
+        }
+        if byte[0] == b'\n' {
+            break;
+        }
+        line.push(byte[0]);
+    }
+    String::from_utf8(line).map_err(io::Error::other)
+}
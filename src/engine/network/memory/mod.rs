@@ -0,0 +1,154 @@
+//! In-memory transport
+//! Nodes of one process exchange envelopes over the channels of a shared `MemoryNetwork`,
+//! so the networked code paths can be tested without sockets. Envelopes go through JSON on
+//! the way, as on a real wire, and can be delayed by a `Latency` or dropped at random,
+//! reproducibly for a given seed. Unlike the simulated `Cluster`, time is real.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::{Serialize, de::DeserializeOwned};
+use crate::core::buildcore::NodeId;
+use crate::engine::process::{Latency, SimRng};
+use super::{Envelope, Transport};
+
+/// An envelope on its way, with when it is due
+type Sent<T> = (Instant, Envelope<T>);
+
+/// Channels between the nodes that joined, see the module docs
+pub struct MemoryNetwork<T> {
+    inboxes: Mutex<HashMap<NodeId, Sender<Sent<T>>>>,
+    latency: Latency,
+    loss: f64,
+    rng: Mutex<SimRng>,
+}
+
+impl<T> Default for MemoryNetwork<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> MemoryNetwork<T> {
+    /// Envelopes arrive as soon as they are sent, and none are lost
+    pub fn new() -> Self {
+        Self { inboxes: Mutex::new(HashMap::new()), latency: Latency::ZERO, loss: 0.0, rng: Mutex::new(SimRng(0)) }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Mutex::new(SimRng(seed));
+        self
+    }
+
+    /// Delay every envelope by a sample of `latency`
+    pub fn with_latency(mut self, latency: Latency) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Drop each envelope with this probability, between 0 and 1
+    pub fn with_loss(mut self, probability: f64) -> Self {
+        self.loss = probability.clamp(0.0, 1.0);
+        self
+    }
+
+    /// A transport for node `id`, whose peers are all the other nodes that joined
+    pub fn join(self: &Arc<Self>, id: &str) -> MemoryTransport<T> {
+        let (tx, rx) = mpsc::channel();
+        let local = NodeId::intern(id);
+        self.inboxes.lock().unwrap().insert(local, tx);
+        MemoryTransport { local, network: self.clone(), inbox: Mutex::new(Inbox { rx, held: BTreeMap::new(), received: 0 }) }
+    }
+
+    /// Take node `id` off the network: sends to it fail as to a node that is down
+    /// Its transport receives nothing more until it joins again.
+    pub fn leave(&self, id: &str) {
+        self.inboxes.lock().unwrap().remove(&NodeId::from(id));
+    }
+
+    /// When an envelope sent now is due, or None if it is lost
+    fn due(&self) -> Option<Instant> {
+        let mut rng = self.rng.lock().unwrap();
+        if self.loss > 0.0 && rng.next_f64() < self.loss {
+            return None;
+        }
+        Some(Instant::now() + self.latency.sample(&mut rng))
+    }
+}
+
+/// Envelopes sent to a node, held until they are due
+struct Inbox<T> {
+    rx: Receiver<Sent<T>>,
+    held: BTreeMap<(Instant, u64), Envelope<T>>, // by when they are due, then arrival
+    received: u64,
+}
+
+impl<T> Inbox<T> {
+    fn hold(&mut self, due: Instant, envelope: Envelope<T>) {
+        self.received += 1;
+        self.held.insert((due, self.received), envelope);
+    }
+}
+
+/// One node's end of a `MemoryNetwork`
+pub struct MemoryTransport<T> {
+    local: NodeId,
+    network: Arc<MemoryNetwork<T>>,
+    inbox: Mutex<Inbox<T>>,
+}
+
+impl<T> MemoryTransport<T> {
+    /// The network this transport joined
+    pub fn network(&self) -> &Arc<MemoryNetwork<T>> {
+        &self.network
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Send + Sync> Transport<T> for MemoryTransport<T> {
+    fn local_id(&self) -> NodeId {
+        self.local
+    }
+
+    fn peers(&self) -> Vec<NodeId> {
+        self.network.inboxes.lock().unwrap().keys().copied().filter(|&peer| peer != self.local).collect()
+    }
+
+    /// Lost envelopes count as sent, as they would on a real network
+    fn send(&self, peer: NodeId, envelope: &Envelope<T>) -> io::Result<()> {
+        let Some(tx) = self.network.inboxes.lock().unwrap().get(&peer).cloned() else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("unknown peer {}", peer)));
+        };
+        let Some(due) = self.network.due() else {
+            return Ok(());
+        };
+        let copy = serde_json::from_slice(&serde_json::to_vec(envelope)?)?;
+        tx.send((due, copy)).map_err(|_| io::Error::new(io::ErrorKind::ConnectionRefused, format!("{} left the network", peer)))
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Option<Envelope<T>> {
+        let deadline = Instant::now() + timeout;
+        let mut inbox = self.inbox.lock().unwrap();
+        loop {
+            while let Ok((due, envelope)) = inbox.rx.try_recv() {
+                inbox.hold(due, envelope);
+            }
+            let now = Instant::now();
+            if let Some(next) = inbox.held.first_entry().filter(|next| next.key().0 <= now) {
+                return Some(next.remove());
+            }
+            if now >= deadline {
+                return None;
+            }
+            let wake = inbox.held.keys().next().map_or(deadline, |&(due, _)| due.min(deadline));
+            match inbox.rx.recv_timeout(wake - now) {
+                Ok((due, envelope)) => inbox.hold(due, envelope),
+                Err(RecvTimeoutError::Timeout) => {}
+                // Left the network; envelopes already on their way still arrive
+                Err(RecvTimeoutError::Disconnected) => thread::sleep(wake - now),
+            }
+        }
+    }
+}
@@ -0,0 +1,650 @@
+//! Inter-node transport: envelopes carrying topic events between nodes,
+//! and client requests answered over the same connection
+
+use std::collections::HashMap;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use crate::core::buildcore::{Event, FailedEvent, LogEntry, NodeId, QueueStats, TraceContext};
+use crate::engine::namespace::NamespaceStats;
+use crate::engine::stats::TopicStats;
+use crate::engine::raft::{Command, RaftMessage};
+use crate::engine::transfer::SnapshotChunk;
+
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(feature = "tls")]
+pub use tls::TlsConfig;
+#[cfg(feature = "udp")]
+mod udp;
+#[cfg(feature = "udp")]
+pub use udp::{MAX_DATAGRAM_LEN, UdpTransport};
+#[cfg(feature = "quic")]
+mod quic;
+#[cfg(feature = "quic")]
+pub use quic::{Lane, QuicTransport};
+#[cfg(feature = "sim")]
+mod memory;
+#[cfg(feature = "sim")]
+pub use memory::{MemoryNetwork, MemoryTransport};
+#[cfg(feature = "auth")]
+mod auth;
+#[cfg(feature = "auth")]
+pub use auth::{AuthError, Authenticator, Keyring};
+mod codec;
+pub use codec::{FRAME_HEADER_LEN, FrameCodec, FrameDecoder, FrameError};
+mod outbound;
+pub use outbound::{DEFAULT_MAX_HELD, DEFAULT_MAX_RECONNECT_BACKOFF, DEFAULT_RECONNECT_BACKOFF, PeerHealth};
+use outbound::Outbound;
+mod channel;
+pub use channel::{ChannelSeq, DEFAULT_MAX_IN_FLIGHT, DEFAULT_RETRANSMIT_AFTER, ReliableTransport};
+pub(crate) mod frame;
+pub use frame::{DEFAULT_MAX_FRAME_LEN, Hello, MIN_FRAME_LEN, MIN_PROTOCOL_VERSION, PROTOBUF, PROTOCOL_VERSION};
+#[cfg(feature = "protobuf")]
+pub mod wire;
+#[cfg(feature = "compress")]
+pub use frame::Compression;
+pub(crate) use frame::{Codec, Decoder, Frame};
+
+/// How long to wait when connecting to a peer
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Messages exchanged between nodes
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Message<T> {
+    /// A replicated queue event for a topic
+    Event { topic: String, event: Event<T> },
+    /// A client request, answered with a `Response` carrying the same id
+    /// The token identifies the client to the node's access control
+    Request {
+        id: u64,
+        request: Request<T>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+    },
+    /// Reply to a client request
+    Response { id: u64, response: Response<T> },
+    /// An idle node asking the owner of `queue` to transfer it up to `max` items
+    Steal { queue: String, max: usize },
+    /// A node's liveness and progress on each queue it hosts, sent periodically
+    Heartbeat { queues: HashMap<String, Heartbeat> },
+    /// The sender's `queue` is near capacity (`engaged`) or has drained again;
+    /// peers hold their events for that queue back while it is engaged
+    Backpressure { queue: String, engaged: bool },
+    /// The sender got every event numbered up to `seq` on our reliable `channel` to it
+    Ack { channel: u64, seq: u64 },
+    /// Between the members of a Raft cluster, see `Node::with_raft`
+    Raft { message: RaftMessage<Command<T>> },
+}
+
+/// One queue's state in a heartbeat
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Heartbeat {
+    /// Events the sender has delivered from each node
+    pub clock: HashMap<NodeId, u64>,
+    pub depth: usize,
+}
+
+/// Operations a client can ask a node to perform
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Request<T> {
+    /// With a key, partitioned topics route the item by it
+    Enqueue {
+        topic: String,
+        item: T,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        key: Option<String>,
+    },
+    /// Only from the given partitions of a partitioned topic, if set
+    Dequeue {
+        topic: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        partitions: Option<Vec<u32>>,
+    },
+    Peek { topic: String },
+    Stats { topic: String },
+    /// The node's `TopicStats` of a topic
+    TopicStats { topic: String },
+    /// Remote events the topic gave up applying
+    FailedEvents { topic: String },
+    LogTail { topic: String, count: usize },
+    Topics,
+    /// Totals over a namespace's queues
+    NamespaceStats { namespace: String },
+    /// Report that processing a dequeued item failed, see `Node::fail`
+    Fail { topic: String, delivery: u64 },
+    /// Up to `max` bytes of a snapshot of the topic from `offset`: of the one named by
+    /// `digest`, or of a fresh one if unset, see `crate::engine::transfer`
+    Snapshot {
+        topic: String,
+        offset: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        digest: Option<u32>,
+        max: usize,
+    },
+}
+
+/// Node replies to client requests
+#[derive(Debug, Serialize, Deserialize)]
+#[allow(clippy::large_enum_variant)] // one per request, stats replies are as common as any
+pub enum Response<T> {
+    Enqueued { event_id: u64 },
+    /// `delivery` identifies the dequeue to `Request::Fail`
+    Dequeued {
+        item: Option<Arc<T>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        delivery: Option<u64>,
+    },
+    Peeked { item: Option<Arc<T>> },
+    Stats(QueueStats),
+    TopicStats(TopicStats),
+    FailedEvents(Vec<FailedEvent>),
+    Logs(Vec<LogEntry<T>>),
+    Topics(Vec<String>),
+    NamespaceStats(NamespaceStats),
+    Failed { event_id: u64 },
+    SnapshotChunk(SnapshotChunk),
+    Error(String),
+}
+
+impl<T> Response<T> {
+    pub(crate) fn dequeued(delivered: Option<(u64, Arc<T>)>) -> Self {
+        match delivered {
+            Some((delivery, item)) => Response::Dequeued { item: Some(item), delivery: Some(delivery) },
+            None => Response::Dequeued { item: None, delivery: None },
+        }
+    }
+}
+
+/// Wire-level unit of transfer between nodes
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    pub from: NodeId,
+    pub message: Message<T>,
+    /// MAC over the message by its origin node, see `Keyring`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Trace context of the hop that sent a client request or response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<TraceContext>,
+    /// Position on the sender's reliable channel to the receiver, see `ReliableTransport`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<ChannelSeq>,
+}
+
+impl<T> Envelope<T> {
+    /// Unsigned envelope
+    pub fn new(from: NodeId, message: Message<T>) -> Self {
+        Self { from, message, signature: None, trace: None, seq: None }
+    }
+
+    /// Carry a trace context to the receiver
+    pub fn with_trace(mut self, trace: Option<TraceContext>) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// Wrap a topic event
+    pub fn event(from: NodeId, topic: &str, event: Event<T>) -> Self {
+        Self::new(from, Message::Event { topic: topic.to_string(), event })
+    }
+}
+
+/// Moves envelopes between nodes
+pub trait Transport<T>: Send + Sync {
+    /// This node's ID
+    fn local_id(&self) -> NodeId;
+
+    /// Peers this transport can reach
+    fn peers(&self) -> Vec<NodeId>;
+
+    /// Send an envelope to one peer
+    fn send(&self, peer: NodeId, envelope: &Envelope<T>) -> io::Result<()>;
+
+    /// Wait up to `timeout` for the next incoming envelope
+    fn recv_timeout(&self, timeout: Duration) -> Option<Envelope<T>>;
+
+    /// Send an envelope to every peer, returning the ones that failed
+    fn broadcast(&self, envelope: &Envelope<T>) -> Vec<(NodeId, io::Error)> {
+        self.peers()
+            .into_iter()
+            .filter_map(|peer| self.send(peer, envelope).err().map(|e| (peer, e)))
+            .collect()
+    }
+}
+
+// Lets a shared transport be wrapped, e.g. by a `ReliableTransport`, and still be used directly
+impl<T, X: Transport<T> + ?Sized> Transport<T> for Arc<X> {
+    fn local_id(&self) -> NodeId {
+        (**self).local_id()
+    }
+
+    fn peers(&self) -> Vec<NodeId> {
+        (**self).peers()
+    }
+
+    fn send(&self, peer: NodeId, envelope: &Envelope<T>) -> io::Result<()> {
+        (**self).send(peer, envelope)
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Option<Envelope<T>> {
+        (**self).recv_timeout(timeout)
+    }
+}
+
+/// A transport connection, plain or TLS; clones share the underlying connection
+pub(crate) enum Stream {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(tls::TlsStream),
+}
+
+impl Stream {
+    pub(crate) fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Stream::Plain(s) => s.try_clone().map(Stream::Plain),
+            #[cfg(feature = "tls")]
+            Stream::Tls(s) => Ok(Stream::Tls(s.clone())),
+        }
+    }
+
+    fn socket(&self) -> &TcpStream {
+        match self {
+            Stream::Plain(s) => s,
+            #[cfg(feature = "tls")]
+            Stream::Tls(s) => s.socket(),
+        }
+    }
+
+    pub(crate) fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.socket().peer_addr()
+    }
+
+    /// Whether the other side proved it is `node`: always on a plain connection, which
+    /// proves nothing, and on TLS if its certificate names `node`
+    #[cfg_attr(not(feature = "tls"), allow(unused_variables))]
+    fn certifies(&self, node: NodeId) -> bool {
+        match self {
+            Stream::Plain(_) => true,
+            #[cfg(feature = "tls")]
+            Stream::Tls(s) => s.certifies(node.as_str()),
+        }
+    }
+
+    pub(crate) fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.socket().set_read_timeout(timeout)
+    }
+
+    pub(crate) fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        self.socket().read_timeout()
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(s) => s.read(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(s) => s.write(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(s) => s.flush(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(s) => s.flush(),
+        }
+    }
+}
+
+/// How connections are secured
+#[derive(Clone)]
+pub(crate) enum Security {
+    Plain,
+    #[cfg(feature = "tls")]
+    Tls(TlsConfig),
+}
+
+impl Security {
+    /// Wrap a socket accepted by the listener
+    fn accept(&self, socket: TcpStream) -> io::Result<Stream> {
+        match self {
+            Security::Plain => Ok(Stream::Plain(socket)),
+            #[cfg(feature = "tls")]
+            Security::Tls(tls) => tls.accept(socket).map(Stream::Tls),
+        }
+    }
+
+    /// Open a connection to `peer`
+    #[cfg_attr(not(feature = "tls"), allow(unused_variables))]
+    pub(crate) fn connect(&self, peer: &str, addr: SocketAddr) -> io::Result<Stream> {
+        let socket = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+        socket.set_nodelay(true).ok();
+        match self {
+            Security::Plain => Ok(Stream::Plain(socket)),
+            #[cfg(feature = "tls")]
+            Security::Tls(tls) => tls.connect(peer, socket).map(Stream::Tls),
+        }
+    }
+}
+
+/// A connection, how frames sent on it are encoded, and when they are chunked
+pub(crate) struct Link {
+    stream: Stream,
+    codec: Codec,
+    max_frame: usize,
+}
+
+impl Link {
+    fn send<T: Serialize>(&mut self, envelope: &Envelope<T>) -> io::Result<()> {
+        self.stream.write_all(&frame::encode(envelope, &self.codec, self.max_frame)?)
+    }
+}
+
+/// Inbound connections that can be written back to, keyed by the sender's ID
+type ReplyStreams = Arc<Mutex<HashMap<NodeId, Link>>>;
+
+/// TCP transport, one envelope per line, as JSON or (negotiated) protobuf, each line
+/// a checksummed frame between nodes that both support it
+/// Envelopes longer than the frame limit are split across several lines.
+/// Outbound connections are opened lazily and re-established once on failure,
+/// then in the background with backoff while events for the peer are held, see `PeerHealth`.
+/// Senders that are not configured peers (clients) are answered on their own
+/// inbound connection.
+pub struct TcpTransport<T> {
+    local: NodeId,
+    local_addr: SocketAddr,
+    max_frame: Arc<AtomicUsize>,
+    outbound: Arc<Outbound>,
+    replies: ReplyStreams,
+    incoming: Mutex<Receiver<Envelope<T>>>,
+    shutdown: Arc<AtomicBool>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl<T: Serialize + DeserializeOwned + Send + Sync + 'static> TcpTransport<T> {
+    /// Listen on `addr` and start accepting peer connections
+    pub fn bind(local: &str, addr: impl Into<SocketAddr>, peers: &[(&str, SocketAddr)]) -> io::Result<Self> {
+        Self::bind_with(local, addr.into(), peers, Security::Plain)
+    }
+
+    /// Like `bind`, but every connection uses TLS with this node's certificate
+    /// Peers are connected to by node ID, so each peer's certificate must be issued for its ID
+    #[cfg(feature = "tls")]
+    pub fn bind_tls(local: &str, addr: impl Into<SocketAddr>, peers: &[(&str, SocketAddr)], tls: TlsConfig) -> io::Result<Self> {
+        Self::bind_with(local, addr.into(), peers, Security::Tls(tls))
+    }
+
+    fn bind_with(local: &str, addr: SocketAddr, peers: &[(&str, SocketAddr)], security: Security) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let (tx, rx) = mpsc::channel();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let dropped = Arc::new(AtomicU64::new(0));
+
+        let replies = ReplyStreams::default();
+        let codec = Arc::new(RwLock::new(Codec::local()));
+        let max_frame = Arc::new(AtomicUsize::new(DEFAULT_MAX_FRAME_LEN));
+        let inbound = security.clone();
+        let peers = peers.iter().map(|(id, a)| (NodeId::intern(id), *a)).collect();
+        let outbound = Arc::new(Outbound::new(security, codec.clone(), max_frame.clone(), peers));
+
+        let stop = shutdown.clone();
+        let streams = replies.clone();
+        let local_codec = codec.clone();
+        let local_max_frame = max_frame.clone();
+        let known = outbound.clone();
+        let counter = dropped.clone();
+        thread::spawn(move || {
+            for socket in listener.incoming() {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = socket.and_then(|s| inbound.accept(s)) else {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                };
+                let tx = tx.clone();
+                let stop = stop.clone();
+                let streams = streams.clone();
+                let known = known.clone();
+                let dropped = counter.clone();
+                let codec = *local_codec.read().unwrap();
+                let max_frame = local_max_frame.load(Ordering::SeqCst);
+                thread::spawn(move || read_envelopes(stream, tx, streams, &known, codec, max_frame, stop, &dropped));
+            }
+        });
+
+        outbound.start_reconnecting(shutdown.clone());
+        Ok(Self {
+            local: NodeId::intern(local),
+            local_addr,
+            max_frame,
+            outbound,
+            replies,
+            incoming: Mutex::new(rx),
+            shutdown,
+            dropped,
+        })
+    }
+}
+
+impl<T> TcpTransport<T> {
+    /// Compress large frames on connections whose other end supports it
+    /// Applies to connections opened from now on
+    #[cfg(feature = "compress")]
+    pub fn with_compression(self, compression: Compression) -> Self {
+        self.outbound.codec.write().unwrap().compression = Some(compression);
+        self.outbound.connections.lock().unwrap().clear();
+        self
+    }
+
+    /// Longest line sent or accepted; longer envelopes are sent in chunks
+    /// Every node of a cluster should use the same limit. Applies to
+    /// connections opened from now on
+    pub fn with_max_frame_len(self, max_frame: usize) -> Self {
+        self.max_frame.store(max_frame.max(MIN_FRAME_LEN), Ordering::SeqCst);
+        self.outbound.connections.lock().unwrap().clear();
+        self
+    }
+
+    /// Wait `initial` before reconnecting to a peer that went down, twice as long after
+    /// each failed attempt, up to `max`
+    pub fn with_reconnect_backoff(self, initial: Duration, max: Duration) -> Self {
+        let mut settings = self.outbound.settings.write().unwrap();
+        (settings.backoff, settings.max_backoff) = (initial, max.max(initial));
+        drop(settings);
+        self
+    }
+
+    /// Hold at most this many events for a peer that is down; sends beyond fail
+    pub fn with_max_held(self, max: usize) -> Self {
+        self.outbound.settings.write().unwrap().max_held = max;
+        self
+    }
+
+    /// How the connection to `peer` is doing, None if nothing was sent to it yet
+    pub fn peer_health(&self, peer: &str) -> Option<PeerHealth> {
+        self.outbound.health(NodeId::intern(peer))
+    }
+
+    /// Protocol version agreed with `peer`, if connected to it either way
+    pub fn protocol_version(&self, peer: &str) -> Option<u32> {
+        let peer = NodeId::intern(peer);
+        if let Some(link) = self.outbound.connections.lock().unwrap().get(&peer) {
+            return Some(link.codec.version);
+        }
+        self.replies.lock().unwrap().get(&peer).map(|link| link.codec.version)
+    }
+
+    /// Address the listener is bound to
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Connections refused or closed during the handshake, and envelopes that could not
+    /// be decoded, since the transport was bound
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Add or update a peer address
+    pub fn add_peer(&self, peer: &str, addr: SocketAddr) {
+        let peer = NodeId::intern(peer);
+        self.outbound.peers.write().unwrap().insert(peer, addr);
+        self.outbound.reset(peer);
+    }
+
+    /// Stop sending to a peer; events held for it are dropped once it is found gone
+    pub fn remove_peer(&self, peer: &str) {
+        let peer = NodeId::intern(peer);
+        self.outbound.peers.write().unwrap().remove(&peer);
+        self.outbound.reset(peer);
+    }
+
+    /// Stop accepting connections and close inbound readers
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wake the accept loop so it sees the flag
+        TcpStream::connect_timeout(&self.local_addr, CONNECT_TIMEOUT).ok();
+    }
+}
+
+impl<T> Drop for TcpTransport<T> {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Read envelopes, as lines or frames, from one inbound connection
+/// A `hello` is answered with this node's versions and capabilities, and the
+/// connection closed if they have no version in common. The first envelope
+/// registers the connection as the reply path for its sender, and envelopes
+/// claiming another sender are dropped. A client's reply path stays with the
+/// connection that registered it, and a second connection claiming the same
+/// client is closed; a peer's moves to its latest connection. Over TLS, a
+/// connection claiming a peer its certificate doesn't name is closed, and one
+/// from a sender it doesn't name only passes on requests. Chunked envelopes
+/// are only passed on once complete and intact. Refused handshakes and envelopes
+/// that can't be decoded count in `dropped`
+#[allow(clippy::too_many_arguments)] // the connection's settings and what it shares with the transport
+fn read_envelopes<T: DeserializeOwned>(
+    stream: Stream,
+    tx: Sender<Envelope<T>>,
+    replies: ReplyStreams,
+    outbound: &Outbound,
+    codec: Codec,
+    max_frame: usize,
+    stop: Arc<AtomicBool>,
+    dropped: &AtomicU64,
+) {
+    let remote = stream.peer_addr().ok();
+    let mut sender = None;
+    let mut certified = false;
+    let mut negotiated = Codec::default();
+    let mut decoder = Decoder::new(max_frame);
+    let mut reader = match stream.try_clone() {
+        Ok(read_half) => BufReader::new(read_half),
+        Err(_) => return,
+    };
+    while let Ok(Some(line)) = frame::read_frame(&mut reader) {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        match decoder.decode::<T>(&line) {
+            Ok(None) => {}
+            Ok(Some(Frame::Hello(hello))) => {
+                // Answer even when incompatible, so the other side can report why
+                let reply = frame::hello_line(&frame::local_hello(&codec));
+                if reply.and_then(|reply| stream.try_clone()?.write_all(&reply)).is_err() {
+                    break;
+                }
+                match frame::negotiate(&codec, &hello) {
+                    Ok(agreed) => negotiated = agreed,
+                    Err(_) => {
+                        dropped.fetch_add(1, Ordering::Relaxed);
+                        break;
+                    }
+                }
+            }
+            Ok(Some(Frame::Envelope(envelope))) => {
+                match sender {
+                    Some(registered) if registered != envelope.from => continue,
+                    Some(_) => {}
+                    None => {
+                        let Ok(write_half) = stream.try_clone() else {
+                            break;
+                        };
+                        let mut replies = replies.lock().unwrap();
+                        let is_peer = outbound.peers.read().unwrap().contains_key(&envelope.from);
+                        certified = stream.certifies(envelope.from);
+                        if (is_peer && !certified) || (!is_peer && replies.contains_key(&envelope.from)) {
+                            break;
+                        }
+                        replies.insert(envelope.from, Link { stream: write_half, codec: negotiated, max_frame });
+                        sender = Some(envelope.from);
+                    }
+                }
+                // A client's certificate doesn't name it, so all it may send is requests
+                if !certified && !matches!(envelope.message, Message::Request { .. }) {
+                    continue;
+                }
+                if tx.send(envelope).is_err() {
+                    break;
+                }
+            }
+            Err(_) => {
+                dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+    // Forget the reply path unless the sender has already reconnected
+    if let Some(sender) = sender {
+        let mut replies = replies.lock().unwrap();
+        if replies.get(&sender).is_some_and(|link| link.stream.peer_addr().ok() == remote) {
+            replies.remove(&sender);
+        }
+    }
+}
+
+impl<T: Serialize + Send + Sync> Transport<T> for TcpTransport<T> {
+    fn local_id(&self) -> NodeId {
+        self.local
+    }
+
+    fn peers(&self) -> Vec<NodeId> {
+        self.outbound.peers.read().unwrap().keys().copied().collect()
+    }
+
+    fn send(&self, peer: NodeId, envelope: &Envelope<T>) -> io::Result<()> {
+        let Some(addr) = self.outbound.peers.read().unwrap().get(&peer).copied() else {
+            // Not a peer: answer on the sender's inbound connection, if it has one
+            let mut replies = self.replies.lock().unwrap();
+            let Some(link) = replies.get_mut(&peer) else {
+                return Err(io::Error::new(io::ErrorKind::NotFound, format!("unknown peer {}", peer)));
+            };
+            return link.send(envelope);
+        };
+        self.outbound.send(peer, addr, envelope)
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Option<Envelope<T>> {
+        self.incoming.lock().unwrap().recv_timeout(timeout).ok()
+    }
+}
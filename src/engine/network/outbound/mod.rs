@@ -0,0 +1,252 @@
+//! Outbound connections of the TCP transport
+//! A peer's connection is opened on first use. When it fails the peer is marked down
+//! and reconnected in the background, waiting twice as long after each failed attempt.
+//! Events sent to a peer while it is down are held, up to a limit, and sent ahead of
+//! anything else once it is back; other messages to it fail as before, as they are
+//! either answered or sent again anyway.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use crate::core::buildcore::NodeId;
+use super::{Codec, Envelope, Link, Message, Security, frame};
+
+/// Wait before the first reconnection attempt, unless configured otherwise
+pub const DEFAULT_RECONNECT_BACKOFF: Duration = Duration::from_millis(100);
+/// Longest wait between reconnection attempts, unless configured otherwise
+pub const DEFAULT_MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(5);
+/// Most events held per peer while it is down, unless configured otherwise
+pub const DEFAULT_MAX_HELD: usize = 10_000;
+
+/// How often the background reconnection looks for peers due another attempt
+const RECONNECT_TICK: Duration = Duration::from_millis(50);
+
+/// How the connection to a peer is doing
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerHealth {
+    pub connected: bool,
+    /// Failed connection attempts since it was last up
+    pub failures: u32,
+    pub last_error: Option<String>,
+    /// Events waiting for the peer to be reachable again
+    pub held: usize,
+    /// When the peer last went up or down
+    pub since: Instant,
+}
+
+/// A peer's connection state
+struct PeerState {
+    up: bool,
+    failures: u32,
+    last_error: Option<String>,
+    since: Instant,
+    retry_at: Instant,
+    held: VecDeque<Vec<u8>>, // encoded frames, oldest first
+}
+
+impl PeerState {
+    fn new() -> Self {
+        let now = Instant::now();
+        Self { up: false, failures: 0, last_error: None, since: now, retry_at: now, held: VecDeque::new() }
+    }
+}
+
+/// Reconnection backoff and how much to hold for a peer that is down
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Settings {
+    pub(crate) backoff: Duration,
+    pub(crate) max_backoff: Duration,
+    pub(crate) max_held: usize,
+}
+
+/// Connections to peers, their health and what is held for them, see the module docs
+pub(crate) struct Outbound {
+    security: Security,
+    pub(crate) codec: Arc<RwLock<Codec>>,
+    max_frame: Arc<AtomicUsize>,
+    pub(crate) peers: RwLock<HashMap<NodeId, SocketAddr>>,
+    pub(crate) connections: Mutex<HashMap<NodeId, Link>>, // locked before `states`
+    states: Mutex<HashMap<NodeId, PeerState>>,
+    pub(crate) settings: RwLock<Settings>,
+}
+
+impl Outbound {
+    pub(crate) fn new(security: Security, codec: Arc<RwLock<Codec>>, max_frame: Arc<AtomicUsize>, peers: HashMap<NodeId, SocketAddr>) -> Self {
+        Self {
+            security,
+            codec,
+            max_frame,
+            peers: RwLock::new(peers),
+            connections: Mutex::new(HashMap::new()),
+            states: Mutex::new(HashMap::new()),
+            settings: RwLock::new(Settings {
+                backoff: DEFAULT_RECONNECT_BACKOFF,
+                max_backoff: DEFAULT_MAX_RECONNECT_BACKOFF,
+                max_held: DEFAULT_MAX_HELD,
+            }),
+        }
+    }
+
+    /// Reconnect peers that are down in the background until `stop` is set
+    pub(crate) fn start_reconnecting(self: &Arc<Self>, stop: Arc<AtomicBool>) {
+        let outbound = Arc::downgrade(self);
+        thread::spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                let Some(outbound) = outbound.upgrade() else { break };
+                outbound.reconnect_due();
+                drop(outbound);
+                thread::sleep(RECONNECT_TICK);
+            }
+        });
+    }
+
+    /// Send to a configured peer at `addr`, see the module docs
+    pub(crate) fn send<T: Serialize>(&self, peer: NodeId, addr: SocketAddr, envelope: &Envelope<T>) -> io::Result<()> {
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(link) = connections.get_mut(&peer) {
+            if link.send(envelope).is_ok() {
+                return Ok(());
+            }
+            // Stale connection, reconnect once below
+            connections.remove(&peer);
+        } else if self.is_down(peer) {
+            return self.hold(peer, envelope, io::Error::new(io::ErrorKind::NotConnected, format!("{} is down", peer)));
+        }
+        if let Err(e) = self.reconnect(peer, addr, &mut connections) {
+            return self.hold(peer, envelope, e);
+        }
+        let link = connections.get_mut(&peer).expect("just connected");
+        if let Err(e) = link.send(envelope) {
+            connections.remove(&peer);
+            self.mark_down(peer, &e);
+            return self.hold(peer, envelope, e);
+        }
+        Ok(())
+    }
+
+    /// Health of the connection to `peer`, None if it was never used
+    pub(crate) fn health(&self, peer: NodeId) -> Option<PeerHealth> {
+        self.states.lock().unwrap().get(&peer).map(|state| PeerHealth {
+            connected: state.up,
+            failures: state.failures,
+            last_error: state.last_error.clone(),
+            held: state.held.len(),
+            since: state.since,
+        })
+    }
+
+    /// Forget the connection to `peer`, e.g. as its address changed; if it was down,
+    /// the next attempt is made right away
+    pub(crate) fn reset(&self, peer: NodeId) {
+        self.connections.lock().unwrap().remove(&peer);
+        if let Some(state) = self.states.lock().unwrap().get_mut(&peer) {
+            state.retry_at = Instant::now();
+        }
+    }
+
+    fn is_down(&self, peer: NodeId) -> bool {
+        self.states.lock().unwrap().get(&peer).is_some_and(|state| !state.up)
+    }
+
+    /// Attempt every peer that is down and due another try
+    fn reconnect_due(&self) {
+        let now = Instant::now();
+        let due: Vec<NodeId> = self.states.lock().unwrap().iter().filter(|(_, s)| !s.up && s.retry_at <= now).map(|(&peer, _)| peer).collect();
+        for peer in due {
+            let Some(addr) = self.peers.read().unwrap().get(&peer).copied() else {
+                // No longer a peer
+                self.states.lock().unwrap().remove(&peer);
+                continue;
+            };
+            let mut connections = self.connections.lock().unwrap();
+            if !connections.contains_key(&peer) {
+                self.reconnect(peer, addr, &mut connections).ok();
+            }
+        }
+    }
+
+    /// Connect to `peer` and send what was held for it, marking it up or down by the outcome
+    fn reconnect(&self, peer: NodeId, addr: SocketAddr, connections: &mut HashMap<NodeId, Link>) -> io::Result<()> {
+        let connected = self.connect(peer, addr).and_then(|mut link| self.flush(peer, &mut link).map(|()| link));
+        match connected {
+            Ok(link) => {
+                connections.insert(peer, link);
+                self.mark_up(peer);
+                Ok(())
+            }
+            Err(e) => {
+                self.mark_down(peer, &e);
+                Err(e)
+            }
+        }
+    }
+
+    fn connect(&self, peer: NodeId, addr: SocketAddr) -> io::Result<Link> {
+        let mut stream = self.security.connect(peer.as_str(), addr)?;
+        let codec = frame::handshake(&mut stream, &self.codec.read().unwrap())?;
+        Ok(Link { stream, codec, max_frame: self.max_frame.load(Ordering::SeqCst) })
+    }
+
+    /// Send the frames held for `peer`, each forgotten once written
+    fn flush(&self, peer: NodeId, link: &mut Link) -> io::Result<()> {
+        let mut states = self.states.lock().unwrap();
+        let Some(state) = states.get_mut(&peer) else {
+            return Ok(());
+        };
+        while let Some(frame) = state.held.front() {
+            link.stream.write_all(frame)?;
+            state.held.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Hold an event for `peer` until it is back; other messages fail with `cause`, as do
+    /// events to a peer that can't be talked to at all, speaking no common protocol version
+    fn hold<T: Serialize>(&self, peer: NodeId, envelope: &Envelope<T>, cause: io::Error) -> io::Result<()> {
+        if !matches!(envelope.message, Message::Event { .. }) || cause.kind() == io::ErrorKind::Unsupported {
+            return Err(cause);
+        }
+        // Plain JSON, which the peer reads whatever the next connection negotiates
+        let frame = frame::encode(envelope, &Codec::default(), self.max_frame.load(Ordering::SeqCst))?;
+        let max_held = self.settings.read().unwrap().max_held;
+        let mut states = self.states.lock().unwrap();
+        let state = states.entry(peer).or_insert_with(PeerState::new);
+        if state.held.len() >= max_held {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!("{} events are already held for {}: {}", state.held.len(), peer, cause),
+            ));
+        }
+        state.held.push_back(frame);
+        Ok(())
+    }
+
+    fn mark_up(&self, peer: NodeId) {
+        let mut states = self.states.lock().unwrap();
+        let state = states.entry(peer).or_insert_with(PeerState::new);
+        if !state.up {
+            state.since = Instant::now();
+        }
+        (state.up, state.failures, state.last_error) = (true, 0, None);
+    }
+
+    fn mark_down(&self, peer: NodeId, e: &io::Error) {
+        let settings = *self.settings.read().unwrap();
+        let mut states = self.states.lock().unwrap();
+        let state = states.entry(peer).or_insert_with(PeerState::new);
+        let now = Instant::now();
+        if state.up {
+            state.since = now;
+        }
+        state.up = false;
+        state.failures = state.failures.saturating_add(1);
+        state.last_error = Some(e.to_string());
+        let backoff = settings.backoff.saturating_mul(1 << (state.failures - 1).min(16));
+        state.retry_at = now + backoff.min(settings.max_backoff);
+    }
+}
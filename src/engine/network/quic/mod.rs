@@ -0,0 +1,282 @@
+//! QUIC peer transport
+//! Each pair of nodes shares a QUIC connection, authenticated with the nodes' certificates
+//! as on the TLS transport, carrying one unidirectional stream per `Lane`: events, control
+//! messages and snapshots. Streams are flow-controlled independently, so a large snapshot
+//! doesn't hold up the events queued behind it as it would on a single TCP connection.
+//! Envelopes are JSON, one per `FrameCodec` frame. As over TLS, a connection only carries
+//! messages from the node its certificate names, apart from client requests.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::{Connection, Endpoint, RecvStream, SendStream};
+use rustls::pki_types::CertificateDer;
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::runtime::Runtime;
+use crate::core::buildcore::NodeId;
+use super::{CONNECT_TIMEOUT, Envelope, FrameCodec, Message, TlsConfig, Transport};
+
+/// Largest envelope sent or accepted, in bytes
+const MAX_ENVELOPE_LEN: usize = 256 << 20;
+/// How much of a stream is read at a time
+const READ_LEN: usize = 64 << 10;
+
+/// Which of a connection's streams an envelope travels on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Lane {
+    /// Replicated events
+    Data,
+    /// Requests, responses, heartbeats and the other small messages
+    Control,
+    /// Bulk state transfers, sent with `QuicTransport::send_on`
+    Snapshot,
+}
+
+impl Lane {
+    /// Lane a message goes on when sent with `Transport::send`
+    pub fn of<T>(message: &Message<T>) -> Self {
+        match message {
+            Message::Event { .. } => Lane::Data,
+            _ => Lane::Control,
+        }
+    }
+}
+
+type Connections = Arc<Mutex<HashMap<NodeId, Connection>>>;
+type Stream = Arc<tokio::sync::Mutex<SendStream>>;
+
+/// QUIC transport, see the module docs
+/// Senders that are not configured peers (clients) are answered on their own connection.
+pub struct QuicTransport<T> {
+    local: NodeId,
+    runtime: Runtime,
+    endpoint: Endpoint,
+    peers: RwLock<HashMap<NodeId, SocketAddr>>,
+    connections: Connections, // opened either way, one per node as long as it stays open
+    streams: Mutex<HashMap<(NodeId, Lane), Stream>>,
+    tx: Sender<Envelope<T>>,
+    incoming: Mutex<Receiver<Envelope<T>>>,
+    dropped: Arc<AtomicU64>,
+}
+
+fn invalid(e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+impl<T: Serialize + DeserializeOwned + Send + Sync + 'static> QuicTransport<T> {
+    /// Listen on `addr` with this node's certificate; peers are connected to by node ID,
+    /// so each peer's certificate must be issued for its ID
+    pub fn bind(local: &str, addr: impl Into<SocketAddr>, peers: &[(&str, SocketAddr)], tls: TlsConfig) -> io::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread().worker_threads(2).enable_all().build()?;
+        let (server, client) = tls.rustls_configs();
+        let server = QuicServerConfig::try_from(server).map_err(invalid)?;
+        let client = QuicClientConfig::try_from(client).map_err(invalid)?;
+        let mut endpoint = {
+            let _runtime = runtime.enter();
+            Endpoint::server(quinn::ServerConfig::with_crypto(Arc::new(server)), addr.into())?
+        };
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(client)));
+
+        let (tx, rx) = mpsc::channel();
+        let connections = Connections::default();
+        let dropped = Arc::new(AtomicU64::new(0));
+        runtime.spawn(accept(endpoint.clone(), tx.clone(), connections.clone(), dropped.clone()));
+        Ok(Self {
+            local: NodeId::intern(local),
+            runtime,
+            endpoint,
+            peers: RwLock::new(peers.iter().map(|(id, a)| (NodeId::intern(id), *a)).collect()),
+            connections,
+            streams: Mutex::new(HashMap::new()),
+            tx,
+            incoming: Mutex::new(rx),
+            dropped,
+        })
+    }
+
+    /// Send an envelope to one peer on a given lane, e.g. `Lane::Snapshot` for bulk transfers
+    pub fn send_on(&self, peer: NodeId, lane: Lane, envelope: &Envelope<T>) -> io::Result<()> {
+        let frame = FrameCodec::new(MAX_ENVELOPE_LEN).encode_json(envelope)?;
+        self.runtime.block_on(async {
+            if self.write(peer, lane, &frame).await.is_ok() {
+                return Ok(());
+            }
+            // Stale connection, reconnect once
+            self.forget(peer);
+            self.write(peer, lane, &frame).await
+        })
+    }
+
+    async fn write(&self, peer: NodeId, lane: Lane, frame: &[u8]) -> io::Result<()> {
+        let stream = self.stream(peer, lane).await?;
+        let mut stream = stream.lock().await;
+        stream.write_all(frame).await.map_err(io::Error::other)
+    }
+
+    /// The stream for `lane` to `peer`, opened on first use
+    async fn stream(&self, peer: NodeId, lane: Lane) -> io::Result<Stream> {
+        let open = self.streams.lock().unwrap().get(&(peer, lane)).cloned();
+        if let Some(stream) = open {
+            return Ok(stream);
+        }
+        let stream = self.connection(peer).await?.open_uni().await.map_err(io::Error::other)?;
+        let mut streams = self.streams.lock().unwrap();
+        Ok(streams.entry((peer, lane)).or_insert_with(|| Arc::new(tokio::sync::Mutex::new(stream))).clone())
+    }
+
+    /// An open connection to `peer`, whichever side opened it, or a new one to a configured peer
+    async fn connection(&self, peer: NodeId) -> io::Result<Connection> {
+        let open = self.connections.lock().unwrap().get(&peer).filter(|c| c.close_reason().is_none()).cloned();
+        if let Some(connection) = open {
+            return Ok(connection);
+        }
+        let Some(addr) = self.peers.read().unwrap().get(&peer).copied() else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("unknown peer {}", peer)));
+        };
+        let connecting = self.endpoint.connect(addr, peer.as_str()).map_err(io::Error::other)?;
+        let connection = tokio::time::timeout(CONNECT_TIMEOUT, connecting)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, format!("connecting to {} timed out", peer)))?
+            .map_err(io::Error::other)?;
+        // The peer answers on streams of its own over the same connection
+        tokio::spawn(read_connection(connection.clone(), self.tx.clone(), self.connections.clone(), self.dropped.clone()));
+        self.connections.lock().unwrap().insert(peer, connection.clone());
+        Ok(connection)
+    }
+
+    /// Drop the connection to `peer` and its streams
+    fn forget(&self, peer: NodeId) {
+        self.connections.lock().unwrap().remove(&peer);
+        self.streams.lock().unwrap().retain(|(node, _), _| *node != peer);
+    }
+}
+
+impl<T> QuicTransport<T> {
+    /// Address the endpoint is bound to
+    pub fn local_addr(&self) -> SocketAddr {
+        self.endpoint.local_addr().expect("bound endpoint has an address")
+    }
+
+    /// Connections refused during the handshake, and envelopes or streams that could not
+    /// be decoded, since the transport was bound
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Add or update a peer address
+    pub fn add_peer(&self, peer: &str, addr: SocketAddr) {
+        let peer = NodeId::intern(peer);
+        self.peers.write().unwrap().insert(peer, addr);
+        self.connections.lock().unwrap().remove(&peer);
+        self.streams.lock().unwrap().retain(|(node, _), _| *node != peer);
+    }
+
+    /// Close every connection and stop accepting new ones
+    pub fn shutdown(&self) {
+        self.endpoint.close(0u32.into(), b"shutdown");
+    }
+}
+
+impl<T> Drop for QuicTransport<T> {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Send + Sync + 'static> Transport<T> for QuicTransport<T> {
+    fn local_id(&self) -> NodeId {
+        self.local
+    }
+
+    fn peers(&self) -> Vec<NodeId> {
+        self.peers.read().unwrap().keys().copied().collect()
+    }
+
+    fn send(&self, peer: NodeId, envelope: &Envelope<T>) -> io::Result<()> {
+        self.send_on(peer, Lane::of(&envelope.message), envelope)
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Option<Envelope<T>> {
+        self.incoming.lock().unwrap().recv_timeout(timeout).ok()
+    }
+}
+
+/// Take in connections until the endpoint closes
+async fn accept<T: DeserializeOwned + Send + Sync + 'static>(endpoint: Endpoint, tx: Sender<Envelope<T>>, connections: Connections, dropped: Arc<AtomicU64>) {
+    while let Some(incoming) = endpoint.accept().await {
+        let (tx, connections, dropped) = (tx.clone(), connections.clone(), dropped.clone());
+        tokio::spawn(async move {
+            match incoming.await {
+                Ok(connection) => read_connection(connection, tx, connections, dropped).await,
+                Err(_) => {
+                    dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+    }
+}
+
+/// Read every stream the other side opens on a connection
+async fn read_connection<T: DeserializeOwned + Send + Sync + 'static>(connection: Connection, tx: Sender<Envelope<T>>, connections: Connections, dropped: Arc<AtomicU64>) {
+    while let Ok(stream) = connection.accept_uni().await {
+        tokio::spawn(read_stream(stream, connection.clone(), tx.clone(), connections.clone(), dropped.clone()));
+    }
+}
+
+/// Whether the other side's certificate names `node`
+fn certifies(connection: &Connection, node: NodeId) -> bool {
+    connection
+        .peer_identity()
+        .and_then(|identity| identity.downcast::<Vec<CertificateDer<'static>>>().ok())
+        .is_some_and(|certs| super::tls::certifies(&certs, node.as_str()))
+}
+
+/// Read framed envelopes from one stream until it ends
+/// The connection becomes the way back to the sender unless it already has an open one,
+/// or, for a sender the certificate doesn't name, any one
+async fn read_stream<T: DeserializeOwned + Send + Sync>(mut stream: RecvStream, connection: Connection, tx: Sender<Envelope<T>>, connections: Connections, dropped: Arc<AtomicU64>) {
+    let mut decoder = FrameCodec::new(MAX_ENVELOPE_LEN).decoder();
+    let mut buf = vec![0; READ_LEN];
+    while let Ok(Some(read)) = stream.read(&mut buf).await {
+        decoder.push(&buf[..read]);
+        loop {
+            let envelope = match decoder.next_frame() {
+                Ok(Some(frame)) => serde_json::from_slice::<Envelope<T>>(&frame).map_err(io::Error::other),
+                Ok(None) => break,
+                Err(e) => Err(e.into()),
+            };
+            let Ok(envelope) = envelope else {
+                dropped.fetch_add(1, Ordering::Relaxed);
+                if decoder.is_broken() {
+                    return;
+                }
+                continue;
+            };
+            let certified = certifies(&connection, envelope.from);
+            if !certified && !matches!(envelope.message, Message::Request { .. }) {
+                continue;
+            }
+            {
+                let mut known = connections.lock().unwrap();
+                let replace = match known.get(&envelope.from) {
+                    Some(existing) => certified && existing.close_reason().is_some(),
+                    None => true,
+                };
+                if replace {
+                    known.insert(envelope.from, connection.clone());
+                }
+            }
+            if tx.send(envelope).is_err() {
+                return;
+            }
+        }
+    }
+    if decoder.finish().is_err() {
+        dropped.fetch_add(1, Ordering::Relaxed);
+    }
+}
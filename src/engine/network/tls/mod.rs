@@ -0,0 +1,167 @@
+//! TLS for the peer transport
+//! Every node presents its own certificate and only accepts peers whose
+//! certificates chain to the shared CA, in both directions. A node's certificate
+//! names it as a DNS subject alternative name; a connection only carries messages
+//! from the node its certificate names, apart from client requests.
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, ClientConnection, Connection, RootCertStore, ServerConfig, ServerConnection};
+
+/// A node's TLS identity and the CA its peers are checked against
+#[derive(Clone)]
+pub struct TlsConfig {
+    server: Arc<ServerConfig>,
+    client: Arc<ClientConfig>,
+}
+
+fn invalid(e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+impl TlsConfig {
+    /// Build from PEM data: this node's certificate chain and key, and the CA certificate(s)
+    pub fn from_pem(cert_chain: &[u8], key: &[u8], ca: &[u8]) -> io::Result<Self> {
+        let chain = CertificateDer::pem_slice_iter(cert_chain).collect::<Result<Vec<_>, _>>().map_err(invalid)?;
+        let key = PrivateKeyDer::from_pem_slice(key).map_err(invalid)?;
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_slice_iter(ca) {
+            roots.add(cert.map_err(invalid)?).map_err(invalid)?;
+        }
+        let roots = Arc::new(roots);
+        let provider = Arc::new(ring::default_provider());
+
+        let verifier = WebPkiClientVerifier::builder_with_provider(roots.clone(), provider.clone())
+            .build()
+            .map_err(invalid)?;
+        let server = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(invalid)?
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(chain.clone(), key.clone_key())
+            .map_err(invalid)?;
+        let client = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(invalid)?
+            .with_root_certificates(roots)
+            .with_client_auth_cert(chain, key)
+            .map_err(invalid)?;
+        Ok(Self { server: Arc::new(server), client: Arc::new(client) })
+    }
+
+    /// Build from PEM files
+    pub fn from_pem_files(cert_chain: impl AsRef<Path>, key: impl AsRef<Path>, ca: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_pem(&std::fs::read(cert_chain)?, &std::fs::read(key)?, &std::fs::read(ca)?)
+    }
+
+    /// Server side of an inbound connection; the handshake runs on first read
+    pub(crate) fn accept(&self, socket: TcpStream) -> io::Result<TlsStream> {
+        let conn = ServerConnection::new(self.server.clone()).map_err(invalid)?;
+        Ok(TlsStream::new(conn.into(), socket))
+    }
+
+    /// Client side of an outbound connection to `server_name`, handshake included
+    pub(crate) fn connect(&self, server_name: &str, mut socket: TcpStream) -> io::Result<TlsStream> {
+        let name = ServerName::try_from(server_name.to_string()).map_err(invalid)?;
+        let mut conn = ClientConnection::new(self.client.clone(), name).map_err(invalid)?;
+        while conn.is_handshaking() {
+            conn.complete_io(&mut socket)?;
+        }
+        Ok(TlsStream::new(conn.into(), socket))
+    }
+
+    /// Server and client configurations, for transports that run TLS themselves
+    #[cfg(feature = "quic")]
+    pub(crate) fn rustls_configs(&self) -> (Arc<ServerConfig>, Arc<ClientConfig>) {
+        (self.server.clone(), self.client.clone())
+    }
+}
+
+/// Whether a peer's certificate chain, as presented, names `node` as a DNS subject alternative name
+pub(crate) fn certifies(certs: &[CertificateDer<'_>], node: &str) -> bool {
+    let (Some(cert), Ok(name)) = (certs.first(), ServerName::try_from(node)) else {
+        return false;
+    };
+    webpki::EndEntityCert::try_from(cert).is_ok_and(|cert| cert.verify_is_valid_for_subject_name(&name).is_ok())
+}
+
+/// TLS connection whose clones share one session
+/// Reads wait on the socket without holding the session lock, so another clone
+/// can keep writing while a reader is blocked.
+#[derive(Clone)]
+pub(crate) struct TlsStream {
+    conn: Arc<Mutex<Connection>>,
+    socket: Arc<TcpStream>,
+}
+
+impl TlsStream {
+    fn new(conn: Connection, socket: TcpStream) -> Self {
+        Self { conn: Arc::new(Mutex::new(conn)), socket: Arc::new(socket) }
+    }
+
+    pub(crate) fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+
+    /// Whether the other side's certificate names `node`, once the handshake is done
+    pub(crate) fn certifies(&self, node: &str) -> bool {
+        self.conn.lock().unwrap().peer_certificates().is_some_and(|certs| certifies(certs, node))
+    }
+
+    /// Send any pending TLS records (handshake replies, encrypted data)
+    fn flush_tls(&self, conn: &mut Connection) -> io::Result<()> {
+        while conn.wants_write() {
+            conn.write_tls(&mut &*self.socket)?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut raw = [0u8; 16 * 1024];
+        loop {
+            {
+                let mut conn = self.conn.lock().unwrap();
+                match conn.reader().read(buf) {
+                    Ok(n) => return Ok(n),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(e) => return Err(e),
+                }
+                self.flush_tls(&mut conn)?;
+            }
+            let n = (&*self.socket).read(&mut raw)?;
+            let mut conn = self.conn.lock().unwrap();
+            if n == 0 {
+                // Peer closed the socket without close_notify
+                return Ok(0);
+            }
+            let mut records = &raw[..n];
+            while !records.is_empty() {
+                conn.read_tls(&mut records)?;
+                conn.process_new_packets().map_err(invalid)?;
+            }
+            self.flush_tls(&mut conn)?;
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        let n = conn.writer().write(buf)?;
+        self.flush_tls(&mut conn)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        self.flush_tls(&mut conn)
+    }
+}
@@ -0,0 +1,141 @@
+//! UDP peer transport
+//! One JSON envelope per datagram, framed by `FrameCodec` so damaged ones are dropped, with no connections to set up or keep, for low
+//! latency between nodes on the same network. Datagrams can be lost, duplicated or
+//! reordered, so events between nodes should go over `UdpTransport::reliable`, which
+//! numbers, acknowledges and retransmits them, see `ReliableTransport`.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+use serde::{Serialize, de::DeserializeOwned};
+use crate::core::buildcore::NodeId;
+use super::{Envelope, FRAME_HEADER_LEN, FrameCodec, ReliableTransport, Transport};
+
+/// Largest datagram sent or accepted, in bytes
+pub const MAX_DATAGRAM_LEN: usize = 65_507;
+
+/// Frames that fit in one datagram
+const CODEC: FrameCodec = FrameCodec::new(MAX_DATAGRAM_LEN - FRAME_HEADER_LEN);
+
+/// How often the reader checks whether the transport was shut down
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// UDP transport, see the module docs
+/// Senders that are not configured peers (clients) are answered at the address
+/// they were last heard from.
+pub struct UdpTransport<T> {
+    local: NodeId,
+    socket: UdpSocket,
+    peers: RwLock<HashMap<NodeId, SocketAddr>>,
+    senders: Arc<Mutex<HashMap<NodeId, SocketAddr>>>,
+    incoming: Mutex<Receiver<Envelope<T>>>,
+    shutdown: Arc<AtomicBool>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl<T: Serialize + DeserializeOwned + Send + Sync + 'static> UdpTransport<T> {
+    /// Bind `addr` and start receiving datagrams
+    pub fn bind(local: &str, addr: impl Into<SocketAddr>, peers: &[(&str, SocketAddr)]) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr.into())?;
+        socket.set_read_timeout(Some(READ_TIMEOUT))?;
+        let reader = socket.try_clone()?;
+        let (tx, rx) = mpsc::channel();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let senders = Arc::new(Mutex::new(HashMap::new()));
+        let dropped = Arc::new(AtomicU64::new(0));
+
+        let stop = shutdown.clone();
+        let heard = senders.clone();
+        let undecodable = dropped.clone();
+        thread::spawn(move || {
+            let mut buf = vec![0; MAX_DATAGRAM_LEN];
+            while !stop.load(Ordering::SeqCst) {
+                let (len, from) = match reader.recv_from(&mut buf) {
+                    Ok(received) => received,
+                    // Read timeouts, and on some platforms errors reported for earlier sends
+                    Err(_) => continue,
+                };
+                let envelope = CODEC.decode_one(&buf[..len]).map_err(io::Error::from);
+                let Ok(envelope) = envelope.and_then(|json| serde_json::from_slice::<Envelope<T>>(json).map_err(io::Error::other)) else {
+                    undecodable.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                };
+                heard.lock().unwrap().insert(envelope.from, from);
+                if tx.send(envelope).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            local: NodeId::intern(local),
+            socket,
+            peers: RwLock::new(peers.iter().map(|(id, a)| (NodeId::intern(id), *a)).collect()),
+            senders,
+            incoming: Mutex::new(rx),
+            shutdown,
+            dropped,
+        })
+    }
+
+    /// Bind `addr`, with events to and from peers sent over reliable channels
+    pub fn reliable(local: &str, addr: impl Into<SocketAddr>, peers: &[(&str, SocketAddr)]) -> io::Result<ReliableTransport<T, Self>> {
+        Self::bind(local, addr, peers).map(ReliableTransport::new)
+    }
+}
+
+impl<T> UdpTransport<T> {
+    /// Address the socket is bound to
+    pub fn local_addr(&self) -> SocketAddr {
+        self.socket.local_addr().expect("bound socket has an address")
+    }
+
+    /// Datagrams that could not be decoded since the transport was bound
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Add or update a peer address
+    pub fn add_peer(&self, peer: &str, addr: SocketAddr) {
+        self.peers.write().unwrap().insert(NodeId::intern(peer), addr);
+    }
+
+    /// Stop receiving datagrams
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+    }
+}
+
+impl<T> Drop for UdpTransport<T> {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl<T: Serialize + Send + Sync> Transport<T> for UdpTransport<T> {
+    fn local_id(&self) -> NodeId {
+        self.local
+    }
+
+    fn peers(&self) -> Vec<NodeId> {
+        self.peers.read().unwrap().keys().copied().collect()
+    }
+
+    fn send(&self, peer: NodeId, envelope: &Envelope<T>) -> io::Result<()> {
+        let addr = self.peers.read().unwrap().get(&peer).copied().or_else(|| self.senders.lock().unwrap().get(&peer).copied());
+        let Some(addr) = addr else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("unknown peer {}", peer)));
+        };
+        let datagram = CODEC.encode_json(envelope)?;
+        self.socket.send_to(&datagram, addr).map(|_| ())
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Option<Envelope<T>> {
+        self.incoming.lock().unwrap().recv_timeout(timeout).ok()
+    }
+}
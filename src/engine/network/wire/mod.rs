@@ -0,0 +1,298 @@
+//! Protobuf encoding of envelopes, see `proto/wire.proto`
+//! Items are carried as JSON bytes, so nodes in other languages only need the
+//! schema and a JSON parser for their item type.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use prost::Message as _;
+use serde::{Serialize, de::DeserializeOwned};
+use crate::core::buildcore::{ControlOp, Event, EventOp, KeySequence, NodeId, State, TraceContext};
+use super::{ChannelSeq, Envelope, Message};
+
+/// Generated protobuf messages
+#[allow(clippy::large_enum_variant)] // generated; events are most of the traffic anyway
+pub mod proto {
+    include!(concat!(env!("OUT_DIR"), "/dqm.wire.v1.rs"));
+}
+
+/// Schema version written by this build
+pub const VERSION: u32 = 1;
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Node a received name refers to, see `NodeId::try_intern`
+fn node_id(name: &str) -> io::Result<NodeId> {
+    NodeId::try_intern(name).ok_or_else(|| invalid(format!("node name {:?} is too long or too many nodes are known", name)))
+}
+
+fn clock_to_proto(clock: &HashMap<NodeId, u64>) -> proto::Clock {
+    proto::Clock { entries: clock.iter().map(|(k, v)| (k.to_string(), *v)).collect() }
+}
+
+fn clock_from_proto(clock: Option<proto::Clock>) -> io::Result<HashMap<NodeId, u64>> {
+    clock.map_or_else(|| Ok(HashMap::new()), |c| c.entries.into_iter().map(|(k, v)| Ok((node_id(&k)?, v))).collect())
+}
+
+fn state_to_proto(state: State) -> proto::State {
+    match state {
+        State::Pending => proto::State::Pending,
+        State::Committed => proto::State::Committed,
+        State::Delivered => proto::State::Delivered,
+        State::Failed => proto::State::Failed,
+        State::DeadLettered => proto::State::DeadLettered,
+    }
+}
+
+fn state_from_proto(state: i32) -> io::Result<State> {
+    match proto::State::try_from(state) {
+        Ok(proto::State::Pending) => Ok(State::Pending),
+        Ok(proto::State::Committed) => Ok(State::Committed),
+        Ok(proto::State::Delivered) => Ok(State::Delivered),
+        Ok(proto::State::Failed) => Ok(State::Failed),
+        Ok(proto::State::DeadLettered) => Ok(State::DeadLettered),
+        Err(_) => Err(invalid(format!("unknown state {}", state))),
+    }
+}
+
+fn control_to_proto(op: &ControlOp) -> (proto::ControlKind, String, String) {
+    match op {
+        ControlOp::MemberAdded { member } => (proto::ControlKind::MemberAdded, member.to_string(), String::new()),
+        ControlOp::MemberRemoved { member } => (proto::ControlKind::MemberRemoved, member.to_string(), String::new()),
+        // The config travels as JSON in the value, empty without one
+        ControlOp::TopicCreated { topic, config } => {
+            let config = config.as_ref().map_or_else(String::new, |config| serde_json::to_string(config).unwrap_or_default());
+            (proto::ControlKind::TopicCreated, topic.clone(), config)
+        }
+        ControlOp::TopicDeleted { topic } => (proto::ControlKind::TopicDeleted, topic.clone(), String::new()),
+        // Versioned configs travel as a JSON [version, config] pair
+        ControlOp::TopicConfigured { topic, config, version } => {
+            (proto::ControlKind::TopicConfigured, topic.clone(), serde_json::to_string(&(version, config)).unwrap_or_default())
+        }
+        ControlOp::Paused => (proto::ControlKind::Paused, String::new(), String::new()),
+        ControlOp::Resumed => (proto::ControlKind::Resumed, String::new(), String::new()),
+        // Lease terms travel in the value, with the expiry as a JSON [term, expires_ms] pair
+        ControlOp::LeaseGranted { holder, term, expires_ms } => {
+            (proto::ControlKind::LeaseGranted, holder.to_string(), serde_json::to_string(&(term, expires_ms)).unwrap_or_default())
+        }
+        ControlOp::LeaseAcked { holder, term } => (proto::ControlKind::LeaseAcked, holder.to_string(), term.to_string()),
+        ControlOp::TokenPassed { to, round } => (proto::ControlKind::TokenPassed, to.to_string(), round.to_string()),
+        ControlOp::ConfigUpdated { key, value } => (proto::ControlKind::ConfigUpdated, key.clone(), value.clone()),
+    }
+}
+
+fn control_from_proto(kind: i32, subject: String, value: String) -> Result<ControlOp, io::Error> {
+    let kind = proto::ControlKind::try_from(kind).map_err(|_| invalid(format!("unknown control action {}", kind)))?;
+    if subject.is_empty() && !matches!(kind, proto::ControlKind::Paused | proto::ControlKind::Resumed) {
+        return Err(invalid("control event has no subject"));
+    }
+    Ok(match kind {
+        proto::ControlKind::MemberAdded => ControlOp::MemberAdded { member: node_id(&subject)? },
+        proto::ControlKind::MemberRemoved => ControlOp::MemberRemoved { member: node_id(&subject)? },
+        proto::ControlKind::TopicCreated => {
+            let config = match value.as_str() {
+                "" => None,
+                json => Some(serde_json::from_str(json).map_err(|e| invalid(format!("invalid topic config: {}", e)))?),
+            };
+            ControlOp::TopicCreated { topic: subject, config }
+        }
+        proto::ControlKind::TopicDeleted => ControlOp::TopicDeleted { topic: subject },
+        proto::ControlKind::TopicConfigured => {
+            let (version, config) = serde_json::from_str(&value).map_err(|e| invalid(format!("invalid topic config: {}", e)))?;
+            ControlOp::TopicConfigured { topic: subject, config, version }
+        }
+        proto::ControlKind::Paused => ControlOp::Paused,
+        proto::ControlKind::Resumed => ControlOp::Resumed,
+        proto::ControlKind::LeaseGranted => {
+            let (term, expires_ms) = serde_json::from_str(&value).map_err(|e| invalid(format!("invalid lease: {}", e)))?;
+            ControlOp::LeaseGranted { holder: node_id(&subject)?, term, expires_ms }
+        }
+        proto::ControlKind::LeaseAcked => {
+            let term = value.parse().map_err(|e| invalid(format!("invalid lease term: {}", e)))?;
+            ControlOp::LeaseAcked { holder: node_id(&subject)?, term }
+        }
+        proto::ControlKind::TokenPassed => {
+            let round = value.parse().map_err(|e| invalid(format!("invalid token round: {}", e)))?;
+            ControlOp::TokenPassed { to: node_id(&subject)?, round }
+        }
+        proto::ControlKind::ConfigUpdated => ControlOp::ConfigUpdated { key: subject, value },
+    })
+}
+
+fn event_to_proto<T: Serialize>(event: &Event<T>) -> io::Result<proto::Event> {
+    let op = match event.op {
+        EventOp::Enqueue => proto::Op::Enqueue,
+        EventOp::Dequeue => proto::Op::Dequeue,
+        EventOp::Transfer { .. } => proto::Op::Transfer,
+        EventOp::Reject { .. } => proto::Op::Reject,
+        EventOp::Fail { .. } => proto::Op::Fail,
+        EventOp::Retry { .. } => proto::Op::Retry,
+        EventOp::StateUpdate { .. } => proto::Op::StateUpdate,
+        EventOp::Purge { .. } => proto::Op::Purge,
+        EventOp::Control { .. } => proto::Op::Control,
+        EventOp::Take { .. } => proto::Op::Take,
+        EventOp::Ack { .. } => proto::Op::Ack,
+    };
+    let transfer_to = match event.op {
+        EventOp::Transfer { to } => to.to_string(),
+        _ => String::new(),
+    };
+    let (rejected_event, rejected_origin) = match event.op {
+        EventOp::Reject { origin, event_id } => (event_id, origin.to_string()),
+        _ => (0, String::new()),
+    };
+    let (delivery, attempt, dead_letter) = match event.op {
+        EventOp::Fail { delivery, attempt, dead_letter } => (delivery, attempt, dead_letter),
+        EventOp::Retry { attempt } => (0, attempt, false),
+        EventOp::Dequeue | EventOp::Take { .. } => (0, event.delivery_attempt, false),
+        _ => (0, 0, false),
+    };
+    let (updated_event, updated_origin, updated_state) = match event.op {
+        EventOp::StateUpdate { origin, event_id, state } => (event_id, origin.to_string(), state_to_proto(state)),
+        _ => (0, String::new(), proto::State::Pending),
+    };
+    let (taken_event, taken_origin) = match event.op {
+        EventOp::Take { origin, event_id } => (event_id, origin.to_string()),
+        _ => (0, String::new()),
+    };
+    let acked = match &event.op {
+        EventOp::Ack { deliveries } => deliveries.clone(),
+        _ => Vec::new(),
+    };
+    let purged_key = match &event.op {
+        EventOp::Purge { key } => key.clone(),
+        _ => String::new(),
+    };
+    let (control, control_subject, control_value) = match &event.op {
+        EventOp::Control { op } => control_to_proto(op),
+        _ => (proto::ControlKind::MemberAdded, String::new(), String::new()),
+    };
+    Ok(proto::Event {
+        global_id: event.global_id,
+        origin_node: event.origin_node.to_string(),
+        op: op as i32,
+        item: event.item.as_deref().map(serde_json::to_vec).transpose().map_err(io::Error::other)?,
+        clock: Some(clock_to_proto(&event.clock)),
+        timestamp_ms: event.timestamp_ms,
+        transfer_to,
+        rejected_event,
+        rejected_origin,
+        delivery,
+        attempt,
+        dead_letter,
+        updated_event,
+        updated_origin,
+        updated_state: updated_state as i32,
+        traceparent: event.trace.map(|trace| trace.to_string()).unwrap_or_default(),
+        purged_key,
+        control: control as i32,
+        control_subject,
+        control_value,
+        taken_event,
+        taken_origin,
+        sequence_key: event.sequence.as_ref().map(|sequence| sequence.key.clone()).unwrap_or_default(),
+        sequence: event.sequence.as_ref().map_or(0, |sequence| sequence.seq),
+        epoch: event.epoch,
+        acked,
+    })
+}
+
+fn event_from_proto<T: DeserializeOwned>(event: proto::Event) -> io::Result<Event<T>> {
+    let op = match proto::Op::try_from(event.op) {
+        Ok(proto::Op::Enqueue) => EventOp::Enqueue,
+        Ok(proto::Op::Dequeue) => EventOp::Dequeue,
+        Ok(proto::Op::Transfer) if !event.transfer_to.is_empty() => EventOp::Transfer { to: node_id(&event.transfer_to)? },
+        Ok(proto::Op::Transfer) => return Err(invalid("transfer event has no target node")),
+        Ok(proto::Op::Reject) if !event.rejected_origin.is_empty() => {
+            EventOp::Reject { origin: node_id(&event.rejected_origin)?, event_id: event.rejected_event }
+        }
+        Ok(proto::Op::Reject) => return Err(invalid("reject event has no rejected origin")),
+        Ok(proto::Op::Fail) => EventOp::Fail { delivery: event.delivery, attempt: event.attempt, dead_letter: event.dead_letter },
+        Ok(proto::Op::Retry) => EventOp::Retry { attempt: event.attempt },
+        Ok(proto::Op::StateUpdate) if !event.updated_origin.is_empty() => EventOp::StateUpdate {
+            origin: node_id(&event.updated_origin)?,
+            event_id: event.updated_event,
+            state: state_from_proto(event.updated_state)?,
+        },
+        Ok(proto::Op::StateUpdate) => return Err(invalid("state update has no updated origin")),
+        Ok(proto::Op::Purge) if !event.purged_key.is_empty() => EventOp::Purge { key: event.purged_key },
+        Ok(proto::Op::Purge) => return Err(invalid("purge event has no key")),
+        Ok(proto::Op::Control) => EventOp::Control { op: control_from_proto(event.control, event.control_subject, event.control_value)? },
+        Ok(proto::Op::Take) if !event.taken_origin.is_empty() => {
+            EventOp::Take { origin: node_id(&event.taken_origin)?, event_id: event.taken_event }
+        }
+        Ok(proto::Op::Take) => return Err(invalid("take event has no taken origin")),
+        Ok(proto::Op::Ack) if !event.acked.is_empty() => EventOp::Ack { deliveries: event.acked },
+        Ok(proto::Op::Ack) => return Err(invalid("ack event settles no deliveries")),
+        Err(_) => return Err(invalid(format!("unknown op {}", event.op))),
+    };
+    if event.origin_node.is_empty() {
+        return Err(invalid("event has no origin node"));
+    }
+    let item = match event.item {
+        Some(bytes) => Some(Arc::new(serde_json::from_slice(&bytes).map_err(io::Error::other)?)),
+        None => None,
+    };
+    // The attempt field carries the delivery attempt on dequeues and takes
+    let delivery_attempt = if matches!(op, EventOp::Dequeue | EventOp::Take { .. }) { event.attempt } else { 0 };
+    Ok(Event {
+        global_id: event.global_id,
+        origin_node: node_id(&event.origin_node)?,
+        op,
+        item,
+        clock: clock_from_proto(event.clock)?,
+        timestamp_ms: event.timestamp_ms,
+        trace: match event.traceparent.as_str() {
+            "" => None,
+            traceparent => Some(parse_traceparent(traceparent)?),
+        },
+        sequence: (!event.sequence_key.is_empty()).then_some(KeySequence { key: event.sequence_key, seq: event.sequence }),
+        epoch: event.epoch,
+        delivery_attempt,
+    })
+}
+
+fn parse_traceparent(traceparent: &str) -> io::Result<TraceContext> {
+    TraceContext::parse(traceparent).ok_or_else(|| invalid(format!("invalid traceparent {:?}", traceparent)))
+}
+
+/// Encode an envelope as a protobuf message
+pub(crate) fn encode<T: Serialize>(envelope: &Envelope<T>) -> io::Result<Vec<u8>> {
+    let message = match &envelope.message {
+        Message::Event { topic, event } => proto::envelope::Message::Event(proto::TopicEvent {
+            topic: topic.clone(),
+            event: Some(event_to_proto(event)?),
+        }),
+        other => proto::envelope::Message::Json(serde_json::to_vec(other).map_err(io::Error::other)?),
+    };
+    let envelope = proto::Envelope {
+        version: VERSION,
+        from: envelope.from.to_string(),
+        signature: envelope.signature.clone(),
+        message: Some(message),
+        traceparent: envelope.trace.map(|trace| trace.to_string()),
+        seq: envelope.seq.map(|seq| proto::ChannelSeq { channel: seq.channel, seq: seq.seq, acked: seq.acked }),
+    };
+    Ok(envelope.encode_to_vec())
+}
+
+/// Decode a protobuf envelope
+pub(crate) fn decode<T: DeserializeOwned>(bytes: &[u8]) -> io::Result<Envelope<T>> {
+    let envelope = proto::Envelope::decode(bytes).map_err(|e| invalid(e.to_string()))?;
+    if envelope.version > VERSION {
+        return Err(invalid(format!("wire version {} is newer than supported {}", envelope.version, VERSION)));
+    }
+    let message = match envelope.message {
+        Some(proto::envelope::Message::Event(topic_event)) => Message::Event {
+            topic: topic_event.topic,
+            event: event_from_proto(topic_event.event.ok_or_else(|| invalid("missing event"))?)?,
+        },
+        Some(proto::envelope::Message::Json(json)) => serde_json::from_slice(&json).map_err(io::Error::other)?,
+        None => return Err(invalid("envelope has no message")),
+    };
+    let trace = envelope.traceparent.map(|traceparent| parse_traceparent(&traceparent)).transpose()?;
+    let seq = envelope.seq.map(|seq| ChannelSeq { channel: seq.channel, seq: seq.seq, acked: seq.acked });
+    Ok(Envelope { from: node_id(&envelope.from)?, message, signature: envelope.signature, trace, seq })
+}
@@ -0,0 +1,2051 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, RwLock};
+use crate::core::buildcore::{ArrivalSignal, AuditEntry, AuditLog, ControlOp, Counter, DistributedQueueSystem, DistributedQueueSystemBuilder, Event, GcReport, NodeId, QueueError, State, TopicConfig};
+use crate::engine::acl::{ANONYMOUS, AccessDenied, Acl, Permission};
+use crate::engine::namespace::{NamespaceQuota, NamespaceStats, split_namespace};
+use crate::engine::partition::{partition_for, partition_name};
+use crate::engine::placement::{HashRing, NotOwner, StealPolicy};
+use crate::engine::stats::TopicStats;
+#[cfg(feature = "net")]
+use std::sync::atomic::AtomicBool;
+#[cfg(feature = "net")]
+use std::thread::{self, JoinHandle};
+#[cfg(feature = "net")]
+use std::collections::BTreeMap;
+#[cfg(feature = "net")]
+use std::sync::{Condvar, Mutex};
+#[cfg(feature = "net")]
+use serde::Serialize;
+#[cfg(feature = "net")]
+use std::time::{Duration, Instant};
+#[cfg(feature = "net")]
+use crate::engine::network::{Envelope, Heartbeat, Message, Request, Response, Transport};
+#[cfg(feature = "net")]
+use crate::core::buildcore::{EventOp, TraceContext};
+#[cfg(feature = "net")]
+use crate::engine::trace::{self, Hop};
+#[cfg(feature = "net")]
+use crate::engine::consumer::{ConsumerPool, Delivery, Subscription};
+#[cfg(feature = "net")]
+use crate::engine::raft::{Command, Consensus, Outbox, Raft, RaftConfig};
+#[cfg(feature = "net")]
+use crate::engine::topology::Topology;
+#[cfg(feature = "net")]
+use crate::engine::transfer::SnapshotServer;
+#[cfg(feature = "net")]
+use crate::engine::client::QueueClient;
+#[cfg(feature = "net")]
+use crate::engine::health::{Check, HealthPolicy, HealthReport};
+#[cfg(feature = "stream")]
+use crate::engine::consumer::DeliveryStream;
+#[cfg(feature = "auth")]
+use crate::engine::network::{Authenticator, Keyring};
+
+/// How often a started node collects garbage below the stable frontier
+#[cfg(feature = "net")]
+const GC_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a started node in Raft mode waits for a message before ticking the log
+#[cfg(feature = "net")]
+const RAFT_POLL: Duration = Duration::from_millis(10);
+/// How long a local operation in Raft mode waits to be applied
+#[cfg(feature = "net")]
+const RAFT_COMMIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+type TopicFactory<T> = Box<dyn Fn(&str) -> DistributedQueueSystemBuilder<T> + Send + Sync>;
+/// Re-reads the node's configuration and applies it, returning what changed, see `Node::set_reloader`
+type Reloader = Box<dyn Fn() -> std::io::Result<Vec<String>> + Send + Sync>;
+#[cfg(feature = "net")]
+type HeldEvents<T> = Vec<Event<T>>;
+#[cfg(feature = "net")]
+type Outcome<T> = (Mutex<Option<Result<Event<T>, QueueError>>>, Condvar);
+
+/// Messages a node could not sign, send or accept, see `ClusterStatus`
+#[cfg(feature = "net")]
+#[derive(Default)]
+struct Failures {
+    unsigned: u64,
+    unsent: HashMap<NodeId, u64>,
+    rejected: HashMap<NodeId, u64>,
+}
+
+/// Where the outcome of an operation proposed to the log goes once it is applied
+#[cfg(feature = "net")]
+enum Waiter<T> {
+    /// A client's request, answered over the transport
+    Client { client: NodeId, id: u64, trace: Option<TraceContext> },
+    /// A local call, blocked until the outcome is set
+    Local(Arc<Outcome<T>>),
+}
+
+/// A node hosting several named queues (topics)
+/// Each topic is an independent `DistributedQueueSystem` with its own clock and log;
+/// partitioned topics are backed by one such queue per partition.
+/// With a transport attached, local operations are broadcast to peers.
+pub struct Node<T> {
+    node_id: NodeId,
+    topics: RwLock<HashMap<String, Arc<DistributedQueueSystem<T>>>>,
+    /// Queues deleted with `ControlOp::TopicDeleted`, whose later events are ignored
+    deleted: RwLock<HashSet<String>>,
+    factory: TopicFactory<T>,
+    partitions: HashMap<String, u32>,
+    namespace_quotas: HashMap<String, NamespaceQuota>,
+    /// Live subscriptions and consumer workers per topic
+    consumers: Arc<std::sync::Mutex<HashMap<String, usize>>>,
+    /// Rotates unkeyed enqueues and the partition dequeues start at
+    next_partition: AtomicU32,
+    /// Queue ownership for client dequeues, everything is local if absent
+    placement: RwLock<Option<HashRing>>,
+    steal: Option<StealPolicy>,
+    acl: Option<Acl>,
+    audit: AuditLog,
+    reloader: RwLock<Option<Reloader>>,
+    /// Notified by every topic when an item lands, for `receive_wait`
+    arrivals: Arc<ArrivalSignal>,
+    epoch: Option<u64>,
+    role: NodeRole,
+    #[cfg(feature = "net")]
+    transport: RwLock<Option<Arc<dyn Transport<T>>>>,
+    /// How often the receive loop sends heartbeats, never if absent
+    #[cfg(feature = "net")]
+    heartbeat: Option<Duration>,
+    #[cfg(feature = "net")]
+    liveness: Mutex<HashMap<NodeId, PeerStatus>>,
+    #[cfg(feature = "net")]
+    backpressure: Option<BackpressurePolicy>,
+    #[cfg(feature = "net")]
+    health: HealthPolicy,
+    /// When the receive loop last turned, none if it hasn't started
+    #[cfg(feature = "net")]
+    last_tick: Mutex<Option<Instant>>,
+    /// Local queues this node told peers to hold back on
+    #[cfg(feature = "net")]
+    pressured: Mutex<HashSet<String>>,
+    /// Events held back from peers that signalled backpressure, by peer and queue
+    #[cfg(feature = "net")]
+    held: Mutex<HashMap<(NodeId, String), HeldEvents<T>>>,
+    #[cfg(feature = "net")]
+    failures: Mutex<Failures>,
+    /// Log enqueues and dequeues go through in Raft mode
+    #[cfg(feature = "net")]
+    consensus: Option<Box<dyn Consensus<Command<T>>>>,
+    /// Operations proposed here, by log index, with the term they were proposed in
+    #[cfg(feature = "net")]
+    proposed: Mutex<HashMap<u64, (u64, Waiter<T>)>>,
+    /// Held while applying committed entries, so they are applied in log order
+    #[cfg(feature = "net")]
+    applying: Mutex<()>,
+    #[cfg(feature = "net")]
+    topology: Option<Topology>,
+    #[cfg(feature = "net")]
+    snapshots: Option<SnapshotServer<T>>,
+    #[cfg(feature = "auth")]
+    auth: Option<Box<dyn Authenticator<T>>>,
+}
+
+/// What a node does with the items of its topics
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NodeRole {
+    /// Takes local enqueues and dequeues, and applies its peers'
+    #[default]
+    Replica,
+    /// Applies its peers' events and serves reads, but refuses local enqueues and
+    /// dequeues and never asks for work; not for Raft mode, where members apply the log
+    ReadOnly,
+    /// Stores no items: follows membership changes and, in Raft mode, votes and
+    /// acknowledges appends without ever leading, so a small cluster can break ties cheaply
+    Witness,
+}
+
+/// When a node asks peers to hold back events for one of its queues, by how full the
+/// queue or its causal buffer is (see `DistributedQueueSystem::load`)
+#[cfg(feature = "net")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BackpressurePolicy {
+    /// Load at which peers are asked to hold back
+    pub high: f64,
+    /// Load at which they may send again
+    pub low: f64,
+    /// Most events held for one peer and queue; beyond it they are dropped and the
+    /// peer catches up from heartbeats once it releases
+    pub max_held: usize,
+}
+
+#[cfg(feature = "net")]
+impl Default for BackpressurePolicy {
+    fn default() -> Self {
+        Self { high: 0.8, low: 0.5, max_held: 10_000 }
+    }
+}
+
+/// A node's view of the cluster, see `Node::cluster_status`
+#[cfg(feature = "net")]
+#[derive(Clone, Debug, Serialize)]
+pub struct ClusterStatus {
+    pub node_id: String,
+    /// Local queues peers were asked to hold back on
+    pub backpressure: Vec<String>,
+    pub peers: BTreeMap<String, PeerSummary>,
+    /// Messages dropped because they could not be signed
+    pub unsigned: u64,
+    /// Messages that could not be sent, by the node or client they were for
+    pub unsent: BTreeMap<String, u64>,
+    /// Envelopes that failed verification, by the node they claimed to come from
+    pub rejected: BTreeMap<String, u64>,
+}
+
+/// One peer in a `ClusterStatus`
+#[cfg(feature = "net")]
+#[derive(Clone, Debug, Serialize)]
+pub struct PeerSummary {
+    /// Since an event or heartbeat from the peer last arrived, none if it never has
+    pub last_seen_ms: Option<u64>,
+    /// Queues of the peer under backpressure, with the events held back for each
+    pub backpressure: BTreeMap<String, usize>,
+}
+
+/// What a node last heard from a peer
+#[cfg(feature = "net")]
+#[derive(Clone, Debug)]
+pub struct PeerStatus {
+    /// When an event or heartbeat from the peer last arrived
+    pub last_seen: Instant,
+    /// Queue depths from the peer's latest heartbeat
+    pub depths: HashMap<String, usize>,
+}
+
+impl<T: Send + Sync + 'static> Node<T> {
+    /// Create a node whose topics track the given peers
+    pub fn new(node_id: &str, peers: &[&str]) -> Self {
+        let id = node_id.to_string();
+        let peers: Vec<String> = peers.iter().map(|p| p.to_string()).collect();
+        Self::with_topic_factory(node_id, move |_topic| {
+            let peers: Vec<&str> = peers.iter().map(|p| p.as_str()).collect();
+            DistributedQueueSystem::builder(id.clone()).peers(&peers)
+        })
+    }
+
+    /// Create a node that configures each new topic with `factory`
+    pub fn with_topic_factory(
+        node_id: &str,
+        factory: impl Fn(&str) -> DistributedQueueSystemBuilder<T> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            node_id: NodeId::intern(node_id),
+            topics: RwLock::new(HashMap::new()),
+            deleted: RwLock::new(HashSet::new()),
+            factory: Box::new(factory),
+            partitions: HashMap::new(),
+            namespace_quotas: HashMap::new(),
+            consumers: Arc::default(),
+            next_partition: AtomicU32::new(0),
+            placement: RwLock::new(None),
+            steal: None,
+            acl: None,
+            audit: AuditLog::new(),
+            reloader: RwLock::new(None),
+            arrivals: Arc::new(ArrivalSignal::new()),
+            epoch: None,
+            role: NodeRole::Replica,
+            #[cfg(feature = "net")]
+            transport: RwLock::new(None),
+            #[cfg(feature = "net")]
+            heartbeat: None,
+            #[cfg(feature = "net")]
+            liveness: Mutex::new(HashMap::new()),
+            #[cfg(feature = "net")]
+            backpressure: None,
+            #[cfg(feature = "net")]
+            health: HealthPolicy::default(),
+            #[cfg(feature = "net")]
+            last_tick: Mutex::new(None),
+            #[cfg(feature = "net")]
+            pressured: Mutex::new(HashSet::new()),
+            #[cfg(feature = "net")]
+            held: Mutex::new(HashMap::new()),
+            #[cfg(feature = "net")]
+            failures: Mutex::default(),
+            #[cfg(feature = "net")]
+            consensus: None,
+            #[cfg(feature = "net")]
+            proposed: Mutex::new(HashMap::new()),
+            #[cfg(feature = "net")]
+            applying: Mutex::new(()),
+            #[cfg(feature = "net")]
+            topology: None,
+            #[cfg(feature = "net")]
+            snapshots: None,
+            #[cfg(feature = "auth")]
+            auth: None,
+        }
+    }
+
+    /// Run every topic as incarnation `epoch` of this node, see `DistributedQueueSystemBuilder::epoch`
+    pub fn with_epoch(mut self, epoch: u64) -> Self {
+        self.epoch = Some(epoch);
+        self
+    }
+
+    /// Act as `role`, see `NodeRole`; only replicas should be on the placement ring,
+    /// as the partitions any other node owns would go unconsumed
+    pub fn with_role(mut self, role: NodeRole) -> Self {
+        self.role = role;
+        self
+    }
+
+    pub fn role(&self) -> NodeRole {
+        self.role
+    }
+
+    /// Check client operations against `acl`; without one every client may do everything
+    /// With one, messages other than requests are only taken from the transport's peers
+    pub fn with_acl(mut self, acl: Acl) -> Self {
+        self.acl = Some(acl);
+        self
+    }
+
+    /// Split `topic` into `count` partitions; every node must use the same count
+    pub fn with_partitions(mut self, topic: &str, count: u32) -> Self {
+        self.partitions.insert(topic.to_string(), count.max(1));
+        self
+    }
+
+    /// Limit local enqueues on the topics of `namespace`, see `NamespaceQuota`
+    pub fn with_namespace_quota(mut self, namespace: &str, quota: NamespaceQuota) -> Self {
+        self.namespace_quotas.insert(namespace.to_string(), quota);
+        self
+    }
+
+    /// Only serve client dequeues for queues this node owns on `ring`
+    /// The node itself is added to the ring, and peers when a transport is attached
+    pub fn with_placement(self, mut ring: HashRing) -> Self {
+        ring.add_member(self.node_id.as_str());
+        *self.placement.write().unwrap() = Some(ring);
+        self
+    }
+
+    /// Let this node take work from owners of deep queues when it has none, and give
+    /// work to idle nodes; needs placement, and the policy should match on every node
+    pub fn with_work_stealing(mut self, policy: StealPolicy) -> Self {
+        self.steal = Some(policy);
+        self
+    }
+
+    /// Audit trail for refused client operations and administrative actions
+    pub fn with_audit_log(mut self, audit: AuditLog) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Have `reload` run `reloader`, which re-reads and applies the node's configuration,
+    /// e.g. `ServerConfig::reload`; set once the node is shared, so it can refer to it
+    pub fn set_reloader(&self, reloader: impl Fn() -> std::io::Result<Vec<String>> + Send + Sync + 'static) {
+        *self.reloader.write().unwrap() = Some(Box::new(reloader));
+    }
+
+    /// Re-read and apply the node's configuration without a restart, returning what changed
+    /// Fails with `Unsupported` if no reloader is set
+    pub fn reload(&self) -> std::io::Result<Vec<String>> {
+        self.reload_as(self.node_id.as_str())
+    }
+
+    /// Reload for a client identity, which the audit trail records as the actor
+    pub fn reload_as(&self, identity: &str) -> std::io::Result<Vec<String>> {
+        let reloader = self.reloader.read().unwrap();
+        let Some(reloader) = reloader.as_ref() else {
+            return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "configuration reload is not set up"));
+        };
+        let changes = reloader()?;
+        self.audit_admin(identity, "config_reload", None, changes.join(", "));
+        Ok(changes)
+    }
+
+    /// Stable frontier of each hosted queue of `topic` (one per partition), see
+    /// `DistributedQueueSystem::stability_watermark`
+    pub fn stability_watermark(&self, topic: &str) -> HashMap<String, HashMap<NodeId, u64>> {
+        self.queue_names(topic)
+            .into_iter()
+            .filter_map(|name| Some((name.clone(), self.get_topic(&name)?.stability_watermark())))
+            .collect()
+    }
+
+    /// Get node ID
+    pub fn node_id(&self) -> &str {
+        self.node_id.as_str()
+    }
+
+    /// Audit trail of refused client operations and administrative actions
+    pub fn audit_log(&self) -> &AuditLog {
+        &self.audit
+    }
+
+    /// Audit an administrative action this node carried out for `actor`
+    fn audit_admin(&self, actor: &str, action: &str, topic: Option<&str>, detail: impl Into<String>) {
+        self.audit.record(AuditEntry::admin(actor, action, topic, detail));
+    }
+
+    /// Identity a client's token stands for; the token itself without access control
+    pub fn identify<'a>(&'a self, token: Option<&'a str>) -> &'a str {
+        match &self.acl {
+            Some(acl) => acl.identify(token),
+            None => token.unwrap_or(ANONYMOUS),
+        }
+    }
+
+    /// Check a client's token against the access control, without auditing
+    pub fn is_permitted(&self, token: Option<&str>, topic: &str, permission: Permission) -> bool {
+        self.acl.as_ref().is_none_or(|acl| acl.check(token, topic, permission).is_ok())
+    }
+
+    /// Check a client's token against the access control, auditing refusals
+    pub fn authorize(&self, token: Option<&str>, topic: &str, permission: Permission) -> Result<(), AccessDenied> {
+        let Some(acl) = &self.acl else {
+            return Ok(());
+        };
+        acl.check(token, topic, permission).inspect_err(|denied| {
+            self.audit.record(AuditEntry::denied(&denied.identity, permission.as_str(), topic));
+        })
+    }
+
+    /// Check a client's token against every topic of a namespace, auditing the first refusal
+    pub fn authorize_namespace(&self, token: Option<&str>, namespace: &str, permission: Permission) -> Result<(), AccessDenied> {
+        self.namespace_topics(namespace).iter().try_for_each(|topic| self.authorize(token, topic, permission))
+    }
+
+    /// Add a cluster member to the placement ring; false without a ring or if already present
+    pub fn add_member(&self, member: &str) -> bool {
+        let added = self.placement.write().unwrap().as_mut().is_some_and(|ring| ring.add_member(member));
+        if added {
+            self.audit_admin(self.node_id.as_str(), "member_add", None, member);
+        }
+        added
+    }
+
+    /// Remove a cluster member, handing its queues to the remaining ones
+    pub fn remove_member(&self, member: &str) -> bool {
+        let removed = self.placement.write().unwrap().as_mut().is_some_and(|ring| ring.remove_member(member));
+        if removed {
+            self.audit_admin(self.node_id.as_str(), "member_remove", None, member);
+        }
+        removed
+    }
+
+    /// Member owning a queue (a topic or `topic#partition`), if placement is enabled
+    pub fn owner(&self, queue: &str) -> Option<String> {
+        self.placement.read().unwrap().as_ref()?.owner(queue).map(str::to_string)
+    }
+
+    /// Check that this node may hand out a queue's items; always true without placement
+    pub fn check_owner(&self, queue: &str) -> Result<(), NotOwner> {
+        match self.owner(queue) {
+            Some(owner) if owner != self.node_id.as_str() => Err(NotOwner { queue: queue.to_string(), owner }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Partitions of `topic` this node owns; all of them without placement
+    pub fn owned_partitions(&self, topic: &str) -> Vec<u32> {
+        let count = self.partition_count(topic).unwrap_or(0);
+        (0..count).filter(|&p| self.check_owner(&partition_name(topic, p)).is_ok()).collect()
+    }
+
+    /// Queues of `topic`: its partitions, or the topic itself
+    fn queue_names(&self, topic: &str) -> Vec<String> {
+        match self.partition_count(topic) {
+            Some(count) => (0..count).map(|p| partition_name(topic, p)).collect(),
+            None => vec![topic.to_string()],
+        }
+    }
+
+    /// Next item of `topic` another node transferred to this one
+    pub fn take_transferred(&self, topic: &str) -> Option<Arc<T>> {
+        self.take_transferred_delivery(topic).map(|(_, item)| item)
+    }
+
+    /// Next transferred item of `topic` with its delivery ID, see `fail`
+    pub fn take_transferred_delivery(&self, topic: &str) -> Option<(u64, Arc<T>)> {
+        self.queue_names(topic).iter().find_map(|name| self.get_topic(name)?.take_transferred_delivery())
+    }
+
+    /// Report that processing an item of `topic` failed, and broadcast the failure
+    /// `delivery` is the ID of the dequeue or transfer event that handed the item out
+    pub fn fail(&self, topic: &str, delivery: u64) -> Result<Event<T>, QueueError> {
+        for name in self.queue_names(topic) {
+            let Some(queue) = self.get_topic(&name) else {
+                continue;
+            };
+            match queue.fail(delivery) {
+                Ok(event) => {
+                    self.broadcast(&name, &event);
+                    return Ok(event);
+                }
+                Err(QueueError::UnknownDelivery { .. }) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(QueueError::UnknownDelivery { delivery })
+    }
+
+    /// Which delivery of its item `delivery` of `topic` is, see `DistributedQueueSystem::delivery_attempt`
+    pub fn delivery_attempt(&self, topic: &str, delivery: u64) -> Option<u32> {
+        self.queue_names(topic).iter().filter_map(|name| self.get_topic(name)).find_map(|queue| queue.delivery_attempt(delivery))
+    }
+
+    /// Report that a dequeued item was processed, see `DistributedQueueSystem::ack`
+    pub fn ack(&self, topic: &str, delivery: u64) -> bool {
+        self.queue_names(topic).iter().filter_map(|name| self.get_topic(name)).any(|queue| queue.ack(delivery))
+    }
+
+    /// Report that many deliveries of `topic` were processed, with one ack event per
+    /// partition holding any of them, broadcast so replicas log the settlement
+    pub fn ack_batch(&self, topic: &str, deliveries: &[u64]) -> Vec<Event<T>> {
+        let mut events = Vec::new();
+        for name in self.queue_names(topic) {
+            if let Some(event) = self.get_topic(&name).and_then(|queue| queue.ack_batch(deliveries)) {
+                self.broadcast(&name, &event);
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    /// Move a queue's log entry for event `event_id` of `origin` to `state`, and broadcast
+    /// the update so the peers' entries follow
+    pub fn update_state(&self, queue: &str, origin: &str, event_id: u64, state: State) -> Result<Event<T>, QueueError> {
+        let event = self.topic(queue).update_state(origin, event_id, state)?;
+        self.audit_admin(self.node_id.as_str(), "state_override", Some(queue), format!("event {} of {} to {:?}", event_id, origin, state));
+        self.broadcast(queue, &event);
+        Ok(event)
+    }
+
+    /// Purge the items of `topic` whose log key is `key`, in every partition, and broadcast
+    /// the purges so every peer removes and redacts them too; see `DistributedQueueSystem::purge_item`
+    pub fn purge_item(&self, topic: &str, key: &str) -> Result<Vec<Event<T>>, QueueError> {
+        self.purge_item_as(self.node_id.as_str(), topic, key)
+    }
+
+    /// Purge for a client identity, which the audit trail records as the actor
+    pub fn purge_item_as(&self, identity: &str, topic: &str, key: &str) -> Result<Vec<Event<T>>, QueueError> {
+        let mut events = Vec::new();
+        for name in self.queue_names(topic) {
+            let Some(queue) = self.get_topic(&name) else {
+                continue;
+            };
+            let event = queue.purge_item(key)?;
+            self.broadcast(&name, &event);
+            events.push(event);
+        }
+        if !events.is_empty() {
+            self.audit_admin(identity, "purge", Some(topic), key);
+        }
+        Ok(events)
+    }
+
+    /// Apply a control action to every queue of `topic`, creating it if needed, and broadcast
+    /// it so peers apply it at the same point of each queue's event stream
+    /// Membership actions also update this node's placement ring, as they do on peers
+    pub fn control(&self, topic: &str, op: ControlOp) -> Vec<Event<T>> {
+        self.control_as(self.node_id.as_str(), topic, op)
+    }
+
+    /// Control action for a client identity, which the audit trail records as the actor
+    pub fn control_as(&self, identity: &str, topic: &str, op: ControlOp) -> Vec<Event<T>> {
+        let events: Vec<Event<T>> = self
+            .queue_names(topic)
+            .iter()
+            .map(|name| {
+                let event = self.topic(name).control(op.clone());
+                self.broadcast(name, &event);
+                event
+            })
+            .collect();
+        if !self.apply_membership(&op) {
+            self.audit_admin(identity, "control", Some(topic), op.to_string());
+        }
+        events
+    }
+
+    /// Create `topic` and log its creation as its first control event, which peers create
+    /// it from once broadcast; nothing if it exists
+    /// `config` travels with the creation, so every replica applies the same settings
+    pub fn create_topic(&self, topic: &str, config: TopicConfig) -> Vec<Event<T>> {
+        let names = self.queue_names(topic);
+        if names.iter().any(|name| self.get_topic(name).is_some()) {
+            return Vec::new();
+        }
+        self.deleted.write().unwrap().retain(|name| !names.contains(name));
+        let config = (!config.is_empty()).then_some(config);
+        self.control(topic, ControlOp::TopicCreated { topic: topic.to_string(), config })
+    }
+
+    /// Override settings of `topic` over those it has, and broadcast the result under the
+    /// next version so peers take it on too; nothing if it doesn't exist
+    /// Replicas keep the newest version, so concurrent changes settle on one of them
+    pub fn configure_topic(&self, topic: &str, overrides: TopicConfig) -> Vec<Event<T>> {
+        self.configure_topic_as(self.node_id.as_str(), topic, overrides)
+    }
+
+    /// Configure for a client identity, which the audit trail records as the actor
+    pub fn configure_topic_as(&self, identity: &str, topic: &str, overrides: TopicConfig) -> Vec<Event<T>> {
+        let events: Vec<Event<T>> = self
+            .queue_names(topic)
+            .iter()
+            .filter_map(|name| {
+                let queue = self.get_topic(name)?;
+                let config = queue.topic_config().unwrap_or_default().merge(&overrides);
+                let version = queue.topic_config_version() + 1;
+                let event = queue.control(ControlOp::TopicConfigured { topic: topic.to_string(), config, version });
+                self.broadcast(name, &event);
+                Some(event)
+            })
+            .collect();
+        if !events.is_empty() {
+            self.audit_admin(identity, "control", Some(topic), format!("topic {} configured with {}", topic, overrides));
+        }
+        events
+    }
+
+    /// Settings in effect on `topic`, its own over the node's, see
+    /// `DistributedQueueSystem::effective_config`; None if it doesn't exist
+    pub fn topic_config(&self, topic: &str) -> Option<TopicConfig> {
+        self.queue_names(topic).iter().find_map(|name| self.get_topic(name)).map(|queue| queue.effective_config())
+    }
+
+    /// Delete `topic`, dropping its items, and broadcast the deletion so peers do too;
+    /// nothing if it doesn't exist
+    /// Nodes forget the topic's queues and ignore their events until `create_topic` brings
+    /// it back. Using a deleted topic otherwise brings it back on this node only.
+    pub fn delete_topic(&self, topic: &str) -> Vec<Event<T>> {
+        let op = ControlOp::TopicDeleted { topic: topic.to_string() };
+        let events: Vec<Event<T>> = self
+            .queue_names(topic)
+            .iter()
+            .filter_map(|name| {
+                let event = self.get_topic(name)?.control(op.clone());
+                self.broadcast(name, &event);
+                self.forget(name);
+                Some(event)
+            })
+            .collect();
+        if !events.is_empty() {
+            self.audit_admin(self.node_id.as_str(), "control", Some(topic), op.to_string());
+        }
+        events
+    }
+
+    /// Drop a deleted queue, and ignore its events from now on
+    fn forget(&self, name: &str) {
+        self.topics.write().unwrap().remove(name);
+        self.deleted.write().unwrap().insert(name.to_string());
+    }
+
+    /// Whether `name` is a deleted queue, see `delete_topic`
+    pub fn is_deleted(&self, name: &str) -> bool {
+        self.deleted.read().unwrap().contains(name)
+    }
+
+    /// Announce that `member` joined (or left) the cluster on every topic this node hosts,
+    /// so each topic's log records it in order with its data, and update the placement ring
+    pub fn announce_membership(&self, member: &str, joined: bool) -> Vec<Event<T>> {
+        let member = NodeId::intern(member);
+        let op = if joined { ControlOp::MemberAdded { member } } else { ControlOp::MemberRemoved { member } };
+        let events = self
+            .topic_names()
+            .iter()
+            .map(|name| {
+                let event = self.topic(name).control(op.clone());
+                self.broadcast(name, &event);
+                event
+            })
+            .collect();
+        self.apply_membership(&op);
+        events
+    }
+
+    /// Update the placement ring for a membership action; false for other actions
+    fn apply_membership(&self, op: &ControlOp) -> bool {
+        match op {
+            ControlOp::MemberAdded { member } => {
+                self.add_member(member.as_str());
+                true
+            }
+            ControlOp::MemberRemoved { member } => {
+                self.remove_member(member.as_str());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Enqueue failed items whose backoff has passed on every topic, and broadcast them;
+    /// also apply remote events waiting out a redelivery backoff, drop expired items of the
+    /// queues this node owns, and pass dequeue tokens on
+    /// Runs in the receive loop of a started node; call it directly otherwise
+    pub fn process_retries(&self) -> usize {
+        let topics: Vec<(String, Arc<DistributedQueueSystem<T>>)> =
+            self.topics.read().unwrap().iter().map(|(name, queue)| (name.clone(), queue.clone())).collect();
+        let mut retried = 0;
+        for (name, queue) in topics {
+            for event in queue.process_retries() {
+                self.broadcast(&name, &event);
+                retried += 1;
+            }
+            queue.process_redeliveries();
+            let expired = if self.check_owner(&name).is_ok() { queue.expire() } else { Vec::new() };
+            for event in expired.into_iter().chain(queue.pass_token()).chain(queue.take_outgoing()) {
+                self.broadcast(&name, &event);
+            }
+        }
+        retried
+    }
+
+    /// Collect garbage below the stable frontier on every topic, see
+    /// `DistributedQueueSystem::collect_garbage`
+    /// Runs periodically in the receive loop of a started node; call it directly otherwise
+    /// A topic it fails on counts the failure in its metrics' `store_failed`
+    pub fn collect_garbage(&self) -> GcReport {
+        let topics: Vec<Arc<DistributedQueueSystem<T>>> = self.topics.read().unwrap().values().cloned().collect();
+        let mut total = GcReport::default();
+        for queue in topics {
+            match queue.collect_garbage() {
+                Ok(report) => {
+                    total.applied += report.applied;
+                    total.log_entries += report.log_entries;
+                }
+                Err(_) => queue.record(Counter::StoreFailed),
+            }
+        }
+        total
+    }
+
+    /// Transfer up to `max` items of a queue to node `to`, and broadcast the transfers
+    pub fn transfer(&self, queue: &str, to: &str, max: usize) -> Vec<Event<T>> {
+        let events = self.topic(queue).transfer(to, max);
+        for event in &events {
+            self.broadcast(queue, event);
+        }
+        events
+    }
+
+    /// Spread the items of the queues of `topic` this node owns evenly over the placement
+    /// members, transferring every other member its share; returns the items transferred
+    /// to each, nothing without placement
+    pub fn rebalance(&self, topic: &str) -> HashMap<String, usize> {
+        self.rebalance_as(self.node_id.as_str(), topic)
+    }
+
+    /// Rebalance for a client identity, which the audit trail records as the actor
+    pub fn rebalance_as(&self, identity: &str, topic: &str) -> HashMap<String, usize> {
+        let members: Vec<String> = match self.placement.read().unwrap().as_ref() {
+            Some(ring) => ring.members().into_iter().map(str::to_string).collect(),
+            None => return HashMap::new(),
+        };
+        let mut moved = HashMap::new();
+        for name in self.queue_names(topic) {
+            let Some(queue) = self.get_topic(&name).filter(|_| self.check_owner(&name).is_ok()) else {
+                continue;
+            };
+            let share = queue.queue_state().0 / members.len().max(1);
+            for member in members.iter().filter(|member| share > 0 && member.as_str() != self.node_id.as_str()) {
+                *moved.entry(member.clone()).or_insert(0) += self.transfer(&name, member, share).len();
+            }
+        }
+        if !moved.is_empty() {
+            self.audit_admin(identity, "rebalance", Some(topic), format!("{:?}", moved));
+        }
+        moved
+    }
+
+    /// Get a topic, creating it on first use
+    pub fn topic(&self, name: &str) -> Arc<DistributedQueueSystem<T>> {
+        if let Some(topic) = self.get_topic(name) {
+            return topic;
+        }
+        let mut topics = self.topics.write().unwrap();
+        topics
+            .entry(name.to_string())
+            .or_insert_with(|| {
+                let mut builder = (self.factory)(name).arrival_signal(self.arrivals.clone());
+                if let Some(epoch) = self.epoch {
+                    builder = builder.epoch(epoch);
+                }
+                if self.role != NodeRole::Replica {
+                    builder = builder.read_only();
+                }
+                Arc::new(builder.build())
+            })
+            .clone()
+    }
+
+    /// Get an existing topic
+    pub fn get_topic(&self, name: &str) -> Option<Arc<DistributedQueueSystem<T>>> {
+        self.topics.read().unwrap().get(name).cloned()
+    }
+
+    /// Number of partitions of `topic`, if it is partitioned
+    pub fn partition_count(&self, topic: &str) -> Option<u32> {
+        self.partitions.get(topic).copied()
+    }
+
+    /// Queue backing one partition of a partitioned topic
+    pub fn partition(&self, topic: &str, partition: u32) -> Option<Arc<DistributedQueueSystem<T>>> {
+        let count = self.partition_count(topic)?;
+        (partition < count).then(|| self.topic(&partition_name(topic, partition)))
+    }
+
+    /// Names of all topics, sorted; partitions are listed by the name of their queue
+    pub fn topic_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.topics.read().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Namespaces with at least one topic on this node, sorted
+    pub fn namespaces(&self) -> Vec<String> {
+        let mut namespaces: Vec<String> = self.topics.read().unwrap().keys().map(|name| split_namespace(name).0.to_string()).collect();
+        namespaces.sort();
+        namespaces.dedup();
+        namespaces
+    }
+
+    /// Topics of a namespace, sorted; a partitioned topic is listed once, by its own name
+    pub fn namespace_topics(&self, namespace: &str) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .topics
+            .read()
+            .unwrap()
+            .keys()
+            .filter(|name| split_namespace(name).0 == namespace)
+            .map(|name| name.split_once('#').map_or(name.as_str(), |(base, _)| base).to_string())
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Totals over a namespace's queues; None if it has no topics on this node
+    pub fn namespace_stats(&self, namespace: &str) -> Option<NamespaceStats> {
+        let topics = self.namespace_topics(namespace);
+        if topics.is_empty() {
+            return None;
+        }
+        let mut stats = NamespaceStats::new(namespace);
+        stats.topics = topics.len();
+        for queue in topics.iter().flat_map(|topic| self.queue_names(topic)).filter_map(|name| self.get_topic(&name)) {
+            stats.add(&queue.stats());
+        }
+        Some(stats)
+    }
+
+    /// Statistics of `topic` on this node, partitions added up; None if it has no queue here
+    pub fn topic_stats(&self, topic: &str) -> Option<TopicStats> {
+        let queues: Vec<_> = self.queue_names(topic).iter().filter_map(|name| self.get_topic(name)).collect();
+        if queues.is_empty() {
+            return None;
+        }
+        let mut stats = TopicStats { topic: topic.to_string(), nodes: vec![self.node_id.to_string()], consumers: self.consumers(topic), ..TopicStats::default() };
+        for queue in queues {
+            let (enqueued, dequeued) = queue.totals();
+            stats.depth += queue.queue_state().0;
+            stats.enqueued += enqueued;
+            stats.dequeued += dequeued;
+            stats.oldest_item_age_ms = stats.oldest_item_age_ms.max(queue.oldest_item_age().map(|age| age.as_millis() as u64));
+            stats.redeliveries += queue.redeliveries();
+            stats.pending_redeliveries += queue.pending_retries();
+        }
+        Some(stats)
+    }
+
+    /// Live subscriptions and consumer workers of `topic` on this node
+    pub fn consumers(&self, topic: &str) -> usize {
+        self.consumers.lock().unwrap().get(topic).copied().unwrap_or(0)
+    }
+
+    #[cfg(feature = "net")]
+    pub(crate) fn consumer_counts(&self) -> &Arc<std::sync::Mutex<HashMap<String, usize>>> {
+        &self.consumers
+    }
+
+    /// Check a local enqueue onto `queue` against its namespace's quota
+    fn check_namespace(&self, queue: &str) -> Result<(), QueueError> {
+        let namespace = split_namespace(queue).0;
+        let Some(quota) = self.namespace_quotas.get(namespace) else {
+            return Ok(());
+        };
+        let topics = self.namespace_topics(namespace);
+        if let Some(max) = quota.max_topics {
+            let topic = queue.split_once('#').map_or(queue, |(base, _)| base);
+            if topics.len() >= max && !topics.iter().any(|t| t == topic) {
+                return Err(QueueError::TooManyTopics { namespace: namespace.to_string(), max });
+            }
+        }
+        if let Some(quota) = quota.max_items {
+            let depth: usize = topics
+                .iter()
+                .flat_map(|topic| self.queue_names(topic))
+                .filter_map(|name| self.get_topic(&name))
+                .map(|queue| queue.queue_state().0)
+                .sum();
+            if depth >= quota {
+                return Err(QueueError::NamespaceQuotaExceeded { namespace: namespace.to_string(), quota });
+            }
+        }
+        Ok(())
+    }
+
+    /// Enqueue on a topic and broadcast the event to peers
+    /// Partitioned topics take unkeyed items round-robin. In Raft mode the enqueue goes
+    /// through the log instead, see `with_raft`.
+    pub fn enqueue(&self, topic: &str, item: T) -> Result<Event<T>, QueueError> {
+        self.enqueue_on(&self.route(topic, None), item)
+    }
+
+    /// Enqueue on the partition `key` maps to, keeping items with the same key in order
+    /// On a topic without partitions this is a plain enqueue
+    pub fn enqueue_keyed(&self, topic: &str, key: &str, item: T) -> Result<Event<T>, QueueError> {
+        self.enqueue_on(&self.route(topic, Some(key)), item)
+    }
+
+    /// Enqueue for a client identity, subject to the topic's per-client rate limit
+    pub fn enqueue_as(&self, identity: &str, topic: &str, key: Option<&str>, item: T) -> Result<Event<T>, QueueError> {
+        let queue = self.route(topic, key);
+        self.check_namespace(&queue)?;
+        #[cfg(feature = "net")]
+        if self.consensus.is_some() {
+            return self.commit(Command::Enqueue { queue, item });
+        }
+        let event = self.topic(&queue).try_enqueue_as(identity, item)?;
+        self.broadcast(&queue, &event);
+        Ok(event)
+    }
+
+    /// Queue an enqueue lands on: the topic, or a partition by key or round-robin
+    fn route(&self, topic: &str, key: Option<&str>) -> String {
+        match (self.partition_count(topic), key) {
+            (Some(count), Some(key)) => partition_name(topic, partition_for(key, count)),
+            (Some(count), None) => partition_name(topic, self.next_partition.fetch_add(1, Ordering::Relaxed) % count),
+            (None, _) => topic.to_string(),
+        }
+    }
+
+    fn enqueue_on(&self, queue: &str, item: T) -> Result<Event<T>, QueueError> {
+        self.check_namespace(queue)?;
+        #[cfg(feature = "net")]
+        if self.consensus.is_some() {
+            return self.commit(Command::Enqueue { queue: queue.to_string(), item });
+        }
+        let event = self.topic(queue).try_enqueue(item)?;
+        self.broadcast(queue, &event);
+        Ok(event)
+    }
+
+    /// Dequeue from a topic and broadcast the event to peers
+    /// Partitioned topics hand out the head of any non-empty partition. In Raft mode the
+    /// dequeue goes through the log instead, panicking if it can't, see `try_dequeue`.
+    pub fn dequeue(&self, topic: &str) -> (Option<Arc<T>>, Event<T>) {
+        #[cfg(feature = "net")]
+        if self.consensus.is_some() {
+            return self.try_dequeue(topic).expect("dequeue through the Raft log");
+        }
+        if let Some(count) = self.partition_count(topic) {
+            let all: Vec<u32> = (0..count).collect();
+            let (_, item, event) = self.dequeue_any(topic, &all).expect("partitioned topics have a partition");
+            return (item, event);
+        }
+        self.dequeue_on(topic)
+    }
+
+    /// `dequeue`, failing rather than panicking when a node in Raft mode can't commit it,
+    /// e.g. as it doesn't lead
+    pub fn try_dequeue(&self, topic: &str) -> Result<(Option<Arc<T>>, Event<T>), QueueError> {
+        #[cfg(feature = "net")]
+        if self.consensus.is_some() {
+            if self.holds_items(topic) {
+                return Ok(self.topic(&self.queue_names(topic)[0]).dequeue());
+            }
+            let event = self.commit(Command::Dequeue { topic: topic.to_string() })?;
+            return Ok((event.item.clone(), event));
+        }
+        Ok(self.dequeue(topic))
+    }
+
+    /// Dequeue the first item of a topic that `pred` accepts, see `DistributedQueueSystem::dequeue_if`
+    /// Partitions are searched in order; returns the delivery ID with the item
+    pub fn dequeue_if(&self, topic: &str, mut pred: impl FnMut(&T) -> bool) -> Option<(u64, Arc<T>)> {
+        self.queue_names(topic).iter().find_map(|name| {
+            self.renew_lease(name);
+            let (item, event) = self.topic(name).dequeue_if(&mut pred)?;
+            self.broadcast(name, &event);
+            Some((event.global_id, item))
+        })
+    }
+
+    /// Dequeue from one of the given partitions, for consumers assigned a share of them
+    /// Returns the partition the item came from; partitions out of range are ignored
+    pub fn dequeue_partitions(&self, topic: &str, partitions: &[u32]) -> Option<(u32, Arc<T>)> {
+        let (partition, item, _) = self.dequeue_any(topic, partitions)?;
+        Some((partition, item?))
+    }
+
+    /// Like `dequeue_partitions`, also returning the delivery ID
+    pub fn dequeue_partitions_delivery(&self, topic: &str, partitions: &[u32]) -> Option<(u32, u64, Arc<T>)> {
+        let (partition, item, event) = self.dequeue_any(topic, partitions)?;
+        Some((partition, event.global_id, item?))
+    }
+
+    /// Dequeue from the first non-empty partition, starting at a rotating one so
+    /// no partition starves; if all are empty, dequeue (nothing) from one of them
+    fn dequeue_any(&self, topic: &str, partitions: &[u32]) -> Option<(u32, Option<Arc<T>>, Event<T>)> {
+        let count = self.partition_count(topic)?;
+        let valid: Vec<u32> = partitions.iter().copied().filter(|&p| p < count).collect();
+        if valid.is_empty() {
+            return None;
+        }
+        let start = self.next_partition.fetch_add(1, Ordering::Relaxed) as usize;
+        let partition = (0..valid.len())
+            .map(|i| valid[(start + i) % valid.len()])
+            .find(|&p| self.get_topic(&partition_name(topic, p)).is_some_and(|queue| queue.peek().is_some()))
+            .unwrap_or(valid[start % valid.len()]);
+        let (item, event) = self.dequeue_on(&partition_name(topic, partition));
+        Some((partition, item, event))
+    }
+
+    fn dequeue_on(&self, queue: &str) -> (Option<Arc<T>>, Event<T>) {
+        self.renew_lease(queue);
+        let (item, event) = self.topic(queue).dequeue();
+        self.broadcast(queue, &event);
+        (item, event)
+    }
+
+    /// Acquire or renew the lease a queue needs for dequeues, see `DistributedQueueSystem::renew_lease`
+    /// Dequeues hand out nothing until peers acknowledge a new lease
+    fn renew_lease(&self, queue: &str) {
+        if let Some(event) = self.topic(queue).renew_lease() {
+            self.broadcast(queue, &event);
+        }
+    }
+
+    #[cfg(not(feature = "net"))]
+    fn broadcast(&self, _topic: &str, _event: &Event<T>) {}
+}
+
+#[cfg(feature = "net")]
+impl<T: Send + Sync + 'static> Node<T> {
+    /// Sign broadcast events and peer messages with this node's key, and reject remote
+    /// events that aren't signed by their origin node and peer messages not signed by
+    /// their sender, see `crate::engine::network::auth`
+    #[cfg(feature = "auth")]
+    pub fn with_keyring(mut self, keyring: Keyring) -> Self
+    where
+        T: serde::Serialize,
+    {
+        self.auth = Some(Box::new(keyring));
+        self
+    }
+
+    /// Send a heartbeat to every peer each `interval` from the receive loop, see `send_heartbeat`
+    pub fn with_heartbeat(mut self, interval: Duration) -> Self {
+        self.heartbeat = Some(interval);
+        self
+    }
+
+    /// Ask peers to hold back events for queues loaded past `policy.high`, and hold back
+    /// for peers that ask; checked by the receive loop, see `check_backpressure`
+    pub fn with_backpressure(mut self, policy: BackpressurePolicy) -> Self {
+        self.backpressure = Some(policy);
+        self
+    }
+
+    /// Judge health and readiness by `policy`, see `crate::engine::health`
+    pub fn with_health(mut self, policy: HealthPolicy) -> Self {
+        self.health = policy;
+        self
+    }
+
+    /// Run enqueues and dequeues through a Raft log among `members`, for linearizable
+    /// queues in place of causal replication, see `crate::engine::raft`
+    /// Only the leader takes them, and every member applies them once committed. Other
+    /// operations stay local and causal, and reads are served from the local replica.
+    /// The leader admits enqueues before proposing them, by its own rate limits, quotas and
+    /// pauses; members apply committed operations whatever their own.
+    /// A node given the witness role beforehand joins as a witness.
+    /// The log is kept in memory, so see `with_raft_log` for a node that may restart.
+    pub fn with_raft(self, members: &[&str], config: RaftConfig) -> Self
+    where
+        T: Clone,
+    {
+        let (members, config) = self.raft_membership(members, config);
+        let raft = Raft::new(self.node_id, &members, config);
+        self.with_consensus(raft)
+    }
+
+    /// `with_raft`, keeping the term, vote and log in the file at `path`, see `Raft::open`
+    /// A restarted node applies the committed log again, so its queues shouldn't be persisted too.
+    pub fn with_raft_log(self, members: &[&str], config: RaftConfig, path: impl AsRef<std::path::Path>) -> std::io::Result<Self>
+    where
+        T: Clone + serde::Serialize + serde::de::DeserializeOwned,
+    {
+        let (members, config) = self.raft_membership(members, config);
+        let raft = Raft::open(self.node_id, &members, config, path)?;
+        Ok(self.with_consensus(raft))
+    }
+
+    fn raft_membership(&self, members: &[&str], config: RaftConfig) -> (Vec<NodeId>, RaftConfig) {
+        let members = members.iter().map(|&member| NodeId::intern(member)).collect();
+        let witness = config.witness || self.role == NodeRole::Witness;
+        (members, RaftConfig { witness, ..config })
+    }
+
+    /// `with_raft` with another implementation of the log
+    pub fn with_consensus(mut self, log: impl Consensus<Command<T>> + 'static) -> Self {
+        self.consensus = Some(Box::new(log));
+        self
+    }
+
+    /// Leader of the log as far as this node knows; None outside Raft mode
+    pub fn leader(&self) -> Option<String> {
+        self.consensus.as_ref()?.leader().map(|leader| leader.to_string())
+    }
+
+    /// Whether every queue of `topic` hands out nothing here, e.g. as it is paused; committed
+    /// dequeues hand out items on every node, so this node doesn't propose one then
+    fn holds_items(&self, topic: &str) -> bool {
+        self.queue_names(topic).iter().all(|name| self.get_topic(name).is_some_and(|queue| queue.holds_items()))
+    }
+
+    /// Propose `command` and wait until it is applied here
+    fn commit(&self, command: Command<T>) -> Result<Event<T>, QueueError> {
+        let outcome: Arc<Outcome<T>> = Arc::new((Mutex::new(None), Condvar::new()));
+        let index = self.propose(command, Waiter::Local(outcome.clone()))?;
+        let (slot, applied) = &*outcome;
+        let mut slot = applied.wait_timeout_while(slot.lock().unwrap(), RAFT_COMMIT_TIMEOUT, |slot| slot.is_none()).unwrap().0;
+        slot.take().unwrap_or(Err(QueueError::CommitTimedOut { index }))
+    }
+
+    /// Append `command` to the log on behalf of `waiter`; returns its index
+    /// Enqueues are admitted here, as every node applies committed ones without checks
+    fn propose(&self, command: Command<T>, waiter: Waiter<T>) -> Result<u64, QueueError> {
+        let command = match command {
+            Command::Enqueue { queue, item } => Command::Enqueue { item: self.topic(&queue).admit(item)?, queue },
+            command => command,
+        };
+        let consensus = self.consensus.as_ref().expect("proposing in Raft mode");
+        let mut proposed = self.proposed.lock().unwrap();
+        let (index, term, outbox) = consensus.propose(command).map_err(|leader| QueueError::NotLeader { leader: leader.map(|l| l.to_string()) })?;
+        let overwritten = proposed.insert(index, (term, waiter));
+        drop(proposed);
+        // Proposed here before, but another leader's entry took its place
+        if let Some((_, waiter)) = overwritten {
+            self.resolve(waiter, Err(QueueError::NotLeader { leader: self.leader() }));
+        }
+        self.send_raft(outbox);
+        self.apply_committed();
+        Ok(index)
+    }
+
+    /// Apply the entries committed since last time, in log order, and pass on the outcomes
+    fn apply_committed(&self) {
+        let Some(consensus) = &self.consensus else {
+            return;
+        };
+        let _applying = self.applying.lock().unwrap();
+        for (index, entry) in consensus.take_committed() {
+            let outcome = self.apply(entry.command);
+            let waiter = self.proposed.lock().unwrap().remove(&index);
+            match waiter {
+                Some((term, waiter)) if term == entry.term => self.resolve(waiter, outcome),
+                Some((_, waiter)) => self.resolve(waiter, Err(QueueError::NotLeader { leader: self.leader() })),
+                None => {}
+            }
+        }
+    }
+
+    /// Apply a committed operation to the local replica; every member gets the same outcome
+    fn apply(&self, command: Command<T>) -> Result<Event<T>, QueueError> {
+        match command {
+            Command::Enqueue { queue, item } => Ok(self.topic(&queue).enqueue_committed(item)),
+            Command::Dequeue { topic } => {
+                let names = self.queue_names(&topic);
+                let queue = names.iter().find(|name| self.get_topic(name).is_some_and(|queue| queue.peek().is_some())).unwrap_or(&names[0]);
+                Ok(self.topic(queue).dequeue_committed().1)
+            }
+        }
+    }
+
+    fn resolve(&self, waiter: Waiter<T>, outcome: Result<Event<T>, QueueError>) {
+        match waiter {
+            Waiter::Local(outcome_slot) => {
+                let (slot, applied) = &*outcome_slot;
+                *slot.lock().unwrap() = Some(outcome);
+                applied.notify_all();
+            }
+            Waiter::Client { client, id, trace } => {
+                let response = match outcome {
+                    Ok(event) if matches!(event.op, EventOp::Enqueue) => Response::Enqueued { event_id: event.global_id },
+                    Ok(event) => Response::dequeued(event.item.map(|item| (event.global_id, item))),
+                    Err(e) => Response::Error(e.to_string()),
+                };
+                self.reply(client, id, response, trace);
+            }
+        }
+    }
+
+    /// Send the log's messages; it sends again what is lost, so failures aren't reported
+    fn send_raft(&self, outbox: Outbox<Command<T>>) {
+        let Some(transport) = self.transport.read().unwrap().clone() else {
+            return;
+        };
+        for (peer, message) in outbox {
+            if let Some(envelope) = self.sign(Envelope::new(self.node_id, Message::Raft { message })) {
+                transport.send(peer, &envelope).ok();
+            }
+        }
+    }
+
+    /// Broadcast events by region, see `crate::engine::topology`; every node should
+    /// share the same topology
+    pub fn with_topology(mut self, topology: Topology) -> Self {
+        self.topology = Some(topology);
+        self
+    }
+
+    /// Serve snapshots of the topics to clients, for peers to restore from, see
+    /// `crate::engine::transfer`
+    pub fn with_snapshot_transfer(mut self) -> Self
+    where
+        T: serde::Serialize,
+    {
+        self.snapshots = Some(SnapshotServer::<T>::new());
+        self
+    }
+
+    /// Serve snapshots like `with_snapshot_transfer`, keeping each for `retention` after
+    /// its last chunk was asked for rather than `crate::engine::transfer::SNAPSHOT_RETENTION`
+    pub fn with_snapshot_retention(mut self, retention: Duration) -> Self
+    where
+        T: serde::Serialize,
+    {
+        self.snapshots.get_or_insert_with(SnapshotServer::<T>::new).set_retention(retention);
+        self
+    }
+
+    /// `topic_stats` over this node and the nodes `peers` are connected to, see
+    /// `TopicStats::aggregate`; peers that can't be reached are left out of `nodes`
+    pub fn cluster_topic_stats(&self, topic: &str, peers: &[&QueueClient<T>]) -> TopicStats
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        let remote = peers.iter().filter_map(|client| client.topic_stats(topic).ok());
+        TopicStats::aggregate(topic, self.topic_stats(topic).into_iter().chain(remote))
+    }
+
+    /// Replace `topic`'s contents with a snapshot pulled from the node `client` is connected to
+    /// Its events from then on apply on top, see `DistributedQueueSystem::restore`
+    pub fn restore_from(&self, topic: &str, client: &QueueClient<T>) -> std::io::Result<()>
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        let snapshot = client.fetch_snapshot(topic)?;
+        self.topic(topic).restore(snapshot);
+        Ok(())
+    }
+
+    /// Route local operations through `transport`
+    pub fn attach(&self, transport: Arc<dyn Transport<T>>) {
+        for peer in transport.peers() {
+            self.add_member(peer.as_str());
+        }
+        *self.transport.write().unwrap() = Some(transport);
+    }
+
+    fn broadcast(&self, topic: &str, event: &Event<T>) {
+        let Some(transport) = self.transport.read().unwrap().clone() else {
+            return;
+        };
+        let Some(envelope) = self.seal(topic, event) else {
+            return;
+        };
+        let mut held = self.held.lock().unwrap();
+        if held.is_empty() && self.topology.is_none() {
+            drop(held);
+            for (peer, _) in transport.broadcast(&envelope) {
+                self.unsent(peer);
+            }
+            return;
+        }
+        let peers = transport.peers();
+        let targets = match &self.topology {
+            Some(topology) => topology.targets(self.node_id, &peers),
+            None => peers,
+        };
+        let max_held = self.backpressure.unwrap_or_default().max_held;
+        let mut ready = Vec::new();
+        for peer in targets {
+            match held.get_mut(&(peer, topic.to_string())) {
+                Some(waiting) if waiting.len() < max_held => waiting.push(event.clone()),
+                Some(waiting) => waiting.clear(),
+                None => ready.push(peer),
+            }
+        }
+        drop(held);
+        for peer in ready {
+            if transport.send(peer, &envelope).is_err() {
+                self.unsent(peer);
+            }
+        }
+    }
+
+    /// Count a message that could not be sent to `to`
+    fn unsent(&self, to: NodeId) {
+        *self.failures.lock().unwrap().unsent.entry(to).or_default() += 1;
+    }
+
+    /// Envelope for a topic event, signed if this node has a keyring
+    fn seal(&self, topic: &str, event: &Event<T>) -> Option<Envelope<T>> {
+        self.sign(Envelope::event(self.node_id, topic, event.clone()))
+    }
+
+    /// `envelope`, signed if this node has a keyring; None if it cannot be signed
+    #[allow(unused_mut)]
+    fn sign(&self, mut envelope: Envelope<T>) -> Option<Envelope<T>> {
+        #[cfg(feature = "auth")]
+        if let Some(auth) = &self.auth
+            && auth.sign(&mut envelope).is_err()
+        {
+            self.failures.lock().unwrap().unsigned += 1;
+            return None;
+        }
+        Some(envelope)
+    }
+
+    /// Tell every peer this node is alive, with the clock and depth of each queue it hosts
+    pub fn send_heartbeat(&self) {
+        let Some(transport) = self.transport.read().unwrap().clone() else {
+            return;
+        };
+        let queues = self
+            .topics
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, queue)| (name.clone(), Heartbeat { clock: queue.vector_clock(), depth: queue.queue_state().0 }))
+            .collect();
+        let Some(envelope) = self.sign(Envelope::new(self.node_id, Message::Heartbeat { queues })) else {
+            return;
+        };
+        for (peer, _) in transport.broadcast(&envelope) {
+            self.unsent(peer);
+        }
+    }
+
+    /// Take in a peer's heartbeat: advance each queue's view of the peer's clock, and
+    /// resend the peer the events it is missing from this node
+    fn receive_heartbeat(&self, peer: NodeId, queues: HashMap<String, Heartbeat>) {
+        let transport = self.transport.read().unwrap().clone();
+        for (name, heartbeat) in &queues {
+            let Some(queue) = self.get_topic(name) else {
+                continue;
+            };
+            queue.observe_peer_clock(peer, &heartbeat.clock);
+            let Some(transport) = &transport else {
+                continue;
+            };
+            let Ok(missing) = queue.events_missing_from(&heartbeat.clock) else {
+                queue.record(Counter::StoreFailed);
+                continue;
+            };
+            for event in missing {
+                if let Some(envelope) = self.seal(name, &event)
+                    && transport.send(peer, &envelope).is_err()
+                {
+                    self.unsent(peer);
+                    break;
+                }
+            }
+        }
+        let depths = queues.into_iter().map(|(name, heartbeat)| (name, heartbeat.depth)).collect();
+        self.liveness.lock().unwrap().insert(peer, PeerStatus { last_seen: Instant::now(), depths });
+    }
+
+    /// Tell peers about local queues whose load crossed the policy's marks since the last
+    /// check; returns the queues now under backpressure
+    pub fn check_backpressure(&self) -> Vec<String> {
+        let Some(policy) = self.backpressure else {
+            return Vec::new();
+        };
+        let loads: Vec<(String, f64)> = self
+            .topics
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|(name, queue)| Some((name.clone(), queue.load()?)))
+            .collect();
+        let mut pressured = self.pressured.lock().unwrap();
+        let mut changed = Vec::new();
+        for (queue, load) in loads {
+            if load >= policy.high && pressured.insert(queue.clone()) {
+                changed.push((queue, true));
+            } else if load <= policy.low && pressured.remove(&queue) {
+                changed.push((queue, false));
+            }
+        }
+        let mut engaged: Vec<String> = pressured.iter().cloned().collect();
+        drop(pressured);
+        engaged.sort();
+        let Some(transport) = self.transport.read().unwrap().clone() else {
+            return engaged;
+        };
+        for (queue, engaged) in changed {
+            let Some(envelope) = self.sign(Envelope::new(self.node_id, Message::Backpressure { queue, engaged })) else {
+                continue;
+            };
+            for (peer, _) in transport.broadcast(&envelope) {
+                self.unsent(peer);
+            }
+        }
+        engaged
+    }
+
+    /// Start holding back events of `queue` for `peer`, or send it the held ones
+    fn receive_backpressure(&self, peer: NodeId, queue: String, engaged: bool) {
+        let mut held = self.held.lock().unwrap();
+        if engaged {
+            held.entry((peer, queue)).or_default();
+            return;
+        }
+        let Some(waiting) = held.remove(&(peer, queue.clone())) else {
+            return;
+        };
+        drop(held);
+        let Some(transport) = self.transport.read().unwrap().clone() else {
+            return;
+        };
+        for event in waiting {
+            if let Some(envelope) = self.seal(&queue, &event)
+                && transport.send(peer, &envelope).is_err()
+            {
+                self.unsent(peer);
+                break;
+            }
+        }
+    }
+
+    /// Liveness of each peer, the backpressure in both directions, and the messages this
+    /// node failed to sign, send or accept
+    pub fn cluster_status(&self) -> ClusterStatus {
+        let mut backpressure: Vec<String> = self.pressured.lock().unwrap().iter().cloned().collect();
+        backpressure.sort();
+        let peers = self.transport.read().unwrap().as_ref().map_or_else(Vec::new, |transport| transport.peers());
+        let liveness = self.liveness.lock().unwrap();
+        let held = self.held.lock().unwrap();
+        let mut summaries: BTreeMap<String, PeerSummary> = peers
+            .into_iter()
+            .chain(liveness.keys().copied())
+            .map(|peer| {
+                let last_seen_ms = liveness.get(&peer).map(|status| status.last_seen.elapsed().as_millis() as u64);
+                (peer.to_string(), PeerSummary { last_seen_ms, backpressure: BTreeMap::new() })
+            })
+            .collect();
+        for ((peer, queue), waiting) in held.iter() {
+            summaries
+                .entry(peer.to_string())
+                .or_insert_with(|| PeerSummary { last_seen_ms: None, backpressure: BTreeMap::new() })
+                .backpressure
+                .insert(queue.clone(), waiting.len());
+        }
+        let failures = self.failures.lock().unwrap();
+        let by_node = |counts: &HashMap<NodeId, u64>| counts.iter().map(|(node, count)| (node.to_string(), *count)).collect();
+        ClusterStatus {
+            node_id: self.node_id.to_string(),
+            backpressure,
+            peers: summaries,
+            unsigned: failures.unsigned,
+            unsent: by_node(&failures.unsent),
+            rejected: by_node(&failures.rejected),
+        }
+    }
+
+    /// Note that `peer` is alive, keeping the depths it last reported
+    fn mark_seen(&self, peer: NodeId) {
+        let mut liveness = self.liveness.lock().unwrap();
+        match liveness.get_mut(&peer) {
+            Some(status) => status.last_seen = Instant::now(),
+            None => {
+                liveness.insert(peer, PeerStatus { last_seen: Instant::now(), depths: HashMap::new() });
+            }
+        }
+    }
+
+    /// What this node last heard from each peer that has sent an event or heartbeat
+    pub fn peer_status(&self) -> HashMap<String, PeerStatus> {
+        self.liveness.lock().unwrap().iter().map(|(peer, status)| (peer.to_string(), status.clone())).collect()
+    }
+
+    /// Peers of the attached transport not heard from within `timeout`, including ones never heard from
+    pub fn suspected_peers(&self, timeout: Duration) -> Vec<String> {
+        let Some(transport) = self.transport.read().unwrap().clone() else {
+            return Vec::new();
+        };
+        let liveness = self.liveness.lock().unwrap();
+        transport
+            .peers()
+            .into_iter()
+            .filter(|peer| liveness.get(peer).is_none_or(|status| status.last_seen.elapsed() > timeout))
+            .map(|peer| peer.to_string())
+            .collect()
+    }
+
+    /// Whether the node works at all: its receive loop turns and its storage takes writes
+    pub fn health(&self) -> HealthReport {
+        HealthReport::new(self.node_id.as_str(), [("receive_loop", self.check_receive_loop()), ("storage", self.check_storage())])
+    }
+
+    /// Whether the node should get traffic: healthy, with peers reachable, queues short
+    /// of saturation and caught up with its peers
+    pub fn readiness(&self) -> HealthReport {
+        HealthReport::new(
+            self.node_id.as_str(),
+            [
+                ("receive_loop", self.check_receive_loop()),
+                ("storage", self.check_storage()),
+                ("transport", self.check_transport()),
+                ("buffers", self.check_buffers()),
+                ("catch_up", self.check_catch_up()),
+            ],
+        )
+    }
+
+    fn check_receive_loop(&self) -> Check {
+        match *self.last_tick.lock().unwrap() {
+            None => Check::fail("not started"),
+            Some(tick) if tick.elapsed() > self.health.stall_timeout => {
+                Check::fail(format!("stalled for {} ms", tick.elapsed().as_millis()))
+            }
+            Some(_) => Check::pass(),
+        }
+    }
+
+    /// Write and remove a probe file in the storage dir
+    fn check_storage(&self) -> Check {
+        let Some(dir) = &self.health.storage_dir else {
+            return Check::skip("no storage dir");
+        };
+        let probe = dir.join(format!(".probe-{}", self.node_id));
+        match std::fs::write(&probe, b"ok").and_then(|_| std::fs::remove_file(&probe)) {
+            Ok(()) => Check::pass(),
+            Err(e) => Check::fail(format!("{} is not writable: {}", dir.display(), e)),
+        }
+    }
+
+    /// Fails only when every peer is unreachable, as the node can serve without some of them
+    fn check_transport(&self) -> Check {
+        let Some(transport) = self.transport.read().unwrap().clone() else {
+            return Check::fail("no transport attached");
+        };
+        let peers = transport.peers();
+        if peers.is_empty() {
+            return Check::pass();
+        }
+        let Some(timeout) = self.health.peer_timeout.or(self.heartbeat.map(|interval| interval * 3)) else {
+            return Check::skip("no heartbeats, peer reachability unknown");
+        };
+        let mut unreachable = self.suspected_peers(timeout);
+        if unreachable.len() < peers.len() {
+            return Check::pass();
+        }
+        unreachable.sort();
+        Check::fail(format!("no peer reachable: {}", unreachable.join(", ")))
+    }
+
+    fn check_buffers(&self) -> Check {
+        let mut saturated: Vec<String> = self
+            .topics
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, queue)| queue.load().is_some_and(|load| load >= self.health.max_load))
+            .map(|(name, _)| name.clone())
+            .collect();
+        if saturated.is_empty() {
+            return Check::pass();
+        }
+        saturated.sort();
+        Check::fail(format!("saturated: {}", saturated.join(", ")))
+    }
+
+    fn check_catch_up(&self) -> Check {
+        let mut behind: Vec<String> = self
+            .topics
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, queue)| (name, queue.lag()))
+            .filter(|&(_, lag)| lag > self.health.max_lag)
+            .map(|(name, lag)| format!("{} ({} events)", name, lag))
+            .collect();
+        if behind.is_empty() {
+            return Check::pass();
+        }
+        behind.sort();
+        Check::fail(format!("catching up: {}", behind.join(", ")))
+    }
+
+    /// Apply an envelope received from a peer or client; returns true if an event was applied
+    /// Client requests are answered through the attached transport
+    pub fn handle(&self, envelope: Envelope<T>) -> bool {
+        #[cfg(feature = "auth")]
+        if let Some(auth) = &self.auth
+            && auth.verify(&envelope).is_err()
+        {
+            *self.failures.lock().unwrap().rejected.entry(envelope.from).or_default() += 1;
+            if let Message::Event { topic, .. } = &envelope.message
+                && let Some(queue) = self.get_topic(topic)
+            {
+                queue.record(Counter::RemoteRejected);
+            }
+            return false;
+        }
+        // With access control, only peers may replicate or steer replication; clients request
+        if self.acl.is_some() && !matches!(envelope.message, Message::Request { .. } | Message::Response { .. }) && !self.is_peer(envelope.from) {
+            return false;
+        }
+        if let Message::Event { .. } = &envelope.message {
+            self.mark_seen(envelope.from);
+        }
+        let signature = envelope.signature;
+        match envelope.message {
+            Message::Event { topic, event } => {
+                let _span = trace::hop(Hop::Replicate, self.node_id, &topic, event.trace);
+                // The ring follows membership as announced, whether or not the event is buffered
+                if let EventOp::Control { op } = &event.op {
+                    self.apply_membership(op);
+                }
+                self.relay(envelope.from, &topic, &event, signature);
+                if self.role == NodeRole::Witness {
+                    return false;
+                }
+                // Only a new creation brings a deleted queue back
+                if self.is_deleted(&topic) {
+                    if !matches!(&event.op, EventOp::Control { op: ControlOp::TopicCreated { .. } }) {
+                        return false;
+                    }
+                    self.deleted.write().unwrap().remove(&topic);
+                }
+                let queue = self.topic(&topic);
+                let applied = queue.apply_remote_event(event);
+                for event in queue.take_outgoing() {
+                    self.broadcast(&topic, &event);
+                }
+                // The deletion may have waited in the causal buffer until now
+                if queue.is_deleted() {
+                    self.forget(&topic);
+                }
+                applied
+            }
+            Message::Request { id, request, token } => {
+                if let Some(request) = self.serve_raft(envelope.from, id, request, token.as_deref(), envelope.trace) {
+                    let (response, trace) = self.serve(request, token.as_deref(), envelope.trace);
+                    self.reply(envelope.from, id, response, trace);
+                }
+                false
+            }
+            // Nodes don't issue requests, so there is nothing waiting on a response
+            Message::Response { .. } => false,
+            Message::Steal { queue, max } => {
+                self.grant_steal(envelope.from, &queue, max);
+                false
+            }
+            Message::Heartbeat { queues } => {
+                self.receive_heartbeat(envelope.from, queues);
+                false
+            }
+            Message::Backpressure { queue, engaged } => {
+                self.mark_seen(envelope.from);
+                self.receive_backpressure(envelope.from, queue, engaged);
+                false
+            }
+            // Consumed by a `ReliableTransport`, if one is in use
+            Message::Ack { .. } => false,
+            Message::Raft { message } => {
+                if let Some(consensus) = &self.consensus {
+                    self.send_raft(consensus.step(envelope.from, message));
+                    self.apply_committed();
+                }
+                false
+            }
+        }
+    }
+
+    /// Pass an event received from `sender` on to the peers the topology has this node
+    /// relay it to, under its origin's signature
+    fn relay(&self, sender: NodeId, topic: &str, event: &Event<T>, signature: Option<String>) {
+        let Some(topology) = &self.topology else {
+            return;
+        };
+        let Some(transport) = self.transport.read().unwrap().clone() else {
+            return;
+        };
+        let relays = topology.relays(self.node_id, sender, &transport.peers());
+        if relays.is_empty() {
+            return;
+        }
+        let mut envelope = Envelope::event(self.node_id, topic, event.clone());
+        envelope.signature = signature;
+        for peer in relays {
+            if transport.send(peer, &envelope).is_err() {
+                self.unsent(peer);
+            }
+        }
+    }
+
+    /// Whether `node` is one of the peers of the attached transport
+    fn is_peer(&self, node: NodeId) -> bool {
+        self.transport.read().unwrap().as_ref().is_some_and(|transport| transport.peers().contains(&node))
+    }
+
+    /// Ask the owner of the deepest queue of `topic` someone else owns for a batch
+    /// Depths come from the local replicas; returns the queue asked for, if any
+    pub fn request_steal(&self, topic: &str) -> Option<String> {
+        if self.role != NodeRole::Replica {
+            return None;
+        }
+        let policy = self.steal?;
+        let transport = self.transport.read().unwrap().clone()?;
+        let (queue, owner) = self
+            .queue_names(topic)
+            .into_iter()
+            .filter_map(|name| {
+                let depth = self.get_topic(&name)?.queue_state().0;
+                let owner = self.check_owner(&name).err()?.owner;
+                (depth >= policy.min_depth).then_some((depth, name, owner))
+            })
+            .max_by_key(|(depth, ..)| *depth)
+            .map(|(_, name, owner)| (name, owner))?;
+        let envelope = self.sign(Envelope::new(self.node_id, Message::Steal { queue: queue.clone(), max: policy.batch }))?;
+        let owner = NodeId::from(owner.as_str());
+        if transport.send(owner, &envelope).is_err() {
+            self.unsent(owner);
+            return None;
+        }
+        Some(queue)
+    }
+
+    /// Transfer part of an owned queue to an idle peer that asked for it
+    fn grant_steal(&self, thief: NodeId, queue: &str, max: usize) {
+        let Some(policy) = self.steal else {
+            return;
+        };
+        if !self.is_peer(thief) {
+            return;
+        }
+        let Some(depth) = self.get_topic(queue).map(|q| q.queue_state().0) else {
+            return;
+        };
+        if self.check_owner(queue).is_err() || depth < policy.min_depth {
+            return;
+        }
+        self.transfer(queue, thief.as_str(), max.min(policy.batch).min(depth / 2));
+    }
+
+    /// An item transferred to this node and its delivery ID, or else a steal request so
+    /// there is one next time
+    pub(crate) fn take_or_steal(&self, topic: &str) -> Option<(u64, Arc<T>)> {
+        let item = self.take_transferred_delivery(topic);
+        if item.is_none() {
+            self.request_steal(topic);
+        }
+        item
+    }
+
+    /// Enqueue for a client identity as part of the client's trace, if it sent one, and broadcast
+    /// The enqueue is reported as a span, whose context the event carries on to replicas and consumers
+    pub fn enqueue_traced(&self, identity: &str, topic: &str, key: Option<&str>, item: T, trace: Option<TraceContext>) -> Result<Event<T>, QueueError> {
+        let queue = self.route(topic, key);
+        self.check_namespace(&queue)?;
+        if self.consensus.is_some() {
+            return self.commit(Command::Enqueue { queue, item });
+        }
+        let span = trace::hop(Hop::Enqueue, self.node_id, topic, trace);
+        let event = self.topic(&queue).try_enqueue_traced_as(identity, item, span.context())?;
+        self.broadcast(&queue, &event);
+        Ok(event)
+    }
+
+    /// Next item of `topic` this node may hand out, with its delivery ID: from owned queues,
+    /// or else items transferred to this node; unknown topics aren't created
+    pub(crate) fn next_delivery(&self, topic: &str) -> Option<(u64, Arc<T>)> {
+        if self.partition_count(topic).is_some() {
+            let owned = self.owned_partitions(topic);
+            return self.dequeue_partitions_delivery(topic, &owned).map(|(_, delivery, item)| (delivery, item)).or_else(|| self.take_or_steal(topic));
+        }
+        if self.check_owner(topic).is_err() {
+            return self.take_or_steal(topic);
+        }
+        self.get_topic(topic)?;
+        let (item, event) = self.try_dequeue(topic).ok()?;
+        Some((event.global_id, item?))
+    }
+
+    /// Whether a queue of `topic` has an item or a transferred one, i.e. `next_delivery` may succeed
+    pub(crate) fn has_arrivals(&self, topic: &str) -> bool {
+        self.queue_names(topic)
+            .iter()
+            .filter_map(|name| self.get_topic(name))
+            .any(|queue| queue.queue_state().0 > 0 || queue.transferred_len() > 0)
+    }
+
+    /// Notified whenever an item lands on any topic
+    #[cfg(feature = "stream")]
+    pub(crate) fn arrivals(&self) -> &ArrivalSignal {
+        &self.arrivals
+    }
+
+    /// Blocking iterator over the deliveries of `topic`, waiting for each like `receive_wait`
+    pub fn subscribe(&self, topic: &str) -> Subscription<'_, T> {
+        Subscription::new(self, topic)
+    }
+
+    /// Run `concurrency` worker threads handing each delivery of `topic` to `handler`, acking it
+    /// on Ok and failing it (so a retry policy applies) on Err or panic; stops when the pool drops
+    pub fn register_consumer<E, F>(self: &Arc<Self>, topic: &str, handler: F, concurrency: usize) -> ConsumerPool
+    where
+        E: std::fmt::Display,
+        F: Fn(Delivery<T>) -> Result<(), E> + Send + Sync + 'static,
+    {
+        ConsumerPool::start(self.clone(), topic, handler, concurrency)
+    }
+
+    /// Async stream of the deliveries of `topic`, for `while let Some(delivery) = stream.next().await`
+    #[cfg(feature = "stream")]
+    pub fn subscribe_stream(self: &Arc<Self>, topic: &str) -> DeliveryStream<T> {
+        DeliveryStream::new(self.clone(), topic)
+    }
+
+    /// Next item of `topic` this node may hand out, with its delivery ID, waiting up to
+    /// `max_wait` for a local or remote enqueue (or a transfer) to land if there is none yet
+    pub fn receive_wait(&self, topic: &str, max_wait: Duration) -> Option<(u64, Arc<T>)> {
+        let deadline = Instant::now() + max_wait;
+        let mut seen = self.arrivals.count();
+        if let Some(delivery) = self.next_delivery(topic) {
+            return Some(delivery);
+        }
+        // Arrivals on other topics wake us too; only try again once this one has something
+        loop {
+            if !self.arrivals.wait_past(seen, deadline) {
+                return None;
+            }
+            seen = self.arrivals.count();
+            if self.has_arrivals(topic) && let Some(delivery) = self.next_delivery(topic) {
+                return Some(delivery);
+            }
+        }
+    }
+
+    /// In Raft mode, propose a client's enqueue or dequeue to the log and answer once it is
+    /// applied; other requests are handed back for `serve`
+    fn serve_raft(&self, client: NodeId, id: u64, request: Request<T>, token: Option<&str>, trace: Option<TraceContext>) -> Option<Request<T>> {
+        let (topic, permission, command) = match request {
+            _ if self.consensus.is_none() || self.role != NodeRole::Replica => return Some(request),
+            Request::Enqueue { topic, item, key } => {
+                let queue = self.route(&topic, key.as_deref());
+                (topic, Permission::Produce, Command::Enqueue { queue, item })
+            }
+            Request::Dequeue { topic, .. } => (topic.clone(), Permission::Consume, Command::Dequeue { topic }),
+            request => return Some(request),
+        };
+        if let Err(denied) = self.authorize(token, &topic, permission) {
+            self.reply(client, id, Response::Error(denied.to_string()), trace);
+            return None;
+        }
+        let admitted = match &command {
+            Command::Enqueue { queue, .. } => self.check_namespace(queue),
+            Command::Dequeue { topic } if self.holds_items(topic) => {
+                self.reply(client, id, Response::dequeued(None), trace);
+                return None;
+            }
+            Command::Dequeue { .. } => Ok(()),
+        };
+        if let Err(e) = admitted.and_then(|()| self.propose(command, Waiter::Client { client, id, trace })) {
+            self.reply(client, id, Response::Error(e.to_string()), trace);
+        }
+        None
+    }
+
+    /// Run a client request against the local topics, in the client's trace if it sent one
+    /// Returns the response and the trace context to reply with
+    fn serve(&self, request: Request<T>, token: Option<&str>, trace: Option<TraceContext>) -> (Response<T>, Option<TraceContext>) {
+        let needs = match &request {
+            Request::Enqueue { topic, .. } => Some((topic, Permission::Produce)),
+            Request::Dequeue { topic, .. }
+            | Request::Peek { topic }
+            | Request::Stats { topic }
+            | Request::TopicStats { topic }
+            | Request::FailedEvents { topic }
+            | Request::LogTail { topic, .. }
+            | Request::Fail { topic, .. }
+            | Request::Snapshot { topic, .. } => Some((topic, Permission::Consume)),
+            Request::Topics | Request::NamespaceStats { .. } => None,
+        };
+        if let Some((topic, permission)) = needs
+            && let Err(denied) = self.authorize(token, topic, permission)
+        {
+            return (Response::Error(denied.to_string()), trace);
+        }
+        if let Request::NamespaceStats { namespace } = &request
+            && let Err(denied) = self.authorize_namespace(token, namespace, Permission::Consume)
+        {
+            return (Response::Error(denied.to_string()), trace);
+        }
+        if self.role != NodeRole::Replica && matches!(request, Request::Enqueue { .. } | Request::Dequeue { .. } | Request::Fail { .. }) {
+            return (Response::Error(QueueError::ReadOnly.to_string()), trace);
+        }
+        let mut reply_trace = trace;
+        let response = match request {
+            Request::Enqueue { topic, item, key } => match self.enqueue_traced(self.identify(token), &topic, key.as_deref(), item, trace) {
+                Ok(event) => {
+                    reply_trace = event.trace;
+                    Response::Enqueued { event_id: event.global_id }
+                }
+                Err(e) => Response::Error(e.to_string()),
+            },
+            // Only partitions this node owns, narrowed to the consumer's assignment if given
+            Request::Dequeue { topic, partitions } if self.partition_count(&topic).is_some() => {
+                let mut owned = self.owned_partitions(&topic);
+                if let Some(partitions) = partitions {
+                    owned.retain(|p| partitions.contains(p));
+                }
+                let (delivered, item_trace) = match self.dequeue_any(&topic, &owned) {
+                    Some((_, Some(item), event)) => (Some((event.global_id, item)), event.trace),
+                    _ => (self.take_or_steal(&topic), None),
+                };
+                reply_trace = trace::hop(Hop::Dequeue, self.node_id, &topic, item_trace).context();
+                Response::dequeued(delivered)
+            }
+            Request::Dequeue { topic, .. } if let Err(e) = self.check_owner(&topic) => match self.take_or_steal(&topic) {
+                Some(delivered) => Response::dequeued(Some(delivered)),
+                None => Response::Error(e.to_string()),
+            },
+            // Unknown topics are empty; don't create them just to dequeue nothing
+            Request::Dequeue { topic, .. } => match self.get_topic(&topic) {
+                Some(_) => {
+                    let (item, event) = self.dequeue(&topic);
+                    reply_trace = trace::hop(Hop::Dequeue, self.node_id, &topic, event.trace).context();
+                    Response::dequeued(item.map(|item| (event.global_id, item)))
+                }
+                None => Response::dequeued(None),
+            },
+            Request::Fail { topic, delivery } => match self.fail(&topic, delivery) {
+                Ok(event) => Response::Failed { event_id: event.global_id },
+                Err(e) => Response::Error(e.to_string()),
+            },
+            Request::Peek { topic } => Response::Peeked { item: self.get_topic(&topic).and_then(|queue| queue.peek()) },
+            Request::Stats { topic } => match self.get_topic(&topic) {
+                Some(queue) => Response::Stats(queue.stats()),
+                None => Response::Error(format!("unknown topic {}", topic)),
+            },
+            Request::TopicStats { topic } => match self.topic_stats(&topic) {
+                Some(stats) => Response::TopicStats(stats),
+                None => Response::Error(format!("unknown topic {}", topic)),
+            },
+            Request::FailedEvents { topic } => match self.get_topic(&topic) {
+                Some(queue) => Response::FailedEvents(queue.failed_events()),
+                None => Response::Error(format!("unknown topic {}", topic)),
+            },
+            Request::LogTail { topic, count } => match self.get_topic(&topic) {
+                Some(queue) => match queue.log_tail(count) {
+                    Ok(entries) => Response::Logs(entries),
+                    Err(e) => Response::Error(e.to_string()),
+                },
+                None => Response::Error(format!("unknown topic {}", topic)),
+            },
+            Request::Topics => Response::Topics(self.topic_names()),
+            Request::NamespaceStats { namespace } => match self.namespace_stats(&namespace) {
+                Some(stats) => Response::NamespaceStats(stats),
+                None => Response::Error(format!("unknown namespace {}", namespace)),
+            },
+            Request::Snapshot { topic, offset, digest, max } => match (&self.snapshots, self.get_topic(&topic)) {
+                (None, _) => Response::Error("snapshot transfer is not enabled".to_string()),
+                (_, None) => Response::Error(format!("unknown topic {}", topic)),
+                (Some(snapshots), Some(queue)) => match snapshots.chunk(&topic, &queue, offset, digest, max) {
+                    Ok(chunk) => Response::SnapshotChunk(chunk),
+                    Err(e) => Response::Error(e.to_string()),
+                },
+            },
+        };
+        (response, reply_trace)
+    }
+
+    fn reply(&self, client: NodeId, id: u64, response: Response<T>, trace: Option<TraceContext>) {
+        let Some(transport) = self.transport.read().unwrap().clone() else {
+            return;
+        };
+        let envelope = Envelope::new(self.node_id, Message::Response { id, response }).with_trace(trace);
+        if transport.send(client, &envelope).is_err() {
+            self.unsent(client);
+        }
+    }
+
+    /// Attach `transport` and apply incoming envelopes on a background thread
+    pub fn start(self: &Arc<Self>, transport: Arc<dyn Transport<T>>) -> NodeHandle {
+        self.attach(transport.clone());
+        let stop = Arc::new(AtomicBool::new(false));
+        let node = self.clone();
+        let running = stop.clone();
+        let thread = thread::spawn(move || {
+            let mut last_heartbeat: Option<Instant> = None;
+            let mut last_gc = Instant::now();
+            while !running.load(Ordering::SeqCst) {
+                *node.last_tick.lock().unwrap() = Some(Instant::now());
+                if let Some(interval) = node.heartbeat
+                    && last_heartbeat.is_none_or(|sent| sent.elapsed() >= interval)
+                {
+                    node.send_heartbeat();
+                    last_heartbeat = Some(Instant::now());
+                }
+                if let Some(consensus) = &node.consensus {
+                    node.send_raft(consensus.tick());
+                }
+                let wait = if node.consensus.is_some() { RAFT_POLL } else { Duration::from_millis(100) };
+                if let Some(envelope) = transport.recv_timeout(wait) {
+                    node.handle(envelope);
+                }
+                node.process_retries();
+                node.check_backpressure();
+                if last_gc.elapsed() >= GC_INTERVAL {
+                    node.collect_garbage();
+                    last_gc = Instant::now();
+                }
+            }
+        });
+        NodeHandle { stop, thread: Some(thread) }
+    }
+}
+
+/// Handle to a node's receive loop
+#[cfg(feature = "net")]
+pub struct NodeHandle {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[cfg(feature = "net")]
+impl NodeHandle {
+    /// Stop the receive loop and wait for it to exit
+    pub fn shutdown(mut self) {
+        self.stop_and_join();
+    }
+
+    fn stop_and_join(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+#[cfg(feature = "net")]
+impl Drop for NodeHandle {
+    fn drop(&mut self) {
+        self.stop_and_join();
+    }
+}
@@ -0,0 +1,39 @@
+//! Partitioned topics
+//! A topic with N partitions is served by N independent queues named
+//! `topic#0` .. `topic#N-1`, each with its own clock and log, so ordering is
+//! kept per partition. Items with the same key always land in the same
+//! partition. Every node must configure the same partition counts.
+
+use std::collections::HashMap;
+
+/// Name of the queue backing one partition of `topic`
+pub fn partition_name(topic: &str, partition: u32) -> String {
+    format!("{}#{}", topic, partition)
+}
+
+/// FNV-1a, so every node and every build agrees
+pub(crate) fn hash_key(key: &str) -> u64 {
+    key.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// Partition a key is routed to
+pub fn partition_for(key: &str, partitions: u32) -> u32 {
+    (hash_key(key) % partitions.max(1) as u64) as u32
+}
+
+/// Spread partitions over consumers, round-robin in consumer name order
+/// Deterministic, so each consumer can work out its own share
+pub fn assign_partitions(partitions: u32, consumers: &[&str]) -> HashMap<String, Vec<u32>> {
+    let mut names: Vec<&str> = consumers.to_vec();
+    names.sort_unstable();
+    names.dedup();
+    let mut assignment: HashMap<String, Vec<u32>> = names.iter().map(|c| (c.to_string(), Vec::new())).collect();
+    if names.is_empty() {
+        return assignment;
+    }
+    for partition in 0..partitions {
+        let consumer = names[partition as usize % names.len()];
+        assignment.get_mut(consumer).unwrap().push(partition);
+    }
+    assignment
+}
@@ -0,0 +1,135 @@
+//! Item ownership across nodes
+//! Queue names (a topic, or one partition of it) are placed on a consistent-hash
+//! ring of the cluster members. The owner is the only node that hands out the
+//! queue's items, so two nodes never dequeue the same item concurrently. When a
+//! member joins or leaves, only the queues next to its ring points move.
+//!
+//! With work stealing, a node with nothing to hand out asks the owner of a deep
+//! queue for a batch. The owner dequeues it as transfer events naming the idle
+//! node, so every replica's log records who processed which item.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use crate::engine::partition::hash_key;
+
+/// Ring points per member unless configured otherwise
+pub const DEFAULT_VNODES: u32 = 64;
+
+/// Consistent-hash ring of cluster members
+#[derive(Clone, Debug)]
+pub struct HashRing {
+    vnodes: u32,
+    members: BTreeSet<String>,
+    points: BTreeMap<u64, String>,
+}
+
+/// Spread FNV output over the whole ring; short labels like `N0#3` hash close together
+fn point(label: &str) -> u64 {
+    let mut x = hash_key(label);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+impl HashRing {
+    /// Empty ring placing each member at `vnodes` points
+    pub fn new(vnodes: u32) -> Self {
+        Self { vnodes: vnodes.max(1), members: BTreeSet::new(), points: BTreeMap::new() }
+    }
+
+    /// Ring of the given members
+    pub fn with_members(members: &[&str], vnodes: u32) -> Self {
+        let mut ring = Self::new(vnodes);
+        ring.members.extend(members.iter().map(|m| m.to_string()));
+        ring.rebuild();
+        ring
+    }
+
+    /// Add a member; false if it was already on the ring
+    pub fn add_member(&mut self, member: &str) -> bool {
+        let added = self.members.insert(member.to_string());
+        if added {
+            self.rebuild();
+        }
+        added
+    }
+
+    /// Remove a member; false if it wasn't on the ring
+    pub fn remove_member(&mut self, member: &str) -> bool {
+        let removed = self.members.remove(member);
+        if removed {
+            self.rebuild();
+        }
+        removed
+    }
+
+    pub fn contains(&self, member: &str) -> bool {
+        self.members.contains(member)
+    }
+
+    /// Members, sorted
+    pub fn members(&self) -> Vec<&str> {
+        self.members.iter().map(|m| m.as_str()).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Member owning `key`: the first ring point at or after the key's hash
+    pub fn owner(&self, key: &str) -> Option<&str> {
+        let hash = point(key);
+        self.points
+            .range(hash..)
+            .next()
+            .or_else(|| self.points.iter().next())
+            .map(|(_, member)| member.as_str())
+    }
+
+    /// Points are recomputed from scratch so colliding points go to the same
+    /// member whatever order members joined in
+    fn rebuild(&mut self) {
+        self.points.clear();
+        for member in &self.members {
+            for vnode in 0..self.vnodes {
+                self.points.entry(point(&format!("{}#{}", member, vnode))).or_insert_with(|| member.clone());
+            }
+        }
+    }
+}
+
+impl Default for HashRing {
+    fn default() -> Self {
+        Self::new(DEFAULT_VNODES)
+    }
+}
+
+/// When idle nodes take work from the owners of deep queues
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StealPolicy {
+    /// Shallowest queue worth stealing from
+    pub min_depth: usize,
+    /// Most items taken per request; owners give away at most half their queue
+    pub batch: usize,
+}
+
+impl Default for StealPolicy {
+    fn default() -> Self {
+        Self { min_depth: 8, batch: 16 }
+    }
+}
+
+/// A dequeue refused because another member owns the queue
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NotOwner {
+    pub queue: String,
+    pub owner: String,
+}
+
+impl Display for NotOwner {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} is owned by {}", self.queue, self.owner)
+    }
+}
+
+impl std::error::Error for NotOwner {}
@@ -0,0 +1,405 @@
+//! In-process cluster simulation
+//! Runs several queues in one process and carries their events between them on
+//! simulated links. Time is virtual: each message is due after a delay drawn from its
+//! link's latency, and `step`/`advance` deliver messages in due order, so a run is
+//! reproducible for a given seed whatever the machine.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::Arc;
+use crate::core::buildcore::{DistributedQueueSystem, Event, NodeId, QueueError};
+use crate::core::time::Duration;
+
+/// Distribution a link's base delay is drawn from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Delay {
+    Fixed(Duration),
+    /// Uniform in `[min, max]`
+    Uniform { min: Duration, max: Duration },
+    /// Normal, clamped at zero
+    Normal { mean: Duration, std_dev: Duration },
+}
+
+impl Delay {
+    fn sample(&self, rng: &mut SimRng) -> Duration {
+        match *self {
+            Delay::Fixed(delay) => delay,
+            Delay::Uniform { min, max } => rng.between(min, max),
+            Delay::Normal { mean, std_dev } => {
+                let sample = mean.as_secs_f64() + std_dev.as_secs_f64() * rng.standard_normal();
+                Duration::from_secs_f64(sample.max(0.0))
+            }
+        }
+    }
+}
+
+/// Latency of a link: a base delay plus up to `jitter` more, uniformly
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Latency {
+    pub delay: Delay,
+    pub jitter: Duration,
+}
+
+impl Latency {
+    /// Messages arrive as soon as they are sent
+    pub const ZERO: Latency = Latency { delay: Delay::Fixed(Duration::ZERO), jitter: Duration::ZERO };
+
+    pub fn new(delay: Delay) -> Self {
+        Self { delay, jitter: Duration::ZERO }
+    }
+
+    pub fn fixed(delay: Duration) -> Self {
+        Self::new(Delay::Fixed(delay))
+    }
+
+    pub fn uniform(min: Duration, max: Duration) -> Self {
+        Self::new(Delay::Uniform { min, max })
+    }
+
+    pub fn normal(mean: Duration, std_dev: Duration) -> Self {
+        Self::new(Delay::Normal { mean, std_dev })
+    }
+
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub(crate) fn sample(&self, rng: &mut SimRng) -> Duration {
+        self.delay.sample(rng) + rng.between(Duration::ZERO, self.jitter)
+    }
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Latency::ZERO
+    }
+}
+
+/// SplitMix64, so runs are reproducible without a dependency on a RNG crate
+pub(crate) struct SimRng(pub(crate) u64);
+
+impl SimRng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1)
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn between(&mut self, min: Duration, max: Duration) -> Duration {
+        if max <= min {
+            return min;
+        }
+        let span = (max - min).as_nanos() as u64;
+        min + Duration::from_nanos(self.next_u64() % (span + 1))
+    }
+
+    /// Box-Muller
+    fn standard_normal(&mut self) -> f64 {
+        let u1 = 1.0 - self.next_f64(); // (0, 1], keeps ln finite
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+}
+
+/// A message on its way between two nodes
+struct InFlight<T> {
+    due: Duration,
+    seq: u64, // send order, breaks ties between messages due at the same time
+    sent_at: Duration,
+    from: NodeId,
+    to: NodeId,
+    event: Event<T>,
+}
+
+impl<T> PartialEq for InFlight<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.due, self.seq) == (other.due, other.seq)
+    }
+}
+
+impl<T> Eq for InFlight<T> {}
+
+impl<T> PartialOrd for InFlight<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for InFlight<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.due, self.seq).cmp(&(other.due, other.seq))
+    }
+}
+
+/// A message the cluster delivered
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Delivery {
+    pub from: NodeId,
+    pub to: NodeId,
+    pub event_id: u64,
+    pub sent_at: Duration,
+    pub delivered_at: Duration,
+    /// False if the receiver already had the event or buffered it
+    pub applied: bool,
+}
+
+/// Queues connected by simulated links
+pub struct Cluster<T> {
+    nodes: Vec<DistributedQueueSystem<T>>, // in the order they were named
+    latency: Latency,
+    links: HashMap<(NodeId, NodeId), Latency>,
+    rng: SimRng,
+    now: Duration,
+    sent: u64,
+    in_flight: BinaryHeap<Reverse<InFlight<T>>>,
+}
+
+impl<T: Send + Sync + 'static> Cluster<T> {
+    /// A node per name, each replicating with all the others, on zero-latency links
+    pub fn new(names: &[&str]) -> Self {
+        let nodes = names
+            .iter()
+            .map(|name| {
+                let peers: Vec<&str> = names.iter().copied().filter(|peer| peer != name).collect();
+                DistributedQueueSystem::new_with_nodes(name.to_string(), &peers)
+            })
+            .collect();
+        Self {
+            nodes,
+            latency: Latency::ZERO,
+            links: HashMap::new(),
+            rng: SimRng(0),
+            now: Duration::ZERO,
+            sent: 0,
+            in_flight: BinaryHeap::new(),
+        }
+    }
+
+    /// Seed of the delay sampling; the same seed and operations give the same run
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = SimRng(seed);
+        self
+    }
+
+    /// Latency of every link without its own
+    pub fn with_latency(mut self, latency: Latency) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Latency of the link from `from` to `to` (one direction)
+    pub fn with_link_latency(mut self, from: &str, to: &str, latency: Latency) -> Self {
+        self.links.insert((NodeId::intern(from), NodeId::intern(to)), latency);
+        self
+    }
+
+    /// Change a link's latency mid-run; messages already in flight keep their due time
+    pub fn set_link_latency(&mut self, from: &str, to: &str, latency: Latency) {
+        self.links.insert((NodeId::intern(from), NodeId::intern(to)), latency);
+    }
+
+    pub fn node(&self, name: &str) -> Option<&DistributedQueueSystem<T>> {
+        self.nodes.iter().find(|node| node.node_id() == name)
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &DistributedQueueSystem<T>> {
+        self.nodes.iter()
+    }
+
+    /// Virtual time since the start of the run
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// Messages sent but not yet delivered
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Enqueue on node `name` and send the event to every other node
+    /// Panics if there is no such node
+    pub fn enqueue(&mut self, name: &str, item: T) -> Result<Event<T>, QueueError> {
+        let event = self.expect_node(name).try_enqueue(item)?;
+        self.broadcast(&event);
+        Ok(event)
+    }
+
+    /// Dequeue on node `name` and send the event to every other node
+    /// Panics if there is no such node
+    pub fn dequeue(&mut self, name: &str) -> Option<Arc<T>> {
+        let (item, event) = self.expect_node(name).dequeue();
+        self.broadcast(&event);
+        item
+    }
+
+    /// Send an event from its origin to every other node
+    pub fn broadcast(&mut self, event: &Event<T>) {
+        let from = event.origin_node;
+        let targets: Vec<NodeId> = self.nodes.iter().map(|node| NodeId::intern(node.node_id())).filter(|&id| id != from).collect();
+        for to in targets {
+            let latency = self.links.get(&(from, to)).copied().unwrap_or(self.latency);
+            let due = self.now + latency.sample(&mut self.rng);
+            self.sent += 1;
+            let message = InFlight { due, seq: self.sent, sent_at: self.now, from, to, event: event.clone() };
+            self.in_flight.push(Reverse(message));
+        }
+    }
+
+    /// Deliver the next message due, moving the clock to its due time
+    pub fn step(&mut self) -> Option<Delivery> {
+        let Reverse(message) = self.in_flight.pop()?;
+        self.now = self.now.max(message.due);
+        let event_id = message.event.global_id;
+        let receiver = self.nodes.iter().position(|node| node.node_id() == message.to.as_str()).expect("messages go to cluster nodes");
+        let applied = self.nodes[receiver].apply_remote_event(message.event);
+        // Forward what applying it created, e.g. quota rejections
+        for event in self.nodes[receiver].take_outgoing() {
+            self.broadcast(&event);
+        }
+        Some(Delivery { from: message.from, to: message.to, event_id, sent_at: message.sent_at, delivered_at: self.now, applied })
+    }
+
+    /// Deliver every message due within `by`, then move the clock on by `by`
+    pub fn advance(&mut self, by: Duration) -> Vec<Delivery> {
+        let until = self.now + by;
+        let mut deliveries = Vec::new();
+        while self.in_flight.peek().is_some_and(|Reverse(message)| message.due <= until) {
+            deliveries.extend(self.step());
+        }
+        self.now = until;
+        deliveries
+    }
+
+    /// Deliver messages until none are left in flight
+    pub fn run_until_quiet(&mut self) -> Vec<Delivery> {
+        let mut deliveries = Vec::new();
+        while let Some(delivery) = self.step() {
+            deliveries.push(delivery);
+        }
+        deliveries
+    }
+
+    fn expect_node(&self, name: &str) -> &DistributedQueueSystem<T> {
+        self.node(name).unwrap_or_else(|| panic!("no node {} in the cluster", name))
+    }
+}
+
+impl<T: PartialEq + Send + Sync + 'static> Cluster<T> {
+    /// Nothing in flight or buffered, and every node holds the same items
+    /// Concurrent enqueues may reach nodes in different orders, so order isn't compared
+    pub fn converged(&self) -> bool {
+        if !self.in_flight.is_empty() || self.nodes.iter().any(|node| node.pending_events_count() > 0) {
+            return false;
+        }
+        let mut snapshots = self.nodes.iter().map(|node| node.snapshot().items);
+        let Some(first) = snapshots.next() else {
+            return true;
+        };
+        snapshots.all(|items| same_items(&first, &items))
+    }
+}
+
+/// Whether two lists hold the same items, counting duplicates, in any order
+fn same_items<T: PartialEq>(a: &[Arc<T>], b: &[Arc<T>]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut matched = vec![false; b.len()];
+    a.iter().all(|item| {
+        let found = (0..b.len()).find(|&i| !matched[i] && b[i] == *item);
+        found.map(|i| matched[i] = true).is_some()
+    })
+}
+
+/// A way a `MisbehavingNode` corrupts the events it sends
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Misbehavior {
+    /// Send every event `copies` extra times
+    Duplicate { copies: usize },
+    /// Claim `by` more events seen from every other node than were ever sent
+    ClockAhead { by: u64 },
+    /// Rewind the origin's own clock component by `by`, as if replaying an earlier event
+    ClockRewind { by: u64 },
+    /// Stamp the origin's own clock component with `u64::MAX`
+    SequenceOutOfRange,
+}
+
+/// Test double for a faulty or Byzantine peer
+/// Wraps a real queue, so its local state stays consistent, but corrupts the events it
+/// sends with each configured `Misbehavior` in turn, for checking that replicas applying
+/// them with `apply_remote_event` stay intact.
+pub struct MisbehavingNode<T> {
+    inner: DistributedQueueSystem<T>,
+    misbehaviors: Vec<Misbehavior>,
+}
+
+impl<T: Send + Sync + 'static> MisbehavingNode<T> {
+    pub fn new(inner: DistributedQueueSystem<T>) -> Self {
+        Self { inner, misbehaviors: Vec::new() }
+    }
+
+    pub fn with(mut self, misbehavior: Misbehavior) -> Self {
+        self.misbehaviors.push(misbehavior);
+        self
+    }
+
+    /// The wrapped queue, with the node's true state
+    pub fn inner(&self) -> &DistributedQueueSystem<T> {
+        &self.inner
+    }
+
+    /// Enqueue locally; returns the corrupted events to send in its place
+    pub fn enqueue(&self, item: T) -> Result<Vec<Event<T>>, QueueError> {
+        Ok(self.corrupt(self.inner.try_enqueue(item)?))
+    }
+
+    /// Dequeue locally; returns the item and the corrupted events to send in place of the dequeue
+    pub fn dequeue(&self) -> (Option<Arc<T>>, Vec<Event<T>>) {
+        let (item, event) = self.inner.dequeue();
+        (item, self.corrupt(event))
+    }
+
+    /// Apply every misbehavior to an event
+    pub fn corrupt(&self, event: Event<T>) -> Vec<Event<T>> {
+        let mut events = vec![event];
+        for misbehavior in &self.misbehaviors {
+            match *misbehavior {
+                Misbehavior::Duplicate { copies } => {
+                    let originals = events.len();
+                    for i in 0..originals * copies {
+                        events.push(events[i % originals].clone());
+                    }
+                }
+                Misbehavior::ClockAhead { by } => {
+                    for event in &mut events {
+                        let origin = event.origin_node;
+                        for (_, time) in event.clock.iter_mut().filter(|(node, _)| **node != origin) {
+                            *time = time.saturating_add(by);
+                        }
+                    }
+                }
+                Misbehavior::ClockRewind { by } => {
+                    for event in &mut events {
+                        if let Some(time) = event.clock.get_mut(&event.origin_node) {
+                            *time = time.saturating_sub(by);
+                        }
+                    }
+                }
+                Misbehavior::SequenceOutOfRange => {
+                    for event in &mut events {
+                        event.clock.insert(event.origin_node, u64::MAX);
+                    }
+                }
+            }
+        }
+        events
+    }
+}
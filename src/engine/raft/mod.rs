@@ -0,0 +1,538 @@
+//! Minimal Raft, for nodes that want linearizable queues over causal ones
+//! Leader election and log replication only: membership is fixed when the node is
+//! built and there are no snapshots. A witness member votes and acknowledges appends
+//! but never leads, keeping only the terms of its entries.
+//! A member opened with `Raft::open` writes its term, vote and log to a file, synced
+//! before any message that depends on them is sent, and comes back from a restart
+//! with them; one that can't write stops sending anything. A member built with
+//! `Raft::new` keeps them in memory only, and may vote twice in a term or forget
+//! entries it acknowledged when it restarts, so the queues are linearizable only
+//! as long as no member restarts.
+//! The core is driven from outside: `tick` on a timer, `step` for each message,
+//! `propose` for new commands; each returns the messages to send. A node drives any
+//! `Consensus`, so another implementation can stand in for this one.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use crate::core::buildcore::NodeId;
+
+/// How often a leader tells followers it is alive, unless configured otherwise
+pub const DEFAULT_RAFT_HEARTBEAT: Duration = Duration::from_millis(50);
+/// Range a follower's election timeout is drawn from, unless configured otherwise
+pub const DEFAULT_ELECTION_TIMEOUT: (Duration, Duration) = (Duration::from_millis(300), Duration::from_millis(600));
+
+/// Most entries sent in one append
+const MAX_APPEND: usize = 256;
+
+/// An operation committed through the log, applied by every node in log order
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Command<T> {
+    Enqueue { queue: String, item: T },
+    /// From the first non-empty of the topic's queues
+    Dequeue { topic: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RaftEntry<C> {
+    pub term: u64,
+    pub command: C,
+}
+
+/// Messages between the nodes of a Raft cluster
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RaftMessage<C> {
+    RequestVote { term: u64, last_index: u64, last_term: u64 },
+    Vote { term: u64, granted: bool },
+    /// Entries following `prev_index`; none for a heartbeat
+    Append { term: u64, prev_index: u64, prev_term: u64, entries: Vec<RaftEntry<C>>, commit: u64 },
+    /// Answer to an append; on success the follower's log matches up to `index`,
+    /// otherwise it holds no more than `index` entries that could
+    Appended { term: u64, success: bool, index: u64 },
+}
+
+/// Timing of elections and heartbeats, and whether this member is a witness
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RaftConfig {
+    pub heartbeat: Duration,
+    /// Followers start an election after hearing nothing for a time drawn from this range
+    pub election_timeout: (Duration, Duration),
+    /// Vote and acknowledge appends without ever leading or keeping commands, to break
+    /// ties cheaply; while the witness holds committed entries no other member has,
+    /// only the member that does can be elected
+    pub witness: bool,
+}
+
+impl Default for RaftConfig {
+    fn default() -> Self {
+        Self { heartbeat: DEFAULT_RAFT_HEARTBEAT, election_timeout: DEFAULT_ELECTION_TIMEOUT, witness: false }
+    }
+}
+
+#[derive(Debug)]
+enum Role {
+    Follower,
+    Candidate { votes: HashSet<NodeId> },
+    /// Next index to send each peer, and how far each is known to match
+    Leader { next: HashMap<NodeId, u64>, matched: HashMap<NodeId, u64> },
+}
+
+/// A log entry as kept: witnesses drop the command
+struct Slot<C> {
+    term: u64,
+    command: Option<C>,
+}
+
+/// A change to the durable part of the state, one per line of a member's file
+#[derive(Serialize, Deserialize)]
+enum Record<C> {
+    Vote { term: u64, voted_for: Option<NodeId> },
+    Entry { term: u64, command: Option<C> },
+    /// Entries past `len` were dropped
+    Truncate { len: u64 },
+}
+
+/// The file a member's term, vote and log are written to, and what it holds so far
+struct Durable<C> {
+    file: File,
+    encode: fn(&Record<&C>) -> serde_json::Result<Vec<u8>>,
+    term: u64,
+    voted_for: Option<NodeId>,
+    len: u64,
+    /// A write failed, so the file no longer matches and the member stays silent
+    failed: bool,
+}
+
+struct State<C> {
+    term: u64,
+    voted_for: Option<NodeId>,
+    log: Vec<Slot<C>>, // entry i is at index i + 1
+    /// Entries that were in the log all along since it was last written
+    kept: u64,
+    durable: Option<Durable<C>>,
+    commit: u64,
+    applied: u64,
+    role: Role,
+    leader: Option<NodeId>,
+    election_at: Instant,
+    heartbeat_at: Instant,
+    rng: u64,
+}
+
+impl<C> State<C> {
+    fn last_index(&self) -> u64 {
+        self.log.len() as u64
+    }
+
+    fn term_at(&self, index: u64) -> u64 {
+        index.checked_sub(1).and_then(|i| self.log.get(i as usize)).map_or(0, |slot| slot.term)
+    }
+
+    /// Write what changed since last time to the member's file, if it has one
+    /// Returns false if that failed, now or before
+    fn persist(&mut self) -> bool {
+        let Some(durable) = &mut self.durable else {
+            return true;
+        };
+        if durable.failed {
+            return false;
+        }
+        let mut records = Vec::new();
+        if (durable.term, durable.voted_for) != (self.term, self.voted_for) {
+            records.push(Record::Vote { term: self.term, voted_for: self.voted_for });
+        }
+        if durable.len > self.kept {
+            records.push(Record::Truncate { len: self.kept });
+        }
+        let written = durable.len.min(self.kept) as usize;
+        records.extend(self.log[written..].iter().map(|slot| Record::Entry { term: slot.term, command: slot.command.as_ref() }));
+        if records.is_empty() {
+            return true;
+        }
+        let mut bytes = Vec::new();
+        for record in &records {
+            match (durable.encode)(record) {
+                Ok(line) => bytes.extend(line),
+                Err(_) => {
+                    durable.failed = true;
+                    return false;
+                }
+            }
+            bytes.push(b'\n');
+        }
+        if durable.file.write_all(&bytes).and_then(|()| durable.file.sync_data()).is_err() {
+            durable.failed = true;
+            return false;
+        }
+        (durable.term, durable.voted_for, durable.len) = (self.term, self.voted_for, self.log.len() as u64);
+        self.kept = durable.len;
+        true
+    }
+
+    /// SplitMix64, enough to spread election timeouts
+    fn next_random(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Outgoing messages, by recipient
+pub type Outbox<C> = Vec<(NodeId, RaftMessage<C>)>;
+
+/// A replicated log a node runs its enqueues and dequeues through, see `Node::with_consensus`
+pub trait Consensus<C>: Send + Sync {
+    fn tick(&self) -> Outbox<C>;
+    /// Index and term `command` was appended at, or the leader to ask instead, if known
+    fn propose(&self, command: C) -> Result<(u64, u64, Outbox<C>), Option<NodeId>>;
+    fn step(&self, from: NodeId, message: RaftMessage<C>) -> Outbox<C>;
+    /// Committed entries not taken yet, in log order
+    fn take_committed(&self) -> Vec<(u64, RaftEntry<C>)>;
+    fn leader(&self) -> Option<NodeId>;
+}
+
+/// One member of a Raft cluster, see the module docs
+pub struct Raft<C> {
+    id: NodeId,
+    peers: Vec<NodeId>,
+    config: RaftConfig,
+    state: Mutex<State<C>>,
+}
+
+impl<C: Clone> Raft<C> {
+    /// A follower of term 0; `peers` are the other members
+    pub fn new(id: NodeId, peers: &[NodeId], config: RaftConfig) -> Self {
+        let peers: Vec<NodeId> = peers.iter().copied().filter(|&peer| peer != id).collect();
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+        let seed = id.as_str().bytes().fold(started, |h, b| h.rotate_left(5) ^ b as u64);
+        let now = Instant::now();
+        let raft = Self {
+            id,
+            peers,
+            config,
+            state: Mutex::new(State {
+                term: 0,
+                voted_for: None,
+                log: Vec::new(),
+                kept: 0,
+                durable: None,
+                commit: 0,
+                applied: 0,
+                role: Role::Follower,
+                leader: None,
+                election_at: now,
+                heartbeat_at: now,
+                rng: seed,
+            }),
+        };
+        let mut state = raft.state.lock().unwrap();
+        raft.reset_election(&mut state);
+        drop(state);
+        raft
+    }
+
+    pub fn term(&self) -> u64 {
+        self.state.lock().unwrap().term
+    }
+
+    pub fn is_leader(&self) -> bool {
+        matches!(self.state.lock().unwrap().role, Role::Leader { .. })
+    }
+
+    /// The leader of the current term, if known
+    pub fn leader(&self) -> Option<NodeId> {
+        self.state.lock().unwrap().leader
+    }
+
+    /// Index of the last entry known to be committed
+    pub fn commit_index(&self) -> u64 {
+        self.state.lock().unwrap().commit
+    }
+
+    /// Start an election if the leader went quiet, or send heartbeats as leader
+    pub fn tick(&self) -> Outbox<C> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let outbox = match state.role {
+            Role::Leader { .. } if now >= state.heartbeat_at => self.append_to_all(&mut state),
+            Role::Leader { .. } => Vec::new(),
+            _ if now >= state.election_at && !self.config.witness => self.campaign(&mut state),
+            _ => Vec::new(),
+        };
+        if !state.persist() {
+            return Vec::new();
+        }
+        outbox
+    }
+
+    /// Append `command` to the log if this node leads; returns its index and term
+    pub fn propose(&self, command: C) -> Result<(u64, u64, Outbox<C>), Option<NodeId>> {
+        let mut state = self.state.lock().unwrap();
+        if !matches!(state.role, Role::Leader { .. }) {
+            return Err(state.leader);
+        }
+        let term = state.term;
+        state.log.push(Slot { term, command: Some(command) });
+        // The leader counts itself among the members holding the entry
+        if !state.persist() {
+            return Err(None);
+        }
+        let index = state.last_index();
+        self.advance_commit(&mut state);
+        Ok((index, term, self.append_to_all(&mut state)))
+    }
+
+    /// Take in a message from `from`
+    pub fn step(&self, from: NodeId, message: RaftMessage<C>) -> Outbox<C> {
+        let mut state = self.state.lock().unwrap();
+        let outbox = self.receive(&mut state, from, message);
+        if !state.persist() {
+            return Vec::new();
+        }
+        outbox
+    }
+
+    fn receive(&self, state: &mut State<C>, from: NodeId, message: RaftMessage<C>) -> Outbox<C> {
+        let term = match &message {
+            RaftMessage::RequestVote { term, .. }
+            | RaftMessage::Vote { term, .. }
+            | RaftMessage::Append { term, .. }
+            | RaftMessage::Appended { term, .. } => *term,
+        };
+        if term > state.term {
+            state.term = term;
+            state.voted_for = None;
+            state.role = Role::Follower;
+            state.leader = None;
+        }
+        match message {
+            RaftMessage::RequestVote { term, last_index, last_term } => {
+                let up_to_date = (last_term, last_index) >= (state.term_at(state.last_index()), state.last_index());
+                let granted = term == state.term && up_to_date && state.voted_for.is_none_or(|voted| voted == from);
+                if granted {
+                    state.voted_for = Some(from);
+                    self.reset_election(state);
+                }
+                vec![(from, RaftMessage::Vote { term: state.term, granted })]
+            }
+            RaftMessage::Vote { term, granted } => {
+                let Role::Candidate { votes } = &mut state.role else {
+                    return Vec::new();
+                };
+                if term != state.term || !granted {
+                    return Vec::new();
+                }
+                votes.insert(from);
+                if self.is_majority(votes.len() + 1) {
+                    return self.lead(state);
+                }
+                Vec::new()
+            }
+            RaftMessage::Append { term, prev_index, prev_term, entries, commit } => {
+                if term < state.term {
+                    return vec![(from, RaftMessage::Appended { term: state.term, success: false, index: 0 })];
+                }
+                state.role = Role::Follower;
+                state.leader = Some(from);
+                self.reset_election(state);
+                if prev_index > state.last_index() || state.term_at(prev_index) != prev_term {
+                    let index = prev_index.saturating_sub(1).min(state.last_index());
+                    return vec![(from, RaftMessage::Appended { term: state.term, success: false, index })];
+                }
+                let matched = prev_index + entries.len() as u64;
+                for (offset, entry) in entries.into_iter().enumerate() {
+                    let index = prev_index + 1 + offset as u64;
+                    if index <= state.last_index() {
+                        if state.term_at(index) == entry.term {
+                            continue;
+                        }
+                        // Conflicts with the leader: drop it and everything after
+                        state.log.truncate(index as usize - 1);
+                        state.kept = state.kept.min(state.last_index());
+                    }
+                    let command = (!self.config.witness).then_some(entry.command);
+                    state.log.push(Slot { term: entry.term, command });
+                }
+                state.commit = state.commit.max(commit.min(matched));
+                vec![(from, RaftMessage::Appended { term: state.term, success: true, index: matched })]
+            }
+            RaftMessage::Appended { term, success, index } => {
+                let last = state.last_index();
+                let Role::Leader { next, matched } = &mut state.role else {
+                    return Vec::new();
+                };
+                if term != state.term || !self.peers.contains(&from) {
+                    return Vec::new();
+                }
+                // A follower can't hold more than was sent to it
+                let index = index.min(last);
+                if success {
+                    let known = matched.entry(from).or_insert(0);
+                    *known = (*known).max(index);
+                    next.insert(from, *known + 1);
+                    self.advance_commit(state);
+                    // Keep a lagging follower coming
+                    if index < last {
+                        return vec![(from, self.append_to(state, from))];
+                    }
+                    return Vec::new();
+                }
+                let to = next.entry(from).or_insert(1);
+                *to = (*to - 1).min(index + 1).max(1);
+                vec![(from, self.append_to(state, from))]
+            }
+        }
+    }
+
+    /// Committed entries not taken yet, with their indices; each is returned once
+    /// A witness has none to return, keeping no commands
+    pub fn take_committed(&self) -> Vec<(u64, RaftEntry<C>)> {
+        let mut state = self.state.lock().unwrap();
+        let (from, to) = (state.applied, state.commit);
+        state.applied = to;
+        (from + 1..=to)
+            .filter_map(|index| {
+                let slot = &state.log[index as usize - 1];
+                Some((index, RaftEntry { term: slot.term, command: slot.command.clone()? }))
+            })
+            .collect()
+    }
+
+    fn is_majority(&self, count: usize) -> bool {
+        count * 2 > self.peers.len() + 1
+    }
+
+    fn reset_election(&self, state: &mut State<C>) {
+        let (min, max) = self.config.election_timeout;
+        let span = max.saturating_sub(min).as_millis() as u64;
+        let jitter = Duration::from_millis(state.next_random() % (span + 1));
+        state.election_at = Instant::now() + min + jitter;
+    }
+
+    fn campaign(&self, state: &mut State<C>) -> Outbox<C> {
+        state.term += 1;
+        state.voted_for = Some(self.id);
+        state.leader = None;
+        state.role = Role::Candidate { votes: HashSet::new() };
+        self.reset_election(state);
+        if self.is_majority(1) {
+            return self.lead(state);
+        }
+        let (last_index, last_term) = (state.last_index(), state.term_at(state.last_index()));
+        let term = state.term;
+        self.peers.iter().map(|&peer| (peer, RaftMessage::RequestVote { term, last_index, last_term })).collect()
+    }
+
+    fn lead(&self, state: &mut State<C>) -> Outbox<C> {
+        let next = state.last_index() + 1;
+        state.role = Role::Leader {
+            next: self.peers.iter().map(|&peer| (peer, next)).collect(),
+            matched: self.peers.iter().map(|&peer| (peer, 0)).collect(),
+        };
+        state.leader = Some(self.id);
+        self.append_to_all(state)
+    }
+
+    fn append_to_all(&self, state: &mut State<C>) -> Outbox<C> {
+        state.heartbeat_at = Instant::now() + self.config.heartbeat;
+        self.peers.iter().map(|&peer| (peer, self.append_to(state, peer))).collect()
+    }
+
+    fn append_to(&self, state: &mut State<C>, peer: NodeId) -> RaftMessage<C> {
+        let next = match &state.role {
+            Role::Leader { next, .. } => next.get(&peer).copied().unwrap_or(1).min(state.last_index() + 1),
+            _ => 1,
+        };
+        let prev_index = next - 1;
+        let end = state.log.len().min(prev_index as usize + MAX_APPEND);
+        RaftMessage::Append {
+            term: state.term,
+            prev_index,
+            prev_term: state.term_at(prev_index),
+            entries: state.log[prev_index as usize..end]
+                .iter()
+                .map(|slot| RaftEntry { term: slot.term, command: slot.command.clone().expect("leaders are never witnesses") })
+                .collect(),
+            commit: state.commit,
+        }
+    }
+
+    /// Commit up to the highest entry of this term that a majority holds
+    fn advance_commit(&self, state: &mut State<C>) {
+        let Role::Leader { matched, .. } = &state.role else {
+            return;
+        };
+        let held = |index: u64| 1 + matched.values().filter(|&&m| m >= index).count();
+        let newest = (state.commit + 1..=state.last_index()).rev().find(|&index| state.term_at(index) == state.term && self.is_majority(held(index)));
+        if let Some(index) = newest {
+            state.commit = index;
+        }
+    }
+}
+
+impl<C: Clone + Serialize + DeserializeOwned> Raft<C> {
+    /// `new`, keeping the term, vote and log in the file at `path` and starting from what
+    /// it holds, if anything; the commit index is learned from the leader again
+    pub fn open(id: NodeId, peers: &[NodeId], config: RaftConfig, path: impl AsRef<Path>) -> io::Result<Self> {
+        let raft = Self::new(id, peers, config);
+        let file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        let mut guard = raft.state.lock().unwrap();
+        let state = &mut *guard;
+        let mut reader = BufReader::new(&file);
+        let (mut line, mut valid) = (Vec::new(), 0);
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)?;
+            // A record cut short by a crash was never synced, so nothing depended on it
+            if read == 0 || line.last() != Some(&b'\n') {
+                break;
+            }
+            match serde_json::from_slice(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))? {
+                Record::Vote { term, voted_for } => (state.term, state.voted_for) = (term, voted_for),
+                Record::Entry { term, command } => state.log.push(Slot { term, command }),
+                Record::Truncate { len } => state.log.truncate(len as usize),
+            }
+            valid += read as u64;
+        }
+        file.set_len(valid)?;
+        state.kept = state.last_index();
+        state.durable = Some(Durable {
+            file,
+            encode: |record: &Record<&C>| serde_json::to_vec(record),
+            term: state.term,
+            voted_for: state.voted_for,
+            len: state.kept,
+            failed: false,
+        });
+        drop(guard);
+        Ok(raft)
+    }
+}
+
+impl<C: Clone + Send> Consensus<C> for Raft<C> {
+    fn tick(&self) -> Outbox<C> {
+        Raft::tick(self)
+    }
+
+    fn propose(&self, command: C) -> Result<(u64, u64, Outbox<C>), Option<NodeId>> {
+        Raft::propose(self, command)
+    }
+
+    fn step(&self, from: NodeId, message: RaftMessage<C>) -> Outbox<C> {
+        Raft::step(self, from, message)
+    }
+
+    fn take_committed(&self) -> Vec<(u64, RaftEntry<C>)> {
+        Raft::take_committed(self)
+    }
+
+    fn leader(&self) -> Option<NodeId> {
+        Raft::leader(self)
+    }
+}
@@ -0,0 +1,323 @@
+//! Redis Streams bridge
+//! Mirrors the enqueues of a topic into a Redis stream (`XADD`), and/or ingests the entries
+//! of a stream into a topic (`XREAD`), so the queue can sit next to Redis-based pipelines.
+//! Mirrored entries carry the fields `topic`, `origin`, `event_id`, `timestamp_ms` and `item`
+//! (the item as JSON); ingested entries need an `item` field, taken as JSON or else as a string.
+//! Ingestion skips entries a bridge mirrored, but mirror and ingest different streams anyway,
+//! or ingested items end up in the stream twice. An entry the topic refuses for now, e.g. as it
+//! is paused or over a rate limit, is read again until it is enqueued; one without a valid item
+//! is skipped. Either way the bridge keeps the error.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use crate::core::buildcore::{Event, EventOp};
+use crate::engine::node::Node;
+use crate::engine::partition::partition_name;
+use crate::engine::worker::{ErrorLog, WorkerErrors};
+
+/// How long a bridge waits for entries before checking whether it was stopped
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// Most stream entries ingested per read
+const READ_BATCH: usize = 100;
+/// How long to wait before reconnecting after an error
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Redis reply (RESP2)
+#[derive(Clone, Debug, PartialEq)]
+enum Reply {
+    Status(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Reply>>),
+}
+
+impl Reply {
+    fn into_array(self) -> Vec<Reply> {
+        match self {
+            Reply::Array(Some(items)) => items,
+            _ => Vec::new(),
+        }
+    }
+
+    fn into_string(self) -> Option<String> {
+        match self {
+            Reply::Bulk(Some(bytes)) => String::from_utf8(bytes).ok(),
+            Reply::Status(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+/// A Redis server and the stream a bridge uses on it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RedisStream {
+    addr: String,
+    key: String,
+    password: Option<String>,
+}
+
+impl RedisStream {
+    /// Stream `key` on the server at `addr` (`host:port`)
+    pub fn new(addr: impl Into<String>, key: impl Into<String>) -> Self {
+        Self { addr: addr.into(), key: key.into(), password: None }
+    }
+
+    /// Authenticate with `AUTH password` after connecting
+    pub fn with_password(mut self, password: impl Into<String>) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    /// Name of the stream
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    fn connect(&self) -> io::Result<Connection> {
+        let addr = self
+            .addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} does not resolve", self.addr)))?;
+        let stream = TcpStream::connect(addr)?;
+        // Blocking reads wait up to POLL_INTERVAL on the server, leave room for the reply
+        stream.set_read_timeout(Some(POLL_INTERVAL * 10))?;
+        let mut connection = Connection { reader: BufReader::new(stream.try_clone()?), writer: stream };
+        if let Some(password) = &self.password {
+            connection.command(&["AUTH", password])?;
+        }
+        Ok(connection)
+    }
+}
+
+struct Connection {
+    writer: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl Connection {
+    /// Send a command and read its reply; error replies become errors
+    fn command(&mut self, args: &[&str]) -> io::Result<Reply> {
+        let mut request = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            request.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            request.extend_from_slice(arg.as_bytes());
+            request.extend_from_slice(b"\r\n");
+        }
+        self.writer.write_all(&request)?;
+        match read_reply(&mut self.reader)? {
+            Reply::Error(message) => Err(io::Error::other(format!("redis: {}", message))),
+            reply => Ok(reply),
+        }
+    }
+}
+
+fn read_reply(reader: &mut impl BufRead) -> io::Result<Reply> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "redis closed the connection"));
+    }
+    let line = line.trim_end_matches("\r\n");
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("malformed redis reply {:?}", line));
+    let (kind, rest) = line.split_at_checked(1).ok_or_else(invalid)?;
+    Ok(match kind {
+        "+" => Reply::Status(rest.to_string()),
+        "-" => Reply::Error(rest.to_string()),
+        ":" => Reply::Integer(rest.parse().map_err(|_| invalid())?),
+        "$" => match rest.parse::<i64>().map_err(|_| invalid())? {
+            len if len < 0 => Reply::Bulk(None),
+            len => {
+                let mut bytes = vec![0; len as usize + 2];
+                reader.read_exact(&mut bytes)?;
+                bytes.truncate(len as usize);
+                Reply::Bulk(Some(bytes))
+            }
+        },
+        "*" => match rest.parse::<i64>().map_err(|_| invalid())? {
+            len if len < 0 => Reply::Array(None),
+            len => Reply::Array(Some((0..len).map(|_| read_reply(reader)).collect::<io::Result<_>>()?)),
+        },
+        _ => return Err(invalid()),
+    })
+}
+
+/// Handle to a running bridge
+pub struct RedisBridge {
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+    errors: ErrorLog,
+}
+
+impl RedisBridge {
+    /// Append every enqueue made on this node to `topic` (its partitions included) to the stream
+    /// Replicated enqueues are left to the nodes they were made on, so each item is added once
+    /// when every node mirrors the topic; retries are not mirrored again
+    pub fn mirror<T>(node: Arc<Node<T>>, topic: &str, redis: RedisStream) -> io::Result<Self>
+    where
+        T: Serialize + Send + Sync + 'static,
+    {
+        let queues = match node.partition_count(topic) {
+            Some(count) => (0..count).map(|p| partition_name(topic, p)).collect(),
+            None => vec![topic.to_string()],
+        };
+        // Connected up front, so a failure leaves no thread behind
+        let connections = queues.iter().map(|_| redis.connect()).collect::<io::Result<Vec<_>>>()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let errors = ErrorLog::default();
+        let mut threads = Vec::new();
+        for (queue, mut connection) in queues.into_iter().zip(connections) {
+            let entries = node.topic(&queue).subscribe_logs();
+            let (running, redis, topic, errors) = (stop.clone(), redis.clone(), topic.to_string(), errors.clone());
+            threads.push(thread::spawn(move || {
+                while !running.load(Ordering::SeqCst) {
+                    let entry = match entries.recv_timeout(POLL_INTERVAL) {
+                        Ok(entry) => entry,
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    };
+                    let event = &entry.event;
+                    if !matches!(event.op, EventOp::Enqueue) || event.origin_node != entry.local_node {
+                        continue;
+                    }
+                    if let Err(e) = mirror_event(&mut connection, &redis, &topic, event) {
+                        errors.record(format_args!("cannot mirror event {} of {} to redis: {}", event.global_id, topic, e));
+                    }
+                }
+            }));
+        }
+        Ok(Self { stop, threads, errors })
+    }
+
+    /// Enqueue the entries appended to the stream from `from` on (an entry ID, or `$` for
+    /// entries added after the bridge starts) onto `topic`
+    pub fn ingest<T>(node: Arc<Node<T>>, topic: &str, redis: RedisStream, from: &str) -> io::Result<Self>
+    where
+        T: DeserializeOwned + Send + Sync + 'static,
+    {
+        let mut connection = redis.connect()?;
+        // Pin `$` to the current last entry, so entries added between reads aren't skipped
+        let mut last_id = match from {
+            "$" => connection
+                .command(&["XREVRANGE", &redis.key, "+", "-", "COUNT", "1"])?
+                .into_array()
+                .into_iter()
+                .find_map(|entry| entry.into_array().into_iter().next()?.into_string())
+                .unwrap_or_else(|| "0-0".to_string()),
+            from => from.to_string(),
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        let errors = ErrorLog::default();
+        let (running, topic, failed) = (stop.clone(), topic.to_string(), errors.clone());
+        let thread = thread::spawn(move || {
+            let (block, count) = (POLL_INTERVAL.as_millis().to_string(), READ_BATCH.to_string());
+            while !running.load(Ordering::SeqCst) {
+                let reply = match connection.command(&["XREAD", "COUNT", &count, "BLOCK", &block, "STREAMS", &redis.key, &last_id]) {
+                    Ok(reply) => reply,
+                    Err(e) => {
+                        failed.record(format_args!("cannot read redis stream {}: {}", redis.key, e));
+                        thread::sleep(RECONNECT_DELAY);
+                        if let Ok(reconnected) = redis.connect() {
+                            connection = reconnected;
+                        }
+                        continue;
+                    }
+                };
+                // [[key, [[id, [field, value, ..]], ..]]], or nil if nothing arrived
+                'streams: for stream in reply.into_array() {
+                    let Some(entries) = stream.into_array().pop() else {
+                        continue;
+                    };
+                    for entry in entries.into_array() {
+                        let mut entry = entry.into_array().into_iter();
+                        let (Some(id), Some(fields)) = (entry.next().and_then(Reply::into_string), entry.next()) else {
+                            continue;
+                        };
+                        match ingest_entry(&node, &topic, &id, fields) {
+                            Ok(()) => {}
+                            // Read again from this entry on after a pause
+                            Err((true, e)) => {
+                                failed.record(e);
+                                thread::sleep(POLL_INTERVAL);
+                                break 'streams;
+                            }
+                            Err((false, e)) => failed.record(e),
+                        }
+                        last_id = id;
+                    }
+                }
+            }
+        });
+        Ok(Self { stop, threads: vec![thread], errors })
+    }
+
+    /// Errors the bridge ran into, see `WorkerErrors`
+    pub fn errors(&self) -> WorkerErrors {
+        self.errors.snapshot()
+    }
+
+    /// Stop the bridge and wait for it
+    pub fn shutdown(mut self) {
+        self.stop_and_join();
+    }
+
+    fn stop_and_join(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            thread.join().ok();
+        }
+    }
+}
+
+impl Drop for RedisBridge {
+    fn drop(&mut self) {
+        self.stop_and_join();
+    }
+}
+
+/// Append an enqueue to the stream, reconnecting once if the connection broke
+fn mirror_event<T: Serialize>(connection: &mut Connection, redis: &RedisStream, topic: &str, event: &Event<T>) -> io::Result<()> {
+    let item = serde_json::to_string(&event.item).map_err(io::Error::other)?;
+    let (event_id, timestamp) = (event.global_id.to_string(), event.timestamp_ms.to_string());
+    let args = [
+        "XADD", &redis.key, "*",
+        "topic", topic,
+        "origin", event.origin_node.as_str(),
+        "event_id", &event_id,
+        "timestamp_ms", &timestamp,
+        "item", &item,
+    ];
+    if connection.command(&args).is_err() {
+        *connection = redis.connect()?;
+        connection.command(&args)?;
+    }
+    Ok(())
+}
+
+/// Enqueue the `item` of a stream entry, unless a bridge mirrored it there
+/// Fails with the reason an entry wasn't enqueued, and whether it may be later
+fn ingest_entry<T: DeserializeOwned + Send + Sync + 'static>(node: &Node<T>, topic: &str, id: &str, fields: Reply) -> Result<(), (bool, String)> {
+    let mut fields = fields.into_array().into_iter().map(Reply::into_string);
+    let mut item = None;
+    while let (Some(Some(field)), Some(value)) = (fields.next(), fields.next()) {
+        match field.as_str() {
+            "item" => item = value,
+            // Mirrored by a bridge
+            "event_id" => return Ok(()),
+            _ => {}
+        }
+    }
+    let Some(item) = item else {
+        return Err((false, format!("redis entry {} has no item field, skipped", id)));
+    };
+    let parsed = serde_json::from_str(&item).or_else(|_| serde_json::from_value(Value::String(item)));
+    let item = parsed.map_err(|e| (false, format!("redis entry {} is not a valid item: {}", id, e)))?;
+    node.enqueue(topic, item).map(|_| ()).map_err(|e| (e.is_transient(), format!("cannot enqueue redis entry {} on {}: {}", id, topic, e)))
+}
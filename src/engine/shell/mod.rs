@@ -0,0 +1,118 @@
+//! Interactive shell for poking at a running node (`queued shell [node-addr]`)
+//!
+//! Commands act on the current topic, switched with `topic <name>`:
+//! `enqueue <item>`, `dequeue`, `peek`, `clock`, `pending`, `stats`, `logs tail [n]`
+
+use std::io::{self, BufRead, Write};
+use std::net::ToSocketAddrs;
+use serde_json::Value;
+use crate::engine::client::QueueClient;
+
+/// Log entries shown by `logs tail` when no count is given
+const DEFAULT_TAIL: usize = 10;
+
+const HELP: &str = "\
+topic <name>     switch the current topic
+enqueue <item>   enqueue a JSON value (bare words are sent as strings)
+dequeue          dequeue the head item
+peek             show the head item without removing it
+clock            show the topic's vector clock
+pending          show the number of buffered remote events
+stats            show the current topic's stats
+logs tail [n]    show the last n log entries (default 10)
+help             show this help
+quit             leave the shell";
+
+/// Shell session attached to one node
+pub struct Shell {
+    client: QueueClient<Value>,
+    topic: String,
+}
+
+impl Shell {
+    /// Attach to the node's peer address, starting on the `default` topic
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self { client: QueueClient::connect(addr)?, topic: "default".to_string() })
+    }
+
+    /// Present `token` to the node's access control
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.client = self.client.with_token(token);
+        self
+    }
+
+    /// Current topic
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Run one command line; returns None when the user asked to quit
+    pub fn execute(&mut self, line: &str) -> Option<String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let output = match words.as_slice() {
+            [] => Ok(String::new()),
+            ["quit"] | ["exit"] => return None,
+            ["help"] => Ok(HELP.to_string()),
+            ["topic"] => Ok(self.topic.clone()),
+            ["topic", name] => {
+                self.topic = name.to_string();
+                Ok(String::new())
+            }
+            ["enqueue", ..] => {
+                let raw = line.trim_start().trim_start_matches("enqueue").trim();
+                let item = serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()));
+                self.client.enqueue(&self.topic, item).map(|id| format!("enqueued event {}", id))
+            }
+            ["dequeue"] => self.client.dequeue(&self.topic).map(|item| show_item(item.as_deref())),
+            ["peek"] => self.client.peek(&self.topic).map(|item| show_item(item.as_deref())),
+            ["clock"] => self.client.stats(&self.topic).map(|stats| {
+                let mut clock: Vec<_> = stats.clock.into_iter().collect();
+                clock.sort();
+                let parts: Vec<String> = clock.iter().map(|(node, time)| format!("{}:{}", node, time)).collect();
+                format!("{{{}}}", parts.join(", "))
+            }),
+            ["pending"] => self.client.stats(&self.topic).map(|stats| stats.pending_events.to_string()),
+            ["stats"] => self.client.stats(&self.topic).map(|stats| serde_json::to_string_pretty(&stats).unwrap()),
+            ["logs", "tail"] | ["logs", "tail", _] => {
+                let count = match words.get(2).map(|n| n.parse()) {
+                    None => DEFAULT_TAIL,
+                    Some(Ok(n)) => n,
+                    Some(Err(_)) => return Some("error: count must be a number".to_string()),
+                };
+                self.client.log_tail(&self.topic, count).map(|entries| {
+                    let lines: Vec<String> = entries
+                        .iter()
+                        .map(|e| {
+                            let item = e.item().map_or("-".to_string(), |i| i.to_string());
+                            format!("#{} {} {} {:?} event={} {}", e.local_log_id, e.event.origin_node, e.op, e.state, e.event_global_id(), item)
+                        })
+                        .collect();
+                    lines.join("\n")
+                })
+            }
+            _ => Ok(format!("unknown command: {} (try `help`)", line.trim())),
+        };
+        Some(output.unwrap_or_else(|e| format!("error: {}", e)))
+    }
+
+    /// Read commands from `input` until EOF or `quit`, writing results to `output`
+    pub fn run(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        write!(output, "{}> ", self.topic)?;
+        output.flush()?;
+        for line in input.lines() {
+            let Some(result) = self.execute(&line?) else {
+                break;
+            };
+            if !result.is_empty() {
+                writeln!(output, "{}", result)?;
+            }
+            write!(output, "{}> ", self.topic)?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+}
+
+fn show_item(item: Option<&Value>) -> String {
+    item.map_or("(empty)".to_string(), |item| item.to_string())
+}
@@ -0,0 +1,352 @@
+//! SQS-compatible HTTP facade, for pointing existing SQS client code at a local cluster
+//! Speaks the AWS JSON 1.0 protocol (`X-Amz-Target: AmazonSQS.<Action>`) that current AWS SDKs use,
+//! for the actions `SendMessage`, `ReceiveMessage`, `DeleteMessage`, `GetQueueAttributes`,
+//! plus `CreateQueue` and `GetQueueUrl` to find queues. Queue URLs end in the topic name;
+//! request signatures are not checked, but the node's ACL applies as for anonymous clients.
+//!
+//! Received messages are in flight until deleted, which acks them (`Node::ack`). One not
+//! deleted within its visibility timeout is reported failed (`Node::fail`) by a timer
+//! thread, so the topic's retry policy brings it back; without a retry policy it is only
+//! logged.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::ToSocketAddrs;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+use tiny_http::{Header, Request, Response};
+use crate::engine::acl::Permission;
+use crate::engine::http::{self, HttpServer};
+use crate::engine::node::Node;
+use crate::engine::partition::partition_name;
+use crate::engine::worker::{ErrorLog, WorkerErrors};
+
+/// Account ID in queue URLs; SDKs expect one, any works
+const ACCOUNT_ID: &str = "000000000000";
+/// How long a received message stays in flight before it is failed, as in SQS
+pub const DEFAULT_VISIBILITY_TIMEOUT: Duration = Duration::from_secs(30);
+/// Longest `WaitTimeSeconds` SQS allows
+const MAX_WAIT: Duration = Duration::from_secs(20);
+/// How often a long poll checks an empty topic
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How often in-flight messages are checked for a passed visibility timeout
+const EXPIRY_INTERVAL: Duration = Duration::from_millis(100);
+
+type SqsResponse = Response<io::Cursor<Vec<u8>>>;
+
+/// Received messages not deleted yet: receipt handle to topic, delivery ID and visibility deadline
+type InFlight = Mutex<HashMap<String, (String, u64, Instant)>>;
+
+/// SQS error: HTTP status, error code and message
+struct SqsError(u16, &'static str, String);
+
+impl SqsError {
+    fn invalid(message: impl Into<String>) -> Self {
+        SqsError(400, "InvalidParameterValue", message.into())
+    }
+
+    fn missing(parameter: &str) -> Self {
+        SqsError(400, "MissingParameter", format!("the request must contain the parameter {}", parameter))
+    }
+
+    fn no_queue(name: &str) -> Self {
+        SqsError(400, "QueueDoesNotExist", format!("the specified queue {} does not exist", name))
+    }
+}
+
+/// SQS facade over a node's topics
+pub struct SqsFacade<T> {
+    node: Arc<Node<T>>,
+    visibility_timeout: Duration,
+    in_flight: InFlight,
+    errors: ErrorLog,
+}
+
+impl<T> SqsFacade<T>
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    pub fn new(node: Arc<Node<T>>) -> Self {
+        Self { node, visibility_timeout: DEFAULT_VISIBILITY_TIMEOUT, in_flight: Mutex::new(HashMap::new()), errors: ErrorLog::default() }
+    }
+
+    /// Messages whose visibility timeout passed but that could not be put back, see `WorkerErrors`
+    pub fn errors(&self) -> WorkerErrors {
+        self.errors.snapshot()
+    }
+
+    /// Visibility timeout of receives that don't set one
+    pub fn with_visibility_timeout(mut self, timeout: Duration) -> Self {
+        self.visibility_timeout = timeout;
+        self
+    }
+
+    /// Start serving on `addr`; each request runs on its own thread, so long polls don't block others
+    /// A timer thread fails expired messages until the server and its requests are gone
+    pub fn serve(self, addr: impl ToSocketAddrs) -> io::Result<HttpServer> {
+        let facade = Arc::new(self);
+        let timer = Arc::downgrade(&facade);
+        thread::spawn(move || expire_until_dropped(timer));
+        http::spawn(addr, move |request| {
+            let facade = facade.clone();
+            thread::spawn(move || facade.handle_request(request));
+        })
+    }
+
+    /// Messages received and not deleted yet, over all topics
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.lock().unwrap().len()
+    }
+
+    fn handle_request(&self, mut request: Request) {
+        let target = header(&request, "X-Amz-Target").unwrap_or_default();
+        let host = header(&request, "Host").unwrap_or_else(|| "localhost".to_string());
+        let mut body = String::new();
+        let response = match request.as_reader().read_to_string(&mut body) {
+            Ok(_) => match serde_json::from_str::<Value>(if body.is_empty() { "{}" } else { &body }) {
+                Ok(input) => self.dispatch(&target, &input, &host),
+                Err(e) => Err(SqsError(400, "SerializationException", e.to_string())),
+            },
+            Err(e) => Err(SqsError(400, "SerializationException", e.to_string())),
+        };
+        let response = match response {
+            Ok(output) => respond(200, &output),
+            Err(SqsError(status, code, message)) => respond(status, &json!({ "__type": format!("com.amazonaws.sqs#{}", code), "message": message }))
+                .with_header(Header::from_bytes("x-amzn-query-error", format!("AWS.SimpleQueueService.{};Sender", code)).unwrap()),
+        };
+        request.respond(response).ok();
+    }
+
+    fn dispatch(&self, target: &str, input: &Value, host: &str) -> Result<Value, SqsError> {
+        let Some(action) = target.strip_prefix("AmazonSQS.") else {
+            return Err(SqsError(400, "InvalidAction", format!("unknown target {:?}", target)));
+        };
+        match action {
+            "CreateQueue" | "GetQueueUrl" => {
+                let name = string(input, "QueueName")?;
+                if action == "CreateQueue" {
+                    self.authorize(name, Permission::Produce)?;
+                    self.node.topic(self.first_queue(name).as_str());
+                } else if !self.exists(name) {
+                    return Err(SqsError::no_queue(name));
+                }
+                Ok(json!({ "QueueUrl": format!("http://{}/{}/{}", host, ACCOUNT_ID, name) }))
+            }
+            "SendMessage" => self.send(input),
+            "ReceiveMessage" => self.receive(input),
+            "DeleteMessage" => self.delete(input),
+            "GetQueueAttributes" => self.attributes(input),
+            _ => Err(SqsError(400, "UnsupportedOperation", format!("{} is not supported", action))),
+        }
+    }
+
+    fn send(&self, input: &Value) -> Result<Value, SqsError> {
+        let topic = queue_name(input)?;
+        self.authorize(topic, Permission::Produce)?;
+        let body = string(input, "MessageBody")?;
+        // JSON bodies become items as they are; anything else is taken as a string
+        let item = serde_json::from_str(body)
+            .or_else(|_| serde_json::from_value(Value::String(body.to_string())))
+            .map_err(|e| SqsError::invalid(format!("message body is not a valid item: {}", e)))?;
+        match self.node.enqueue(topic, item) {
+            Ok(event) => Ok(json!({
+                "MessageId": format!("{}-{}", event.origin_node, event.global_id),
+                "MD5OfMessageBody": md5_hex(body.as_bytes()),
+            })),
+            Err(e) => Err(SqsError(400, "RequestThrottled", e.to_string())),
+        }
+    }
+
+    fn receive(&self, input: &Value) -> Result<Value, SqsError> {
+        let topic = queue_name(input)?;
+        self.authorize(topic, Permission::Consume)?;
+        if !self.exists(topic) {
+            return Err(SqsError::no_queue(topic));
+        }
+        let max = input.get("MaxNumberOfMessages").and_then(Value::as_u64).unwrap_or(1);
+        if !(1..=10).contains(&max) {
+            return Err(SqsError::invalid("MaxNumberOfMessages must be between 1 and 10"));
+        }
+        let visibility = input.get("VisibilityTimeout").and_then(Value::as_u64).map_or(self.visibility_timeout, Duration::from_secs);
+        let wait = input.get("WaitTimeSeconds").and_then(Value::as_u64).map_or(Duration::ZERO, Duration::from_secs).min(MAX_WAIT);
+        let deadline = Instant::now() + wait;
+        // Newer SDKs ask for system attributes as MessageSystemAttributeNames
+        let receive_count = ["AttributeNames", "MessageSystemAttributeNames"].iter().any(|field| {
+            input.get(field).and_then(Value::as_array).is_some_and(|names| {
+                names.iter().filter_map(Value::as_str).any(|name| name == "All" || name == "ApproximateReceiveCount")
+            })
+        });
+
+        let mut messages = Vec::new();
+        loop {
+            while messages.len() < max as usize
+                && let Some((delivery, item)) = self.node.next_delivery(topic)
+            {
+                let body = match serde_json::to_value(&*item) {
+                    Ok(Value::String(text)) => text,
+                    Ok(value) => value.to_string(),
+                    Err(e) => return Err(SqsError(500, "InternalError", e.to_string())),
+                };
+                let receipt = format!("{}:{}", topic, delivery);
+                self.in_flight.lock().unwrap().insert(receipt.clone(), (topic.to_string(), delivery, Instant::now() + visibility));
+                let mut message = json!({
+                    "MessageId": delivery.to_string(),
+                    "ReceiptHandle": receipt,
+                    "MD5OfBody": md5_hex(body.as_bytes()),
+                    "Body": body,
+                });
+                if receive_count {
+                    let attempt = self.node.delivery_attempt(topic, delivery).unwrap_or(1);
+                    message["Attributes"] = json!({ "ApproximateReceiveCount": attempt.to_string() });
+                }
+                messages.push(message);
+            }
+            if !messages.is_empty() || Instant::now() >= deadline {
+                break;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        Ok(json!({ "Messages": messages }))
+    }
+
+    fn delete(&self, input: &Value) -> Result<Value, SqsError> {
+        let topic = queue_name(input)?;
+        self.authorize(topic, Permission::Consume)?;
+        let receipt = string(input, "ReceiptHandle")?;
+        // Deleting a message that was failed already, or twice, succeeds like in SQS
+        if let Some((topic, delivery, _)) = self.in_flight.lock().unwrap().remove(receipt) {
+            self.node.ack(&topic, delivery);
+        }
+        Ok(json!({}))
+    }
+
+    fn attributes(&self, input: &Value) -> Result<Value, SqsError> {
+        let topic = queue_name(input)?;
+        self.authorize(topic, Permission::Consume)?;
+        if !self.exists(topic) {
+            return Err(SqsError::no_queue(topic));
+        }
+        let queues: Vec<_> = match self.node.partition_count(topic) {
+            Some(count) => (0..count).filter_map(|p| self.node.partition(topic, p)).collect(),
+            None => self.node.get_topic(topic).into_iter().collect(),
+        };
+        let visible: usize = queues.iter().map(|queue| queue.queue_state().0).sum();
+        let delayed: usize = queues.iter().map(|queue| queue.pending_retries()).sum();
+        let not_visible = self.in_flight.lock().unwrap().values().filter(|(t, ..)| t == topic).count();
+        let all = HashMap::from([
+            ("ApproximateNumberOfMessages", visible.to_string()),
+            ("ApproximateNumberOfMessagesNotVisible", not_visible.to_string()),
+            ("ApproximateNumberOfMessagesDelayed", delayed.to_string()),
+            ("VisibilityTimeout", self.visibility_timeout.as_secs().to_string()),
+        ]);
+        let names: Vec<&str> = input
+            .get("AttributeNames")
+            .and_then(Value::as_array)
+            .map(|names| names.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        let attributes: HashMap<&str, String> =
+            all.into_iter().filter(|(name, _)| names.iter().any(|wanted| *wanted == "All" || wanted == name)).collect();
+        Ok(json!({ "Attributes": attributes }))
+    }
+
+    /// Fail the in-flight messages whose visibility timeout passed
+    fn expire_in_flight(&self) {
+        let now = Instant::now();
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let expired: Vec<String> = in_flight.iter().filter(|(_, (.., deadline))| *deadline <= now).map(|(receipt, _)| receipt.clone()).collect();
+        let expired: Vec<_> = expired.into_iter().filter_map(|receipt| in_flight.remove(&receipt)).collect();
+        drop(in_flight);
+        for (topic, delivery, _) in expired {
+            if let Err(e) = self.node.fail(&topic, delivery) {
+                self.errors.record(format_args!("sqs message {} of {} timed out and is not retried: {}", delivery, topic, e));
+            }
+        }
+    }
+
+    fn authorize(&self, topic: &str, permission: Permission) -> Result<(), SqsError> {
+        self.node.authorize(None, topic, permission).map_err(|denied| SqsError(403, "AccessDenied", denied.to_string()))
+    }
+
+    /// Topics exist once created or sent to
+    fn exists(&self, topic: &str) -> bool {
+        self.node.get_topic(&self.first_queue(topic)).is_some()
+    }
+
+    /// The topic's queue, or its first partition's
+    fn first_queue(&self, topic: &str) -> String {
+        match self.node.partition_count(topic) {
+            Some(_) => partition_name(topic, 0),
+            None => topic.to_string(),
+        }
+    }
+}
+
+/// Fail expired in-flight messages every `EXPIRY_INTERVAL`, until the facade is dropped
+fn expire_until_dropped<T>(facade: Weak<SqsFacade<T>>)
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    loop {
+        thread::sleep(EXPIRY_INTERVAL);
+        let Some(facade) = facade.upgrade() else {
+            return;
+        };
+        facade.expire_in_flight();
+    }
+}
+
+fn header(request: &Request, name: &'static str) -> Option<String> {
+    request.headers().iter().find(|h| h.field.equiv(name)).map(|h| h.value.as_str().to_string())
+}
+
+fn respond(status: u16, body: &Value) -> SqsResponse {
+    let header = Header::from_bytes("Content-Type", "application/x-amz-json-1.0").unwrap();
+    Response::from_string(body.to_string()).with_status_code(status).with_header(header)
+}
+
+fn string<'a>(input: &'a Value, parameter: &str) -> Result<&'a str, SqsError> {
+    input.get(parameter).and_then(Value::as_str).ok_or_else(|| SqsError::missing(parameter))
+}
+
+/// Topic named by the last segment of `QueueUrl`
+fn queue_name(input: &Value) -> Result<&str, SqsError> {
+    let url = string(input, "QueueUrl")?;
+    url.trim_end_matches('/').rsplit('/').next().filter(|name| !name.is_empty()).ok_or_else(|| SqsError::invalid("QueueUrl names no queue"))
+}
+
+/// MD5 of a message body, hex-encoded; SDKs check it against what they sent or received
+fn md5_hex(data: &[u8]) -> String {
+    const SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+    let k: Vec<u32> = (0..64).map(|i| ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32).collect();
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    for block in message.chunks(64) {
+        let words: Vec<u32> = block.chunks(4).map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]])).collect();
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a.wrapping_add(f).wrapping_add(k[i]).wrapping_add(words[g]).rotate_left(SHIFTS[(i / 16) * 4 + i % 4]);
+            (a, d, c) = (d, c, b);
+            b = b.wrapping_add(rotated);
+        }
+        for (word, add) in state.iter_mut().zip([a, b, c, d]) {
+            *word = word.wrapping_add(add);
+        }
+    }
+    state.iter().flat_map(|word| word.to_le_bytes()).map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,40 @@
+//! Statistics of a topic, on one node or gathered from the whole cluster
+//! `Node::topic_stats` reports a node's view of a topic, partitions added up;
+//! `Node::cluster_topic_stats` adds the views of peers, asked over the network.
+
+use serde::{Deserialize, Serialize};
+
+/// Statistics of a topic, see the module docs
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopicStats {
+    pub topic: String,
+    /// Nodes reporting, in the order asked; just the node itself for one node's stats
+    pub nodes: Vec<String>,
+    pub depth: usize,                    // items currently queued
+    pub enqueued: u64,                   // items enqueued so far, replicated ones and retries included
+    pub dequeued: u64,                   // items dequeued so far, here or on replicas
+    pub oldest_item_age_ms: Option<u64>, // how long the oldest queued item has waited
+    pub consumers: usize,                // subscriptions and consumer workers of the topic
+    pub redeliveries: u64,               // failed items enqueued again
+    pub pending_redeliveries: usize,     // failed items waiting out their backoff
+}
+
+impl TopicStats {
+    /// Combine the stats of one topic from several nodes
+    /// Replicas hold the same items, so item counts are those of the replica furthest
+    /// along rather than sums; consumers and pending redeliveries are per node and add up
+    pub fn aggregate(topic: &str, stats: impl IntoIterator<Item = TopicStats>) -> TopicStats {
+        let mut total = TopicStats { topic: topic.to_string(), ..TopicStats::default() };
+        for stats in stats {
+            total.nodes.extend(stats.nodes);
+            total.depth = total.depth.max(stats.depth);
+            total.enqueued = total.enqueued.max(stats.enqueued);
+            total.dequeued = total.dequeued.max(stats.dequeued);
+            total.oldest_item_age_ms = total.oldest_item_age_ms.max(stats.oldest_item_age_ms);
+            total.consumers += stats.consumers;
+            total.redeliveries = total.redeliveries.max(stats.redeliveries);
+            total.pending_redeliveries += stats.pending_redeliveries;
+        }
+        total
+    }
+}
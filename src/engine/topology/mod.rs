@@ -0,0 +1,91 @@
+//! Region-aware broadcast, to cut the events sent between regions
+//! Nodes labelled with the same region send each other their events directly. Across
+//! regions only each region's forwarder talks: it passes its region's events on to the
+//! other forwarders, and the events they pass on to the rest of its region. A region's
+//! forwarder is the one configured, or else its member with the lowest ID.
+//! Nodes without a label take part as before, sending to and hearing from everyone.
+
+use std::collections::HashMap;
+use crate::core::buildcore::NodeId;
+
+/// Region labels of the cluster's nodes, and the forwarder of each region
+#[derive(Clone, Debug, Default)]
+pub struct Topology {
+    regions: HashMap<NodeId, String>,
+    forwarders: HashMap<String, NodeId>,
+}
+
+impl Topology {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Label `node` as being in `region`
+    pub fn with_node(mut self, node: &str, region: &str) -> Self {
+        self.regions.insert(NodeId::intern(node), region.to_string());
+        self
+    }
+
+    /// Have `node` forward the events of `region`, instead of its member with the lowest ID
+    pub fn with_forwarder(mut self, region: &str, node: &str) -> Self {
+        self.forwarders.insert(region.to_string(), NodeId::intern(node));
+        self
+    }
+
+    /// Region of `node`, None if it isn't labelled
+    pub fn region(&self, node: NodeId) -> Option<&str> {
+        self.regions.get(&node).map(String::as_str)
+    }
+
+    /// Node forwarding the events of `region`, None if it has no members
+    pub fn forwarder(&self, region: &str) -> Option<NodeId> {
+        if let Some(&forwarder) = self.forwarders.get(region) {
+            return Some(forwarder);
+        }
+        self.regions.iter().filter(|(_, r)| r.as_str() == region).map(|(&node, _)| node).min_by(|a, b| a.as_str().cmp(b.as_str()))
+    }
+
+    fn is_forwarder(&self, node: NodeId) -> bool {
+        self.region(node).is_some_and(|region| self.forwarder(region) == Some(node))
+    }
+
+    /// Peers among `peers` that `me` sends its own events to
+    pub fn targets(&self, me: NodeId, peers: &[NodeId]) -> Vec<NodeId> {
+        let Some(region) = self.region(me) else {
+            return peers.to_vec();
+        };
+        let forwarder = self.is_forwarder(me);
+        peers
+            .iter()
+            .copied()
+            .filter(|&peer| match self.region(peer) {
+                None => true,
+                Some(theirs) if theirs == region => true,
+                Some(_) => forwarder && self.is_forwarder(peer),
+            })
+            .collect()
+    }
+
+    /// Peers among `peers` that `me` passes an event from `sender` on to: none unless
+    /// `me` forwards its region, see the module docs
+    pub fn relays(&self, me: NodeId, sender: NodeId, peers: &[NodeId]) -> Vec<NodeId> {
+        let (Some(region), Some(from)) = (self.region(me), self.region(sender)) else {
+            return Vec::new();
+        };
+        if !self.is_forwarder(me) {
+            return Vec::new();
+        }
+        peers
+            .iter()
+            .copied()
+            .filter(|&peer| peer != sender)
+            .filter(|&peer| match self.region(peer) {
+                // From within the region: on to the other regions' forwarders
+                Some(theirs) if from == region => theirs != region && self.is_forwarder(peer),
+                // From another region's forwarder: on to the rest of this region
+                Some(theirs) => theirs == region,
+                None => false,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,104 @@
+//! Spans for each hop of a traced message
+//! With the `otel` feature every hop reports a span to the global OpenTelemetry tracer,
+//! as a child of the trace context the message carries; without it the context passes through.
+
+use crate::core::buildcore::{NodeId, TraceContext};
+
+/// Hops a traced message makes through the nodes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Hop {
+    /// A client's enqueue, on the node it sent it to
+    Enqueue,
+    /// A replicated event applied on a peer
+    Replicate,
+    /// A client's dequeue, on the node that handed the item out
+    Dequeue,
+}
+
+#[cfg(feature = "otel")]
+impl Hop {
+    fn name(self) -> &'static str {
+        match self {
+            Hop::Enqueue => "dqm.enqueue",
+            Hop::Replicate => "dqm.replicate",
+            Hop::Dequeue => "dqm.dequeue",
+        }
+    }
+}
+
+/// Span of one hop, ended when dropped
+pub(crate) struct HopSpan {
+    context: Option<TraceContext>,
+    #[cfg(feature = "otel")]
+    span: Option<opentelemetry::global::BoxedSpan>,
+}
+
+impl HopSpan {
+    /// Context to carry on to the next hop: this span's, or the parent's if no span is recorded
+    pub(crate) fn context(&self) -> Option<TraceContext> {
+        self.context
+    }
+}
+
+/// Start the span of a hop on `node` for `topic`; untraced messages get no span
+#[cfg(not(feature = "otel"))]
+pub(crate) fn hop(_hop: Hop, _node: NodeId, _topic: &str, parent: Option<TraceContext>) -> HopSpan {
+    HopSpan { context: parent }
+}
+
+/// Start the span of a hop on `node` for `topic`; enqueues without a context start a new trace
+#[cfg(feature = "otel")]
+pub(crate) fn hop(hop: Hop, node: NodeId, topic: &str, parent: Option<TraceContext>) -> HopSpan {
+    use opentelemetry::trace::{Span, SpanContext, SpanId, SpanKind, TraceContextExt, TraceFlags, TraceId, TraceState, Tracer};
+    use opentelemetry::{Context, KeyValue, global};
+
+    if parent.is_none() && hop != Hop::Enqueue {
+        return HopSpan { context: None, span: None };
+    }
+    let cx = match parent {
+        Some(parent) => Context::new().with_remote_span_context(SpanContext::new(
+            TraceId::from_bytes(parent.trace_id.to_be_bytes()),
+            SpanId::from_bytes(parent.span_id.to_be_bytes()),
+            if parent.sampled { TraceFlags::SAMPLED } else { TraceFlags::default() },
+            true,
+            TraceState::default(),
+        )),
+        None => Context::new(),
+    };
+    let kind = match hop {
+        Hop::Enqueue => SpanKind::Producer,
+        Hop::Replicate | Hop::Dequeue => SpanKind::Consumer,
+    };
+    let tracer = global::tracer("DistributedQueueMini");
+    let span = tracer
+        .span_builder(hop.name())
+        .with_kind(kind)
+        .with_attributes([
+            KeyValue::new("messaging.system", "dqm"),
+            KeyValue::new("messaging.destination.name", topic.to_string()),
+            KeyValue::new("dqm.node", node.to_string()),
+        ])
+        .start_with_context(&tracer, &cx);
+    let span_context = span.span_context();
+    // The no-op tracer (no provider installed) records nothing; keep the parent's context then
+    let context = if span_context.is_valid() {
+        Some(TraceContext {
+            trace_id: u128::from_be_bytes(span_context.trace_id().to_bytes()),
+            span_id: u64::from_be_bytes(span_context.span_id().to_bytes()),
+            sampled: span_context.is_sampled(),
+        })
+    } else {
+        parent
+    };
+    HopSpan { context, span: Some(span) }
+}
+
+#[cfg(feature = "otel")]
+impl Drop for HopSpan {
+    fn drop(&mut self) {
+        use opentelemetry::trace::Span;
+        if let Some(span) = &mut self.span {
+            span.end();
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Snapshot transfer between nodes, so a node can restore a topic from a peer
+//! A node serves snapshots of its topics in chunks, see `Node::with_snapshot_transfer`.
+//! The first chunk of a transfer takes a fresh snapshot, encoded as JSON, which the
+//! following ones name by its digest, the CRC32 of the whole encoding. The node keeps
+//! every snapshot it is serving, by topic and digest, until no chunk of it was asked for
+//! during the retention time, so concurrent transfers don't replace each other's
+//! snapshot and one that broke off can resume where it stopped, over a new connection
+//! if need be. Each chunk carries the CRC32 of its own bytes too, and the assembled
+//! snapshot is checked against the digest.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use crate::core::buildcore::{DistributedQueueSystem, Snapshot};
+
+/// Most snapshot bytes sent in one chunk, unless asked for fewer
+/// Chunk bytes take up to four times as much in a JSON frame, so this fits the default frame length
+pub const SNAPSHOT_CHUNK_LEN: usize = 128 * 1024;
+
+/// How long a snapshot is kept after its last chunk was served, unless set otherwise
+pub const SNAPSHOT_RETENTION: Duration = Duration::from_secs(60);
+
+/// Part of an encoded snapshot
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotChunk {
+    /// CRC32 of the whole encoded snapshot, naming it
+    pub digest: u32,
+    /// Length of the whole encoded snapshot
+    pub total: u64,
+    pub offset: u64,
+    pub data: Vec<u8>,
+    /// CRC32 of `data`
+    pub crc32: u32,
+}
+
+/// Why a chunk can't be served
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServeError {
+    /// The snapshot asked for expired, or was never taken here; start over
+    Gone { digest: u32 },
+    /// Past the end of the snapshot
+    OutOfRange { offset: u64, total: u64 },
+    Encode(String),
+}
+
+impl std::fmt::Display for ServeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServeError::Gone { digest } => write!(f, "snapshot {:08x} is no longer available, start over", digest),
+            ServeError::OutOfRange { offset, total } => write!(f, "offset {} is past the end of the snapshot ({} bytes)", offset, total),
+            ServeError::Encode(e) => write!(f, "can't encode snapshot: {}", e),
+        }
+    }
+}
+
+/// An encoded snapshot being served, and when a chunk of it was last asked for
+struct Served {
+    encoded: Arc<Vec<u8>>,
+    used: Instant,
+}
+
+/// Serving side: the encoded snapshots transfers are reading, by topic and digest, see
+/// the module docs
+pub(crate) struct SnapshotServer<T> {
+    encode: fn(&Snapshot<T>) -> serde_json::Result<Vec<u8>>,
+    retention: Duration,
+    served: Mutex<HashMap<(String, u32), Served>>,
+}
+
+impl<T> SnapshotServer<T> {
+    pub(crate) fn new() -> Self
+    where
+        T: Serialize,
+    {
+        Self { encode: serde_json::to_vec::<Snapshot<T>>, retention: SNAPSHOT_RETENTION, served: Mutex::new(HashMap::new()) }
+    }
+
+    /// Keep each snapshot for `retention` after its last chunk was served
+    pub(crate) fn set_retention(&mut self, retention: Duration) {
+        self.retention = retention;
+    }
+
+    /// Up to `max` bytes of `queue`'s snapshot from `offset`: of the snapshot named by
+    /// `digest`, or of a fresh one if there is none
+    pub(crate) fn chunk(&self, topic: &str, queue: &DistributedQueueSystem<T>, offset: u64, digest: Option<u32>, max: usize) -> Result<SnapshotChunk, ServeError>
+    where
+        T: Send + Sync + 'static,
+    {
+        let (digest, encoded) = match digest {
+            Some(digest) => {
+                let mut served = self.served.lock().unwrap();
+                served.retain(|_, snapshot| snapshot.used.elapsed() < self.retention);
+                match served.get_mut(&(topic.to_string(), digest)) {
+                    Some(snapshot) => {
+                        snapshot.used = Instant::now();
+                        (digest, snapshot.encoded.clone())
+                    }
+                    None => return Err(ServeError::Gone { digest }),
+                }
+            }
+            None => {
+                let encoded = Arc::new((self.encode)(&queue.snapshot()).map_err(|e| ServeError::Encode(e.to_string()))?);
+                let digest = crc32fast::hash(&encoded);
+                let mut served = self.served.lock().unwrap();
+                served.retain(|_, snapshot| snapshot.used.elapsed() < self.retention);
+                served.insert((topic.to_string(), digest), Served { encoded: encoded.clone(), used: Instant::now() });
+                (digest, encoded)
+            }
+        };
+        let total = encoded.len() as u64;
+        if offset > total {
+            return Err(ServeError::OutOfRange { offset, total });
+        }
+        let start = offset as usize;
+        let end = start + max.clamp(1, SNAPSHOT_CHUNK_LEN).min(encoded.len() - start);
+        let data = encoded[start..end].to_vec();
+        Ok(SnapshotChunk { digest, total, offset, crc32: crc32fast::hash(&data), data })
+    }
+}
+
+/// Receiving side: the chunks of one snapshot assembled so far, see `QueueClient::resume_snapshot`
+#[derive(Clone, Debug)]
+pub struct SnapshotDownload {
+    chunk_len: usize,
+    digest: Option<u32>,
+    total: Option<u64>,
+    data: Vec<u8>,
+}
+
+impl Default for SnapshotDownload {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SnapshotDownload {
+    pub fn new() -> Self {
+        Self { chunk_len: SNAPSHOT_CHUNK_LEN, digest: None, total: None, data: Vec::new() }
+    }
+
+    /// Ask for at most `len` bytes per chunk
+    pub fn with_chunk_len(mut self, len: usize) -> Self {
+        self.chunk_len = len.max(1);
+        self
+    }
+
+    pub fn chunk_len(&self) -> usize {
+        self.chunk_len
+    }
+
+    /// Digest of the snapshot being downloaded, None until the first chunk is in
+    pub fn digest(&self) -> Option<u32> {
+        self.digest
+    }
+
+    /// Bytes received so far, where the next chunk starts
+    pub fn offset(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.total == Some(self.offset())
+    }
+
+    /// Add the next chunk, checking it belongs to this snapshot, follows on and is intact
+    pub fn accept(&mut self, chunk: SnapshotChunk) -> io::Result<()> {
+        let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        if self.digest.is_some_and(|digest| digest != chunk.digest) {
+            return invalid(format!("chunk of snapshot {:08x}, expected {:08x}", chunk.digest, self.digest.unwrap_or_default()));
+        }
+        if chunk.offset != self.offset() {
+            return invalid(format!("chunk at offset {}, expected {}", chunk.offset, self.offset()));
+        }
+        if crc32fast::hash(&chunk.data) != chunk.crc32 {
+            return invalid(format!("checksum mismatch in chunk at offset {}", chunk.offset));
+        }
+        if chunk.offset + chunk.data.len() as u64 > chunk.total || (chunk.data.is_empty() && chunk.offset < chunk.total) {
+            return invalid(format!("chunk at offset {} doesn't fit a snapshot of {} bytes", chunk.offset, chunk.total));
+        }
+        self.digest = Some(chunk.digest);
+        self.total = Some(chunk.total);
+        self.data.extend_from_slice(&chunk.data);
+        Ok(())
+    }
+
+    /// Check the complete snapshot against its digest and decode it
+    pub fn finish<T: DeserializeOwned>(self) -> io::Result<Snapshot<T>> {
+        if !self.is_complete() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("snapshot incomplete at {} bytes", self.offset())));
+        }
+        if self.digest != Some(crc32fast::hash(&self.data)) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "snapshot doesn't match its digest"));
+        }
+        serde_json::from_slice(&self.data).map_err(io::Error::other)
+    }
+}
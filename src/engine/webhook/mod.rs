@@ -0,0 +1,171 @@
+//! Webhook delivery of dequeued items
+//! A dispatcher dequeues the items of each configured topic and POSTs them to the topic's
+//! endpoint as `{"topic", "delivery", "item"}` JSON. A post that fails or gets a non-2xx status
+//! is reported with `Node::fail`, so the topic's retry policy enqueues the item again after
+//! a backoff and dead-letters it when out of attempts; give webhook topics a retry policy,
+//! without one a failed post is only logged.
+//! Only plain `http://` endpoints are supported.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use serde::Serialize;
+use serde_json::json;
+use crate::engine::node::Node;
+use crate::engine::worker::{ErrorLog, WorkerErrors};
+
+/// How long to wait for an endpoint to accept a post and answer it
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often a dispatcher polls an empty topic
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// HTTP endpoint items of a topic are posted to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Webhook {
+    host: String,
+    port: u16,
+    path: String,
+    timeout: Duration,
+}
+
+impl Webhook {
+    /// Endpoint at `url`, `http://host[:port][/path]`
+    pub fn new(url: &str) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("webhook url {}: {}", url, message));
+        let Some(rest) = url.strip_prefix("http://") else {
+            return Err(invalid("only http:// endpoints are supported"));
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(at) => rest.split_at(at),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid("invalid port"))?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(invalid("no host"));
+        }
+        Ok(Self { host: host.to_string(), port, path: path.to_string(), timeout: DEFAULT_TIMEOUT })
+    }
+
+    /// Give up on a post after `timeout` (connecting, and again waiting for the answer)
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// URL of the endpoint
+    pub fn url(&self) -> String {
+        format!("http://{}:{}{}", self.host, self.port, self.path)
+    }
+
+    /// POST a JSON body, returning the response status
+    pub fn post(&self, body: &[u8]) -> io::Result<u16> {
+        let addr = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} does not resolve", self.host)))?;
+        let mut stream = TcpStream::connect_timeout(&addr, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.path,
+            self.host,
+            self.port,
+            body.len()
+        )?;
+        stream.write_all(body)?;
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line)?;
+        // "HTTP/1.1 200 OK"
+        status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("malformed status line {:?}", status_line.trim_end())))
+    }
+}
+
+/// Handle to the threads posting a node's items to webhooks, one per topic
+pub struct WebhookDispatcher {
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+    errors: ErrorLog,
+}
+
+impl WebhookDispatcher {
+    /// Start posting the items of each topic in `webhooks` to its endpoint
+    /// Like client dequeues, only queues this node owns are dispatched (see `Node::with_placement`);
+    /// failed items come back once the node processes retries, which the dispatcher does too
+    pub fn start<T>(node: Arc<Node<T>>, webhooks: HashMap<String, Webhook>) -> Self
+    where
+        T: Serialize + Send + Sync + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let errors = ErrorLog::default();
+        let threads = webhooks
+            .into_iter()
+            .map(|(topic, webhook)| {
+                let (node, stop, errors) = (node.clone(), stop.clone(), errors.clone());
+                thread::spawn(move || {
+                    while !stop.load(Ordering::SeqCst) {
+                        node.process_retries();
+                        if !dispatch_next(&node, &topic, &webhook, &errors) {
+                            thread::sleep(POLL_INTERVAL);
+                        }
+                    }
+                })
+            })
+            .collect();
+        Self { stop, threads, errors }
+    }
+
+    /// Errors the webhooks ran into, see `WorkerErrors`
+    pub fn errors(&self) -> WorkerErrors {
+        self.errors.snapshot()
+    }
+
+    /// Stop dispatching and wait for posts in flight
+    pub fn shutdown(mut self) {
+        self.stop_and_join();
+    }
+
+    fn stop_and_join(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            thread.join().ok();
+        }
+    }
+}
+
+impl Drop for WebhookDispatcher {
+    fn drop(&mut self) {
+        self.stop_and_join();
+    }
+}
+
+/// Dequeue and post the next item of `topic`, reporting it failed if the post fails
+/// Returns false if there was nothing to dispatch
+fn dispatch_next<T: Serialize + Send + Sync + 'static>(node: &Node<T>, topic: &str, webhook: &Webhook, errors: &ErrorLog) -> bool {
+    let Some((delivery, item)) = node.next_delivery(topic) else {
+        return false;
+    };
+    let body = json!({ "topic": topic, "delivery": delivery, "item": &*item }).to_string();
+    let error = match webhook.post(body.as_bytes()) {
+        Ok(status) if (200..300).contains(&status) => return true,
+        Ok(status) => format!("status {}", status),
+        Err(e) => e.to_string(),
+    };
+    errors.record(format_args!("webhook {} rejected delivery {} of {}: {}", webhook.url(), delivery, topic, error));
+    if let Err(e) = node.fail(topic, delivery) {
+        errors.record(format_args!("delivery {} of {} is not retried: {}", delivery, topic, e));
+    }
+    true
+}
@@ -0,0 +1,31 @@
+//! Errors of the threads a node runs in the background: consumers, webhook dispatchers,
+//! mirrors and bridges. They have no caller to return an error to, so each keeps count of
+//! the ones it ran into, and the latest, for its handle's `errors`
+
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use serde::Serialize;
+
+/// Errors a background worker ran into since it started
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct WorkerErrors {
+    pub count: u64,
+    /// Message of the latest one, None if there was none
+    pub last: Option<String>,
+}
+
+/// Errors shared between a handle and its threads
+#[derive(Clone, Default)]
+pub(crate) struct ErrorLog(Arc<Mutex<WorkerErrors>>);
+
+impl ErrorLog {
+    pub(crate) fn record(&self, error: impl Display) {
+        let mut errors = self.0.lock().unwrap();
+        errors.count += 1;
+        errors.last = Some(error.to_string());
+    }
+
+    pub(crate) fn snapshot(&self) -> WorkerErrors {
+        self.0.lock().unwrap().clone()
+    }
+}
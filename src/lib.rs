@@ -1,2 +1,6 @@
+#![allow(non_snake_case)]
+
 pub mod core;
-pub mod engine;
\ No newline at end of file
+pub mod engine;
+#[cfg(any(feature = "python", feature = "capi", feature = "wasm"))]
+pub mod bindings;
\ No newline at end of file
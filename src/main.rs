@@ -1,3 +1,5 @@
+#![allow(non_snake_case)]
+
 use DistributedQueueMini::core::log::append_logs;
 use std::sync::Arc;
 use std::thread;
@@ -31,7 +33,9 @@ fn main() {
             // Enqueue 3 items
             for i in 1..=3 {
                 let item = format!("{}-Item {}", node_clone.node_id(), i);
-                node_clone.enqueue(item);
+                if let Err(e) = node_clone.enqueue(item) {
+                    eprintln!("enqueue failed: {}", e);
+                }
                 thread::sleep(Duration::from_millis(10));
             }
 
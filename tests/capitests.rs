@@ -0,0 +1,55 @@
+#![cfg(feature = "capi")]
+
+use std::ffi::CString;
+use std::ptr;
+use DistributedQueueMini::bindings::capi::*;
+
+fn bytes(buffer: &DqBuffer) -> &[u8] {
+    if buffer.data.is_null() { &[] } else { unsafe { std::slice::from_raw_parts(buffer.data, buffer.len) } }
+}
+
+#[test]
+fn test_capi_replicates_byte_items_between_nodes() {
+    let (a_name, b_name) = (CString::new("A").unwrap(), CString::new("B").unwrap());
+    unsafe {
+        let a = dq_node_new(a_name.as_ptr(), &b_name.as_ptr(), 1);
+        let b = dq_node_new(b_name.as_ptr(), &a_name.as_ptr(), 1);
+        assert!(!a.is_null() && !b.is_null());
+
+        let mut event = DqBuffer { data: ptr::null_mut(), len: 0 };
+        let item = b"\x00binary\xff";
+        assert_eq!(dq_enqueue(a, item.as_ptr(), item.len(), &mut event), DqStatus::Ok);
+        assert_eq!(dq_apply_remote_event(b, event.data, event.len), 1);
+        assert_eq!(dq_apply_remote_event(b, event.data, event.len), 0, "duplicate applied");
+        dq_buffer_free(event);
+        assert_eq!((dq_len(a), dq_len(b)), (1, 1));
+
+        let mut dequeued = DqBuffer { data: ptr::null_mut(), len: 0 };
+        let mut event = DqBuffer { data: ptr::null_mut(), len: 0 };
+        assert_eq!(dq_dequeue(b, &mut dequeued, &mut event), 1);
+        assert_eq!(bytes(&dequeued), item);
+        assert_eq!(dq_apply_remote_event(a, event.data, event.len), 1);
+        assert_eq!(dq_len(a), 0);
+        dq_buffer_free(dequeued);
+        dq_buffer_free(event);
+
+        assert_eq!(dq_dequeue(b, ptr::null_mut(), ptr::null_mut()), 0);
+        let mut outgoing = DqBuffer { data: ptr::null_mut(), len: 0 };
+        assert_eq!(dq_take_outgoing(a, &mut outgoing), 0);
+        assert_eq!(bytes(&outgoing), b"[]");
+        dq_buffer_free(outgoing);
+
+        let mut stats = DqBuffer { data: ptr::null_mut(), len: 0 };
+        assert_eq!(dq_stats(b, &mut stats), DqStatus::Ok);
+        let stats_json: serde_json::Value = serde_json::from_slice(bytes(&stats)).unwrap();
+        assert_eq!(stats_json["node_id"], "B");
+        assert_eq!(stats_json["depth"], 0);
+        dq_buffer_free(stats);
+
+        let garbage = b"not an event";
+        assert_eq!(dq_apply_remote_event(a, garbage.as_ptr(), garbage.len()), DqStatus::InvalidArgument as i32);
+        assert_eq!(dq_enqueue(ptr::null(), item.as_ptr(), item.len(), ptr::null_mut()), DqStatus::NullArgument);
+        dq_node_free(a);
+        dq_node_free(b);
+    }
+}
@@ -0,0 +1,90 @@
+#![cfg(feature = "grpc")]
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use DistributedQueueMini::core::buildcore::DistributedQueueSystem;
+use DistributedQueueMini::engine::grpc::{proto, serve_with_shutdown, QueueServiceClient};
+use tonic::transport::server::TcpIncoming;
+
+#[tokio::test]
+async fn test_grpc_enqueue_dequeue_and_snapshot() {
+    let node = Arc::new(DistributedQueueSystem::<Vec<u8>>::new("grpc-node".to_string()));
+    let incoming = TcpIncoming::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = incoming.local_addr().unwrap();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(serve_with_shutdown(node.clone(), incoming, async {
+        stopped.await.ok();
+    }));
+
+    let mut client = QueueServiceClient::connect(format!("http://{}", addr)).await.unwrap();
+    for item in [b"a".to_vec(), b"b".to_vec()] {
+        client.enqueue(proto::EnqueueRequest { item }).await.unwrap();
+    }
+
+    // An event from another node, applied through the API
+    let remote = DistributedQueueSystem::<Vec<u8>>::new("grpc-remote".to_string());
+    let event = remote.enqueue(b"c".to_vec()).unwrap();
+    let applied = client
+        .apply_event(proto::ApplyEventRequest { event: Some((&event).into()) })
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(applied.applied);
+
+    let snapshot = client.snapshot(proto::SnapshotRequest {}).await.unwrap().into_inner();
+    assert_eq!(snapshot.node_id, "grpc-node");
+    assert_eq!(snapshot.items, vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+
+    let dequeued = client.dequeue(proto::DequeueRequest {}).await.unwrap().into_inner();
+    assert_eq!(dequeued.item, Some(b"a".to_vec()));
+
+    let logs = client
+        .get_logs_since(proto::GetLogsSinceRequest { clock: HashMap::new() })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(logs.entries.len(), 4);
+
+    stop.send(()).unwrap();
+    server.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_grpc_calls_are_checked_against_the_acl() {
+    use DistributedQueueMini::engine::acl::{Acl, Permission};
+    use DistributedQueueMini::engine::grpc::QueueGrpcService;
+
+    let node = Arc::new(DistributedQueueSystem::<Vec<u8>>::new("grpc-acl-node".to_string()));
+    let acl = Acl::new().token("p-token", "producer").grant("producer", "jobs", &[Permission::Produce]);
+    let incoming = TcpIncoming::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = incoming.local_addr().unwrap();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(QueueGrpcService::new(node.clone()).with_acl(acl, "jobs").into_server())
+            .serve_with_incoming_shutdown(incoming, async {
+                stopped.await.ok();
+            }),
+    );
+    fn with_token<M>(message: M, token: &str) -> tonic::Request<M> {
+        let mut request = tonic::Request::new(message);
+        request.metadata_mut().insert("authorization", format!("Bearer {}", token).parse().unwrap());
+        request
+    }
+
+    let mut client = QueueServiceClient::connect(format!("http://{}", addr)).await.unwrap();
+    let anonymous = client.enqueue(proto::EnqueueRequest { item: b"a".to_vec() }).await.unwrap_err();
+    assert_eq!(anonymous.code(), tonic::Code::PermissionDenied);
+    client.enqueue(with_token(proto::EnqueueRequest { item: b"a".to_vec() }, "p-token")).await.unwrap();
+    let denied = client.dequeue(with_token(proto::DequeueRequest {}, "p-token")).await.unwrap_err();
+    assert_eq!(denied.code(), tonic::Code::PermissionDenied);
+
+    // Replicated events could dequeue too, so they need both permissions
+    let event = DistributedQueueSystem::<Vec<u8>>::new("grpc-acl-remote".to_string()).enqueue(b"b".to_vec()).unwrap();
+    let request = with_token(proto::ApplyEventRequest { event: Some((&event).into()) }, "p-token");
+    assert_eq!(client.apply_event(request).await.unwrap_err().code(), tonic::Code::PermissionDenied);
+    assert_eq!(node.queue_state().0, 1);
+
+    stop.send(()).unwrap();
+    server.await.unwrap().unwrap();
+}
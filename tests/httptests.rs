@@ -0,0 +1,557 @@
+#![cfg(feature = "http")]
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use DistributedQueueMini::engine::http;
+use DistributedQueueMini::engine::node::Node;
+
+/// Minimal HTTP/1.1 client returning (status, body)
+fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, String) {
+    request_with_headers(addr, method, path, "", body)
+}
+
+/// Like `request`, with extra header lines (each ending in \r\n)
+fn request_with_headers(addr: SocketAddr, method: &str, path: &str, headers: &str, body: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        headers,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body = response.split_once("\r\n\r\n").map(|(_, b)| b.to_string()).unwrap_or_default();
+    (status, body)
+}
+
+#[test]
+fn test_http_queue_roundtrip() {
+    let node = Arc::new(Node::<String>::new("http-node", &[]));
+    let server = http::serve(node.clone(), "127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+
+    assert_eq!(request(addr, "POST", "/queues/jobs/messages", r#""first""#).0, 201);
+    assert_eq!(request(addr, "POST", "/queues/jobs/messages", r#""second""#).0, 201);
+    assert_eq!(request(addr, "POST", "/queues/jobs/messages", "not json").0, 400);
+
+    let (status, body) = request(addr, "GET", "/queues/jobs/stats", "");
+    assert_eq!(status, 200);
+    assert!(body.contains(r#""depth":2"#));
+
+    let (status, body) = request(addr, "DELETE", "/queues/jobs/messages", "");
+    assert_eq!(status, 200);
+    assert!(body.contains(r#""item":"first""#));
+
+    let (status, body) = request(addr, "GET", "/logs?topic=jobs", "");
+    assert_eq!(status, 200);
+    assert!(body.starts_with(r#"{"jobs":["#));
+
+    assert_eq!(request(addr, "GET", "/queues/missing/stats", "").0, 404);
+    server.shutdown();
+}
+
+#[test]
+fn test_http_access_control() {
+    use DistributedQueueMini::engine::acl::{Acl, Permission};
+
+    let acl = Acl::new()
+        .token("orders-secret", "orders")
+        .grant("orders", "orders*", &[Permission::Produce, Permission::Consume])
+        .grant("*", "public", &[Permission::Consume]);
+    let node = Arc::new(Node::<String>::new("acl-node", &[]).with_acl(acl));
+    let server = http::serve(node.clone(), "127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let auth = "Authorization: Bearer orders-secret\r\n";
+
+    assert_eq!(request(addr, "POST", "/queues/orders.eu/messages", r#""x""#).0, 403);
+    assert_eq!(request_with_headers(addr, "POST", "/queues/orders.eu/messages", auth, r#""x""#).0, 201);
+    assert_eq!(request_with_headers(addr, "POST", "/queues/public/messages", auth, r#""y""#).0, 403);
+    node.enqueue("public", "news".to_string()).unwrap();
+    assert_eq!(request(addr, "DELETE", "/queues/public/messages", "").0, 200);
+
+    // Listings leave out topics the client can't read
+    let (status, body) = request(addr, "GET", "/logs", "");
+    assert_eq!(status, 200);
+    assert!(body.contains("public") && !body.contains("orders.eu"));
+    assert_eq!(request(addr, "GET", "/logs?topic=orders.eu", "").0, 403);
+
+    let audit = node.audit_log().entries();
+    let denied: Vec<(&str, &str, Option<&str>)> =
+        audit.iter().map(|e| (e.actor.as_str(), e.action.as_str(), e.topic.as_deref())).collect();
+    assert_eq!(
+        denied,
+        [
+            ("anonymous", "produce", Some("orders.eu")),
+            ("orders", "produce", Some("public")),
+            ("anonymous", "consume", Some("orders.eu")),
+        ]
+    );
+    assert!(audit.iter().all(|e| !e.allowed));
+    server.shutdown();
+}
+
+#[test]
+fn test_http_admin_actions_need_the_admin_permission() {
+    use DistributedQueueMini::engine::acl::{Acl, Permission};
+
+    let acl = Acl::new()
+        .token("orders-secret", "orders")
+        .token("ops-secret", "ops")
+        .grant("orders", "orders*", &[Permission::Produce, Permission::Consume])
+        .grant("ops", "*", &[Permission::Admin]);
+    let node = Arc::new(Node::<String>::new("admin-node", &[]).with_acl(acl));
+    node.enqueue("orders.eu", "x".to_string()).unwrap();
+    let server = http::serve(node.clone(), "127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let orders = "Authorization: Bearer orders-secret\r\n";
+    let ops = "Authorization: Bearer ops-secret\r\n";
+
+    // Producing on a topic doesn't make a client its administrator
+    for (method, path, body) in [
+        ("POST", "/queues/orders.eu/pause", ""),
+        ("POST", "/queues/orders.eu/resume", ""),
+        ("POST", "/queues/orders.eu/purge?key=k", ""),
+        ("PUT", "/queues/orders.eu/config", r#"{"capacity":10}"#),
+        ("POST", "/admin/rebalance?topic=orders.eu", ""),
+        ("GET", "/admin/status", ""),
+        ("GET", "/admin/pending", ""),
+    ] {
+        assert_eq!(request_with_headers(addr, method, path, orders, body).0, 403, "{} {}", method, path);
+    }
+
+    assert_eq!(request_with_headers(addr, "POST", "/queues/orders.eu/pause", ops, "").0, 200);
+    assert_eq!(request_with_headers(addr, "POST", "/admin/rebalance?topic=orders.eu", ops, "").0, 200);
+    assert_eq!(request_with_headers(addr, "GET", "/admin/status", ops, "").0, 200);
+
+    // The audit trail names the client that carried the action out
+    let audit = node.audit_log().entries();
+    let paused = audit.iter().find(|e| e.allowed && e.action == "control").unwrap();
+    assert_eq!((paused.actor.as_str(), paused.topic.as_deref()), ("ops", Some("orders.eu")));
+    assert!(audit.iter().filter(|e| !e.allowed).all(|e| e.actor == "orders" && e.action == "admin"));
+    server.shutdown();
+}
+
+#[test]
+fn test_namespaces_isolate_topics_and_enforce_quotas() {
+    use DistributedQueueMini::core::buildcore::QueueError;
+    use DistributedQueueMini::engine::namespace::NamespaceQuota;
+
+    let node = Arc::new(
+        Node::<String>::new("ns-node", &[])
+            .with_partitions("shop/orders", 2)
+            .with_namespace_quota("shop", NamespaceQuota::new().max_topics(2).max_items(3)),
+    );
+    let server = http::serve(node.clone(), "127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+
+    // The same topic name in two namespaces is two queues
+    assert_eq!(request(addr, "POST", "/namespaces/shop/queues/jobs/messages", r#""shop job""#).0, 201);
+    assert_eq!(request(addr, "POST", "/namespaces/blog/queues/jobs/messages", r#""blog job""#).0, 201);
+    assert_eq!(request(addr, "POST", "/queues/jobs/messages", r#""default job""#).0, 201);
+    let (status, body) = request(addr, "DELETE", "/namespaces/blog/queues/jobs/messages", "");
+    assert_eq!(status, 200);
+    assert!(body.contains(r#""item":"blog job""#));
+    assert_eq!(node.namespaces(), ["blog", "default", "shop"]);
+
+    // A partitioned topic counts as one topic; a third one is refused
+    node.enqueue("shop/orders", "o1".to_string()).unwrap();
+    assert_eq!(node.namespace_topics("shop"), ["shop/jobs", "shop/orders"]);
+    assert_eq!(node.enqueue("shop/refunds", "r1".to_string()).unwrap_err(), QueueError::TooManyTopics { namespace: "shop".to_string(), max: 2 });
+    node.enqueue("shop/orders", "o2".to_string()).unwrap();
+    assert_eq!(
+        node.enqueue("shop/jobs", "one too many".to_string()).unwrap_err(),
+        QueueError::NamespaceQuotaExceeded { namespace: "shop".to_string(), quota: 3 }
+    );
+    assert_eq!(request(addr, "POST", "/namespaces/shop/queues/jobs/messages", r#""refused""#).0, 503);
+    // Other namespaces are unaffected
+    node.enqueue("blog/posts", "p1".to_string()).unwrap();
+
+    let (status, body) = request(addr, "GET", "/namespaces/shop/stats", "");
+    assert_eq!(status, 200);
+    assert!(body.contains(r#""topics":2"#) && body.contains(r#""depth":3"#));
+    assert_eq!(request(addr, "GET", "/namespaces/nothing/stats", "").0, 404);
+
+    let (status, body) = request(addr, "GET", "/logs?namespace=blog", "");
+    assert_eq!(status, 200);
+    assert!(body.contains(r#""blog/jobs""#) && body.contains(r#""blog/posts""#) && !body.contains("shop/"));
+    server.shutdown();
+}
+
+#[test]
+#[cfg(feature = "server")]
+fn test_server_config_parses() {
+    use DistributedQueueMini::engine::config::ServerConfig;
+
+    let config = ServerConfig::from_toml(
+        r#"
+        node_id = "N0"
+        listen = "127.0.0.1:7000"
+        http = "127.0.0.1:8080"
+        audit_log = "audit.ndjson"
+
+        [webhooks.orders]
+        url = "http://127.0.0.1:9000/orders"
+
+        [namespaces.billing]
+        max_topics = 4
+
+        [[peers]]
+        id = "N1"
+        addr = "127.0.0.1:7001"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.node_id, "N0");
+    assert_eq!(config.peer_ids(), ["N1"]);
+    assert_eq!(config.storage_dir, None);
+    assert_eq!(config.acl, None);
+    assert_eq!(config.audit_log(), Some(std::path::Path::new("audit.ndjson")));
+    let webhook = &config.webhooks["orders"];
+    assert_eq!(webhook.to_webhook().unwrap().url(), "http://127.0.0.1:9000/orders");
+    assert_eq!(webhook.retry_policy(None).max_attempts(), DistributedQueueMini::engine::config::DEFAULT_WEBHOOK_ATTEMPTS);
+    assert_eq!(config.namespaces["billing"].max_topics, Some(4));
+    assert!(ServerConfig::from_toml("node_id = 3").is_err());
+}
+
+#[test]
+#[cfg(feature = "server")]
+fn test_config_reload_applies_limits_and_peers_without_a_restart() {
+    use DistributedQueueMini::core::buildcore::{RateLimit, Throttle};
+    use DistributedQueueMini::engine::acl::{Acl, Permission};
+    use DistributedQueueMini::engine::config::ServerConfig;
+    use DistributedQueueMini::engine::network::{TcpTransport, Transport};
+
+    let base = r#"
+        node_id = "R0"
+        listen = "127.0.0.1:0"
+        http = "127.0.0.1:8080"
+
+        [[peers]]
+        id = "R1"
+        addr = "127.0.0.1:7901"
+        "#;
+    let old = ServerConfig::from_toml(base).unwrap();
+    let new = ServerConfig::from_toml(
+        r#"
+        node_id = "R0"
+        listen = "127.0.0.1:0"
+        http = "127.0.0.1:8080"
+        max_buffered_events = 8
+
+        [rate_limit.node]
+        rate = 1.0
+        burst = 1
+
+        [retention]
+        max_entries = 5
+
+        [[peers]]
+        id = "R2"
+        addr = "127.0.0.1:7902"
+        "#,
+    )
+    .unwrap();
+    assert!(!old.needs_restart(&new));
+    assert!(old.needs_restart(&ServerConfig::from_toml(&base.replace("8080", "8081")).unwrap()));
+
+    let node = Arc::new(Node::<String>::new("R0", &["R1"]));
+    let transport = TcpTransport::<String>::bind("R0", old.listen, &[("R1", old.peers[0].addr)]).unwrap();
+    let queue = node.topic("jobs");
+    let changes = old.reload(&new, &node, &transport);
+    assert_eq!(changes.len(), 5, "{:?}", changes);
+    assert!(changes.contains(&"peer R2 added at 127.0.0.1:7902".to_string()));
+    assert!(changes.contains(&"peer R1 removed".to_string()));
+    assert_eq!(queue.max_buffered_events(), Some(8));
+    assert_eq!(queue.rate_limit(), Some((RateLimit::per_second(1.0, 1), Throttle::Reject)));
+    assert_eq!(queue.client_rate_limit(), None);
+    assert_eq!(transport.peers().iter().map(|p| p.as_str()).collect::<Vec<_>>(), ["R2"]);
+    assert!(node.enqueue("jobs", "first".to_string()).is_ok());
+    assert!(node.enqueue("jobs", "second".to_string()).is_err());
+    assert!(new.reload(&new, &node, &transport).is_empty());
+
+    // The admin endpoint runs the reloader the server installed
+    let server = http::serve(node.clone(), "127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    assert_eq!(request(addr, "POST", "/admin/reload", "").0, 404);
+    node.set_reloader(|| Ok(vec!["max_buffered_events = Some(16)".to_string()]));
+    let (status, body) = request(addr, "POST", "/admin/reload", "");
+    assert_eq!(status, 200);
+    assert_eq!(body, r#"{"changes":["max_buffered_events = Some(16)"]}"#);
+    node.set_reloader(|| Err(std::io::Error::other("bad config")));
+    assert_eq!(request(addr, "POST", "/admin/reload", "").0, 500);
+    server.shutdown();
+
+    // With access control, only administrators may reload
+    let acl = Acl::new()
+        .token("ops-secret", "ops")
+        .grant("ops", "*", &[Permission::Admin])
+        .grant("*", "*", &[Permission::Produce, Permission::Consume]);
+    let node = Arc::new(Node::<String>::new("R0", &[]).with_acl(acl));
+    node.set_reloader(|| Ok(Vec::new()));
+    let server = http::serve(node.clone(), "127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    assert_eq!(request(addr, "POST", "/admin/reload", "").0, 403);
+    assert_eq!(request_with_headers(addr, "POST", "/admin/reload", "Authorization: Bearer ops-secret\r\n", "").0, 200);
+    let audit = node.audit_log().entries();
+    let actions: Vec<(&str, &str, bool)> = audit.iter().map(|e| (e.actor.as_str(), e.action.as_str(), e.allowed)).collect();
+    assert_eq!(actions, [("anonymous", "admin", false), ("ops", "config_reload", true)]);
+    server.shutdown();
+}
+
+/// Endpoint answering each post with the next status, then 200; records the bodies
+fn webhook_endpoint(statuses: Vec<u16>) -> (SocketAddr, Arc<std::sync::Mutex<Vec<String>>>) {
+    use std::io::BufRead;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let bodies = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let received = bodies.clone();
+    std::thread::spawn(move || {
+        let mut statuses = statuses.into_iter();
+        for stream in listener.incoming() {
+            let mut reader = std::io::BufReader::new(stream.unwrap());
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            received.lock().unwrap().push(String::from_utf8(body).unwrap());
+            let status = statuses.next().unwrap_or(200);
+            write!(reader.get_mut(), "HTTP/1.1 {} Webhook\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+        }
+    });
+    (addr, bodies)
+}
+
+#[test]
+fn test_webhooks_retry_then_dead_letter() {
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+    use DistributedQueueMini::core::buildcore::{DistributedQueueSystem, RetryPolicy};
+    use DistributedQueueMini::engine::webhook::{Webhook, WebhookDispatcher};
+
+    let (addr, bodies) = webhook_endpoint(vec![500, 503, 200, 500, 500, 500]);
+    let policy = RetryPolicy::new(3).backoff(Duration::from_millis(10), 1.0, Duration::from_millis(10));
+    let node = Arc::new(Node::<String>::with_topic_factory("webhook-node", move |_| {
+        DistributedQueueSystem::builder("webhook-node").retry_policy(policy)
+    }));
+    assert!(Webhook::new("https://example.com/hook").is_err());
+    let webhook = Webhook::new(&format!("http://{}/hooks/jobs", addr)).unwrap();
+    assert_eq!(webhook.url(), format!("http://{}/hooks/jobs", addr));
+
+    // The first item gets through on its third attempt; the second fails all three
+    node.enqueue("jobs", "first".to_string()).unwrap();
+    let dispatcher = WebhookDispatcher::start(node.clone(), HashMap::from([("jobs".to_string(), webhook)]));
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while bodies.lock().unwrap().len() < 3 && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    node.enqueue("jobs", "second".to_string()).unwrap();
+    let queue = node.topic("jobs");
+    while queue.dead_letters().is_empty() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    dispatcher.shutdown();
+
+    let bodies = bodies.lock().unwrap();
+    assert_eq!(bodies.len(), 6);
+    assert!(bodies[..3].iter().all(|body| body.contains(r#""item":"first""#) && body.contains(r#""topic":"jobs""#)));
+    assert!(bodies[3..].iter().all(|body| body.contains(r#""item":"second""#)));
+    assert_eq!(queue.dead_letters().iter().map(|item| item.as_str()).collect::<Vec<_>>(), ["second"]);
+    assert_eq!(queue.queue_state().0, 0);
+}
+
+#[test]
+#[cfg(feature = "sqs")]
+fn test_sqs_facade_send_receive_delete() {
+    use std::time::Duration;
+    use serde_json::Value;
+    use DistributedQueueMini::core::buildcore::{DistributedQueueSystem, RetryPolicy};
+    use DistributedQueueMini::engine::sqs::SqsFacade;
+
+    let policy = RetryPolicy::new(3).backoff(Duration::ZERO, 1.0, Duration::ZERO);
+    let node = Arc::new(Node::<Value>::with_topic_factory("sqs-node", move |_| DistributedQueueSystem::builder("sqs-node").retry_policy(policy)));
+    let server = SqsFacade::new(node.clone()).serve("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let sqs = |action: &str, input: Value| {
+        let headers = format!("X-Amz-Target: AmazonSQS.{}\r\nContent-Type: application/x-amz-json-1.0\r\n", action);
+        let (status, body) = request_with_headers(addr, "POST", "/", &headers, &input.to_string());
+        (status, serde_json::from_str::<Value>(&body).unwrap())
+    };
+
+    let (status, output) = sqs("GetQueueUrl", serde_json::json!({ "QueueName": "jobs" }));
+    assert_eq!((status, output["__type"].as_str()), (400, Some("com.amazonaws.sqs#QueueDoesNotExist")));
+    let url = sqs("CreateQueue", serde_json::json!({ "QueueName": "jobs" })).1["QueueUrl"].as_str().unwrap().to_string();
+    assert!(url.ends_with("/jobs"));
+
+    let (status, sent) = sqs("SendMessage", serde_json::json!({ "QueueUrl": url, "MessageBody": "hello" }));
+    assert_eq!(status, 200);
+    assert_eq!(sent["MD5OfMessageBody"], "5d41402abc4b2a76b9719d911017c592");
+    sqs("SendMessage", serde_json::json!({ "QueueUrl": url, "MessageBody": r#"{"id":7}"# }));
+    assert_eq!(node.topic("jobs").peek().as_deref(), Some(&Value::from("hello")));
+
+    let received = sqs("ReceiveMessage", serde_json::json!({ "QueueUrl": url, "MaxNumberOfMessages": 10, "VisibilityTimeout": 1 })).1;
+    let messages = received["Messages"].as_array().unwrap();
+    assert_eq!(messages.iter().map(|m| m["Body"].as_str().unwrap()).collect::<Vec<_>>(), ["hello", r#"{"id":7}"#]);
+    assert_eq!(messages[0]["MD5OfBody"], "5d41402abc4b2a76b9719d911017c592");
+    sqs("DeleteMessage", serde_json::json!({ "QueueUrl": url, "ReceiptHandle": messages[0]["ReceiptHandle"] }));
+    // Deleting acks the delivery, so it can't be failed any more
+    let deleted: u64 = messages[0]["MessageId"].as_str().unwrap().parse().unwrap();
+    assert!(node.fail("jobs", deleted).is_err());
+
+    // The undeleted message outlived its visibility timeout, so it was failed without
+    // waiting for another request, and comes back
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while node.topic("jobs").pending_retries() == 0 && std::time::Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(node.topic("jobs").pending_retries(), 1);
+    let attributes = sqs("GetQueueAttributes", serde_json::json!({ "QueueUrl": url, "AttributeNames": ["All"] })).1;
+    assert_eq!(attributes["Attributes"]["ApproximateNumberOfMessagesNotVisible"], "0");
+    assert_eq!(attributes["Attributes"]["ApproximateNumberOfMessagesDelayed"], "1");
+    node.process_retries();
+    let received = sqs("ReceiveMessage", serde_json::json!({ "QueueUrl": url, "WaitTimeSeconds": 1 })).1;
+    assert_eq!(received["Messages"][0]["Body"], r#"{"id":7}"#);
+    server.shutdown();
+}
+
+#[test]
+fn test_health_and_readiness_probes() {
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+    use DistributedQueueMini::core::buildcore::{DistributedQueueSystem, NodeId};
+    use DistributedQueueMini::engine::health::HealthPolicy;
+    use DistributedQueueMini::engine::network::TcpTransport;
+
+    let wait_for = |check: &dyn Fn() -> bool| {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !check() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        check()
+    };
+    let dir = std::env::temp_dir().join(format!("dqm-probe-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let any = "127.0.0.1:0".parse::<SocketAddr>().unwrap();
+    let ta = Arc::new(TcpTransport::<String>::bind("probe-a", any, &[]).unwrap());
+    let tb = Arc::new(TcpTransport::<String>::bind("probe-b", any, &[]).unwrap());
+    ta.add_peer("probe-b", tb.local_addr());
+    tb.add_peer("probe-a", ta.local_addr());
+    let policy = HealthPolicy { peer_timeout: Some(Duration::from_millis(500)), storage_dir: Some(dir.clone()), ..HealthPolicy::default() };
+    let a = Arc::new(
+        Node::<String>::with_topic_factory("probe-a", |_| DistributedQueueSystem::builder("probe-a").peers(&["probe-b"]).queue_capacity(2))
+            .with_heartbeat(Duration::from_millis(20))
+            .with_health(policy),
+    );
+    let b = Arc::new(Node::<String>::new("probe-b", &["probe-a"]).with_heartbeat(Duration::from_millis(20)));
+    let server = http::serve(a.clone(), "127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+
+    // Not live until the receive loop runs, and not ready while no peer is heard from
+    let (status, body) = request(addr, "GET", "/healthz", "");
+    assert_eq!(status, 503);
+    assert!(body.contains("not started"));
+    let ha = a.start(ta.clone());
+    assert!(wait_for(&|| request(addr, "GET", "/healthz", "").0 == 200));
+    let (status, body) = request(addr, "GET", "/readyz", "");
+    assert_eq!(status, 503);
+    assert!(body.contains("no peer reachable: probe-b"));
+    let hb = b.start(tb.clone());
+    assert!(wait_for(&|| request(addr, "GET", "/readyz", "").0 == 200));
+
+    // A full queue is saturated
+    a.enqueue("jobs", "one".to_string()).unwrap();
+    a.enqueue("jobs", "two".to_string()).unwrap();
+    let (status, body) = request(addr, "GET", "/readyz", "");
+    assert_eq!(status, 503);
+    assert!(body.contains("saturated: jobs"));
+    a.dequeue("jobs");
+    assert_eq!(request(addr, "GET", "/readyz", "").0, 200);
+
+    // Peers known to be far ahead mean the node is still catching up
+    a.topic("jobs").observe_peer_clock(NodeId::intern("probe-c"), &HashMap::from([(NodeId::intern("probe-c"), 500)]));
+    let (status, body) = request(addr, "GET", "/readyz", "");
+    assert_eq!(status, 503);
+    assert!(body.contains("catching up: jobs (500 events)"));
+
+    // Storage that can't be written to fails both probes
+    std::fs::remove_dir_all(&dir).unwrap();
+    let (status, body) = request(addr, "GET", "/healthz", "");
+    assert_eq!(status, 503);
+    assert!(body.contains("is not writable"));
+
+    server.shutdown();
+    ha.shutdown();
+    hb.shutdown();
+}
+
+#[test]
+fn test_admin_client_drives_a_node() {
+    use std::io::ErrorKind;
+    use DistributedQueueMini::core::buildcore::{DistributedQueueSystem, QueueError};
+    use DistributedQueueMini::engine::admin::AdminClient;
+    use DistributedQueueMini::engine::placement::HashRing;
+
+    let node = Arc::new(
+        Node::<String>::with_topic_factory("ctl-a", |_| DistributedQueueSystem::builder("ctl-a").log_key(|item: &String| Some(item.clone())))
+            .with_placement(HashRing::with_members(&["ctl-a", "ctl-b"], 64)),
+    );
+    let server = http::serve(node.clone(), "127.0.0.1:0").unwrap();
+    let client = AdminClient::new(server.local_addr().unwrap().to_string());
+    // A topic this node owns, so it may rebalance it
+    let topic = (0..).map(|i| format!("jobs{}", i)).find(|topic| node.owner(topic).as_deref() == Some("ctl-a")).unwrap();
+    for i in 0..8 {
+        node.enqueue(&topic, format!("item{}", i)).unwrap();
+    }
+
+    let status = client.status().unwrap();
+    assert_eq!(status["node_id"], "ctl-a");
+    assert_eq!(status["topics"][&topic]["depth"], 8);
+    assert_eq!(client.peers().unwrap()["node_id"], "ctl-a");
+    assert_eq!(client.pending(None).unwrap()[&topic]["buffered_events"], 0);
+    assert_eq!(client.pending(Some(&topic)).unwrap(), serde_json::json!([]));
+
+    client.pause(&topic).unwrap();
+    assert!(matches!(node.enqueue(&topic, "refused".to_string()), Err(QueueError::Paused)));
+    client.resume(&topic).unwrap();
+    assert_eq!(client.purge(&topic, "item7").unwrap()["event_ids"].as_array().unwrap().len(), 1);
+    assert_eq!(client.snapshot(&topic).unwrap()["items"].as_array().unwrap().len(), 7);
+
+    // Half of the items go to the other member
+    assert_eq!(client.rebalance(&topic).unwrap()["transferred"]["ctl-b"], 3);
+    assert_eq!(node.topic(&topic).queue_state().0, 4);
+
+    // Topics and keys with URL delimiters round-trip through the encoding
+    let odd = (0..).map(|i| format!("a/b?c&d%e f{}", i)).find(|topic| node.owner(topic).as_deref() == Some("ctl-a")).unwrap();
+    node.enqueue(&odd, "k/1?x&y%z w".to_string()).unwrap();
+    node.enqueue(&odd, "plain".to_string()).unwrap();
+    assert_eq!(client.status().unwrap()["topics"][&odd]["depth"], 2);
+    assert_eq!(client.pending(Some(&odd)).unwrap(), serde_json::json!([]));
+    client.pause(&odd).unwrap();
+    assert!(matches!(node.enqueue(&odd, "refused".to_string()), Err(QueueError::Paused)));
+    client.resume(&odd).unwrap();
+    assert!(client.config(&odd).unwrap().is_object());
+    assert_eq!(client.purge(&odd, "k/1?x&y%z w").unwrap()["event_ids"].as_array().unwrap().len(), 1);
+    assert_eq!(client.snapshot(&odd).unwrap()["items"], serde_json::json!(["plain"]));
+    assert!(client.rebalance(&odd).unwrap()["transferred"].is_object());
+    assert!(node.get_topic("a").is_none());
+
+    assert_eq!(client.pause("missing").unwrap_err().kind(), ErrorKind::NotFound);
+    assert_eq!(client.purge(&topic, "").unwrap_err().kind(), ErrorKind::InvalidInput);
+    server.shutdown();
+}
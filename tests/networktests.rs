@@ -0,0 +1,2415 @@
+#![cfg(feature = "net")]
+
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use DistributedQueueMini::core::buildcore::NodeId;
+use DistributedQueueMini::engine::client::QueueClient;
+use DistributedQueueMini::engine::network::TcpTransport;
+use DistributedQueueMini::engine::node::Node;
+
+/// Poll until `check` passes or a few seconds elapse
+fn wait_for(check: impl Fn() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        if check() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+#[test]
+fn test_tcp_transport_replicates_between_nodes() {
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let ta = Arc::new(TcpTransport::<String>::bind("tcp-a", any, &[]).unwrap());
+    let tb = Arc::new(TcpTransport::<String>::bind("tcp-b", any, &[]).unwrap());
+    ta.add_peer("tcp-b", tb.local_addr());
+    tb.add_peer("tcp-a", ta.local_addr());
+
+    let a = Arc::new(Node::<String>::new("tcp-a", &["tcp-b"]));
+    let b = Arc::new(Node::<String>::new("tcp-b", &["tcp-a"]));
+    let ha = a.start(ta.clone());
+    let hb = b.start(tb.clone());
+
+    for i in 0..5 {
+        a.enqueue("jobs", format!("a{}", i)).unwrap();
+    }
+    b.enqueue("jobs", "b0".to_string()).unwrap();
+
+    assert!(wait_for(|| b.topic("jobs").queue_state().0 == 6));
+    assert!(wait_for(|| a.topic("jobs").queue_state().0 == 6));
+    assert_eq!(b.topic("jobs").pending_events_count(), 0);
+
+    ha.shutdown();
+    hb.shutdown();
+}
+
+#[test]
+fn test_tcp_transport_counts_envelopes_it_cannot_decode() {
+    use std::io::Write;
+    use DistributedQueueMini::engine::network::Transport;
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let transport = TcpTransport::<String>::bind("undecodable", any, &[]).unwrap();
+    let mut stream = std::net::TcpStream::connect(transport.local_addr()).unwrap();
+    stream.write_all(b"not an envelope\n").unwrap();
+
+    assert!(wait_for(|| transport.dropped() == 1));
+    assert!(transport.recv_timeout(Duration::from_millis(50)).is_none());
+}
+
+#[test]
+fn test_heartbeats_detect_peers_and_resend_missed_events() {
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let ta = Arc::new(TcpTransport::<String>::bind("hb-a", any, &[]).unwrap());
+    let tb = Arc::new(TcpTransport::<String>::bind("hb-b", any, &[]).unwrap());
+    ta.add_peer("hb-b", tb.local_addr());
+    tb.add_peer("hb-a", ta.local_addr());
+
+    let a = Arc::new(Node::<String>::new("hb-a", &["hb-b"]).with_heartbeat(Duration::from_millis(20)));
+    let b = Arc::new(Node::<String>::new("hb-b", &["hb-a"]).with_heartbeat(Duration::from_millis(20)));
+    // Enqueued before any transport is attached, so never broadcast
+    a.enqueue("jobs", "missed-1".to_string()).unwrap();
+    a.enqueue("jobs", "missed-2".to_string()).unwrap();
+    b.topic("jobs");
+    assert!(a.topic("jobs").stability_watermark().values().all(|&t| t == 0));
+
+    let ha = a.start(ta.clone());
+    let hb = b.start(tb.clone());
+    // B's heartbeat shows it is behind, so A resends without new traffic
+    assert!(wait_for(|| b.topic("jobs").queue_state().0 == 2));
+    assert_eq!(b.topic("jobs").peek().as_deref().map(String::as_str), Some("missed-1"));
+    // and its next one moves A's watermark past the resent events
+    assert!(wait_for(|| a.topic("jobs").stability_watermark().get(&NodeId::from("hb-a")) == Some(&2)));
+    assert!(wait_for(|| a.peer_status().get("hb-b").is_some_and(|status| status.depths.get("jobs") == Some(&2))));
+    assert!(a.suspected_peers(Duration::from_secs(5)).is_empty());
+
+    hb.shutdown();
+    assert!(wait_for(|| a.suspected_peers(Duration::from_millis(200)) == ["hb-b"]));
+    ha.shutdown();
+}
+
+#[test]
+fn test_backpressure_holds_events_until_the_peer_drains() {
+    use DistributedQueueMini::core::buildcore::DistributedQueueSystem;
+    use DistributedQueueMini::engine::node::BackpressurePolicy;
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let ta = Arc::new(TcpTransport::<String>::bind("bp-a", any, &[]).unwrap());
+    let tb = Arc::new(TcpTransport::<String>::bind("bp-b", any, &[]).unwrap());
+    ta.add_peer("bp-b", tb.local_addr());
+    tb.add_peer("bp-a", ta.local_addr());
+
+    let a = Arc::new(Node::<String>::new("bp-a", &["bp-b"]));
+    let policy = BackpressurePolicy { high: 0.75, low: 0.25, ..BackpressurePolicy::default() };
+    let b = Node::<String>::with_topic_factory("bp-b", |_| DistributedQueueSystem::builder("bp-b").peers(&["bp-a"]).queue_capacity(4));
+    let b = Arc::new(b.with_backpressure(policy));
+    let ha = a.start(ta.clone());
+    let hb = b.start(tb.clone());
+
+    for i in 0..3 {
+        a.enqueue("jobs", format!("job{}", i)).unwrap();
+    }
+    assert!(wait_for(|| b.cluster_status().backpressure == ["jobs"]));
+    assert!(wait_for(|| a.cluster_status().peers["bp-b"].backpressure.get("jobs") == Some(&0)));
+
+    // Held by the sender while B is loaded
+    a.enqueue("jobs", "job3".to_string()).unwrap();
+    a.enqueue("jobs", "job4".to_string()).unwrap();
+    assert_eq!(a.cluster_status().peers["bp-b"].backpressure["jobs"], 2);
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(b.topic("jobs").queue_state().0, 3);
+
+    // Draining releases them
+    for _ in 0..3 {
+        b.dequeue("jobs");
+    }
+    assert!(wait_for(|| b.topic("jobs").queue_state().0 == 2));
+    assert!(b.cluster_status().backpressure.is_empty());
+    assert!(a.cluster_status().peers["bp-b"].backpressure.is_empty());
+
+    ha.shutdown();
+    hb.shutdown();
+}
+
+#[test]
+fn test_receive_wait_returns_when_a_remote_enqueue_lands() {
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let ta = Arc::new(TcpTransport::<String>::bind("lp-a", any, &[]).unwrap());
+    let tb = Arc::new(TcpTransport::<String>::bind("lp-b", any, &[]).unwrap());
+    ta.add_peer("lp-b", tb.local_addr());
+    tb.add_peer("lp-a", ta.local_addr());
+    let a = Arc::new(Node::<String>::new("lp-a", &["lp-b"]));
+    let b = Arc::new(Node::<String>::new("lp-b", &["lp-a"]));
+    let ha = a.start(ta.clone());
+    let hb = b.start(tb.clone());
+
+    let started = Instant::now();
+    assert_eq!(b.receive_wait("jobs", Duration::from_millis(100)), None);
+    assert!(started.elapsed() >= Duration::from_millis(100));
+
+    let producer = a.clone();
+    let sender = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        producer.enqueue("other", "elsewhere".to_string()).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        producer.enqueue("jobs", "late".to_string()).unwrap();
+    });
+    let started = Instant::now();
+    let (_, item) = b.receive_wait("jobs", Duration::from_secs(5)).unwrap();
+    assert_eq!(*item, "late");
+    assert!(started.elapsed() < Duration::from_secs(5));
+    sender.join().unwrap();
+    assert_eq!(b.topic("other").queue_state().0, 1, "the other topic's item stays queued");
+
+    ha.shutdown();
+    hb.shutdown();
+}
+
+#[test]
+fn test_subscriptions_yield_deliveries_as_they_land() {
+    let node = Arc::new(Node::<String>::new("sub-a", &[]));
+    let producer = node.clone();
+    let sender = thread::spawn(move || {
+        for i in 0..3 {
+            thread::sleep(Duration::from_millis(20));
+            producer.enqueue("jobs", format!("job{}", i)).unwrap();
+        }
+    });
+    let mut received = Vec::new();
+    for delivery in node.subscribe("jobs").with_idle_timeout(Duration::from_millis(500)) {
+        assert_eq!(delivery.topic, "jobs");
+        received.push(delivery.item.to_string());
+    }
+    sender.join().unwrap();
+    assert_eq!(received, ["job0", "job1", "job2"]);
+
+    #[cfg(feature = "stream")]
+    {
+        use std::pin::Pin;
+        use std::task::{Context, Poll, Wake, Waker};
+        use futures_core::Stream;
+
+        struct Unpark(thread::Thread);
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+        /// Next item of a stream, parking the thread while it is pending
+        fn block_on_next<S: Stream + Unpin>(stream: &mut S) -> Option<S::Item> {
+            let waker = Waker::from(Arc::new(Unpark(thread::current())));
+            let mut cx = Context::from_waker(&waker);
+            loop {
+                match Pin::new(&mut *stream).poll_next(&mut cx) {
+                    Poll::Ready(item) => return item,
+                    Poll::Pending => thread::park(),
+                }
+            }
+        }
+
+        let mut stream = node.subscribe_stream("jobs");
+        let producer = node.clone();
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            producer.enqueue("jobs", "streamed".to_string()).unwrap();
+        });
+        let delivery = block_on_next(&mut stream).unwrap();
+        assert_eq!(*delivery.item, "streamed");
+        sender.join().unwrap();
+    }
+}
+
+#[test]
+fn test_registered_consumer_acks_and_retries_through_its_handler() {
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+    use DistributedQueueMini::core::buildcore::{DistributedQueueSystem, RetryPolicy};
+
+    let policy = RetryPolicy::new(3).backoff(Duration::ZERO, 1.0, Duration::ZERO);
+    let node = Arc::new(Node::<String>::with_topic_factory("pool-a", move |_| {
+        DistributedQueueSystem::builder("pool-a").retry_policy(policy)
+    }));
+    for i in 0..6 {
+        node.enqueue("jobs", format!("job{}", i)).unwrap();
+    }
+
+    // Odd jobs fail (or panic) on the first attempt and succeed on the retry
+    let attempted = Arc::new(Mutex::new(HashSet::new()));
+    let done = Arc::new(Mutex::new(Vec::new()));
+    let (seen, finished) = (attempted.clone(), done.clone());
+    let pool = node.register_consumer("jobs", move |delivery| {
+        let item = delivery.item.to_string();
+        let first = seen.lock().unwrap().insert(item.clone());
+        match item.as_str() {
+            "job1" if first => panic!("flaky handler"),
+            "job3" | "job5" if first => return Err(format!("{} not ready", item)),
+            _ => {}
+        }
+        finished.lock().unwrap().push(item);
+        Ok(())
+    }, 3);
+
+    assert!(wait_for(|| done.lock().unwrap().len() == 6));
+    // Each failed first attempt is kept for the pool's handle, not printed
+    let errors = pool.errors();
+    assert_eq!(errors.count, 3);
+    assert!(errors.last.is_some_and(|last| last.contains("failed")));
+    pool.shutdown();
+    let mut done = done.lock().unwrap().clone();
+    done.sort();
+    assert_eq!(done, ["job0", "job1", "job2", "job3", "job4", "job5"]);
+    let queue = node.get_topic("jobs").unwrap();
+    assert_eq!(queue.queue_state().0, 0);
+    assert!(queue.dead_letters().is_empty());
+    // Acked deliveries can no longer be reported failed
+    assert!(!node.ack("jobs", 1));
+}
+
+#[test]
+fn test_queue_client_round_trip() {
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let transport = Arc::new(TcpTransport::<String>::bind("client-node", any, &[]).unwrap());
+    let node = Arc::new(Node::<String>::new("client-node", &[]));
+    let handle = node.start(transport.clone());
+
+    let client = QueueClient::<String>::connect(transport.local_addr()).unwrap();
+    assert_eq!(client.dequeue("jobs").unwrap(), None);
+    assert!(client.stats("jobs").is_err());
+
+    client.enqueue("jobs", "one".to_string()).unwrap();
+    client.enqueue("jobs", "two".to_string()).unwrap();
+    let stats = client.stats("jobs").unwrap();
+    assert_eq!(stats.depth, 2);
+    assert_eq!(stats.node_id, "client-node");
+
+    let received: Vec<String> = client
+        .subscribe("jobs")
+        .take(2)
+        .map(|item| item.unwrap().to_string())
+        .collect();
+    assert_eq!(received, ["one", "two"]);
+    assert_eq!(node.topic("jobs").queue_state().0, 0);
+
+    handle.shutdown();
+}
+
+#[test]
+fn test_trace_context_travels_from_producer_to_consumer() {
+    use DistributedQueueMini::core::buildcore::{EventOp, TraceContext};
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let ta = Arc::new(TcpTransport::<String>::bind("trace-a", any, &[]).unwrap());
+    let tb = Arc::new(TcpTransport::<String>::bind("trace-b", any, &[]).unwrap());
+    ta.add_peer("trace-b", tb.local_addr());
+    tb.add_peer("trace-a", ta.local_addr());
+    let a = Arc::new(Node::<String>::new("trace-a", &["trace-b"]));
+    let b = Arc::new(Node::<String>::new("trace-b", &["trace-a"]));
+    let ha = a.start(ta.clone());
+    let hb = b.start(tb.clone());
+
+    let producer = QueueClient::<String>::connect(ta.local_addr()).unwrap();
+    let trace = TraceContext::new(0x0af7651916cd43dd8448eb211c80319c, 0xb7ad6b7169203331);
+    let (_, enqueued) = producer.enqueue_traced("jobs", "traced".to_string(), trace).unwrap();
+    assert_eq!(enqueued.trace_id, trace.trace_id);
+    producer.enqueue("jobs", "untraced".to_string()).unwrap();
+
+    // The replica's copy of the enqueue carries the trace
+    assert!(wait_for(|| b.topic("jobs").queue_state().0 == 2));
+    let replicated = b.topic("jobs").logs().into_iter().find(|entry| entry.event.item.as_deref().is_some_and(|item| item == "traced")).unwrap();
+    assert_eq!(replicated.event.trace, Some(enqueued));
+
+    // So does the item handed to the consumer, and the dequeue replicated to the other node
+    let consumer = QueueClient::<String>::connect(tb.local_addr()).unwrap();
+    let (item, consumed) = consumer.dequeue_traced("jobs").unwrap().unwrap();
+    assert_eq!(item.as_str(), "traced");
+    assert_eq!(consumed.map(|context| context.trace_id), Some(trace.trace_id));
+    assert!(wait_for(|| a.topic("jobs").logs().iter().any(|entry| matches!(entry.event.op, EventOp::Dequeue) && entry.event.trace == Some(enqueued))));
+    assert_eq!(consumer.dequeue_traced("jobs").unwrap().map(|(item, trace)| (item.to_string(), trace)), Some(("untraced".to_string(), None)));
+
+    ha.shutdown();
+    hb.shutdown();
+}
+
+#[test]
+fn test_queue_client_access_control() {
+    use DistributedQueueMini::core::buildcore::DistributedQueueSystem;
+    use DistributedQueueMini::engine::acl::{Acl, Permission};
+    use DistributedQueueMini::engine::network::{Envelope, Transport};
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let transport = Arc::new(TcpTransport::<String>::bind("acl-client-node", any, &[]).unwrap());
+    let acl = Acl::new()
+        .token("p-token", "producer")
+        .token("c-token", "consumer")
+        .grant("producer", "jobs", &[Permission::Produce])
+        .grant("consumer", "jobs", &[Permission::Consume]);
+    let node = Arc::new(Node::<String>::new("acl-client-node", &[]).with_acl(acl));
+    let handle = node.start(transport.clone());
+
+    let producer = QueueClient::<String>::connect(transport.local_addr()).unwrap().with_token("p-token");
+    let consumer = QueueClient::<String>::connect(transport.local_addr()).unwrap().with_token("c-token");
+    producer.enqueue("jobs", "work".to_string()).unwrap();
+    let denied = producer.dequeue("jobs").unwrap_err();
+    assert_eq!(denied.to_string(), "producer may not consume on topic jobs");
+    assert!(consumer.enqueue("jobs", "sneaky".to_string()).is_err());
+    assert_eq!(consumer.dequeue("jobs").unwrap().as_deref().map(String::as_str), Some("work"));
+    assert_eq!(node.audit_log().len(), 2);
+
+    // A connection that isn't a peer can't replicate around the checks
+    let intruder = TcpTransport::<String>::bind("acl-intruder", any, &[("acl-client-node", transport.local_addr())]).unwrap();
+    let forged = DistributedQueueSystem::new_with_nodes("acl-intruder".to_string(), &["acl-client-node"]).enqueue("forged".to_string()).unwrap();
+    intruder.send(NodeId::from("acl-client-node"), &Envelope::event(NodeId::from("acl-intruder"), "jobs", forged)).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(node.topic("jobs").queue_state().0, 0);
+
+    handle.shutdown();
+}
+
+#[test]
+fn test_replies_stay_on_the_connection_of_their_client() {
+    use std::io::{BufRead, BufReader, Write};
+    use DistributedQueueMini::engine::network::{Envelope, Message, Request};
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let transport = Arc::new(TcpTransport::<String>::bind("reply-node", any, &[]).unwrap());
+    let node = Arc::new(Node::<String>::new("reply-node", &[]));
+    let handle = node.start(transport.clone());
+    let client = QueueClient::<String>::connect(transport.local_addr()).unwrap();
+    client.enqueue("jobs", "mine".to_string()).unwrap();
+
+    // Another connection claiming to be the client is closed before its request is served
+    let impostor = std::net::TcpStream::connect(transport.local_addr()).unwrap();
+    let dequeue = Request::Dequeue { topic: "jobs".to_string(), partitions: None };
+    let envelope = Envelope::<String>::new(NodeId::intern(client.client_id()), Message::Request { id: 1, request: dequeue, token: None });
+    (&impostor).write_all(format!("{}\n", serde_json::to_string(&envelope).unwrap()).as_bytes()).unwrap();
+    assert_eq!(BufReader::new(&impostor).read_line(&mut String::new()).unwrap(), 0);
+    assert_eq!(client.dequeue("jobs").unwrap().as_deref().map(String::as_str), Some("mine"));
+
+    handle.shutdown();
+}
+
+#[test]
+#[cfg(feature = "server")]
+fn test_shell_commands() {
+    use DistributedQueueMini::engine::shell::Shell;
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let transport = Arc::new(TcpTransport::<serde_json::Value>::bind("shell-node", any, &[]).unwrap());
+    let node = Arc::new(Node::<serde_json::Value>::new("shell-node", &[]));
+    let handle = node.start(transport.clone());
+
+    let mut shell = Shell::connect(transport.local_addr()).unwrap();
+    assert_eq!(shell.execute("topic jobs").unwrap(), "");
+    assert_eq!(shell.topic(), "jobs");
+    assert_eq!(shell.execute("peek").unwrap(), "(empty)");
+    assert!(shell.execute("enqueue {\"n\": 1}").unwrap().starts_with("enqueued event"));
+    assert!(shell.execute("enqueue hello world").unwrap().starts_with("enqueued event"));
+    assert_eq!(shell.execute("peek").unwrap(), "{\"n\":1}");
+    assert_eq!(shell.execute("clock").unwrap(), "{shell-node:2}");
+    assert_eq!(shell.execute("pending").unwrap(), "0");
+    assert_eq!(shell.execute("dequeue").unwrap(), "{\"n\":1}");
+
+    let tail = shell.execute("logs tail 2").unwrap();
+    let lines: Vec<&str> = tail.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].contains("enqueue") && lines[0].ends_with("\"hello world\""));
+    assert!(lines[1].contains("dequeue"));
+
+    assert!(shell.execute("bogus").unwrap().starts_with("unknown command"));
+    assert!(shell.execute("quit").is_none());
+
+    handle.shutdown();
+}
+
+#[test]
+#[cfg(feature = "tui")]
+fn test_dashboard_refresh() {
+    use DistributedQueueMini::engine::dashboard::Dashboard;
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let transport = Arc::new(TcpTransport::<serde_json::Value>::bind("dash-node", any, &[]).unwrap());
+    let node = Arc::new(Node::<serde_json::Value>::new("dash-node", &[]));
+    let handle = node.start(transport.clone());
+    node.enqueue("a", serde_json::json!(1)).unwrap();
+    node.enqueue("b", serde_json::json!(2)).unwrap();
+    node.dequeue("b");
+
+    // Nothing listens on port 1, so the second node is reported unreachable
+    let addrs = [transport.local_addr().to_string(), "127.0.0.1:1".to_string()];
+    let nodes = Dashboard::new(&addrs).with_log_lines(2).refresh();
+    assert_eq!(nodes.len(), 2);
+    let topics: Vec<(&str, usize)> = nodes[0].topics.iter().map(|t| (t.topic.as_str(), t.stats.depth)).collect();
+    assert_eq!(topics, [("a", 1), ("b", 0)]);
+    assert_eq!(nodes[0].recent.len(), 2);
+    assert_eq!(nodes[0].recent[0].0, "b");
+    assert_eq!(nodes[0].recent[0].1.op, "dequeue");
+    assert!(nodes[1].error.is_some());
+
+    handle.shutdown();
+}
+
+#[cfg(feature = "tls")]
+fn tls_config(name: &str) -> DistributedQueueMini::engine::network::TlsConfig {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/certs");
+    DistributedQueueMini::engine::network::TlsConfig::from_pem_files(
+        format!("{}/{}.pem", dir, name),
+        format!("{}/{}.key", dir, name),
+        format!("{}/ca.pem", dir),
+    )
+    .unwrap()
+}
+
+#[test]
+#[cfg(feature = "tls")]
+fn test_tls_transport_replicates_and_rejects_untrusted_nodes() {
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let ta = Arc::new(TcpTransport::<String>::bind_tls("tls-a", any, &[], tls_config("tls-a")).unwrap());
+    let tb = Arc::new(TcpTransport::<String>::bind_tls("tls-b", any, &[], tls_config("tls-b")).unwrap());
+    ta.add_peer("tls-b", tb.local_addr());
+
+    let a = Arc::new(Node::<String>::new("tls-a", &["tls-b"]));
+    let b = Arc::new(Node::<String>::new("tls-b", &["tls-a"]));
+    let ha = a.start(ta.clone());
+    let hb = b.start(tb.clone());
+
+    a.enqueue("jobs", "secret".to_string()).unwrap();
+    assert!(wait_for(|| b.topic("jobs").queue_state().0 == 1));
+
+    // Clients need a certificate from the same CA too
+    let client = QueueClient::<String>::connect_tls(tb.local_addr(), "tls-b", &tls_config("tls-a")).unwrap();
+    assert_eq!(client.dequeue("jobs").unwrap().as_deref().map(String::as_str), Some("secret"));
+    assert!(QueueClient::<String>::connect(tb.local_addr()).and_then(|c| c.stats("jobs")).is_err());
+
+    // A node with a certificate from another CA can't get events in
+    let tr = Arc::new(TcpTransport::<String>::bind_tls("rogue", any, &[], tls_config("rogue")).unwrap());
+    tr.add_peer("tls-b", tb.local_addr());
+    let rogue = Arc::new(Node::<String>::new("rogue", &["tls-b"]));
+    rogue.attach(tr.clone());
+    rogue.enqueue("jobs", "forged".to_string()).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(b.topic("jobs").queue_state().0, 0);
+
+    // Nor can a trusted node claim to be another one: a's certificate doesn't name tls-c
+    let ti = Arc::new(TcpTransport::<String>::bind_tls("tls-c", any, &[], tls_config("tls-a")).unwrap());
+    ti.add_peer("tls-b", tb.local_addr());
+    let impostor = Arc::new(Node::<String>::new("tls-c", &["tls-b"]));
+    impostor.attach(ti.clone());
+    impostor.enqueue("jobs", "impersonated".to_string()).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(b.topic("jobs").queue_state().0, 0);
+
+    ha.shutdown();
+    hb.shutdown();
+}
+
+#[test]
+#[cfg(feature = "auth")]
+fn test_signed_events_reject_forgeries() {
+    use DistributedQueueMini::core::buildcore::{DistributedQueueSystem, NodeId};
+    use DistributedQueueMini::engine::network::{AuthError, Envelope, Keyring, Message};
+
+    let keyring = Keyring::new().with_key("auth-a", "a-secret").with_key("auth-b", "b-secret");
+    let b = Node::<String>::new("auth-b", &["auth-a"]).with_keyring(keyring.clone());
+    // Rejections are only counted on topics that exist, so forgeries can't create topics
+    b.topic("jobs");
+
+    // Stand-in for node a, producing events that are then signed or tampered with
+    let a = DistributedQueueSystem::new_with_nodes("auth-a".to_string(), &["auth-b"]);
+    let first = a.enqueue("one".to_string()).unwrap();
+    let second = a.enqueue("two".to_string()).unwrap();
+
+    let unsigned = Envelope::event(NodeId::intern("auth-a"), "jobs", first.clone());
+    assert_eq!(keyring.verify(&unsigned), Err(AuthError::MissingSignature));
+    assert!(!b.handle(unsigned));
+
+    // Signed with the wrong node's key
+    let impostor = Keyring::new().with_key("auth-a", "guess");
+    let mut forged = Envelope::event(NodeId::intern("auth-a"), "jobs", first.clone());
+    impostor.sign(&mut forged).unwrap();
+    assert!(!b.handle(forged));
+
+    // Valid signature, payload changed afterwards
+    let mut tampered = Envelope::event(NodeId::intern("auth-a"), "jobs", first.clone());
+    keyring.sign(&mut tampered).unwrap();
+    if let Message::Event { event, .. } = &mut tampered.message {
+        event.item = Some(Arc::new("evil".to_string()));
+    }
+    assert_eq!(keyring.verify(&tampered), Err(AuthError::BadSignature));
+    assert!(!b.handle(tampered));
+
+    for event in [first, second] {
+        let mut envelope = Envelope::event(NodeId::intern("auth-a"), "jobs", event);
+        keyring.sign(&mut envelope).unwrap();
+        assert!(b.handle(envelope));
+    }
+    let items: Vec<String> = b.topic("jobs").snapshot().items.iter().map(|i| i.to_string()).collect();
+    assert_eq!(items, ["one", "two"]);
+    #[cfg(feature = "metrics")]
+    assert_eq!(b.topic("jobs").metrics().remote_rejected, 3);
+}
+
+#[test]
+#[cfg(feature = "auth")]
+fn test_unsigned_peer_messages_are_rejected() {
+    use std::collections::HashMap;
+    use DistributedQueueMini::core::buildcore::NodeId;
+    use DistributedQueueMini::engine::network::{AuthError, Envelope, Keyring, Message};
+    use DistributedQueueMini::engine::raft::{RaftConfig, RaftMessage};
+
+    let keyring = Keyring::new().with_key("peer-a", "a-secret").with_key("peer-b", "b-secret");
+    let b = Node::<String>::new("peer-b", &[]).with_keyring(keyring.clone()).with_raft(&["peer-a", "peer-b"], RaftConfig::default());
+    let append = || Message::Raft { message: RaftMessage::Append { term: 1, prev_index: 0, prev_term: 0, entries: Vec::new(), commit: 0 } };
+
+    // A stranger claiming to lead the log
+    let unsigned = Envelope::new(NodeId::intern("peer-a"), append());
+    assert_eq!(keyring.verify(&unsigned), Err(AuthError::MissingSignature));
+    b.handle(unsigned);
+    assert_eq!(b.leader(), None);
+
+    let mut signed = Envelope::new(NodeId::intern("peer-a"), append());
+    keyring.sign(&mut signed).unwrap();
+    b.handle(signed);
+    assert_eq!(b.leader().as_deref(), Some("peer-a"));
+
+    // Nor are the other messages only peers send accepted unsigned
+    let unsigned = [
+        Message::<String>::Steal { queue: "jobs".to_string(), max: 1 },
+        Message::Heartbeat { queues: HashMap::new() },
+        Message::Backpressure { queue: "jobs".to_string(), engaged: true },
+        Message::Ack { channel: 1, seq: 1 },
+    ];
+    for message in unsigned {
+        assert_eq!(keyring.verify(&Envelope::new(NodeId::intern("peer-a"), message)), Err(AuthError::MissingSignature));
+    }
+}
+
+#[test]
+#[cfg(feature = "compress")]
+fn test_large_frames_are_compressed_when_negotiated() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use DistributedQueueMini::engine::network::{Compression, Envelope, Transport};
+    use DistributedQueueMini::core::buildcore::{DistributedQueueSystem, NodeId};
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let big = "x".repeat(64 * 1024);
+
+    // A raw peer that announces zstd and records the first frame it receives
+    let raw = TcpListener::bind(any).unwrap();
+    let raw_addr = raw.local_addr().unwrap();
+    let recorder = thread::spawn(move || {
+        let (socket, _) = raw.accept().unwrap();
+        let mut reader = BufReader::new(socket.try_clone().unwrap());
+        let mut hello = String::new();
+        reader.read_line(&mut hello).unwrap();
+        assert!(hello.starts_with(r#"{"hello""#));
+        (&socket).write_all(b"{\"hello\":{\"compression\":[\"zstd\"]}}\n").unwrap();
+        let mut frame = String::new();
+        reader.read_line(&mut frame).unwrap();
+        frame
+    });
+    let sender = TcpTransport::<String>::bind("zstd-a", any, &[("raw", raw_addr)])
+        .unwrap()
+        .with_compression(Compression::zstd());
+    let origin = DistributedQueueSystem::new("zstd-a".to_string());
+    let envelope = Envelope::event(NodeId::from("zstd-a"), "jobs", origin.enqueue(big.clone()).unwrap());
+    sender.send(NodeId::from("raw"), &envelope).unwrap();
+    let frame = recorder.join().unwrap();
+    assert!(frame.starts_with(r#"{"zstd""#));
+    assert!(frame.len() < big.len() / 10);
+
+    // Node to node, and to a node without compression, events arrive intact
+    let ta = Arc::new(TcpTransport::<String>::bind("zstd-b", any, &[]).unwrap().with_compression(Compression::zstd()));
+    let tb = Arc::new(TcpTransport::<String>::bind("zstd-c", any, &[]).unwrap().with_compression(Compression::zstd()));
+    let tc = Arc::new(TcpTransport::<String>::bind("zstd-d", any, &[]).unwrap());
+    ta.add_peer("zstd-c", tb.local_addr());
+    ta.add_peer("zstd-d", tc.local_addr());
+    let a = Arc::new(Node::<String>::new("zstd-b", &["zstd-c", "zstd-d"]));
+    let b = Arc::new(Node::<String>::new("zstd-c", &["zstd-b"]));
+    let c = Arc::new(Node::<String>::new("zstd-d", &["zstd-b"]));
+    let (ha, hb, hc) = (a.start(ta.clone()), b.start(tb.clone()), c.start(tc.clone()));
+    a.enqueue("jobs", big.clone()).unwrap();
+    assert!(wait_for(|| b.topic("jobs").peek().is_some_and(|item| *item == big)));
+    assert!(wait_for(|| c.topic("jobs").peek().is_some_and(|item| *item == big)));
+
+    // Clients negotiate too
+    let client = QueueClient::<String>::connect(ta.local_addr()).unwrap().with_compression(Compression::zstd()).unwrap();
+    assert!(client.is_compressed());
+    assert_eq!(client.log_tail("jobs", 1).unwrap()[0].item().map(|i| i.as_str()), Some(big.as_str()));
+    assert!(!QueueClient::<String>::connect(tc.local_addr()).unwrap().with_compression(Compression::zstd()).unwrap().is_compressed());
+
+    ha.shutdown();
+    hb.shutdown();
+    hc.shutdown();
+}
+
+#[test]
+fn test_oversized_items_are_sent_in_chunks() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use DistributedQueueMini::engine::network::{Envelope, Transport};
+    use DistributedQueueMini::core::buildcore::{DistributedQueueSystem, NodeId};
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    // Quotes and backslashes grow when escaped, multi-byte characters must not be split
+    let big = "\"é\\ ".repeat(5000);
+
+    // A raw peer sees only lines within the limit
+    let raw = TcpListener::bind(any).unwrap();
+    let raw_addr = raw.local_addr().unwrap();
+    let recorder = thread::spawn(move || {
+        let (socket, _) = raw.accept().unwrap();
+        // Skipping the hello sent when built with protobuf
+        BufReader::new(socket).lines().map(|line| line.unwrap()).filter(|line| !line.starts_with(r#"{"hello""#)).collect::<Vec<_>>()
+    });
+    let sender = TcpTransport::<String>::bind("chunk-a", any, &[("raw", raw_addr)]).unwrap().with_max_frame_len(1024);
+    let origin = DistributedQueueSystem::new("chunk-a".to_string());
+    sender.send(NodeId::from("raw"), &Envelope::event(NodeId::from("chunk-a"), "jobs", origin.enqueue(big.clone()).unwrap())).unwrap();
+    drop(sender);
+    let lines = recorder.join().unwrap();
+    assert!(lines.len() > 20);
+    assert!(lines.iter().all(|line| line.len() < 1024 && line.starts_with(r#"{"chunk""#)));
+
+    // Between nodes the item is reassembled before it is applied
+    let ta = Arc::new(TcpTransport::<String>::bind("chunk-b", any, &[]).unwrap().with_max_frame_len(1024));
+    let tb = Arc::new(TcpTransport::<String>::bind("chunk-c", any, &[]).unwrap().with_max_frame_len(1024));
+    ta.add_peer("chunk-c", tb.local_addr());
+    let a = Arc::new(Node::<String>::new("chunk-b", &["chunk-c"]));
+    let b = Arc::new(Node::<String>::new("chunk-c", &["chunk-b"]));
+    let (ha, hb) = (a.start(ta.clone()), b.start(tb.clone()));
+    a.enqueue("jobs", big.clone()).unwrap();
+    assert!(wait_for(|| b.topic("jobs").peek().is_some_and(|item| *item == big)));
+
+    // Clients chunk requests and reassemble replies
+    let client = QueueClient::<String>::connect(tb.local_addr()).unwrap().with_max_frame_len(1024);
+    client.enqueue("jobs", big.clone() + "!").unwrap();
+    assert_eq!(client.log_tail("jobs", 1).unwrap()[0].item().map(|i| i.as_str()), Some(format!("{}!", big).as_str()));
+
+    // A message failing its checksum is dropped whole, later ones still arrive
+    let td = TcpTransport::<String>::bind("chunk-d", any, &[]).unwrap().with_max_frame_len(1024);
+    let line = serde_json::to_string(&Envelope::event(NodeId::from("chunk-x"), "jobs", origin.enqueue("small".to_string()).unwrap())).unwrap();
+    let (head, tail) = line.split_at(line.len() / 2);
+    let mut raw = TcpStream::connect(td.local_addr()).unwrap();
+    for (index, data) in [head, tail].iter().enumerate() {
+        let chunk = format!(r#"{{"chunk":{{"id":1,"index":{},"count":2,"crc32":12345,"data":{}}}}}"#, index, serde_json::to_string(data).unwrap());
+        raw.write_all(format!("{}\n", chunk).as_bytes()).unwrap();
+    }
+    raw.write_all(format!("{}\n", line).as_bytes()).unwrap();
+    let received = td.recv_timeout(Duration::from_secs(2)).unwrap();
+    assert_eq!(received.from.as_str(), "chunk-x");
+    assert!(td.recv_timeout(Duration::from_millis(100)).is_none());
+
+    ha.shutdown();
+    hb.shutdown();
+}
+
+#[test]
+fn test_peers_announcing_frames_exchange_checksummed_frames() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use DistributedQueueMini::engine::network::{Envelope, FRAME_HEADER_LEN, FrameCodec, Transport};
+    use DistributedQueueMini::core::buildcore::{DistributedQueueSystem, NodeId};
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let codec = FrameCodec::new(1 << 20);
+    let origin = DistributedQueueSystem::new("frames-a".to_string());
+
+    // A raw peer announcing frames gets the envelope as one, behind the hello line
+    let raw = TcpListener::bind(any).unwrap();
+    let raw_addr = raw.local_addr().unwrap();
+    let recorder = thread::spawn(move || {
+        let (socket, _) = raw.accept().unwrap();
+        let mut reader = BufReader::new(socket.try_clone().unwrap());
+        let mut hello = String::new();
+        reader.read_line(&mut hello).unwrap();
+        assert!(hello.contains(r#""frames""#));
+        (&socket).write_all(b"{\"hello\":{\"version\":2,\"features\":[\"frames\"]}}\n").unwrap();
+        let mut frame = vec![0; FRAME_HEADER_LEN];
+        reader.read_exact(&mut frame).unwrap();
+        let len = u32::from_be_bytes(frame[..4].try_into().unwrap()) as usize;
+        frame.resize(FRAME_HEADER_LEN + len, 0);
+        reader.read_exact(&mut frame[FRAME_HEADER_LEN..]).unwrap();
+        frame
+    });
+    let sender = TcpTransport::<String>::bind("frames-a", any, &[("raw", raw_addr)]).unwrap();
+    let envelope = Envelope::event(NodeId::from("frames-a"), "jobs", origin.enqueue("framed".to_string()).unwrap());
+    sender.send(NodeId::from("raw"), &envelope).unwrap();
+    let frame = recorder.join().unwrap();
+    let payload = String::from_utf8(codec.decode_one(&frame).unwrap().to_vec()).unwrap();
+    assert!(payload.contains("framed"));
+
+    // A frame failing its checksum is skipped, the next one still arrives
+    let receiver = TcpTransport::<String>::bind("frames-b", any, &[]).unwrap();
+    let line = serde_json::to_vec(&Envelope::event(NodeId::from("frames-x"), "jobs", origin.enqueue("intact".to_string()).unwrap())).unwrap();
+    let mut corrupt = codec.encode(&line).unwrap();
+    *corrupt.last_mut().unwrap() ^= 1;
+    let mut raw = TcpStream::connect(receiver.local_addr()).unwrap();
+    raw.write_all(&corrupt).unwrap();
+    raw.write_all(&codec.encode(&line).unwrap()).unwrap();
+    let received = receiver.recv_timeout(Duration::from_secs(2)).unwrap();
+    assert_eq!(received.from.as_str(), "frames-x");
+    assert!(receiver.recv_timeout(Duration::from_millis(100)).is_none());
+}
+
+#[test]
+#[cfg(feature = "protobuf")]
+fn test_events_travel_as_protobuf_when_negotiated() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use base64::Engine;
+    use prost::Message as _;
+    use DistributedQueueMini::engine::network::{Envelope, Transport, wire};
+    use DistributedQueueMini::core::buildcore::{DistributedQueueSystem, NodeId};
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+
+    // A peer in another language sees a schema-versioned protobuf envelope
+    let raw = TcpListener::bind(any).unwrap();
+    let raw_addr = raw.local_addr().unwrap();
+    let recorder = thread::spawn(move || {
+        let (socket, _) = raw.accept().unwrap();
+        let mut reader = BufReader::new(socket.try_clone().unwrap());
+        let mut hello = String::new();
+        reader.read_line(&mut hello).unwrap();
+        assert!(hello.contains(r#""encodings":["protobuf"]"#));
+        (&socket).write_all(b"{\"hello\":{\"encodings\":[\"protobuf\"]}}\n").unwrap();
+        let mut frame = String::new();
+        reader.read_line(&mut frame).unwrap();
+        frame
+    });
+    let sender = TcpTransport::<String>::bind("pb-a", any, &[("raw", raw_addr)]).unwrap();
+    let origin = DistributedQueueSystem::new("pb-a".to_string());
+    sender.send(NodeId::from("raw"), &Envelope::event(NodeId::from("pb-a"), "jobs", origin.enqueue("job".to_string()).unwrap())).unwrap();
+    let frame: serde_json::Value = serde_json::from_str(&recorder.join().unwrap()).unwrap();
+    let bytes = base64::engine::general_purpose::STANDARD.decode(frame["pb"].as_str().unwrap()).unwrap();
+    let envelope = wire::proto::Envelope::decode(bytes.as_slice()).unwrap();
+    assert_eq!(envelope.version, wire::VERSION);
+    assert_eq!(envelope.from, "pb-a");
+    let Some(wire::proto::envelope::Message::Event(topic_event)) = envelope.message else {
+        panic!("expected an event");
+    };
+    assert_eq!(topic_event.topic, "jobs");
+    let event = topic_event.event.unwrap();
+    assert_eq!(event.item.as_deref(), Some(br#""job""#.as_slice()));
+    assert_eq!(event.clock.unwrap().entries["pb-a"], 1);
+
+    // Node to node, events and client requests both arrive intact
+    let ta = Arc::new(TcpTransport::<Vec<u32>>::bind("pb-b", any, &[]).unwrap());
+    let tb = Arc::new(TcpTransport::<Vec<u32>>::bind("pb-c", any, &[]).unwrap());
+    ta.add_peer("pb-c", tb.local_addr());
+    let a = Arc::new(Node::<Vec<u32>>::new("pb-b", &["pb-c"]));
+    let b = Arc::new(Node::<Vec<u32>>::new("pb-c", &["pb-b"]));
+    let (ha, hb) = (a.start(ta.clone()), b.start(tb.clone()));
+    a.enqueue("jobs", vec![1, 2, 3]).unwrap();
+    assert!(wait_for(|| b.topic("jobs").peek().is_some_and(|item| *item == [1, 2, 3])));
+    let client = QueueClient::<Vec<u32>>::connect(tb.local_addr()).unwrap();
+    assert_eq!(client.dequeue("jobs").unwrap().as_deref(), Some(&vec![1, 2, 3]));
+
+    ha.shutdown();
+    hb.shutdown();
+}
+
+#[test]
+fn test_protocol_versions_are_negotiated() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use DistributedQueueMini::engine::network::{Envelope, Hello, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, Transport};
+    use DistributedQueueMini::core::buildcore::{DistributedQueueSystem, NodeId};
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let origin = DistributedQueueSystem::new("ver-a".to_string());
+    let event = Envelope::event(NodeId::from("ver-a"), "jobs", origin.enqueue("job".to_string()).unwrap());
+
+    // Raw peers: one predating versioning, one that only speaks a future version
+    let peer = |reply: &'static str| {
+        let listener = TcpListener::bind(any).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(socket.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let hello: serde_json::Value = serde_json::from_str(&line).unwrap();
+            (&socket).write_all(reply.as_bytes()).unwrap();
+            // Keep the connection open until the sender is done
+            reader.read_line(&mut String::new()).ok();
+            hello["hello"].clone()
+        });
+        (addr, handle)
+    };
+    let (old_addr, old) = peer("{\"hello\":{\"compression\":[]}}\n");
+    let (new_addr, new) = peer("{\"hello\":{\"version\":9,\"min_version\":9}}\n");
+    let sender = TcpTransport::<String>::bind("ver-a", any, &[("old", old_addr), ("new", new_addr)]).unwrap();
+    sender.send(NodeId::from("old"), &event).unwrap();
+    assert_eq!(sender.protocol_version("old"), Some(1));
+    let refused = sender.send(NodeId::from("new"), &event).unwrap_err();
+    assert!(refused.to_string().contains("no common protocol version"), "{}", refused);
+    assert_eq!(sender.protocol_version("new"), None);
+    drop(sender);
+    let announced = old.join().unwrap();
+    assert_eq!(announced["version"], PROTOCOL_VERSION);
+    assert_eq!(announced["min_version"], MIN_PROTOCOL_VERSION);
+    new.join().unwrap();
+
+    // Nodes of this version agree on it
+    let ta = TcpTransport::<String>::bind("ver-b", any, &[]).unwrap();
+    let tb = TcpTransport::<String>::bind("ver-c", any, &[]).unwrap();
+    ta.add_peer("ver-c", tb.local_addr());
+    let event = Envelope::event(NodeId::from("ver-b"), "jobs", origin.enqueue("job".to_string()).unwrap());
+    ta.send(NodeId::from("ver-c"), &event).unwrap();
+    assert_eq!(ta.protocol_version("ver-c"), Some(PROTOCOL_VERSION));
+    assert_eq!(tb.recv_timeout(Duration::from_secs(2)).unwrap().from.as_str(), "ver-b");
+    assert!(wait_for(|| tb.protocol_version("ver-b") == Some(PROTOCOL_VERSION)));
+
+    // A node hears out an incompatible hello, answers with its versions, then hangs up
+    let mut raw = TcpStream::connect(tb.local_addr()).unwrap();
+    raw.write_all(b"{\"hello\":{\"version\":9,\"min_version\":9}}\n").unwrap();
+    let mut reply = String::new();
+    raw.read_to_string(&mut reply).unwrap();
+    let hello: Hello = serde_json::from_str::<serde_json::Value>(&reply)
+        .map(|line| serde_json::from_value(line["hello"].clone()).unwrap())
+        .unwrap();
+    assert_eq!(hello.version, PROTOCOL_VERSION);
+}
+
+#[test]
+fn test_partitioned_topics_keep_per_key_order() {
+    use DistributedQueueMini::engine::partition::{assign_partitions, partition_for};
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let transport = Arc::new(TcpTransport::<String>::bind("part-node", any, &[]).unwrap());
+    let node = Arc::new(Node::<String>::new("part-node", &[]).with_partitions("orders", 4));
+    let handle = node.start(transport.clone());
+    let client = QueueClient::<String>::connect(transport.local_addr()).unwrap();
+
+    let keys = ["alice", "bob", "carol", "dave", "erin"];
+    for i in 0..4 {
+        for key in keys {
+            client.enqueue_keyed("orders", key, format!("{}-{}", key, i)).unwrap();
+        }
+    }
+    // Each key lives in one partition, in enqueue order
+    for key in keys {
+        let queue = node.partition("orders", partition_for(key, 4)).unwrap();
+        let items: Vec<String> = queue.snapshot().items.iter().map(|item| item.to_string()).filter(|item| item.starts_with(key)).collect();
+        assert_eq!(items, (0..4).map(|i| format!("{}-{}", key, i)).collect::<Vec<_>>());
+    }
+    assert!(node.partition("orders", 4).is_none());
+
+    // Two consumers split the partitions and together drain every item, each key in order
+    let assignment = assign_partitions(4, &["c1", "c2", "c1"]);
+    assert_eq!(assignment.len(), 2);
+    assert_eq!(assignment["c1"], [0, 2]);
+    assert_eq!(assignment["c2"], [1, 3]);
+    let mut received: Vec<String> = Vec::new();
+    for consumer in ["c1", "c2"] {
+        while let Some(item) = client.dequeue_partitions("orders", &assignment[consumer]).unwrap() {
+            received.push(item.to_string());
+        }
+    }
+    assert_eq!(received.len(), 20);
+    for key in keys {
+        let order: Vec<&String> = received.iter().filter(|item| item.starts_with(key)).collect();
+        let expected: Vec<String> = (0..4).map(|i| format!("{}-{}", key, i)).collect();
+        assert_eq!(order, expected.iter().collect::<Vec<_>>());
+    }
+
+    // Unkeyed items are spread round-robin and a plain dequeue reaches every partition
+    for i in 0..4 {
+        client.enqueue("orders", format!("any-{}", i)).unwrap();
+    }
+    assert!((0..4).all(|p| node.partition("orders", p).unwrap().queue_state().0 == 1));
+    for _ in 0..4 {
+        assert!(client.dequeue("orders").unwrap().is_some());
+    }
+    assert_eq!(client.dequeue("orders").unwrap(), None);
+
+    handle.shutdown();
+}
+
+#[test]
+fn test_placement_ring_arbitrates_dequeues() {
+    use DistributedQueueMini::engine::placement::HashRing;
+
+    // Membership changes only move the keys of the member that came or went
+    let keys: Vec<String> = (0..1000).map(|i| format!("key-{}", i)).collect();
+    let mut ring = HashRing::with_members(&["N0", "N1", "N2"], 64);
+    let before: Vec<String> = keys.iter().map(|k| ring.owner(k).unwrap().to_string()).collect();
+    assert!(["N0", "N1", "N2"].iter().all(|m| before.iter().filter(|o| o == m).count() > 200));
+    assert!(ring.remove_member("N2"));
+    for (key, owner) in keys.iter().zip(&before) {
+        if owner != "N2" {
+            assert_eq!(ring.owner(key), Some(owner.as_str()));
+        }
+    }
+    assert!(ring.add_member("N2") && !ring.add_member("N2"));
+    let rejoined: Vec<String> = keys.iter().map(|k| ring.owner(k).unwrap().to_string()).collect();
+    assert_eq!(rejoined, before);
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let ta = Arc::new(TcpTransport::<String>::bind("ring-a", any, &[]).unwrap());
+    let tb = Arc::new(TcpTransport::<String>::bind("ring-b", any, &[]).unwrap());
+    ta.add_peer("ring-b", tb.local_addr());
+    tb.add_peer("ring-a", ta.local_addr());
+    let a = Arc::new(Node::<String>::new("ring-a", &["ring-b"]).with_placement(HashRing::default()).with_partitions("orders", 8));
+    let b = Arc::new(Node::<String>::new("ring-b", &["ring-a"]).with_placement(HashRing::default()).with_partitions("orders", 8));
+    let ha = a.start(ta.clone());
+    let hb = b.start(tb.clone());
+
+    // Both nodes agree on owners, and every partition has exactly one
+    let topic = (0..20).map(|i| format!("jobs-{}", i)).find(|t| a.owner(t).as_deref() == Some("ring-b")).unwrap();
+    assert_eq!(b.owner(&topic).as_deref(), Some("ring-b"));
+    let (mut owned_a, owned_b) = (a.owned_partitions("orders"), b.owned_partitions("orders"));
+    owned_a.extend(&owned_b);
+    owned_a.sort();
+    assert_eq!(owned_a, (0..8).collect::<Vec<_>>());
+
+    a.enqueue(&topic, "x".to_string()).unwrap();
+    assert!(wait_for(|| b.get_topic(&topic).is_some_and(|q| q.queue_state().0 == 1)));
+    let client_a = QueueClient::<String>::connect(ta.local_addr()).unwrap();
+    let client_b = QueueClient::<String>::connect(tb.local_addr()).unwrap();
+    let refused = client_a.dequeue(&topic).unwrap_err();
+    assert!(refused.to_string().contains("owned by ring-b"), "{}", refused);
+    assert_eq!(client_b.dequeue(&topic).unwrap().as_deref().map(String::as_str), Some("x"));
+
+    // Once the owner leaves, the remaining node takes its queues over
+    assert!(a.remove_member("ring-b"));
+    a.enqueue(&topic, "y".to_string()).unwrap();
+    assert_eq!(a.owned_partitions("orders").len(), 8);
+    assert!(wait_for(|| a.topic(&topic).queue_state().0 == 1));
+    assert_eq!(client_a.dequeue(&topic).unwrap().as_deref().map(String::as_str), Some("y"));
+
+    ha.shutdown();
+    hb.shutdown();
+}
+
+#[test]
+fn test_idle_nodes_steal_work_from_owners() {
+    use DistributedQueueMini::core::buildcore::EventOp;
+    use DistributedQueueMini::engine::network::{Envelope, Message, Transport};
+    use DistributedQueueMini::engine::placement::{HashRing, StealPolicy};
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let ta = Arc::new(TcpTransport::<String>::bind("steal-a", any, &[]).unwrap());
+    let tb = Arc::new(TcpTransport::<String>::bind("steal-b", any, &[]).unwrap());
+    ta.add_peer("steal-b", tb.local_addr());
+    tb.add_peer("steal-a", ta.local_addr());
+    let policy = StealPolicy { min_depth: 4, batch: 5 };
+    let node = |id: &str, peer: &str| Node::<String>::new(id, &[peer]).with_placement(HashRing::default()).with_work_stealing(policy);
+    let a = Arc::new(node("steal-a", "steal-b"));
+    let b = Arc::new(node("steal-b", "steal-a"));
+    let ha = a.start(ta.clone());
+    let hb = b.start(tb.clone());
+
+    let topic = (0..20).map(|i| format!("jobs-{}", i)).find(|t| a.owner(t).as_deref() == Some("steal-b")).unwrap();
+    for i in 0..12 {
+        b.enqueue(&topic, format!("job-{}", i)).unwrap();
+    }
+    assert!(wait_for(|| a.get_topic(&topic).is_some_and(|q| q.queue_state().0 == 12)));
+
+    // A node that isn't a peer gets nothing
+    let intruder = TcpTransport::<String>::bind("steal-intruder", any, &[("steal-b", tb.local_addr())]).unwrap();
+    let steal = Envelope::new(NodeId::intern("steal-intruder"), Message::Steal { queue: topic.clone(), max: 5 });
+    intruder.send(NodeId::intern("steal-b"), &steal).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(b.topic(&topic).queue_state().0, 12);
+
+    // The idle node has nothing of its own, so it asks the owner for a batch
+    let client_a = QueueClient::<String>::connect(ta.local_addr()).unwrap();
+    assert!(client_a.dequeue(&topic).is_err());
+    assert!(wait_for(|| a.topic(&topic).transferred_len() == 5));
+    let stolen: Vec<String> = (0..5).map(|_| client_a.dequeue(&topic).unwrap().unwrap().to_string()).collect();
+    assert_eq!(stolen, (0..5).map(|i| format!("job-{}", i)).collect::<Vec<_>>());
+    assert_eq!(b.topic(&topic).queue_state().0, 7);
+    assert_eq!(b.dequeue(&topic).0.as_deref().map(String::as_str), Some("job-5"));
+
+    // Both logs record the transfers to the idle node
+    for node in [&a, &b] {
+        let transfers: Vec<_> = node.topic(&topic).logs().into_iter().filter(|e| e.op == "transfer").collect();
+        assert_eq!(transfers.len(), 5);
+        assert!(transfers.iter().all(|e| matches!(e.event.op, EventOp::Transfer { to } if to.as_str() == "steal-a")));
+    }
+
+    ha.shutdown();
+    hb.shutdown();
+}
+
+/// Entries of a fake Redis stream: ID and fields
+#[cfg(feature = "redis")]
+type Stream = Arc<std::sync::Mutex<Vec<(u64, Vec<String>)>>>;
+
+/// In-memory stand-in for a Redis server, with just the stream commands the bridge uses
+#[cfg(feature = "redis")]
+fn fake_redis() -> (std::net::SocketAddr, Stream) {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    fn bulk(text: &str) -> String {
+        format!("${}\r\n{}\r\n", text.len(), text)
+    }
+    fn entries(entries: &[(u64, Vec<String>)]) -> String {
+        let mut reply = format!("*{}\r\n", entries.len());
+        for (id, fields) in entries {
+            reply += &format!("*2\r\n{}*{}\r\n", bulk(&format!("{}-0", id)), fields.len());
+            reply += &fields.iter().map(|field| bulk(field)).collect::<String>();
+        }
+        reply
+    }
+    fn serve(stream: std::net::TcpStream, entries_of: Stream) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            let count: usize = line.trim()[1..].parse().unwrap();
+            let mut args = Vec::new();
+            for _ in 0..count {
+                let mut len = String::new();
+                reader.read_line(&mut len).unwrap();
+                let mut arg = vec![0; len.trim()[1..].parse::<usize>().unwrap() + 2];
+                reader.read_exact(&mut arg).unwrap();
+                args.push(String::from_utf8(arg[..arg.len() - 2].to_vec()).unwrap());
+            }
+            let reply = match args[0].as_str() {
+                "XADD" => {
+                    let mut stream = entries_of.lock().unwrap();
+                    let id = stream.len() as u64 + 1;
+                    stream.push((id, args[3..].to_vec()));
+                    bulk(&format!("{}-0", id))
+                }
+                "XREVRANGE" => entries(&entries_of.lock().unwrap().last().cloned().into_iter().collect::<Vec<_>>()),
+                "XREAD" => {
+                    let after: u64 = args[7].split('-').next().unwrap().parse().unwrap();
+                    thread::sleep(Duration::from_millis(20));
+                    let newer: Vec<_> = entries_of.lock().unwrap().iter().filter(|(id, _)| *id > after).cloned().collect();
+                    match newer.is_empty() {
+                        true => "*-1\r\n".to_string(),
+                        false => format!("*1\r\n*2\r\n{}{}", bulk(&args[6]), entries(&newer)),
+                    }
+                }
+                other => format!("-ERR unknown command {}\r\n", other),
+            };
+            writer.write_all(reply.as_bytes()).unwrap();
+        }
+    }
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let stream: Stream = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let shared = stream.clone();
+    thread::spawn(move || {
+        for connection in listener.incoming() {
+            let shared = shared.clone();
+            thread::spawn(move || serve(connection.unwrap(), shared));
+        }
+    });
+    (addr, stream)
+}
+
+#[test]
+#[cfg(feature = "redis")]
+fn test_redis_bridge_mirrors_and_ingests() {
+    use DistributedQueueMini::core::buildcore::ControlOp;
+    use DistributedQueueMini::engine::redis::{RedisBridge, RedisStream};
+
+    let (addr, stream) = fake_redis();
+    let node = Arc::new(Node::<serde_json::Value>::new("redis-node", &["redis-peer"]));
+    let mirror = RedisBridge::mirror(node.clone(), "orders", RedisStream::new(addr.to_string(), "orders-events")).unwrap();
+    node.enqueue("orders", serde_json::json!({ "id": 1 })).unwrap();
+    // Replicated enqueues are mirrored by the node that made them
+    let peer = DistributedQueueMini::core::buildcore::DistributedQueueSystem::new("redis-peer".to_string());
+    node.topic("orders").apply_remote_event(peer.enqueue(serde_json::json!({ "id": 2 })).unwrap());
+    assert!(wait_for(|| stream.lock().unwrap().len() == 1));
+    let fields = stream.lock().unwrap()[0].1.clone();
+    assert_eq!(fields[..4], ["topic", "orders", "origin", "redis-node"]);
+    assert_eq!(fields[8..], ["item", r#"{"id":1}"#]);
+
+    // Entries the bridge mirrored are skipped, others enqueued: JSON as is, anything else as a string
+    let ingest = RedisBridge::ingest(node.clone(), "inbox", RedisStream::new(addr.to_string(), "orders-events"), "0").unwrap();
+    for item in [r#"{"id":3}"#, "plain"] {
+        let mut entries = stream.lock().unwrap();
+        let id = entries.len() as u64 + 1;
+        entries.push((id, vec!["item".to_string(), item.to_string()]));
+    }
+    assert!(wait_for(|| node.topic("inbox").queue_state().0 == 2));
+    assert_eq!(node.dequeue("inbox").0.as_deref(), Some(&serde_json::json!({ "id": 3 })));
+    assert_eq!(node.dequeue("inbox").0.as_deref(), Some(&serde_json::json!("plain")));
+
+    // Entries without an item are skipped; ones the topic refuses for now are read again
+    node.control("inbox", ControlOp::Paused);
+    for fields in [["other", "x"], ["item", "later"]] {
+        let mut entries = stream.lock().unwrap();
+        let id = entries.len() as u64 + 1;
+        entries.push((id, fields.map(str::to_string).to_vec()));
+    }
+    assert!(wait_for(|| ingest.errors().count >= 3));
+    assert_eq!(node.topic("inbox").queue_state().0, 0);
+    node.control("inbox", ControlOp::Resumed);
+    assert!(wait_for(|| node.topic("inbox").queue_state().0 == 1));
+    assert_eq!(node.dequeue("inbox").0.as_deref(), Some(&serde_json::json!("later")));
+
+    mirror.shutdown();
+    ingest.shutdown();
+}
+
+/// Messages published to a fake NATS server, and the connections subscribed to it
+#[cfg(feature = "nats")]
+#[derive(Default)]
+struct FakeNats {
+    published: Vec<(String, String)>,
+    subscribers: Vec<std::net::TcpStream>,
+}
+
+/// In-memory stand-in for a NATS server; `send` delivers a message to every subscriber
+#[cfg(feature = "nats")]
+fn fake_nats() -> (std::net::SocketAddr, Arc<std::sync::Mutex<FakeNats>>) {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    fn serve(mut stream: std::net::TcpStream, state: Arc<std::sync::Mutex<FakeNats>>) {
+        stream.write_all(b"INFO {\"server_id\":\"fake\",\"max_payload\":1048576}\r\n").unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts.first().copied() {
+                Some("PING") => stream.write_all(b"PONG\r\n").unwrap(),
+                Some("SUB") => state.lock().unwrap().subscribers.push(stream.try_clone().unwrap()),
+                Some("PUB") => {
+                    let mut payload = vec![0; parts[2].parse::<usize>().unwrap() + 2];
+                    reader.read_exact(&mut payload).unwrap();
+                    let payload = String::from_utf8(payload[..payload.len() - 2].to_vec()).unwrap();
+                    state.lock().unwrap().published.push((parts[1].to_string(), payload));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let state = Arc::new(std::sync::Mutex::new(FakeNats::default()));
+    let shared = state.clone();
+    thread::spawn(move || {
+        for connection in listener.incoming() {
+            let shared = shared.clone();
+            thread::spawn(move || serve(connection.unwrap(), shared));
+        }
+    });
+    (addr, state)
+}
+
+#[test]
+#[cfg(feature = "nats")]
+fn test_nats_connector_publishes_and_subscribes() {
+    use std::io::Write;
+    use DistributedQueueMini::core::buildcore::ControlOp;
+    use DistributedQueueMini::engine::nats::{NatsConnector, NatsServer};
+
+    let (addr, nats) = fake_nats();
+    let server = NatsServer::new(addr.to_string());
+    let node = Arc::new(Node::<serde_json::Value>::new("nats-node", &["nats-peer"]));
+    let publisher = NatsConnector::publish(node.clone(), "orders", &server, "orders.events").unwrap();
+    node.enqueue("orders", serde_json::json!({ "id": 1 })).unwrap();
+    node.dequeue("orders");
+    assert!(wait_for(|| nats.lock().unwrap().published.len() == 2));
+    let published = nats.lock().unwrap().published.clone();
+    assert_eq!(published[0].0, "orders.events.enqueue");
+    assert_eq!(published[1].0, "orders.events.dequeue");
+    let event: serde_json::Value = serde_json::from_str(&published[0].1).unwrap();
+    assert_eq!(event["item"], serde_json::json!({ "id": 1 }));
+
+    // Messages are enqueued, JSON as is, anything else as a string; JetStream ones get acked
+    let subscriber = NatsConnector::subscribe(node.clone(), "payments", &server, "payments.in", Some("queued")).unwrap();
+    assert!(wait_for(|| nats.lock().unwrap().subscribers.len() == 1));
+    let mut connection = nats.lock().unwrap().subscribers[0].try_clone().unwrap();
+    connection.write_all(b"MSG payments.in 1 8\r\n{\"id\":2}\r\n").unwrap();
+    connection.write_all(b"MSG payments.in 1 $JS.ACK.payments.c.1.2.2.0.0 5\r\nplain\r\n").unwrap();
+    assert!(wait_for(|| node.topic("payments").queue_state().0 == 2));
+    assert_eq!(node.dequeue("payments").0.as_deref(), Some(&serde_json::json!({ "id": 2 })));
+    assert_eq!(node.dequeue("payments").0.as_deref(), Some(&serde_json::json!("plain")));
+    assert!(wait_for(|| nats.lock().unwrap().published.iter().any(|(subject, _)| subject == "$JS.ACK.payments.c.1.2.2.0.0")));
+
+    // JetStream is told to redeliver what the topic refuses for now; every failure is kept
+    let published = |subject: &str, ack: &str| nats.lock().unwrap().published.contains(&(subject.to_string(), ack.to_string()));
+    node.control("payments", ControlOp::Paused);
+    connection.write_all(b"MSG payments.in 1 $JS.ACK.payments.c.1.3.3.0.0 5\r\nlater\r\n").unwrap();
+    connection.write_all(b"MSG payments.in 1 5\r\nplain\r\n").unwrap();
+    assert!(wait_for(|| published("$JS.ACK.payments.c.1.3.3.0.0", "-NAK")));
+    assert!(wait_for(|| subscriber.errors().count == 2));
+    node.control("payments", ControlOp::Resumed);
+
+    // What would never be enqueued is terminated rather than redelivered forever
+    let counts = Arc::new(Node::<u64>::new("nats-counts", &[]));
+    let terminating = NatsConnector::subscribe(counts.clone(), "counts", &server, "counts.in", None).unwrap();
+    assert!(wait_for(|| nats.lock().unwrap().subscribers.len() == 2));
+    let mut connection = nats.lock().unwrap().subscribers[1].try_clone().unwrap();
+    connection.write_all(b"MSG counts.in 1 $JS.ACK.counts.c.1.1.1.0.0 3\r\nabc\r\n").unwrap();
+    assert!(wait_for(|| published("$JS.ACK.counts.c.1.1.1.0.0", "+TERM")));
+    assert!(terminating.errors().last.is_some_and(|last| last.contains("not a valid item")));
+
+    publisher.shutdown();
+    subscriber.shutdown();
+    terminating.shutdown();
+}
+
+/// Packets a fake MQTT broker received after the subscription, and the subscribed connection
+#[cfg(feature = "mqtt")]
+#[derive(Default)]
+struct FakeMqtt {
+    client_id: String,
+    packets: Vec<(u8, Vec<u8>)>,
+    subscriber: Option<std::net::TcpStream>,
+}
+
+/// In-memory stand-in for an MQTT broker, accepting any connection and subscription
+#[cfg(feature = "mqtt")]
+fn fake_mqtt() -> (std::net::SocketAddr, Arc<std::sync::Mutex<FakeMqtt>>) {
+    use std::io::{Read, Write};
+
+    fn read_packet(stream: &mut std::net::TcpStream) -> Option<(u8, Vec<u8>)> {
+        let mut header = [0];
+        stream.read_exact(&mut header).ok()?;
+        let (mut len, mut shift) = (0, 0);
+        loop {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).ok()?;
+            len |= ((byte[0] & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).ok()?;
+        Some((header[0], body))
+    }
+    fn serve(mut stream: std::net::TcpStream, state: Arc<std::sync::Mutex<FakeMqtt>>) {
+        let (_, connect) = read_packet(&mut stream).unwrap();
+        // Protocol name, level, flags and keep alive take 10 bytes, then the client ID
+        let id_len = u16::from_be_bytes([connect[10], connect[11]]) as usize;
+        state.lock().unwrap().client_id = String::from_utf8(connect[12..12 + id_len].to_vec()).unwrap();
+        stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+        let (_, subscribe) = read_packet(&mut stream).unwrap();
+        let granted = subscribe[subscribe.len() - 1];
+        stream.write_all(&[0x90, 3, subscribe[0], subscribe[1], granted]).unwrap();
+        state.lock().unwrap().subscriber = Some(stream.try_clone().unwrap());
+        while let Some(packet) = read_packet(&mut stream) {
+            state.lock().unwrap().packets.push(packet);
+        }
+    }
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let state = Arc::new(std::sync::Mutex::new(FakeMqtt::default()));
+    let shared = state.clone();
+    thread::spawn(move || {
+        for connection in listener.incoming() {
+            let shared = shared.clone();
+            thread::spawn(move || serve(connection.unwrap(), shared));
+        }
+    });
+    (addr, state)
+}
+
+#[test]
+#[cfg(feature = "mqtt")]
+fn test_mqtt_bridge_maps_qos_to_delivery_guarantees() {
+    use std::io::Write;
+    use DistributedQueueMini::engine::mqtt::{MqttBridge, MqttBroker, QoS};
+
+    let (addr, mqtt) = fake_mqtt();
+    let node = Arc::new(Node::<serde_json::Value>::new("mqtt-node", &["mqtt-peer"]));
+    let bridge = MqttBridge::ingest(node.clone(), "temperatures", &MqttBroker::new(addr.to_string()), "sensors/+/temperature", QoS::ExactlyOnce).unwrap();
+    assert!(wait_for(|| mqtt.lock().unwrap().subscriber.is_some()));
+    assert_eq!(mqtt.lock().unwrap().client_id, "mqtt-node-temperatures");
+    let mut broker = mqtt.lock().unwrap().subscriber.take().unwrap();
+    let publish = |qos: u8, id: u16, payload: &str| {
+        let name = b"sensors/a/temperature";
+        let mut body = (name.len() as u16).to_be_bytes().to_vec();
+        body.extend_from_slice(name);
+        if qos > 0 {
+            body.extend_from_slice(&id.to_be_bytes());
+        }
+        body.extend_from_slice(payload.as_bytes());
+        let mut packet = vec![0x30 | qos << 1, body.len() as u8];
+        packet.extend(body);
+        packet
+    };
+
+    // QoS 0 is not acked, QoS 1 is acked once enqueued
+    broker.write_all(&publish(0, 0, "21.5")).unwrap();
+    broker.write_all(&publish(1, 7, r#"{"sensor":"a","celsius":22}"#)).unwrap();
+    assert!(wait_for(|| mqtt.lock().unwrap().packets.contains(&(0x40, vec![0, 7]))));
+    // QoS 2 is enqueued once even if the broker sends it again before getting the PUBREC
+    broker.write_all(&publish(2, 8, "hot")).unwrap();
+    broker.write_all(&publish(2, 8, "hot")).unwrap();
+    assert!(wait_for(|| mqtt.lock().unwrap().packets.iter().filter(|packet| **packet == (0x50, vec![0, 8])).count() == 2));
+    broker.write_all(&[0x62, 2, 0, 8]).unwrap();
+    assert!(wait_for(|| mqtt.lock().unwrap().packets.contains(&(0x70, vec![0, 8]))));
+
+    assert_eq!(node.topic("temperatures").queue_state().0, 3);
+    assert_eq!(node.dequeue("temperatures").0.as_deref(), Some(&serde_json::json!(21.5)));
+    assert_eq!(node.dequeue("temperatures").0.as_deref(), Some(&serde_json::json!({ "sensor": "a", "celsius": 22 })));
+    assert_eq!(node.dequeue("temperatures").0.as_deref(), Some(&serde_json::json!("hot")));
+    bridge.shutdown();
+
+    // A message that is never going to be enqueued is acked rather than delivered forever
+    let (addr, mqtt) = fake_mqtt();
+    let counts = Arc::new(Node::<u64>::new("mqtt-counts", &[]));
+    let bridge = MqttBridge::ingest(counts.clone(), "counts", &MqttBroker::new(addr.to_string()), "sensors/+/temperature", QoS::AtLeastOnce).unwrap();
+    assert!(wait_for(|| mqtt.lock().unwrap().subscriber.is_some()));
+    let mut broker = mqtt.lock().unwrap().subscriber.take().unwrap();
+    broker.write_all(&publish(1, 9, "not a count")).unwrap();
+    assert!(wait_for(|| mqtt.lock().unwrap().packets.contains(&(0x40, vec![0, 9]))));
+    let errors = bridge.errors();
+    assert_eq!(errors.count, 1);
+    assert!(errors.last.is_some_and(|last| last.contains("not a valid item")));
+    assert_eq!(counts.topic("counts").queue_state().0, 0);
+    bridge.shutdown();
+}
+
+#[test]
+fn test_reliable_channel_retransmits_and_releases_events_in_order() {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use DistributedQueueMini::core::buildcore::Event;
+    use DistributedQueueMini::engine::network::{Envelope, Message, ReliableTransport, Transport};
+
+    /// In-memory transport that loses the event sends whose number is in `lose`
+    struct Lossy {
+        id: NodeId,
+        links: Arc<Mutex<HashMap<NodeId, Sender<Envelope<String>>>>>,
+        inbox: Mutex<Receiver<Envelope<String>>>,
+        events_sent: AtomicUsize,
+        lose: Vec<usize>,
+    }
+    impl Transport<String> for Lossy {
+        fn local_id(&self) -> NodeId {
+            self.id
+        }
+        fn peers(&self) -> Vec<NodeId> {
+            self.links.lock().unwrap().keys().copied().filter(|&peer| peer != self.id).collect()
+        }
+        fn send(&self, peer: NodeId, envelope: &Envelope<String>) -> std::io::Result<()> {
+            if matches!(envelope.message, Message::Event { .. }) && self.lose.contains(&self.events_sent.fetch_add(1, Ordering::SeqCst)) {
+                return Ok(());
+            }
+            let copy = serde_json::from_value(serde_json::to_value(envelope)?)?;
+            self.links.lock().unwrap()[&peer].send(copy).map_err(std::io::Error::other)
+        }
+        fn recv_timeout(&self, timeout: Duration) -> Option<Envelope<String>> {
+            self.inbox.lock().unwrap().recv_timeout(timeout).ok()
+        }
+    }
+
+    let links = Arc::new(Mutex::new(HashMap::new()));
+    let endpoint = |id: &str, lose: Vec<usize>| {
+        let (tx, rx) = mpsc::channel();
+        links.lock().unwrap().insert(NodeId::from(id), tx);
+        let lossy = Lossy { id: NodeId::from(id), links: links.clone(), inbox: Mutex::new(rx), events_sent: AtomicUsize::new(0), lose };
+        ReliableTransport::new(lossy).with_retransmit_after(Duration::from_millis(20))
+    };
+    let a = endpoint("rel-a", vec![0]);
+    let b = endpoint("rel-b", Vec::new());
+    let to_b = NodeId::from("rel-b");
+    let item = |envelope: Option<Envelope<String>>| match envelope.map(|e| e.message) {
+        Some(Message::Event { event, .. }) => event.item.map(|item| item.to_string()),
+        _ => None,
+    };
+
+    // The first event is lost: the second waits for it instead of reaching the node
+    for name in ["first", "second"] {
+        let event = Event::new_enqueue(NodeId::from("rel-a"), Arc::new(name.to_string()), HashMap::new());
+        a.send(to_b, &Envelope::event(a.local_id(), "jobs", event)).unwrap();
+    }
+    assert_eq!(a.in_flight("rel-b"), 2);
+    assert!(b.recv_timeout(Duration::from_millis(50)).is_none());
+
+    // a retransmits both while it waits for envelopes; b releases them in order, once each
+    assert!(a.recv_timeout(Duration::from_millis(50)).is_none());
+    assert_eq!(item(b.recv_timeout(Duration::from_millis(200))).as_deref(), Some("first"));
+    assert_eq!(item(b.recv_timeout(Duration::from_millis(200))).as_deref(), Some("second"));
+    assert!(b.recv_timeout(Duration::from_millis(50)).is_none());
+    assert!(a.recv_timeout(Duration::from_millis(50)).is_none());
+    assert_eq!(a.in_flight("rel-b"), 0);
+}
+
+#[test]
+#[cfg(all(feature = "auth", feature = "sim"))]
+fn test_reliable_channels_ignore_forged_acknowledgements() {
+    use DistributedQueueMini::core::buildcore::Event;
+    use DistributedQueueMini::engine::network::{Envelope, Keyring, MemoryNetwork, Message, ReliableTransport, Transport};
+
+    let keyring = Keyring::new().with_key("signed-a", "a-secret").with_key("signed-b", "b-secret");
+    let network = Arc::new(MemoryNetwork::<String>::new());
+    let a = ReliableTransport::new(network.join("signed-a")).with_keyring(keyring.clone());
+    let b = network.join("signed-b");
+    let to_b = NodeId::from("signed-b");
+    let event = Event::new_enqueue(NodeId::from("signed-a"), Arc::new("job".to_string()), Default::default());
+    a.send(to_b, &Envelope::event(a.local_id(), "jobs", event)).unwrap();
+    let channel = b.recv_timeout(Duration::from_millis(200)).and_then(|envelope| envelope.seq).unwrap().channel;
+
+    // An unsigned acknowledgement leaves the event waiting for retransmission
+    let ack = || Envelope::<String>::new(to_b, Message::Ack { channel, seq: 1 });
+    b.send(a.local_id(), &ack()).unwrap();
+    assert!(a.recv_timeout(Duration::from_millis(50)).is_none());
+    assert_eq!(a.in_flight("signed-b"), 1);
+
+    let mut signed = ack();
+    keyring.sign(&mut signed).unwrap();
+    b.send(a.local_id(), &signed).unwrap();
+    assert!(a.recv_timeout(Duration::from_millis(50)).is_none());
+    assert_eq!(a.in_flight("signed-b"), 0);
+}
+
+#[cfg(feature = "udp")]
+#[test]
+fn test_udp_transport_replicates_reliably_between_nodes() {
+    use DistributedQueueMini::engine::network::UdpTransport;
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let ta = Arc::new(UdpTransport::<String>::reliable("udp-a", any, &[]).unwrap());
+    let tb = Arc::new(UdpTransport::<String>::reliable("udp-b", any, &[]).unwrap());
+    ta.inner().add_peer("udp-b", tb.inner().local_addr());
+    tb.inner().add_peer("udp-a", ta.inner().local_addr());
+
+    let a = Arc::new(Node::<String>::new("udp-a", &["udp-b"]));
+    let b = Arc::new(Node::<String>::new("udp-b", &["udp-a"]));
+    let ha = a.start(ta.clone());
+    let hb = b.start(tb.clone());
+
+    for i in 0..20 {
+        a.enqueue("jobs", format!("a{}", i)).unwrap();
+    }
+    b.enqueue("jobs", "b0".to_string()).unwrap();
+
+    assert!(wait_for(|| b.topic("jobs").queue_state().0 == 21));
+    assert!(wait_for(|| a.topic("jobs").queue_state().0 == 21));
+    assert!(wait_for(|| ta.in_flight("udp-b") == 0 && tb.in_flight("udp-a") == 0));
+
+    ha.shutdown();
+    hb.shutdown();
+}
+
+#[cfg(feature = "quic")]
+#[test]
+fn test_quic_transport_replicates_between_nodes() {
+    use DistributedQueueMini::engine::network::QuicTransport;
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let ta = Arc::new(QuicTransport::<String>::bind("tls-a", any, &[], tls_config("tls-a")).unwrap());
+    let tb = Arc::new(QuicTransport::<String>::bind("tls-b", any, &[], tls_config("tls-b")).unwrap());
+    ta.add_peer("tls-b", tb.local_addr());
+    tb.add_peer("tls-a", ta.local_addr());
+
+    let a = Arc::new(Node::<String>::new("tls-a", &["tls-b"]));
+    let b = Arc::new(Node::<String>::new("tls-b", &["tls-a"]));
+    let ha = a.start(ta.clone());
+    let hb = b.start(tb.clone());
+
+    a.enqueue("bulk", "x".repeat(4 << 20)).unwrap();
+    for i in 0..20 {
+        a.enqueue("jobs", format!("a{}", i)).unwrap();
+    }
+    b.enqueue("jobs", "b0".to_string()).unwrap();
+
+    assert!(wait_for(|| b.topic("jobs").queue_state().0 == 21));
+    assert!(wait_for(|| a.topic("jobs").queue_state().0 == 21));
+    assert!(wait_for(|| b.topic("bulk").queue_state().0 == 1));
+
+    ha.shutdown();
+    hb.shutdown();
+}
+
+#[cfg(feature = "sim")]
+#[test]
+fn test_memory_transport_delays_and_drops_envelopes_without_sockets() {
+    use DistributedQueueMini::engine::network::{Envelope, MemoryNetwork, MemoryTransport, Message, ReliableTransport, Transport};
+    use DistributedQueueMini::engine::process::Latency;
+
+    let network = Arc::new(
+        MemoryNetwork::<String>::new()
+            .with_seed(7)
+            .with_latency(Latency::uniform(Duration::from_millis(1), Duration::from_millis(20)))
+            .with_loss(0.2),
+    );
+    let names = ["mem-a", "mem-b", "mem-c"];
+    let transports: Vec<Arc<ReliableTransport<String, MemoryTransport<String>>>> = names
+        .iter()
+        .map(|name| Arc::new(ReliableTransport::new(network.join(name)).with_retransmit_after(Duration::from_millis(30))))
+        .collect();
+    let nodes: Vec<Arc<Node<String>>> = names
+        .iter()
+        .map(|name| Arc::new(Node::new(name, &names.iter().copied().filter(|n| n != name).collect::<Vec<_>>())))
+        .collect();
+    let handles: Vec<_> = nodes.iter().zip(&transports).map(|(node, transport)| node.start(transport.clone())).collect();
+
+    for i in 0..10 {
+        nodes[i % 3].enqueue("jobs", format!("item{}", i)).unwrap();
+    }
+    for node in &nodes {
+        assert!(wait_for(|| node.topic("jobs").queue_state().0 == 10));
+    }
+
+    // Sends to a node that left fail, as to one that is down
+    network.leave("mem-c");
+    let to_c = Envelope::new(NodeId::from("mem-a"), Message::<String>::Ack { channel: 0, seq: 0 });
+    assert!(transports[0].inner().send(NodeId::from("mem-c"), &to_c).is_err());
+
+    for handle in handles {
+        handle.shutdown();
+    }
+}
+
+#[test]
+fn test_frame_codec_survives_truncated_corrupted_and_oversized_frames() {
+    use DistributedQueueMini::engine::network::{FRAME_HEADER_LEN, FrameCodec, FrameError};
+
+    // SplitMix64, so failures reproduce
+    let mut state = 42u64;
+    let mut next = move |bound: usize| {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        ((z ^ (z >> 31)) % bound.max(1) as u64) as usize
+    };
+    let codec = FrameCodec::new(512);
+
+    for _ in 0..500 {
+        let payloads: Vec<Vec<u8>> = (0..1 + next(8)).map(|_| (0..next(513)).map(|_| next(256) as u8).collect()).collect();
+        let mut stream: Vec<u8> = payloads.iter().flat_map(|p| codec.encode(p).unwrap()).collect();
+
+        // Intact, fed in pieces of random sizes: every frame comes out once, in order
+        let mut decoder = codec.decoder();
+        let mut decoded = Vec::new();
+        let mut rest = stream.as_slice();
+        while !rest.is_empty() {
+            let (piece, tail) = rest.split_at(1 + next(rest.len()).min(rest.len() - 1));
+            decoder.push(piece);
+            rest = tail;
+            while let Some(frame) = decoder.next_frame().unwrap() {
+                decoded.push(frame);
+            }
+        }
+        assert_eq!(decoded, payloads);
+        assert_eq!(decoder.finish(), Ok(()));
+
+        // Truncated: frames past the cut never come out, and the end reports one cut short
+        let cut = next(stream.len());
+        let mut decoder = codec.decoder();
+        decoder.push(&stream[..cut]);
+        let mut count = 0;
+        while let Some(frame) = decoder.next_frame().unwrap() {
+            assert_eq!(frame, payloads[count]);
+            count += 1;
+        }
+        assert!(count < payloads.len());
+        match decoder.pending() {
+            0 => assert_eq!(decoder.finish(), Ok(())),
+            _ => assert!(matches!(decoder.finish(), Err(FrameError::Truncated { .. }))),
+        }
+
+        // Damaged anywhere: the frames before it come out; a damaged payload is skipped and
+        // the rest follow, a damaged header (length included) stops the stream
+        let at = next(stream.len());
+        stream[at] ^= 1 << next(8);
+        let (mut damaged, mut offset) = (0, at);
+        while offset >= FRAME_HEADER_LEN + payloads[damaged].len() {
+            offset -= FRAME_HEADER_LEN + payloads[damaged].len();
+            damaged += 1;
+        }
+        let mut decoder = codec.decoder();
+        decoder.push(&stream);
+        for payload in &payloads[..damaged] {
+            assert_eq!(decoder.next_frame().unwrap().as_ref(), Some(payload));
+        }
+        if offset < FRAME_HEADER_LEN {
+            let e = decoder.next_frame().unwrap_err();
+            assert!(matches!(e, FrameError::Oversized { .. } | FrameError::HeaderChecksumMismatch) && decoder.is_broken());
+        } else {
+            assert_eq!(decoder.next_frame(), Err(FrameError::ChecksumMismatch));
+            for payload in &payloads[damaged + 1..] {
+                assert_eq!(decoder.next_frame().unwrap().as_ref(), Some(payload));
+            }
+            assert_eq!(decoder.next_frame(), Ok(None));
+        }
+    }
+
+    // Oversized lengths are refused before waiting for, or allocating, the payload
+    let mut decoder = codec.decoder();
+    decoder.push(&u32::MAX.to_be_bytes());
+    assert_eq!(decoder.next_frame(), Err(FrameError::Oversized { len: u32::MAX as usize, max: 512 }));
+    decoder.push(&codec.encode(b"after").unwrap());
+    assert!(decoder.next_frame().is_err());
+    assert!(codec.encode(&[0; 513]).is_err());
+
+    // A datagram is exactly one frame
+    let frame = codec.encode(b"datagram").unwrap();
+    assert_eq!(codec.decode_one(&frame), Ok(&b"datagram"[..]));
+    assert_eq!(codec.decode_one(&frame[..FRAME_HEADER_LEN + 2]), Err(FrameError::Truncated { missing: 6 }));
+    assert_eq!(codec.decode_one(&[frame.as_slice(), b"x"].concat()), Err(FrameError::Trailing { extra: 1 }));
+
+    // A damaged length within the limit is caught by the header checksum, not trusted
+    let mut damaged = frame.clone();
+    damaged[3] ^= 1;
+    assert_eq!(codec.decode_one(&damaged), Err(FrameError::HeaderChecksumMismatch));
+    let header: [u8; FRAME_HEADER_LEN] = damaged[..FRAME_HEADER_LEN].try_into().unwrap();
+    assert_eq!(codec.payload_len(&header), Err(FrameError::HeaderChecksumMismatch));
+    assert_eq!(codec.payload_len(frame[..FRAME_HEADER_LEN].try_into().unwrap()), Ok(8));
+}
+
+#[test]
+fn test_events_for_a_peer_that_is_down_are_held_until_it_reconnects() {
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    // Reserve an address for the peer, then leave nothing listening on it
+    let b_addr = TcpTransport::<String>::bind("rc-b", any, &[]).unwrap().local_addr();
+    let ta = Arc::new(
+        TcpTransport::<String>::bind("rc-a", any, &[("rc-b", b_addr)])
+            .unwrap()
+            .with_reconnect_backoff(Duration::from_millis(20), Duration::from_millis(100)),
+    );
+    let a = Arc::new(Node::<String>::new("rc-a", &["rc-b"]));
+    let ha = a.start(ta.clone());
+
+    for i in 0..5 {
+        a.enqueue("jobs", format!("job{}", i)).unwrap();
+    }
+    assert!(wait_for(|| ta.peer_health("rc-b").is_some_and(|h| !h.connected && h.held == 5 && h.failures >= 2)));
+
+    let tb = loop {
+        match TcpTransport::<String>::bind("rc-b", b_addr, &[("rc-a", ta.local_addr())]) {
+            Ok(transport) => break Arc::new(transport),
+            Err(_) => thread::sleep(Duration::from_millis(10)),
+        }
+    };
+    let b = Arc::new(Node::<String>::new("rc-b", &["rc-a"]));
+    let hb = b.start(tb.clone());
+
+    assert!(wait_for(|| b.topic("jobs").queue_state().0 == 5));
+    let health = ta.peer_health("rc-b").unwrap();
+    assert!(health.connected && health.held == 0 && health.failures == 0 && health.last_error.is_none());
+    assert_eq!(b.dequeue("jobs").0.as_deref().map(String::as_str), Some("job0"));
+
+    ha.shutdown();
+    hb.shutdown();
+}
+
+#[test]
+#[cfg(feature = "sim")]
+fn test_raft_mode_applies_operations_in_one_order_on_every_node() {
+    use DistributedQueueMini::core::buildcore::{ControlOp, QueueError, RateLimit, Throttle};
+    use DistributedQueueMini::engine::network::MemoryNetwork;
+    use DistributedQueueMini::engine::process::Latency;
+    use DistributedQueueMini::engine::raft::RaftConfig;
+
+    let network = Arc::new(MemoryNetwork::<String>::new().with_seed(3).with_latency(Latency::uniform(Duration::from_millis(1), Duration::from_millis(5))));
+    let names = ["raft-a", "raft-b", "raft-c"];
+    let nodes: Vec<Arc<Node<String>>> = names.iter().map(|name| Arc::new(Node::new(name, &[]).with_raft(&names, RaftConfig::default()))).collect();
+    let mut handles: Vec<_> = nodes.iter().zip(names).map(|(node, name)| Some(node.start(Arc::new(network.join(name))))).collect();
+    let agreed = |nodes: &[&Arc<Node<String>>]| {
+        let leader = nodes[0].leader();
+        leader.is_some() && nodes.iter().all(|node| node.leader() == leader)
+    };
+    assert!(wait_for(|| agreed(&nodes.iter().collect::<Vec<_>>())));
+
+    let leader_name = nodes[0].leader().unwrap();
+    let leader = names.iter().position(|&name| name == leader_name).unwrap();
+    let follower = (leader + 1) % 3;
+    assert_eq!(nodes[follower].enqueue("jobs", "refused".into()), Err(QueueError::NotLeader { leader: Some(leader_name.clone()) }));
+    // Followers apply what was committed even if they wouldn't admit it themselves
+    nodes[follower].topic("jobs").control(ControlOp::Paused);
+    nodes[(leader + 2) % 3].topic("jobs").set_rate_limit(Some((RateLimit::per_second(0.001, 1), Throttle::Reject)));
+    for i in 0..5 {
+        nodes[leader].enqueue("jobs", format!("job{}", i)).unwrap();
+    }
+    assert_eq!(nodes[leader].try_dequeue("jobs").unwrap().0.as_deref().map(String::as_str), Some("job0"));
+    for node in &nodes {
+        assert!(wait_for(|| node.topic("jobs").queue_state().0 == 4));
+        assert_eq!(node.topic("jobs").peek().as_deref().map(String::as_str), Some("job1"));
+    }
+    // A paused leader would hand out nothing
+    nodes[follower].topic("jobs").control(ControlOp::Resumed);
+
+    // The others elect a new leader, which has every committed operation
+    handles[leader].take().unwrap().shutdown();
+    network.leave(&leader_name);
+    let survivors: Vec<&Arc<Node<String>>> = (0..3).filter(|&i| i != leader).map(|i| &nodes[i]).collect();
+    assert!(wait_for(|| agreed(&survivors) && survivors[0].leader() != Some(leader_name.clone())));
+    let new_leader = survivors.iter().find(|node| node.leader().as_deref() == Some(node.node_id())).unwrap();
+    assert_eq!(new_leader.try_dequeue("jobs").unwrap().0.as_deref().map(String::as_str), Some("job1"));
+    for node in &survivors {
+        assert!(wait_for(|| node.topic("jobs").queue_state().0 == 3));
+    }
+}
+
+#[test]
+fn test_read_only_replicas_mirror_topics_without_consuming_them() {
+    use DistributedQueueMini::core::buildcore::QueueError;
+    use DistributedQueueMini::engine::node::NodeRole;
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let tw = Arc::new(TcpTransport::<String>::bind("ro-writer", any, &[]).unwrap());
+    let tm = Arc::new(TcpTransport::<String>::bind("ro-mirror", any, &[]).unwrap());
+    tw.add_peer("ro-mirror", tm.local_addr());
+    tm.add_peer("ro-writer", tw.local_addr());
+    let writer = Arc::new(Node::<String>::new("ro-writer", &["ro-mirror"]));
+    let mirror = Arc::new(Node::<String>::new("ro-mirror", &["ro-writer"]).with_role(NodeRole::ReadOnly));
+    let hw = writer.start(tw.clone());
+    let hm = mirror.start(tm.clone());
+
+    for i in 0..3 {
+        writer.enqueue("jobs", format!("job{}", i)).unwrap();
+    }
+    assert!(wait_for(|| mirror.topic("jobs").queue_state().0 == 3));
+    assert_eq!(mirror.enqueue("jobs", "local".to_string()), Err(QueueError::ReadOnly));
+    assert!(mirror.dequeue("jobs").0.is_none());
+
+    // Clients may read from the mirror, but not take from it
+    let client = QueueClient::<String>::connect(tm.local_addr()).unwrap();
+    assert_eq!(client.peek("jobs").unwrap().as_deref().map(String::as_str), Some("job0"));
+    assert_eq!(client.stats("jobs").unwrap().depth, 3);
+    assert!(client.dequeue("jobs").unwrap_err().to_string().contains("read-only"));
+
+    // It keeps following the writer's dequeues
+    assert_eq!(writer.dequeue("jobs").0.as_deref().map(String::as_str), Some("job0"));
+    assert!(wait_for(|| mirror.topic("jobs").queue_state().0 == 2));
+
+    hw.shutdown();
+    hm.shutdown();
+}
+
+#[test]
+fn test_raft_leader_ignores_acknowledgements_past_its_log() {
+    use DistributedQueueMini::engine::raft::{Raft, RaftConfig, RaftMessage};
+
+    let (a, b, c) = (NodeId::intern("acked-a"), NodeId::intern("acked-b"), NodeId::intern("acked-c"));
+    let config = RaftConfig { election_timeout: (Duration::ZERO, Duration::ZERO), ..RaftConfig::default() };
+    let leader = Raft::<u64>::new(a, &[a, b, c], config);
+    leader.tick();
+    leader.step(b, RaftMessage::Vote { term: 1, granted: true });
+    assert!(leader.is_leader());
+    leader.propose(1).unwrap();
+
+    // Only members count, and none holds more than the leader sent it
+    leader.step(NodeId::intern("acked-stranger"), RaftMessage::Appended { term: 1, success: true, index: 1 });
+    assert_eq!(leader.commit_index(), 0);
+    leader.step(b, RaftMessage::Appended { term: 1, success: true, index: u64::MAX - 1 });
+    assert_eq!(leader.commit_index(), 1);
+    let (_, _, outbox) = leader.propose(2).unwrap();
+    assert_eq!(leader.commit_index(), 1);
+    let to_b = outbox.into_iter().find(|(peer, _)| *peer == b).map(|(_, message)| message);
+    assert!(matches!(to_b, Some(RaftMessage::Append { prev_index: 1, ref entries, .. }) if entries.len() == 1));
+}
+
+#[test]
+fn test_raft_log_keeps_term_vote_and_entries_across_restarts() {
+    use DistributedQueueMini::engine::raft::{Raft, RaftConfig, RaftEntry, RaftMessage};
+
+    let path = std::env::temp_dir().join(format!("dqm-raft-{}.log", std::process::id()));
+    std::fs::remove_file(&path).ok();
+    let (a, b, c) = (NodeId::intern("durable-a"), NodeId::intern("durable-b"), NodeId::intern("durable-c"));
+    let members = [a, b, c];
+    let open = || Raft::<u64>::open(a, &members, RaftConfig::default(), &path).unwrap();
+    let granted = |outbox: Vec<(NodeId, RaftMessage<u64>)>| matches!(outbox[..], [(_, RaftMessage::Vote { granted, .. })] if granted);
+    let entry = |term, command| RaftEntry { term, command };
+
+    let follower = open();
+    assert!(granted(follower.step(b, RaftMessage::RequestVote { term: 4, last_index: 0, last_term: 0 })));
+    follower.step(b, RaftMessage::Append { term: 4, prev_index: 0, prev_term: 0, entries: vec![entry(4, 1), entry(4, 2)], commit: 1 });
+    drop(follower);
+
+    // The vote still stands, and the entries are back
+    let follower = open();
+    assert_eq!(follower.term(), 4);
+    assert!(!granted(follower.step(c, RaftMessage::RequestVote { term: 4, last_index: 2, last_term: 4 })));
+    let outbox = follower.step(b, RaftMessage::Append { term: 4, prev_index: 2, prev_term: 4, entries: Vec::new(), commit: 1 });
+    assert!(matches!(outbox[..], [(_, RaftMessage::Appended { success: true, index: 2, .. })]));
+
+    // A conflicting entry replaces the second one for good
+    follower.step(c, RaftMessage::Append { term: 5, prev_index: 1, prev_term: 4, entries: vec![entry(5, 3)], commit: 2 });
+    drop(follower);
+    let follower = open();
+    let outbox = follower.step(c, RaftMessage::Append { term: 5, prev_index: 2, prev_term: 5, entries: Vec::new(), commit: 2 });
+    assert!(matches!(outbox[..], [(_, RaftMessage::Appended { success: true, index: 2, .. })]));
+    let committed: Vec<u64> = follower.take_committed().into_iter().map(|(_, entry)| entry.command).collect();
+    assert_eq!(committed, [1, 3]);
+    std::fs::remove_file(&path).ok();
+}
+
+#[test]
+#[cfg(feature = "sim")]
+fn test_a_witness_breaks_raft_ties_without_storing_items() {
+    use DistributedQueueMini::engine::network::MemoryNetwork;
+    use DistributedQueueMini::engine::node::NodeRole;
+    use DistributedQueueMini::engine::raft::RaftConfig;
+
+    let network = Arc::new(MemoryNetwork::<String>::new());
+    let names = ["wit-a", "wit-b", "wit-w"];
+    let nodes: Vec<Arc<Node<String>>> = names
+        .iter()
+        .map(|&name| {
+            let role = if name == "wit-w" { NodeRole::Witness } else { NodeRole::Replica };
+            Arc::new(Node::new(name, &[]).with_role(role).with_raft(&names, RaftConfig::default()))
+        })
+        .collect();
+    let mut handles: Vec<_> = nodes.iter().zip(names).map(|(node, name)| Some(node.start(Arc::new(network.join(name))))).collect();
+    assert!(wait_for(|| nodes.iter().all(|node| node.leader().is_some() && node.leader() == nodes[2].leader())));
+    let leader_name = nodes[2].leader().unwrap();
+    assert_ne!(leader_name, "wit-w");
+
+    let leader = names.iter().position(|&name| name == leader_name).unwrap();
+    let other = 1 - leader;
+    for i in 0..3 {
+        nodes[leader].enqueue("jobs", format!("job{}", i)).unwrap();
+    }
+    assert!(wait_for(|| nodes[other].topic("jobs").queue_state().0 == 3));
+    assert!(nodes[2].get_topic("jobs").is_none());
+
+    // With the leader gone, the witness's vote elects the other replica
+    handles[leader].take().unwrap().shutdown();
+    network.leave(&leader_name);
+    assert!(wait_for(|| nodes[other].leader().as_deref() == Some(names[other])));
+    assert_eq!(nodes[other].try_dequeue("jobs").unwrap().0.as_deref().map(String::as_str), Some("job0"));
+    assert!(nodes[2].get_topic("jobs").is_none());
+}
+
+#[test]
+#[cfg(feature = "sim")]
+fn test_region_topology_sends_across_regions_only_through_forwarders() {
+    use DistributedQueueMini::engine::network::MemoryNetwork;
+    use DistributedQueueMini::engine::topology::Topology;
+
+    let names = ["eu-1", "eu-2", "us-1", "us-2"];
+    let topology = Topology::new().with_node("eu-1", "eu").with_node("eu-2", "eu").with_node("us-1", "us").with_node("us-2", "us");
+    let id = NodeId::intern;
+    let peers = |me: &str| names.iter().filter(|&&name| name != me).map(|&name| id(name)).collect::<Vec<_>>();
+    assert_eq!(topology.forwarder("eu"), Some(id("eu-1")));
+    assert_eq!(topology.targets(id("eu-2"), &peers("eu-2")), vec![id("eu-1")]);
+    assert_eq!(topology.targets(id("eu-1"), &peers("eu-1")), vec![id("eu-2"), id("us-1")]);
+    assert_eq!(topology.relays(id("eu-1"), id("eu-2"), &peers("eu-1")), vec![id("us-1")]);
+    assert_eq!(topology.relays(id("us-1"), id("eu-1"), &peers("us-1")), vec![id("us-2")]);
+    assert!(topology.relays(id("us-2"), id("us-1"), &peers("us-2")).is_empty());
+
+    // Events from every node reach every other one
+    let network = Arc::new(MemoryNetwork::<String>::new());
+    let nodes: Vec<Arc<Node<String>>> = names.iter().map(|&name| Arc::new(Node::new(name, &[]).with_topology(topology.clone()))).collect();
+    let handles: Vec<_> = nodes.iter().zip(names).map(|(node, name)| node.start(Arc::new(network.join(name)))).collect();
+    for (node, name) in nodes.iter().zip(names) {
+        node.enqueue("jobs", format!("from {}", name)).unwrap();
+    }
+    for node in &nodes {
+        assert!(wait_for(|| node.topic("jobs").queue_state().0 == 4));
+    }
+    for handle in handles {
+        handle.shutdown();
+    }
+}
+
+#[test]
+fn test_a_node_restores_a_topic_from_a_snapshot_pulled_from_a_peer() {
+    use DistributedQueueMini::engine::transfer::SnapshotDownload;
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let ts = Arc::new(TcpTransport::<String>::bind("snap-src", any, &[]).unwrap());
+    let source = Arc::new(Node::<String>::new("snap-src", &["snap-dst"]).with_snapshot_transfer());
+    let hs = source.start(ts.clone());
+    for i in 0..20 {
+        source.enqueue("jobs", format!("job{}", i)).unwrap();
+    }
+    source.dequeue("jobs");
+
+    // A transfer broken off after two chunks resumes over another connection
+    let first = QueueClient::<String>::connect(ts.local_addr()).unwrap();
+    let mut download = SnapshotDownload::new().with_chunk_len(64);
+    first.fetch_chunk("jobs", &mut download).unwrap();
+    first.fetch_chunk("jobs", &mut download).unwrap();
+    assert_eq!(download.offset(), 128);
+    drop(first);
+    let client = QueueClient::<String>::connect(ts.local_addr()).unwrap();
+    client.resume_snapshot("jobs", &mut download).unwrap();
+    let snapshot = download.finish::<String>().unwrap();
+    assert_eq!(snapshot.items.len(), 19);
+    assert!(client.fetch_snapshot("missing").is_err());
+
+    // Interleaved transfers of different snapshots of one topic both complete
+    let mut early = SnapshotDownload::new().with_chunk_len(64);
+    client.fetch_chunk("jobs", &mut early).unwrap();
+    source.enqueue("jobs", "late".to_string()).unwrap();
+    let mut late = SnapshotDownload::new().with_chunk_len(64);
+    client.fetch_chunk("jobs", &mut late).unwrap();
+    assert_ne!(early.digest(), late.digest());
+    while !early.is_complete() || !late.is_complete() {
+        for download in [&mut early, &mut late] {
+            if !download.is_complete() {
+                client.fetch_chunk("jobs", download).unwrap();
+            }
+        }
+    }
+    assert_eq!(early.finish::<String>().unwrap().items.len(), 19);
+    assert_eq!(late.finish::<String>().unwrap().items.len(), 20);
+
+    // The restored node picks up the source's events from where the snapshot left off
+    let td = Arc::new(TcpTransport::<String>::bind("snap-dst", any, &[]).unwrap());
+    let target = Arc::new(Node::<String>::new("snap-dst", &["snap-src"]));
+    target.restore_from("jobs", &client).unwrap();
+    assert_eq!(target.topic("jobs").queue_state().0, 20);
+    assert_eq!(target.topic("jobs").peek().as_deref().map(String::as_str), Some("job1"));
+    ts.add_peer("snap-dst", td.local_addr());
+    td.add_peer("snap-src", ts.local_addr());
+    let ht = target.start(td.clone());
+    source.enqueue("jobs", "job20".to_string()).unwrap();
+    assert!(wait_for(|| target.topic("jobs").queue_state().0 == 21));
+    assert_eq!(target.topic("jobs").snapshot().items, source.topic("jobs").snapshot().items);
+
+    hs.shutdown();
+    ht.shutdown();
+
+    // A snapshot nobody asked a chunk of for the retention time is dropped
+    let tr = Arc::new(TcpTransport::<String>::bind("snap-ret", any, &[]).unwrap());
+    let retaining = Arc::new(Node::<String>::new("snap-ret", &[]).with_snapshot_retention(Duration::from_millis(50)));
+    let hr = retaining.start(tr.clone());
+    for i in 0..20 {
+        retaining.enqueue("jobs", format!("job{}", i)).unwrap();
+    }
+    let client = QueueClient::<String>::connect(tr.local_addr()).unwrap();
+    let mut download = SnapshotDownload::new().with_chunk_len(64);
+    client.fetch_chunk("jobs", &mut download).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    assert!(client.fetch_chunk("jobs", &mut download).unwrap_err().to_string().contains("no longer available"));
+    hr.shutdown();
+}
+
+#[test]
+fn test_topic_stats_count_traffic_and_add_up_across_the_cluster() {
+    use DistributedQueueMini::core::buildcore::{DistributedQueueSystem, RetryPolicy};
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let ta = Arc::new(TcpTransport::<String>::bind("ts-a", any, &[]).unwrap());
+    let tb = Arc::new(TcpTransport::<String>::bind("ts-b", any, &[]).unwrap());
+    ta.add_peer("ts-b", tb.local_addr());
+    tb.add_peer("ts-a", ta.local_addr());
+    let policy = RetryPolicy::new(3).backoff(Duration::ZERO, 1.0, Duration::ZERO);
+    let factory = |id: &'static str, peer: &'static str, policy: RetryPolicy| {
+        move |_: &str| DistributedQueueSystem::builder(id).peers(&[peer]).retry_policy(policy)
+    };
+    let a = Arc::new(Node::<String>::with_topic_factory("ts-a", factory("ts-a", "ts-b", policy)));
+    let b = Arc::new(Node::<String>::with_topic_factory("ts-b", factory("ts-b", "ts-a", policy)));
+    let ha = a.start(ta.clone());
+    let hb = b.start(tb.clone());
+    assert_eq!(a.topic_stats("jobs"), None);
+
+    for i in 0..3 {
+        a.enqueue("jobs", format!("job{}", i)).unwrap();
+    }
+    let (item, event) = a.dequeue("jobs");
+    assert_eq!(item.as_deref().map(String::as_str), Some("job0"));
+    a.fail("jobs", event.global_id).unwrap();
+
+    // The failed item comes back once, counted as an enqueue and a redelivery
+    assert!(wait_for(|| a.topic_stats("jobs").is_some_and(|s| s.redeliveries == 1 && s.depth == 3)));
+    let stats = a.topic_stats("jobs").unwrap();
+    assert_eq!(stats.nodes, ["ts-a"]);
+    assert_eq!((stats.enqueued, stats.dequeued, stats.pending_redeliveries), (4, 1, 0));
+    assert!(stats.oldest_item_age_ms.is_some());
+    assert_eq!(stats.consumers, 0);
+
+    let sa = a.subscribe("jobs");
+    let sb = b.subscribe("jobs");
+    assert_eq!(a.consumers("jobs"), 1);
+    assert!(wait_for(|| b.topic_stats("jobs").is_some_and(|s| s.depth == 3 && s.redeliveries == 1)));
+
+    let client = QueueClient::<String>::connect(tb.local_addr()).unwrap();
+    let cluster = a.cluster_topic_stats("jobs", &[&client]);
+    assert_eq!(cluster.nodes, ["ts-a", "ts-b"]);
+    assert_eq!((cluster.depth, cluster.enqueued, cluster.dequeued, cluster.redeliveries), (3, 4, 1, 1));
+    assert_eq!(cluster.consumers, 2);
+
+    drop((sa, sb));
+    assert_eq!(a.consumers("jobs"), 0);
+    ha.shutdown();
+    hb.shutdown();
+}
+
+#[test]
+fn test_topics_are_created_and_deleted_on_every_node() {
+    use DistributedQueueMini::core::buildcore::{QueueError, TopicConfig};
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let ta = Arc::new(TcpTransport::<String>::bind("topic-a", any, &[]).unwrap());
+    let tb = Arc::new(TcpTransport::<String>::bind("topic-b", any, &[]).unwrap());
+    ta.add_peer("topic-b", tb.local_addr());
+    tb.add_peer("topic-a", ta.local_addr());
+    let a = Arc::new(Node::<String>::new("topic-a", &["topic-b"]));
+    let b = Arc::new(Node::<String>::new("topic-b", &["topic-a"]));
+    let ha = a.start(ta.clone());
+    let hb = b.start(tb.clone());
+
+    // The peer creates the topic with the same settings
+    let config = TopicConfig { capacity: Some(2), retention_entries: Some(100), max_attempts: Some(2), ..TopicConfig::default() };
+    assert_eq!(a.create_topic("orders", config.clone()).len(), 1);
+    assert!(wait_for(|| b.get_topic("orders").and_then(|queue| queue.topic_config()) == Some(config.clone())));
+    b.enqueue("orders", "one".to_string()).unwrap();
+    b.enqueue("orders", "two".to_string()).unwrap();
+    assert!(matches!(b.enqueue("orders", "three".to_string()), Err(QueueError::Full { capacity: 2 })));
+    assert!(wait_for(|| a.topic("orders").queue_state().0 == 2));
+
+    // Deleting it drops it everywhere
+    assert_eq!(a.delete_topic("orders").len(), 1);
+    assert!(a.get_topic("orders").is_none() && a.is_deleted("orders"));
+    assert!(wait_for(|| b.get_topic("orders").is_none() && b.is_deleted("orders")));
+    assert!(a.delete_topic("orders").is_empty());
+
+    // Until it is created again
+    a.create_topic("orders", TopicConfig::default());
+    assert!(wait_for(|| b.get_topic("orders").is_some()));
+    assert!(!b.is_deleted("orders"));
+    assert_eq!(b.get_topic("orders").unwrap().topic_config(), None);
+
+    ha.shutdown();
+    hb.shutdown();
+}
+
+#[test]
+fn test_topic_config_overrides_are_replicated_and_versioned() {
+    use DistributedQueueMini::core::buildcore::{ControlOp, DeliveryMode, DistributedQueueSystem, QueueError, RetryPolicy, TopicConfig};
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let ta = Arc::new(TcpTransport::<String>::bind("conf-a", any, &[]).unwrap());
+    let tb = Arc::new(TcpTransport::<String>::bind("conf-b", any, &[]).unwrap());
+    ta.add_peer("conf-b", tb.local_addr());
+    tb.add_peer("conf-a", ta.local_addr());
+    let node = |id: &'static str, peer: &'static str| {
+        Node::<String>::with_topic_factory(id, move |_| {
+            DistributedQueueSystem::builder(id)
+                .peers(&[peer])
+                .retry_policy(RetryPolicy::new(3))
+                .priority(|item: &String| u8::from(item.starts_with('!')))
+        })
+    };
+    let a = Arc::new(node("conf-a", "conf-b"));
+    let b = Arc::new(node("conf-b", "conf-a"));
+    let ha = a.start(ta.clone());
+    let hb = b.start(tb.clone());
+
+    // Node defaults until the topic overrides them
+    assert_eq!(a.topic_config("tasks"), None);
+    a.create_topic("tasks", TopicConfig::default());
+    let defaults = a.topic_config("tasks").unwrap();
+    assert_eq!((defaults.priority, defaults.delivery_mode, defaults.max_attempts), (Some(true), Some(DeliveryMode::AtLeastOnce), Some(3)));
+
+    // Overrides merge over earlier ones, and every replica takes on the newest version
+    a.configure_topic("tasks", TopicConfig { priority: Some(false), ..TopicConfig::default() });
+    a.configure_topic("tasks", TopicConfig { capacity: Some(10), ..TopicConfig::default() });
+    let expected = TopicConfig { capacity: Some(10), priority: Some(false), ..TopicConfig::default() };
+    assert!(wait_for(|| b.get_topic("tasks").is_some_and(|queue| queue.topic_config_version() == 2)));
+    assert_eq!(b.get_topic("tasks").unwrap().topic_config(), Some(expected.clone()));
+    assert_eq!(b.topic_config("tasks").unwrap().capacity, Some(10));
+
+    // An older version is ignored
+    let stale = TopicConfig { capacity: Some(1), ..TopicConfig::default() };
+    b.topic("tasks").control(ControlOp::TopicConfigured { topic: "tasks".to_string(), config: stale, version: 1 });
+    assert_eq!(b.get_topic("tasks").unwrap().topic_config(), Some(expected));
+
+    // Priority is off, then on again
+    a.enqueue("tasks", "low".to_string()).unwrap();
+    a.enqueue("tasks", "!high".to_string()).unwrap();
+    assert_eq!(a.dequeue("tasks").0.as_deref().map(String::as_str), Some("low"));
+    a.configure_topic("tasks", TopicConfig { priority: Some(true), ..TopicConfig::default() });
+    a.enqueue("tasks", "later".to_string()).unwrap();
+    a.enqueue("tasks", "!urgent".to_string()).unwrap();
+    assert_eq!(a.dequeue("tasks").0.as_deref().map(String::as_str), Some("!high"));
+    assert_eq!(a.dequeue("tasks").0.as_deref().map(String::as_str), Some("!urgent"));
+    assert!(wait_for(|| b.topic("tasks").snapshot().items == [Arc::new("later".to_string())]));
+
+    // At most once: deliveries aren't tracked, so they can't be failed
+    a.configure_topic("tasks", TopicConfig { delivery_mode: Some(DeliveryMode::AtMostOnce), ..TopicConfig::default() });
+    let (item, event) = a.dequeue("tasks");
+    assert_eq!(item.as_deref().map(String::as_str), Some("later"));
+    assert!(matches!(a.fail("tasks", event.global_id), Err(QueueError::UnknownDelivery { .. })));
+
+    // Expired items are dropped on every replica
+    a.configure_topic("tasks", TopicConfig { ttl_ms: Some(50), ..TopicConfig::default() });
+    a.enqueue("tasks", "stale".to_string()).unwrap();
+    assert!(wait_for(|| a.topic("tasks").queue_state().0 == 0 && b.topic("tasks").queue_state().0 == 0));
+    assert_eq!(b.topic_config("tasks").unwrap().ttl_ms, Some(50));
+
+    ha.shutdown();
+    hb.shutdown();
+}
+
+#[test]
+fn test_only_the_lease_holder_dequeues() {
+    use DistributedQueueMini::core::buildcore::{DistributedQueueSystem, QueueError};
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let ta = Arc::new(TcpTransport::<String>::bind("lease-a", any, &[]).unwrap());
+    let tb = Arc::new(TcpTransport::<String>::bind("lease-b", any, &[]).unwrap());
+    ta.add_peer("lease-b", tb.local_addr());
+    tb.add_peer("lease-a", ta.local_addr());
+    let node = |id: &'static str, peer: &'static str| {
+        Node::<String>::with_topic_factory(id, move |_| {
+            DistributedQueueSystem::builder(id).peers(&[peer]).lease(Duration::from_millis(300)).max_clock_skew(Duration::from_millis(50))
+        })
+    };
+    let a = Arc::new(node("lease-a", "lease-b"));
+    let b = Arc::new(node("lease-b", "lease-a"));
+    let ha = a.start(ta.clone());
+    let hb = b.start(tb.clone());
+
+    for i in 0..4 {
+        a.enqueue("jobs", format!("job{}", i)).unwrap();
+    }
+    assert!(wait_for(|| b.topic("jobs").queue_state().0 == 4));
+
+    // The first dequeue asks for the lease; items flow once the peer acknowledged it
+    assert_eq!(a.dequeue("jobs").0, None);
+    let taken = std::cell::RefCell::new(Vec::new());
+    assert!(wait_for(|| match a.dequeue("jobs").0 {
+        Some(item) => {
+            taken.borrow_mut().push(item);
+            true
+        }
+        None => false,
+    }));
+    assert!(a.topic("jobs").holds_lease());
+
+    // The peer sees the lease and hands out nothing while it lasts
+    assert!(wait_for(|| b.topic("jobs").lease().is_some_and(|lease| lease.holder == "lease-a")));
+    assert!(matches!(b.topic("jobs").acquire_lease(Duration::from_secs(1)), Err(QueueError::Leased { .. })));
+    assert_eq!(b.dequeue("jobs").0, None);
+
+    // Once it expires the peer takes over, and no item is handed out twice
+    assert!(wait_for(|| match b.dequeue("jobs").0 {
+        Some(item) => {
+            taken.borrow_mut().push(item);
+            true
+        }
+        None => false,
+    }));
+    assert!(!a.topic("jobs").holds_lease());
+    assert_eq!(a.dequeue("jobs").0, None);
+    let mut taken = taken.into_inner();
+    taken.sort();
+    taken.dedup();
+    assert_eq!(taken.len(), 2);
+    assert!(wait_for(|| a.topic("jobs").queue_state().0 == 2 && b.topic("jobs").queue_state().0 == 2));
+
+    ha.shutdown();
+    hb.shutdown();
+}
+
+#[test]
+fn test_the_dequeue_token_circulates() {
+    use DistributedQueueMini::core::buildcore::DistributedQueueSystem;
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let ta = Arc::new(TcpTransport::<String>::bind("ring-a", any, &[]).unwrap());
+    let tb = Arc::new(TcpTransport::<String>::bind("ring-b", any, &[]).unwrap());
+    ta.add_peer("ring-b", tb.local_addr());
+    tb.add_peer("ring-a", ta.local_addr());
+    let node = |id: &'static str, peer: &'static str| {
+        Node::<String>::with_topic_factory(id, move |_| DistributedQueueSystem::builder(id).peers(&[peer]).token_ring(Duration::from_millis(500)))
+    };
+    let a = Arc::new(node("ring-a", "ring-b"));
+    let b = Arc::new(node("ring-b", "ring-a"));
+    let ha = a.start(ta.clone());
+    let hb = b.start(tb.clone());
+    for i in 0..4 {
+        a.enqueue("jobs", format!("job{}", i)).unwrap();
+    }
+    assert!(wait_for(|| b.topic("jobs").queue_state().0 == 4));
+
+    // The first node in name order starts with the token; the other waits for it
+    assert!(a.topic("jobs").holds_token() || b.topic("jobs").holds_token());
+    let (holder, other) = if a.topic("jobs").holds_token() { (&a, &b) } else { (&b, &a) };
+    assert_eq!(holder.dequeue("jobs").0.as_deref().map(String::as_str), Some("job0"));
+    assert_eq!(other.dequeue("jobs").0, None);
+
+    // It comes round, and the next holder goes on where the last one stopped
+    assert!(wait_for(|| other.topic("jobs").holds_token()));
+    assert!(!holder.topic("jobs").holds_token());
+    assert_eq!(other.dequeue("jobs").0.as_deref().map(String::as_str), Some("job1"));
+    assert_eq!(holder.dequeue("jobs").0, None);
+    assert!(wait_for(|| holder.topic("jobs").holds_token()));
+    assert_eq!(holder.dequeue("jobs").0.as_deref().map(String::as_str), Some("job2"));
+    assert!(wait_for(|| a.topic("jobs").queue_state().0 == 1 && b.topic("jobs").queue_state().0 == 1));
+
+    ha.shutdown();
+    hb.shutdown();
+}
+
+#[test]
+fn test_deliveries_count_their_attempts_across_replicas() {
+    use DistributedQueueMini::core::buildcore::{DistributedQueueSystem, RetryPolicy};
+
+    let policy = RetryPolicy::new(3).backoff(Duration::ZERO, 2.0, Duration::ZERO);
+    let a = DistributedQueueSystem::builder("attempt-a").peers(&["attempt-b"]).retry_policy(policy).build();
+    let b = Node::<String>::with_topic_factory("attempt-b", move |_| DistributedQueueSystem::builder("attempt-b").peers(&["attempt-a"]).retry_policy(policy));
+    b.topic("jobs").apply_remote_event(a.enqueue("job".to_string()).unwrap());
+
+    // The dequeue event carries the attempt, first one here
+    let (_, dequeue) = a.dequeue();
+    assert_eq!(dequeue.delivery_attempt, 1);
+    assert_eq!(a.delivery_attempt(dequeue.global_id), Some(1));
+    b.topic("jobs").apply_remote_event(dequeue.clone());
+    b.topic("jobs").apply_remote_event(a.fail(dequeue.global_id).unwrap());
+    for event in a.process_retries() {
+        b.topic("jobs").apply_remote_event(event);
+    }
+
+    // The replica goes on counting, and consumers see the count
+    let delivery = b.subscribe("jobs").with_idle_timeout(Duration::from_millis(100)).next().unwrap();
+    assert_eq!((delivery.item.as_str(), delivery.attempt), ("job", 2));
+    b.fail("jobs", delivery.delivery).unwrap();
+    b.process_retries();
+    let (_, dequeue) = b.dequeue("jobs");
+    assert_eq!(dequeue.delivery_attempt, 3);
+
+    // Past the last attempt the item is dead-lettered
+    b.fail("jobs", dequeue.global_id).unwrap();
+    assert_eq!(b.topic("jobs").dead_letters().len(), 1);
+}
+
+#[test]
+fn test_one_event_acks_a_batch_of_deliveries() {
+    use DistributedQueueMini::core::buildcore::{DistributedQueueSystem, EventOp, RetryPolicy};
+
+    let policy = RetryPolicy::new(3);
+    let a = DistributedQueueSystem::builder("batch-a").peers(&["batch-b"]).retry_policy(policy).build();
+    let b = DistributedQueueSystem::<String>::builder("batch-b").peers(&["batch-a"]).retry_policy(policy).build();
+    let mut deliveries = Vec::new();
+    for job in ["one", "two", "three"] {
+        b.apply_remote_event(a.enqueue(job.to_string()).unwrap());
+        let (_, dequeue) = a.dequeue();
+        deliveries.push(dequeue.global_id);
+        b.apply_remote_event(dequeue);
+    }
+
+    // Untracked deliveries are skipped; the rest settle in a single event
+    let ack = a.ack_batch(&[deliveries[0], deliveries[2], 42]).unwrap();
+    assert!(matches!(&ack.op, EventOp::Ack { deliveries: acked } if *acked == vec![deliveries[0], deliveries[2]]));
+    assert!(a.fail(deliveries[0]).is_err());
+    assert!(a.fail(deliveries[1]).is_ok());
+    assert!(a.ack_batch(&[deliveries[2]]).is_none());
+
+    b.apply_remote_event(ack);
+    let acks: Vec<_> = b.log_view().iter().filter(|entry| entry.op == "ack").cloned().collect();
+    assert_eq!(acks.len(), 1);
+    assert_eq!(acks[0].event.origin_node.as_str(), "batch-a");
+}
+
+#[test]
+fn test_mirror_forwards_local_enqueues_and_resumes_from_its_position() {
+    use DistributedQueueMini::core::buildcore::DistributedQueueSystem;
+    use DistributedQueueMini::engine::mirror::{ClusterMirror, RemoteCluster};
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let transport = Arc::new(TcpTransport::<String>::bind("standby", any, &[]).unwrap());
+    let standby = Arc::new(Node::<String>::new("standby", &[]));
+    let handle = standby.start(transport.clone());
+    let remote = RemoteCluster::new([transport.local_addr().to_string()]).with_topic("jobs-copy");
+    let positions = std::env::temp_dir().join(format!("dqm-mirror-{}.json", std::process::id()));
+    std::fs::remove_file(&positions).ok();
+
+    // Local enqueues are forwarded, dequeued or not; a replicated one is left to its origin,
+    // and one the peer rejects over its quota is never forwarded
+    let primary = Arc::new(Node::<String>::new("primary", &["primary-peer"]));
+    let peer = DistributedQueueSystem::<String>::builder("primary-peer").peers(&["primary"]).node_quota("primary", 2).build();
+    let mut events: Vec<_> = ["one", "two", "rejected"].iter().map(|item| primary.enqueue("jobs", item.to_string()).unwrap()).collect();
+    events.push(primary.dequeue("jobs").1);
+    let replicate = |events: Vec<_>| {
+        for event in events {
+            peer.apply_remote_event(event);
+        }
+        for event in peer.take_outgoing() {
+            primary.topic("jobs").apply_remote_event(event);
+        }
+        primary.topic("jobs").observe_peer_clock(NodeId::intern("primary-peer"), &peer.vector_clock());
+    };
+
+    // Nothing is forwarded until the peer applied it
+    let mirror = ClusterMirror::start(primary.clone(), "jobs", remote.clone(), Some(positions.clone())).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(standby.topic("jobs-copy").queue_state().0, 0);
+    replicate(events);
+    primary.topic("jobs").apply_remote_event(peer.enqueue("elsewhere".to_string()).unwrap());
+    assert!(wait_for(|| standby.topic("jobs-copy").queue_state().0 == 2));
+    let end = primary.topic("jobs").retained_range().end;
+    assert!(wait_for(|| mirror.positions().get("jobs") == Some(&end)));
+    mirror.shutdown();
+
+    // A restarted mirror picks up where the saved position left off
+    replicate(vec![primary.enqueue("jobs", "three".to_string()).unwrap()]);
+    let mirror = ClusterMirror::start(primary.clone(), "jobs", remote, Some(positions.clone())).unwrap();
+    assert!(wait_for(|| mirror.positions()["jobs"] > end));
+    assert!(wait_for(|| standby.topic("jobs-copy").queue_state().0 == 3));
+    let items: Vec<String> = (0..3).filter_map(|_| standby.dequeue("jobs-copy").0).map(|item| item.to_string()).collect();
+    assert_eq!(items, ["one", "two", "three"]);
+
+    mirror.shutdown();
+    handle.shutdown();
+    std::fs::remove_file(&positions).ok();
+}
+
+/// Path of an example binary; the examples are built (or brought up to date) once per run
+fn example(name: &str) -> std::path::PathBuf {
+    static BUILD: std::sync::Once = std::sync::Once::new();
+    BUILD.call_once(|| {
+        let built = std::process::Command::new(env!("CARGO"))
+            .args(["build", "--offline", "--examples"])
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .status()
+            .unwrap();
+        assert!(built.success());
+    });
+    // target/debug/deps/networktests-... next to target/debug/examples/
+    let deps = std::env::current_exe().unwrap().parent().unwrap().to_path_buf();
+    deps.parent().unwrap().join("examples").join(name)
+}
+
+#[test]
+fn test_examples_push_pull_and_inspect_a_running_node() {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let any = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap();
+    let transport = Arc::new(TcpTransport::<serde_json::Value>::bind("ex-node", any, &[]).unwrap());
+    let node = Arc::new(Node::<serde_json::Value>::new("ex-node", &[]));
+    let handle = node.start(transport.clone());
+    let addr = transport.local_addr().to_string();
+
+    // Messages from the arguments, JSON or not, then from stdin
+    let produced = Command::new(example("producer")).args([addr.as_str(), "jobs", r#"{"n":1}"#, "hello"]).output().unwrap();
+    assert!(produced.status.success());
+    assert_eq!(String::from_utf8_lossy(&produced.stdout).matches("enqueued event").count(), 2);
+    let mut producer = Command::new(example("producer")).args([addr.as_str(), "jobs"]).stdin(Stdio::piped()).stdout(Stdio::null()).spawn().unwrap();
+    producer.stdin.take().unwrap().write_all(b"[1,2]\nfrom stdin\n").unwrap();
+    assert!(producer.wait().unwrap().success());
+    assert_eq!(node.topic("jobs").queue_state().0, 4);
+
+    let inspected = Command::new(example("admin")).args([addr.as_str(), "jobs"]).output().unwrap();
+    assert!(inspected.status.success());
+    let stats: serde_json::Value = serde_json::from_slice(&inspected.stdout).unwrap();
+    assert_eq!((stats["node_id"].as_str(), stats["depth"].as_u64()), (Some("ex-node"), Some(4)));
+
+    let consumed = Command::new(example("consumer")).args([addr.as_str(), "jobs", "4"]).output().unwrap();
+    assert!(consumed.status.success());
+    assert_eq!(String::from_utf8_lossy(&consumed.stdout).lines().collect::<Vec<_>>(), [r#"{"n":1}"#, r#""hello""#, "[1,2]", r#""from stdin""#]);
+    assert_eq!(node.topic("jobs").queue_state().0, 0);
+
+    // Bad usage and unreachable nodes fail
+    assert_eq!(Command::new(example("producer")).output().unwrap().status.code(), Some(2));
+    handle.shutdown();
+    drop(transport);
+    assert!(!Command::new(example("admin")).args([addr.as_str(), "jobs"]).output().unwrap().status.success());
+}
@@ -0,0 +1,42 @@
+#![cfg(feature = "python")]
+
+use pyo3::prelude::*;
+use DistributedQueueMini::bindings::python::distributed_queue;
+
+#[test]
+fn test_python_bindings_replicate_between_nodes() {
+    pyo3::append_to_inittab!(distributed_queue);
+    Python::initialize();
+    Python::attach(|py| {
+        py.run(
+            cr#"
+from distributed_queue import DistributedQueueSystem
+
+a, b = DistributedQueueSystem("A", ["B"]), DistributedQueueSystem("B", ["A"])
+assert a.node_id == "A"
+event = a.enqueue({"job": 1, "tags": ["x", None], "ok": True, "weight": 0.5})
+assert event["op"] == "Enqueue" and event["origin_node"] == "A"
+assert b.apply_remote_event(event)
+assert not b.apply_remote_event(event)
+assert len(a) == len(b) == 1
+
+item, dequeued = b.dequeue()
+assert item == {"job": 1, "tags": ["x", None], "ok": True, "weight": 0.5}
+assert a.apply_remote_event(dequeued)
+assert len(a) == 0
+assert b.dequeue()[0] is None
+
+assert [entry["op"] for entry in a.logs()] == ["enqueue", "dequeue"]
+assert b.stats()["depth"] == 0 and b.stats()["node_id"] == "B"
+try:
+    a.enqueue(object())
+    raise AssertionError("object() enqueued")
+except TypeError:
+    pass
+"#,
+            None,
+            None,
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+    });
+}
@@ -4,7 +4,7 @@ fn test_duplicate_event_handling() {
     let node1 = DistributedQueueSystem::new("node1".to_string());
     let node2 = DistributedQueueSystem::new("node2".to_string());
 
-    let event = node1.enqueue("item1".to_string());
+    let event = node1.enqueue("item1".to_string()).unwrap();
 
     // Apply event once
     assert!(node2.apply_remote_event(event.clone()));
@@ -14,4 +14,1758 @@ fn test_duplicate_event_handling() {
     assert!(!node2.apply_remote_event(event));
     assert_eq!(node2.queue_state().0, 1); // Should remain unchanged
 
-}
\ No newline at end of file
+}
+#[test]
+fn test_concurrent_remote_events_from_many_origins() {
+    use std::sync::Arc;
+    use std::thread;
+
+    let receiver = Arc::new(DistributedQueueSystem::new("receiver".to_string()));
+    let mut handles = vec![];
+    for n in 0..32 {
+        let receiver = receiver.clone();
+        handles.push(thread::spawn(move || {
+            let origin = DistributedQueueSystem::new(format!("origin{}", n));
+            let event = origin.enqueue(format!("origin{}-item", n)).unwrap();
+            assert!(receiver.apply_remote_event(event.clone()));
+            assert!(!receiver.apply_remote_event(event));
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(receiver.queue_state().0, 32);
+}
+
+#[test]
+fn test_vector_clock_add_node_keeps_counters() {
+    use DistributedQueueMini::core::buildcore::{NodeId, VectorClock};
+    use std::collections::HashMap;
+
+    let (a, b, c) = (NodeId::from("a"), NodeId::from("b"), NodeId::from("c"));
+
+    let clock = VectorClock::new_single("a");
+    clock.tick();
+    clock.add_node("b");
+    clock.add_node("b");
+    let snap = clock.tick_snapshot();
+    assert_eq!(snap.get(&a), Some(&2));
+    assert_eq!(snap.get(&b), Some(&0));
+
+    clock.update(&HashMap::from([(b, 5), (c, 9)]));
+    let snap = clock.snapshot();
+    assert_eq!(snap.get(&a), Some(&3));
+    assert_eq!(snap.get(&b), Some(&5));
+    assert_eq!(snap.get(&c), None);
+}
+
+#[test]
+fn test_interned_node_ids_serialize_as_names() {
+    use DistributedQueueMini::core::buildcore::{Event, NodeId};
+
+    let node = DistributedQueueSystem::new("serde-node".to_string());
+    let event = node.enqueue("item".to_string()).unwrap();
+    assert_eq!(event.origin_node, NodeId::from("serde-node"));
+
+    let json = serde_json::to_string(&event).unwrap();
+    assert!(json.contains(r#""origin_node":"serde-node""#));
+    assert!(json.contains(r#""clock":{"serde-node":1}"#));
+
+    let back: Event<String> = serde_json::from_str(&json).unwrap();
+    assert_eq!(back.origin_node, event.origin_node);
+    assert_eq!(back.clock, event.clock);
+}
+
+#[test]
+fn test_payloads_are_shared_not_cloned() {
+    use std::sync::Arc;
+
+    // Deliberately not Clone
+    #[derive(Debug, PartialEq)]
+    struct Blob(Vec<u8>);
+
+    let node = DistributedQueueSystem::new("arc-node".to_string());
+    let payload = Arc::new(Blob(vec![7; 1024]));
+    let event = node.enqueue_arc(payload.clone()).unwrap();
+
+    let logged = node.logs()[0].item().cloned().unwrap();
+    assert!(Arc::ptr_eq(&payload, event.item.as_ref().unwrap()));
+    assert!(Arc::ptr_eq(&payload, &logged));
+
+    let (item, _) = node.dequeue();
+    assert!(Arc::ptr_eq(&payload, &item.unwrap()));
+}
+
+#[test]
+fn test_log_entries_share_the_recorded_event() {
+    use DistributedQueueMini::core::buildcore::LogEntry;
+
+    let node = DistributedQueueSystem::new("log-node".to_string());
+    let event = node.enqueue("a".to_string()).unwrap();
+    let entry = node.logs().remove(0);
+    assert_eq!(entry.event_global_id(), event.global_id);
+    assert_eq!(entry.clock(), &event.clock);
+    assert_eq!(entry.item().map(|i| i.as_str()), Some("a"));
+
+    let json = serde_json::to_string(&entry).unwrap();
+    let back: LogEntry<String> = serde_json::from_str(&json).unwrap();
+    assert_eq!(back.event_global_id(), event.global_id);
+    assert_eq!(back.item().map(|i| i.as_str()), Some("a"));
+}
+
+#[test]
+fn test_events_and_entries_carry_wall_clock_time() {
+    use std::time::{Duration, SystemTime};
+    use DistributedQueueMini::core::buildcore::{Event, LogEntry};
+
+    let before = SystemTime::now() - Duration::from_millis(1);
+    let origin = DistributedQueueSystem::new("wall-a".to_string());
+    let receiver = DistributedQueueSystem::new("wall-b".to_string());
+    let event = origin.enqueue("x".to_string()).unwrap();
+    assert!(event.timestamp() >= before && event.timestamp() <= SystemTime::now());
+
+    // Replicated entries keep the origin's event time but record their own logging time
+    receiver.apply_remote_event(event.clone());
+    let entry = receiver.logs().remove(0);
+    assert_eq!(entry.event.timestamp_ms, event.timestamp_ms);
+    assert!(entry.logged_at_ms >= event.timestamp_ms);
+
+    // Entries written before timestamps existed still load
+    let mut json: serde_json::Value = serde_json::to_value(&entry).unwrap();
+    json.as_object_mut().unwrap().remove("logged_at_ms");
+    json["event"].as_object_mut().unwrap().remove("timestamp_ms");
+    let old: LogEntry<String> = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!((old.logged_at_ms, old.event.timestamp_ms), (0, 0));
+    let _: Event<String> = serde_json::from_value(json["event"].clone()).unwrap();
+}
+
+#[test]
+fn test_clock_skew_is_reported_per_peer() {
+    use std::time::Duration;
+    use DistributedQueueMini::core::buildcore::NodeId;
+
+    let fast = DistributedQueueSystem::new("skew-fast".to_string());
+    let fine = DistributedQueueSystem::new("skew-fine".to_string());
+    let receiver = DistributedQueueSystem::builder("skew-recv")
+        .max_clock_skew(Duration::from_millis(500))
+        .build();
+
+    let mut ahead = fast.enqueue(1).unwrap();
+    ahead.timestamp_ms += 5_000;
+    receiver.apply_remote_event(ahead);
+    receiver.apply_remote_event(fine.enqueue(2).unwrap());
+
+    assert_eq!(receiver.skewed_peers(), [NodeId::from("skew-fast")]);
+    let stats = receiver.stats();
+    let skew = stats.clock_skew_ms[&NodeId::from("skew-fast")];
+    assert!((4_000..=5_000).contains(&skew), "skew {}", skew);
+    assert!(stats.clock_skew_ms[&NodeId::from("skew-fine")].abs() < 500);
+    #[cfg(feature = "metrics")]
+    assert_eq!(stats.metrics.clock_skewed, 1);
+}
+
+#[test]
+#[cfg(feature = "persist")]
+fn test_log_spills_to_storage_and_pages_back() {
+    use DistributedQueueMini::core::buildcore::FileStore;
+
+    let path = std::env::temp_dir().join(format!("dqm-spill-{}.ndjson", std::process::id()));
+    let store = FileStore::<String>::create(&path).unwrap();
+    let node = DistributedQueueSystem::builder("spill-node")
+        .max_log_entries(2)
+        .storage(Box::new(store))
+        .build();
+
+    for i in 0..5 {
+        node.enqueue(format!("item{}", i)).unwrap();
+    }
+    assert_eq!(node.logs().len(), 2);
+
+    let all = node.log_page(0, 10).unwrap();
+    let items: Vec<&str> = all.iter().map(|e| e.item().unwrap().as_str()).collect();
+    assert_eq!(items, ["item0", "item1", "item2", "item3", "item4"]);
+
+    let middle = node.log_page(2, 2).unwrap();
+    assert_eq!(middle[0].item().unwrap().as_str(), "item2");
+    assert_eq!(middle[1].item().unwrap().as_str(), "item3");
+    std::fs::remove_file(path).ok();
+}
+
+#[test]
+#[cfg(feature = "persist")]
+fn test_retention_waits_for_the_stability_watermark() {
+    use std::time::Duration;
+    use DistributedQueueMini::core::buildcore::{FileStore, NdjsonSink, NodeId, RetentionPolicy};
+
+    let dir = std::env::temp_dir();
+    let store_path = dir.join(format!("dqm-retain-{}.ndjson", std::process::id()));
+    let archive_path = dir.join(format!("dqm-archive-{}.ndjson", std::process::id()));
+    let a = DistributedQueueSystem::builder("ret-a")
+        .peers(&["ret-b"])
+        .max_log_entries(1)
+        .storage(Box::new(FileStore::<String>::create(&store_path).unwrap()))
+        .retention(RetentionPolicy::new().max_entries(2).archive_to(Box::new(NdjsonSink::append_to(&archive_path).unwrap())))
+        .build();
+    let b = DistributedQueueSystem::builder("ret-b").peers(&["ret-a"]).build();
+
+    let events: Vec<_> = (0..5).map(|i| a.enqueue(format!("item{}", i)).unwrap()).collect();
+    // ret-b hasn't confirmed anything yet, so the cap can't be enforced
+    assert_eq!(a.retained_range(), 0..5);
+    assert_eq!(a.stability_watermark().get(&NodeId::from("ret-a")), Some(&0));
+
+    for event in events {
+        b.apply_remote_event(event);
+    }
+    a.apply_remote_event(b.enqueue("from-b".to_string()).unwrap());
+    assert_eq!(a.retained_range(), 4..6);
+    let kept: Vec<String> = a.log_page(0, 10).unwrap().iter().map(|e| e.item().unwrap().to_string()).collect();
+    assert_eq!(kept, ["item4", "from-b"]);
+    let archived = std::fs::read_to_string(&archive_path).unwrap();
+    assert_eq!(archived.lines().count(), 4);
+    assert!(archived.contains("item0") && !archived.contains("item4"));
+
+    // A single node is its own watermark
+    let solo = DistributedQueueSystem::builder("ret-solo")
+        .retention(RetentionPolicy::new().max_age(Duration::from_millis(200)))
+        .build();
+    solo.enqueue(1).unwrap();
+    solo.enqueue(2).unwrap();
+    assert_eq!(solo.enforce_retention().unwrap(), 0);
+    std::thread::sleep(Duration::from_millis(250));
+    assert_eq!(solo.enforce_retention().unwrap(), 2);
+    assert_eq!(solo.retained_range(), 2..2);
+    std::fs::remove_file(store_path).ok();
+    std::fs::remove_file(archive_path).ok();
+}
+
+#[test]
+fn test_entries_since_a_clock_only_read_back_the_spilled_runs_after_it() {
+    use std::sync::{Arc, Mutex};
+    use DistributedQueueMini::core::buildcore::{LogEntry, LogStore, Logger, NodeId, State};
+
+    // Store counting how many entries are read back
+    struct Counting {
+        entries: Vec<LogEntry<i32>>,
+        read: Arc<Mutex<usize>>,
+    }
+    impl LogStore<i32> for Counting {
+        fn append(&mut self, entries: &[LogEntry<i32>]) -> std::io::Result<()> {
+            self.entries.extend_from_slice(entries);
+            Ok(())
+        }
+        fn len(&self) -> usize {
+            self.entries.len()
+        }
+        fn read(&self, offset: usize, limit: usize) -> std::io::Result<Vec<LogEntry<i32>>> {
+            let page = self.entries.iter().skip(offset).take(limit).cloned().collect::<Vec<_>>();
+            *self.read.lock().unwrap() += page.len();
+            Ok(page)
+        }
+    }
+
+    let read = Arc::new(Mutex::new(0));
+    let mut logger = Logger::new(NodeId::from("since-a"))
+        .with_max_entries(10)
+        .with_store(Box::new(Counting { entries: Vec::new(), read: read.clone() }));
+    let origin = DistributedQueueSystem::new("since-a".to_string());
+    let events: Vec<_> = (0..2000).map(|item| origin.enqueue(item).unwrap()).collect();
+    for event in &events {
+        logger.log("enqueue", State::Committed, Arc::new(event.clone())).unwrap();
+    }
+
+    let since = logger.get_entries_since(&events[1979].clock).unwrap();
+    let items: Vec<i32> = since.iter().map(|entry| **entry.item().unwrap()).collect();
+    assert_eq!(items, (1980..2000).collect::<Vec<_>>());
+    assert!(*read.lock().unwrap() <= 256);
+    assert_eq!(logger.get_entries_since(&Default::default()).unwrap().len(), 2000);
+}
+
+#[test]
+#[cfg(feature = "persist")]
+fn test_verify_log_finds_damaged_entries() {
+    use DistributedQueueMini::core::buildcore::{FileStore, LogStore, verify_log};
+    use DistributedQueueMini::core::storage::CorruptionKind;
+
+    let path = std::env::temp_dir().join(format!("dqm-verify-{}.ndjson", std::process::id()));
+    let node = DistributedQueueSystem::new("verify-node".to_string());
+    for i in 0..3 {
+        node.enqueue(format!("item{}", i)).unwrap();
+    }
+    FileStore::<String>::create(&path).unwrap().append(&node.logs()).unwrap();
+    let report = verify_log(&path).unwrap();
+    assert!(report.is_clean());
+    assert_eq!(report.valid_entries, 3);
+    assert_eq!(report.unchecked_entries, 0);
+    let good = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(report.valid_bytes, good.len() as u64);
+
+    // Flip a byte in the second entry
+    let first_len = good.find('\n').unwrap() + 1;
+    let damaged = good.replacen("item1", "itemX", 1);
+    std::fs::write(&path, &damaged).unwrap();
+    let report = verify_log(&path).unwrap();
+    let corruption = report.corruption.unwrap();
+    assert_eq!((corruption.line, corruption.kind), (2, CorruptionKind::ChecksumMismatch));
+    assert_eq!(report.valid_bytes, first_len as u64);
+    assert!(FileStore::<String>::open(&path).unwrap().read(0, 3).is_err());
+
+    // Crash mid-write: the last line has no newline
+    std::fs::write(&path, &good[..good.len() - 10]).unwrap();
+    let report = verify_log(&path).unwrap();
+    assert_eq!(report.valid_entries, 2);
+    assert_eq!(report.corruption.unwrap().kind, CorruptionKind::Truncated);
+
+    // Entries written without checksums are still accepted
+    let legacy = serde_json::to_string(&node.logs()[0]).unwrap();
+    std::fs::write(&path, format!("{}\n", legacy)).unwrap();
+    let report = verify_log(&path).unwrap();
+    assert!(report.is_clean());
+    assert_eq!(report.unchecked_entries, 1);
+    std::fs::remove_file(path).ok();
+}
+
+#[test]
+#[cfg(feature = "persist")]
+fn test_log_writer_round_trips_each_format() {
+    use std::io::ErrorKind;
+    use DistributedQueueMini::core::buildcore::{LogFormat, LogWriter, read_logs};
+
+    let node = DistributedQueueSystem::new("format-node".to_string());
+    for i in 0..3 {
+        node.enqueue(format!("item{}", i)).unwrap();
+    }
+    node.dequeue();
+    let logs = node.logs();
+
+    for format in [LogFormat::Ndjson, LogFormat::MessagePack, LogFormat::Cbor] {
+        let path = std::env::temp_dir().join(format!("dqm-format-{}.{}", std::process::id(), format.extension()));
+        std::fs::remove_file(&path).ok();
+        if !format.is_available() {
+            assert_eq!(LogWriter::append_to(&path, format).err().map(|e| e.kind()), Some(ErrorKind::Unsupported));
+            continue;
+        }
+        LogWriter::append_to(&path, format).unwrap().write_entries(&logs).unwrap();
+        let read = read_logs::<String>(&path, format).unwrap();
+        assert_eq!(read.len(), 4, "{}", format);
+        assert_eq!(read[2].item().map(|i| i.as_str()), Some("item2"));
+        assert_eq!(read[3].op, "dequeue");
+        assert_eq!(read[3].clock(), logs[3].clock());
+        assert_eq!(format.to_string().parse::<LogFormat>().unwrap(), format);
+
+        // A damaged entry and a torn last entry are both reported
+        let mut bytes = std::fs::read(&path).unwrap();
+        let at = bytes.windows(5).position(|w| w == b"item1").unwrap();
+        bytes[at + 4] = b'X';
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(read_logs::<String>(&path, format).unwrap_err().kind(), ErrorKind::InvalidData, "{}", format);
+        bytes[at + 4] = b'1';
+        std::fs::write(&path, &bytes[..bytes.len() - 3]).unwrap();
+        assert_eq!(read_logs::<String>(&path, format).unwrap_err().kind(), ErrorKind::UnexpectedEof, "{}", format);
+        std::fs::remove_file(&path).ok();
+    }
+}
+
+#[test]
+#[cfg(feature = "encrypt")]
+fn test_encrypted_storage_survives_key_rotation() {
+    use DistributedQueueMini::core::buildcore::{FileStore, LogStore, PayloadCipher, SnapshotFile};
+
+    let old_key = PayloadCipher::generate_key();
+    let new_key = PayloadCipher::generate_key();
+    let cipher = PayloadCipher::new(1, old_key).for_queue("secrets");
+    let path = std::env::temp_dir().join(format!("dqm-sealed-{}.ndjson", std::process::id()));
+    let node = DistributedQueueSystem::builder("sealed-node")
+        .max_log_entries(1)
+        .storage(Box::new(FileStore::<String>::create(&path).unwrap().with_cipher(cipher)))
+        .build();
+    node.enqueue("card-4242".to_string()).unwrap();
+    node.enqueue("card-1337".to_string()).unwrap();
+
+    // Rotate: new entries use key 2, the entry sealed with key 1 stays readable
+    let mut master = PayloadCipher::new(1, old_key);
+    master.rotate(2, new_key);
+    let rotated = master.for_queue("secrets");
+    let mut store = FileStore::<String>::open(&path).unwrap().with_cipher(rotated.clone());
+    store.append(&node.logs()).unwrap();
+    let items: Vec<String> = store.read(0, 10).unwrap().iter().map(|e| e.item().unwrap().to_string()).collect();
+    assert_eq!(items, ["card-4242", "card-1337"]);
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    assert!(!text.contains("card-"));
+    assert!(text.contains("\"key\":1") && text.contains("\"key\":2"));
+
+    // Another queue's derived key can't open this one's entries
+    let other = FileStore::<String>::open(&path).unwrap().with_cipher(PayloadCipher::new(1, old_key).for_queue("other"));
+    assert!(other.read(0, 1).is_err());
+
+    let snapshot_path = path.with_extension("snapshot");
+    let snapshots = SnapshotFile::new(&snapshot_path).with_cipher(rotated);
+    snapshots.save(&node.snapshot()).unwrap();
+    assert!(!std::fs::read_to_string(&snapshot_path).unwrap().contains("card-"));
+    let restored = snapshots.load::<String>().unwrap();
+    assert_eq!(restored.items.len(), 2);
+    assert_eq!(restored.items[0].as_str(), "card-4242");
+    std::fs::remove_file(path).ok();
+    std::fs::remove_file(snapshot_path).ok();
+}
+
+#[test]
+fn test_log_view_is_a_stable_snapshot() {
+    let node = DistributedQueueSystem::new("view-node".to_string());
+    for i in 0..300 {
+        node.enqueue(i).unwrap();
+    }
+    let view = node.log_view();
+    for i in 300..600 {
+        node.enqueue(i).unwrap();
+    }
+    assert_eq!(view.len(), 300);
+    assert_eq!(view.iter().last().unwrap().item().map(|i| **i), Some(299));
+    assert_eq!(node.log_view().len(), 600);
+}
+
+#[test]
+fn test_builder_capacity_and_buffer_limits() {
+    use DistributedQueueMini::core::buildcore::{ClockType, ConsistencyLevel, QueueError};
+
+    let node = DistributedQueueSystem::builder("cap-node").queue_capacity(2).build();
+    node.enqueue("a".to_string()).unwrap();
+    node.enqueue("b".to_string()).unwrap();
+    assert_eq!(node.try_enqueue("c".to_string()).unwrap_err(), QueueError::Full { capacity: 2 });
+    assert_eq!(node.enqueue("c".to_string()).unwrap_err(), QueueError::Full { capacity: 2 });
+
+    // Second event from the origin can't be applied before the first
+    let origin = DistributedQueueSystem::new("cap-origin".to_string());
+    let _first = origin.enqueue(1).unwrap();
+    let second = origin.enqueue(2).unwrap();
+    let third = origin.enqueue(3).unwrap();
+
+    let causal = DistributedQueueSystem::<i32>::builder("cap-causal").max_buffered_events(1).build();
+    assert!(!causal.apply_remote_event(second.clone()));
+    assert!(!causal.apply_remote_event(third.clone()));
+    assert_eq!(causal.pending_events_count(), 1);
+
+    let eventual = DistributedQueueSystem::<i32>::builder("cap-eventual")
+        .consistency(ConsistencyLevel::Eventual)
+        .clock_type(ClockType::Dynamic)
+        .build();
+    assert!(eventual.apply_remote_event(third));
+    assert!(eventual.apply_remote_event(second));
+    assert_eq!(eventual.queue_state().0, 2);
+}
+
+#[test]
+fn test_rate_limits_throttle_local_enqueues() {
+    use std::time::{Duration, Instant};
+    use DistributedQueueMini::core::buildcore::{QueueError, RateLimit, Throttle};
+
+    let node = DistributedQueueSystem::builder("rate-node")
+        .rate_limit(RateLimit::per_second(1.0, 3), Throttle::Reject)
+        .client_rate_limit(RateLimit::per_second(1.0, 1), Throttle::Reject)
+        .build();
+    assert!(node.try_enqueue_as("alice", 1).is_ok());
+    assert!(matches!(node.try_enqueue_as("alice", 2), Err(QueueError::RateLimited { retry_after }) if retry_after > Duration::ZERO));
+    // Other identities have their own bucket but share the node's
+    assert!(node.try_enqueue_as("bob", 3).is_ok());
+    assert!(node.try_enqueue(4).is_ok());
+    assert!(matches!(node.try_enqueue(5), Err(QueueError::RateLimited { .. })));
+    assert_eq!(node.queue_state().0, 3);
+    #[cfg(feature = "metrics")]
+    assert_eq!(node.metrics().throttled, 2);
+
+    // Replicated events are never limited
+    let origin = DistributedQueueSystem::new("rate-origin".to_string());
+    assert!(node.apply_remote_event(origin.enqueue(6).unwrap()));
+
+    let blocking = DistributedQueueSystem::builder("rate-blocking").rate_limit(RateLimit::per_second(20.0, 1), Throttle::Block).build();
+    let started = Instant::now();
+    for i in 0..3 {
+        blocking.try_enqueue(i).unwrap();
+    }
+    assert!(started.elapsed() >= Duration::from_millis(90));
+    assert_eq!(blocking.queue_state().0, 3);
+}
+
+#[test]
+fn test_node_quotas_reject_and_replicate_rejections() {
+    use DistributedQueueMini::core::buildcore::{EventOp, QueueError};
+
+    let producer = DistributedQueueSystem::builder("quota-producer").peers(&["quota-replica"]).node_quota("quota-producer", 3).build();
+    let replica = DistributedQueueSystem::builder("quota-replica").peers(&["quota-producer"]).node_quota("quota-producer", 2).build();
+    let events: Vec<_> = (0..3).map(|i| producer.try_enqueue(i).unwrap()).collect();
+    assert_eq!(
+        producer.try_enqueue(3).unwrap_err(),
+        QueueError::QuotaExceeded { origin: "quota-producer".to_string(), quota: 3 }
+    );
+
+    // The replica takes two and turns down the third
+    for event in &events {
+        assert!(replica.apply_remote_event(event.clone()));
+    }
+    assert_eq!(replica.queue_state().0, 2);
+    let outgoing = replica.take_outgoing();
+    assert_eq!(outgoing.len(), 1);
+    assert!(matches!(outgoing[0].op, EventOp::Reject { event_id, .. } if event_id == events[2].global_id));
+    assert!(replica.take_outgoing().is_empty());
+
+    // The producer learns about it and drops the item, so both queues agree
+    assert!(producer.apply_remote_event(outgoing[0].clone()));
+    assert_eq!(producer.rejected_enqueues(), [events[2].global_id]);
+    let rejected = producer.logs().into_iter().find(|e| e.op == "enqueue" && e.event_global_id() == events[2].global_id).unwrap();
+    assert_eq!(rejected.state, DistributedQueueMini::core::buildcore::State::Failed);
+    let items = |q: &DistributedQueueSystem<i32>| q.snapshot().items.iter().map(|i| **i).collect::<Vec<_>>();
+    assert_eq!(items(&producer), [0, 1]);
+    assert_eq!(items(&replica), [0, 1]);
+    assert!(replica.logs().iter().any(|e| e.op == "reject"));
+    #[cfg(feature = "metrics")]
+    assert_eq!((producer.metrics().quota_exceeded, replica.metrics().quota_exceeded), (1, 1));
+
+    // Consuming frees quota again
+    producer.dequeue();
+    assert!(producer.try_enqueue(4).is_ok());
+}
+
+#[test]
+fn test_round_robin_dequeue_interleaves_origins() {
+    use DistributedQueueMini::core::buildcore::DequeuePolicy;
+
+    let nodes = ["fair-a", "fair-b", "fair-c"];
+    let replica = |id: &str| {
+        let peers: Vec<&str> = nodes.iter().copied().filter(|n| *n != id).collect();
+        DistributedQueueSystem::builder(id).peers(&peers).dequeue_policy(DequeuePolicy::RoundRobin).build()
+    };
+    let (a, b, c) = (replica("fair-a"), replica("fair-b"), replica("fair-c"));
+
+    // A chatty producer enqueues first, the others later
+    let mut events: Vec<_> = (0..4).map(|i| a.enqueue(format!("a{}", i)).unwrap()).collect();
+    events.push(b.enqueue("b0".to_string()).unwrap());
+    events.extend((0..2).map(|i| c.enqueue(format!("c{}", i)).unwrap()));
+    for event in &events {
+        for node in [&a, &b, &c] {
+            node.apply_remote_event(event.clone());
+        }
+    }
+
+    assert_eq!(c.peek().as_deref().map(String::as_str), Some("a0"));
+    let mut taken = Vec::new();
+    let mut dequeues = Vec::new();
+    while let (Some(item), event) = c.dequeue() {
+        taken.push(item.to_string());
+        dequeues.push(event);
+    }
+    assert_eq!(taken, ["a0", "b0", "c0", "a1", "c1", "a2", "a3"]);
+
+    // Replicas applying the same dequeues remove the same items
+    for event in dequeues.iter().take(4) {
+        a.apply_remote_event(event.clone());
+    }
+    let rest: Vec<String> = a.snapshot().items.iter().map(|i| i.to_string()).collect();
+    assert_eq!(rest, ["a2", "a3", "c1"]);
+    assert_eq!(a.peek().as_deref().map(String::as_str), Some("c1"));
+}
+
+#[test]
+fn test_failed_deliveries_retry_then_dead_letter() {
+    use std::time::Duration;
+    use DistributedQueueMini::core::buildcore::{EventOp, QueueError, RetryPolicy, State};
+
+    let policy = RetryPolicy::new(2).backoff(Duration::ZERO, 2.0, Duration::ZERO);
+    let a = DistributedQueueSystem::builder("retry-a").peers(&["retry-b"]).retry_policy(policy).build();
+    let b = DistributedQueueSystem::builder("retry-b").peers(&["retry-a"]).retry_policy(policy).build();
+    b.apply_remote_event(a.enqueue("job".to_string()).unwrap());
+
+    // First failure: logged as Failed everywhere, then the item goes back in
+    let (_, dequeue) = a.dequeue();
+    b.apply_remote_event(dequeue.clone());
+    let fail = a.fail(dequeue.global_id).unwrap();
+    assert!(matches!(fail.op, EventOp::Fail { attempt: 1, dead_letter: false, .. }));
+    assert_eq!(a.fail(dequeue.global_id), Err(QueueError::UnknownDelivery { delivery: dequeue.global_id }));
+    b.apply_remote_event(fail);
+    assert!(b.logs().iter().any(|entry| entry.op == "fail" && matches!(entry.state, State::Failed)));
+    let retries = a.process_retries();
+    assert_eq!(retries.len(), 1);
+    for event in retries {
+        b.apply_remote_event(event);
+    }
+    assert_eq!(b.queue_state().0, 1);
+
+    // The replica delivering it next knows it is the last attempt
+    let (item, dequeue) = b.dequeue();
+    assert_eq!(item.as_deref().map(String::as_str), Some("job"));
+    a.apply_remote_event(dequeue.clone());
+    let fail = b.fail(dequeue.global_id).unwrap();
+    assert!(matches!(fail.op, EventOp::Fail { attempt: 2, dead_letter: true, .. }));
+    a.apply_remote_event(fail);
+    assert!(b.process_retries().is_empty());
+    for node in [&a, &b] {
+        assert_eq!(node.queue_state(), (0, true));
+        assert_eq!(node.dead_letters().iter().map(|i| i.as_str()).collect::<Vec<_>>(), ["job"]);
+    }
+}
+
+#[test]
+fn test_state_transitions_are_validated_and_replicated() {
+    use DistributedQueueMini::core::buildcore::{EventOp, QueueError, State};
+
+    assert!(State::Pending.can_become(State::Committed));
+    assert!(State::Delivered.can_become(State::Failed));
+    assert!(State::Failed.can_become(State::DeadLettered));
+    assert!(!State::Delivered.can_become(State::Committed));
+    assert!(!State::DeadLettered.can_become(State::Failed));
+
+    let a = DistributedQueueSystem::new_with_nodes("state-a".to_string(), &["state-b"]);
+    let b = DistributedQueueSystem::new_with_nodes("state-b".to_string(), &["state-a"]);
+    let enqueue = a.enqueue(1).unwrap();
+    b.apply_remote_event(enqueue.clone());
+
+    assert_eq!(
+        a.update_state("state-a", enqueue.global_id, State::Pending),
+        Err(QueueError::InvalidTransition { from: State::Committed, to: State::Pending })
+    );
+    assert!(matches!(a.update_state("state-a", 0, State::Failed), Err(QueueError::UnknownEntry { id: 0 })));
+    let update = a.update_state("state-a", enqueue.global_id, State::Failed).unwrap();
+    assert!(matches!(update.op, EventOp::StateUpdate { state: State::Failed, .. }));
+    b.apply_remote_event(update);
+
+    // Both replicas end with the enqueue failed and the update itself logged
+    for node in [&a, &b] {
+        let logs = node.logs();
+        let entry = logs.iter().find(|e| e.event_global_id() == enqueue.global_id).unwrap();
+        assert_eq!(entry.state, State::Failed);
+        assert!(logs.iter().any(|e| e.op == "update" && e.state == State::Failed));
+    }
+}
+
+#[test]
+fn test_local_state_updates_reach_every_replica() {
+    use DistributedQueueMini::core::buildcore::{QueueError, State};
+
+    let nodes = ["update-a", "update-b", "update-c"];
+    let replicas: Vec<_> = nodes
+        .iter()
+        .map(|id| DistributedQueueSystem::new_with_nodes(id.to_string(), &nodes.iter().copied().filter(|n| n != id).collect::<Vec<_>>()))
+        .collect();
+    let enqueue = replicas[0].enqueue("job").unwrap();
+    let (_, dequeue) = replicas[0].dequeue();
+    for replica in &replicas[1..] {
+        replica.apply_remote_event(enqueue.clone());
+        replica.apply_remote_event(dequeue.clone());
+    }
+
+    // The origin marks its entry by local log ID; replicas' entries have other IDs
+    let log_id = replicas[0].logs()[1].local_log_id;
+    assert!(matches!(replicas[0].update_entry_state(log_id + 1000, State::Failed), Err(QueueError::UnknownEntry { .. })));
+    let update = replicas[0].update_entry_state(log_id, State::Failed).unwrap();
+    for replica in &replicas[1..] {
+        assert!(replica.apply_remote_event(update.clone()));
+    }
+    for replica in &replicas {
+        let logs = replica.logs();
+        let entry = logs.iter().find(|e| e.event_global_id() == dequeue.global_id).unwrap();
+        assert_eq!(entry.state, State::Failed, "on {}", replica.node_id());
+    }
+}
+
+#[test]
+fn test_log_lookups_by_event_id_and_key() {
+    use DistributedQueueMini::core::buildcore::{MemoryStore, RetentionPolicy, State};
+
+    // Small in-memory cap, so most entries are looked up in the store
+    let queue = DistributedQueueSystem::builder("index-node")
+        .max_log_entries(300)
+        .storage(Box::new(MemoryStore::new()))
+        .log_key(|order: &(String, u32)| Some(order.0.clone()))
+        .build();
+    let events: Vec<_> = (0..1000).map(|i| queue.enqueue((format!("customer-{}", i % 7), i)).unwrap()).collect();
+
+    for event in [&events[0], &events[650], &events[999]] {
+        let found = queue.find_by_event_id(event.global_id).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].item(), event.item.as_ref());
+    }
+    assert!(queue.find_by_event_id(0).unwrap().is_empty());
+    let orders: Vec<u32> = queue.find_by_key("customer-3").unwrap().iter().map(|e| e.item().unwrap().1).collect();
+    assert_eq!(orders, (0..1000).filter(|i| i % 7 == 3).collect::<Vec<_>>());
+    assert!(queue.find_by_key("customer-9").unwrap().is_empty());
+
+    // State updates find their entry through the index too
+    queue.update_state("index-node", events[900].global_id, State::Failed).unwrap();
+    assert_eq!(queue.find_by_event_id(events[900].global_id).unwrap()[0].state, State::Failed);
+
+    // Entries removed by retention leave the index
+    let queue = DistributedQueueSystem::builder("index-retention")
+        .retention(RetentionPolicy::new().max_entries(2))
+        .build();
+    let events: Vec<_> = (0..3).map(|i| queue.enqueue(i).unwrap()).collect();
+    assert!(queue.find_by_event_id(events[0].global_id).unwrap().is_empty());
+    assert_eq!(queue.find_by_event_id(events[2].global_id).unwrap().len(), 1);
+}
+
+#[test]
+fn test_log_subscribers_see_new_entries() {
+    use std::sync::mpsc::TryRecvError;
+
+    let origin = DistributedQueueSystem::new("tail-origin".to_string());
+    let queue = DistributedQueueSystem::new("tail-node".to_string());
+    queue.enqueue("before").unwrap();
+    let tail = queue.subscribe_logs();
+    let dropped = queue.subscribe_logs();
+    drop(dropped);
+
+    queue.enqueue("local").unwrap();
+    queue.apply_remote_event(origin.enqueue("remote").unwrap());
+    queue.dequeue();
+    let seen: Vec<(String, Option<&str>)> = tail.try_iter().map(|e| (e.op.clone(), e.item().map(|i| **i))).collect();
+    assert_eq!(
+        seen,
+        [("enqueue".to_string(), Some("local")), ("enqueue".to_string(), Some("remote")), ("dequeue".to_string(), Some("before"))]
+    );
+    drop(queue);
+    assert_eq!(tail.try_recv().unwrap_err(), TryRecvError::Disconnected);
+}
+
+#[test]
+#[cfg(feature = "persist")]
+fn test_export_logs_to_csv() {
+    use DistributedQueueMini::core::buildcore::export_csv;
+
+    let origin = DistributedQueueSystem::new("csv-origin".to_string());
+    let queue = DistributedQueueSystem::new("csv-node".to_string());
+    queue.apply_remote_event(origin.enqueue("a, \"quoted\" item".to_string()).unwrap());
+    let (_, dequeue) = queue.dequeue();
+    queue.dequeue();
+
+    let mut csv = Vec::new();
+    export_csv(&queue.log_page(0, usize::MAX).unwrap(), &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "local_log_id,local_node,op,state,event_global_id,origin_node,timestamp_ms,logged_at_ms,item,clock.csv-node,clock.csv-origin"
+    );
+    assert_eq!(lines.len(), 4);
+    assert!(lines[1].contains(",enqueue,Committed,"));
+    assert!(lines[1].ends_with(r#","""a, \""quoted\"" item""",0,1"#), "{}", lines[1]);
+    assert!(lines[2].starts_with(&format!("{},csv-node,dequeue,Delivered,{},csv-node,", queue.logs()[1].local_log_id, dequeue.global_id)));
+    assert!(lines[3].ends_with(",,2,1"), "empty dequeue has no item: {}", lines[3]);
+}
+
+#[test]
+#[cfg(feature = "parquet")]
+fn test_export_logs_to_parquet() {
+    use DistributedQueueMini::core::buildcore::export_parquet;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    let queue = DistributedQueueSystem::new_with_nodes("parquet-node".to_string(), &["parquet-peer"]);
+    for i in 0..5 {
+        queue.enqueue(i).unwrap();
+    }
+    queue.dequeue();
+
+    let path = std::env::temp_dir().join(format!("dqm-export-{}.parquet", std::process::id()));
+    export_parquet(&queue.logs(), std::fs::File::create(&path).unwrap()).unwrap();
+    let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+    let metadata = reader.metadata();
+    assert_eq!(metadata.file_metadata().num_rows(), 6);
+    let columns: Vec<&str> = metadata.file_metadata().schema_descr().columns().iter().map(|c| c.name()).collect();
+    assert_eq!(columns.last(), Some(&"clock.parquet-peer"));
+    assert!(columns.contains(&"item") && columns.contains(&"clock.parquet-node"));
+    std::fs::remove_file(&path).ok();
+}
+
+#[test]
+#[cfg(feature = "metrics")]
+fn test_metrics_count_operations() {
+    let origin = DistributedQueueSystem::new("metrics-origin".to_string());
+    let node = DistributedQueueSystem::new("metrics-node".to_string());
+    let event = origin.enqueue(1).unwrap();
+    node.apply_remote_event(event.clone());
+    node.apply_remote_event(event);
+    node.enqueue(2).unwrap();
+    node.dequeue();
+
+    let m = node.metrics();
+    assert_eq!((m.enqueued, m.dequeued), (1, 1));
+    assert_eq!((m.remote_applied, m.remote_duplicates), (1, 1));
+}
+
+#[test]
+#[cfg(feature = "metrics")]
+fn test_failed_log_sink_writes_are_counted_not_lost() {
+    use std::io;
+    use DistributedQueueMini::core::buildcore::LogEntry;
+    use DistributedQueueMini::core::storage::LogSink;
+
+    struct Broken;
+    impl LogSink<i32> for Broken {
+        fn write(&mut self, _entry: &LogEntry<i32>) -> io::Result<()> {
+            Err(io::Error::other("disk full"))
+        }
+    }
+
+    let node = DistributedQueueSystem::builder("broken-sink").log_sink(Box::new(Broken)).build();
+    node.enqueue(1).unwrap();
+    node.enqueue(2).unwrap();
+
+    assert_eq!(node.metrics().store_failed, 2);
+    assert_eq!(node.queue_state().0, 2);
+    assert_eq!(node.logs().len(), 2);
+}
+
+#[test]
+fn test_causal_delivery_with_known_peers() {
+    let a = DistributedQueueSystem::new_with_nodes("causal-a".to_string(), &["causal-b", "causal-c"]);
+    let b = DistributedQueueSystem::new_with_nodes("causal-b".to_string(), &["causal-a", "causal-c"]);
+    let c = DistributedQueueSystem::new_with_nodes("causal-c".to_string(), &["causal-a", "causal-b"]);
+
+    let a1 = a.enqueue(1).unwrap();
+    let a2 = a.enqueue(2).unwrap();
+    assert!(b.apply_remote_event(a1.clone()));
+    // b's event depends on a1
+    let b1 = b.enqueue(3).unwrap();
+
+    // c sees b1 and a2 before a1: both must wait
+    assert!(!c.apply_remote_event(b1));
+    assert!(!c.apply_remote_event(a2));
+    assert_eq!(c.pending_events_count(), 2);
+
+    assert!(c.apply_remote_event(a1));
+    assert_eq!(c.pending_events_count(), 0);
+    assert_eq!(c.queue_state().0, 3);
+}
+
+#[test]
+fn test_delivering_remote_events_leaves_the_receivers_own_time_alone() {
+    use DistributedQueueMini::core::buildcore::NodeId;
+
+    let a = DistributedQueueSystem::new_with_nodes("own-time-a".to_string(), &["own-time-b"]);
+    let b = DistributedQueueSystem::new_with_nodes("own-time-b".to_string(), &["own-time-a"]);
+    for item in 0..3 {
+        assert!(b.apply_remote_event(a.enqueue(item).unwrap()));
+    }
+    // Ticking on every delivery made b's next event look like it followed three a never got,
+    // so a buffered it for good
+    assert_eq!(b.vector_clock()[&NodeId::from("own-time-b")], 0);
+    assert!(a.apply_remote_event(b.enqueue(3).unwrap()));
+    assert_eq!(a.pending_events_count(), 0);
+}
+
+#[test]
+fn test_buffered_chain_is_delivered_in_causal_order() {
+    let a = DistributedQueueSystem::new_with_nodes("chain-a".to_string(), &["chain-b"]);
+    let b = DistributedQueueSystem::new_with_nodes("chain-b".to_string(), &["chain-a"]);
+    let events: Vec<_> = (0..200).map(|item| a.enqueue(item).unwrap()).collect();
+
+    // Everything after the first waits for it, then the whole chain is delivered
+    for event in events[1..].iter().rev() {
+        assert!(!b.apply_remote_event(event.clone()));
+    }
+    assert_eq!(b.pending_events_count(), 199);
+    assert!(b.apply_remote_event(events[0].clone()));
+    assert_eq!(b.pending_events_count(), 0);
+    let items: Vec<i32> = b.snapshot().items.iter().map(|item| **item).collect();
+    assert_eq!(items, (0..200).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_trace_context_follows_item_to_its_dequeue() {
+    use DistributedQueueMini::core::buildcore::{EventOp, TraceContext};
+
+    let trace = TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+    assert_eq!(trace, TraceContext::new(0x4bf92f3577b34da6a3ce929d0e0e4736, 0x00f067aa0ba902b7));
+    assert_eq!(trace.to_string(), "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01");
+    assert_eq!(TraceContext::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01"), None);
+    assert_eq!(serde_json::to_string(&trace).unwrap(), format!("\"{}\"", trace));
+
+    let producer = DistributedQueueSystem::new("trace-producer".to_string());
+    let replica = DistributedQueueSystem::new("trace-replica".to_string());
+    let untraced = producer.enqueue(1).unwrap();
+    let traced = producer.try_enqueue_traced(2, trace).unwrap();
+    assert_eq!((untraced.trace, traced.trace), (None, Some(trace)));
+    replica.apply_remote_event(untraced);
+    replica.apply_remote_event(traced);
+
+    // The replica hands out the traced item second, and its dequeue carries the trace on
+    let (_, first) = replica.dequeue();
+    let (item, second) = replica.dequeue();
+    assert_eq!((first.trace, item.as_deref(), second.trace), (None, Some(&2), Some(trace)));
+    producer.apply_remote_event(first);
+    producer.apply_remote_event(second);
+    let logged = producer.logs().iter().filter(|entry| matches!(entry.event.op, EventOp::Dequeue)).filter_map(|entry| entry.event.trace).count();
+    assert_eq!(logged, 1);
+}
+
+#[test]
+#[cfg(feature = "sim")]
+fn test_cluster_delivers_after_seeded_link_latency() {
+    use std::time::Duration;
+    use DistributedQueueMini::engine::process::{Cluster, Delivery, Latency};
+
+    let ms = Duration::from_millis;
+    let run = |seed| {
+        let mut cluster = Cluster::new(&["lat-a", "lat-b", "lat-c"])
+            .with_seed(seed)
+            .with_latency(Latency::normal(ms(20), ms(5)).with_jitter(ms(10)))
+            .with_link_latency("lat-a", "lat-c", Latency::fixed(ms(100)));
+        for i in 0..5 {
+            cluster.enqueue("lat-a", i).unwrap();
+            cluster.enqueue("lat-b", 10 + i).unwrap();
+        }
+        assert_eq!(cluster.in_flight(), 20);
+        assert!(!cluster.converged());
+        let deliveries = cluster.run_until_quiet();
+        assert!(cluster.converged());
+        deliveries
+    };
+
+    let deliveries = run(7);
+    assert_eq!(deliveries.len(), 20);
+    // Jitter reorders each origin's events, so some wait in the causal buffer
+    assert!(deliveries.iter().any(|d| !d.applied));
+    assert!(deliveries.windows(2).all(|pair| pair[0].delivered_at <= pair[1].delivered_at));
+    for delivery in &deliveries {
+        let delay = delivery.delivered_at - delivery.sent_at;
+        if (delivery.from.as_str(), delivery.to.as_str()) == ("lat-a", "lat-c") {
+            assert_eq!(delay, ms(100));
+        } else {
+            assert!(delay <= ms(60), "delay {:?}", delay);
+        }
+    }
+    // Same seed, same run; another seed reorders it (event IDs are process-wide, so left out)
+    let timeline = |deliveries: Vec<Delivery>| -> Vec<_> {
+        deliveries.into_iter().map(|d| (d.from, d.to, d.delivered_at, d.applied)).collect()
+    };
+    let first = timeline(deliveries);
+    assert_eq!(timeline(run(7)), first);
+    assert_ne!(timeline(run(8)), first);
+
+    // Advancing the clock only delivers what is due by then
+    let mut cluster = Cluster::new(&["lat-a", "lat-b"]).with_latency(Latency::uniform(ms(10), ms(30)));
+    cluster.enqueue("lat-a", 1).unwrap();
+    assert!(cluster.advance(ms(5)).is_empty());
+    assert_eq!(cluster.node("lat-b").unwrap().queue_state().0, 0);
+    assert_eq!(cluster.advance(ms(25)).len(), 1);
+    assert_eq!(cluster.now(), ms(30));
+    assert_eq!(cluster.node("lat-b").unwrap().queue_state().0, 1);
+}
+
+#[test]
+#[cfg(feature = "sim")]
+fn test_replicas_survive_a_misbehaving_peer() {
+    use DistributedQueueMini::core::buildcore::{EventRejection, QueueError};
+    use DistributedQueueMini::engine::process::{Misbehavior, MisbehavingNode};
+
+    let names = ["byz-dup", "byz-ahead", "byz-range", "byz-rewind", "byz-good", "byz-replica"];
+    let node = |me: &str| {
+        let peers: Vec<&str> = names.iter().copied().filter(|n| *n != me).collect();
+        DistributedQueueSystem::new_with_nodes(me.to_string(), &peers)
+    };
+    let replica = node("byz-replica");
+    let reason = |result: Result<bool, QueueError>| match result {
+        Err(QueueError::InvalidEvent { reason, .. }) => Some(reason),
+        _ => None,
+    };
+
+    // Duplicates are applied once
+    let duplicating = MisbehavingNode::new(node("byz-dup")).with(Misbehavior::Duplicate { copies: 3 });
+    let events = duplicating.enqueue("once".to_string()).unwrap();
+    assert_eq!(events.len(), 4);
+    let applied = events.into_iter().filter(|event| replica.apply_remote_event(event.clone())).count();
+    assert_eq!((applied, replica.queue_state().0), (1, 1));
+
+    // Claiming unseen history could be legitimate, so it waits in the buffer for good
+    let ahead = MisbehavingNode::new(node("byz-ahead")).with(Misbehavior::ClockAhead { by: 5 });
+    for event in ahead.enqueue("garbage".to_string()).unwrap() {
+        assert_eq!(replica.try_apply_remote_event(event), Ok(false));
+    }
+    assert_eq!(replica.pending_events_count(), 1);
+
+    // Impossible sequence numbers and replays are rejected outright
+    let out_of_range = MisbehavingNode::new(node("byz-range")).with(Misbehavior::SequenceOutOfRange);
+    for event in out_of_range.enqueue("garbage".to_string()).unwrap() {
+        assert!(matches!(reason(replica.try_apply_remote_event(event)), Some(EventRejection::TooFarAhead { .. })));
+    }
+    let rewinding = MisbehavingNode::new(node("byz-rewind")).with(Misbehavior::ClockRewind { by: 1 });
+    assert!(replica.apply_remote_event(rewinding.inner().enqueue("first".to_string()).unwrap()));
+    for event in rewinding.enqueue("garbage".to_string()).unwrap() {
+        let rejection = reason(replica.try_apply_remote_event(event));
+        assert_eq!(rejection, Some(EventRejection::Stale { origin_time: 1, delivered: 1 }));
+    }
+    assert_eq!(replica.pending_events_count(), 1);
+
+    // Honest peers keep replicating
+    assert!(replica.apply_remote_event(node("byz-good").enqueue("honest".to_string()).unwrap()));
+    let items: Vec<String> = replica.snapshot().items.iter().map(|item| item.to_string()).collect();
+    assert_eq!(items, ["once", "first", "honest"]);
+}
+
+#[test]
+fn test_invalid_remote_events_are_rejected_with_a_reason() {
+    use DistributedQueueMini::core::buildcore::{EventOp, EventRejection, QueueError};
+
+    let origin = DistributedQueueSystem::new_with_nodes("valid-origin".to_string(), &["valid-replica"]);
+    let replica = DistributedQueueSystem::builder("valid-replica")
+        .peers(&["valid-origin"])
+        .max_event_gap(10)
+        .build();
+    let reason = |result: Result<bool, QueueError>| match result {
+        Err(QueueError::InvalidEvent { reason, .. }) => reason,
+        other => panic!("not rejected: {:?}", other),
+    };
+
+    let mut no_item = origin.enqueue("item".to_string()).unwrap();
+    no_item.item = None;
+    assert_eq!(reason(replica.try_apply_remote_event(no_item)), EventRejection::MissingItem);
+
+    let mut no_origin = origin.enqueue("item".to_string()).unwrap();
+    no_origin.clock.clear();
+    assert_eq!(reason(replica.try_apply_remote_event(no_origin)), EventRejection::MissingOriginTime);
+
+    let mut far = origin.enqueue("item".to_string()).unwrap();
+    far.clock.insert("valid-origin".into(), 11);
+    assert_eq!(reason(replica.try_apply_remote_event(far)), EventRejection::TooFarAhead { origin_time: 11, delivered: 0, max_gap: 10 });
+
+    // Within the gap, events wait for their predecessors as before
+    let within = origin.enqueue("item".to_string()).unwrap();
+    assert_eq!(replica.try_apply_remote_event(within), Ok(false));
+    assert_eq!(replica.pending_events_count(), 1);
+    let mut unexpected = origin.enqueue("item".to_string()).unwrap();
+    unexpected.op = EventOp::Reject { origin: "valid-origin".into(), event_id: 1 };
+    assert!(!replica.apply_remote_event(unexpected));
+    #[cfg(feature = "metrics")]
+    assert_eq!(replica.metrics().remote_invalid, 4);
+}
+
+#[test]
+fn test_remote_events_are_redelivered_after_a_full_buffer() {
+    use std::time::Duration;
+    use DistributedQueueMini::core::buildcore::{QueueError, RetryPolicy};
+
+    let origin = DistributedQueueSystem::new_with_nodes("redeliver-origin".to_string(), &["redeliver-replica"]);
+    let first = origin.enqueue(1).unwrap();
+    let second = origin.enqueue(2).unwrap();
+    let third = origin.enqueue(3).unwrap();
+
+    let replica = DistributedQueueSystem::<i32>::builder("redeliver-replica")
+        .peers(&["redeliver-origin"])
+        .max_buffered_events(1)
+        .redelivery_policy(RetryPolicy::new(3).backoff(Duration::ZERO, 1.0, Duration::ZERO))
+        .build();
+    assert_eq!(replica.try_apply_remote_event(second), Ok(false));
+    assert_eq!(replica.try_apply_remote_event(third), Err(QueueError::BufferFull { capacity: 1 }));
+    assert_eq!(replica.pending_redeliveries(), 1);
+
+    // Still full: rescheduled, not lost
+    assert_eq!(replica.process_redeliveries(), 0);
+    assert_eq!(replica.pending_redeliveries(), 1);
+
+    assert!(replica.apply_remote_event(first));
+    assert_eq!(replica.process_redeliveries(), 1);
+    assert_eq!(replica.queue_state().0, 3);
+    assert_eq!(replica.pending_redeliveries(), 0);
+    assert!(replica.failed_events().is_empty());
+
+    // Invalid events fail for good on the first try
+    let mut invalid = origin.enqueue(4).unwrap();
+    invalid.item = None;
+    assert!(!replica.apply_remote_event(invalid));
+    let failed = replica.failed_events();
+    assert_eq!(failed.len(), 1);
+    assert_eq!((failed[0].origin.as_str(), failed[0].attempts), ("redeliver-origin", 1));
+    assert_eq!(replica.stats().failed_events, 1);
+}
+
+#[test]
+fn test_panicking_event_is_quarantined_without_blocking_later_ones() {
+    let origin = DistributedQueueSystem::new_with_nodes("poison-origin".to_string(), &["poison-replica"]);
+    let replica = DistributedQueueSystem::builder("poison-replica")
+        .peers(&["poison-origin"])
+        .log_key(|item: &i32| if *item == 13 { panic!("cannot index item 13") } else { Some(item.to_string()) })
+        .build();
+    let first = origin.enqueue(1).unwrap();
+    let poison = origin.enqueue(13).unwrap();
+    let third = origin.enqueue(3).unwrap();
+
+    // The poison event panics while being drained from the buffer
+    assert!(!replica.apply_remote_event(third));
+    assert!(!replica.apply_remote_event(poison.clone()));
+    assert!(replica.apply_remote_event(first));
+    assert_eq!(replica.pending_events_count(), 0);
+
+    let quarantined = replica.quarantined();
+    assert_eq!(quarantined.len(), 1);
+    assert_eq!(quarantined[0].event.global_id, poison.global_id);
+    assert_eq!(quarantined[0].error, "cannot index item 13");
+    assert!(!replica.apply_remote_event(poison), "quarantined event applied again");
+
+    // Locks the panic poisoned are usable again
+    assert!(replica.logs().iter().any(|entry| entry.item().is_some_and(|item| **item == 3)));
+    assert!(replica.apply_remote_event(origin.enqueue(4).unwrap()));
+    assert_eq!(replica.stats().quarantined, 1);
+    #[cfg(feature = "metrics")]
+    assert_eq!(replica.metrics().quarantined, 1);
+}
+
+#[cfg(feature = "payload")]
+#[test]
+fn test_payload_queue_carries_mixed_items_and_replicates_with_typed_nodes() {
+    use DistributedQueueMini::core::buildcore::{Event, Payload};
+    use serde_json::{Value, json};
+
+    let mixed = DistributedQueueSystem::<Payload>::new_with_nodes("payload-mixed".to_string(), &["payload-json"]);
+    let typed = DistributedQueueSystem::<Value>::new_with_nodes("payload-json".to_string(), &["payload-mixed"]);
+    fn wire<A: serde::Serialize, B: serde::de::DeserializeOwned>(event: &Event<A>) -> Event<B> {
+        serde_json::from_str(&serde_json::to_string(event).unwrap()).unwrap()
+    }
+
+    for item in [Payload::from(&b"\x00\xff"[..]), "hello".into(), json!({ "kind": "order", "id": 7 }).into()] {
+        assert!(typed.apply_remote_event(wire(&mixed.enqueue(item).unwrap())));
+    }
+    assert!(mixed.apply_remote_event(wire(&typed.enqueue(json!("from json")).unwrap())));
+
+    let replicated: Vec<Value> = std::iter::from_fn(|| typed.dequeue().0).map(|item| (*item).clone()).collect();
+    assert_eq!(replicated, vec![json!([0, 255]), json!("hello"), json!({ "kind": "order", "id": 7 }), json!("from json")]);
+
+    let items: Vec<Payload> = std::iter::from_fn(|| mixed.dequeue().0).map(|item| (*item).clone()).collect();
+    assert_eq!(items, vec![
+        Payload::Bytes(vec![0, 255]),
+        Payload::String("hello".into()),
+        Payload::Json(json!({ "kind": "order", "id": 7 })),
+        Payload::String("from json".into()),
+    ]);
+    assert_eq!(items[0].as_bytes(), Some(&[0, 255][..]));
+    assert_eq!(items[1].as_str(), Some("hello"));
+    assert_eq!(items[2].as_json().map(|doc| &doc["id"]), Some(&json!(7)));
+}
+
+#[test]
+#[cfg(feature = "persist")]
+fn test_purge_removes_and_redacts_an_item_on_every_node() {
+    use DistributedQueueMini::core::buildcore::{EventOp, FileStore, QueueError};
+
+    let path = std::env::temp_dir().join(format!("dqm-purge-{}.ndjson", std::process::id()));
+    let key = |order: &(String, u32)| Some(order.0.clone());
+    let origin = DistributedQueueSystem::builder("purge-origin").peers(&["purge-replica"]).log_key(key).build();
+    let replica = DistributedQueueSystem::builder("purge-replica")
+        .peers(&["purge-origin"])
+        .max_log_entries(2)
+        .storage(Box::new(FileStore::create(&path).unwrap()))
+        .log_key(key)
+        .build();
+    for (i, customer) in ["alice", "bob", "alice", "carol"].into_iter().enumerate() {
+        assert!(replica.apply_remote_event(origin.enqueue((customer.to_string(), i as u32)).unwrap()));
+    }
+    assert!(replica.log_page(0, 2).unwrap().iter().all(|e| e.item().is_some()), "spilled entries lost their items");
+
+    let purge = origin.purge_item("alice").unwrap();
+    assert!(matches!(&purge.op, EventOp::Purge { key } if key == "alice"));
+    assert!(replica.apply_remote_event(purge));
+
+    for node in [&origin, &replica] {
+        let queued: Vec<u32> = node.snapshot().items.iter().map(|order| order.1).collect();
+        assert_eq!(queued, [1, 3], "on {}", node.node_id());
+        let log = node.log_page(0, 10).unwrap();
+        let redacted: Vec<usize> = log.iter().enumerate().filter(|(_, e)| e.redacted).map(|(i, _)| i).collect();
+        assert_eq!(redacted, [0, 2], "on {}", node.node_id());
+        assert!(log.iter().filter(|e| e.redacted).all(|e| e.item().is_none()));
+        assert_eq!(log.last().unwrap().op, "purge", "no tombstone on {}", node.node_id());
+        assert!(node.find_by_key("alice").unwrap().is_empty());
+    }
+    // The spilled entries are redacted on disk, not just in memory
+    let stored = std::fs::read_to_string(&path).unwrap();
+    assert!(!stored.contains("alice"));
+    assert!(stored.contains("bob"));
+
+    let unkeyed = DistributedQueueSystem::new("purge-unkeyed".to_string());
+    unkeyed.enqueue(1).unwrap();
+    assert_eq!(unkeyed.purge_item("1").unwrap_err(), QueueError::NoKeyIndex);
+    std::fs::remove_file(path).ok();
+}
+
+#[test]
+#[cfg(feature = "persist")]
+fn test_admin_actions_are_audited_apart_from_the_operation_log() {
+    use DistributedQueueMini::core::buildcore::{AuditLog, State};
+    use DistributedQueueMini::engine::node::Node;
+    use DistributedQueueMini::engine::placement::HashRing;
+
+    let path = std::env::temp_dir().join(format!("dqm-audit-{}.ndjson", std::process::id()));
+    std::fs::remove_file(&path).ok();
+    let node = Node::with_topic_factory("audit-node", |_| DistributedQueueSystem::builder("audit-node").log_key(|item: &String| Some(item.clone())))
+        .with_placement(HashRing::new(16))
+        .with_audit_log(AuditLog::append_to(&path).unwrap());
+    let event = node.enqueue("orders", "alice".to_string()).unwrap();
+    node.update_state("orders", "audit-node", event.global_id, State::Failed).unwrap();
+    node.purge_item("orders", "alice").unwrap();
+    assert!(node.add_member("audit-peer"));
+    assert!(!node.add_member("audit-peer"), "unchanged membership audited");
+    assert!(node.remove_member("audit-peer"));
+
+    let audited: Vec<(String, Option<String>)> =
+        node.audit_log().page(0, 10).unwrap().into_iter().map(|e| (e.action, e.topic)).collect();
+    let orders = Some("orders".to_string());
+    assert_eq!(audited, [
+        ("state_override".to_string(), orders.clone()),
+        ("purge".to_string(), orders),
+        ("member_add".to_string(), None),
+        ("member_remove".to_string(), None),
+    ]);
+    assert!(node.audit_log().entries().iter().all(|e| e.allowed && e.actor == "audit-node"));
+    // The operation log only holds the operations themselves
+    let ops: Vec<String> = node.topic("orders").logs().iter().map(|e| e.op.clone()).collect();
+    assert_eq!(ops, ["enqueue", "update", "purge"]);
+
+    // The file survives the node and keeps growing when reopened
+    drop(node);
+    let reopened = AuditLog::append_to(&path).unwrap();
+    assert_eq!(reopened.stored(), 4);
+    assert_eq!(reopened.page(1, 1).unwrap()[0].detail.as_deref(), Some("alice"));
+    std::fs::remove_file(path).ok();
+}
+
+#[test]
+fn test_control_events_are_ordered_with_data_and_replayable() {
+    use DistributedQueueMini::core::buildcore::{ControlOp, EventOp, NodeId, QueueError, TopicConfig};
+    use DistributedQueueMini::engine::node::Node;
+    use DistributedQueueMini::engine::placement::HashRing;
+
+    let origin = DistributedQueueSystem::new_with_nodes("control-origin".to_string(), &["control-replica"]);
+    let replica = DistributedQueueSystem::new_with_nodes("control-replica".to_string(), &["control-origin"]);
+    let events = vec![
+        origin.enqueue(1).unwrap(),
+        origin.control(ControlOp::Paused),
+        origin.control(ControlOp::ConfigUpdated { key: "max_depth".to_string(), value: "10".to_string() }),
+    ];
+    assert_eq!(origin.try_enqueue(2).unwrap_err(), QueueError::Paused);
+    let (dequeued, empty) = origin.dequeue();
+    assert_eq!(dequeued, None, "paused queue handed out an item");
+    let resume = origin.control(ControlOp::Resumed);
+    let joined = origin.control(ControlOp::MemberAdded { member: NodeId::intern("control-late") });
+
+    // The replica is paused exactly between the events that paused and resumed the origin
+    for event in events {
+        assert!(replica.apply_remote_event(event));
+    }
+    assert!(replica.is_paused() && replica.stats().paused);
+    assert_eq!(replica.control_config()["max_depth"], "10");
+    assert!(replica.apply_remote_event(empty));
+    assert_eq!(replica.queue_state().0, 1, "paused replica applied the dequeue of an item");
+    assert!(replica.apply_remote_event(resume));
+    assert!(!replica.is_paused());
+    assert!(replica.apply_remote_event(joined));
+    assert!(replica.stats().clock.contains_key(&NodeId::intern("control-late")));
+
+    // Replaying the log shows the control actions among the data operations
+    let replayed: Vec<String> = replica.logs().iter().map(|entry| match &entry.event.op {
+        EventOp::Control { op } => op.to_string(),
+        _ => entry.op.clone(),
+    }).collect();
+    assert_eq!(replayed, ["enqueue", "paused", "max_depth set to 10", "dequeue", "resumed", "member control-late added"]);
+
+    // On a node, creating a topic logs it first and membership follows the placement ring
+    let node = Node::<i32>::new("control-node", &[]).with_placement(HashRing::new(16));
+    assert_eq!(node.create_topic("jobs", TopicConfig::default()).len(), 1);
+    assert!(node.create_topic("jobs", TopicConfig::default()).is_empty(), "topic created twice");
+    node.enqueue("jobs", 1).unwrap();
+    node.control("jobs", ControlOp::Paused);
+    assert!(node.enqueue("jobs", 2).is_err());
+    node.announce_membership("control-peer", true);
+    assert!((0..64).any(|i| node.owner(&format!("q{}", i)).as_deref() == Some("control-peer")));
+    let ops: Vec<String> = node.topic("jobs").logs().iter().map(|entry| entry.op.clone()).collect();
+    assert_eq!(ops, ["control", "enqueue", "control", "control"]);
+    let audited: Vec<String> = node.audit_log().entries().into_iter().map(|e| e.action).collect();
+    assert_eq!(audited, ["control", "control", "member_add"]);
+}
+
+#[test]
+fn test_stable_frontier_covers_events_every_node_has_applied() {
+    use std::collections::HashMap;
+    use DistributedQueueMini::core::buildcore::{ControlOp, NodeId};
+
+    let a = DistributedQueueSystem::builder("sf-a").peers(&["sf-b", "sf-c"]).build();
+    let b = DistributedQueueSystem::builder("sf-b").peers(&["sf-a", "sf-c"]).build();
+    let c = DistributedQueueSystem::builder("sf-c").peers(&["sf-a", "sf-b"]).build();
+    let first = a.enqueue("first".to_string()).unwrap();
+    assert!(!a.is_stable(&first));
+
+    // sf-b confirms through its own traffic, sf-c through a heartbeat-style report
+    b.apply_remote_event(first.clone());
+    a.apply_remote_event(b.enqueue("from-b".to_string()).unwrap());
+    assert!(!a.is_stable(&first), "sf-c hasn't applied it");
+    c.apply_remote_event(first.clone());
+    a.observe_peer_clock(NodeId::from("sf-c"), &c.vector_clock());
+    assert!(a.is_stable(&first));
+    let frontier = a.stability_watermark();
+    assert_eq!(frontier[&NodeId::from("sf-a")], 1);
+    assert_eq!(frontier[&NodeId::from("sf-b")], 0, "sf-c hasn't applied from-b");
+    assert_eq!(a.stats().stable, frontier);
+
+    // A removed member no longer holds the frontier back
+    let second = a.enqueue("second".to_string()).unwrap();
+    b.apply_remote_event(second.clone());
+    a.apply_remote_event(b.enqueue("again".to_string()).unwrap());
+    assert!(!a.is_stable(&second));
+    a.control(ControlOp::MemberRemoved { member: NodeId::from("sf-c") });
+    assert!(a.is_stable(&second));
+    let expected: HashMap<NodeId, u64> = [(NodeId::from("sf-a"), 2), (NodeId::from("sf-b"), 2), (NodeId::from("sf-c"), 0)].into();
+    assert_eq!(a.stability_watermark(), expected);
+}
+
+#[test]
+fn test_garbage_below_the_stable_frontier_is_collected() {
+    use DistributedQueueMini::core::buildcore::{GcReport, RetentionPolicy};
+
+    let a = DistributedQueueSystem::builder("gc-a").peers(&["gc-b", "gc-c"]).build();
+    let b = DistributedQueueSystem::builder("gc-b")
+        .peers(&["gc-a", "gc-c"])
+        .retention(RetentionPolicy::new().once_stable())
+        .build();
+    let c = DistributedQueueSystem::builder("gc-c").peers(&["gc-a", "gc-b"]).build();
+
+    let events: Vec<_> = (0..3).map(|i| a.enqueue(format!("item{}", i)).unwrap()).collect();
+    for event in &events {
+        b.apply_remote_event(event.clone());
+    }
+    assert_eq!(b.applied_records(), 3);
+    // gc-c hasn't confirmed anything, so nothing is stable yet
+    assert_eq!(b.collect_garbage().unwrap(), GcReport::default());
+    assert_eq!(b.retained_range(), 0..3);
+
+    for event in &events[..2] {
+        c.apply_remote_event(event.clone());
+    }
+    // Logging from-c already let the log entries of item0 and item1 go
+    b.apply_remote_event(c.enqueue("from-c".to_string()).unwrap());
+    assert_eq!(b.collect_garbage().unwrap(), GcReport { applied: 2, log_entries: 0 });
+    assert_eq!(b.applied_records(), 2, "item2 and from-c are still recorded");
+    assert_eq!(b.retained_range(), 2..4);
+
+    // Collected events are still recognized as duplicates
+    assert!(!b.apply_remote_event(events[0].clone()));
+    assert_eq!(b.queue_state().0, 4);
+}
+
+#[test]
+fn test_buffered_events_list_their_missing_predecessors() {
+    use DistributedQueueMini::core::buildcore::{MissingEvents, NodeId};
+
+    let a = DistributedQueueSystem::builder("dep-a").peers(&["dep-b", "dep-c"]).build();
+    let b = DistributedQueueSystem::builder("dep-b").peers(&["dep-a", "dep-c"]).build();
+    let c = DistributedQueueSystem::builder("dep-c").peers(&["dep-a", "dep-b"]).build();
+    let from_a: Vec<_> = (0..3).map(|i| a.enqueue(format!("a{}", i)).unwrap()).collect();
+    b.apply_remote_event(from_a[0].clone());
+    b.apply_remote_event(from_a[1].clone());
+    let from_b = b.enqueue("b0".to_string()).unwrap();
+
+    // c has seen nothing: a2 waits for a1..a2, b0 for a's first two
+    assert!(!c.apply_remote_event(from_a[2].clone()));
+    assert!(!c.apply_remote_event(from_b.clone()));
+    let buffered = c.buffered_events();
+    assert_eq!(buffered.len(), 2);
+    let a2 = buffered.iter().find(|e| e.event_id == from_a[2].global_id).unwrap();
+    assert_eq!(a2.sequence, 3);
+    assert_eq!(a2.missing, [MissingEvents { node: NodeId::from("dep-a"), from: 1, to: 2 }]);
+    let b0 = buffered.iter().find(|e| e.origin == NodeId::from("dep-b")).unwrap();
+    assert_eq!(b0.missing, [MissingEvents { node: NodeId::from("dep-a"), from: 1, to: 2 }]);
+
+    c.apply_remote_event(from_a[0].clone());
+    let buffered = c.buffered_events();
+    assert!(buffered.iter().all(|e| e.missing == [MissingEvents { node: NodeId::from("dep-a"), from: 2, to: 2 }]));
+    c.apply_remote_event(from_a[1].clone());
+    assert!(c.buffered_events().is_empty());
+    assert_eq!(c.queue_state().0, 4);
+}
+
+#[test]
+fn test_conditional_dequeue_takes_the_same_item_on_every_replica() {
+    use DistributedQueueMini::core::buildcore::EventOp;
+
+    let a = DistributedQueueSystem::builder("cond-a").peers(&["cond-b"]).build();
+    let b = DistributedQueueSystem::builder("cond-b").peers(&["cond-a"]).build();
+    for item in ["low:1", "high:1", "low:2", "high:2"] {
+        b.apply_remote_event(a.enqueue(item.to_string()).unwrap());
+    }
+
+    let (item, event) = a.dequeue_if(|item| item.starts_with("high")).unwrap();
+    assert_eq!(*item, "high:1");
+    assert!(matches!(event.op, EventOp::Take { .. }));
+    assert!(a.dequeue_if(|item| item.starts_with("none")).is_none());
+    // The head is low:1, so a head-only predicate for high items takes nothing
+    assert!(a.dequeue_head_if(|item| item.starts_with("high")).is_none());
+    let (head, head_event) = a.dequeue_head_if(|item| item.starts_with("low")).unwrap();
+    assert_eq!(*head, "low:1");
+
+    assert!(b.apply_remote_event(event));
+    assert!(b.apply_remote_event(head_event));
+    let remaining = |q: &DistributedQueueSystem<String>| std::iter::from_fn(|| q.dequeue().0).map(|i| i.to_string()).collect::<Vec<_>>();
+    assert_eq!(remaining(&b), ["low:2", "high:2"]);
+    assert_eq!(remaining(&a), ["low:2", "high:2"]);
+}
+
+#[test]
+fn test_keyed_fifo_hands_out_each_key_in_enqueue_order_on_every_node() {
+    use DistributedQueueMini::core::buildcore::KeySequence;
+
+    let names = ["kf-a", "kf-b", "kf-c", "kf-d"];
+    let nodes: Vec<DistributedQueueSystem<String>> = names
+        .iter()
+        .map(|id| {
+            DistributedQueueSystem::builder(*id)
+                .peers(&names.iter().copied().filter(|n| n != id).collect::<Vec<_>>())
+                .keyed_fifo(|item: &String| item.split_once(':').map(|(key, _)| key.to_string()))
+                .build()
+        })
+        .collect();
+    let [a, b, c, d] = [&nodes[0], &nodes[1], &nodes[2], &nodes[3]];
+
+    // a1 and b1 are concurrent, so both are first of their key; origin breaks the tie
+    let a1 = a.enqueue("order:a1".to_string()).unwrap();
+    let b1 = b.enqueue("order:b1".to_string()).unwrap();
+    let x = a.enqueue("other:x".to_string()).unwrap();
+    assert_eq!(a1.sequence, Some(KeySequence { key: "order".to_string(), seq: 1 }));
+    assert_eq!(b1.sequence, a1.sequence);
+    a.apply_remote_event(b1.clone());
+    b.apply_remote_event(a1.clone());
+    b.apply_remote_event(x.clone());
+    let b2 = b.enqueue("order:b2".to_string()).unwrap();
+    assert_eq!(b2.sequence.as_ref().unwrap().seq, 2);
+    a.apply_remote_event(b2.clone());
+    for event in [&b1, &a1, &x, &b2] {
+        c.apply_remote_event(event.clone());
+    }
+    for event in [&a1, &x, &b1, &b2] {
+        d.apply_remote_event(event.clone());
+    }
+
+    // b1 is c's head, but a1 goes out first; a dequeue_if can't jump ahead within a key either
+    assert!(c.dequeue_if(|item| item == "order:b2").is_none());
+    for node in [a, b, c, d] {
+        let delivered: Vec<String> = std::iter::from_fn(|| node.dequeue().0).map(|item| item.to_string()).collect();
+        let ordered: Vec<&str> = delivered.iter().map(String::as_str).filter(|item| item.starts_with("order:")).collect();
+        assert_eq!(ordered, ["order:a1", "order:b1", "order:b2"], "on {}", node.node_id());
+        assert_eq!(delivered.len(), 4);
+    }
+}
+
+#[test]
+fn test_events_of_a_restarted_node_are_told_apart_by_epoch() {
+    use DistributedQueueMini::core::buildcore::{EventRejection, NodeId, QueueError};
+
+    let incarnation = |epoch| DistributedQueueSystem::<String>::builder("epoch-a").peers(&["epoch-b"]).epoch(epoch).build();
+    let replica = DistributedQueueSystem::<String>::builder("epoch-b").peers(&["epoch-a"]).build();
+
+    let first = incarnation(1);
+    for item in ["a1", "a2"] {
+        assert!(replica.apply_remote_event(first.enqueue(item.to_string()).unwrap()));
+    }
+    let late = first.enqueue("a3".to_string()).unwrap();
+
+    // The restarted node's counters start over, with its events still applied in order
+    let second = incarnation(2);
+    let restarted = second.enqueue("b1".to_string()).unwrap();
+    assert_eq!(restarted.epoch, 2);
+    assert!(replica.apply_remote_event(restarted));
+    assert!(replica.apply_remote_event(second.enqueue("b2".to_string()).unwrap()));
+    assert_eq!(replica.peer_epochs().get(&NodeId::from("epoch-a")), Some(&2));
+
+    // What the previous incarnation sent last is turned down, not taken for a replay
+    let rejection = match replica.try_apply_remote_event(late) {
+        Err(QueueError::InvalidEvent { reason, .. }) => Some(reason),
+        _ => None,
+    };
+    assert_eq!(rejection, Some(EventRejection::OldEpoch { epoch: 1, newest: 2 }));
+    let items: Vec<String> = std::iter::from_fn(|| replica.dequeue().0).map(|item| item.to_string()).collect();
+    assert_eq!(items, ["a1", "a2", "b1", "b2"]);
+
+    // An invalid event of a newer epoch doesn't restart its origin, nor does an epoch whose
+    // clock would start past the counter's end
+    let reason = |result: Result<bool, QueueError>| match result {
+        Err(QueueError::InvalidEvent { reason, .. }) => Some(reason),
+        _ => None,
+    };
+    let mut invalid = incarnation(3).enqueue("c1".to_string()).unwrap();
+    invalid.item = None;
+    assert_eq!(reason(replica.try_apply_remote_event(invalid)), Some(EventRejection::MissingItem));
+    let wrapped = incarnation(4).enqueue("d1".to_string()).unwrap().with_epoch(1 << 32);
+    assert_eq!(reason(replica.try_apply_remote_event(wrapped)), Some(EventRejection::EpochTooLarge { epoch: 1 << 32 }));
+    assert_eq!(replica.peer_epochs().get(&NodeId::from("epoch-a")), Some(&2));
+    assert!(replica.apply_remote_event(second.enqueue("b3".to_string()).unwrap()));
+}
+
+#[test]
+#[cfg(feature = "persist")]
+fn test_incremental_snapshots_chain_deltas_onto_a_full_base() {
+    use DistributedQueueMini::core::buildcore::SnapshotChain;
+
+    let path = std::env::temp_dir().join(format!("dqm-chain-{}.snapshot", std::process::id()));
+    let deltas_path = path.with_extension("deltas");
+    let node = DistributedQueueSystem::<String>::new("chain-node".to_string());
+    for i in 0..100 {
+        node.enqueue(format!("job{}", i)).unwrap();
+    }
+    let mut chain = SnapshotChain::new(&path).with_max_deltas(2);
+    assert!(chain.save(node.snapshot()).unwrap());
+
+    // Deltas only hold what changed: here two items gone and one added
+    node.dequeue();
+    node.dequeue();
+    node.enqueue("job100".to_string()).unwrap();
+    assert!(!chain.save(node.snapshot()).unwrap());
+    let written = std::fs::read_to_string(&deltas_path).unwrap();
+    assert!(written.contains("job100") && !written.contains("job50"));
+    node.enqueue("job101".to_string()).unwrap();
+    assert!(!chain.save(node.snapshot()).unwrap());
+
+    let loaded = SnapshotChain::<String>::new(&path).load().unwrap();
+    assert_eq!(loaded.items, node.snapshot().items);
+    assert_eq!(loaded.clock, node.snapshot().clock);
+    let restored = DistributedQueueSystem::<String>::new("chain-copy".to_string());
+    restored.restore(loaded);
+    assert_eq!(restored.peek().as_deref().map(String::as_str), Some("job2"));
+    assert_eq!(restored.queue_state().0, 100);
+
+    // The chain is full, so the next save starts a new base and drops the deltas
+    node.dequeue();
+    assert!(chain.save(node.snapshot()).unwrap());
+    assert!(std::fs::read_to_string(&deltas_path).unwrap().is_empty());
+    assert_eq!(SnapshotChain::<String>::new(&path).load().unwrap().items.len(), 99);
+    std::fs::remove_file(path).ok();
+    std::fs::remove_file(deltas_path).ok();
+}
+
+#[test]
+fn test_memory_stats_show_which_part_grows() {
+    let replica = DistributedQueueSystem::<String>::builder("mem-b")
+        .peers(&["mem-a"])
+        .item_size(|item: &String| size_of::<String>() + item.capacity())
+        .build();
+    let origin = DistributedQueueSystem::<String>::builder("mem-a").peers(&["mem-b"]).build();
+    let empty = replica.memory_stats();
+    assert_eq!(empty.event_buffer, 0);
+
+    let events: Vec<_> = (0..50).map(|_| origin.enqueue("x".repeat(1000)).unwrap()).collect();
+    for event in &events[1..] {
+        replica.apply_remote_event(event.clone());
+    }
+    let waiting = replica.memory_stats();
+    assert!(waiting.event_buffer > 49 * 1000, "{:?}", waiting);
+    assert_eq!(waiting.queue, empty.queue);
+
+    // Once the missing event arrives, the items move on to the queue and the log
+    assert!(replica.apply_remote_event(events[0].clone()));
+    let applied = replica.memory_stats();
+    assert!(applied.event_buffer < 1000, "{:?}", applied);
+    assert!(applied.queue > 50 * 1000 && applied.log > 50 * 1000, "{:?}", applied);
+    assert!(applied.applied_events > empty.applied_events);
+    assert_eq!(applied.total(), applied.queue + applied.log + applied.event_buffer + applied.applied_events);
+
+    // Without an item size only the inline size of each item counts
+    assert!(origin.memory_stats().queue < 50 * 1000);
+}
+
+#[test]
+fn test_byte_limit_rejects_enqueues_that_dont_fit_and_counts_towards_load() {
+    use DistributedQueueMini::core::buildcore::{MessageSize, QueueError, serialized_size};
+
+    let queue = DistributedQueueSystem::<String>::builder("bytes-a").max_bytes(10).build();
+    assert_eq!(queue.load(), Some(0.0));
+    queue.try_enqueue("12345".to_string()).unwrap();
+    queue.try_enqueue("123".to_string()).unwrap();
+    assert_eq!(queue.stats().bytes, 8);
+    assert_eq!(queue.load(), Some(0.8));
+
+    // Too big for what's left, though the queue isn't full; smaller items still fit
+    assert_eq!(queue.try_enqueue("123".to_string()), Err(QueueError::FullBytes { max_bytes: 10 }));
+    queue.try_enqueue("12".to_string()).unwrap();
+    assert_eq!(queue.load(), Some(1.0));
+
+    // Dequeues free their bytes
+    assert_eq!(queue.dequeue().0.as_deref().map(String::as_str), Some("12345"));
+    assert_eq!(queue.stats().bytes, 5);
+    queue.try_enqueue("1234".to_string()).unwrap();
+
+    // Items and bytes are limited together; the fuller one is the load
+    let both = DistributedQueueSystem::<String>::builder("bytes-b").queue_capacity(4).max_bytes(100).build();
+    both.try_enqueue("x".to_string()).unwrap();
+    assert_eq!(both.load(), Some(0.25));
+
+    // Types without a `MessageSize` are measured serialized
+    let json = DistributedQueueSystem::<Vec<u32>>::builder("bytes-c").max_bytes_by(8, serialized_size).build();
+    json.try_enqueue(vec![1, 2, 3]).unwrap();
+    assert_eq!(json.stats().bytes, "[1,2,3]".len());
+    assert!(json.try_enqueue(vec![4]).is_err());
+    assert_eq!(serde_json::json!({"a": 1}).message_size(), 7);
+}
+
+#[test]
+#[cfg(feature = "persist")]
+fn test_claim_check_stores_large_items_and_fetches_them_back_on_dequeue() {
+    use std::sync::Arc;
+    use DistributedQueueMini::engine::blob::{BlobStore, Claim, ClaimCheck, FileBlobStore};
+    use DistributedQueueMini::engine::node::Node;
+
+    let dir = std::env::temp_dir().join(format!("dqm-blobs-{}", std::process::id()));
+    let store = Arc::new(FileBlobStore::open(&dir).unwrap());
+    let claims = ClaimCheck::new(store.clone(), 64);
+    let node = Node::<Claim<String>>::new("blob-a", &[]);
+
+    // The large item's event carries only a reference to the stored blob
+    let large = "x".repeat(1000);
+    let event = claims.enqueue(&node, "uploads", large.clone()).unwrap();
+    let encoded = serde_json::to_string(&event).unwrap();
+    assert!(encoded.contains(r#""$blob""#) && !encoded.contains(&large));
+    claims.enqueue(&node, "uploads", "small".to_string()).unwrap();
+
+    let (item, claim) = claims.dequeue(&node, "uploads").unwrap().unwrap();
+    assert_eq!(item, large);
+    let blob = claim.blob().unwrap().clone();
+    assert_eq!(blob.len, 1002);
+    let (item, claim) = claims.dequeue(&node, "uploads").unwrap().unwrap();
+    assert_eq!(item, "small");
+    assert!(claim.blob().is_none());
+    assert!(claims.dequeue(&node, "uploads").unwrap().is_none());
+
+    // A blob that changed is refused; a released one is gone
+    let stored = Claim::<String>::Blob { blob: blob.clone() };
+    store.put(&blob.key, br#""tampered""#).unwrap();
+    assert_eq!(claims.redeem(&stored).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    claims.release(&stored).unwrap();
+    assert_eq!(store.get(&blob.key).unwrap_err().kind(), std::io::ErrorKind::NotFound);
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_snowflake_ids_are_unique_across_threads_and_restarts() {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use DistributedQueueMini::core::id::{IdGenerator, split_id, worker_for};
+
+    let generator = Arc::new(IdGenerator::new(7));
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let generator = generator.clone();
+            std::thread::spawn(move || (0..10_000).map(|_| generator.next_id()).collect::<Vec<_>>())
+        })
+        .collect();
+    let mut seen = HashSet::new();
+    for handle in handles {
+        let ids = handle.join().unwrap();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ids.into_iter().all(|id| seen.insert(id)));
+    }
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis() as u64;
+    let (ms, worker, _) = split_id(generator.next_id());
+    assert_eq!(worker, 7);
+    assert!(ms.abs_diff(now) < 60_000);
+
+    // A restarted process starts a new generator, and carries on past its old IDs
+    let before = IdGenerator::new(7).next_id();
+    std::thread::sleep(std::time::Duration::from_millis(2));
+    assert!(IdGenerator::new(7).next_id() > before);
+
+    // Events and log entries carry IDs of their node's worker
+    let queue = DistributedQueueSystem::<String>::builder("snowflake-a").id_worker(42).build();
+    let event = queue.enqueue("item".to_string()).unwrap();
+    assert_eq!(split_id(event.global_id).1, 42);
+    let derived = DistributedQueueSystem::<String>::new("snowflake-b".to_string()).enqueue("item".to_string()).unwrap();
+    assert_eq!(split_id(derived.global_id).1, worker_for("snowflake-b"));
+    let logs = queue.logs();
+    assert_eq!(split_id(logs[0].local_log_id).1, 42);
+    assert_ne!(logs[0].local_log_id, event.global_id);
+}
+
+#[test]
+fn test_rebuild_at_a_vector_clock_replays_the_log_up_to_it() {
+    use std::collections::HashMap;
+    use DistributedQueueMini::core::buildcore::NodeId;
+
+    let a = DistributedQueueSystem::builder("rebuild-a").peers(&["rebuild-b"]).build();
+    let b = DistributedQueueSystem::<String>::builder("rebuild-b").peers(&["rebuild-a"]).build();
+    let by_name = |clock: HashMap<NodeId, u64>| clock.into_iter().map(|(node, time)| (node.to_string(), time)).collect::<HashMap<_, _>>();
+    let items = |snapshot: &DistributedQueueMini::core::buildcore::Snapshot<String>| snapshot.items.iter().map(|item| item.to_string()).collect::<Vec<_>>();
+
+    for job in ["one", "two", "three"] {
+        b.apply_remote_event(a.enqueue(job.to_string()).unwrap());
+    }
+    a.apply_remote_event(b.dequeue().1);
+    b.apply_remote_event(a.enqueue("four".to_string()).unwrap());
+    let then = b.snapshot();
+    let frontier = by_name(b.vector_clock());
+
+    a.apply_remote_event(b.enqueue("five".to_string()).unwrap());
+    b.apply_remote_event(a.dequeue().1);
+    b.apply_remote_event(a.dequeue_if(|item| item.as_str() == "four").unwrap().1);
+
+    // Either replica rebuilds what b had at its clock then
+    for node in [&a, &b] {
+        let rebuilt = node.rebuild_at(frontier.clone()).unwrap();
+        assert_eq!(items(&rebuilt), ["two", "three", "four"]);
+        assert_eq!(rebuilt.sources, then.sources);
+    }
+    assert_eq!(items(&b.rebuild_at(HashMap::new()).unwrap()), Vec::<String>::new());
+    assert_eq!(items(&b.rebuild_at(by_name(b.vector_clock())).unwrap()), items(&b.snapshot()));
+    // Names of no known node count as 0, and aren't registered
+    let mut unknown = frontier.clone();
+    unknown.insert("rebuild-never-seen".to_string(), 7);
+    assert_eq!(items(&b.rebuild_at(unknown).unwrap()), ["two", "three", "four"]);
+    assert_eq!(NodeId::lookup("rebuild-never-seen"), None);
+
+    // From a snapshot, only the entries after it are replayed; it can't be past the frontier
+    let rebuilt = b.rebuild_from(then.clone(), by_name(b.vector_clock())).unwrap();
+    assert_eq!(items(&rebuilt), ["three", "five"]);
+    assert!(b.rebuild_from(b.snapshot(), frontier).is_err());
+}
+
+#[test]
+fn test_history_diff_finds_missing_and_reordered_events() {
+    use DistributedQueueMini::core::buildcore::diff_histories;
+
+    let a = DistributedQueueSystem::builder("diff-a").peers(&["diff-b", "diff-c"]).build();
+    let b = DistributedQueueSystem::<String>::builder("diff-b").peers(&["diff-a", "diff-c"]).build();
+    let c = DistributedQueueSystem::<String>::builder("diff-c").peers(&["diff-a", "diff-b"]).build();
+    let first = a.enqueue("first".to_string()).unwrap();
+    b.apply_remote_event(first.clone());
+    assert!(diff_histories(&a.logs(), &b.logs()).is_identical());
+
+    // Concurrent enqueues land in different orders, and one never reaches b
+    let from_b = b.enqueue("from-b".to_string()).unwrap();
+    let from_c = c.enqueue("from-c".to_string()).unwrap();
+    a.apply_remote_event(from_c.clone());
+    a.apply_remote_event(from_b.clone());
+    let late = a.enqueue("late".to_string()).unwrap();
+
+    let diff = diff_histories(&a.logs(), &b.logs());
+    assert!(!diff.same_events());
+    let only_a: Vec<u64> = diff.only_left.iter().map(|event| event.event_id).collect();
+    assert_eq!(only_a, [from_c.global_id, late.global_id]);
+    assert!(diff.only_right.is_empty());
+    assert!(diff.reordered.is_empty());
+    let divergence = diff.divergence.unwrap();
+    assert_eq!(divergence.common_prefix, 1);
+    assert_eq!(divergence.left.map(|event| event.event_id), Some(from_c.global_id));
+    assert_eq!(divergence.right.map(|event| (event.event_id, event.index)), Some((from_b.global_id, 1)));
+    assert_eq!(divergence.clock, first.clock);
+
+    // Once b has everything, only the order of the concurrent pair differs
+    b.apply_remote_event(from_c.clone());
+    b.apply_remote_event(late);
+    let diff = diff_histories(&a.logs(), &b.logs());
+    assert!(diff.same_events());
+    assert_eq!(diff.reordered.len(), 1);
+    assert!([from_b.global_id, from_c.global_id].contains(&diff.reordered[0].event_id));
+}
+
+#[test]
+fn test_node_names_read_from_peers_are_bounded() {
+    use DistributedQueueMini::core::buildcore::NodeId;
+    let known = NodeId::intern("bounded-known");
+    assert_eq!(NodeId::lookup("bounded-known"), Some(known));
+    assert_eq!(NodeId::lookup("bounded-never-seen"), None);
+    assert_eq!(serde_json::from_str::<NodeId>("\"bounded-known\"").unwrap(), known);
+
+    // A new name past the length limit is refused rather than interned for good
+    let long = "x".repeat(1000);
+    assert!(serde_json::from_str::<NodeId>(&format!("\"{}\"", long)).is_err());
+    assert_eq!(NodeId::lookup(&long), None);
+    assert!(NodeId::try_intern("bounded-new").is_some());
+}
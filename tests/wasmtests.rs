@@ -0,0 +1,23 @@
+#![cfg(all(feature = "wasm", not(target_arch = "wasm32")))]
+
+use std::process::Command;
+
+/// Builds the library for wasm32-unknown-unknown, which needs that target installed
+#[test]
+fn test_core_builds_for_wasm32_and_exports_the_js_bindings() {
+    let target_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("target");
+    let built = Command::new(env!("CARGO"))
+        .args(["build", "--offline", "--lib", "--target", "wasm32-unknown-unknown", "--no-default-features", "--features", "wasm"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .env("CARGO_TARGET_DIR", &target_dir)
+        .status()
+        .unwrap();
+    assert!(built.success());
+
+    // wasm-bindgen exports each method as `<class>_<jsName>`, for the JS glue to wrap
+    let module = std::fs::read(target_dir.join("wasm32-unknown-unknown/debug/DistributedQueueMini.wasm")).unwrap();
+    let module = String::from_utf8_lossy(&module);
+    for method in ["new", "nodeId", "depth", "enqueue", "dequeue", "applyRemoteEvent", "takeOutgoing", "pendingEvents", "logs", "stats"] {
+        assert!(module.contains(&format!("distributedqueuesystem_{}", method)), "{} is not exported", method);
+    }
+}